@@ -0,0 +1,134 @@
+//!Headless host: simulation and relay of remote players commands, no window nor local player.
+//!Builds without the client feature, so without wgpu, winit and imgui.
+use clap::{App, Arg};
+use crossbeam_channel::unbounded;
+use oxidator::*;
+
+///Buildable without --defs
+const DEFS: [&str; 5] = [
+    "src/asset/botdef/building_example.json",
+    "src/asset/botdef/tech_lab_example.json",
+    "src/asset/botdef/unit_t2_example.json",
+    "src/asset/botdef/storage_example.json",
+    "src/asset/botdef/generator_example.json",
+];
+
+fn main() {
+    let matches = App::new("oxidator_dedicated")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Headless host, the game goes on when a player leaves")
+        .arg(Arg::with_name("bind").default_value("0.0.0.0:4567"))
+        .arg(
+            Arg::with_name("log")
+                .long("log")
+                .takes_value(true)
+                .help("env_logger filter, ex: info,oxidator::net_server=debug"),
+        )
+        .arg(
+            Arg::with_name("map")
+                .long("map")
+                .takes_value(true)
+                .default_value("src/asset/map/map_example")
+                .help("Map directory"),
+        )
+        .arg(
+            Arg::with_name("unit")
+                .long("unit")
+                .takes_value(true)
+                .default_value("src/asset/botdef/unit_example.json")
+                .help("Botdef of the two armies"),
+        )
+        .arg(
+            Arg::with_name("defs")
+                .long("defs")
+                .takes_value(true)
+                .multiple(true)
+                .help("Botdefs the players can build, the examples by default"),
+        )
+        .get_matches();
+
+    let log_lines = logging::init(matches.value_of("log").map(|s| s.to_owned()));
+    crash::install(log_lines);
+
+    let defs: Vec<&str> = match matches.values_of("defs") {
+        Some(defs) => defs.collect(),
+        None => DEFS.to_vec(),
+    };
+    let frame = match start_frame(
+        matches.value_of("map").unwrap(),
+        matches.value_of("unit").unwrap(),
+        &defs,
+    ) {
+        Ok(frame) => frame,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    run(matches.value_of("bind").unwrap().to_owned(), frame);
+}
+
+///Frame::example_game on the map, with the botdefs of defs buildable
+fn start_frame(map: &str, unit: &str, defs: &[&str]) -> Result<frame::Frame, String> {
+    if !std::path::Path::new(map).join("height.png").exists() {
+        return Err(format!("No height.png in map {}", map));
+    }
+    let open = |path: &str| {
+        botdef::BotDef::open(path).map_err(|e| format!("Could not read botdef {}: {}", path, e))
+    };
+    let unit = open(unit)?;
+    let mut bot_defs = Vec::new();
+    for path in defs {
+        bot_defs.push(open(path)?);
+    }
+
+    let mut heightmap_phy = heightmap_phy::HeightmapPhy::new(2048, 2048);
+    heightmap_phy.load(map);
+    let mut bot_defs = bot_defs.into_iter();
+    //example_game needs a second botdef, the unit itself without any other
+    let building = bot_defs.next().unwrap_or_else(|| unit.clone());
+    let mut frame = frame::Frame::example_game(heightmap_phy, unit, building);
+    for botdef in bot_defs {
+        frame.add_bot_def(botdef);
+    }
+    Ok(frame)
+}
+
+fn run(bind: String, frame: frame::Frame) {
+    let (s_to_frame_server, r_to_frame_server) = unbounded::<frame_server::ToFrameServer>();
+    let (s_from_frame_server, r_from_frame_server) = unbounded::<frame_server::FromFrameServer>();
+
+    frame_server::FrameServerCache::spawn(r_to_frame_server, s_from_frame_server);
+
+    let (s_from_client_to_manager, r_from_client_to_manager) = unbounded::<FromClient>();
+    let (s_to_client, r_to_client) = unbounded::<ToClient>();
+    manager::Manager::new(
+        s_to_client,
+        s_to_frame_server,
+        r_from_frame_server,
+        r_from_client_to_manager,
+    );
+
+    log::info!("Dedicated server listening on {}", bind);
+    let _ = s_from_client_to_manager.send(FromClient::StartServer(StartServer {
+        bind,
+        relay: None,
+        net_sim: net_sim::NetSim::none(),
+        rollback: false,
+        host_slot: None,
+    }));
+    let _ = s_from_client_to_manager.send(FromClient::PlayerInput(
+        frame::FrameEventFromPlayer::ReplaceFrame(frame),
+    ));
+
+    //Nobody renders the frames, only the net info is worth logging
+    let mut last_log = std::time::Instant::now();
+    for to_client in r_to_client.iter() {
+        if let ToClient::GlobalInfo(global_info) = to_client {
+            if last_log.elapsed() > std::time::Duration::from_secs(10) {
+                last_log = std::time::Instant::now();
+                log::info!("{:?}", global_info.net_server);
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::heightmap_phy::{self, HeightmapPhy};
 use crate::unit;
 use crate::utils;
 use serde::{Deserialize, Serialize};
@@ -23,10 +24,334 @@ pub struct BotDef {
     pub max_speed: f32,
     ///metal/frame
     pub build_power: f32,
+    ///energy/frame, produced once completed
+    #[serde(default)]
+    pub energy_income: f32,
     ///m
     pub build_dist: f32,
     ///metal
     pub metal_cost: i32,
+    ///Name of the effect played on death, see effect::EffectLibrary
+    #[serde(default = "default_death_effect")]
+    pub death_effect: String,
+    ///m, 0 for units without shield
+    #[serde(default)]
+    pub shield_radius: f32,
+    #[serde(default)]
+    pub weapon0: Weapon,
+    ///Weapon0 can target flying units
+    #[serde(default)]
+    pub anti_air: bool,
+    #[serde(default)]
+    pub movement: MovementClass,
+    ///m above the ground, only used by MovementClass::Air
+    #[serde(default = "default_cruise_altitude")]
+    pub cruise_altitude: f32,
+    ///Can only be built where both land and water are near, for shipyards
+    #[serde(default)]
+    pub shoreline_only: bool,
+    ///Number of ground units it can carry, 0 for units that are not transports
+    #[serde(default)]
+    pub transport_capacity: u32,
+    #[serde(default)]
+    pub strategic: Strategic,
+    ///Its owner is defeated when all of them are destroyed, see frame::WinCondition
+    #[serde(default)]
+    pub commander: bool,
+    ///Special weapon fired on order, the d-gun of commanders
+    #[serde(default)]
+    pub dgun: Option<DGun>,
+    ///Blast on death that digs the terrain, on top of the death_effect
+    #[serde(default)]
+    pub death_explosion: Option<DeathExplosion>,
+    ///Bonus given to the factories its footprint touches
+    #[serde(default)]
+    pub adjacency: Adjacency,
+    ///Tech level its owner needs to start one, see frame::Player::tech_tier
+    #[serde(default = "default_tier")]
+    pub tier: u8,
+    ///Tech level of its owner while one is completed, 0 for units that unlock nothing
+    #[serde(default)]
+    pub unlocks_tier: u8,
 
     pub part_tree: unit::PartTree,
 }
+
+///m, every weapon fires at ennemies this close
+pub const WEAPON_RANGE: f32 = 6.0;
+
+///Multiplier of metal_cost for each tier, from tier 1
+pub const TIER_COST_MULTIPLIERS: [f32; 3] = [1.0, 1.5, 2.25];
+
+///m, side of the cells of the placement grid buildings are snapped to
+pub const PLACEMENT_CELL: f32 = 2.0;
+
+///Highest discount on the units of a factory, whatever touches it
+pub const MAX_ADJACENCY_DISCOUNT: f32 = 0.5;
+
+///What a unit fires at ennemies in range
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Weapon {
+    ///Ballistic shell, see mobile::KinematicProjectile
+    Projectile,
+    ///Instant hit laser, damage is in life point per shot
+    Beam { damage: i32, color: [f32; 3] },
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        Weapon::Projectile
+    }
+}
+
+///Long build time weapon, each shot is built over time in a stockpile
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Strategic {
+    None,
+    ///Missile fired on order anywhere on the map, damage falls off to 0 at radius
+    Nuke {
+        stockpile_frames: i32,
+        damage: i32,
+        radius: f32,
+    },
+    ///Spends one stockpiled interceptor per ennemy nuke falling within radius
+    AntiNuke {
+        stockpile_frames: i32,
+        radius: f32,
+    },
+}
+
+impl Strategic {
+    pub const MAX_STOCKPILE: u32 = 5;
+
+    pub fn stockpile_frames(&self) -> Option<i32> {
+        match self {
+            Strategic::None => None,
+            Strategic::Nuke {
+                stockpile_frames, ..
+            }
+            | Strategic::AntiNuke {
+                stockpile_frames, ..
+            } => Some(*stockpile_frames),
+        }
+    }
+}
+
+///Shot fired in a straight line, hitting every unit on its way
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DGun {
+    ///Life points per unit hit
+    pub damage: i32,
+    ///m
+    pub range: f32,
+    ///m from the line of fire to be hit
+    pub width: f32,
+    ///Energy spent per shot, not fired when the owner has less
+    pub energy_cost: f64,
+    pub reload_frames: i32,
+}
+
+///Damage falls off to 0 at radius, the crater is a third as wide
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DeathExplosion {
+    pub damage: i32,
+    ///m
+    pub radius: f32,
+    ///m
+    pub crater_depth: f32,
+}
+
+///Buildings lowering the cost of the units built by the factories they touch
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Adjacency {
+    None,
+    Storage,
+    Generator,
+}
+
+impl Adjacency {
+    ///Part of the metal cost saved per building touching the factory
+    pub fn discount(self) -> f32 {
+        match self {
+            Adjacency::None => 0.0,
+            Adjacency::Storage => 0.1,
+            Adjacency::Generator => 0.15,
+        }
+    }
+}
+
+impl Default for Adjacency {
+    fn default() -> Self {
+        Adjacency::None
+    }
+}
+
+///Cells of the placement grid covered by a building, max excluded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Footprint {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl Footprint {
+    ///Sharing a side, corners don't count
+    pub fn touches(&self, other: &Footprint) -> bool {
+        let overlap_x = self.min_x < other.max_x && other.min_x < self.max_x;
+        let overlap_y = self.min_y < other.max_y && other.min_y < self.max_y;
+        let side_x = self.max_x == other.min_x || other.max_x == self.min_x;
+        let side_y = self.max_y == other.min_y || other.max_y == self.min_y;
+        (overlap_x && side_y) || (overlap_y && side_x)
+    }
+}
+
+impl Default for Strategic {
+    fn default() -> Self {
+        Strategic::None
+    }
+}
+
+impl BotDef {
+    ///Read only, unlike the unit editor this doesn't create the file
+    pub fn open(path: &str) -> serde_json::Result<BotDef> {
+        let file = std::fs::File::open(path).map_err(serde_json::Error::io)?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+    }
+
+    pub fn is_transport(&self) -> bool {
+        self.transport_capacity > 0
+    }
+
+    ///Builders that can't move work through their mobile::BuildQueue
+    pub fn is_factory(&self) -> bool {
+        self.build_power > 0.0 && self.max_speed <= 0.0
+    }
+
+    pub fn is_building(&self) -> bool {
+        self.max_speed <= 0.0
+    }
+
+    ///Cells of the placement grid per side, at least one
+    fn footprint_cells(&self) -> i32 {
+        ((self.radius * 2.0 / PLACEMENT_CELL).ceil() as i32).max(1)
+    }
+
+    ///Where a building asked at (x, y) is placed so that it covers whole cells, units aren't moved
+    pub fn snap(&self, x: f32, y: f32) -> (f32, f32) {
+        if !self.is_building() {
+            return (x, y);
+        }
+        //Odd footprints are centered on a cell, even ones on a corner
+        let offset = if self.footprint_cells() % 2 == 1 {
+            0.5
+        } else {
+            0.0
+        };
+        let snap = |v: f32| ((v / PLACEMENT_CELL - offset).round() + offset) * PLACEMENT_CELL;
+        (snap(x), snap(y))
+    }
+
+    ///Footprint of a building snapped at (x, y)
+    pub fn footprint(&self, x: f32, y: f32) -> Footprint {
+        let cells = self.footprint_cells();
+        let min_x = (x / PLACEMENT_CELL - cells as f32 / 2.0).round() as i32;
+        let min_y = (y / PLACEMENT_CELL - cells as f32 / 2.0).round() as i32;
+        Footprint {
+            min_x,
+            min_y,
+            max_x: min_x + cells,
+            max_y: min_y + cells,
+        }
+    }
+
+    ///Part of the metal cost saved by a factory at (x, y), given the buildings around it
+    pub fn adjacency_discount<'a>(
+        &self,
+        x: f32,
+        y: f32,
+        neighbors: impl IntoIterator<Item = (&'a BotDef, f32, f32)>,
+    ) -> f32 {
+        if !self.is_factory() {
+            return 0.0;
+        }
+        let footprint = self.footprint(x, y);
+        neighbors
+            .into_iter()
+            .filter(|(botdef, x, y)| {
+                botdef.adjacency != Adjacency::None
+                    && botdef.is_building()
+                    && botdef.footprint(*x, *y).touches(&footprint)
+            })
+            .map(|(botdef, _, _)| botdef.adjacency.discount())
+            .sum::<f32>()
+            .min(MAX_ADJACENCY_DISCOUNT)
+    }
+
+    ///Only finished ground units can be loaded, transports don't carry each others
+    pub fn can_be_transported(&self) -> bool {
+        !self.is_transport()
+            && (self.movement == MovementClass::Ground
+                || self.movement == MovementClass::Amphibious)
+    }
+
+    ///metal_cost times the multiplier of its tier, what building one actually costs
+    pub fn tier_metal_cost(&self) -> f32 {
+        let i = (self.tier.max(1) as usize - 1).min(TIER_COST_MULTIPLIERS.len() - 1);
+        self.metal_cost as f32 * TIER_COST_MULTIPLIERS[i]
+    }
+
+    ///Where a unit of this botdef can be started by a ConOrder
+    pub fn can_be_placed(&self, heightmap: &HeightmapPhy, x: f32, y: f32) -> bool {
+        if self.shoreline_only {
+            heightmap.is_shoreline(x, y, self.radius * 4.0)
+        } else {
+            self.movement.can_traverse(heightmap, x, y)
+        }
+    }
+}
+
+///How a unit moves, and which units it avoids while moving
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum MovementClass {
+    Ground,
+    ///Flies in straight lines at cruise_altitude, lands when idle
+    Air,
+    ///Floats at heightmap_phy::WATER_LEVEL, water only
+    Naval,
+    ///Walks on land and on the sea floor
+    Amphibious,
+}
+
+impl MovementClass {
+    ///m of water a ground unit can wade through
+    const FORD_DEPTH: f32 = 1.0;
+
+    pub fn can_traverse(&self, heightmap: &HeightmapPhy, x: f32, y: f32) -> bool {
+        match self {
+            MovementClass::Ground => {
+                heightmap.z_linear(x, y) > heightmap_phy::WATER_LEVEL - Self::FORD_DEPTH
+            }
+            MovementClass::Naval => heightmap.is_water(x, y),
+            MovementClass::Air | MovementClass::Amphibious => true,
+        }
+    }
+}
+
+impl Default for MovementClass {
+    fn default() -> Self {
+        MovementClass::Ground
+    }
+}
+
+fn default_cruise_altitude() -> f32 {
+    8.0
+}
+
+fn default_tier() -> u8 {
+    1
+}
+
+fn default_death_effect() -> String {
+    "small_explosion".to_owned()
+}
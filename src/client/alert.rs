@@ -0,0 +1,179 @@
+use super::client::*;
+use super::event_bus::Consumer;
+use crate::game_event::GameEvent;
+use crate::platform::Instant;
+use crate::*;
+use na::Point3;
+use std::collections::VecDeque;
+use winit::event::VirtualKeyCode;
+
+///Sec before another alert of the same kind near the same place
+const COOLDOWN_SEC: f32 = 10.0;
+///m, an alert this close to a recent one of the same kind is not repeated
+const MERGE_RADIUS: f32 = 30.0;
+const MAX_KEPT: usize = 16;
+///Sec the minimap marks an alert
+pub const FLASH_SEC: f32 = 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlertKind {
+    ///A mobile builder finished its work, or was just built, and has nothing to do
+    IdleBuilder,
+    UnderAttack,
+    UnitLost,
+    ///Metal or energy ran out, at a construction it stalls
+    Stall,
+}
+
+impl AlertKind {
+    fn effect(self) -> &'static str {
+        match self {
+            AlertKind::IdleBuilder => "alert_idle",
+            AlertKind::UnderAttack => "alert_attack",
+            AlertKind::UnitLost => "alert_lost",
+            AlertKind::Stall => "alert_stall",
+        }
+    }
+
+    pub fn color(self) -> [f32; 4] {
+        match self {
+            AlertKind::IdleBuilder => [1.0, 0.9, 0.3, 1.0],
+            AlertKind::UnderAttack => [1.0, 0.25, 0.2, 1.0],
+            AlertKind::UnitLost => [0.6, 0.1, 0.1, 1.0],
+            AlertKind::Stall => [0.3, 0.6, 1.0, 1.0],
+        }
+    }
+}
+
+pub struct Alert {
+    pub kind: AlertKind,
+    pub position: Point3<f32>,
+    pub received: Instant,
+}
+
+impl Alert {
+    pub fn age(&self) -> f32 {
+        self.received.elapsed().as_secs_f32()
+    }
+}
+
+///Latest first
+pub struct Alerts {
+    pub list: VecDeque<Alert>,
+    ///Index in list of the alert the hotkey goes to next
+    next: usize,
+}
+
+impl Alerts {
+    pub fn new() -> Self {
+        Alerts {
+            list: VecDeque::new(),
+            next: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.next = 0;
+    }
+
+    ///False when a recent alert of the same kind already covers the place
+    fn push(&mut self, kind: AlertKind, position: Point3<f32>) -> bool {
+        let repeated = self.list.iter().any(|alert| {
+            alert.kind == kind
+                && alert.age() < COOLDOWN_SEC
+                && (alert.position - position).xy().magnitude() < MERGE_RADIUS
+        });
+        if repeated {
+            return false;
+        }
+        self.list.push_front(Alert {
+            kind,
+            position,
+            received: Instant::now(),
+        });
+        self.list.truncate(MAX_KEPT);
+        self.next = 0;
+        true
+    }
+}
+
+impl App {
+    ///Our units damaged, lost and our builders left idle since the previous frame, our stocks
+    ///that ran out
+    pub fn detect_alerts(&mut self) {
+        let events = self.game_state.events.drain(Consumer::Notifications);
+        let me = match self.game_state.my_player_id {
+            Some(me) => me,
+            None => return,
+        };
+        let frame_zero = &self.game_state.frame_zero;
+        let frame_minus_one = &self.game_state.frame_minus_one;
+        let mut found = Vec::new();
+        for event in events.iter().filter(|event| event.player_id() == me) {
+            match event {
+                GameEvent::UnitDied { position, .. } => {
+                    found.push((AlertKind::UnitLost, *position))
+                }
+                //The hotkey goes to one of our constructions waiting for it
+                GameEvent::ResourceDepleted { .. } => {
+                    let stalled = frame_zero
+                        .kbots
+                        .values()
+                        .filter(|kbot| kbot.player_id == me && kbot.con_completed < 1.0)
+                        .min_by_key(|kbot| kbot.id.value);
+                    if let Some(kbot) = stalled {
+                        found.push((AlertKind::Stall, kbot.position));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for kbot in frame_zero.kbots.values().filter(|k| k.player_id == me) {
+            let before = match frame_minus_one.kbots.get(&kbot.id) {
+                Some(before) => before,
+                None => continue,
+            };
+            if kbot.life < before.life {
+                found.push((AlertKind::UnderAttack, kbot.position));
+            }
+            let is_builder = frame_zero
+                .bot_defs
+                .get(&kbot.botdef_id)
+                .map(|botdef| botdef.build_power > 0.0 && botdef.max_speed > 0.0)
+                .unwrap_or(false);
+            let was_busy = !before.is_idle() || before.con_completed < 1.0;
+            if is_builder && kbot.con_completed >= 1.0 && kbot.is_idle() && was_busy {
+                found.push((AlertKind::IdleBuilder, kbot.position));
+            }
+        }
+
+        for (kind, position) in found {
+            if self.game_state.alerts.push(kind, position) {
+                log::info!("{:?} at {:?}", kind, position.coords);
+                let sec = self.game_state.server_sec;
+                let mut seed = sec;
+                self.game_state
+                    .spawn_effect(kind.effect(), position, sec, &mut seed);
+            }
+        }
+    }
+
+    ///Space goes to the latest alert, then to the ones before on each press
+    pub fn handle_alert_hotkey(&mut self) {
+        if !self
+            .input_state
+            .key_trigger
+            .contains(&VirtualKeyCode::Space)
+        {
+            return;
+        }
+        let alerts = &mut self.game_state.alerts;
+        let position = match alerts.list.get(alerts.next) {
+            Some(alert) => alert.position,
+            None => return,
+        };
+        alerts.next = (alerts.next + 1) % alerts.list.len();
+        self.look_at(position.x, position.y);
+    }
+}
@@ -0,0 +1,118 @@
+use super::client::*;
+use super::game_state;
+use crate::frame::{Alliance, FrameEventFromPlayer};
+use crate::*;
+use imgui::*;
+
+///Resources sent per click of a gift button
+const GIFT_AMOUNT: f64 = 100.0;
+const MAX_TEAM: i32 = 7;
+
+///Team and rules chosen in the multiplayer lobby
+pub struct AllianceUi {
+    ///Otherwise the host plays team 0 and the client team 1
+    pub pick_team: bool,
+    pub team: i32,
+    ///Only those of the host are played
+    pub rules: Alliance,
+    ///SetTeam is sent once our player is known, at the start of the game
+    pub team_sent: bool,
+}
+
+impl AllianceUi {
+    pub fn new() -> Self {
+        AllianceUi {
+            pick_team: false,
+            team: 0,
+            rules: Alliance::default(),
+            team_sent: true,
+        }
+    }
+}
+
+impl App {
+    pub fn draw_lobby_alliance_ui(ui: &Ui, alliance: &mut AllianceUi, position: [f32; 2]) {
+        imgui::Window::new(im_str!("Teams"))
+            .size([300.0, 150.0], imgui::Condition::FirstUseEver)
+            .position(position, imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                ui.checkbox(im_str!("pick team"), &mut alliance.pick_team);
+                if alliance.pick_team {
+                    imgui::Slider::new(im_str!("team"), 0..=MAX_TEAM).build(ui, &mut alliance.team);
+                }
+                ui.separator();
+                ui.text(im_str!("Rules of the host"));
+                let rules = &mut alliance.rules;
+                ui.checkbox(im_str!("fog of war"), &mut rules.fog_of_war);
+                ui.checkbox(im_str!("friendly fire"), &mut rules.friendly_fire);
+                ui.checkbox(im_str!("shared control"), &mut rules.shared_control);
+            });
+    }
+
+    ///Joins the team picked in the lobby during the first frames of a multiplayer game
+    pub fn step_alliance(&mut self) {
+        if self.alliance.team_sent || self.game_state.frame_zero.number > Alliance::TEAM_LOCK_FRAMES
+        {
+            return;
+        }
+        let me = match self.game_state.my_player_id {
+            Some(id) => id,
+            None => return,
+        };
+        if !self.game_state.frame_zero.players.contains_key(&me) {
+            return;
+        }
+        self.alliance.team_sent = true;
+        let event = FrameEventFromPlayer::SetTeam {
+            id: me,
+            team: self.alliance.team as u8,
+        };
+        let _ = self
+            .sender_from_client_to_manager
+            .try_send(client::FromClient::PlayerInput(event));
+    }
+
+    ///Gift buttons for each player of our team
+    pub fn draw_gift_ui(ui: &Ui, game_state: &game_state::State) -> Vec<FrameEventFromPlayer> {
+        let mut gifts = Vec::new();
+        let me = match game_state.my_player() {
+            Some(me) => me,
+            None => return gifts,
+        };
+        let mut allies: Vec<_> = game_state
+            .players
+            .values()
+            .filter(|p| p.team == me.team && p.id != me.id && !p.defeated)
+            .collect();
+        if allies.is_empty() {
+            return gifts;
+        }
+        allies.sort_by_key(|p| p.slot);
+
+        imgui::Window::new(im_str!("Allies"))
+            .size([300.0, 120.0], imgui::Condition::FirstUseEver)
+            .position([500.0, 160.0], imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                for ally in allies {
+                    ui.text(im_str!(
+                        "Player {} metal {:.0} energy {:.0}",
+                        ally.id,
+                        ally.metal,
+                        ally.energy
+                    ));
+                    let metal = ui.small_button(&im_str!("Give metal##{}", ally.id));
+                    ui.same_line(0.0);
+                    let energy = ui.small_button(&im_str!("Give energy##{}", ally.id));
+                    if metal || energy {
+                        gifts.push(FrameEventFromPlayer::Gift {
+                            id: me.id,
+                            to: ally.id,
+                            metal: if metal { GIFT_AMOUNT } else { 0.0 },
+                            energy: if energy { GIFT_AMOUNT } else { 0.0 },
+                        });
+                    }
+                }
+            });
+        gifts
+    }
+}
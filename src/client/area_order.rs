@@ -0,0 +1,91 @@
+use super::client::*;
+use super::uitool::UiTool;
+use crate::frame::FrameEventFromPlayer;
+use crate::mobile::AreaKind;
+use crate::*;
+use na::Vector3;
+
+///m, shorter drags are a click on the unit under the cursor
+const MIN_RADIUS: f32 = 2.0;
+
+impl App {
+    ///Center and radius of the circle dragged with the repair or reclaim tool
+    pub fn area_circle(&self) -> Option<(Vector3<f32>, f32)> {
+        let center = self.game_state.area_center?;
+        let radius = self
+            .game_state
+            .mouse_world_pos
+            .map(|pos| (pos - center).xy().magnitude())
+            .unwrap_or(0.0);
+        Some((center, radius))
+    }
+
+    ///Repair and reclaim orders are given when the right button goes up, on the unit clicked or
+    ///on the units in the circle dragged
+    pub fn handle_area_order(&mut self) {
+        if !self
+            .input_state
+            .mouse_release
+            .contains(&winit::event::MouseButton::Right)
+        {
+            return;
+        }
+        let (center, radius) = match self.area_circle() {
+            Some(circle) => circle,
+            None => return,
+        };
+        self.game_state.area_center = None;
+        let kind = match self.game_state.uitool {
+            UiTool::Repair => AreaKind::Repair,
+            UiTool::Reclaim => AreaKind::Reclaim,
+            _ => return,
+        };
+        self.game_state.uitool = UiTool::None;
+        let id = match self.game_state.my_player_id {
+            Some(id) if self.input_gate().order => id,
+            _ => return,
+        };
+        let selected = self.game_state.selected.clone();
+
+        let order = if radius >= MIN_RADIUS {
+            Some(FrameEventFromPlayer::AreaOrder {
+                id,
+                selected,
+                kind,
+                center,
+                radius,
+            })
+        } else {
+            let frame_zero = &self.game_state.frame_zero;
+            let my_team = self.game_state.my_player().map(|me| me.team);
+            let under = self
+                .game_state
+                .under_mouse
+                .and_then(|under| frame_zero.kbots.get(&under));
+            match (kind, under) {
+                (AreaKind::Repair, Some(kbot)) => Some(FrameEventFromPlayer::RepairOrder {
+                    id,
+                    selected,
+                    to_repair: kbot.id,
+                }),
+                (AreaKind::Reclaim, Some(kbot)) if Some(kbot.team) == my_team => {
+                    Some(FrameEventFromPlayer::ReclaimOrder {
+                        id,
+                        selected,
+                        to_reclaim: kbot.id,
+                    })
+                }
+                _ => {
+                    log::info!("Nothing to {:?} here", kind);
+                    None
+                }
+            }
+        };
+        if let Some(order) = order {
+            log::info!("order {:?} from {}", kind, id);
+            let _ = self
+                .sender_from_client_to_manager
+                .try_send(client::FromClient::PlayerInput(order));
+        }
+    }
+}
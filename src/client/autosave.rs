@@ -0,0 +1,207 @@
+use super::client::*;
+use crate::frame::Frame;
+use crate::*;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{Read, Write};
+
+pub const AUTOSAVE_DIR: &str = "autosave";
+///Written when a game starts and removed when it is left, still there after a crash
+const SESSION_MARKER: &str = "autosave/playing";
+///Ahead of replay::VERSION at the start of each save, which then holds the gzipped bincode of a
+///Frame. The Frame layout is what replay::VERSION follows.
+const MAGIC: [u8; 4] = *b"OXAS";
+
+struct Job {
+    frame: Frame,
+    path: String,
+    keep: usize,
+}
+
+///Periodic snapshots of the last frame, compressed and written by a background thread
+pub struct Autosave {
+    ///Game second of the last snapshot
+    last_sec: f32,
+    ///Filled while the writer serializes the other buffer, None until the writer gives it back
+    spare: Option<Frame>,
+    s_job: Sender<Job>,
+    r_done: Receiver<Frame>,
+    ///Latest save of a game that didn't end, offered on the home screen
+    pub recovery: Option<String>,
+    ///Read from recovery, played instead of a new game by init_play
+    pub resume: Option<Frame>,
+}
+
+impl Autosave {
+    pub fn new() -> Self {
+        let (s_job, r_job) = unbounded::<Job>();
+        let (s_done, r_done) = unbounded::<Frame>();
+        let _ = std::thread::Builder::new()
+            .name("autosave".to_string())
+            .spawn(move || {
+                for job in r_job.iter() {
+                    match write(&job.frame, &job.path) {
+                        Ok(()) => {
+                            log::info!("Autosaved frame {} to {}", job.frame.number, job.path)
+                        }
+                        Err(e) => log::error!("Can't autosave to {}: {}", job.path, e),
+                    }
+                    prune(job.keep);
+                    if s_done.send(job.frame).is_err() {
+                        break;
+                    }
+                }
+            });
+
+        let recovery = if std::path::Path::new(SESSION_MARKER).exists() {
+            let _ = std::fs::remove_file(SESSION_MARKER);
+            saves().pop()
+        } else {
+            None
+        };
+        Autosave {
+            last_sec: 0.0,
+            spare: Some(Frame::new()),
+            s_job,
+            r_done,
+            recovery,
+            resume: None,
+        }
+    }
+
+    pub fn begin_session(&mut self, server_sec: f32) {
+        self.last_sec = server_sec;
+        let _ = std::fs::create_dir_all(AUTOSAVE_DIR);
+        if let Err(e) = std::fs::write(SESSION_MARKER, "") {
+            log::error!("Can't write {}: {}", SESSION_MARKER, e);
+        }
+    }
+
+    ///The game ended or was left on purpose, nothing to recover
+    pub fn end_session(&mut self) {
+        let _ = std::fs::remove_file(SESSION_MARKER);
+    }
+
+    pub fn open(path: &str) -> Result<Frame, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}", e))?;
+        let mut reader = std::io::BufReader::new(file);
+        let mut header = [0; 8];
+        reader
+            .read_exact(&mut header)
+            .map_err(|e| format!("{}", e))?;
+        if header[..4] != MAGIC {
+            return Err("not an autosave".to_owned());
+        }
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != replay::VERSION {
+            return Err(format!(
+                "autosave version {}, this build reads {}",
+                version,
+                replay::VERSION
+            ));
+        }
+        bincode::deserialize_from(GzDecoder::new(reader)).map_err(|e| format!("{}", e))
+    }
+}
+
+fn write(frame: &Frame, path: &str) -> Result<(), String> {
+    //Renamed once complete, a crash while writing doesn't leave a broken save
+    let tmp = format!("{}.tmp", path);
+    let file = std::fs::File::create(&tmp).map_err(|e| format!("{}", e))?;
+    let mut writer = std::io::BufWriter::new(file);
+    writer
+        .write_all(&MAGIC)
+        .and_then(|()| writer.write_all(&replay::VERSION.to_le_bytes()))
+        .map_err(|e| format!("{}", e))?;
+    let mut encoder = GzEncoder::new(writer, flate2::Compression::fast());
+    bincode::serialize_into(&mut encoder, frame).map_err(|e| format!("{}", e))?;
+    encoder
+        .finish()
+        .and_then(|mut w| w.flush())
+        .map_err(|e| format!("{}", e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("{}", e))
+}
+
+///Paths of the saves, oldest first
+fn saves() -> Vec<String> {
+    let mut paths: Vec<String> = std::fs::read_dir(AUTOSAVE_DIR)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().to_string_lossy().into_owned())
+                .filter(|path| path.ends_with(".bin"))
+                .collect()
+        })
+        .unwrap_or_default();
+    //Names hold a fixed width timestamp
+    paths.sort();
+    paths
+}
+
+fn prune(keep: usize) {
+    let paths = saves();
+    for path in paths.iter().take(paths.len().saturating_sub(keep.max(1))) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+impl App {
+    ///Snapshot of frame_zero every autosave_interval_sec game seconds, skipped while the
+    ///previous one is still being written
+    pub fn step_autosave(&mut self) {
+        let gameplay = &self.settings.applied.gameplay;
+        let sec = self.game_state.server_sec;
+        if gameplay.autosave_interval_sec == 0
+            || self.benchmark.is_some()
+            || self.net_mode == NetMode::Client
+            || self.net_mode == NetMode::Spectator
+            || sec - self.autosave.last_sec < gameplay.autosave_interval_sec as f32
+        {
+            return;
+        }
+        let autosave = &mut self.autosave;
+        if let Ok(frame) = autosave.r_done.try_recv() {
+            autosave.spare = Some(frame);
+        }
+        let mut frame = match autosave.spare.take() {
+            Some(frame) => frame,
+            None => return,
+        };
+        autosave.last_sec = sec;
+
+        frame.clone_from(&self.game_state.frame_zero);
+        //The simulation only keeps the heightmap in its cache
+        frame.heightmap_phy = Some(self.heightmap_gpu.phy.clone());
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let _ = autosave.s_job.send(Job {
+            frame,
+            path: format!("{}/autosave-{:012}.bin", AUTOSAVE_DIR, timestamp),
+            keep: gameplay.autosave_keep as usize,
+        });
+    }
+
+    ///Latest autosave in place of the map and the game, then Loading as for a new game
+    pub fn resume_autosave(&mut self, path: &str) {
+        let frame = match Autosave::open(path) {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::error!("Can't read autosave {}: {}", path, e);
+                return;
+            }
+        };
+        if let Some(phy) = &frame.heightmap_phy {
+            self.heightmap_gpu.phy = phy.clone();
+            let (width, height) = (phy.width as u32, phy.height as u32);
+            self.heightmap_gpu.update_rect(0, 0, width, height);
+        }
+        self.autosave.resume = Some(frame);
+        self.mailbox.push(RenderEvent::ChangeMode {
+            from: self.main_menu,
+            to: MainMode::Loading,
+        });
+        self.main_menu = MainMode::Loading;
+    }
+}
@@ -0,0 +1,201 @@
+use super::client::*;
+use super::settings;
+use super::trigger;
+use crate::*;
+use na::{Point3, Vector3};
+use serde::Serialize;
+use spin_sleep::LoopHelper;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+///Fixed map and battle, see start_benchmark
+pub const BENCHMARK_MISSION: &str = "src/asset/mission/benchmark.json";
+pub const REPORT_PATH: &str = "benchmark_report.json";
+///Game seconds before the recording, while the pipelines load and the armies close in
+const WARMUP_SEC: f32 = 5.0;
+const DURATION_SEC: f32 = 40.0;
+///Game seconds since the start, x y and height above ground, direction
+const CAMERA_PATH: [(f32, [f32; 3], [f32; 3]); 5] = [
+    (0.0, [270.0, 120.0, 120.0], [0.0, 0.3, -1.0]),
+    (10.0, [270.0, 200.0, 80.0], [0.0, 0.5, -1.0]),
+    (20.0, [200.0, 270.0, 40.0], [0.5, 0.3, -1.0]),
+    (30.0, [330.0, 330.0, 150.0], [-0.3, -0.3, -1.0]),
+    (45.0, [270.0, 120.0, 300.0], [0.0, 0.6, -1.0]),
+];
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Percentiles {
+    pub mean: f32,
+    pub p50: f32,
+    pub p90: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+impl Percentiles {
+    pub fn of(samples: &mut Vec<f32>) -> Self {
+        if samples.is_empty() {
+            return Percentiles::default();
+        }
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let at = |p: f32| samples[((samples.len() - 1) as f32 * p).round() as usize];
+        Percentiles {
+            mean: samples.iter().sum::<f32>() / samples.len() as f32,
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            max: samples[samples.len() - 1],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub frames: usize,
+    pub duration_sec: f32,
+    pub mean_fps: f32,
+    ///Alive at the end of the recording
+    pub kbots: usize,
+    pub scene_size: (u32, u32),
+    pub graphics: settings::Graphics,
+    pub frame_ms: Percentiles,
+    ///Cpu time to record each pass, wgpu has no timestamp queries to time them on the gpu
+    pub pass_ms: BTreeMap<String, Percentiles>,
+}
+
+pub struct Benchmark {
+    ///Game second of the first frame in Play
+    start_sec: Option<f32>,
+    ///Past the warmup, passes and frames are sampled
+    pub recording: bool,
+    frame_ms: Vec<f32>,
+    pass_ms: BTreeMap<String, Vec<f32>>,
+}
+
+impl Benchmark {
+    pub fn new() -> Self {
+        Benchmark {
+            start_sec: None,
+            recording: false,
+            frame_ms: Vec::new(),
+            pass_ms: BTreeMap::new(),
+        }
+    }
+
+    pub fn pass(&mut self, name: &str, duration: Duration) {
+        if self.recording {
+            self.pass_ms
+                .entry(name.to_owned())
+                .or_insert_with(Vec::new)
+                .push(duration.as_secs_f32() * 1000.0);
+        }
+    }
+}
+
+///Linear between the keys of CAMERA_PATH
+fn camera_at(t: f32) -> ([f32; 3], [f32; 3]) {
+    let next = CAMERA_PATH
+        .iter()
+        .position(|(key_t, _, _)| *key_t > t)
+        .unwrap_or(CAMERA_PATH.len() - 1)
+        .max(1);
+    let (t0, p0, d0) = CAMERA_PATH[next - 1];
+    let (t1, p1, d1) = CAMERA_PATH[next];
+    let k = ((t - t0) / (t1 - t0)).max(0.0).min(1.0);
+    let lerp = |a: [f32; 3], b: [f32; 3]| {
+        [
+            a[0] + (b[0] - a[0]) * k,
+            a[1] + (b[1] - a[1]) * k,
+            a[2] + (b[2] - a[2]) * k,
+        ]
+    };
+    (lerp(p0, p1), lerp(d0, d1))
+}
+
+impl App {
+    ///Plays the benchmark mission uncapped, the report is written before exiting
+    pub fn start_benchmark(&mut self) {
+        match trigger::Mission::open(BENCHMARK_MISSION) {
+            Ok(mission) => self.mission = Some(mission),
+            Err(e) => {
+                log::error!("Could not read {}: {}", BENCHMARK_MISSION, e);
+                return;
+            }
+        }
+        self.benchmark = Some(Benchmark::new());
+        self.loop_helper = LoopHelper::builder().build_with_target_rate(10000.0);
+        self.mailbox.push(RenderEvent::ChangeMode {
+            from: self.main_menu,
+            to: MainMode::Loading,
+        });
+        self.main_menu = MainMode::Loading;
+    }
+
+    ///Moves the camera along its path and samples the last frame time
+    pub fn step_benchmark(&mut self, frame_time: Duration) {
+        if self.main_menu != MainMode::Play {
+            return;
+        }
+        let sec = self.game_state.server_sec;
+        let benchmark = match self.benchmark.as_mut() {
+            Some(benchmark) => benchmark,
+            None => return,
+        };
+        let t = sec - *benchmark.start_sec.get_or_insert(sec);
+
+        let ([x, y, height], dir) = camera_at(t);
+        self.game_state.position = Point3::new(x, y, self.heightmap_gpu.phy.z(x, y) + height);
+        self.game_state.dir = Vector3::new(dir[0], dir[1], dir[2]);
+
+        if benchmark.recording {
+            benchmark.frame_ms.push(frame_time.as_secs_f32() * 1000.0);
+        }
+        benchmark.recording = t >= WARMUP_SEC;
+
+        if t >= WARMUP_SEC + DURATION_SEC {
+            let mut benchmark = self.benchmark.take().unwrap();
+            let report = self.benchmark_report(&mut benchmark);
+            log::info!(
+                "Benchmark: {} frames, {:.1} fps, frame p50 {:.2} ms p99 {:.2} ms",
+                report.frames,
+                report.mean_fps,
+                report.frame_ms.p50,
+                report.frame_ms.p99
+            );
+            let res = std::fs::File::create(REPORT_PATH)
+                .map_err(|e| format!("{}", e))
+                .and_then(|file| {
+                    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &report)
+                        .map_err(|e| format!("{}", e))
+                });
+            match res {
+                Ok(()) => log::info!("Benchmark report written to {}", REPORT_PATH),
+                Err(e) => log::error!("Can't write benchmark report {}: {}", REPORT_PATH, e),
+            }
+            self.sender_to_event_loop.send(EventLoopMsg::Stop).unwrap();
+        }
+    }
+
+    fn benchmark_report(&self, benchmark: &mut Benchmark) -> Report {
+        let frames = benchmark.frame_ms.len();
+        let total_ms: f32 = benchmark.frame_ms.iter().sum();
+        Report {
+            frames,
+            duration_sec: total_ms / 1000.0,
+            mean_fps: if total_ms > 0.0 {
+                frames as f32 * 1000.0 / total_ms
+            } else {
+                0.0
+            },
+            kbots: self.game_state.frame_zero.kbots.len(),
+            scene_size: self.scene_size(),
+            graphics: self.settings.applied.graphics.clone(),
+            frame_ms: Percentiles::of(&mut benchmark.frame_ms),
+            pass_ms: benchmark
+                .pass_ms
+                .iter_mut()
+                .map(|(name, samples)| (name.clone(), Percentiles::of(samples)))
+                .collect(),
+        }
+    }
+}
@@ -1,8 +1,11 @@
 extern crate nalgebra as na;
 use super::client::*;
-use na::{Matrix4, Point3, Vector3};
+use super::game_state::ActiveEffectPart;
+use crate::effect;
+use bytemuck::{Pod, Zeroable};
+use na::{Matrix4, Point3, Vector2, Vector3};
 
-const FOVY: f32 = 3.14 / 4.0;
+pub const FOVY: f32 = 3.14 / 4.0;
 const NEAR: f32 = 1.0;
 const FAR: f32 = 8000.0;
 
@@ -22,44 +25,136 @@ pub fn create_proj(aspect_ratio: f32, near: f32) -> Matrix4<f32> {
     mx_correction * mx_projection
 }
 
+///shake is post-multiplied on the view matrix, see create_shake
 pub fn create_view_proj(
     aspect_ratio: f32,
     near: f32,
     pos: &Point3<f32>,
     dir: &Vector3<f32>,
+    shake: &Matrix4<f32>,
 ) -> Matrix4<f32> {
-    let mx_view = create_view(pos, dir);
+    let mx_view = shake * create_view(pos, dir);
     let mx_proj = create_proj(aspect_ratio, near);
     mx_proj * mx_view
 }
 
-pub fn create_camera_uniform_vec(
+///Locals block of the shaders, bound at binding 0 of the main bind group
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct CameraUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+    pub normal: [[f32; 4]; 4],
+    pub mouse_pos: [f32; 2],
+    pub resolution: [f32; 2],
+    pub inv_resolution: [f32; 2],
+    pub start_drag: [f32; 2],
+    pub pen_radius: f32,
+    pub pen_strength: f32,
+    pub hmap_size: [f32; 2],
+    ///Animation time of the materials
+    pub time: f32,
+    ///Lighting bands of the toon style, 0 for smooth lighting
+    pub toon_bands: f32,
+    ///Scene resolution over the window one, for the ui layer drawn in window pixels
+    pub render_scale: f32,
+    pub _padding: f32,
+    ///view_proj of the last frame, for the motion vectors
+    pub last_view_proj: [[f32; 4]; 4],
+    ///Sub-pixel offset of the opaque geometry in clip space, xy, while TAA is on
+    pub jitter: [f32; 4],
+}
+
+///Matrices only, the caller fills the other members
+pub fn create_camera_uniform(
     screen_res: (u32, u32),
     near: f32,
     pos: &Point3<f32>,
     dir: &Vector3<f32>,
-) -> Vec<f32> {
-    let mut res = Vec::new();
-    //ViewProj
-    let mx_total = create_view_proj(screen_res.0 as f32 / screen_res.1 as f32, near, pos, dir);
-    let mx_ref: &[f32] = mx_total.as_slice();
-    res.extend_from_slice(mx_ref);
-    //View
-    let mx_total = create_view(pos, dir);
-    let mx_ref: &[f32] = mx_total.as_slice();
-    res.extend_from_slice(mx_ref);
-    //Proj
-    let mx_total = create_proj(screen_res.0 as f32 / screen_res.1 as f32, near);
-    let mx_ref: &[f32] = mx_total.as_slice();
-    res.extend_from_slice(mx_ref);
-    //Normal
-    let mx_total = create_normal(pos, dir);
-    let mx_ref: &[f32] = mx_total.as_slice();
-    res.extend_from_slice(mx_ref);
-    res
+    shake: &Matrix4<f32>,
+) -> CameraUniform {
+    let aspect_ratio = screen_res.0 as f32 / screen_res.1 as f32;
+    let view = shake * create_view(pos, dir);
+    CameraUniform {
+        view_proj: create_view_proj(aspect_ratio, near, pos, dir, shake).into(),
+        view: view.into(),
+        proj: create_proj(aspect_ratio, near).into(),
+        normal: view.try_inverse().unwrap().transpose().into(),
+        ..CameraUniform::zeroed()
+    }
+}
+
+///Radical inverse of index in base, 0 to 1
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+///Clip space offset of the frame, cycling over 8 points of the Halton (2, 3) sequence inside a
+///pixel so that TAA accumulates samples spread over it
+pub fn taa_jitter(frame: u32, screen_res: (u32, u32)) -> [f32; 4] {
+    let index = frame % 8 + 1;
+    [
+        (halton(index, 2) - 0.5) * 2.0 / screen_res.0 as f32,
+        (halton(index, 3) - 0.5) * 2.0 / screen_res.1 as f32,
+        0.0,
+        0.0,
+    ]
+}
+
+///Sum of the camera shakes felt at pos, in view space.
+///Each shake fades linearly with the distance to its origin and with time.
+pub fn create_shake(
+    pos: &Point3<f32>,
+    server_sec: f32,
+    active_effect_parts: &[ActiveEffectPart],
+) -> Matrix4<f32> {
+    let mut offset = Vector3::new(0.0, 0.0, 0.0);
+    let mut roll = 0.0;
+    for active in active_effect_parts.iter() {
+        if let effect::EffectPart::CameraShake {
+            amplitude,
+            frequency,
+            decay,
+            radius,
+        } = active.part
+        {
+            let t = (server_sec - active.born_sec).max(0.0);
+            let distance = (active.position.coords - pos.coords).magnitude();
+            let strength =
+                amplitude * (1.0 - decay * t).max(0.0) * (1.0 - distance / radius).max(0.0);
+            if strength > 0.0 {
+                let phase = 2.0 * std::f32::consts::PI * frequency * t;
+                offset += Vector3::new(phase.sin(), (phase * 1.3 + 1.7).sin(), 0.0) * strength;
+                roll += (phase * 0.7 + 0.5).sin() * strength * 0.02;
+            }
+        }
+    }
+    Matrix4::new_rotation(Vector3::new(0.0, 0.0, roll)) * Matrix4::new_translation(&offset)
 }
 
 impl App {
+    ///Keeps the camera height and direction, moving what it looks at
+    pub fn look_at(&mut self, x: f32, y: f32) {
+        let center = self
+            .game_state
+            .screen_center_world_pos
+            .map(|center| Vector2::new(center.x, center.y))
+            .unwrap_or_else(|| {
+                Vector2::new(self.game_state.position.x, self.game_state.position.y)
+            });
+        let shift = Vector2::new(x, y) - center;
+        self.game_state.position.x += shift.x;
+        self.game_state.position.y += shift.y;
+    }
+
     pub fn rts_camera(&mut self, sim_sec: f32) {
         use winit::event::VirtualKeyCode as Key;
         let key_pressed = &self.input_state.key_pressed;
@@ -93,8 +188,9 @@ impl App {
             .or(Some(screen_center_world_pos_fallback.coords))
             .map(|scwp| (self.game_state.position.coords - scwp).magnitude())
             .unwrap_or(height_from_ground);
-        let k =
-            (if !on(Key::LShift) { 1.0 } else { 2.0 }) * distance_camera_middle_screen.max(10.0);
+        let k = (if !on(Key::LShift) { 1.0 } else { 2.0 })
+            * self.game_state.camera_speed
+            * distance_camera_middle_screen.max(10.0);
         //Game
         if on(Key::S) {
             offset.y -= k;
@@ -0,0 +1,141 @@
+use super::client::*;
+use crate::*;
+use imgui::*;
+use serde::{Deserialize, Serialize};
+
+pub const CAMPAIGN_PATH: &str = "src/asset/mission/campaign.json";
+///Unlocked and completed missions, kept between sessions
+pub const PROGRESS_PATH: &str = "campaign_progress.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CampaignEntry {
+    pub title: String,
+    ///Mission json, also its id in the progress
+    pub path: String,
+    ///Playable without winning another mission first
+    #[serde(default)]
+    pub unlocked: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CampaignDef {
+    pub name: String,
+    pub missions: Vec<CampaignEntry>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Progress {
+    pub unlocked: Vec<String>,
+    pub completed: Vec<String>,
+}
+
+impl Progress {
+    ///Empty on the first run
+    pub fn open(path: &str) -> Self {
+        platform::read_json(path).unwrap_or_else(|e| {
+            log::info!("No campaign progress read from {}: {}", path, e);
+            Progress::default()
+        })
+    }
+
+    pub fn save(&self, path: &str) {
+        if let Err(e) = platform::write_json(path, self) {
+            log::error!("Can't write campaign progress {}: {}", path, e);
+        }
+    }
+}
+
+pub struct Campaign {
+    pub def: CampaignDef,
+    pub progress: Progress,
+}
+
+impl Campaign {
+    pub fn open() -> Self {
+        let def = std::fs::File::open(CAMPAIGN_PATH)
+            .map_err(|e| format!("{}", e))
+            .and_then(|file| {
+                serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}", e))
+            })
+            .unwrap_or_else(|e| {
+                log::error!("Could not read campaign {}: {}", CAMPAIGN_PATH, e);
+                CampaignDef {
+                    name: "Campaign".to_owned(),
+                    missions: Vec::new(),
+                }
+            });
+        Campaign {
+            def,
+            progress: Progress::open(PROGRESS_PATH),
+        }
+    }
+
+    pub fn is_unlocked(&self, entry: &CampaignEntry) -> bool {
+        entry.unlocked || self.progress.unlocked.contains(&entry.path)
+    }
+
+    pub fn is_completed(&self, entry: &CampaignEntry) -> bool {
+        self.progress.completed.contains(&entry.path)
+    }
+
+    ///After a victory in the mission at path, saved at once
+    pub fn complete(&mut self, path: &str, unlocks: &[String]) {
+        let progress = &mut self.progress;
+        if !progress.completed.iter().any(|p| p == path) {
+            progress.completed.push(path.to_owned());
+        }
+        for unlock in unlocks {
+            if !progress.unlocked.contains(unlock) {
+                log::info!("Campaign mission {} unlocked", unlock);
+                progress.unlocked.push(unlock.clone());
+            }
+        }
+        progress.save(PROGRESS_PATH);
+    }
+}
+
+pub enum CampaignChoice {
+    ///Path of the mission to play
+    Start(String),
+    Back,
+}
+
+impl App {
+    ///Mission list of the campaign menu, locked ones greyed out
+    pub fn draw_campaign_ui(
+        ui: &Ui,
+        campaign: &Campaign,
+        width: f32,
+        height: f32,
+    ) -> Option<CampaignChoice> {
+        let mut choice = None;
+        imgui::Window::new(&im_str!("{}", campaign.def.name))
+            .position([width / 2.0, height / 2.0], imgui::Condition::Always)
+            .position_pivot([0.5, 0.5])
+            .always_auto_resize(true)
+            .resizable(false)
+            .movable(false)
+            .collapsible(false)
+            .build(ui, || {
+                for entry in campaign.def.missions.iter() {
+                    let label = if campaign.is_completed(entry) {
+                        im_str!("{} (done)", entry.title)
+                    } else {
+                        im_str!("{}", entry.title)
+                    };
+                    if campaign.is_unlocked(entry) {
+                        if ui.button(&label, [300.0_f32, 60.0]) {
+                            choice = Some(CampaignChoice::Start(entry.path.clone()));
+                        }
+                    } else {
+                        ui.text_disabled(&im_str!("{} (locked)", entry.title));
+                    }
+                }
+                ui.separator();
+                if ui.button(im_str!("Back"), [300.0_f32, 60.0]) {
+                    choice = Some(CampaignChoice::Back);
+                }
+            });
+        choice
+    }
+}
@@ -0,0 +1,204 @@
+use super::client::*;
+use super::factory;
+use super::game_state;
+use crate::frame::{FrameEventFromPlayer, Player};
+use crate::replay::Replay;
+use crate::stats::Series;
+use crate::utils::Id;
+use crate::*;
+use fnv::FnvHashMap;
+use imgui::*;
+use na::{Point3, Vector3};
+
+///Replay opened from disk, watched once init_play gives it to the manager
+pub struct ReplayUi {
+    pub path: String,
+    pub last_frame: i32,
+    ///Taken by init_play
+    replay: Option<Replay>,
+}
+
+///Players of the game in lobby order
+fn players_by_slot(game_state: &game_state::State) -> Vec<&Player> {
+    let mut players: Vec<_> = game_state.players.values().collect();
+    players.sort_by_key(|p| p.slot);
+    players
+}
+
+///Builds in progress and queued items of a player, by unit name
+fn production(game_state: &game_state::State, player: &Player) -> Vec<(String, usize, usize)> {
+    let frame = &game_state.frame_zero;
+    let mut counts: FnvHashMap<String, (usize, usize)> = FnvHashMap::default();
+    let name = |botdef_id| {
+        frame
+            .bot_defs
+            .get(&botdef_id)
+            .map(factory::name)
+            .unwrap_or_default()
+    };
+    for kbot in player.kbots.iter().filter_map(|id| frame.kbots.get(id)) {
+        if kbot.con_completed < 1.0 {
+            counts.entry(name(kbot.botdef_id)).or_default().0 += 1;
+        }
+        for item in kbot.build_queue.items.iter() {
+            counts.entry(name(*item)).or_default().1 += 1;
+        }
+    }
+    let mut production: Vec<_> = counts
+        .into_iter()
+        .map(|(name, (building, queued))| (name, building, queued))
+        .collect();
+    production.sort();
+    production
+}
+
+impl App {
+    ///Replay in place of the map and the game, then Loading as for a new game
+    pub fn start_replay(&mut self, path: &str) {
+        let replay = match Replay::open(path) {
+            Ok(replay) => replay,
+            Err(e) => {
+                log::error!("Can't read replay {}: {}", path, e);
+                return;
+            }
+        };
+        if let Some(phy) = &replay.start.heightmap_phy {
+            self.heightmap_gpu.phy = phy.clone();
+            let (width, height) = (phy.width as u32, phy.height as u32);
+            self.heightmap_gpu.update_rect(0, 0, width, height);
+        }
+        log::info!("Watching {} up to frame {}", path, replay.last_frame);
+        self.replay = Some(ReplayUi {
+            path: path.to_owned(),
+            last_frame: replay.last_frame,
+            replay: Some(replay),
+        });
+        self.mailbox.push(RenderEvent::ChangeMode {
+            from: self.main_menu,
+            to: MainMode::Loading,
+        });
+        self.main_menu = MainMode::Loading;
+    }
+
+    ///Gives the replay to the manager, true when a replay is watched instead of a new game
+    pub fn init_replay(&mut self) -> bool {
+        let replay = match self.replay.as_mut().and_then(|r| r.replay.take()) {
+            Some(replay) => replay,
+            None => return false,
+        };
+        self.clear_gpu_instance_and_game_state();
+        self.game_state.position =
+            Point3::new(300.0, 100.0, self.heightmap_gpu.phy.z(300.0, 100.0) + 50.0);
+        self.game_state.dir = Vector3::new(0.0, 0.3, -1.0);
+        //Watched as a spectator, no order can be given
+        self.game_state.my_player_id = None;
+        self.game_state.players = replay.start.players.clone();
+        let _ = self
+            .sender_from_client_to_manager
+            .try_send(client::FromClient::PlayReplay(replay));
+        true
+    }
+
+    ///Income and army value of every player side by side, and what each one is building
+    pub fn draw_caster_ui(ui: &Ui, game_state: &game_state::State) {
+        let players = players_by_slot(game_state);
+        let last =
+            |player: &Player, series| player.stats.series(series).last().copied().unwrap_or(0.0);
+        let max_of = |series| {
+            players
+                .iter()
+                .map(|p| last(*p, series))
+                .fold(0.0_f32, f32::max)
+                .max(1.0)
+        };
+        let (max_income, max_army) = (max_of(Series::MetalIncome), max_of(Series::ArmyValue));
+
+        imgui::Window::new(im_str!("Caster"))
+            .size([350.0, 300.0], imgui::Condition::FirstUseEver)
+            .position([910.0, 210.0], imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                for series in &[Series::MetalIncome, Series::ArmyValue] {
+                    ui.text(im_str!("{}", series.name()));
+                    let max = if *series == Series::MetalIncome {
+                        max_income
+                    } else {
+                        max_army
+                    };
+                    for player in players.iter() {
+                        let value = last(*player, *series);
+                        ProgressBar::new(value / max)
+                            .overlay_text(&im_str!("Player {} {:.0}", player.id, value))
+                            .build(ui);
+                    }
+                }
+                ui.separator();
+                if ui.collapsing_header(im_str!("Production")).build() {
+                    for player in players.iter().filter(|p| !p.defeated) {
+                        let away = if player.away { " away" } else { "" };
+                        ui.text(im_str!(
+                            "Player {} (team {}){}",
+                            player.id,
+                            player.team,
+                            away
+                        ));
+                        for (name, building, queued) in production(game_state, player) {
+                            ui.bullet_text(&im_str!(
+                                "{} building {} queued {}",
+                                name,
+                                building,
+                                queued
+                            ));
+                        }
+                    }
+                }
+            });
+    }
+
+    ///Frame slider seeking in the replay, with its own pause as nobody votes in a replay
+    pub fn draw_timeline_ui(
+        ui: &Ui,
+        replay: &ReplayUi,
+        game_state: &game_state::State,
+        global_info: Option<manager::GlobalInfo>,
+        width: f32,
+    ) -> Vec<client::FromClient> {
+        let mut messages = Vec::new();
+        let last_frame = replay.last_frame;
+        let mut frame = game_state.frame_zero.number.min(last_frame);
+        let game_speed = global_info.map(|info| info.manager.game_speed);
+        let paused = game_speed.map(|speed| speed.paused).unwrap_or(false);
+        imgui::Window::new(im_str!("Timeline"))
+            .size([width * 0.6, 60.0], imgui::Condition::FirstUseEver)
+            .position([width * 0.2, 70.0], imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(im_str!("{}", replay.path));
+                ui.same_line(0.0);
+                let pause_label = if paused {
+                    im_str!("Resume")
+                } else {
+                    im_str!("Pause")
+                };
+                if ui.small_button(pause_label) {
+                    //Offline the vote is applied whoever the player is
+                    let event = FrameEventFromPlayer::SpeedVote {
+                        id: Id::new(0),
+                        speed: game_speed.map(|speed| speed.speed).unwrap_or(1.0),
+                        paused: !paused,
+                    };
+                    messages.push(client::FromClient::PlayerInput(event));
+                }
+                ui.same_line(0.0);
+                let label = im_str!(
+                    "{:02}:{:02} / {:02}:{:02}",
+                    frame / 600,
+                    frame / 10 % 60,
+                    last_frame / 600,
+                    last_frame / 10 % 60
+                );
+                if imgui::Slider::new(&label, 0..=last_frame).build(ui, &mut frame) {
+                    messages.push(client::FromClient::SeekReplay(frame));
+                }
+            });
+        messages
+    }
+}
@@ -0,0 +1,149 @@
+use super::client::*;
+use super::uitool::UiTool;
+use serde::{Deserialize, Serialize};
+use winit::window::CursorIcon;
+
+///What a right click would do at the cursor, shown by its icon
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CursorContext {
+    Default,
+    Move,
+    Attack,
+    Repair,
+    Build,
+    Guard,
+    ///The order under the cursor would be refused
+    Invalid,
+}
+
+impl CursorContext {
+    ///Cell in cursor_icons.png, a 4 by 4 atlas
+    pub fn atlas_cell(self) -> (u32, u32) {
+        match self {
+            CursorContext::Default => (2, 0),
+            CursorContext::Move => (3, 0),
+            CursorContext::Attack => (1, 1),
+            CursorContext::Repair => (0, 0),
+            CursorContext::Build => (1, 0),
+            CursorContext::Guard => (2, 1),
+            CursorContext::Invalid => (0, 1),
+        }
+    }
+
+    ///Point of the cell that sits on the mouse, from its top left corner in cell units
+    pub fn hotspot(self) -> (f32, f32) {
+        match self {
+            //Tip of the arrow and of the pin
+            CursorContext::Default | CursorContext::Move => (0.5, 0.9),
+            _ => (0.5, 0.5),
+        }
+    }
+
+    ///Closest system cursor, winit can't load custom images
+    pub fn system_icon(self) -> CursorIcon {
+        match self {
+            CursorContext::Default => CursorIcon::Default,
+            CursorContext::Move => CursorIcon::Move,
+            CursorContext::Attack => CursorIcon::Crosshair,
+            CursorContext::Repair => CursorIcon::Cell,
+            CursorContext::Build => CursorIcon::Copy,
+            CursorContext::Guard => CursorIcon::Hand,
+            CursorContext::Invalid => CursorIcon::NotAllowed,
+        }
+    }
+}
+
+///How the cursor is drawn
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum CursorMode {
+    ///System cursors, no input lag but look different on each platform
+    Hardware,
+    ///Hidden system cursor and an atlas quad drawn in the ui pass, same look everywhere
+    Software,
+}
+
+impl CursorMode {
+    pub const ALL: [CursorMode; 2] = [CursorMode::Hardware, CursorMode::Software];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CursorMode::Hardware => "hardware",
+            CursorMode::Software => "software",
+        }
+    }
+}
+
+impl App {
+    ///Mirrors the right click handling of render
+    pub fn cursor_context(&self) -> CursorContext {
+        let (x, y) = self.input_state.cursor_pos;
+        if self.main_menu != MainMode::Play
+            || self.imgui_wrap.imgui.io().want_capture_mouse
+            || self.hud.contains(x as f32, y as f32)
+        {
+            return CursorContext::Default;
+        }
+        let mouse_world_pos = match self.game_state.mouse_world_pos {
+            Some(pos) => pos,
+            None if self.game_state.uitool == UiTool::None => return CursorContext::Default,
+            None => return CursorContext::Invalid,
+        };
+
+        let frame_zero = &self.game_state.frame_zero;
+        let under_mouse = self
+            .game_state
+            .under_mouse
+            .and_then(|under| frame_zero.kbots.get(&under));
+        let under_enemy = match (under_mouse, self.game_state.my_player()) {
+            (Some(kbot), Some(player)) => kbot.team != player.team,
+            _ => false,
+        };
+
+        match self.game_state.uitool {
+            UiTool::None | UiTool::Move => {
+                if self.game_state.selected.is_empty() {
+                    CursorContext::Default
+                } else if under_enemy {
+                    CursorContext::Attack
+                } else {
+                    CursorContext::Move
+                }
+            }
+            UiTool::Spawn(botdef_id) => {
+                let can_be_placed = frame_zero
+                    .bot_defs
+                    .get(&botdef_id)
+                    .map(|botdef| {
+                        let (x, y) = botdef.snap(mouse_world_pos.x, mouse_world_pos.y);
+                        botdef.can_be_placed(&self.heightmap_gpu.phy, x, y)
+                    })
+                    .unwrap_or(false);
+                if can_be_placed {
+                    CursorContext::Build
+                } else {
+                    CursorContext::Invalid
+                }
+            }
+            //Also dragged on the ground for an area order
+            UiTool::Repair | UiTool::Reclaim if !under_enemy => CursorContext::Repair,
+            UiTool::Repair | UiTool::Reclaim => CursorContext::Invalid,
+            UiTool::Load => {
+                let can_be_loaded = under_mouse
+                    .and_then(|kbot| frame_zero.bot_defs.get(&kbot.botdef_id))
+                    .map(|botdef| botdef.can_be_transported())
+                    .unwrap_or(false);
+                if can_be_loaded {
+                    CursorContext::Move
+                } else {
+                    CursorContext::Invalid
+                }
+            }
+            UiTool::Unload | UiTool::Patrol | UiTool::Ferry => CursorContext::Move,
+            UiTool::Guard => match under_mouse {
+                Some(_) if !under_enemy => CursorContext::Guard,
+                _ => CursorContext::Invalid,
+            },
+            UiTool::Attack | UiTool::Nuke | UiTool::DGun => CursorContext::Attack,
+        }
+    }
+}
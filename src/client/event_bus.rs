@@ -0,0 +1,84 @@
+use crate::game_event::{GameEvent, GameEventKind};
+use std::collections::VecDeque;
+
+///Events kept for a consumer that doesn't run, the oldest are dropped
+const MAX_QUEUED: usize = 1024;
+
+///System of the client reacting to what happened in the simulation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consumer {
+    ///Voice cues, through the sounds of the effect library
+    Audio,
+    ///Death explosions and impact flashes
+    Effects,
+    ///Alerts of the player, see alert::Alerts
+    Notifications,
+    ///Conditions of the mission triggers, see trigger::Condition::Happened
+    Triggers,
+}
+
+impl Consumer {
+    const ALL: [Consumer; 4] = [
+        Consumer::Audio,
+        Consumer::Effects,
+        Consumer::Notifications,
+        Consumer::Triggers,
+    ];
+
+    ///Kinds of events queued for it
+    fn subscribes(self, kind: GameEventKind) -> bool {
+        match self {
+            Consumer::Audio => kind == GameEventKind::BuildingCompleted,
+            Consumer::Effects => {
+                kind == GameEventKind::UnitDied || kind == GameEventKind::ProjectileImpact
+            }
+            Consumer::Notifications => {
+                kind == GameEventKind::UnitDied || kind == GameEventKind::ResourceDepleted
+            }
+            Consumer::Triggers => true,
+        }
+    }
+}
+
+///Frame::events of the frames received, queued for each consumer. Each system drains its own
+///queue when it runs: the effects with every frame, the triggers with every rendered frame.
+pub struct EventBus {
+    ///Indexed like Consumer::ALL
+    queues: Vec<VecDeque<GameEvent>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            queues: vec![VecDeque::new(); Consumer::ALL.len()],
+        }
+    }
+
+    pub fn publish(&mut self, events: &[GameEvent]) {
+        for (consumer, queue) in Consumer::ALL.iter().zip(self.queues.iter_mut()) {
+            queue.extend(
+                events
+                    .iter()
+                    .filter(|event| consumer.subscribes(event.kind()))
+                    .cloned(),
+            );
+            if queue.len() > MAX_QUEUED {
+                let excess = queue.len() - MAX_QUEUED;
+                log::debug!("{:?} drops {} events", consumer, excess);
+                queue.drain(..excess);
+            }
+        }
+    }
+
+    ///Oldest first
+    pub fn drain(&mut self, consumer: Consumer) -> Vec<GameEvent> {
+        let i = Consumer::ALL.iter().position(|&c| c == consumer).unwrap();
+        self.queues[i].drain(..).collect()
+    }
+
+    pub fn clear(&mut self) {
+        for queue in self.queues.iter_mut() {
+            queue.clear();
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use super::client::*;
+use super::game_state;
+use crate::botdef::BotDef;
+use crate::frame::FrameEventFromPlayer;
+use crate::mobile::QueueEdit;
+use crate::utils::Id;
+use crate::*;
+use imgui::*;
+use serde::{Deserialize, Serialize};
+
+pub const TEMPLATES_PATH: &str = "build_templates.json";
+
+///Queue preset replacing the queue of the selected factories in one click
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Template {
+    pub name: String,
+    pub items: Vec<Id<BotDef>>,
+}
+
+pub struct FactoryUi {
+    pub templates: Vec<Template>,
+    ///Name of the next template saved
+    pub name: ImString,
+    ///Index of the queued item being dragged
+    pub dragged: Option<usize>,
+}
+
+impl FactoryUi {
+    ///No template on the first run
+    pub fn new() -> Self {
+        let templates = platform::read_json(TEMPLATES_PATH).unwrap_or_else(|e| {
+            log::info!("No build templates read from {}: {}", TEMPLATES_PATH, e);
+            Vec::new()
+        });
+        FactoryUi {
+            templates,
+            name: ImString::with_capacity(32),
+            dragged: None,
+        }
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = platform::write_json(TEMPLATES_PATH, &self.templates) {
+            log::error!("Can't write build templates {}: {}", TEMPLATES_PATH, e);
+        }
+    }
+}
+
+pub fn name(botdef: &BotDef) -> String {
+    std::path::Path::new(&botdef.file_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl App {
+    ///Queue of the first selected factory, edits are given to every selected factory
+    pub fn draw_factory_ui(
+        ui: &Ui,
+        factory: &mut FactoryUi,
+        game_state: &game_state::State,
+    ) -> Vec<FrameEventFromPlayer> {
+        let mut edits = Vec::new();
+        let frame_zero = &game_state.frame_zero;
+        let id = match game_state.my_player_id {
+            Some(id) => id,
+            None => return Vec::new(),
+        };
+        let first = game_state
+            .selected
+            .iter()
+            .filter_map(|id| frame_zero.kbots.get(id))
+            .find(|kbot| {
+                frame_zero
+                    .bot_defs
+                    .get(&kbot.botdef_id)
+                    .map(|botdef| botdef.is_factory())
+                    .unwrap_or(false)
+            });
+        let first = match first {
+            Some(first) => first,
+            None => {
+                factory.dragged = None;
+                return Vec::new();
+            }
+        };
+        let mut botdefs: Vec<_> = frame_zero
+            .bot_defs
+            .values()
+            .filter(|botdef| !botdef.commander)
+            .collect();
+        botdefs.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+        imgui::Window::new(im_str!("Factory"))
+            .size([300.0, 300.0], imgui::Condition::FirstUseEver)
+            .position([406.0, 415.0], imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                for botdef in botdefs.iter() {
+                    if ui.small_button(&im_str!("+ {}##{}", name(botdef), botdef.id)) {
+                        edits.push(QueueEdit::Push(botdef.id));
+                    }
+                }
+
+                let mut repeat = first.build_queue.repeat;
+                if ui.checkbox(im_str!("repeat"), &mut repeat) {
+                    edits.push(QueueEdit::Repeat(repeat));
+                }
+
+                ui.separator();
+                let mut hovered = None;
+                for (i, item) in first.build_queue.items.iter().enumerate() {
+                    let label = frame_zero.bot_defs.get(item).map(name).unwrap_or_default();
+                    if ui.small_button(&im_str!("x##remove{}", i)) {
+                        edits.push(QueueEdit::Remove(i));
+                    }
+                    ui.same_line(0.0);
+                    Selectable::new(&im_str!("{}. {}##queued{}", i + 1, label, i))
+                        .selected(factory.dragged == Some(i))
+                        .build(ui);
+                    if ui.is_item_active() && factory.dragged.is_none() {
+                        factory.dragged = Some(i);
+                    }
+                    if ui.is_item_hovered_with_flags(
+                        ItemHoveredFlags::ALLOW_WHEN_BLOCKED_BY_ACTIVE_ITEM,
+                    ) {
+                        hovered = Some(i);
+                    }
+                }
+                if ui.is_mouse_released(MouseButton::Left) {
+                    //Dropped on another row
+                    if let (Some(from), Some(to)) = (factory.dragged.take(), hovered) {
+                        if from != to {
+                            edits.push(QueueEdit::Move { from, to });
+                        }
+                    }
+                }
+
+                ui.separator();
+                for (i, template) in factory.templates.iter().enumerate() {
+                    if ui.small_button(&im_str!("{}##template{}", template.name, i)) {
+                        edits.push(QueueEdit::Replace(template.items.clone()));
+                    }
+                }
+                ui.input_text(im_str!("name"), &mut factory.name).build();
+                if ui.small_button(im_str!("Save queue")) && !factory.name.to_str().is_empty() {
+                    let name = factory.name.to_str().to_owned();
+                    let items = first.build_queue.items.clone();
+                    match factory.templates.iter_mut().find(|t| t.name == name) {
+                        Some(template) => template.items = items,
+                        None => factory.templates.push(Template { name, items }),
+                    }
+                    factory.name.clear();
+                    factory.save();
+                }
+            });
+
+        edits
+            .into_iter()
+            .map(|edit| FrameEventFromPlayer::QueueOrder {
+                id,
+                selected: game_state.selected.clone(),
+                edit,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,373 @@
+use crate::gpu_obj::depth_mode::DepthMode;
+use crate::gpu_obj::dof::DofQuality;
+use crate::gpu_obj::draw_list::Layer;
+use crate::gpu_obj::oit;
+use crate::gpu_obj::render_graph::{Clear, Pass, PassId, RenderGraph, TextureId};
+use crate::gpu_obj::ssr;
+use crate::gpu_obj::taa;
+use crate::gpu_obj::upscale::Upscale;
+use wgpu::{TextureFormat, TextureUsage, TextureView};
+
+const BACKGROUND: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+///Pixels where nothing was drawn
+const UNSET: wgpu::Color = wgpu::Color {
+    r: -1.0,
+    g: -1.0,
+    b: -1.0,
+    a: -1.0,
+};
+
+///Textures and passes of a frame
+pub struct FrameGraph {
+    pub graph: RenderGraph,
+    pub first_color: TextureId,
+    pub secon_color: TextureId,
+    ///first_color blurred by the depth of field
+    pub dof_color: TextureId,
+    ///Output of the color grading, at the scene resolution
+    pub graded: TextureId,
+    ///graded upscaled to the window, under the ui
+    pub ui_color: TextureId,
+    pub position: TextureId,
+    pub normal: TextureId,
+    ///Motion vectors of the opaque geometry
+    pub velocity: TextureId,
+    ///secon_color of the last frame, resolved by the taa
+    pub taa_history: TextureId,
+    pub reflection: TextureId,
+    ///Sampled by the HiZ after the pre-pass
+    pub depth: TextureId,
+    pub oit_accum: TextureId,
+    pub oit_reveal: TextureId,
+    pub depth_prepass: PassId,
+    pub main: PassId,
+    pub ssr: PassId,
+    pub ssr_composite: PassId,
+    ///Lines of the toon style over the opaque geometry
+    pub outline: PassId,
+    ///Economy overlay over the opaque geometry, under the transparents
+    pub economy_overlay: PassId,
+    ///Grid of simulation data over the terrain, see heatmap::HeatmapOverlay
+    pub heatmap: PassId,
+    ///Range circles of the selected units and of the unit being placed
+    pub range_rings: PassId,
+    pub transparent: PassId,
+    pub transparent_oit: PassId,
+    pub oit_composite: PassId,
+    pub post: PassId,
+    pub dof: PassId,
+    pub fxaa: PassId,
+    pub taa: PassId,
+    ///Copies secon_color to taa_history
+    pub taa_copy: PassId,
+    pub grade: PassId,
+    pub upscale: PassId,
+    pub ui: PassId,
+    pub copy: PassId,
+    ///Draw the opaque layers depth only before the main pass. Off, the pre-pass only clears depth.
+    pub use_depth_prepass: bool,
+    ///Draw shields and trails in the order-independent transparent pass instead of sorted
+    pub use_oit: bool,
+    ///Off, glossy surfaces and water only reflect the environment cube
+    pub use_ssr: bool,
+    pub dof_quality: DofQuality,
+    ///Temporal anti-aliasing instead of the fxaa, the opaque geometry is jittered
+    pub use_taa: bool,
+    ///Scene resolution over the window one, from 0.5 for weak gpus to 2.0 for supersampling
+    pub render_scale: f32,
+    pub upscale_mode: Upscale,
+    ///Scene size the graph was compiled for
+    compiled_scene_size: (u32, u32),
+}
+
+impl FrameGraph {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let mut graph = RenderGraph::new();
+        let color_usage = TextureUsage::SAMPLED | TextureUsage::COPY_SRC;
+        let first_color = graph.texture(
+            "first_color",
+            TextureFormat::Bgra8UnormSrgb,
+            color_usage,
+            Clear::Color(BACKGROUND),
+        );
+        let secon_color = graph.texture(
+            "secon_color",
+            TextureFormat::Bgra8UnormSrgb,
+            color_usage,
+            Clear::Color(BACKGROUND),
+        );
+        let dof_color = graph.texture(
+            "dof_color",
+            TextureFormat::Bgra8UnormSrgb,
+            color_usage,
+            Clear::Color(BACKGROUND),
+        );
+        let graded = graph.texture(
+            "graded",
+            TextureFormat::Bgra8UnormSrgb,
+            color_usage,
+            Clear::Color(BACKGROUND),
+        );
+        let ui_color = graph.texture(
+            "ui_color",
+            TextureFormat::Bgra8UnormSrgb,
+            color_usage,
+            Clear::Color(BACKGROUND),
+        );
+        graph.window_sized(ui_color);
+        let position = graph.texture(
+            "position",
+            TextureFormat::Rgba32Float,
+            TextureUsage::SAMPLED | TextureUsage::COPY_SRC,
+            Clear::Color(UNSET),
+        );
+        let normal = graph.texture(
+            "normal",
+            TextureFormat::Rgba16Float,
+            TextureUsage::SAMPLED,
+            Clear::Color(UNSET),
+        );
+        let velocity = graph.texture(
+            "velocity",
+            taa::VELOCITY_FORMAT,
+            TextureUsage::SAMPLED,
+            Clear::Color(wgpu::Color::BLACK),
+        );
+        let taa_history = graph.texture(
+            "taa_history",
+            TextureFormat::Bgra8UnormSrgb,
+            color_usage,
+            Clear::Color(BACKGROUND),
+        );
+        let depth = graph.texture(
+            "depth",
+            TextureFormat::Depth32Float,
+            TextureUsage::SAMPLED,
+            Clear::Depth(1.0),
+        );
+        let reflection = graph.texture(
+            "reflection",
+            ssr::REFLECTION_FORMAT,
+            TextureUsage::SAMPLED,
+            Clear::Color(UNSET),
+        );
+        let oit_accum = graph.texture(
+            "oit_accum",
+            oit::ACCUM_FORMAT,
+            TextureUsage::SAMPLED,
+            Clear::Color(wgpu::Color::TRANSPARENT),
+        );
+        let oit_reveal = graph.texture(
+            "oit_reveal",
+            oit::REVEAL_FORMAT,
+            TextureUsage::SAMPLED,
+            Clear::Color(wgpu::Color::WHITE),
+        );
+        //The reflections sample the last frame, the cursor position is copied after the passes
+        graph.persist(secon_color);
+        graph.persist(position);
+        graph.persist(taa_history);
+
+        let depth_prepass = graph.add_pass(Pass::new("depth_prepass").depth(depth));
+        let main = graph.add_pass(
+            Pass::new("main")
+                .color(first_color)
+                .color(position)
+                .color(normal)
+                .color(velocity)
+                .depth(depth),
+        );
+        let ssr = graph.add_pass(
+            Pass::new("ssr")
+                .color(reflection)
+                .read(position)
+                .read(normal),
+        );
+        let ssr_composite = graph.add_pass(
+            Pass::new("ssr_composite")
+                .color(first_color)
+                .read(reflection),
+        );
+        let outline = graph.add_pass(
+            Pass::new("outline")
+                .color(first_color)
+                .read(position)
+                .read(normal),
+        );
+        let economy_overlay = graph.add_pass(
+            Pass::new("economy_overlay")
+                .color(first_color)
+                .read(position),
+        );
+        let heatmap = graph.add_pass(Pass::new("heatmap").color(first_color).read(position));
+        let range_rings =
+            graph.add_pass(Pass::new("range_rings").color(first_color).read(position));
+        let transparent = graph.add_pass(
+            Pass::new("transparent")
+                .color(first_color)
+                .depth(depth)
+                .read(position)
+                .read(reflection),
+        );
+        let transparent_oit = graph.add_pass(
+            Pass::new("transparent_oit")
+                .color(oit_accum)
+                .color(oit_reveal)
+                .depth(depth),
+        );
+        let oit_composite = graph.add_pass(
+            Pass::new("oit_composite")
+                .color(first_color)
+                .read(oit_accum)
+                .read(oit_reveal),
+        );
+        let post = graph.add_pass(
+            Pass::new("post")
+                .color(first_color)
+                .read(position)
+                .read(normal),
+        );
+        let dof = graph.add_pass(
+            Pass::new("dof")
+                .color(dof_color)
+                .read(first_color)
+                .read(position),
+        );
+        let fxaa = graph.add_pass(
+            Pass::new("fxaa")
+                .color(secon_color)
+                .read(first_color)
+                .read(dof_color),
+        );
+        //Off, the fxaa writes secon_color alone. The history is read by the taa the next frame.
+        let taa = graph.add_pass(
+            Pass::new("taa")
+                .color(secon_color)
+                .read(first_color)
+                .read(dof_color)
+                .read(velocity),
+        );
+        let taa_copy = graph.add_pass(Pass::new("taa_copy").color(taa_history).read(secon_color));
+        let grade = graph.add_pass(Pass::new("grade").color(graded).read(secon_color));
+        let upscale = graph.add_pass(Pass::new("upscale").color(ui_color).read(graded));
+        let ui = graph.add_pass(Pass::new("ui").color(ui_color));
+        let copy = graph.add_pass(Pass::new("copy").frame().read(ui_color));
+
+        let mut frame_graph = FrameGraph {
+            graph,
+            first_color,
+            secon_color,
+            dof_color,
+            graded,
+            ui_color,
+            position,
+            normal,
+            velocity,
+            taa_history,
+            reflection,
+            depth,
+            oit_accum,
+            oit_reveal,
+            depth_prepass,
+            main,
+            ssr,
+            ssr_composite,
+            outline,
+            economy_overlay,
+            heatmap,
+            range_rings,
+            transparent,
+            transparent_oit,
+            oit_composite,
+            post,
+            dof,
+            fxaa,
+            taa,
+            taa_copy,
+            grade,
+            upscale,
+            ui,
+            copy,
+            use_depth_prepass: false,
+            use_oit: false,
+            use_ssr: true,
+            dof_quality: DofQuality::Off,
+            use_taa: false,
+            render_scale: 1.0,
+            upscale_mode: Upscale::Sharpen,
+            compiled_scene_size: (0, 0),
+        };
+        frame_graph.compile(device, width, height);
+        frame_graph
+    }
+
+    ///Window size times render_scale
+    pub fn scene_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = |size: u32| ((size as f32 * self.render_scale).round() as u32).max(1);
+        (scale(width), scale(height))
+    }
+
+    ///Whether render_scale changed since the last compile
+    pub fn needs_compile(&self, width: u32, height: u32) -> bool {
+        self.scene_size(width, height) != self.compiled_scene_size
+    }
+
+    ///On each resize or change of render_scale, see RenderGraph::compile
+    pub fn compile(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let scene_size = self.scene_size(width, height);
+        log::debug!(
+            "FrameGraph window {}x{}, scene {}x{}",
+            width,
+            height,
+            scene_size.0,
+            scene_size.1
+        );
+        self.graph.compile(device, (width, height), scene_size);
+        self.compiled_scene_size = scene_size;
+    }
+
+    pub fn view(&self, texture: TextureId) -> &TextureView {
+        self.graph.view(texture)
+    }
+
+    ///Color the fxaa or the taa reads: the blurred one when the depth of field is on
+    pub fn aa_source(&self) -> TextureId {
+        if self.dof_quality == DofQuality::Off {
+            self.first_color
+        } else {
+            self.dof_color
+        }
+    }
+
+    ///Layers of the draw list recorded in pass, before its fullscreen draws
+    pub fn layers(&self, pass: PassId) -> &'static [Layer] {
+        if pass == self.depth_prepass && !self.use_depth_prepass {
+            &[]
+        } else if pass == self.main || pass == self.depth_prepass {
+            &[Layer::OpaqueTerrain, Layer::OpaqueModels]
+        } else if pass == self.transparent {
+            &[Layer::Decals, Layer::Transparent, Layer::Effects]
+        } else if pass == self.transparent_oit && self.use_oit {
+            &[Layer::Transparent, Layer::Effects]
+        } else if pass == self.ui {
+            &[Layer::Ui]
+        } else {
+            &[]
+        }
+    }
+    ///Pipelines of the opaque draws recorded in pass
+    pub fn depth_mode(&self, pass: PassId) -> DepthMode {
+        if pass == self.depth_prepass {
+            DepthMode::PrePass
+        } else if self.use_depth_prepass {
+            DepthMode::Equal
+        } else {
+            DepthMode::Write
+        }
+    }
+}
@@ -1,14 +1,24 @@
 extern crate nalgebra as na;
+use super::alert;
+use super::event_bus::{Consumer, EventBus};
 use super::heightmap_editor;
+use super::ping;
+use super::trail;
 use crate::botdef;
+use crate::effect;
 use crate::frame::Frame;
+use crate::game_event::GameEvent;
+use crate::gpu_obj::point_light::PointLight;
+use crate::gpu_obj::toon::RenderStyle;
 use crate::mobile;
+use crate::platform::Instant;
 use crate::utils;
+use crate::vision;
 use fnv::{FnvHashMap, FnvHashSet};
 use na::{Matrix4, Point3, Vector2, Vector3};
-use std::time::Instant;
 use utils::*;
 
+use super::cursor::CursorMode;
 use super::uitool::UiTool;
 use crate::frame::Player;
 use mobile::*;
@@ -22,6 +32,25 @@ pub struct Explosion {
     pub seed: f32,
 }
 
+///Non particle part of an effect, alive until death_sec
+#[derive(Clone, Debug)]
+pub struct ActiveEffectPart {
+    pub position: Point3<f32>,
+    pub part: effect::EffectPart,
+    pub born_sec: f32,
+    pub death_sec: f32,
+}
+
+///Beam shot, drawn until death_sec
+#[derive(Clone, Debug)]
+pub struct ActiveBeam {
+    pub from: Point3<f32>,
+    pub to: Point3<f32>,
+    pub color: [f32; 3],
+    pub born_sec: f32,
+    pub death_sec: f32,
+}
+
 pub struct State {
     pub position: Point3<f32>,
     pub dir: Vector3<f32>,
@@ -38,23 +67,41 @@ pub struct State {
     pub frame_minus_one: Frame,
     pub frame_zero: Frame,
     pub frame_zero_time_received: Instant,
+    ///Smoothed duration between two frames, shorter when the game is sped up
+    pub frame_period_sec: f64,
 
     //Interpolated from curve
     pub kbots: Vec<(KBot, ClientKbot)>,
     pub server_sec: f32,
     //Extrapolated from events
     pub explosions: Vec<Explosion>,
+    pub active_effect_parts: Vec<ActiveEffectPart>,
+    pub beams: Vec<ActiveBeam>,
+    pub effect_library: effect::EffectLibrary,
     pub kinematic_projectiles_cache: FnvHashMap<Id<KinematicProjectile>, KinematicProjectile>,
     pub kinematic_projectiles: Vec<Point3<f32>>,
+    pub trails: trail::TrailSet,
+    pub pings: Vec<ping::ActivePing>,
+    pub alerts: alert::Alerts,
+    ///Frame::events of the frames received, for the systems presenting them
+    pub events: EventBus,
 
     pub selected: FnvHashSet<Id<KBot>>,
     pub under_mouse: Option<Id<KBot>>,
+    ///Where the right button went down with the repair or reclaim tool, see App::area_circle
+    pub area_center: Option<Vector3<f32>>,
+    ///First click with the ferry tool, the dropoff is the second
+    pub ferry_pickup: Option<Vector3<f32>>,
     pub uitool: UiTool,
 
     pub start_time: Instant,
     pub last_frame: Instant,
 
     pub my_player_id: Option<Id<Player>>,
+    ///Player whose point of view is shown to spectators, None to see every player
+    pub observed_player_id: Option<Id<Player>>,
+    ///Spectators only see what the observed player and its allies see
+    pub perspective: bool,
 
     pub players: FnvHashMap<Id<Player>, Player>,
 
@@ -62,6 +109,19 @@ pub struct State {
 
     //parameters
     pub unit_icon_distance: f32,
+    ///From this distance to unit_icon_distance the units are billboards
+    pub impostor_distance: f32,
+    ///Can be turned off for players sensitive to motion
+    pub camera_shake: bool,
+    ///Chosen before the match
+    pub render_style: RenderStyle,
+    ///Metal coverage grid over the terrain, toggled with F4
+    pub economy_overlay: bool,
+    pub cursor_mode: CursorMode,
+    ///Multiplies the keyboard scroll speed
+    pub camera_speed: f32,
+    ///Over the hidpi factor of the screen, for the hud, imgui and the software cursor
+    pub ui_scale: f32,
 }
 
 impl State {
@@ -80,45 +140,103 @@ impl State {
             frame_minus_one: Frame::new(),
             frame_zero: Frame::new(),
             frame_zero_time_received: Instant::now(),
+            frame_period_sec: 0.1,
 
             kbots: Vec::new(),
             kinematic_projectiles_cache: FnvHashMap::default(),
             kinematic_projectiles: Vec::new(),
+            trails: trail::TrailSet::new(),
+            pings: Vec::new(),
+            alerts: alert::Alerts::new(),
+            events: EventBus::new(),
 
             explosions: Vec::new(),
+            active_effect_parts: Vec::new(),
+            beams: Vec::new(),
+            effect_library: effect::EffectLibrary::open(effect::EFFECTS_PATH),
             server_sec: 0.0,
 
             selected: FnvHashSet::default(),
             under_mouse: None,
+            area_center: None,
+            ferry_pickup: None,
             uitool: UiTool::None,
 
             players: FnvHashMap::default(),
             my_player_id: None,
+            observed_player_id: None,
+            perspective: false,
 
             start_time: Instant::now(),
             last_frame: Instant::now(),
             fps: 144,
             unit_icon_distance: 200.0,
+            impostor_distance: 100.0,
+            camera_shake: true,
+            render_style: RenderStyle::Standard,
+            economy_overlay: false,
+            cursor_mode: CursorMode::Software,
+            camera_speed: 1.0,
+            ui_scale: 1.0,
         }
     }
 
     pub fn handle_new_frame(&mut self, frame: Frame) {
         let time_between = self.frame_zero_time_received.elapsed();
         log::trace!("receive: NewFrame after {:?}", time_between);
+        //Long pauses are not taken into account
+        let measured = time_between.as_secs_f64().max(0.01).min(0.25);
+        self.frame_period_sec = self.frame_period_sec * 0.8 + measured * 0.2;
         self.frame_zero_time_received = Instant::now();
         self.frame_minus_one = std::mem::replace(&mut self.frame_zero, frame);
 
         let sec = self.frame_zero.number as f32 / 10.0;
         let mut seed = sec * 3.141592;
 
-        for explosion in self.frame_zero.explosions.iter() {
-            seed += 1.0;
-            self.explosions.push(Explosion {
-                position: explosion.position,
+        for effect_event in self.frame_zero.effects.clone() {
+            self.spawn_effect(&effect_event.name, effect_event.position, sec, &mut seed);
+        }
+
+        self.events.publish(&self.frame_zero.events);
+        for event in self.events.drain(Consumer::Effects) {
+            match event {
+                GameEvent::UnitDied {
+                    botdef_id,
+                    position,
+                    ..
+                } => {
+                    let death_effect = self
+                        .frame_zero
+                        .bot_defs
+                        .get(&botdef_id)
+                        .map(|botdef| botdef.death_effect.clone());
+                    if let Some(death_effect) = death_effect {
+                        self.spawn_effect(&death_effect, position, sec, &mut seed);
+                    }
+                }
+                GameEvent::ProjectileImpact { position, .. } => {
+                    self.spawn_effect("impact", position, sec, &mut seed)
+                }
+                _ => {}
+            }
+        }
+        //Our constructions, heard wherever the camera is
+        for event in self.events.drain(Consumer::Audio) {
+            if let GameEvent::BuildingCompleted { player_id, .. } = event {
+                if Some(player_id) == self.my_player_id {
+                    let position = self.position_smooth;
+                    self.spawn_effect("unit_ready", position, sec, &mut seed);
+                }
+            }
+        }
+
+        for beam in self.frame_zero.beams.iter() {
+            self.beams.push(ActiveBeam {
+                from: beam.from,
+                to: beam.to,
+                color: beam.color,
                 born_sec: sec,
-                death_sec: sec + explosion.life_time,
-                size: explosion.size,
-                seed,
+                death_sec: sec + 0.2,
             });
         }
 
@@ -137,18 +255,83 @@ impl State {
             .copied()
             .collect();
 
+        //The poses drawn carry over to the new frame, for the motion vectors
+        let mut last_poses: FnvHashMap<Id<KBot>, Pose> = self
+            .kbots
+            .iter()
+            .filter_map(|(kbot, client_kbot)| client_kbot.pose().map(|pose| (kbot.id, pose)))
+            .collect();
+        //Spectators see everything, unless they take the perspective of a player
+        let vision = match (self.my_player_id, self.observed_player_id) {
+            (Some(id), _) if self.frame_zero.alliance.fog_of_war => {
+                Some(vision::Vision::of_team(&self.frame_zero, id))
+            }
+            (None, Some(id)) if self.perspective => {
+                Some(vision::Vision::of_team(&self.frame_zero, id))
+            }
+            _ => None,
+        };
+        let my_team = self
+            .my_player_id
+            .or(self.observed_player_id)
+            .and_then(|id| self.frame_zero.players.get(&id))
+            .map(|me| me.team);
         self.kbots = self
             .frame_zero
             .kbots
             .values()
-            .map(|kbot| (kbot.clone(), ClientKbot::new(kbot.position)))
+            .filter(|kbot| match &vision {
+                Some(vision) => {
+                    Some(kbot.team) == my_team
+                        || vision.is_visible(kbot.position.x, kbot.position.y)
+                }
+                None => true,
+            })
+            .map(|kbot| {
+                let mut client_kbot = ClientKbot::new(kbot.position);
+                client_kbot.last_pose = last_poses.remove(&kbot.id);
+                (kbot.clone(), client_kbot)
+            })
             .collect();
     }
 
+    pub fn spawn_effect(&mut self, name: &str, position: Point3<f32>, sec: f32, seed: &mut f32) {
+        if let Some(def) = self.effect_library.get(name) {
+            for (offset, size, life_time) in def.particles() {
+                *seed += 1.0;
+                self.explosions.push(Explosion {
+                    position: position + offset,
+                    born_sec: sec,
+                    death_sec: sec + life_time,
+                    size,
+                    seed: *seed,
+                });
+            }
+
+            for part in def.parts.iter() {
+                match part {
+                    effect::EffectPart::Particles { .. } => {}
+                    effect::EffectPart::Sound { path, .. } => {
+                        //TODO play it once there is an audio backend
+                        log::trace!("sound {} at {:?}", path, position);
+                    }
+                    _ => self.active_effect_parts.push(ActiveEffectPart {
+                        position,
+                        part: part.clone(),
+                        born_sec: sec,
+                        death_sec: sec + part.life_time(),
+                    }),
+                }
+            }
+        }
+    }
+
     pub fn interpolate(&mut self, threadpool: &rayon::ThreadPool, view_proj: &Matrix4<f32>) {
+        profile_scope!("interpolate");
         let elapsed = self.frame_zero_time_received.elapsed().as_secs_f64();
-        //elapsed normalize between 0 and 1 if frame arrives every 100ms (0.1s)
-        let lambda = (elapsed / 0.1) as f32;
+        //elapsed normalize between 0 and 1, frames arrive every 100ms (0.1s) at normal speed
+        //No extrapolation so units stay still when the game is paused
+        let lambda = (elapsed / self.frame_period_sec).min(1.0) as f32;
         let i0 = lambda;
         let im = 1.0 - lambda;
 
@@ -189,11 +372,20 @@ impl State {
             .filter(|e| e.death_sec > self.server_sec)
             .collect();
 
+        let server_sec = self.server_sec;
+        self.active_effect_parts
+            .retain(|e| e.death_sec > server_sec);
+        self.beams.retain(|e| e.death_sec > server_sec);
+
         let mut kbots = std::mem::replace(&mut self.kbots, Vec::new());
 
         threadpool.install(|| {
             kbots.par_chunks_mut(1000).for_each(|chunk| {
                 for (kbot_0, client_kbot0) in chunk.iter_mut() {
+                    //Unless a new frame just replaced it
+                    if client_kbot0.trans.is_some() {
+                        client_kbot0.last_pose = client_kbot0.pose();
+                    }
                     let kbot_m_opt = self.frame_minus_one.kbots.get(&kbot_0.id);
                     if let Some(kbot_m) = kbot_m_opt {
                         client_kbot0.position =
@@ -237,17 +429,80 @@ impl State {
                 + kproj.position_at(self.frame_zero.number + 1).coords * i0;
 
             self.kinematic_projectiles.push(pos);
+            self.trails.record(kproj.id.value, pos, self.server_sec);
+        }
+
+        let frame_f = self.frame_minus_one.number as f32 * im + self.frame_zero.number as f32 * i0;
+        for missile in self.frame_zero.missiles.values() {
+            let pos = missile.position_at(frame_f);
+            self.kinematic_projectiles.push(pos);
+            self.trails.record(missile.id.value, pos, self.server_sec);
+        }
+
+        for (kbot, client_kbot) in self.kbots.iter() {
+            if kbot.speed.magnitude() > trail::MIN_SPEED {
+                self.trails
+                    .record(kbot.id.value, client_kbot.position, self.server_sec);
+            }
         }
+        self.trails.expire(self.server_sec);
 
         self.players = self.frame_zero.players.clone();
     }
 
+    ///Light flashes of effects, beam impacts and muzzle flashes of units that shot during the last frame
+    pub fn point_lights(&self) -> Vec<PointLight> {
+        let mut lights = Vec::new();
+        for active in self.active_effect_parts.iter() {
+            if let effect::EffectPart::LightFlash { color, radius, .. } = active.part {
+                let life = (self.server_sec - active.born_sec)
+                    / (active.death_sec - active.born_sec).max(0.001);
+                lights.push(PointLight {
+                    position: active.position + Vector3::new(0.0, 0.0, 1.0),
+                    radius,
+                    color,
+                    intensity: (1.0 - life).max(0.0).min(1.0) * 2.0,
+                });
+            }
+        }
+
+        for beam in self.beams.iter() {
+            lights.push(PointLight {
+                position: beam.to,
+                radius: 3.0,
+                color: beam.color,
+                intensity: 1.5,
+            });
+        }
+
+        for (kbot, client_kbot) in self.kbots.iter() {
+            if client_kbot.is_in_screen && kbot.frame_last_shot == self.frame_zero.number {
+                lights.push(PointLight {
+                    position: client_kbot.position
+                        + client_kbot.weapon0_dir
+                        + Vector3::new(0.0, 0.0, 0.5),
+                    radius: 2.5,
+                    color: [1.0, 0.85, 0.5],
+                    intensity: 1.0,
+                });
+            }
+        }
+        lights
+    }
+
     pub fn my_player(&self) -> Option<&Player> {
         self.my_player_id
             .map(|id| self.players.get(&id))
             .unwrap_or(None)
     }
 
+    ///Own player, or the one observed when spectating
+    pub fn viewed_player(&self) -> Option<&Player> {
+        self.my_player_id
+            .or(self.observed_player_id)
+            .and_then(|id| self.players.get(&id))
+    }
+
     pub fn near(&self) -> f32 {
         if self.position_smooth.z > 515.0
             || self.position_smooth.coords.x < -500.0
@@ -0,0 +1,330 @@
+use super::client::*;
+use super::settings;
+use crate::botdef::BotDef;
+use crate::frame::{Frame, Player};
+use crate::gpu_obj::dof::DofQuality;
+use crate::gpu_obj::toon::RenderStyle;
+use crate::mobile::KBot;
+use crate::*;
+use fnv::FnvHashMap;
+use na::{Point3, Vector3};
+use spin_sleep::LoopHelper;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+///Reference images, one png per scene
+pub const GOLDEN_DIR: &str = "src/asset/golden";
+///Renders that failed, next to the difference with their reference
+pub const OUTPUT_DIR: &str = "golden_output";
+const SIZE: (u32, u32) = (256, 256);
+///Largest difference of a channel, out of 255, still counted as equal
+const TOLERANCE: u8 = 8;
+///Share of the pixels allowed past the tolerance
+const MAX_BAD_RATIO: f32 = 0.002;
+///Frames rendered before the capture, for the shadows, mips and smoothing to settle
+const SETTLE_FRAMES: u32 = 8;
+const BUILDING: &str = "src/asset/botdef/building_example.json";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scene {
+    CubeGrid,
+    TerrainPatch,
+    Shields,
+}
+
+impl Scene {
+    pub const ALL: [Scene; 3] = [Scene::CubeGrid, Scene::TerrainPatch, Scene::Shields];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scene::CubeGrid => "cube_grid",
+            Scene::TerrainPatch => "terrain_patch",
+            Scene::Shields => "shields",
+        }
+    }
+}
+
+///Rgba8 pixels of SIZE
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    fn open(path: &str) -> Result<Image, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
+        if (info.color_type, info.bit_depth) != (png::ColorType::RGBA, png::BitDepth::Eight) {
+            return Err(format!(
+                "{:?} {:?} is not 8 bits RGBA",
+                info.color_type, info.bit_depth
+            ));
+        }
+        let mut pixels = vec![0; info.buffer_size()];
+        reader.next_frame(&mut pixels).map_err(|e| e.to_string())?;
+        Ok(Image {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    fn save(&self, path: &str) -> std::io::Result<()> {
+        let w = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+
+    ///Share of the pixels past the tolerance, and the image of the differences
+    fn diff(&self, other: &Image) -> (f32, Image) {
+        let mut bad = 0;
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for (a, b) in self.pixels.chunks(4).zip(other.pixels.chunks(4)) {
+            let worst = (0..3)
+                .map(|c| (a[c] as i32 - b[c] as i32).abs() as u8)
+                .max()
+                .unwrap();
+            if worst > TOLERANCE {
+                bad += 1;
+                pixels.extend_from_slice(&[255, 0, 255, 255]);
+            } else {
+                //Faded reference, to locate the differences
+                pixels.extend_from_slice(&[a[0] / 4, a[1] / 4, a[2] / 4, 255]);
+            }
+        }
+        let ratio = bad as f32 / (self.width * self.height) as f32;
+        (
+            ratio,
+            Image {
+                width: self.width,
+                height: self.height,
+                pixels,
+            },
+        )
+    }
+}
+
+impl App {
+    ///Renders every scene offscreen and compares it with its reference, or replaces the
+    ///references when update. False if any scene differs.
+    pub fn run_golden(&mut self, update: bool) -> bool {
+        self.resize_swap_chain(winit::dpi::PhysicalSize::new(SIZE.0 as f64, SIZE.1 as f64));
+        //Default settings rather than the ones of the player, without anything that changes
+        //from one frame or run to the next
+        self.apply_settings(&settings::Settings::default());
+        self.frame_graph.use_taa = false;
+        self.frame_graph.dof_quality = DofQuality::Off;
+        self.game_state.camera_shake = false;
+        self.game_state.render_style = RenderStyle::Standard;
+        self.loop_helper = LoopHelper::builder().build_with_target_rate(10000.0);
+        self.main_menu = MainMode::Play;
+        let _ = std::fs::create_dir_all(if update { GOLDEN_DIR } else { OUTPUT_DIR });
+
+        let mut passed = true;
+        for &scene in Scene::ALL.iter() {
+            let image = self.render_scene(scene);
+            let reference_path = format!("{}/{}.png", GOLDEN_DIR, scene.name());
+            if update {
+                match image.save(&reference_path) {
+                    Ok(()) => log::info!("Golden {} written to {}", scene.name(), reference_path),
+                    Err(e) => {
+                        log::error!("Can't write {}: {}", reference_path, e);
+                        passed = false;
+                    }
+                }
+                continue;
+            }
+
+            let output_path = format!("{}/{}.png", OUTPUT_DIR, scene.name());
+            //Not a failure, the references of new scenes are added by golden update
+            if !std::path::Path::new(&reference_path).exists() {
+                log::warn!(
+                    "Golden {}: no reference {}, render kept in {}, run golden update to add it",
+                    scene.name(),
+                    reference_path,
+                    output_path
+                );
+                let _ = image.save(&output_path);
+                continue;
+            }
+            let ok = match Image::open(&reference_path) {
+                Ok(reference) if (reference.width, reference.height) == SIZE => {
+                    let (ratio, diff) = reference.diff(&image);
+                    if ratio > MAX_BAD_RATIO {
+                        log::error!(
+                            "Golden {}: {:.2}% of the pixels differ, see {}",
+                            scene.name(),
+                            ratio * 100.0,
+                            output_path
+                        );
+                        let _ = diff.save(&format!("{}/{}_diff.png", OUTPUT_DIR, scene.name()));
+                        false
+                    } else {
+                        log::info!("Golden {}: ok", scene.name());
+                        true
+                    }
+                }
+                Ok(reference) => {
+                    log::error!(
+                        "Golden {}: reference is {}x{} instead of {:?}",
+                        scene.name(),
+                        reference.width,
+                        reference.height,
+                        SIZE
+                    );
+                    false
+                }
+                Err(e) => {
+                    log::error!(
+                        "Golden {}: can't read reference {} ({}), run golden update",
+                        scene.name(),
+                        reference_path,
+                        e
+                    );
+                    false
+                }
+            };
+            if !ok {
+                let _ = image.save(&output_path);
+            }
+            passed &= ok;
+        }
+        passed
+    }
+
+    fn render_scene(&mut self, scene: Scene) -> Image {
+        self.clear_gpu_instance_and_game_state();
+        let frame = self.golden_frame(scene);
+        self.game_state.my_player_id = frame.players.keys().next().copied();
+        self.game_state.players = frame.players.clone();
+        self.game_state.handle_new_frame(frame.clone());
+        self.game_state.handle_new_frame(frame);
+
+        let target = Point3::new(100.0, 100.0, self.heightmap_gpu.phy.z(100.0, 100.0));
+        self.game_state.position = target + Vector3::new(0.0, -40.0, 45.0);
+        self.game_state.dir = Vector3::new(0.0, 0.9, -1.0).normalize();
+        for _ in 0..SETTLE_FRAMES {
+            self.game_state.position_smooth = self.game_state.position;
+            self.game_state.dir_smooth = self.game_state.dir;
+            //Animated shaders see the same time on each run
+            self.game_state.start_time = Instant::now();
+            self.render();
+        }
+        self.read_graded()
+    }
+
+    ///Units of the scene around (100, 100), on the map already loaded
+    fn golden_frame(&mut self, scene: Scene) -> Frame {
+        let mut player = Player::new();
+        let mut kbots = FnvHashMap::default();
+        let mut bot_defs: FnvHashMap<_, BotDef> = FnvHashMap::default();
+        let positions: Vec<(f32, f32)> = match scene {
+            Scene::CubeGrid => (0..36)
+                .map(|i| (80.0 + (i % 6) as f32 * 8.0, 90.0 + (i / 6) as f32 * 8.0))
+                .collect(),
+            Scene::TerrainPatch => Vec::new(),
+            Scene::Shields => vec![(88.0, 105.0), (100.0, 105.0), (112.0, 105.0)],
+        };
+        if !positions.is_empty() {
+            let mut botdef = BotDef::open(BUILDING).unwrap();
+            if scene == Scene::Shields {
+                botdef.shield_radius = 6.0;
+            }
+            for (x, y) in positions {
+                let z = self.heightmap_gpu.phy.z(x, y);
+                let kbot = KBot::new(Point3::new(x, y, z), &botdef, player.id);
+                player.kbots.insert(kbot.id);
+                kbots.insert(kbot.id, kbot);
+            }
+            bot_defs.insert(botdef.id, botdef);
+        }
+
+        let mut players = FnvHashMap::default();
+        players.insert(player.id, player);
+        let moddef = moddef::ModDef {
+            units_id: bot_defs.keys().copied().collect(),
+            con_map: FnvHashMap::default(),
+        };
+        Frame {
+            players,
+            moddef,
+            kbots,
+            heightmap_phy: Some(self.heightmap_gpu.phy.clone()),
+            bot_defs,
+            ..Frame::new()
+        }
+    }
+
+    ///Graded scene of the last frame, before the ui is drawn over it
+    fn read_graded(&mut self) -> Image {
+        let (width, height) = self.scene_size();
+        let row_pitch = width * 4;
+        let buffer = self
+            .gpu
+            .device
+            .create_buffer_mapped::<u8>(
+                (row_pitch * height) as usize,
+                wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            )
+            .finish();
+        let mut encoder = self
+            .gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: self.frame_graph.graph.texture_of(self.frame_graph.graded),
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                offset: 0,
+                row_pitch,
+                image_height: height,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+        );
+        self.gpu.queue.submit(&[encoder.finish()]);
+
+        let read = Arc::new(Mutex::new(Vec::new()));
+        let read_in_callback = read.clone();
+        buffer.map_read_async(
+            0,
+            (row_pitch * height) as u64,
+            move |e: wgpu::BufferMapAsyncResult<&[u8]>| {
+                if let Ok(e) = e {
+                    *read_in_callback.lock().unwrap() = e.data.to_vec();
+                }
+            },
+        );
+        self.gpu.device.poll(true);
+
+        //Bgra8 to the rgba8 of the png
+        let mut pixels = std::mem::take(&mut *read.lock().unwrap());
+        for pixel in pixels.chunks_mut(4) {
+            pixel.swap(0, 2);
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
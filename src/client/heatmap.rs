@@ -0,0 +1,180 @@
+use super::game_state;
+use super::uitool::UiTool;
+use crate::botdef::{self, BotDef, MovementClass};
+use crate::frame::Player;
+use crate::gpu_obj::heatmap::{HeatmapKind, GRID_SIZE};
+use crate::heightmap_phy::HeightmapPhy;
+use crate::utils::Id;
+use crate::vision::Vision;
+
+///Weapons covering a cell for the darkest red of the threat map
+const FULL_THREAT: f32 = 4.0;
+///m of height per m across a cell for the highest path cost of a passable cell
+const FULL_SLOPE: f32 = 1.0;
+
+pub const ALL: [HeatmapKind; 4] = [
+    HeatmapKind::PathCost,
+    HeatmapKind::Threat,
+    HeatmapKind::Vision,
+    HeatmapKind::Placement,
+];
+
+pub fn name(kind: HeatmapKind) -> &'static str {
+    match kind {
+        HeatmapKind::PathCost => "path cost",
+        HeatmapKind::Threat => "threat",
+        HeatmapKind::Vision => "vision",
+        HeatmapKind::Placement => "placement",
+    }
+}
+
+///Simulation data drawn over the terrain to debug fog of war and unit behaviour, cycled with F6
+pub struct HeatmapOverlay {
+    pub shown: Option<HeatmapKind>,
+    ///What the uploaded grid was computed from: it changes at most once per frame of the game
+    computed: Option<(HeatmapKind, i32, Option<Id<Player>>, Option<Id<BotDef>>)>,
+}
+
+impl HeatmapOverlay {
+    pub fn new() -> Self {
+        HeatmapOverlay {
+            shown: None,
+            computed: None,
+        }
+    }
+
+    ///None, then each grid in turn
+    pub fn cycle(&mut self) {
+        self.shown = match self.shown {
+            None => Some(ALL[0]),
+            Some(kind) => ALL
+                .iter()
+                .position(|&k| k == kind)
+                .and_then(|i| ALL.get(i + 1))
+                .cloned(),
+        };
+    }
+
+    ///The grid shown, None when the one uploaded is still up to date
+    pub fn grid(
+        &mut self,
+        game_state: &game_state::State,
+        phy: &HeightmapPhy,
+    ) -> Option<(HeatmapKind, Vec<f32>)> {
+        let kind = match self.shown {
+            Some(kind) => kind,
+            None => {
+                self.computed = None;
+                return None;
+            }
+        };
+        let frame = &game_state.frame_zero;
+        let player = game_state.viewed_player();
+        //Path cost of the first unit selected, placement of the building under the cursor
+        let botdef = match kind {
+            HeatmapKind::PathCost => game_state
+                .selected
+                .iter()
+                .filter_map(|id| frame.kbots.get(id))
+                .map(|kbot| kbot.botdef_id)
+                .next(),
+            HeatmapKind::Placement => match game_state.uitool {
+                UiTool::Spawn(botdef_id) => Some(botdef_id),
+                _ => None,
+            },
+            _ => None,
+        };
+        let key = (kind, frame.number, player.map(|p| p.id), botdef);
+        if self.computed == Some(key) {
+            return None;
+        }
+        self.computed = Some(key);
+
+        let botdef = botdef.and_then(|id| frame.bot_defs.get(&id));
+        let cell_w = phy.width as f32 / GRID_SIZE as f32;
+        let cell_h = phy.height as f32 / GRID_SIZE as f32;
+        let center = |i: usize, j: usize| ((i as f32 + 0.5) * cell_w, (j as f32 + 0.5) * cell_h);
+        let mut cells = vec![-1.0; GRID_SIZE * GRID_SIZE];
+
+        match kind {
+            //No pathfinding yet: units go straight and stop where their movement class can't go
+            HeatmapKind::PathCost => {
+                let movement = botdef.map(|b| b.movement).unwrap_or_default();
+                for j in 0..GRID_SIZE {
+                    for i in 0..GRID_SIZE {
+                        let (x, y) = center(i, j);
+                        cells[i + j * GRID_SIZE] = if !movement.can_traverse(phy, x, y) {
+                            1.0
+                        } else if movement == MovementClass::Air {
+                            0.0
+                        } else {
+                            let dx = phy.z_linear(x + cell_w / 2.0, y)
+                                - phy.z_linear(x - cell_w / 2.0, y);
+                            let dy = phy.z_linear(x, y + cell_h / 2.0)
+                                - phy.z_linear(x, y - cell_h / 2.0);
+                            let slope = (dx / cell_w).abs().max((dy / cell_h).abs());
+                            (slope / FULL_SLOPE).min(0.9)
+                        };
+                    }
+                }
+            }
+            //Weapons of every completed enemy unit, seen or not
+            HeatmapKind::Threat => {
+                let team = player.map(|p| p.team);
+                let cell_radius = cell_w.hypot(cell_h) / 2.0;
+                for kbot in frame.kbots.values() {
+                    if Some(kbot.team) == team || kbot.con_completed < 1.0 {
+                        continue;
+                    }
+                    let range = frame
+                        .bot_defs
+                        .get(&kbot.botdef_id)
+                        .and_then(|b| b.dgun.as_ref())
+                        .map(|dgun| dgun.range)
+                        .unwrap_or(0.0)
+                        .max(botdef::WEAPON_RANGE)
+                        + cell_radius;
+                    let (x, y) = (kbot.position.x, kbot.position.y);
+                    let to_cell = |v: f32, size: f32| (v / size).max(0.0) as usize;
+                    let (min_i, max_i) = (to_cell(x - range, cell_w), to_cell(x + range, cell_w));
+                    let (min_j, max_j) = (to_cell(y - range, cell_h), to_cell(y + range, cell_h));
+                    for j in min_j..=max_j.min(GRID_SIZE - 1) {
+                        for i in min_i..=max_i.min(GRID_SIZE - 1) {
+                            let (cx, cy) = center(i, j);
+                            if (cx - x).hypot(cy - y) <= range {
+                                let cell = &mut cells[i + j * GRID_SIZE];
+                                *cell = cell.max(0.0) + 1.0 / FULL_THREAT;
+                            }
+                        }
+                    }
+                }
+            }
+            HeatmapKind::Vision => {
+                if let Some(player) = player {
+                    let vision = Vision::of_team(frame, player.id);
+                    for j in 0..GRID_SIZE {
+                        for i in 0..GRID_SIZE {
+                            let (x, y) = center(i, j);
+                            cells[i + j * GRID_SIZE] =
+                                if vision.is_visible(x, y) { 1.0 } else { 0.0 };
+                        }
+                    }
+                }
+            }
+            //Without a building to place, where a ground one could go
+            HeatmapKind::Placement => {
+                for j in 0..GRID_SIZE {
+                    for i in 0..GRID_SIZE {
+                        let (x, y) = center(i, j);
+                        let valid = match botdef {
+                            Some(botdef) => botdef.can_be_placed(phy, x, y),
+                            None => MovementClass::Ground.can_traverse(phy, x, y),
+                        };
+                        cells[i + j * GRID_SIZE] = if valid { 1.0 } else { 0.0 };
+                    }
+                }
+            }
+        }
+        Some((kind, cells))
+    }
+}
@@ -2,7 +2,10 @@ use imgui::*;
 use na::Vector3;
 use std::collections::HashSet;
 
+use crate::gpu_obj::color_grading;
 use crate::gpu_obj::heightmap_gpu;
+use crate::sky;
+use crate::weather;
 use noise::{NoiseFn, Seedable};
 
 #[derive(PartialEq, Clone, Copy)]
@@ -47,6 +50,7 @@ impl State {
         let noise_seed: &mut i32 = &mut (self.noise.seed() as i32);
         let mut update_noise = false;
 
+        let map_path = &self.map_path;
         let min_z = &mut self.min_z;
         let max_z = &mut self.max_z;
         let edit_height_window = imgui::Window::new(im_str!("Heightmap editor"));
@@ -83,6 +87,57 @@ impl State {
                     .build(&ui, min_z);
                 imgui::Slider::new(im_str!("max height"), 0.0..=heightmap_gpu::MAX_Z)
                     .build(&ui, max_z);
+                ui.separator();
+
+                let weather = &mut heightmap_gpu.phy.data.weather;
+                for (i, (name, preset)) in weather::Weather::presets().into_iter().enumerate() {
+                    if i > 0 {
+                        ui.same_line(0.0);
+                    }
+                    if ui.small_button(&im_str!("{}", name)) {
+                        *weather = preset;
+                    }
+                }
+                imgui::Slider::new(im_str!("weather intensity"), 0.0..=1.0)
+                    .build(&ui, &mut weather.intensity);
+                imgui::Slider::new(im_str!("wind x"), -20.0..=20.0).build(&ui, &mut weather.wind.x);
+                imgui::Slider::new(im_str!("wind y"), -20.0..=20.0).build(&ui, &mut weather.wind.y);
+                ui.separator();
+
+                //Environment lighting is baked again when the sky changes
+                ui.text(im_str!("sky"));
+                for (name, preset) in sky::Sky::presets() {
+                    ui.same_line(0.0);
+                    if ui.small_button(&im_str!("{}", name)) {
+                        heightmap_gpu.phy.data.sky = preset;
+                    }
+                }
+
+                //LUTs are graded from the neutral strip in an image editor, then put in lut_dir
+                let lut = &mut heightmap_gpu.phy.data.lut;
+                ui.text(im_str!(
+                    "color grading: {}",
+                    lut.as_ref().map_or("neutral", |l| l.as_str())
+                ));
+                if ui.small_button(im_str!("Export neutral LUT")) {
+                    let lut_dir = "src/asset/lut";
+                    let path = format!("{}/neutral.png", lut_dir);
+                    let written = std::fs::create_dir_all(lut_dir)
+                        .and_then(|_| color_grading::ColorGrading::write_neutral(&path));
+                    match written {
+                        Ok(()) => log::info!("Neutral LUT written to {}", path),
+                        Err(e) => log::error!("Could not write {}: {}", path, e),
+                    }
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Use map LUT")) {
+                    *lut = Some(format!("{}/lut.png", map_path));
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("No LUT")) {
+                    *lut = None;
+                }
+                ui.separator();
 
                 if ui.small_button(im_str!("Save")) {
                     Self::save(heightmap_gpu, "src/asset/map/map_example");
@@ -338,30 +393,7 @@ impl State {
     }
 
     pub fn load(heightmap_gpu: &mut heightmap_gpu::HeightmapGpu, path: &str) {
-        use byteorder::{BigEndian, ReadBytesExt};
-        use std::fs::File;
-        use std::io::Cursor;
-        let height_path = format!("{}/height.png", path);
-        let mut decoder = png::Decoder::new(File::open(&height_path).unwrap());
-        decoder.set_transformations(png::Transformations::IDENTITY);
-        let (info, mut reader) = decoder.read_info().unwrap();
-        log::debug!("info: {:?}", info.width);
-        log::debug!("height: {:?}", info.height);
-        log::debug!("bit depth: {:?}", info.bit_depth);
-        log::debug!("buffer size: {:?}", info.buffer_size());
-        let mut buf = vec![0; info.buffer_size()];
-        reader.next_frame(&mut buf).unwrap();
-        // Transform buffer into 16 bits slice.
-        let mut buffer_u16 = vec![0; (info.width * info.height) as usize];
-        let mut buffer_cursor = Cursor::new(buf);
-        buffer_cursor
-            .read_u16_into::<BigEndian>(&mut buffer_u16)
-            .unwrap();
-
-        for i in 0..heightmap_gpu.phy.width * heightmap_gpu.phy.height {
-            heightmap_gpu.phy.texels[i as usize] =
-                buffer_u16[i as usize] as f32 / (65535.0 / 511.0);
-        }
+        heightmap_gpu.phy.load(path);
         heightmap_gpu.update_rect(
             0 as u32,
             0 as u32,
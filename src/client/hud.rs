@@ -0,0 +1,592 @@
+use super::client::*;
+use super::uitool::UiTool;
+use crate::gpu_obj::hud::{Sprite, INSTANCE_LEN};
+use imgui::*;
+use serde::{Deserialize, Serialize};
+
+///Smallest window holding the layout at scale 1, the hud shrinks in smaller ones
+pub const MIN_SIZE: (f32, f32) = (1280.0, 720.0);
+///There is no storage yet, the bars fill like the Resources window
+const RESOURCE_BAR_FULL: f64 = 500.0;
+
+const PANEL_COLOR: [f32; 4] = [0.05, 0.07, 0.1, 0.8];
+const BORDER_COLOR: [f32; 4] = [0.4, 0.5, 0.6, 0.9];
+const BUTTON_COLOR: [f32; 4] = [0.15, 0.2, 0.25, 1.0];
+const HOVERED_COLOR: [f32; 4] = [0.25, 0.35, 0.45, 1.0];
+const ACTIVE_COLOR: [f32; 4] = [0.3, 0.55, 0.3, 1.0];
+const TEXT_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const METAL_COLOR: [f32; 4] = [0.6, 0.65, 0.7, 1.0];
+const ENERGY_COLOR: [f32; 4] = [0.95, 0.85, 0.2, 1.0];
+const LIFE_COLOR: [f32; 4] = [0.2, 0.8, 0.3, 1.0];
+const BUILD_COLOR: [f32; 4] = [0.3, 0.6, 1.0, 1.0];
+const ALLY_COLOR: [f32; 4] = [0.3, 1.0, 0.4, 1.0];
+const ENEMY_COLOR: [f32; 4] = [1.0, 0.3, 0.25, 1.0];
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const LOCKED_COLOR: [f32; 4] = [0.4, 0.4, 0.4, 0.5];
+
+///Panels of the hud, named by the mission triggers
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HudElement {
+    ResourceBar,
+    SelectionPanel,
+    BuildMenu,
+    Minimap,
+}
+
+///Where a panel sticks when the window is resized
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Anchor {
+    Top,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+///Window pixels, y down
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Rect { x, y, w, h }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.w && y >= self.y && y < self.y + self.h
+    }
+
+    ///Shrunk by margin on every side
+    pub fn inset(&self, margin: f32) -> Rect {
+        Rect::new(
+            self.x + margin,
+            self.y + margin,
+            (self.w - 2.0 * margin).max(0.0),
+            (self.h - 2.0 * margin).max(0.0),
+        )
+    }
+
+    ///Left part of the rect, fraction in [0,1]
+    pub fn left(&self, fraction: f32) -> Rect {
+        Rect::new(self.x, self.y, self.w * fraction.max(0.0).min(1.0), self.h)
+    }
+
+    ///Cell of a grid of columns by rows
+    pub fn cell(&self, columns: usize, rows: usize, column: usize, row: usize) -> Rect {
+        let w = self.w / columns as f32;
+        let h = self.h / rows as f32;
+        Rect::new(self.x + column as f32 * w, self.y + row as f32 * h, w, h)
+    }
+}
+
+///Text drawn over the quads by imgui
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub x: f32,
+    pub y: f32,
+    pub text: String,
+    pub color: [f32; 4],
+}
+
+///Immediate mode in-game ui: rebuilt every frame from the game state as quads for HudGpu and
+///labels for imgui. Input is tested against the rects of the previous frame, the layout does
+///not move between two frames.
+pub struct Hud {
+    width: f32,
+    height: f32,
+    ///Reference pixels to window pixels
+    pub scale: f32,
+    ///Over the imgui font, already sized by App::ui_scale
+    font_scale: f32,
+    ///Texture coordinates of Sprite::Solid
+    solid: ([f32; 2], [f32; 2]),
+    cursor: (f32, f32),
+    ///Left click not yet handled by a widget
+    click: Option<(f32, f32)>,
+    ///INSTANCE_LEN floats per quad
+    pub quads: Vec<f32>,
+    pub labels: Vec<Label>,
+    ///Areas taking the mouse, of the frame being built and of the last one
+    next_hit_rects: Vec<Rect>,
+    hit_rects: Vec<Rect>,
+    ///Framed by a pulsing border
+    highlights: Vec<HudElement>,
+    ///Alpha of the highlight border
+    pulse: f32,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud {
+            width: MIN_SIZE.0,
+            height: MIN_SIZE.1,
+            scale: 1.0,
+            font_scale: 1.0,
+            solid: ([0.0; 2], [0.0; 2]),
+            cursor: (0.0, 0.0),
+            click: None,
+            quads: Vec::new(),
+            labels: Vec::new(),
+            next_hit_rects: Vec::new(),
+            hit_rects: Vec::new(),
+            highlights: Vec::new(),
+            pulse: 1.0,
+        }
+    }
+
+    ///Whether the mouse is over the hud, world clicks there are ignored
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        self.hit_rects.iter().any(|rect| rect.contains(x, y))
+    }
+
+    ///Left press over the hud, consumed by the widget under it during the next build
+    pub fn press(&mut self, x: f32, y: f32) {
+        self.click = Some((x, y));
+    }
+
+    pub fn begin(
+        &mut self,
+        width: u32,
+        height: u32,
+        cursor: (u32, u32),
+        solid: ([f32; 2], [f32; 2]),
+        ui_scale: f32,
+    ) {
+        self.solid = solid;
+        self.width = width as f32;
+        self.height = height as f32;
+        let fit = (self.width / MIN_SIZE.0).min(self.height / MIN_SIZE.1);
+        self.scale = ui_scale.min(fit);
+        self.font_scale = self.scale / ui_scale;
+        self.cursor = (cursor.0 as f32, cursor.1 as f32);
+        self.quads.clear();
+        self.labels.clear();
+        self.next_hit_rects.clear();
+    }
+
+    ///Elements to highlight until the next call, time in sec drives the pulse
+    pub fn highlight(&mut self, elements: &[HudElement], time: f32) {
+        self.highlights.clear();
+        self.highlights.extend_from_slice(elements);
+        self.pulse = 0.6 + 0.4 * (time * 6.0).sin();
+    }
+
+    ///Pulsing border around the rect of a highlighted element
+    pub fn element(&mut self, element: HudElement, rect: Rect) {
+        if self.highlights.contains(&element) {
+            let mut color = HIGHLIGHT_COLOR;
+            color[3] = self.pulse;
+            let border = 3.0 * self.scale;
+            self.frame(rect.inset(-border), color);
+            self.frame(rect.inset(-border * 0.5), color);
+        }
+    }
+
+    pub fn end(&mut self) {
+        std::mem::swap(&mut self.hit_rects, &mut self.next_hit_rects);
+        self.click = None;
+    }
+
+    ///Nothing drawn nor hit, outside of a match
+    pub fn clear(&mut self) {
+        self.quads.clear();
+        self.labels.clear();
+        self.hit_rects.clear();
+        self.click = None;
+    }
+
+    ///Rect of w by h reference pixels at offset from the anchor, toward the window center
+    pub fn anchored(&self, anchor: Anchor, w: f32, h: f32, offset_x: f32, offset_y: f32) -> Rect {
+        let (w, h) = (w * self.scale, h * self.scale);
+        let (ox, oy) = (offset_x * self.scale, offset_y * self.scale);
+        let x = match anchor {
+            Anchor::BottomLeft => ox,
+            Anchor::Top | Anchor::Bottom => (self.width - w) / 2.0 + ox,
+            Anchor::BottomRight => self.width - w - ox,
+        };
+        let y = match anchor {
+            Anchor::Top => oy,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => self.height - h - oy,
+        };
+        Rect::new(x, y, w, h)
+    }
+
+    pub fn sprite(&mut self, rect: Rect, uv: ([f32; 2], [f32; 2]), color: [f32; 4]) {
+        let (min_tex, max_tex) = uv;
+        self.quads.extend_from_slice(&[
+            rect.x,
+            rect.y,
+            rect.x + rect.w,
+            rect.y + rect.h,
+            min_tex[0],
+            min_tex[1],
+            max_tex[0],
+            max_tex[1],
+        ]);
+        self.quads.extend_from_slice(&color);
+        debug_assert_eq!(self.quads.len() % INSTANCE_LEN, 0);
+    }
+
+    pub fn fill(&mut self, rect: Rect, color: [f32; 4]) {
+        self.sprite(rect, self.solid, color);
+    }
+
+    ///Outline of 1.5 reference pixels inside the rect
+    pub fn frame(&mut self, rect: Rect, color: [f32; 4]) {
+        let t = (1.5 * self.scale).max(1.0);
+        self.fill(Rect::new(rect.x, rect.y, rect.w, t), color);
+        self.fill(Rect::new(rect.x, rect.y + rect.h - t, rect.w, t), color);
+        self.fill(Rect::new(rect.x, rect.y, t, rect.h), color);
+        self.fill(Rect::new(rect.x + rect.w - t, rect.y, t, rect.h), color);
+    }
+
+    ///Background taking the mouse
+    pub fn panel(&mut self, rect: Rect) {
+        self.next_hit_rects.push(rect);
+        self.fill(rect, PANEL_COLOR);
+        self.frame(rect, BORDER_COLOR);
+    }
+
+    pub fn bar(&mut self, rect: Rect, fraction: f32, color: [f32; 4]) {
+        self.fill(rect, BUTTON_COLOR);
+        self.fill(rect.left(fraction), color);
+    }
+
+    ///At x, y in reference pixels from the rect top left corner
+    pub fn label(&mut self, rect: Rect, x: f32, y: f32, text: String, color: [f32; 4]) {
+        self.labels.push(Label {
+            x: rect.x + x * self.scale,
+            y: rect.y + y * self.scale,
+            text,
+            color,
+        });
+    }
+
+    ///Where the pending click landed in the rect, in [0,1], consuming it
+    pub fn clicked(&mut self, rect: Rect) -> Option<(f32, f32)> {
+        match self.click {
+            Some((x, y)) if rect.contains(x, y) => {
+                self.click = None;
+                Some(((x - rect.x) / rect.w, (y - rect.y) / rect.h))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn button(&mut self, rect: Rect, icon: ([f32; 2], [f32; 2]), active: bool) -> bool {
+        let color = if active {
+            ACTIVE_COLOR
+        } else if rect.contains(self.cursor.0, self.cursor.1) {
+            HOVERED_COLOR
+        } else {
+            BUTTON_COLOR
+        };
+        self.fill(rect, color);
+        self.sprite(rect.inset(rect.w * 0.15), icon, [1.0; 4]);
+        self.clicked(rect).is_some()
+    }
+
+    ///Labels of the frame, over a transparent window ignoring inputs
+    pub fn draw_labels(&self, ui: &Ui) {
+        if self.labels.is_empty() {
+            return;
+        }
+        imgui::Window::new(im_str!("Hud labels"))
+            .position([0.0, 0.0], imgui::Condition::Always)
+            .size([self.width, self.height], imgui::Condition::Always)
+            .flags(
+                WindowFlags::NO_DECORATION
+                    | WindowFlags::NO_BACKGROUND
+                    | WindowFlags::NO_INPUTS
+                    | WindowFlags::NO_SAVED_SETTINGS
+                    | WindowFlags::NO_FOCUS_ON_APPEARING
+                    | WindowFlags::NO_BRING_TO_FRONT_ON_FOCUS,
+            )
+            .build(ui, || {
+                ui.set_window_font_scale(self.font_scale);
+                for label in self.labels.iter() {
+                    ui.set_cursor_pos([label.x, label.y]);
+                    ui.text_colored(label.color, &im_str!("{}", label.text));
+                }
+            });
+    }
+}
+
+impl App {
+    ///Resource bar, selected units, build menu and minimap, in Play
+    pub fn build_hud(&mut self) {
+        let mut hud = std::mem::replace(&mut self.hud, Hud::new());
+        hud.begin(
+            self.gpu.sc_desc.width,
+            self.gpu.sc_desc.height,
+            self.input_state.cursor_pos,
+            self.hud_gpu.uv(Sprite::Solid),
+            self.ui_scale(),
+        );
+        let highlights = self
+            .mission
+            .as_ref()
+            .map(|mission| &mission.highlights[..])
+            .unwrap_or(&[]);
+        hud.highlight(
+            highlights,
+            self.game_state.start_time.elapsed().as_secs_f32(),
+        );
+
+        self.hud_resource_bar(&mut hud);
+        self.hud_selection_panel(&mut hud);
+        self.hud_build_menu(&mut hud);
+        self.hud_minimap(&mut hud);
+
+        hud.end();
+        self.hud = hud;
+    }
+
+    fn hud_resource_bar(&self, hud: &mut Hud) {
+        let me = match self.game_state.viewed_player() {
+            Some(me) => me,
+            None => return,
+        };
+        let rect = hud.anchored(Anchor::Top, 640.0, 44.0, 0.0, 4.0);
+        hud.panel(rect);
+        hud.element(HudElement::ResourceBar, rect);
+        let resources = [
+            ("metal", me.metal, METAL_COLOR),
+            ("energy", me.energy, ENERGY_COLOR),
+        ];
+        for (i, (name, amount, color)) in resources.iter().enumerate() {
+            let cell = rect.cell(2, 1, i, 0).inset(8.0 * hud.scale);
+            let bar = Rect::new(cell.x, cell.y + cell.h * 0.6, cell.w, cell.h * 0.4);
+            hud.bar(bar, (amount / RESOURCE_BAR_FULL) as f32, *color);
+            hud.label(
+                cell,
+                0.0,
+                -2.0,
+                format!("{} {:.0}", name, amount),
+                TEXT_COLOR,
+            );
+        }
+    }
+
+    fn hud_selection_panel(&self, hud: &mut Hud) {
+        const COLUMNS: usize = 8;
+        const ROWS: usize = 3;
+        let frame_zero = &self.game_state.frame_zero;
+        let mut selected: Vec<_> = self
+            .game_state
+            .selected
+            .iter()
+            .filter_map(|id| frame_zero.kbots.get(id))
+            .collect();
+        if selected.is_empty() {
+            return;
+        }
+        //Stable order, the selection is a hash set
+        selected.sort_by_key(|kbot| kbot.id.value);
+
+        let rect = hud.anchored(Anchor::Bottom, 560.0, 200.0, 0.0, 4.0);
+        hud.panel(rect);
+        hud.element(HudElement::SelectionPanel, rect);
+        hud.label(
+            rect,
+            8.0,
+            4.0,
+            format!("{} selected", selected.len()),
+            TEXT_COLOR,
+        );
+        let grid = Rect::new(
+            rect.x,
+            rect.y + 24.0 * hud.scale,
+            rect.w,
+            rect.h - 24.0 * hud.scale,
+        )
+        .inset(6.0 * hud.scale);
+
+        for (i, kbot) in selected.iter().take(COLUMNS * ROWS).enumerate() {
+            let botdef = match frame_zero.bot_defs.get(&kbot.botdef_id) {
+                Some(botdef) => botdef,
+                None => continue,
+            };
+            let cell = grid
+                .cell(COLUMNS, ROWS, i % COLUMNS, i / COLUMNS)
+                .inset(3.0 * hud.scale);
+            hud.fill(cell, BUTTON_COLOR);
+            let name = std::path::Path::new(&botdef.file_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            hud.label(cell, 3.0, 2.0, name.chars().take(6).collect(), TEXT_COLOR);
+
+            let bar_h = cell.h * 0.15;
+            let life = Rect::new(cell.x, cell.y + cell.h - bar_h, cell.w, bar_h);
+            hud.bar(life, kbot.life as f32 / botdef.max_life as f32, LIFE_COLOR);
+            if kbot.con_completed < 1.0 {
+                let con = Rect::new(cell.x, life.y - bar_h, cell.w, bar_h);
+                hud.bar(con, kbot.con_completed, BUILD_COLOR);
+            }
+        }
+    }
+
+    fn hud_build_menu(&mut self, hud: &mut Hud) {
+        const COLUMNS: usize = 4;
+        const CELL: f32 = 72.0;
+        let frame_zero = &self.game_state.frame_zero;
+        let has_builder = self.game_state.selected.iter().any(|id| {
+            frame_zero
+                .kbots
+                .get(id)
+                .and_then(|kbot| frame_zero.bot_defs.get(&kbot.botdef_id))
+                .map(|botdef| botdef.build_power > 0.0)
+                .unwrap_or(false)
+        });
+        if !has_builder {
+            return;
+        }
+        //Stable order, the botdefs are in a hash map
+        let mut botdefs: Vec<_> = frame_zero
+            .bot_defs
+            .values()
+            .filter(|botdef| !botdef.commander)
+            .collect();
+        botdefs.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+        let rows = (botdefs.len() + COLUMNS - 1) / COLUMNS;
+        let rect = hud.anchored(
+            Anchor::BottomLeft,
+            COLUMNS as f32 * CELL + 12.0,
+            rows as f32 * CELL + 36.0,
+            4.0,
+            4.0,
+        );
+        hud.panel(rect);
+        hud.element(HudElement::BuildMenu, rect);
+        hud.label(rect, 8.0, 4.0, "build".to_owned(), TEXT_COLOR);
+        let grid = Rect::new(
+            rect.x,
+            rect.y + 30.0 * hud.scale,
+            rect.w,
+            rect.h - 30.0 * hud.scale,
+        )
+        .inset(6.0 * hud.scale);
+
+        let hammer = self.hud_gpu.uv(Sprite::Icon(1, 0));
+        let tech_tier = self
+            .game_state
+            .viewed_player()
+            .map(|me| me.tech_tier)
+            .unwrap_or(1);
+        let mut uitool = self.game_state.uitool;
+        for (i, botdef) in botdefs.iter().enumerate() {
+            let cell = grid
+                .cell(COLUMNS, rows, i % COLUMNS, i / COLUMNS)
+                .inset(3.0 * hud.scale);
+            //Locked entries are shown dimmed with the tier they need, and don't take clicks
+            if botdef.tier > tech_tier {
+                hud.fill(cell, BUTTON_COLOR);
+                hud.sprite(cell.inset(cell.w * 0.15), hammer, LOCKED_COLOR);
+                hud.label(
+                    cell,
+                    cell.w / hud.scale - 24.0,
+                    2.0,
+                    format!("T{}", botdef.tier),
+                    HIGHLIGHT_COLOR,
+                );
+            } else if hud.button(cell, hammer, uitool == UiTool::Spawn(botdef.id)) {
+                uitool = UiTool::Spawn(botdef.id);
+            }
+            let name = std::path::Path::new(&botdef.file_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            hud.label(cell, 3.0, 2.0, name.chars().take(8).collect(), TEXT_COLOR);
+            hud.label(
+                cell,
+                3.0,
+                cell.h / hud.scale - 20.0,
+                format!("{:.0}", botdef.tier_metal_cost()),
+                METAL_COLOR,
+            );
+        }
+        //Unlocked tiers can be lost with the upgrade building
+        if let UiTool::Spawn(botdef_id) = uitool {
+            if let Some(botdef) = frame_zero.bot_defs.get(&botdef_id) {
+                if botdef.tier > tech_tier {
+                    uitool = UiTool::None;
+                }
+            }
+        }
+        self.game_state.uitool = uitool;
+    }
+
+    ///Frame with the units as dots and the camera target, a click moves the camera
+    fn hud_minimap(&mut self, hud: &mut Hud) {
+        const SIDE: f32 = 260.0;
+        let map_w = self.heightmap_gpu.phy.width as f32;
+        let map_h = self.heightmap_gpu.phy.height as f32;
+        let (w, h) = if map_w > map_h {
+            (SIDE, SIDE * map_h / map_w)
+        } else {
+            (SIDE * map_w / map_h, SIDE)
+        };
+        let rect = hud.anchored(Anchor::BottomRight, w + 12.0, h + 12.0, 4.0, 4.0);
+        hud.panel(rect);
+        hud.element(HudElement::Minimap, rect);
+        let map = rect.inset(6.0 * hud.scale);
+        hud.fill(map, [0.0, 0.0, 0.0, 0.6]);
+
+        //World y goes up, the minimap y goes down like the window
+        let to_map =
+            |x: f32, y: f32| (map.x + x / map_w * map.w, map.y + (1.0 - y / map_h) * map.h);
+
+        let my_team = self.game_state.viewed_player().map(|me| me.team);
+        let dot = (3.0 * hud.scale).max(2.0);
+        for kbot in self.game_state.frame_zero.kbots.values() {
+            let (x, y) = to_map(kbot.position.x, kbot.position.y);
+            let color = if Some(kbot.team) == my_team {
+                ALLY_COLOR
+            } else {
+                ENEMY_COLOR
+            };
+            hud.fill(Rect::new(x - dot / 2.0, y - dot / 2.0, dot, dot), color);
+        }
+        //Alerts flash 4 times a second for a while
+        for alert in self.game_state.alerts.list.iter() {
+            let age = alert.age();
+            if age < super::alert::FLASH_SEC && (age * 4.0).fract() < 0.5 {
+                let (x, y) = to_map(alert.position.x, alert.position.y);
+                let side = 14.0 * hud.scale;
+                hud.frame(
+                    Rect::new(x - side / 2.0, y - side / 2.0, side, side),
+                    alert.kind.color(),
+                );
+            }
+        }
+        //Blinking twice a second
+        for active in self.game_state.pings.iter() {
+            if active.age().fract() < 0.5 {
+                let (x, y) = to_map(active.ping.position.x, active.ping.position.y);
+                let side = 10.0 * hud.scale;
+                let color = super::ping::color(active.ping.kind);
+                hud.frame(Rect::new(x - side / 2.0, y - side / 2.0, side, side), color);
+            }
+        }
+        if let Some(center) = self.game_state.screen_center_world_pos {
+            let (x, y) = to_map(center.x, center.y);
+            let side = 16.0 * hud.scale;
+            hud.frame(
+                Rect::new(x - side / 2.0, y - side / 2.0, side, side),
+                TEXT_COLOR,
+            );
+        }
+        hud.frame(map, BORDER_COLOR);
+
+        let camera = self.input_gate().camera;
+        if let (Some((u, v)), true) = (hud.clicked(map), camera) {
+            self.look_at(u * map_w, (1.0 - v) * map_h);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use super::client::*;
+use super::factory;
+use super::game_state;
+use crate::frame::{DebugEdit, FrameEventFromPlayer, Player};
+use crate::utils::{Id, IdValue};
+use crate::*;
+use imgui::*;
+use na::Vector3;
+
+///Rows of the entity list, the others are counted
+const MAX_LISTED: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntityKind {
+    Units,
+    Projectiles,
+    Missiles,
+}
+
+impl EntityKind {
+    const ALL: [EntityKind; 3] = [
+        EntityKind::Units,
+        EntityKind::Projectiles,
+        EntityKind::Missiles,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            EntityKind::Units => "units",
+            EntityKind::Projectiles => "projectiles",
+            EntityKind::Missiles => "missiles",
+        }
+    }
+}
+
+///Debug window listing the entities of the frame, units are edited through
+///FrameEventFromPlayer::DebugEdit so that the simulation stays deterministic, offline only
+pub struct InspectorUi {
+    pub open: bool,
+    kind: EntityKind,
+    ///None lists the entities of every player
+    owner: Option<Id<Player>>,
+    ///Value of the id of the entity shown
+    inspected: Option<IdValue>,
+    ///Edited values of the inspected unit, loaded when it is picked
+    life: i32,
+    position: [f32; 3],
+}
+
+impl InspectorUi {
+    pub fn new() -> Self {
+        InspectorUi {
+            open: false,
+            kind: EntityKind::Units,
+            owner: None,
+            inspected: None,
+            life: 0,
+            position: [0.0; 3],
+        }
+    }
+}
+
+impl App {
+    ///Edits of the inspected unit
+    pub fn draw_inspector_ui(
+        ui: &Ui,
+        inspector: &mut InspectorUi,
+        game_state: &game_state::State,
+    ) -> Vec<FrameEventFromPlayer> {
+        let mut events = Vec::new();
+        let frame = &game_state.frame_zero;
+        let me = match game_state.my_player() {
+            Some(me) => me.id,
+            None => return events,
+        };
+
+        imgui::Window::new(im_str!("Entity inspector (F7)"))
+            .size([420.0, 600.0], imgui::Condition::FirstUseEver)
+            .position([430.0, 250.0], imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                for (i, &kind) in EntityKind::ALL.iter().enumerate() {
+                    if i > 0 {
+                        ui.same_line(0.0);
+                    }
+                    ui.radio_button(&im_str!("{}", kind.name()), &mut inspector.kind, kind);
+                }
+                ui.radio_button(im_str!("all players"), &mut inspector.owner, None);
+                for player in frame.players.values() {
+                    ui.same_line(0.0);
+                    ui.radio_button(
+                        &im_str!("{}##owner", player.id),
+                        &mut inspector.owner,
+                        Some(player.id),
+                    );
+                }
+                if ui.small_button(im_str!("Inspect the selection")) {
+                    if let Some(kbot) = game_state
+                        .selected
+                        .iter()
+                        .next()
+                        .and_then(|id| frame.kbots.get(id))
+                    {
+                        inspector.kind = EntityKind::Units;
+                        inspector.inspected = Some(kbot.id.value);
+                        inspector.life = kbot.life;
+                        inspector.position = [kbot.position.x, kbot.position.y, kbot.position.z];
+                    }
+                }
+                ui.separator();
+
+                let mut rows: Vec<(IdValue, String)> = match inspector.kind {
+                    EntityKind::Units => frame
+                        .kbots
+                        .values()
+                        .filter(|kbot| inspector.owner.map_or(true, |o| o == kbot.player_id))
+                        .map(|kbot| {
+                            let name = frame
+                                .bot_defs
+                                .get(&kbot.botdef_id)
+                                .map(factory::name)
+                                .unwrap_or_default();
+                            (
+                                kbot.id.value,
+                                format!("{} {} life {}", name, kbot.id, kbot.life),
+                            )
+                        })
+                        .collect(),
+                    EntityKind::Projectiles => frame
+                        .kinematic_projectiles
+                        .values()
+                        .map(|p| (p.id.value, format!("{}", p.id)))
+                        .collect(),
+                    EntityKind::Missiles => frame
+                        .missiles
+                        .values()
+                        .map(|m| (m.id.value, format!("{} team {}", m.id, m.team)))
+                        .collect(),
+                };
+                rows.sort_by_key(|row| row.0);
+                ui.text(im_str!("{} {}", rows.len(), inspector.kind.name()));
+                ChildWindow::new(im_str!("entities"))
+                    .size([0.0, 200.0])
+                    .border(true)
+                    .build(ui, || {
+                        for (id, label) in rows.iter().take(MAX_LISTED) {
+                            let picked = Selectable::new(&im_str!("{}##{}", label, id))
+                                .selected(inspector.inspected == Some(*id))
+                                .build(ui);
+                            if picked {
+                                inspector.inspected = Some(*id);
+                                if let Some(kbot) = frame.kbots.get(&Id::new(*id)) {
+                                    inspector.life = kbot.life;
+                                    inspector.position =
+                                        [kbot.position.x, kbot.position.y, kbot.position.z];
+                                }
+                            }
+                        }
+                        if rows.len() > MAX_LISTED {
+                            ui.text(im_str!("... {} more", rows.len() - MAX_LISTED));
+                        }
+                    });
+                ui.separator();
+
+                let inspected = match inspector.inspected {
+                    Some(inspected) => inspected,
+                    None => return,
+                };
+                let json = match inspector.kind {
+                    EntityKind::Units => frame
+                        .kbots
+                        .get(&Id::new(inspected))
+                        .map(serde_json::to_string_pretty),
+                    EntityKind::Projectiles => frame
+                        .kinematic_projectiles
+                        .get(&Id::new(inspected))
+                        .map(serde_json::to_string_pretty),
+                    EntityKind::Missiles => frame
+                        .missiles
+                        .get(&Id::new(inspected))
+                        .map(serde_json::to_string_pretty),
+                };
+                let json = match json {
+                    Some(json) => json.unwrap_or_default(),
+                    None => {
+                        ui.text(im_str!("Entity {} is gone", inspected));
+                        return;
+                    }
+                };
+
+                if inspector.kind == EntityKind::Units {
+                    let kbot = Id::new(inspected);
+                    let max_life = frame
+                        .kbots
+                        .get(&kbot)
+                        .and_then(|kbot| frame.bot_defs.get(&kbot.botdef_id))
+                        .map(|botdef| botdef.max_life)
+                        .unwrap_or(1);
+                    let mut edit =
+                        |edit| events.push(FrameEventFromPlayer::DebugEdit { id: me, kbot, edit });
+                    imgui::Slider::new(im_str!("life"), 0..=max_life)
+                        .build(ui, &mut inspector.life);
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Set##life")) {
+                        edit(DebugEdit::Life(inspector.life));
+                    }
+                    ui.drag_float3(im_str!("position"), &mut inspector.position)
+                        .build();
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Set##position")) {
+                        let [x, y, z] = inspector.position;
+                        edit(DebugEdit::Position(Vector3::new(x, y, z)));
+                    }
+                    if ui.small_button(im_str!("Stop orders")) {
+                        edit(DebugEdit::Stop);
+                    }
+                    ui.text(im_str!("Give to"));
+                    for player in frame.players.values() {
+                        ui.same_line(0.0);
+                        if ui.small_button(&im_str!("{}##give", player.id)) {
+                            edit(DebugEdit::Owner(player.id));
+                        }
+                    }
+                    ui.separator();
+                }
+                ui.text_wrapped(&im_str!("{}", json));
+            });
+        events
+    }
+}
@@ -0,0 +1,157 @@
+use super::client::*;
+use super::heightmap_editor;
+use crate::botdef::BotDef;
+use crate::frame::{Frame, Player, WinCondition};
+use crate::mobile::KBot;
+use crate::*;
+use fnv::FnvHashMap;
+use na::{Point3, Vector3};
+
+const UNIT: &str = "src/asset/botdef/unit_example.json";
+const COMMANDER: &str = "src/asset/botdef/commander_example.json";
+///Side of the square army of each player, in units
+const ARMY_SIDE: usize = 12;
+const SPACING: f32 = 4.0;
+
+///Game of the play command, replaces the example game
+pub struct Skirmish {
+    ///Map directory, the one already loaded when None
+    pub map: Option<String>,
+    ///Enemy players, each on its own team. No computer player exists yet, they only return fire.
+    pub ai: u32,
+    pub win_condition: WinCondition,
+}
+
+impl App {
+    ///Starts the skirmish from the command line instead of the home menu
+    pub fn start_skirmish(&mut self, map: Option<String>, ai: u32, win_condition: WinCondition) {
+        self.skirmish = Some(Skirmish {
+            map,
+            ai: ai.max(1),
+            win_condition,
+        });
+        self.mailbox.push(RenderEvent::ChangeMode {
+            from: self.main_menu,
+            to: MainMode::Loading,
+        });
+        self.main_menu = MainMode::Loading;
+    }
+
+    pub fn keep_window_size(&mut self) {
+        self.maximize = false;
+    }
+
+    pub fn start_editor(&mut self, unit: bool) {
+        let to = if unit {
+            MainMode::UnitEditor
+        } else {
+            MainMode::MapEditor
+        };
+        self.mailbox.push(RenderEvent::ChangeMode {
+            from: self.main_menu,
+            to,
+        });
+        self.main_menu = to;
+    }
+
+    ///Armies evenly spread on a circle around the center of the map, ours first, each led by a
+    ///commander
+    pub fn skirmish_frame(&mut self) -> Option<Frame> {
+        let (map, ai, mut win_condition) = match self.skirmish.as_ref() {
+            Some(skirmish) => (
+                skirmish.map.clone(),
+                skirmish.ai,
+                skirmish.win_condition.clone(),
+            ),
+            None => return None,
+        };
+        if let Some(map) = &map {
+            heightmap_editor::State::load(&mut self.heightmap_gpu, map);
+        }
+        let botdef = match BotDef::open(UNIT) {
+            Ok(botdef) => botdef,
+            Err(e) => {
+                log::error!("Could not read botdef {}: {}", UNIT, e);
+                return None;
+            }
+        };
+        let commander = match BotDef::open(COMMANDER) {
+            Ok(commander) => Some(commander),
+            Err(e) => {
+                log::error!("Could not read botdef {}: {}", COMMANDER, e);
+                if win_condition == WinCondition::CommanderDeath {
+                    log::warn!("Playing until annihilation instead");
+                    win_condition = WinCondition::Annihilation;
+                }
+                None
+            }
+        };
+
+        let phy = &self.heightmap_gpu.phy;
+        let center = (phy.width as f32 / 2.0, phy.height as f32 / 2.0);
+        let radius = center.0.min(center.1) * 0.6;
+        let half = (ARMY_SIDE - 1) as f32 * SPACING / 2.0;
+        let mut players = FnvHashMap::default();
+        let mut kbots = FnvHashMap::default();
+        let mut camera = center;
+        for i in 0..=ai {
+            let angle = std::f32::consts::PI * (1.0 + 2.0 * i as f32 / (ai + 1) as f32);
+            let (cx, cy) = (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            );
+            let mut player = Player::new();
+            player.team = i as u8;
+            player.slot = i as u8;
+            for n in 0..ARMY_SIDE * ARMY_SIDE {
+                let x = cx - half + (n % ARMY_SIDE) as f32 * SPACING;
+                let y = cy - half + (n / ARMY_SIDE) as f32 * SPACING;
+                let mut kbot = KBot::new(Point3::new(x, y, phy.z(x, y)), &botdef, player.id);
+                kbot.team = player.team;
+                player.kbots.insert(kbot.id);
+                kbots.insert(kbot.id, kbot);
+            }
+            //In front of the army, on the side of the camera
+            if let Some(commander) = &commander {
+                let y = cy - half - SPACING * 2.0;
+                let mut kbot = KBot::new(Point3::new(cx, y, phy.z(cx, y)), commander, player.id);
+                kbot.team = player.team;
+                player.kbots.insert(kbot.id);
+                kbots.insert(kbot.id, kbot);
+            }
+            if i == 0 {
+                camera = (cx, cy - 50.0);
+            }
+            players.insert(player.id, player);
+        }
+        log::info!(
+            "Starting a skirmish against {} enemies with {} bots",
+            ai,
+            kbots.len()
+        );
+
+        self.game_state.position =
+            Point3::new(camera.0, camera.1, phy.z(camera.0, camera.1) + 50.0);
+        self.game_state.dir = Vector3::new(0.0, 0.3, -1.0);
+
+        let mut bot_defs = FnvHashMap::default();
+        bot_defs.insert(botdef.id, botdef);
+        let moddef = moddef::ModDef {
+            units_id: bot_defs.keys().copied().collect(),
+            con_map: FnvHashMap::default(),
+        };
+        //Only placed at the start, commanders can't be built
+        if let Some(commander) = commander {
+            bot_defs.insert(commander.id, commander);
+        }
+        Some(Frame {
+            players,
+            moddef,
+            kbots,
+            heightmap_phy: Some(self.heightmap_gpu.phy.clone()),
+            bot_defs,
+            win_condition,
+            ..Frame::new()
+        })
+    }
+}
@@ -2,69 +2,60 @@ use super::client::*;
 use crate::*;
 use unit_part_gpu::*;
 
+use super::cursor::{CursorContext, CursorMode};
 use super::uitool::UiTool;
 impl App {
     pub fn clear_gpu_instance_and_game_state(&mut self) {
         self.game_state.players.clear();
         self.game_state.my_player_id = None;
+        self.game_state.observed_player_id = None;
         self.game_state.kbots.clear();
         self.game_state.selected.clear();
         self.game_state.explosions.clear();
+        self.game_state.active_effect_parts.clear();
+        self.game_state.beams.clear();
         self.game_state.kinematic_projectiles_cache.clear();
+        self.game_state.trails.clear();
+        self.game_state.pings.clear();
+        self.game_state.alerts.clear();
+        self.game_state.events.clear();
         // self.unit_editor.root.children.clear();
 
-        self.health_bar.update_instance(&[], &self.gpu.device);
-        self.unit_icon.update_instance(&[], &self.gpu.device);
-        self.explosion_gpu.update_instance(&[], &self.gpu.device);
-        for (model_gpu_state) in self.unit_part_gpu.states.iter_mut() {
-            match model_gpu_state {
-                ModelGpuState::Ready(model_gpu) => {
-                    model_gpu.update_instance_dirty(&[], &self.gpu.device)
-                }
-                _ => {}
-            }
-        }
-        self.kinematic_projectile_gpu
-            .update_instance_dirty(&[], &self.gpu.device);
+        self.health_bar.clear_instance();
+        self.unit_icon.clear_instance();
+        self.explosion_gpu.clear_instance();
+        self.model_batch.clear_instances();
+        self.impostor_gpu.clear_instance();
+        self.kinematic_projectile_gpu.clear_instance();
+        self.nanolathe_gpu.clear_instance();
+        self.shield_gpu.clear_instance();
+        self.trail_gpu.update_vertices(&[], &self.gpu.device);
+        self.beam_gpu.clear_instance();
+        self.ground_shadow_gpu.clear_instance();
+        self.waypoint_gpu.clear_instance();
     }
 
+    ///Extraction of the instance data of each part, out is indexed by mesh_index.
+    ///ready has the texture layer of each mesh uploaded to the ModelBatch.
+    ///Doesn't touch the gpu so that it can run on any thread.
     pub fn visit_part_tree(
         part_tree: &unit::PartTree,
-        root_trans: &Matrix4<f32>,
-        unit_part_gpu: &mut UnitPartGpu,
+        pose: &mobile::Pose,
+        last_pose: &mobile::Pose,
+        out: &mut Vec<Vec<f32>>,
+        ready: &[Option<f32>],
         highlight_factor: f32,
         team: f32,
         con_completed: f32,
-        weapon0_dir: Vector3<f32>,
-        wheel0_angle: f32,
     ) {
         for c in part_tree.children.iter() {
+            let combined = Self::joint_trans(c, pose);
+            let last_combined = Self::joint_trans(c, last_pose);
             if let Some(placed_mesh) = &c.placed_mesh {
                 let display_model = &placed_mesh;
 
-                let combined = match &c.joint {
-                    unit::Joint::Fix => root_trans * c.parent_to_self,
-                    unit::Joint::AimWeapon0 => {
-                        let comb = root_trans * c.parent_to_self;
-
-                        utils::face_towards_dir(
-                            &Vector3::new(comb[12], comb[13], comb[14]),
-                            &weapon0_dir,
-                            &Vector3::new(0.0, 0.0, 1.0),
-                        )
-                    }
-                    unit::Joint::Wheel0 => {
-                        let comb = root_trans * c.parent_to_self;
-
-                        comb * utils::face_towards_dir(
-                            &Vector3::new(0.0, 0.0, 0.0),
-                            &Vector3::new(0.0, 1.0, 0.0),
-                            &Vector3::new(f32::cos(wheel0_angle), 0.0, f32::sin(wheel0_angle)),
-                        )
-                    }
-                };
-
                 let for_display = combined * display_model.trans;
+                let last_for_display = last_combined * display_model.trans;
                 // log::warn!(
                 //     "root {:?}\nlocal {:?}\ncombined {:?}\n",
                 //     root_trans,
@@ -72,22 +63,11 @@ impl App {
                 //     combined
                 // );
 
-                //TODO fix performance : HALF OF TIME IS IN GET_MUT
-                match unit_part_gpu.get_mut(placed_mesh.mesh_index) {
-                    //}  get_mut(&placed_mesh.mesh_path) {
-                    ModelGpuState::Ready(generic_cpu) => {
-                        let buf = &mut generic_cpu.instance_attr_cpu_buf;
+                match ready.get(placed_mesh.mesh_index) {
+                    Some(Some(texture_layer)) => {
+                        let buf = &mut out[placed_mesh.mesh_index];
 
-                        let isometry: Isometry3<f32> = unsafe {
-                            na::convert_unchecked::<Matrix4<f32>, Isometry3<f32>>(for_display)
-                        };
-                        let euler = isometry.rotation.euler_angles();
-                        buf.push(for_display[12]);
-                        buf.push(for_display[13]);
-                        buf.push(for_display[14]);
-                        buf.push(euler.0);
-                        buf.push(euler.1);
-                        buf.push(euler.2);
+                        Self::push_position_euler(buf, for_display);
 
                         //Bit representation in decimal order
                         //SELECTED TEAM TEAM
@@ -96,49 +76,165 @@ impl App {
 
                         buf.push(bitpacked);
                         buf.push(con_completed);
+
+                        let material = &placed_mesh.material;
+                        buf.push(material.emissive);
+                        buf.push(material.pulse_frequency);
+                        buf.push(material.uv_scroll[0]);
+                        buf.push(material.uv_scroll[1]);
+                        buf.push(*texture_layer);
+
+                        Self::push_position_euler(buf, last_for_display);
                     }
                     _ => {}
                 }
-                Self::visit_part_tree(
-                    c,
-                    &combined,
-                    unit_part_gpu,
-                    highlight_factor,
-                    team,
-                    con_completed,
-                    weapon0_dir,
-                    wheel0_angle,
-                );
-            } else {
-                Self::visit_part_tree(
-                    c,
-                    root_trans,
-                    unit_part_gpu,
-                    highlight_factor,
-                    team,
-                    con_completed,
-                    weapon0_dir,
-                    wheel0_angle,
-                );
+            }
+            Self::visit_part_tree(
+                c,
+                &mobile::Pose {
+                    trans: combined,
+                    ..*pose
+                },
+                &mobile::Pose {
+                    trans: last_combined,
+                    ..*last_pose
+                },
+                out,
+                ready,
+                highlight_factor,
+                team,
+                con_completed,
+            );
+        }
+    }
+
+    ///Mesh index and transform of each part under part_tree, for the impostor bake
+    fn collect_part_meshes(
+        part_tree: &unit::PartTree,
+        pose: &mobile::Pose,
+        out: &mut Vec<(usize, Matrix4<f32>)>,
+    ) {
+        for c in part_tree.children.iter() {
+            let combined = Self::joint_trans(c, pose);
+            if let Some(placed_mesh) = &c.placed_mesh {
+                out.push((placed_mesh.mesh_index, combined * placed_mesh.trans));
+            }
+            Self::collect_part_meshes(
+                c,
+                &mobile::Pose {
+                    trans: combined,
+                    ..*pose
+                },
+                out,
+            );
+        }
+    }
+
+    ///Bakes the impostor of the unit types whose meshes are all loaded
+    fn bake_impostors(&mut self) {
+        let rest_pose = mobile::Pose {
+            trans: Matrix4::identity(),
+            weapon0_dir: Vector3::new(1.0, 0.0, 0.0),
+            wheel0_angle: 0.0,
+        };
+        for botdef in self.game_state.frame_zero.bot_defs.values() {
+            if self.impostor_gpu.baked().contains_key(&botdef.id) {
+                continue;
+            }
+            let mut parts = Vec::new();
+            Self::collect_part_meshes(&botdef.part_tree, &rest_pose, &mut parts);
+            let mut meshes = Vec::new();
+            let mut loaded = true;
+            for (mesh_index, trans) in parts {
+                match self.unit_part_gpu.states.get(mesh_index) {
+                    Some(ModelGpuState::Ready(mesh)) => {
+                        let (vertices, indices) = self.model_batch.mesh_data(*mesh);
+                        meshes.push(gpu_obj::impostor::BakeMesh {
+                            trans,
+                            vertices,
+                            indices,
+                        });
+                    }
+                    //Drawn without it
+                    Some(ModelGpuState::Error(_)) => {}
+                    _ => {
+                        loaded = false;
+                        break;
+                    }
+                }
+            }
+            if loaded {
+                self.impostor_gpu.bake(botdef.id, &meshes);
+            }
+        }
+    }
+
+    ///Transform of the part c, child of pose.trans
+    fn joint_trans(c: &unit::PartTree, pose: &mobile::Pose) -> Matrix4<f32> {
+        if c.placed_mesh.is_none() {
+            return pose.trans;
+        }
+        match &c.joint {
+            unit::Joint::Fix => pose.trans * c.parent_to_self,
+            unit::Joint::AimWeapon0 => {
+                let comb = pose.trans * c.parent_to_self;
+
+                utils::face_towards_dir(
+                    &Vector3::new(comb[12], comb[13], comb[14]),
+                    &pose.weapon0_dir,
+                    &Vector3::new(0.0, 0.0, 1.0),
+                )
+            }
+            unit::Joint::Wheel0 => {
+                let comb = pose.trans * c.parent_to_self;
+
+                comb * utils::face_towards_dir(
+                    &Vector3::new(0.0, 0.0, 0.0),
+                    &Vector3::new(0.0, 1.0, 0.0),
+                    &Vector3::new(
+                        f32::cos(pose.wheel0_angle),
+                        0.0,
+                        f32::sin(pose.wheel0_angle),
+                    ),
+                )
             }
         }
     }
 
+    ///Position then euler angles of trans, as the instances of the models start
+    fn push_position_euler(buf: &mut Vec<f32>, trans: Matrix4<f32>) {
+        let isometry: Isometry3<f32> =
+            unsafe { na::convert_unchecked::<Matrix4<f32>, Isometry3<f32>>(trans) };
+        let euler = isometry.rotation.euler_angles();
+        buf.push(trans[12]);
+        buf.push(trans[13]);
+        buf.push(trans[14]);
+        buf.push(euler.0);
+        buf.push(euler.1);
+        buf.push(euler.2);
+    }
+
     pub fn upload_to_gpu(&mut self, view_proj: &Matrix4<f32>, encoder: &mut wgpu::CommandEncoder) {
         //Upload to gpu
         let upload_to_gpu_duration = time(|| {
+            profile_scope!("upload_to_gpu");
             let unit_icon_distance = self.game_state.unit_icon_distance;
+            let impostor_distance = self.game_state.impostor_distance;
+            self.bake_impostors();
 
             //generic_gpu
             {
-                for model_gpu in self.unit_part_gpu.states.iter_mut() {
-                    match model_gpu {
-                        ModelGpuState::Ready(model_gpu) => {
-                            model_gpu.instance_attr_cpu_buf.clear();
-                        }
-                        _ => {}
-                    }
-                }
+                let model_batch = &self.model_batch;
+                let ready: Vec<Option<f32>> = self
+                    .unit_part_gpu
+                    .states
+                    .iter()
+                    .map(|model_gpu| match model_gpu {
+                        ModelGpuState::Ready(mesh) => Some(model_batch.texture_layer(*mesh) as f32),
+                        _ => None,
+                    })
+                    .collect();
+                let mut extracted = vec![Vec::new(); ready.len()];
 
                 let identity = utils::face_towards_dir(
                     &Vector3::new(300.0_f32, 100.0, 0.50),
@@ -148,66 +244,156 @@ impl App {
 
                 let t = self.game_state.start_time.elapsed().as_secs_f32();
                 if self.main_menu == MainMode::UnitEditor {
+                    //Still, the motion vectors only follow the camera
+                    let pose = mobile::Pose {
+                        trans: identity,
+                        weapon0_dir: Vector3::new(
+                            f32::cos(t),
+                            f32::sin(t),
+                            f32::sin(t / 5.0) * 0.1,
+                        )
+                        .normalize(),
+                        wheel0_angle: t * 2.0,
+                    };
                     Self::visit_part_tree(
                         &self.unit_editor.botdef.part_tree,
-                        &identity,
-                        &mut self.unit_part_gpu,
+                        &pose,
+                        &pose,
+                        &mut extracted,
+                        &ready,
                         0.0,
                         0.0,
                         1.0,
-                        Vector3::new(f32::cos(t), f32::sin(t), f32::sin(t / 5.0) * 0.1).normalize(),
-                        (t * 2.0),
                     );
                 }
 
-                //Kbot
+                //Kbot, extracted in parallel by chunks, joined in order before the upload
                 {
-                    for (mobile, client_kbot) in
-                        self.game_state.kbots.iter_mut().filter(|e| {
-                            e.1.is_in_screen && e.1.distance_to_camera < unit_icon_distance
-                        })
-                    {
-                        let mat = client_kbot.trans.unwrap();
-
-                        let highlight_factor: f32 = match (
-                            self.game_state.selected.contains(&mobile.id),
-                            self.game_state.under_mouse == Some(mobile.id),
-                        ) {
-                            (true, false) => 1.0,
-                            (false, false) => 0.0,
-                            (false, true) => 2.0,
-                            (true, true) => 3.0,
-                        };
+                    use rayon::prelude::*;
+                    let kbots = &self.game_state.kbots;
+                    let selected = &self.game_state.selected;
+                    let under_mouse = self.game_state.under_mouse;
+                    let bot_defs = &self.game_state.frame_zero.bot_defs;
+                    let baked = self.impostor_gpu.baked();
+                    let ready = &ready;
+                    let chunks: Vec<Vec<Vec<f32>>> = self.threadpool.install(|| {
+                        kbots
+                            .par_chunks(1000)
+                            .map(|chunk| {
+                                let mut out = vec![Vec::new(); ready.len()];
+                                for (mobile, client_kbot) in chunk.iter().filter(|e| {
+                                    e.1.is_in_screen
+                                        && e.1.distance_to_camera < unit_icon_distance
+                                        && !(e.1.distance_to_camera >= impostor_distance
+                                            && baked
+                                                .get(&e.0.botdef_id)
+                                                .map_or(false, Option::is_some))
+                                }) {
+                                    let pose = client_kbot.pose().unwrap();
+                                    let last_pose = client_kbot.last_pose.unwrap_or(pose);
+
+                                    let highlight_factor: f32 = match (
+                                        selected.contains(&mobile.id),
+                                        under_mouse == Some(mobile.id),
+                                    ) {
+                                        (true, false) => 1.0,
+                                        (false, false) => 0.0,
+                                        (false, true) => 2.0,
+                                        (true, true) => 3.0,
+                                    };
 
-                        let team = mobile.team;
-
-                        if let Some(botdef) =
-                            self.game_state.frame_zero.bot_defs.get(&mobile.botdef_id)
-                        {
-                            Self::visit_part_tree(
-                                &botdef.part_tree,
-                                &mat,
-                                &mut self.unit_part_gpu,
-                                highlight_factor,
-                                team as f32,
-                                mobile.con_completed,
-                                client_kbot.weapon0_dir,
-                                client_kbot.wheel0_angle,
-                            );
+                                    let team = mobile.team;
+
+                                    if let Some(botdef) = bot_defs.get(&mobile.botdef_id) {
+                                        Self::visit_part_tree(
+                                            &botdef.part_tree,
+                                            &pose,
+                                            &last_pose,
+                                            &mut out,
+                                            ready,
+                                            highlight_factor,
+                                            team as f32,
+                                            mobile.con_completed,
+                                        );
+                                    }
+                                }
+                                out
+                            })
+                            .collect()
+                    });
+                    for chunk in chunks {
+                        for (buf, part) in extracted.iter_mut().zip(chunk) {
+                            buf.extend(part);
                         }
                     }
                 }
 
-                for model_gpu in self.unit_part_gpu.states.iter_mut() {
-                    match model_gpu {
-                        ModelGpuState::Ready(model_gpu) => {
-                            model_gpu.update_instance_dirty_own_buffer(&self.gpu.device);
-                        }
-                        _ => {}
+                //The extracted data replaces the instances of each mesh
+                for (state, buf) in self.unit_part_gpu.states.iter().zip(extracted) {
+                    if let ModelGpuState::Ready(mesh) = state {
+                        self.model_batch.set_instances(*mesh, buf);
                     }
                 }
+                self.model_batch.upload(
+                    &self.gpu.device,
+                    encoder,
+                    &mut self.staging_belt,
+                    &mut self.mip_generator,
+                    view_proj,
+                );
             }
 
+            //Impostor, the models without one stay models
+            self.vertex_attr_buffer_f32.clear();
+            for (kbot, client_kbot) in self.game_state.kbots.iter().filter(|e| {
+                e.1.is_in_screen
+                    && e.1.distance_to_camera >= impostor_distance
+                    && e.1.distance_to_camera < unit_icon_distance
+            }) {
+                let baked = match self.impostor_gpu.baked().get(&kbot.botdef_id) {
+                    Some(Some(baked)) => *baked,
+                    _ => continue,
+                };
+                let last_position = client_kbot
+                    .last_pose
+                    .map_or(client_kbot.position.coords, |pose| {
+                        Vector3::new(pose.trans[12], pose.trans[13], pose.trans[14])
+                    });
+                let highlight_factor: f32 = match (
+                    self.game_state.selected.contains(&kbot.id),
+                    self.game_state.under_mouse == Some(kbot.id),
+                ) {
+                    (true, false) => 1.0,
+                    (false, false) => 0.0,
+                    (false, true) => 2.0,
+                    (true, true) => 3.0,
+                };
+                self.vertex_attr_buffer_f32.extend_from_slice(&[
+                    client_kbot.position.x,
+                    client_kbot.position.y,
+                    client_kbot.position.z,
+                    client_kbot.dir.y.atan2(client_kbot.dir.x),
+                    last_position.x,
+                    last_position.y,
+                    last_position.z,
+                    baked.radius,
+                    baked.layer as f32,
+                    highlight_factor * 100. + kbot.team as f32,
+                ]);
+            }
+            self.impostor_gpu.upload(
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+                &mut self.mip_generator,
+            );
+            self.impostor_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
+
             // //Kbot
             // {
             //     self.vertex_attr_buffer_f32.clear();
@@ -255,11 +441,27 @@ impl App {
                 self.vertex_attr_buffer_f32.push(euler.1);
                 self.vertex_attr_buffer_f32.push(euler.2);
                 self.vertex_attr_buffer_f32.push(99.);
-                self.vertex_attr_buffer_f32.push(1.0)
+                self.vertex_attr_buffer_f32.push(1.0);
+                //Projectiles glow steadily
+                self.vertex_attr_buffer_f32
+                    .extend_from_slice(&[1.0, 0.0, 0.0, 0.0]);
+                //Default texture layer
+                self.vertex_attr_buffer_f32.push(0.0);
+                //Their motion is not tracked, TAA clamps their trails away
+                self.vertex_attr_buffer_f32.push(mat[12]);
+                self.vertex_attr_buffer_f32.push(mat[13]);
+                self.vertex_attr_buffer_f32.push(mat[14]);
+                self.vertex_attr_buffer_f32.push(euler.0);
+                self.vertex_attr_buffer_f32.push(euler.1);
+                self.vertex_attr_buffer_f32.push(euler.2);
             }
 
-            self.kinematic_projectile_gpu
-                .update_instance_dirty(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+            self.kinematic_projectile_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
 
             //Arrow
             self.vertex_attr_buffer_f32.clear();
@@ -278,8 +480,163 @@ impl App {
                     .push((arrow.end.coords - arrow.position.coords).magnitude());
             }
 
-            self.arrow_gpu
-                .update_instance(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+            self.arrow_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
+
+            //Nanolathe streams
+            self.vertex_attr_buffer_f32.clear();
+            for (kbot, client_kbot) in self
+                .game_state
+                .kbots
+                .iter()
+                .filter(|e| e.1.is_in_screen && e.1.distance_to_camera < unit_icon_distance)
+            {
+                let (target_id, reclaim) = match kbot.current_command {
+                    mobile::Command::Build(id) | mobile::Command::Repair(id) => (id, false),
+                    mobile::Command::Reclaim(id) => (id, true),
+                    mobile::Command::Area {
+                        kind,
+                        target: Some(id),
+                        ..
+                    } => (id, kind == mobile::AreaKind::Reclaim),
+                    _ => continue,
+                };
+                let frame_zero = &self.game_state.frame_zero;
+                if let (Some(target), Some(botdef)) = (
+                    frame_zero.kbots.get(&target_id),
+                    frame_zero.bot_defs.get(&kbot.botdef_id),
+                ) {
+                    let max_life = frame_zero
+                        .bot_defs
+                        .get(&target.botdef_id)
+                        .map(|b| b.max_life)
+                        .unwrap_or(0);
+                    let is_working =
+                        reclaim || target.con_completed < 1.0 || target.life < max_life;
+                    let dist = (target.position.coords - client_kbot.position.coords).magnitude();
+                    if is_working && dist <= botdef.build_dist {
+                        let nozzle = client_kbot.position
+                            + client_kbot.weapon0_dir * 0.3
+                            + Vector3::new(0.0, 0.0, 0.6);
+                        let target = target.position + Vector3::new(0.0, 0.0, 0.5);
+                        //Reclaimed matter flows back to the nozzle
+                        let (from, to) = if reclaim {
+                            (target, nozzle)
+                        } else {
+                            (nozzle, target)
+                        };
+                        //Stable per constructor so the stream doesn't flicker between frames
+                        let seed = (kbot.id.value % 1000) as f32;
+                        self.vertex_attr_buffer_f32.extend_from_slice(&[
+                            from.x,
+                            from.y,
+                            from.z,
+                            seed,
+                            to.x,
+                            to.y,
+                            to.z,
+                            kbot.team as f32,
+                        ]);
+                    }
+                }
+            }
+
+            self.nanolathe_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
+
+            //Shield
+            self.vertex_attr_buffer_f32.clear();
+            for (kbot, client_kbot) in self.game_state.kbots.iter().filter(|e| e.1.is_in_screen) {
+                if let Some(botdef) = self.game_state.frame_zero.bot_defs.get(&kbot.botdef_id) {
+                    if botdef.shield_radius > 0.0 && kbot.con_completed >= 1.0 {
+                        self.vertex_attr_buffer_f32.extend_from_slice(&[
+                            client_kbot.position.x,
+                            client_kbot.position.y,
+                            client_kbot.position.z,
+                            botdef.shield_radius,
+                        ]);
+                    }
+                }
+            }
+
+            self.shield_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
+
+            //Trail
+            self.vertex_attr_buffer_f32.clear();
+            self.game_state.trails.build_vertices(
+                &self.game_state.position_smooth,
+                self.game_state.server_sec,
+                &mut self.vertex_attr_buffer_f32,
+            );
+
+            self.trail_gpu
+                .update_vertices(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+
+            //Beam
+            self.vertex_attr_buffer_f32.clear();
+            let server_sec = self.game_state.server_sec;
+            for (i, beam) in self.game_state.beams.iter().enumerate() {
+                let life = (server_sec - beam.born_sec) / (beam.death_sec - beam.born_sec);
+                self.vertex_attr_buffer_f32.extend_from_slice(&[
+                    beam.from.x,
+                    beam.from.y,
+                    beam.from.z,
+                    0.15,
+                    beam.to.x,
+                    beam.to.y,
+                    beam.to.z,
+                    (1.0 - life).max(0.0).min(1.0),
+                    beam.color[0],
+                    beam.color[1],
+                    beam.color[2],
+                    i as f32,
+                ]);
+            }
+
+            self.beam_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
+
+            //Ground shadow of flying units
+            self.vertex_attr_buffer_f32.clear();
+            for (kbot, client_kbot) in self.game_state.kbots.iter().filter(|e| e.1.is_in_screen) {
+                if kbot.altitude > 0.0 {
+                    if let Some(botdef) = self.game_state.frame_zero.bot_defs.get(&kbot.botdef_id) {
+                        let (x, y) = (client_kbot.position.x, client_kbot.position.y);
+                        let fade = 1.0 - kbot.altitude / (botdef.cruise_altitude * 2.0);
+                        self.vertex_attr_buffer_f32.extend_from_slice(&[
+                            x,
+                            y,
+                            self.heightmap_gpu.phy.z_linear(x, y),
+                            botdef.radius * 1.2,
+                            0.5 * fade.max(0.0).min(1.0),
+                        ]);
+                    }
+                }
+            }
+
+            self.ground_shadow_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
 
             //Unit life
             self.vertex_attr_buffer_f32.clear();
@@ -318,7 +675,8 @@ impl App {
 
                 let display_life = life < 1.0;
                 let display_con_completed = con_completed < 1.0;
-                let display_one = display_life || display_con_completed;
+                let rank = kbot.rank();
+                let display_one = display_life || display_con_completed || rank > 0;
 
                 if alpha > 0.0 && display_one {
                     let w = self.gpu.sc_desc.width as f32;
@@ -341,14 +699,33 @@ impl App {
                     let min = offset - half_size;
                     let max = offset + half_size;
                     let life = kbot.life as f32 / botdef.max_life as f32;
-                    self.vertex_attr_buffer_f32
-                        .extend_from_slice(min.as_slice());
-                    self.vertex_attr_buffer_f32
-                        .extend_from_slice(max.as_slice());
-                    self.vertex_attr_buffer_f32.push(life);
-                    self.vertex_attr_buffer_f32.push(alpha);
-                    //health
-                    self.vertex_attr_buffer_f32.push(0.0);
+                    if display_life || display_con_completed {
+                        self.vertex_attr_buffer_f32
+                            .extend_from_slice(min.as_slice());
+                        self.vertex_attr_buffer_f32
+                            .extend_from_slice(max.as_slice());
+                        self.vertex_attr_buffer_f32.push(life);
+                        self.vertex_attr_buffer_f32.push(alpha);
+                        //health
+                        self.vertex_attr_buffer_f32.push(0.0);
+                    }
+
+                    //Veterancy chevrons, one per rank, above the health bar
+                    if rank > 0 {
+                        let chevron_half_size =
+                            Vector2::new(4.0 * rank as f32 / w, 4.0 / h) * size_factor;
+                        let center = offset + Vector2::new(0.0, half_size.y + chevron_half_size.y);
+                        let min = center - chevron_half_size;
+                        let max = center + chevron_half_size;
+                        self.vertex_attr_buffer_f32
+                            .extend_from_slice(min.as_slice());
+                        self.vertex_attr_buffer_f32
+                            .extend_from_slice(max.as_slice());
+                        self.vertex_attr_buffer_f32.push(rank as f32);
+                        self.vertex_attr_buffer_f32.push(alpha);
+                        //rank
+                        self.vertex_attr_buffer_f32.push(2.0);
+                    }
 
                     let mut next_bar_offset = Vector2::new(0., -3. * half_size.y);
                     if display_con_completed {
@@ -366,8 +743,12 @@ impl App {
                     }
                 }
             }
-            self.health_bar
-                .update_instance(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+            self.health_bar.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
 
             //Icon
             self.vertex_attr_buffer_f32.clear();
@@ -388,50 +769,55 @@ impl App {
                 let team = if is_selected { -1.0 } else { kbot.team as f32 };
                 self.vertex_attr_buffer_f32.push(team);
             }
-            self.unit_icon
-                .update_instance(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+            self.unit_icon.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
 
             //Cursor Icon
             self.vertex_attr_buffer_f32.clear();
             {
-                let cursor_icon_size: i32 = 48;
-                let cursor_icon_size_half = cursor_icon_size / 2;
+                let cursor_icon_size = (48.0 * self.ui_scale()) as i32;
                 let cursor_icon_size_third = cursor_icon_size / 3;
+                let context = self.cursor_context();
                 let (x, y) = self.input_state.cursor_pos;
                 let (x, y) = (x as i32, y as i32);
-                let (x, y) = (x + cursor_icon_size_third, y - cursor_icon_size_third);
+
+                let over_ui = self.imgui_wrap.imgui.io().want_capture_mouse;
+
+                //Software: the icon is the cursor, its hotspot on the mouse, imgui draws its own
+                //cursor over the ui
+                //Hardware: a badge next to the system cursor, hidden when there is nothing to say
+                let (min_x, min_y, size) = match self.game_state.cursor_mode {
+                    CursorMode::Software if over_ui => (x, y, 0),
+                    CursorMode::Software => {
+                        let (hx, hy) = context.hotspot();
+                        let min_x = x - (hx * cursor_icon_size as f32) as i32;
+                        let min_y = y - (hy * cursor_icon_size as f32) as i32;
+                        (min_x, min_y, cursor_icon_size)
+                    }
+                    CursorMode::Hardware if context == CursorContext::Default => (x, y, 0),
+                    CursorMode::Hardware => (
+                        x + cursor_icon_size_third - cursor_icon_size / 2,
+                        y - cursor_icon_size_third - cursor_icon_size / 2,
+                        cursor_icon_size,
+                    ),
+                };
 
                 let min_screen = Vector2::new(
-                    (x - cursor_icon_size_half) as f32 / self.gpu.sc_desc.width as f32,
-                    (y - cursor_icon_size_half) as f32 / self.gpu.sc_desc.height as f32,
+                    min_x as f32 / self.gpu.sc_desc.width as f32,
+                    min_y as f32 / self.gpu.sc_desc.height as f32,
                 );
                 let max_screen = Vector2::new(
-                    (x + cursor_icon_size_half) as f32 / self.gpu.sc_desc.width as f32,
-                    (y + cursor_icon_size_half) as f32 / self.gpu.sc_desc.height as f32,
+                    (min_x + size) as f32 / self.gpu.sc_desc.width as f32,
+                    (min_y + size) as f32 / self.gpu.sc_desc.height as f32,
                 );
-                let mut min_texture = Vector2::new(0.0, 0.0);
-                let mut max_texture = Vector2::new(0.0, 0.0);
 
-                let mut index_to_vector = |x, y| {
-                    min_texture = Vector2::new(x as f32 * 0.25, y as f32 * 0.25);
-                    max_texture = min_texture + Vector2::new(0.25, 0.25);
-                };
-
-                match self.game_state.uitool {
-                    UiTool::Repair => {
-                        index_to_vector(0, 0);
-                    }
-                    UiTool::Spawn(..) => {
-                        index_to_vector(1, 0);
-                    }
-                    UiTool::Guard => {
-                        index_to_vector(2, 1);
-                    }
-                    UiTool::Attack => {
-                        index_to_vector(1, 1);
-                    }
-                    _ => {}
-                }
+                let (cell_x, cell_y) = context.atlas_cell();
+                let min_texture = Vector2::new(cell_x as f32 * 0.25, cell_y as f32 * 0.25);
+                let max_texture = min_texture + Vector2::new(0.25, 0.25);
                 self.vertex_attr_buffer_f32
                     .extend_from_slice(min_screen.as_slice());
                 self.vertex_attr_buffer_f32
@@ -441,17 +827,157 @@ impl App {
                 self.vertex_attr_buffer_f32
                     .extend_from_slice(max_texture.as_slice());
             }
-            self.cursor_icon
-                .update_instance(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+            self.cursor_icon.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
+
+            self.hud_gpu.update(
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+                self.gpu.sc_desc.width,
+                self.gpu.sc_desc.height,
+                &self.hud.quads,
+            );
+
+            //Range rings of the selected units, and of the unit placed under the cursor
+            {
+                use gpu_obj::range_ring::{RangeRing, RingKind};
+                let bot_defs = &self.game_state.frame_zero.bot_defs;
+                let mut rings = Vec::new();
+                let mut push_rings = |botdef: &botdef::BotDef, x: f32, y: f32| {
+                    let mut ring = |radius: f32, kind: RingKind| {
+                        rings.push(RangeRing { x, y, radius, kind });
+                    };
+                    ring(botdef::WEAPON_RANGE, RingKind::Weapon);
+                    if botdef.shield_radius > 0.0 {
+                        ring(botdef.shield_radius, RingKind::Shield);
+                    }
+                    if botdef.build_power > 0.0 {
+                        ring(botdef.build_dist, RingKind::Build);
+                    }
+                    if let botdef::Strategic::AntiNuke { radius, .. } = botdef.strategic {
+                        ring(radius, RingKind::AntiNuke);
+                    }
+                };
+                for (kbot, client_kbot) in self.game_state.kbots.iter() {
+                    if self.game_state.selected.contains(&kbot.id) {
+                        if let Some(botdef) = bot_defs.get(&kbot.botdef_id) {
+                            push_rings(botdef, client_kbot.position.x, client_kbot.position.y);
+                        }
+                    }
+                }
+                if let (UiTool::Spawn(botdef_id), Some(placement)) =
+                    (self.game_state.uitool, Self::placement(&self.game_state))
+                {
+                    if let Some(botdef) = bot_defs.get(&botdef_id) {
+                        push_rings(botdef, placement.x, placement.y);
+                    }
+                    for (x, y, radius) in placement.touching {
+                        rings.push(RangeRing {
+                            x,
+                            y,
+                            radius: radius + 0.5,
+                            kind: RingKind::Adjacency,
+                        });
+                    }
+                }
+                if let (UiTool::Ferry, Some(pickup)) =
+                    (self.game_state.uitool, self.game_state.ferry_pickup)
+                {
+                    rings.push(RangeRing {
+                        x: pickup.x,
+                        y: pickup.y,
+                        radius: mobile::FERRY_RADIUS,
+                        kind: RingKind::Build,
+                    });
+                }
+                rings.extend(super::ping::rings(&self.game_state));
+                if let Some((center, radius)) = self.area_circle() {
+                    rings.push(RangeRing {
+                        x: center.x,
+                        y: center.y,
+                        radius,
+                        kind: RingKind::Build,
+                    });
+                }
+                self.range_ring_gpu.update(
+                    &self.gpu.device,
+                    encoder,
+                    &mut self.staging_belt,
+                    &rings,
+                );
+            }
+
+            let see_all_order = self
+                .input_state
+                .key_pressed
+                .contains(&winit::event::VirtualKeyCode::LShift);
+
+            //Waypoints, units keep one move target and one command
+            self.vertex_attr_buffer_f32.clear();
+            for (kbot, client_kbot) in self.game_state.kbots.iter() {
+                if see_all_order || self.game_state.selected.contains(&kbot.id) {
+                    let mut waypoints = Vec::new();
+                    if let Some(target) = kbot.move_target {
+                        waypoints.push((target, gpu_obj::waypoint::WaypointKind::Move));
+                    }
+                    if let mobile::Command::Unload(point) = kbot.current_command {
+                        if kbot.move_target != Some(point) {
+                            waypoints.push((point, gpu_obj::waypoint::WaypointKind::Unload));
+                        }
+                    }
+                    //The whole loop, starting with the waypoint the unit walks to
+                    if let mobile::Command::Patrol {
+                        waypoints: patrol,
+                        next,
+                    } = &kbot.current_command
+                    {
+                        waypoints.clear();
+                        for i in 0..=patrol.len() {
+                            let point = patrol[(next + i) % patrol.len()];
+                            waypoints.push((point, gpu_obj::waypoint::WaypointKind::Patrol));
+                        }
+                    }
+                    //The current leg, then the way back
+                    if let mobile::Command::Ferry {
+                        pickup,
+                        dropoff,
+                        unloading,
+                    } = kbot.current_command
+                    {
+                        let (to, from) = if unloading {
+                            (dropoff, pickup)
+                        } else {
+                            (pickup, dropoff)
+                        };
+                        waypoints.clear();
+                        for &point in [to, from, to].iter() {
+                            waypoints.push((point, gpu_obj::waypoint::WaypointKind::Ferry));
+                        }
+                    }
+                    gpu_obj::waypoint::WaypointGpu::push_path(
+                        &mut self.vertex_attr_buffer_f32,
+                        &self.heightmap_gpu.phy,
+                        &client_kbot.position,
+                        &waypoints,
+                    );
+                }
+            }
+            self.waypoint_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
 
             //Line
             self.vertex_attr_buffer_f32.clear();
             {
                 let mut count = 0;
-                let see_all_order = self
-                    .input_state
-                    .key_pressed
-                    .contains(&winit::event::VirtualKeyCode::LShift);
                 {
                     for (kbot, client_kbot) in self.game_state.kbots.iter() {
                         if see_all_order || self.game_state.selected.contains(&kbot.id) {
@@ -481,23 +1007,12 @@ impl App {
                                     buffer.push(min.y / min.w);
                                     buffer.push(max.x / max.w);
                                     buffer.push(max.y / max.w);
-                                    //0.0 is move line
                                     //1.0 is build line
                                     buffer.push(type_);
                                     buffer.push(0.0);
                                 }
                             }
 
-                            if let Some(target) = kbot.move_target {
-                                add_line(
-                                    view_proj,
-                                    &mut self.vertex_attr_buffer_f32,
-                                    &client_kbot.position,
-                                    &target,
-                                    0.0,
-                                    &mut count,
-                                );
-                            }
                             match kbot.current_command {
                                 mobile::Command::Build(id_builded) => {
                                     for target_kbot in
@@ -513,7 +1028,9 @@ impl App {
                                         );
                                     }
                                 }
-                                mobile::Command::Repair(id_builded) => {
+                                mobile::Command::Repair(id_builded)
+                                | mobile::Command::Reclaim(id_builded)
+                                | mobile::Command::Guard(id_builded) => {
                                     for target_kbot in
                                         self.game_state.frame_zero.kbots.get(&id_builded)
                                     {
@@ -536,8 +1053,12 @@ impl App {
                     }
                 }
             }
-            self.line_gpu
-                .update_instance(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+            self.line_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
 
             //Explosions
             self.vertex_attr_buffer_f32.clear();
@@ -561,8 +1082,12 @@ impl App {
                     self.vertex_attr_buffer_f32.push(explosion.size);
                 }
             }
-            self.explosion_gpu
-                .update_instance(&self.vertex_attr_buffer_f32[..], &self.gpu.device);
+            self.explosion_gpu.update_instance(
+                &self.vertex_attr_buffer_f32[..],
+                &self.gpu.device,
+                encoder,
+                &mut self.staging_belt,
+            );
         });
         self.profiler
             .mix("upload_to_gpu", upload_to_gpu_duration, 20);
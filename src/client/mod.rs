@@ -4,60 +4,106 @@ use na::{Isometry3, Matrix4, Point3, Vector2, Vector3, Vector4};
 
 use gpu_obj::imgui_wgpu::Renderer;
 
+use bytemuck::Zeroable;
 use gpu_obj::arrow_gpu::ArrowGpu;
 use gpu_obj::blit_texture::BlitTextureGpu;
+use gpu_obj::environment::Environment;
 use gpu_obj::gpu;
 use gpu_obj::heightmap_gpu::HeightmapGpu;
 use gpu_obj::model_gpu::ModelGpu;
+use gpu_obj::pipeline_cache::PipelineCache;
+use gpu_obj::shadow_map::ShadowMap;
+use gpu_obj::texture_array::TextureArray;
 use gpu_obj::trait_gpu::TraitGpu;
+use gpu_obj::uniform_buffer::UniformBuffer;
 use gpu_obj::water::WaterGpu;
 use imgui::*;
 use imgui_winit_support;
 use imgui_winit_support::WinitPlatform;
+mod alert;
+mod alliance;
+mod area_order;
+mod autosave;
+mod benchmark;
 mod camera;
+mod campaign;
+mod caster;
+mod cursor;
+mod event_bus;
+mod factory;
+mod frame_graph;
 mod game_state;
+mod golden;
+mod heatmap;
 mod unit_part_gpu;
 use unit_part_gpu::UnitPartGpu;
 
 mod unit_editor;
 
 mod heightmap_editor;
+mod hud;
 mod input_state;
+mod inspector;
+mod launch;
 mod misc;
+mod ping;
+mod placement;
 mod play;
 mod render;
+mod settings;
+mod stress;
+mod time_travel;
+mod trail;
+mod trigger;
 mod uitool;
 
 use crate::heightmap_phy;
+use crate::platform::Instant;
 use log::info;
 use spin_sleep::LoopHelper;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
 use utils::time;
 use wgpu::{BufferMapAsyncResult, Extent3d, SwapChain, TextureFormat};
 use winit::event::WindowEvent;
 
-pub struct StartClient {
-    pub bind: String,
-}
-
-pub struct StartServer {
-    pub bind: String,
-}
-
-pub enum FromClient {
-    PlayerInput(frame::FrameEventFromPlayer),
-    StartServer(StartServer),
-    StartClient(StartClient),
-    DisconnectServer,
-    DisconnectClient,
-}
+pub use crate::{FromClient, StartClient, StartServer};
 
 struct ImguiWrap {
     imgui: imgui::Context,
     platform: WinitPlatform,
     renderer: Renderer,
+    ///Style before the ui scale, rescaled from it when the scale changes
+    base_style: imgui::Style,
+    ///Applied to the style, App::ui_scale
+    style_scale: f32,
+}
+
+impl ImguiWrap {
+    ///Default font rasterized at the hidpi factor, sharp without relying on font_global_scale
+    fn add_font(imgui: &mut imgui::Context, hidpi_factor: f64) {
+        let font_size = (13.0 * hidpi_factor) as f32;
+        imgui.fonts().clear();
+        imgui.fonts().add_font(&[FontSource::DefaultFontData {
+            config: Some(imgui::FontConfig {
+                oversample_h: 1,
+                pixel_snap_h: true,
+                size_pixels: font_size,
+                ..Default::default()
+            }),
+        }]);
+    }
+
+    ///Style sizes follow the ui scale, the font only the user part of it
+    fn set_scale(&mut self, ui_scale: f32, user_ui_scale: f32) {
+        if self.style_scale != ui_scale {
+            let mut style = self.base_style;
+            style.scale_all_sizes(ui_scale);
+            *self.imgui.style_mut() = style;
+            self.style_scale = ui_scale;
+        }
+        self.imgui.io_mut().font_global_scale = user_ui_scale;
+    }
 }
 
 #[derive(Clone)]
@@ -68,29 +114,54 @@ enum RenderEvent {
 #[derive(PartialEq, Clone, Copy)]
 pub enum MainMode {
     Home,
+    ///Mission list, a mission is then played in Play
+    Campaign,
+    ///Waiting for the first frame with our player, then Play
+    Loading,
     Play,
+    ///Game ended, the last frame is still shown under the results
+    PostGame,
     UnitEditor,
     MapEditor,
     MultiplayerLobby,
 }
 
+impl MainMode {
+    ///Scene is updated and rendered, menus only draw imgui
+    pub fn has_world(&self) -> bool {
+        match self {
+            MainMode::Play | MainMode::PostGame | MainMode::MapEditor | MainMode::UnitEditor => {
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn has_camera(&self) -> bool {
+        *self == MainMode::Play || *self == MainMode::MapEditor
+    }
+
+    ///Mouse presses and drags go to the world, not only to imgui
+    pub fn takes_world_input(&self) -> bool {
+        *self == MainMode::Play || *self == MainMode::MapEditor || *self == MainMode::UnitEditor
+    }
+}
+
 #[derive(PartialEq, Clone, Copy)]
 pub enum NetMode {
     Offline,
     Server,
     Client,
+    ///Client without a player, watching the game
+    Spectator,
 }
 
 pub struct App {
     //Wgpu
     gpu: gpu::WgpuState,
 
-    first_color_att_view: wgpu::TextureView,
-    secon_color_att_view: wgpu::TextureView,
-    normal_att_view: wgpu::TextureView,
-    forward_depth: wgpu::TextureView,
-    position_att: wgpu::Texture,
-    position_att_view: wgpu::TextureView,
+    frame_graph: frame_graph::FrameGraph,
+    draw_list: gpu_obj::draw_list::DrawList<render::Draw>,
 
     heightmap_gpu: HeightmapGpu,
     water_gpu: WaterGpu,
@@ -98,28 +169,72 @@ pub struct App {
 
     arrow_gpu: ArrowGpu,
     kinematic_projectile_gpu: ModelGpu,
+    model_batch: gpu_obj::model_batch::ModelBatch,
+    mip_generator: gpu_obj::mipmap::MipGenerator,
+    shadow_map: ShadowMap,
+    environment: Environment,
+    hi_z: gpu_obj::hi_z::HiZ,
+    oit_composite: gpu_obj::oit::OitComposite,
+    ssr: gpu_obj::ssr::Ssr,
+    pipeline_cache: PipelineCache,
     vertex_attr_buffer_f32: Vec<f32>,
+    staging_belt: gpu_obj::staging_belt::StagingBelt,
 
     bind_group: wgpu::BindGroup,
     bind_group_layout: wgpu::BindGroupLayout,
 
-    ub_camera_mat: wgpu::Buffer,
+    ub_camera: UniformBuffer<camera::CameraUniform>,
+    ///None before the first frame
+    last_view_proj: Option<[[f32; 4]; 4]>,
 
     postfx: gpu_obj::post_fx::PostFx,
     postfxaa: gpu_obj::post_fxaa::PostFxaa,
+    ///Texture postfxaa and taa read, follows FrameGraph::aa_source
+    aa_source: gpu_obj::render_graph::TextureId,
+    dof: gpu_obj::dof::Dof,
+    taa: gpu_obj::taa::Taa,
+    outline: gpu_obj::toon::Outline,
+    economy_gpu: gpu_obj::economy::EconomyGpu,
+    range_ring_gpu: gpu_obj::range_ring::RangeRingGpu,
+    heatmap_gpu: gpu_obj::heatmap::HeatmapGpu,
+    ///Copies the resolved frame to the history of the taa
+    taa_copy: gpu_obj::texture_view_bicopy::TextureViewBiCopy,
     post_bicopy: gpu_obj::texture_view_bicopy::TextureViewBiCopy,
+    upscale: gpu_obj::upscale::UpscaleGpu,
+    color_grading: gpu_obj::color_grading::ColorGrading,
     health_bar: gpu_obj::health_bar::HealthBarGpu,
     line_gpu: gpu_obj::line::LineGpu,
     cursor_icon: BlitTextureGpu,
+    hud: hud::Hud,
+    hud_gpu: gpu_obj::hud::HudGpu,
     unit_icon: gpu_obj::unit_icon::UnitIconGpu,
+    impostor_gpu: gpu_obj::impostor::ImpostorGpu,
     explosion_gpu: gpu_obj::explosion::ExplosionGpu,
+    weather_gpu: gpu_obj::weather::WeatherGpu,
+    nanolathe_gpu: gpu_obj::nanolathe::NanolatheGpu,
+    shield_gpu: gpu_obj::shield::ShieldGpu,
+    trail_gpu: gpu_obj::trail::TrailGpu,
+    beam_gpu: gpu_obj::beam::BeamGpu,
+    ground_shadow_gpu: gpu_obj::ground_shadow::GroundShadowGpu,
+    waypoint_gpu: gpu_obj::waypoint::WaypointGpu,
+    point_light_gpu: gpu_obj::point_light::PointLightGpu,
 
     game_state: game_state::State,
     input_state: input_state::InputState,
     imgui_wrap: ImguiWrap,
 
     main_menu: MainMode,
+    ///Entered Loading, the menu is shown again when no frame with our player came in time
+    loading_since: Instant,
     net_mode: NetMode,
+    ///Empty means no relay
+    relay_addr: imgui::ImString,
+    relay_room: imgui::ImString,
+    net_sim: crate::net_sim::NetSim,
+    rollback: bool,
+    log_lines: crate::logging::LogLines,
+    ///Path of the report of a previous crash, shown until dismissed
+    crash_report: Option<String>,
 
     unit_editor: unit_editor::UnitEditor,
 
@@ -138,6 +253,24 @@ pub struct App {
     loop_helper: LoopHelper,
     profiler: frame::ProfilerMap,
     global_info: Option<manager::GlobalInfo>,
+    ///Tutorial or scripted match being played
+    mission: Option<trigger::Mission>,
+    ///Started with the play command, played instead of the example game
+    skirmish: Option<launch::Skirmish>,
+    replay: Option<caster::ReplayUi>,
+    ///On the first frame, unless a window size was given on the command line
+    maximize: bool,
+    campaign: campaign::Campaign,
+    autosave: autosave::Autosave,
+    settings: settings::SettingsUi,
+    ///Started with the bench command, None once the report is written
+    benchmark: Option<benchmark::Benchmark>,
+    stress: stress::StressUi,
+    time_travel: time_travel::TimeTravelUi,
+    inspector: inspector::InspectorUi,
+    heatmap: heatmap::HeatmapOverlay,
+    factory: factory::FactoryUi,
+    alliance: alliance::AllianceUi,
     threadpool: rayon::ThreadPool,
 
     frame_count: i32,
@@ -151,38 +284,82 @@ impl App {
 
         sender_to_event_loop: crossbeam_channel::Sender<EventLoopMsg>,
         sender_from_client_to_manager: crossbeam_channel::Sender<FromClient>,
+        log_lines: crate::logging::LogLines,
+        backends: wgpu::BackendBit,
     ) -> (Self) {
         log::trace!("App init");
 
-        let mut gpu = gpu::WgpuState::new(window);
+        let mut gpu = gpu::WgpuState::new(window, backends);
 
         let mut init_encoder = gpu
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
 
+        let [shadow_uniform, shadow_sampler, shadow_texture] = ShadowMap::layout_bindings(6);
+        let [env_sampler, env_irradiance, env_specular] = Environment::layout_bindings(9);
+        let main_bindings = [
+            UniformBuffer::<camera::CameraUniform>::layout_binding(
+                0,
+                wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+            ),
+            wgpu::BindGroupLayoutBinding {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+            },
+            wgpu::BindGroupLayoutBinding {
+                binding: 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler,
+            },
+            UniformBuffer::<gpu_obj::point_light::PointLightsUniform>::layout_binding(
+                3,
+                wgpu::ShaderStage::FRAGMENT,
+            ),
+            //Unit albedo and normal, layers of the ModelBatch
+            TextureArray::layer_binding(4),
+            TextureArray::layer_binding(5),
+            //Sun cascades
+            shadow_uniform,
+            shadow_sampler,
+            shadow_texture,
+            //Sky irradiance and prefiltered specular
+            env_sampler,
+            env_irradiance,
+            env_specular,
+        ];
+        //The shaders of the main pipelines use set 0
+        for (name, vs, fs) in &[
+            (
+                "cube_instanced",
+                "./src/shader/cube_instanced.vert",
+                "./src/shader/cube_instanced.frag",
+            ),
+            (
+                "heightmap",
+                "./src/shader/heightmap.vert",
+                "./src/shader/heightmap.frag",
+            ),
+        ] {
+            if let (Ok(vs), Ok(fs)) = (gpu_obj::shaders::load(vs), gpu_obj::shaders::load(fs)) {
+                gpu_obj::shader_reflect::validate(
+                    name,
+                    0,
+                    &main_bindings,
+                    &[
+                        (&vs, wgpu::ShaderStage::VERTEX),
+                        (&fs, wgpu::ShaderStage::FRAGMENT),
+                    ],
+                );
+            }
+        }
         let bind_group_layout =
             gpu.device
                 .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    bindings: &[
-                        wgpu::BindGroupLayoutBinding {
-                            binding: 0,
-                            visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
-                            ty: wgpu::BindingType::UniformBuffer { dynamic: false },
-                        },
-                        wgpu::BindGroupLayoutBinding {
-                            binding: 1,
-                            visibility: wgpu::ShaderStage::FRAGMENT,
-                            ty: wgpu::BindingType::SampledTexture {
-                                multisampled: false,
-                                dimension: wgpu::TextureViewDimension::D2,
-                            },
-                        },
-                        wgpu::BindGroupLayoutBinding {
-                            binding: 2,
-                            visibility: wgpu::ShaderStage::FRAGMENT,
-                            ty: wgpu::BindingType::Sampler,
-                        },
-                    ],
+                    bindings: &main_bindings,
                 });
 
         // Create the texture
@@ -193,14 +370,17 @@ impl App {
             height: size,
             depth: 1,
         };
+        let texture_levels = gpu_obj::mipmap::level_count(size, size);
         let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
             size: texture_extent,
             array_layer_count: 1,
-            mip_level_count: 1,
+            mip_level_count: texture_levels,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+            usage: wgpu::TextureUsage::SAMPLED
+                | wgpu::TextureUsage::COPY_DST
+                | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
         });
 
         let texture_view = texture.create_default_view();
@@ -227,76 +407,61 @@ impl App {
             },
             texture_extent,
         );
+        let mut mip_generator = gpu_obj::mipmap::MipGenerator::new(&gpu.device);
+        mip_generator.generate(
+            &gpu.device,
+            &mut init_encoder,
+            &texture,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            texture_levels,
+            0,
+        );
 
         // Create other resources
+        //Trilinear, wgpu 0.4 has no anisotropic filtering
         let sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::MirrorRepeat,
             address_mode_v: wgpu::AddressMode::MirrorRepeat,
             address_mode_w: wgpu::AddressMode::MirrorRepeat,
             mag_filter: wgpu::FilterMode::Nearest,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             lod_min_clamp: -100.0,
             lod_max_clamp: 100.0,
             compare_function: wgpu::CompareFunction::Always,
         });
 
-        let mx_total = camera::create_view_proj(
-            gpu.sc_desc.width as f32 / gpu.sc_desc.height as f32,
-            1.0,
-            &Point3::new(0.0, 0.0, 0.0),
-            &Vector3::new(0.0, 0.0, 0.0),
+        let ub_camera = UniformBuffer::new(&gpu.device, &camera::CameraUniform::zeroed());
+
+        let point_light_gpu = gpu_obj::point_light::PointLightGpu::new(&gpu.device);
+
+        let format: TextureFormat = gpu.sc_desc.format;
+
+        let mut pipeline_cache = PipelineCache::new();
+        let kinematic_projectile_gpu = ModelGpu::new(
+            &model::open_obj("./src/asset/3d/small_sphere.obj").unwrap(),
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            &mut pipeline_cache,
         );
-        let mx_ref: &[f32] = mx_total.as_slice();
-
-        let mx_view =
-            camera::create_view(&Point3::new(0.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, 0.0));
-        let mx_view_ref: &[f32] = mx_view.as_slice();
-
-        let mx_normal =
-            camera::create_normal(&Point3::new(0.0, 0.0, 0.0), &Vector3::new(0.0, 0.0, 0.0));
-        let mx_normal_ref: &[f32] = mx_normal.as_slice();
-
-        let mut filler = Vec::new();
-        filler.extend_from_slice(mx_ref);
-        filler.extend_from_slice(mx_view_ref);
-        //TODO reuse camera.rs code
-        filler.extend_from_slice(mx_ref);
-        filler.extend_from_slice(mx_normal_ref);
-        filler.extend_from_slice(&[
-            0.0_f32, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
-        ]);
-        // mat4 cor_proj_view;
-        // mat4 u_View;
-        // mat4 u_proj;
-        // mat4 u_Normal;
-        // vec2 mouse_pos;
-        // vec2 resolution;
-        // vec2 inv_resolution;
-        // vec2 start_drag;
-        // float radius
-        // float pen_strength
-        // vec2 mapSize;
-
-        let ub_camera_mat = gpu
-            .device
-            .create_buffer_mapped(
-                16 * 4 + 12,
-                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-            )
-            .fill_from_slice(&filler[..]);
+        let model_batch = gpu_obj::model_batch::ModelBatch::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            &mut pipeline_cache,
+        );
+
+        let shadow_map = ShadowMap::new(&gpu.device);
+        let environment = Environment::new(&gpu.device);
 
         // Create bind group
+        let [shadow_uniform, shadow_sampler, shadow_texture] = shadow_map.bindings(6);
+        let [env_sampler, env_irradiance, env_specular] = environment.bindings(9);
         let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &bind_group_layout,
             bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer {
-                        buffer: &ub_camera_mat,
-                        range: 0..(4 * 16 + 12) * 4,
-                    },
-                },
+                ub_camera.binding(0),
                 wgpu::Binding {
                     binding: 1,
                     resource: wgpu::BindingResource::TextureView(&texture_view),
@@ -305,6 +470,21 @@ impl App {
                     binding: 2,
                     resource: wgpu::BindingResource::Sampler(&sampler),
                 },
+                point_light_gpu.uniform.binding(3),
+                wgpu::Binding {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(model_batch.albedo_view()),
+                },
+                wgpu::Binding {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(model_batch.normal_view()),
+                },
+                shadow_uniform,
+                shadow_sampler,
+                shadow_texture,
+                env_sampler,
+                env_irradiance,
+                env_specular,
             ],
         });
 
@@ -320,19 +500,14 @@ impl App {
             );
             imgui.set_ini_filename(None);
 
-            let font_size = (13.0 * gpu.hidpi_factor) as f32;
+            //Set each frame from game_state.ui_scale
             imgui.io_mut().font_global_scale = (1.0) as f32;
 
+            //Set each frame from game_state.cursor_mode
             imgui.io_mut().mouse_draw_cursor = true;
 
-            imgui.fonts().add_font(&[FontSource::DefaultFontData {
-                config: Some(imgui::FontConfig {
-                    oversample_h: 1,
-                    pixel_snap_h: true,
-                    size_pixels: font_size,
-                    ..Default::default()
-                }),
-            }]);
+            ImguiWrap::add_font(&mut imgui, gpu.hidpi_factor);
+            let base_style = *imgui.style();
 
             // imgui <-> wgpu
             let renderer = Renderer::new(
@@ -347,11 +522,11 @@ impl App {
                 imgui,
                 platform,
                 renderer,
+                base_style,
+                style_scale: 1.0,
             }
         };
 
-        let format: TextureFormat = gpu.sc_desc.format;
-
         let heightmap_gpu = HeightmapGpu::new(
             &gpu.device,
             &mut init_encoder,
@@ -360,13 +535,6 @@ impl App {
             heightmap_phy::HeightmapPhy::new(2048, 2048),
         );
 
-        let kinematic_projectile_gpu = ModelGpu::new(
-            &model::open_obj("./src/asset/3d/small_sphere.obj").unwrap(),
-            &gpu.device,
-            format,
-            &bind_group_layout,
-        );
-
         let arrow_gpu = ArrowGpu::new(
             &model::open_obj("./src/asset/3d/arrow.obj").unwrap(),
             &gpu.device,
@@ -393,42 +561,34 @@ impl App {
             crate::utils::ImageRGBA8::open("./src/asset/2d/cursor_icons.png"),
         );
 
+        let hud_gpu = gpu_obj::hud::HudGpu::new(
+            &mut init_encoder,
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            crate::utils::ImageRGBA8::open("./src/asset/2d/cursor_icons.png"),
+        );
+
         let unit_icon =
             gpu_obj::unit_icon::UnitIconGpu::new(&gpu.device, format, &bind_group_layout);
+        let impostor_gpu =
+            gpu_obj::impostor::ImpostorGpu::new(&gpu.device, format, &bind_group_layout);
 
-        let depth_texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: gpu.sc_desc.width,
-                height: gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-        });
-
-        let forward_depth = depth_texture.create_default_view();
-
-        let position_att = gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: gpu.sc_desc.width,
-                height: gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
-                | wgpu::TextureUsage::SAMPLED
-                | wgpu::TextureUsage::COPY_SRC,
-        });
-
-        let position_att_view = position_att.create_default_view();
+        let frame_graph =
+            frame_graph::FrameGraph::new(&gpu.device, gpu.sc_desc.width, gpu.sc_desc.height);
+        let scene_size = frame_graph.scene_size(gpu.sc_desc.width, gpu.sc_desc.height);
+        let hi_z = gpu_obj::hi_z::HiZ::new(
+            &gpu.device,
+            frame_graph.view(frame_graph.depth),
+            scene_size.0,
+            scene_size.1,
+        );
+        let oit_composite = gpu_obj::oit::OitComposite::new(
+            &gpu.device,
+            format,
+            frame_graph.view(frame_graph.oit_accum),
+            frame_graph.view(frame_graph.oit_reveal),
+        );
 
         let game_state = game_state::State::new();
 
@@ -451,93 +611,131 @@ impl App {
             (rx, watcher)
         };
 
-        let first_color_att = gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: gpu.sc_desc.width,
-                height: gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
-                | wgpu::TextureUsage::SAMPLED
-                | wgpu::TextureUsage::COPY_SRC,
-        });
-
-        let first_color_att_view = first_color_att.create_default_view();
-
-        let secon_color_att = gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: gpu.sc_desc.width,
-                height: gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
-                | wgpu::TextureUsage::SAMPLED
-                | wgpu::TextureUsage::COPY_SRC,
-        });
-
-        let secon_color_att_view = secon_color_att.create_default_view();
-
-        let normal_att = gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: gpu.sc_desc.width,
-                height: gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rg16Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
-        });
-
-        let normal_att_view = normal_att.create_default_view();
-
         let explosion_gpu = gpu_obj::explosion::ExplosionGpu::new(
             &mut init_encoder,
             &gpu.device,
             format,
             &bind_group_layout,
-            &position_att_view,
-            &normal_att_view,
+            frame_graph.view(frame_graph.position),
+            frame_graph.view(frame_graph.normal),
         );
 
         let water_gpu = WaterGpu::new(
             &gpu.device,
             format,
             &bind_group_layout,
-            &secon_color_att_view,
-            &position_att_view,
+            frame_graph.view(frame_graph.reflection),
+        );
+        let ssr = gpu_obj::ssr::Ssr::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            frame_graph.view(frame_graph.position),
+            frame_graph.view(frame_graph.normal),
+            frame_graph.view(frame_graph.secon_color),
+            frame_graph.view(frame_graph.reflection),
+        );
+
+        let weather_gpu =
+            gpu_obj::weather::WeatherGpu::new(&gpu.device, format, &bind_group_layout);
+
+        let nanolathe_gpu =
+            gpu_obj::nanolathe::NanolatheGpu::new(&gpu.device, format, &bind_group_layout);
+
+        let trail_gpu = gpu_obj::trail::TrailGpu::new(&gpu.device, format, &bind_group_layout);
+
+        let beam_gpu = gpu_obj::beam::BeamGpu::new(&gpu.device, format, &bind_group_layout);
+
+        let ground_shadow_gpu =
+            gpu_obj::ground_shadow::GroundShadowGpu::new(&gpu.device, format, &bind_group_layout);
+        let waypoint_gpu =
+            gpu_obj::waypoint::WaypointGpu::new(&gpu.device, format, &bind_group_layout);
+
+        let shield_gpu = gpu_obj::shield::ShieldGpu::new(
+            &model::open_obj("./src/asset/3d/small_sphere.obj").unwrap(),
+            &gpu.device,
+            format,
+            &bind_group_layout,
         );
 
         let postfx = gpu_obj::post_fx::PostFx::new(
             &gpu.device,
             &bind_group_layout,
             format,
-            &position_att_view,
+            frame_graph.view(frame_graph.position),
         );
         let postfxaa = gpu_obj::post_fxaa::PostFxaa::new(
             &gpu.device,
             &bind_group_layout,
             format,
-            &first_color_att_view,
+            frame_graph.view(frame_graph.aa_source()),
+        );
+        let aa_source = frame_graph.aa_source();
+        let dof = gpu_obj::dof::Dof::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            frame_graph.view(frame_graph.first_color),
+            frame_graph.view(frame_graph.position),
+        );
+        let taa = gpu_obj::taa::Taa::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            frame_graph.view(aa_source),
+            frame_graph.view(frame_graph.velocity),
+            frame_graph.view(frame_graph.taa_history),
+        );
+        let outline = gpu_obj::toon::Outline::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            frame_graph.view(frame_graph.position),
+            frame_graph.view(frame_graph.normal),
+        );
+        let economy_gpu = gpu_obj::economy::EconomyGpu::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            frame_graph.view(frame_graph.position),
+        );
+        let range_ring_gpu = gpu_obj::range_ring::RangeRingGpu::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            frame_graph.view(frame_graph.position),
+        );
+        let heatmap_gpu = gpu_obj::heatmap::HeatmapGpu::new(
+            &gpu.device,
+            format,
+            &bind_group_layout,
+            frame_graph.view(frame_graph.position),
+        );
+        let taa_copy = gpu_obj::texture_view_bicopy::TextureViewBiCopy::new(
+            &gpu.device,
+            &bind_group_layout,
+            format,
+            frame_graph.view(frame_graph.secon_color),
         );
 
         let post_bicopy = gpu_obj::texture_view_bicopy::TextureViewBiCopy::new(
             &gpu.device,
             &bind_group_layout,
             format,
-            &secon_color_att_view,
+            frame_graph.view(frame_graph.ui_color),
+        );
+
+        let upscale = gpu_obj::upscale::UpscaleGpu::new(
+            &gpu.device,
+            format,
+            frame_graph.view(frame_graph.graded),
+        );
+
+        let color_grading = gpu_obj::color_grading::ColorGrading::new(
+            &gpu.device,
+            &mut init_encoder,
+            format,
+            frame_graph.view(frame_graph.secon_color),
         );
 
         let mut unit_editor = unit_editor::UnitEditor::new();
@@ -549,41 +747,75 @@ impl App {
 
         gpu.queue.submit(&[init_encoder.finish()]);
         // Done
-        let this = App {
+        let mut this = App {
             gpu,
 
             bind_group,
             bind_group_layout,
-            ub_camera_mat,
+            ub_camera,
+            last_view_proj: None,
 
             unit_part_gpu,
             kinematic_projectile_gpu,
+            model_batch,
+            mip_generator,
+            shadow_map,
+            environment,
+            hi_z,
+            oit_composite,
+            ssr,
+            pipeline_cache,
             arrow_gpu,
             heightmap_gpu,
             water_gpu,
             vertex_attr_buffer_f32: Vec::new(),
+            staging_belt: gpu_obj::staging_belt::StagingBelt::new(),
 
-            first_color_att_view,
-            secon_color_att_view,
-            normal_att_view,
-            forward_depth,
-            position_att_view,
-            position_att,
+            frame_graph,
+            draw_list: gpu_obj::draw_list::DrawList::new(),
 
             postfx,
             postfxaa,
+            aa_source,
+            dof,
+            taa,
+            outline,
+            economy_gpu,
+            range_ring_gpu,
+            heatmap_gpu,
+            taa_copy,
             post_bicopy,
+            upscale,
+            color_grading,
             health_bar,
             line_gpu,
             cursor_icon,
+            hud: hud::Hud::new(),
+            hud_gpu,
             unit_icon,
+            impostor_gpu,
             explosion_gpu,
+            weather_gpu,
+            nanolathe_gpu,
+            shield_gpu,
+            trail_gpu,
+            beam_gpu,
+            ground_shadow_gpu,
+            waypoint_gpu,
+            point_light_gpu,
 
             game_state,
             input_state: input_state::InputState::new(),
             imgui_wrap,
             main_menu: MainMode::Home,
+            loading_since: Instant::now(),
             net_mode: NetMode::Offline,
+            relay_addr: imgui::ImString::with_capacity(64),
+            relay_room: imgui::ImString::with_capacity(64),
+            net_sim: crate::net_sim::NetSim::none(),
+            rollback: false,
+            log_lines,
+            crash_report: crate::crash::pending_report(),
             unit_editor,
 
             sender_to_client,
@@ -598,124 +830,131 @@ impl App {
             loop_helper: LoopHelper::builder().build_with_target_rate(144.0),
             profiler: frame::ProfilerMap::new(),
             global_info: None,
+            mission: None,
+            skirmish: None,
+            replay: None,
+            maximize: true,
+            campaign: campaign::Campaign::open(),
+            autosave: autosave::Autosave::new(),
+            settings: settings::SettingsUi::new(),
+            benchmark: None,
+            stress: stress::StressUi::new(),
+            time_travel: time_travel::TimeTravelUi::new(),
+            inspector: inspector::InspectorUi::new(),
+            heatmap: heatmap::HeatmapOverlay::new(),
+            factory: factory::FactoryUi::new(),
+            alliance: alliance::AllianceUi::new(),
             threadpool: rayon::ThreadPoolBuilder::new()
                 // .num_threads(8)
                 .build()
                 .unwrap(),
             frame_count: 0,
         };
+        let settings = this.settings.applied.clone();
+        this.apply_settings(&settings);
 
         (this)
     }
 
-    fn resize(&mut self) -> Option<wgpu::CommandBuffer> {
-        log::trace!("resize");
-
-        let normal_att = self.gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: self.gpu.sc_desc.width,
-                height: self.gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rg16Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
-        });
-
-        self.normal_att_view = normal_att.create_default_view();
+    fn resize_swap_chain(&mut self, physical: winit::dpi::PhysicalSize) {
+        self.gpu.sc_desc.width = physical.width.round() as u32;
+        self.gpu.sc_desc.height = physical.height.round() as u32;
+        self.gpu.swap_chain = self
+            .gpu
+            .device
+            .create_swap_chain(&self.gpu.surface, &self.gpu.sc_desc);
+        let command_buf = self.resize();
+        if let Some(command_buf) = command_buf {
+            self.gpu.queue.submit(&[command_buf]);
+        }
+    }
 
-        let first_color_att = self.gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: self.gpu.sc_desc.width,
-                height: self.gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
-                | wgpu::TextureUsage::SAMPLED
-                | wgpu::TextureUsage::COPY_SRC,
-        });
+    ///Window pixels per reference pixel of the ui: hidpi factor of the screen times the user
+    ///setting
+    pub fn ui_scale(&self) -> f32 {
+        self.gpu.hidpi_factor as f32 * self.game_state.ui_scale
+    }
 
-        self.first_color_att_view = first_color_att.create_default_view();
+    ///Size of the 3d scene targets, the window one times the render scale
+    pub fn scene_size(&self) -> (u32, u32) {
+        self.frame_graph
+            .scene_size(self.gpu.sc_desc.width, self.gpu.sc_desc.height)
+    }
 
-        let secon_color_att = self.gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: self.gpu.sc_desc.width,
-                height: self.gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
-                | wgpu::TextureUsage::SAMPLED
-                | wgpu::TextureUsage::COPY_SRC,
-        });
+    fn resize(&mut self) -> Option<wgpu::CommandBuffer> {
+        log::trace!("resize");
 
-        self.secon_color_att_view = secon_color_att.create_default_view();
+        self.frame_graph.compile(
+            &self.gpu.device,
+            self.gpu.sc_desc.width,
+            self.gpu.sc_desc.height,
+        );
+        let scene_size = self.scene_size();
+        let frame_graph = &self.frame_graph;
+        self.hi_z.resize(
+            &self.gpu.device,
+            frame_graph.view(frame_graph.depth),
+            scene_size.0,
+            scene_size.1,
+        );
+        self.oit_composite.update_views(
+            &self.gpu.device,
+            frame_graph.view(frame_graph.oit_accum),
+            frame_graph.view(frame_graph.oit_reveal),
+        );
 
         self.postfxaa
-            .update_last_pass_view(&self.gpu.device, &self.first_color_att_view);
+            .update_last_pass_view(&self.gpu.device, frame_graph.view(frame_graph.aa_source()));
+        self.aa_source = frame_graph.aa_source();
+        self.dof.update_views(
+            &self.gpu.device,
+            frame_graph.view(frame_graph.first_color),
+            frame_graph.view(frame_graph.position),
+        );
+        self.taa.update_views(
+            &self.gpu.device,
+            frame_graph.view(self.aa_source),
+            frame_graph.view(frame_graph.velocity),
+            frame_graph.view(frame_graph.taa_history),
+        );
+        self.outline.update_views(
+            &self.gpu.device,
+            frame_graph.view(frame_graph.position),
+            frame_graph.view(frame_graph.normal),
+        );
+        self.economy_gpu
+            .update_views(&self.gpu.device, frame_graph.view(frame_graph.position));
+        self.range_ring_gpu
+            .update_views(&self.gpu.device, frame_graph.view(frame_graph.position));
+        self.heatmap_gpu
+            .update_views(&self.gpu.device, frame_graph.view(frame_graph.position));
+        self.taa_copy
+            .update_last_pass_view(&self.gpu.device, frame_graph.view(frame_graph.secon_color));
 
         self.post_bicopy
-            .update_last_pass_view(&self.gpu.device, &self.secon_color_att_view);
-
-        let depth_texture = self.gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: self.gpu.sc_desc.width,
-                height: self.gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-        });
-        self.forward_depth = depth_texture.create_default_view();
-
-        let position_att = self.gpu.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: self.gpu.sc_desc.width,
-                height: self.gpu.sc_desc.height,
-                depth: 1,
-            },
-            array_layer_count: 1,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT
-                | wgpu::TextureUsage::SAMPLED
-                | wgpu::TextureUsage::COPY_SRC,
-        });
-
-        self.position_att_view = position_att.create_default_view();
-
-        self.water_gpu.update_bind_group(
+            .update_last_pass_view(&self.gpu.device, frame_graph.view(frame_graph.ui_color));
+        self.upscale
+            .update_views(&self.gpu.device, frame_graph.view(frame_graph.graded));
+        self.color_grading
+            .update_views(&self.gpu.device, frame_graph.view(frame_graph.secon_color));
+
+        self.water_gpu
+            .update_bind_group(&self.gpu.device, frame_graph.view(frame_graph.reflection));
+        self.ssr.update_views(
             &self.gpu.device,
-            &self.secon_color_att_view,
-            &self.position_att_view,
+            frame_graph.view(frame_graph.position),
+            frame_graph.view(frame_graph.normal),
+            frame_graph.view(frame_graph.secon_color),
+            frame_graph.view(frame_graph.reflection),
         );
 
         self.postfx
-            .update_pos_att_view(&self.gpu.device, &self.position_att_view);
-        self.position_att = position_att;
+            .update_pos_att_view(&self.gpu.device, frame_graph.view(frame_graph.position));
 
         self.explosion_gpu.update_bind_group(
             &self.gpu.device,
-            &self.position_att_view,
-            &self.normal_att_view,
+            frame_graph.view(frame_graph.position),
+            frame_graph.view(frame_graph.normal),
         );
 
         None
@@ -739,19 +978,28 @@ impl App {
             } => {
                 let physical = size.to_physical(self.gpu.hidpi_factor);
                 info!("Resizing to logical {:?} physical {:?}", size, physical);
-                self.gpu.sc_desc.width = physical.width.round() as u32;
-                self.gpu.sc_desc.height = physical.height.round() as u32;
-                self.gpu.swap_chain = self
-                    .gpu
-                    .device
-                    .create_swap_chain(&self.gpu.surface, &self.gpu.sc_desc);
-                let command_buf = self.resize();
-                if let Some(command_buf) = command_buf {
-                    self.gpu.queue.submit(&[command_buf]);
-                }
+                self.resize_swap_chain(physical);
+            }
+            //Moved to a screen of another density: the scene stays at native resolution, the
+            //font is rasterized again for the new density
+            event::Event::WindowEvent {
+                event: WindowEvent::HiDpiFactorChanged(hidpi_factor),
+                ..
+            } => {
+                info!("Hidpi factor changed to {}", hidpi_factor);
+                self.gpu.hidpi_factor = *hidpi_factor;
+                ImguiWrap::add_font(&mut self.imgui_wrap.imgui, *hidpi_factor);
+                self.imgui_wrap.renderer.reload_font_texture(
+                    &mut self.imgui_wrap.imgui,
+                    &mut self.gpu.device,
+                    &mut self.gpu.queue,
+                );
+                let physical = self.gpu.window.inner_size().to_physical(*hidpi_factor);
+                self.resize_swap_chain(physical);
             }
             event::Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => {
+                    self.autosave.end_session();
                     self.sender_to_event_loop.send(EventLoopMsg::Stop).unwrap();
                 }
                 WindowEvent::KeyboardInput {
@@ -811,17 +1059,30 @@ impl App {
                 }
 
                 WindowEvent::MouseInput { state, button, .. } => {
-                    if !self.imgui_wrap.imgui.io().want_capture_mouse {
+                    //Presses over the hud are its own, releases still end the drags of the world
+                    let (x, y) = self.input_state.cursor_pos;
+                    let hud_press = *state == event::ElementState::Pressed
+                        && self.hud.contains(x as f32, y as f32);
+                    if hud_press
+                        && *button == event::MouseButton::Left
+                        && !self.imgui_wrap.imgui.io().want_capture_mouse
+                    {
+                        self.hud.press(x as f32, y as f32);
+                    }
+                    if !self.imgui_wrap.imgui.io().want_capture_mouse && !hud_press {
                         if let &winit::event::ElementState::Pressed = state {
-                            self.input_state.mouse_pressed.insert(*button);
-                            self.input_state.mouse_trigger.insert(*button);
-
-                            if let event::MouseButton::Left = button {
-                                self.input_state.drag = input_state::Drag::Start {
-                                    x0: self.input_state.cursor_pos.0 as u32,
-                                    y0: self.input_state.cursor_pos.1 as u32,
-                                }
-                            };
+                            //Menus have no world to press on
+                            if self.main_menu.takes_world_input() {
+                                self.input_state.mouse_pressed.insert(*button);
+                                self.input_state.mouse_trigger.insert(*button);
+
+                                if let event::MouseButton::Left = button {
+                                    self.input_state.drag = input_state::Drag::Start {
+                                        x0: self.input_state.cursor_pos.0 as u32,
+                                        y0: self.input_state.cursor_pos.1 as u32,
+                                    }
+                                };
+                            }
                         } else {
                             self.input_state.mouse_pressed.remove(button);
                             self.input_state.mouse_release.insert(*button);
@@ -903,6 +1164,112 @@ impl App {
                     );
                 }
 
+                if event.paths.iter().any(|p| {
+                    p.file_name()
+                        .iter()
+                        .any(|name| name.to_os_string() == "dof.frag")
+                }) {
+                    log::info!("Reloading dof.frag");
+                    self.dof.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name()
+                        .iter()
+                        .any(|name| name.to_os_string() == "taa.frag")
+                }) {
+                    log::info!("Reloading taa.frag");
+                    self.taa.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name()
+                        .iter()
+                        .any(|name| name.to_os_string() == "upscale.frag")
+                }) {
+                    log::info!("Reloading upscale.frag");
+                    self.upscale.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name()
+                        .iter()
+                        .any(|name| name.to_os_string() == "outline.frag")
+                }) {
+                    log::info!("Reloading outline.frag");
+                    self.outline.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "metal_spot.vert"
+                            || name.to_os_string() == "metal_spot.frag"
+                            || name.to_os_string() == "economy_overlay.frag"
+                    })
+                }) {
+                    log::info!("Reloading metal_spot.vert/metal_spot.frag/economy_overlay.frag");
+                    self.economy_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name()
+                        .iter()
+                        .any(|name| name.to_os_string() == "range_ring.frag")
+                }) {
+                    log::info!("Reloading range_ring.frag");
+                    self.range_ring_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name()
+                        .iter()
+                        .any(|name| name.to_os_string() == "heatmap.frag")
+                }) {
+                    log::info!("Reloading heatmap.frag");
+                    self.heatmap_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "hud.frag" || name.to_os_string() == "hud.vert"
+                    })
+                }) {
+                    log::info!("Reloading hud.vert/hud.frag");
+                    self.hud_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
                 if event.paths.iter().any(|p| {
                     p.file_name().iter().any(|name| {
                         name.to_os_string() == "heightmap.frag"
@@ -924,17 +1291,19 @@ impl App {
                     })
                 }) {
                     log::info!("Reloading cube_instanced.vert/cube_instanced.frag");
-                    for (model_gpu_state) in self.unit_part_gpu.states.iter_mut() {
-                        match model_gpu_state {
-                            unit_part_gpu::ModelGpuState::Ready(model_gpu) => model_gpu
-                                .reload_shader(
-                                    &self.gpu.device,
-                                    &self.bind_group_layout,
-                                    self.gpu.sc_desc.format,
-                                ),
-                            _ => {}
-                        }
-                    }
+                    self.pipeline_cache.clear();
+                    self.kinematic_projectile_gpu.reload_shared_pipeline(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                        &mut self.pipeline_cache,
+                    );
+                    self.model_batch.reload_shared_pipeline(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                        &mut self.pipeline_cache,
+                    );
                 }
 
                 if event.paths.iter().any(|p| {
@@ -978,6 +1347,20 @@ impl App {
                     );
                 }
 
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "impostor.frag"
+                            || name.to_os_string() == "impostor.vert"
+                    })
+                }) {
+                    log::info!("Reloading impostor.vert/impostor.frag");
+                    self.impostor_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
                 if event.paths.iter().any(|p| {
                     p.file_name().iter().any(|name| {
                         name.to_os_string() == "explosion.frag"
@@ -992,6 +1375,101 @@ impl App {
                     );
                 }
 
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "weather.frag"
+                            || name.to_os_string() == "weather.vert"
+                    })
+                }) {
+                    log::info!("Reloading weather.vert/weather.frag");
+                    self.weather_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "nanolathe.frag"
+                            || name.to_os_string() == "nanolathe.vert"
+                    })
+                }) {
+                    log::info!("Reloading nanolathe.vert/nanolathe.frag");
+                    self.nanolathe_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "shield.frag" || name.to_os_string() == "shield.vert"
+                    })
+                }) {
+                    log::info!("Reloading shield.vert/shield.frag");
+                    self.shield_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "trail.frag" || name.to_os_string() == "trail.vert"
+                    })
+                }) {
+                    log::info!("Reloading trail.vert/trail.frag");
+                    self.trail_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "beam.frag" || name.to_os_string() == "beam.vert"
+                    })
+                }) {
+                    log::info!("Reloading beam.vert/beam.frag");
+                    self.beam_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "ground_shadow.frag"
+                            || name.to_os_string() == "ground_shadow.vert"
+                    })
+                }) {
+                    log::info!("Reloading ground_shadow.vert/ground_shadow.frag");
+                    self.ground_shadow_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "waypoint.frag"
+                            || name.to_os_string() == "waypoint.vert"
+                    })
+                }) {
+                    log::info!("Reloading waypoint.vert/waypoint.frag");
+                    self.waypoint_gpu.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
                 if event.paths.iter().any(|p| {
                     p.file_name().iter().any(|name| {
                         name.to_os_string() == "line.frag" || name.to_os_string() == "line.vert"
@@ -1018,6 +1496,33 @@ impl App {
                     );
                 }
 
+                if event.paths.iter().any(|p| {
+                    p.file_name().iter().any(|name| {
+                        name.to_os_string() == "ssr.frag"
+                            || name.to_os_string() == "ssr_composite.frag"
+                    })
+                }) {
+                    log::info!("Reloading ssr.frag/ssr_composite.frag");
+                    self.ssr.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
+                if event.paths.iter().any(|p| {
+                    p.file_name()
+                        .iter()
+                        .any(|name| name.to_os_string() == "color_grading.frag")
+                }) {
+                    log::info!("Reloading color_grading.frag");
+                    self.color_grading.reload_shader(
+                        &self.gpu.device,
+                        &self.bind_group_layout,
+                        self.gpu.sc_desc.format,
+                    );
+                }
+
                 if event.paths.iter().any(|p| {
                     p.file_name().iter().any(|name| {
                         name.to_os_string() == "blit_texture.frag"
@@ -1043,10 +1548,43 @@ impl App {
                         log::trace!("receive: MapReadAsyncMessage");
                         self.map_read_async_msg(vec, usage);
                     }
-                    ToClient::NewFrame(frame) => {
+                    ToClient::NewFrame(mut frame) => {
+                        //After a seek in a replay, the craters are already in
+                        if let Some(phy) = frame.heightmap_phy.take() {
+                            let (width, height) = (phy.width as u32, phy.height as u32);
+                            self.heightmap_gpu.phy = phy;
+                            self.heightmap_gpu.update_rect(0, 0, width, height);
+                            frame.craters.clear();
+                        }
+                        if self.time_travel.open && self.net_mode == NetMode::Offline {
+                            self.time_travel.record(&frame);
+                        }
                         self.game_state.handle_new_frame(frame);
+                        //Game joined after its start, init_play saw no player, or another
+                        //seat given by a successor of the host
+                        if self.net_mode == NetMode::Client {
+                            if let Some(player) = self.client_player() {
+                                self.game_state.my_player_id = Some(player);
+                            }
+                        }
+                        self.detect_alerts();
+                        for crater in self.game_state.frame_zero.craters.iter() {
+                            let (min_x, min_y, width, height) = self.heightmap_gpu.phy.crater(
+                                crater.position.x,
+                                crater.position.y,
+                                crater.radius,
+                                crater.depth,
+                            );
+                            self.heightmap_gpu.update_rect(min_x, min_y, width, height);
+                        }
                     }
                     ToClient::GlobalInfo(global_info) => self.global_info = Some(global_info),
+                    ToClient::Pings(pings) => self.receive_pings(pings),
+                    ToClient::HostMigrated => {
+                        if self.net_mode == NetMode::Client {
+                            self.net_mode = NetMode::Server;
+                        }
+                    }
                 }
             }
         }
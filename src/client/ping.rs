@@ -0,0 +1,130 @@
+use super::client::*;
+use super::game_state;
+use crate::frame::{FrameEventFromPlayer, Ping, PingKind};
+use crate::gpu_obj::range_ring::{RangeRing, RingKind};
+use crate::platform::Instant;
+use crate::*;
+use na::Point3;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+///Sec a ping stays on the map
+pub const LIFE_SEC: f32 = 5.0;
+///m, radius of the marker, its pulse grows to 4 times that each second
+const RADIUS: f32 = 2.0;
+
+pub struct ActivePing {
+    pub ping: Ping,
+    ///Not the game time, pings keep fading while the game is paused
+    pub received: Instant,
+}
+
+impl ActivePing {
+    pub fn age(&self) -> f32 {
+        self.received.elapsed().as_secs_f32()
+    }
+}
+
+pub fn ring_kind(kind: PingKind) -> RingKind {
+    match kind {
+        PingKind::Attack => RingKind::Weapon,
+        PingKind::Defend => RingKind::Shield,
+        PingKind::Assist => RingKind::Build,
+    }
+}
+
+///Same colors as the rings
+pub fn color(kind: PingKind) -> [f32; 4] {
+    match kind {
+        PingKind::Attack => [1.0, 0.25, 0.2, 1.0],
+        PingKind::Defend => [0.3, 0.6, 1.0, 1.0],
+        PingKind::Assist => [0.3, 1.0, 0.4, 1.0],
+    }
+}
+
+///Marker and its pulse for each ping
+pub fn rings(game_state: &game_state::State) -> Vec<RangeRing> {
+    let mut rings = Vec::new();
+    for active in game_state.pings.iter() {
+        let (x, y) = (active.ping.position.x, active.ping.position.y);
+        let kind = ring_kind(active.ping.kind);
+        rings.push(RangeRing {
+            x,
+            y,
+            radius: RADIUS,
+            kind,
+        });
+        rings.push(RangeRing {
+            x,
+            y,
+            radius: RADIUS * (1.0 + 3.0 * active.age().fract()),
+            kind,
+        });
+    }
+    rings
+}
+
+impl App {
+    pub fn ping_modifier(&self) -> bool {
+        let keys = &self.input_state.key_pressed;
+        keys.contains(&VirtualKeyCode::LAlt) || keys.contains(&VirtualKeyCode::RAlt)
+    }
+
+    ///Alt + left click pings an attack, Alt + right click a defense, with Shift an assist.
+    ///Returns true while Alt is held, the clicks are then not orders.
+    pub fn handle_ping(&mut self) -> bool {
+        self.game_state
+            .pings
+            .retain(|active| active.age() < LIFE_SEC);
+
+        if !self.ping_modifier() {
+            return false;
+        }
+        let keys = &self.input_state.key_pressed;
+        let clicks = &self.input_state.mouse_trigger;
+        let kind = if keys.contains(&VirtualKeyCode::LShift)
+            && (clicks.contains(&MouseButton::Left) || clicks.contains(&MouseButton::Right))
+        {
+            PingKind::Assist
+        } else if clicks.contains(&MouseButton::Left) {
+            PingKind::Attack
+        } else if clicks.contains(&MouseButton::Right) {
+            PingKind::Defend
+        } else {
+            return true;
+        };
+        if let (Some(id), Some(position)) = (
+            self.game_state.my_player_id,
+            self.game_state.mouse_world_pos,
+        ) {
+            let event = FrameEventFromPlayer::Ping { id, kind, position };
+            let _ = self
+                .sender_from_client_to_manager
+                .try_send(client::FromClient::PlayerInput(event));
+        }
+        true
+    }
+
+    ///Pings of our team, all of them when spectating everyone
+    pub fn receive_pings(&mut self, pings: Vec<Ping>) {
+        let my_team = self.game_state.viewed_player().map(|me| me.team);
+        for ping in pings {
+            let team = self
+                .game_state
+                .frame_zero
+                .players
+                .get(&ping.player_id)
+                .map(|p| p.team);
+            if my_team.is_some() && team != my_team {
+                continue;
+            }
+            let sec = self.game_state.server_sec;
+            let mut seed = sec;
+            self.game_state
+                .spawn_effect("ping", Point3::from(ping.position), sec, &mut seed);
+            self.game_state.pings.push(ActivePing {
+                ping,
+                received: Instant::now(),
+            });
+        }
+    }
+}
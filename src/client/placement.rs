@@ -0,0 +1,67 @@
+use super::client::*;
+use super::game_state;
+use super::uitool::UiTool;
+use crate::*;
+
+///Building under the cursor, snapped to the placement grid
+pub struct Placement {
+    pub x: f32,
+    pub y: f32,
+    ///Our buildings it would give or take an adjacency bonus from, with their radius
+    pub touching: Vec<(f32, f32, f32)>,
+    ///Discount it would get as a factory, or give to each factory touched
+    pub discount: f32,
+}
+
+impl App {
+    pub fn placement(game_state: &game_state::State) -> Option<Placement> {
+        let (botdef_id, pos) = match (game_state.uitool, game_state.mouse_world_pos) {
+            (UiTool::Spawn(botdef_id), Some(pos)) => (botdef_id, pos),
+            _ => return None,
+        };
+        let frame_zero = &game_state.frame_zero;
+        let botdef = frame_zero.bot_defs.get(&botdef_id)?;
+        let (x, y) = botdef.snap(pos.x, pos.y);
+        let mut placement = Placement {
+            x,
+            y,
+            touching: Vec::new(),
+            discount: 0.0,
+        };
+        if !botdef.is_building() {
+            return Some(placement);
+        }
+
+        let footprint = botdef.footprint(x, y);
+        let neighbors: Vec<_> = frame_zero
+            .kbots
+            .values()
+            .filter(|kbot| {
+                Some(kbot.player_id) == game_state.my_player_id && kbot.con_completed >= 1.0
+            })
+            .filter_map(|kbot| {
+                let other = frame_zero.bot_defs.get(&kbot.botdef_id)?;
+                let (ox, oy) = (kbot.position.x, kbot.position.y);
+                Some((other, ox, oy))
+                    .filter(|_| other.is_building() && other.footprint(ox, oy).touches(&footprint))
+            })
+            .collect();
+
+        if botdef.is_factory() {
+            placement.discount = botdef.adjacency_discount(x, y, neighbors.iter().cloned());
+            placement.touching = neighbors
+                .iter()
+                .filter(|(other, _, _)| other.adjacency != botdef::Adjacency::None)
+                .map(|(other, ox, oy)| (*ox, *oy, other.radius))
+                .collect();
+        } else if botdef.adjacency != botdef::Adjacency::None {
+            placement.discount = botdef.adjacency.discount();
+            placement.touching = neighbors
+                .iter()
+                .filter(|(other, _, _)| other.is_factory())
+                .map(|(other, ox, oy)| (*ox, *oy, other.radius))
+                .collect();
+        }
+        Some(placement)
+    }
+}
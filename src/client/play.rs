@@ -2,6 +2,7 @@ use super::client::*;
 use crate::botdef;
 use crate::frame::FrameEventFromPlayer;
 use crate::frame::Player;
+use crate::utils::Id;
 use crate::*;
 use fnv::{FnvHashMap, FnvHashSet};
 use imgui::*;
@@ -12,6 +13,11 @@ use utils::*;
 
 impl App {
     pub fn init_play(&mut self) {
+        self.alliance.team_sent = !(self.alliance.pick_team
+            && (self.net_mode == NetMode::Server || self.net_mode == NetMode::Client));
+        if self.init_replay() {
+            return;
+        }
         match self.net_mode {
             NetMode::Offline | NetMode::Server => {
                 self.clear_gpu_instance_and_game_state();
@@ -19,74 +25,50 @@ impl App {
                     Point3::new(300.0, 100.0, self.heightmap_gpu.phy.z(300.0, 100.0) + 50.0);
                 self.game_state.dir = Vector3::new(0.0, 0.3, -1.0);
 
-                let mut player_me = Player::new();
-                let mut player_ennemy = Player::new();
-                player_ennemy.team = 1;
-
-                let mut kbots = FnvHashMap::default();
-
-                let tank_example =
-                    Self::load_botdef_on_disk("src/asset/botdef/unit_example.json").unwrap();
-                let building_example =
-                    Self::load_botdef_on_disk("src/asset/botdef/building_example.json").unwrap();
-
-                for i in (100..300).step_by(4) {
-                    for j in (100..500).step_by(4) {
-                        let m = mobile::KBot::new(
-                            Point3::new(i as f32, j as f32, 100.0),
-                            &tank_example,
-                            player_me.id,
+                let mut frame = match self
+                    .autosave
+                    .resume
+                    .take()
+                    .or_else(|| self.mission_frame())
+                    .or_else(|| self.skirmish_frame())
+                {
+                    Some(frame) => frame,
+                    None => {
+                        let tank_example =
+                            Self::load_botdef_on_disk("src/asset/botdef/unit_example.json")
+                                .unwrap();
+                        let building_example =
+                            Self::load_botdef_on_disk("src/asset/botdef/building_example.json")
+                                .unwrap();
+
+                        let mut frame = frame::Frame::example_game(
+                            self.heightmap_gpu.phy.clone(),
+                            tank_example,
+                            building_example,
                         );
-                        player_me.kbots.insert(m.id);
-                        kbots.insert(m.id, m);
+                        for path in &[
+                            "src/asset/botdef/tech_lab_example.json",
+                            "src/asset/botdef/unit_t2_example.json",
+                            "src/asset/botdef/storage_example.json",
+                            "src/asset/botdef/generator_example.json",
+                        ] {
+                            match Self::load_botdef_on_disk(path) {
+                                Ok(botdef) => frame.add_bot_def(botdef),
+                                Err(e) => log::error!("Could not read botdef {}: {}", path, e),
+                            }
+                        }
+                        frame
                     }
-                }
+                };
 
-                for i in (320..520).step_by(4) {
-                    for j in (100..500).step_by(4) {
-                        let mut m = mobile::KBot::new(
-                            Point3::new(i as f32, j as f32, 100.0),
-                            &tank_example,
-                            player_ennemy.id,
-                        );
-                        m.team = 1;
-                        player_ennemy.kbots.insert(m.id);
-                        kbots.insert(m.id, m);
-                    }
+                if self.net_mode == NetMode::Server {
+                    frame.alliance = self.alliance.rules.clone();
                 }
+                self.game_state.my_player_id =
+                    frame.players.values().find(|p| p.slot == 0).map(|p| p.id);
+                self.game_state.players = frame.players.clone();
 
-                log::info!("Starting a game with {} bots", kbots.len());
-
-                self.game_state.my_player_id = Some(player_me.id);
-                self.game_state.players.insert(player_me.id, player_me);
-                self.game_state
-                    .players
-                    .insert(player_ennemy.id, player_ennemy);
-
-                let mut bot_defs = FnvHashMap::default();
-                bot_defs.insert(tank_example.id, tank_example);
-                bot_defs.insert(building_example.id, building_example);
-
-                let mut moddef = crate::moddef::ModDef {
-                    units_id: bot_defs.keys().copied().collect(),
-                    con_map: FnvHashMap::default(),
-                };
-
-                let replacer = FrameEventFromPlayer::ReplaceFrame(frame::Frame {
-                    number: 0,
-                    players: self.game_state.players.clone(),
-                    moddef,
-                    kbots,
-                    kbots_dead: HashSet::default(),
-                    kinematic_projectiles_dead: Vec::new(),
-                    kinematic_projectiles_birth: Vec::new(),
-                    kinematic_projectiles: self.game_state.kinematic_projectiles_cache.clone(),
-                    arrows: Vec::new(),
-                    explosions: Vec::new(),
-                    heightmap_phy: Some(self.heightmap_gpu.phy.clone()),
-                    frame_profiler: frame::ProfilerMap::new(),
-                    bot_defs,
-                });
+                let replacer = FrameEventFromPlayer::ReplaceFrame(frame);
                 let _ = self
                     .sender_from_client_to_manager
                     .try_send(client::FromClient::PlayerInput(replacer));
@@ -97,18 +79,32 @@ impl App {
                 self.game_state.position =
                     Point3::new(300.0, 100.0, self.heightmap_gpu.phy.z(300.0, 100.0) + 50.0);
                 self.game_state.dir = Vector3::new(0.0, 0.3, -1.0);
-                self.game_state.my_player_id = self
-                    .game_state
-                    .frame_zero
-                    .players
-                    .values()
-                    .filter(|p| p.team == 1)
-                    .map(|p| p.id.clone())
-                    .next();
+                self.game_state.my_player_id = self.client_player();
+            }
+
+            NetMode::Spectator => {
+                self.clear_gpu_instance_and_game_state();
+                self.game_state.position =
+                    Point3::new(300.0, 100.0, self.heightmap_gpu.phy.z(300.0, 100.0) + 50.0);
+                self.game_state.dir = Vector3::new(0.0, 0.3, -1.0);
+                //Without a player, no order can be given
+                self.game_state.my_player_id = None;
             }
         }
     }
 
+    ///Player in the slot the host gave us, known once a frame of the game arrived
+    pub fn client_player(&self) -> Option<Id<Player>> {
+        let seat = self.global_info?.net_client?.seat()?;
+        self.game_state
+            .frame_zero
+            .players
+            .values()
+            .filter(|p| p.slot == seat)
+            .map(|p| p.id.clone())
+            .next()
+    }
+
     pub fn handle_play(
         &mut self,
         delta_sim_sec: f32,
@@ -120,6 +116,7 @@ impl App {
             self.game_state.interpolate(&self.threadpool, &view_proj);
         });
 
+        let can_select = self.input_gate().select && !self.ping_modifier();
         // Selection on screen
         let selection_screen = time(|| {
             //Under_cursor
@@ -174,7 +171,7 @@ impl App {
                 }
             }
 
-            if let Some(me) = self.game_state.my_player() {
+            if let (Some(me), true) = (self.game_state.my_player(), can_select) {
                 //Selection square
                 if let input_state::Drag::End { x0, y0, x1, y1 } = self.input_state.drag {
                     let start_sel = std::time::Instant::now();
@@ -1,21 +1,211 @@
+use super::campaign::CampaignChoice;
 use super::client::*;
+use super::cursor::CursorMode;
+use super::frame_graph::FrameGraph;
+use super::heatmap;
+use super::trigger;
 use super::uitool::UiTool;
 use crate::frame;
 use crate::frame::FrameEventFromPlayer;
 use crate::frame::Player;
+use crate::gpu_obj::dof::DofQuality;
+use crate::gpu_obj::draw_list::{Layer, SortKey};
+use crate::gpu_obj::render_graph::PassId;
+use crate::gpu_obj::toon::RenderStyle;
+use crate::mobile::{FireStance, MoveStance};
+use crate::net_client::BindState;
+use crate::platform::Instant;
+use crate::replay::Replay;
 use crate::*;
 use imgui::*;
 use na::{IsometryMatrix3, Matrix4, Point3, Vector2, Vector3, Vector4};
 use std::time::Duration;
-use std::time::Instant;
 use unit_part_gpu;
 use unit_part_gpu::UnitPartGpu;
 use utils::time;
 use wgpu::{BufferMapAsyncResult, Extent3d};
 
+///Waited for the first frame with our player before going back to the menu
+const LOADING_TIMEOUT_SEC: f32 = 30.0;
+
+///Behind the menus, which render no scene
+const MENU_BACKGROUND: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+
+///Gpu objects recorded from the draw list
+#[derive(Clone, Copy, Debug)]
+pub enum Draw {
+    Heightmap,
+    ///Every unit part, through the ModelBatch
+    Models,
+    KinematicProjectile,
+    Arrow,
+    ///Far units, as billboards
+    Impostor,
+    GroundShadow,
+    ///Glowing decals of the metal spots
+    MetalSpot,
+    ///Paths of the orders, on the ground
+    Waypoint,
+    Water,
+    Shield,
+    Trail,
+    Nanolathe,
+    Beam,
+    Weather,
+    HealthBar,
+    UnitIcon,
+    Line,
+    Hud,
+    CursorIcon,
+}
+
+impl Draw {
+    ///Every object has its own pipeline but the models, their material being the model
+    fn key(self) -> SortKey {
+        match self {
+            Draw::Heightmap => SortKey::new(Layer::OpaqueTerrain, 0, 0),
+            Draw::Models => SortKey::new(Layer::OpaqueModels, 1, 0),
+            Draw::KinematicProjectile => SortKey::new(Layer::OpaqueModels, 1, 1),
+            Draw::Arrow => SortKey::new(Layer::OpaqueModels, 2, 0),
+            Draw::Impostor => SortKey::new(Layer::OpaqueModels, 14, 0),
+            Draw::GroundShadow => SortKey::new(Layer::Decals, 3, 0),
+            Draw::MetalSpot => SortKey::new(Layer::Decals, 15, 0),
+            Draw::Waypoint => SortKey::new(Layer::Decals, 16, 0),
+            Draw::Water => SortKey::new(Layer::Transparent, 4, 0),
+            Draw::Shield => SortKey::new(Layer::Transparent, 5, 0),
+            Draw::Trail => SortKey::new(Layer::Effects, 6, 0),
+            Draw::Nanolathe => SortKey::new(Layer::Effects, 7, 0),
+            Draw::Beam => SortKey::new(Layer::Effects, 8, 0),
+            Draw::Weather => SortKey::new(Layer::Effects, 9, 0),
+            Draw::HealthBar => SortKey::new(Layer::Ui, 10, 0),
+            Draw::UnitIcon => SortKey::new(Layer::Ui, 11, 0),
+            Draw::Line => SortKey::new(Layer::Ui, 12, 0),
+            Draw::Hud => SortKey::new(Layer::Ui, 13, 0),
+            //Over the hud
+            Draw::CursorIcon => SortKey::new(Layer::Ui, 17, 0),
+        }
+    }
+
+    ///Opaque draws with a depth only pipeline. The arrows and the impostors keep testing and
+    ///writing depth in the main pass.
+    fn in_depth_prepass(self) -> bool {
+        match self {
+            Draw::Heightmap | Draw::Models | Draw::KinematicProjectile => true,
+            _ => false,
+        }
+    }
+
+    ///Transparent draws blended in any order in the oit pass when it is on. Water stays sorted, it
+    ///samples the position of the last frame.
+    fn order_independent(self) -> bool {
+        match self {
+            Draw::Shield | Draw::Trail => true,
+            _ => false,
+        }
+    }
+
+    ///Among the draws of the layers of pass
+    fn recorded_in(self, frame_graph: &FrameGraph, pass: PassId) -> bool {
+        if pass == frame_graph.depth_prepass {
+            self.in_depth_prepass()
+        } else if pass == frame_graph.transparent {
+            !(frame_graph.use_oit && self.order_independent())
+        } else if pass == frame_graph.transparent_oit {
+            self.order_independent()
+        } else {
+            true
+        }
+    }
+}
+
+///Picked before a match, in the home menu and the lobby
+fn render_style_radio(ui: &Ui, render_style: &mut RenderStyle) {
+    ui.text(im_str!("Style"));
+    for &style in RenderStyle::ALL.iter() {
+        ui.same_line(0.0);
+        ui.radio_button(&im_str!("{}", style.name()), render_style, style);
+    }
+}
+
+///Records one draw of the list. A macro and not a method of App: the ui keeps part of the App
+///borrowed while the passes are recorded.
+///depth picks the pipelines of the draws taking part in the depth pre-pass, oit the ones of
+///the order-independent transparents
+macro_rules! record_draw {
+    ($app:ident, $rpass:expr, $draw:expr, $pipeline_changed:expr, $depth:expr, $oit:expr) => {{
+        let bind_group = &$app.bind_group;
+        match $draw {
+            Draw::Heightmap => $app.heightmap_gpu.render($rpass, bind_group, $depth),
+            Draw::Models => $app
+                .model_batch
+                .render($rpass, bind_group, $pipeline_changed, $depth),
+            Draw::KinematicProjectile if $pipeline_changed => $app
+                .kinematic_projectile_gpu
+                .render($rpass, bind_group, $depth),
+            Draw::KinematicProjectile => $app.kinematic_projectile_gpu.render_same_state($rpass),
+            Draw::Arrow => $app.arrow_gpu.render($rpass, bind_group),
+            Draw::Impostor => $app.impostor_gpu.render($rpass, bind_group),
+            Draw::GroundShadow => $app.ground_shadow_gpu.render($rpass, bind_group),
+            Draw::MetalSpot => $app.economy_gpu.render_decals($rpass, bind_group),
+            Draw::Waypoint => $app.waypoint_gpu.render($rpass, bind_group),
+            Draw::Water => $app.water_gpu.render($rpass, bind_group),
+            Draw::Shield => $app.shield_gpu.render($rpass, bind_group, $oit),
+            Draw::Trail => $app.trail_gpu.render($rpass, bind_group, $oit),
+            Draw::Nanolathe => $app.nanolathe_gpu.render($rpass, bind_group),
+            Draw::Beam => $app.beam_gpu.render($rpass, bind_group),
+            Draw::Weather => $app.weather_gpu.render($rpass, bind_group),
+            Draw::HealthBar => $app.health_bar.render($rpass, bind_group),
+            Draw::UnitIcon => $app.unit_icon.render($rpass, bind_group),
+            Draw::Line => $app.line_gpu.render($rpass, bind_group),
+            Draw::Hud => $app.hud_gpu.render($rpass, bind_group),
+            Draw::CursorIcon => $app.cursor_icon.render($rpass, bind_group),
+        }
+    }};
+}
+
 impl App {
+    fn fill_draw_list(&mut self) {
+        let draw_list = &mut self.draw_list;
+        draw_list.clear();
+        for draw in &[
+            Draw::Heightmap,
+            Draw::Arrow,
+            Draw::Impostor,
+            Draw::GroundShadow,
+            Draw::MetalSpot,
+            Draw::Waypoint,
+            Draw::Water,
+            Draw::Shield,
+            Draw::Trail,
+            Draw::Nanolathe,
+            Draw::Beam,
+            Draw::Weather,
+            Draw::HealthBar,
+            Draw::UnitIcon,
+            Draw::Line,
+            Draw::Hud,
+            Draw::CursorIcon,
+        ] {
+            draw_list.push(draw.key(), *draw);
+        }
+        //Empty models are left out, the next model would skip binding the shared pipeline
+        if self.model_batch.has_instances() {
+            draw_list.push(Draw::Models.key(), Draw::Models);
+        }
+        if self.kinematic_projectile_gpu.has_instances() {
+            draw_list.push(Draw::KinematicProjectile.key(), Draw::KinematicProjectile);
+        }
+        draw_list.sort();
+    }
+
     pub fn render(&mut self) {
-        if self.frame_count == 1 {
+        profile_scope!("render");
+        if self.frame_count == 1 && self.maximize {
             self.gpu.window.set_maximized(true);
         }
         self.frame_count += 1;
@@ -52,15 +242,19 @@ impl App {
 
                 RenderEvent::ChangeMode {
                     from,
-                    to: MainMode::Play,
+                    to: MainMode::Loading,
                 } => {
+                    self.loading_since = Instant::now();
                     self.init_play();
                 }
 
-                RenderEvent::ChangeMode {
-                    from,
-                    to: MainMode::Home,
-                } => {
+                RenderEvent::ChangeMode { to, .. }
+                    if to == MainMode::Home || to == MainMode::Campaign =>
+                {
+                    self.mission = None;
+                    self.skirmish = None;
+                    self.replay = None;
+                    self.autosave.end_session();
                     self.clear_gpu_instance_and_game_state();
                     self.game_state.position = Point3::new(200.0, 100.0, 50.0);
                     self.game_state.dir = Vector3::new(0.0, 0.3, -1.0);
@@ -71,9 +265,21 @@ impl App {
                                 .sender_from_client_to_manager
                                 .try_send(client::FromClient::PlayerInput(replacer));
                         }
-                        NetMode::Client => {}
+                        NetMode::Client | NetMode::Spectator => {}
                     }
                 }
+                RenderEvent::ChangeMode {
+                    to: MainMode::Play,
+                    ..
+                } => {
+                    self.autosave.begin_session(self.game_state.server_sec);
+                }
+                RenderEvent::ChangeMode {
+                    to: MainMode::PostGame,
+                    ..
+                } => {
+                    self.autosave.end_session();
+                }
                 RenderEvent::ChangeMode {
                     from,
                     to: MainMode::MultiplayerLobby,
@@ -105,11 +311,67 @@ impl App {
             }
         }
 
-        let mode_with_camera = [MainMode::Play, MainMode::MapEditor];
+        if self
+            .input_state
+            .key_trigger
+            .contains(&winit::event::VirtualKeyCode::F4)
+        {
+            self.game_state.economy_overlay = !self.game_state.economy_overlay;
+        }
+
+        if self
+            .input_state
+            .key_trigger
+            .contains(&winit::event::VirtualKeyCode::F6)
+        {
+            self.heatmap.cycle();
+        }
+
+        if self
+            .input_state
+            .key_trigger
+            .contains(&winit::event::VirtualKeyCode::F9)
+        {
+            self.stress.open = !self.stress.open;
+        }
+
+        if self
+            .input_state
+            .key_trigger
+            .contains(&winit::event::VirtualKeyCode::F8)
+        {
+            self.time_travel.open = !self.time_travel.open;
+        }
+
+        if self
+            .input_state
+            .key_trigger
+            .contains(&winit::event::VirtualKeyCode::F7)
+        {
+            self.inspector.open = !self.inspector.open;
+        }
+
+        if self
+            .input_state
+            .key_trigger
+            .contains(&winit::event::VirtualKeyCode::F10)
+        {
+            if self.settings.open {
+                self.settings.open = false;
+                self.settings.edit = self.settings.applied.clone();
+            } else {
+                self.settings.show();
+            }
+        }
+
+        //Menus skip the update and render of the scene
+        let world = self.main_menu.has_world();
+
         // Camera Movements
-        if mode_with_camera.contains(&self.main_menu) {
+        if self.main_menu.has_camera() && self.input_gate().camera {
             self.rts_camera(sim_sec);
         }
+        self.step_benchmark(frame_time);
 
         if self.main_menu == MainMode::UnitEditor {
             self.orbit_camera(sim_sec);
@@ -130,6 +392,15 @@ impl App {
         self.profiler
             .mix("heightmap_editor", heightmap_editor_duration, 20);
 
+        if self
+            .frame_graph
+            .needs_compile(self.gpu.sc_desc.width, self.gpu.sc_desc.height)
+        {
+            if let Some(command_buf) = self.resize() {
+                self.gpu.queue.submit(&[command_buf]);
+            }
+        }
+
         //Render
         let mut encoder_render = self
             .gpu
@@ -137,99 +408,27 @@ impl App {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { todo: 0 });
 
         //Load pending generic gpu
-        for (index, generic_gpu_state) in self.unit_part_gpu.states.iter_mut().enumerate() {
-            if let unit_part_gpu::ModelGpuState::ToLoad(tri_list) = generic_gpu_state {
-                let mut generic_gpu = ModelGpu::new(
-                    &tri_list,
-                    &self.gpu.device,
-                    self.gpu.sc_desc.format,
-                    &self.bind_group_layout,
-                );
+        for index in 0..self.unit_part_gpu.states.len() {
+            if let unit_part_gpu::ModelGpuState::ToLoad(tri_list, textures) =
+                &mut self.unit_part_gpu.states[index]
+            {
+                let textures = std::mem::take(textures);
+                let mesh = self.model_batch.add_mesh(tri_list, textures);
+                //Uploaded with the instances of this frame
+                crate::crash::set_gpu_context(format!(
+                    "model {:?}",
+                    self.unit_part_gpu.path_of(index)
+                ));
                 log::debug!("Load pending generic gpu {:?} ", index);
-                let mut generic_gpu_state_new = unit_part_gpu::ModelGpuState::Ready(generic_gpu);
-                std::mem::replace(generic_gpu_state, generic_gpu_state_new);
-            }
-        }
-
-        let view_proj = camera::create_view_proj(
-            self.gpu.sc_desc.width as f32 / self.gpu.sc_desc.height as f32,
-            self.game_state.near(),
-            &self.game_state.position_smooth,
-            &self.game_state.dir_smooth,
-        );
-        if self.main_menu == MainMode::Play {
-            self.handle_play(sim_sec, &mut encoder_render, &view_proj);
-        }
-
-        self.upload_to_gpu(&view_proj, &mut encoder_render);
-
-        let heightmap_gpu_step_duration = time(|| {
-            self.heightmap_gpu
-                .step(&self.gpu.device, &mut encoder_render);
-        });
-
-        self.profiler
-            .mix("heightmap_gpu_step", heightmap_gpu_step_duration, 20);
-
-        let mut start_drag = (
-            self.input_state.cursor_pos.0 as f32,
-            self.input_state.cursor_pos.1 as f32,
-        );
-
-        if let MainMode::Play = self.main_menu {
-            if let input_state::Drag::Dragging { x0, y0, .. } = self.input_state.drag {
-                start_drag = (x0 as f32, y0 as f32);
+                self.unit_part_gpu.states[index] = unit_part_gpu::ModelGpuState::Ready(mesh);
             }
         }
 
-        let radius = if self.main_menu == MainMode::MapEditor {
-            self.game_state.heightmap_editor.pen_radius as f32
+        if world {
+            self.update_world(sim_sec, &mut encoder_render);
         } else {
-            0.0
-        };
-
-        let mut filler = camera::create_camera_uniform_vec(
-            (self.gpu.sc_desc.width, self.gpu.sc_desc.height),
-            self.game_state.near(),
-            &self.game_state.position_smooth,
-            &self.game_state.dir_smooth,
-        );
-
-        filler.extend_from_slice(&[
-            self.input_state.cursor_pos.0 as f32,
-            self.input_state.cursor_pos.1 as f32,
-            self.gpu.sc_desc.width as f32,
-            self.gpu.sc_desc.height as f32,
-            1.0 / self.gpu.sc_desc.width as f32,
-            1.0 / self.gpu.sc_desc.height as f32,
-            start_drag.0,
-            start_drag.1,
-            radius,
-            self.game_state.heightmap_editor.pen_strength as f32,
-            self.heightmap_gpu.phy.width as f32,
-            self.heightmap_gpu.phy.height as f32,
-        ]);
-
-        let ub_camera_temp = self
-            .gpu
-            .device
-            .create_buffer_mapped(4 * 16 + 12, wgpu::BufferUsage::COPY_SRC)
-            .fill_from_slice(&filler[..]);
-
-        encoder_render.copy_buffer_to_buffer(
-            &ub_camera_temp,
-            0,
-            &self.ub_camera_mat,
-            0,
-            (4 * 16 + 12) * 4,
-        );
-
-        self.heightmap_gpu.update_uniform(
-            &self.gpu.device,
-            &mut encoder_render,
-            self.game_state.position_smooth.x,
-            self.game_state.position_smooth.y,
-        );
+            self.hud.clear();
+        }
 
         //Imgui
         let start = Instant::now();
@@ -240,14 +439,32 @@ impl App {
             .prepare_frame(self.imgui_wrap.imgui.io_mut(), &self.gpu.window)
             .expect("Failed to prepare frame");
 
+        let ui_scale = self.ui_scale();
+        self.imgui_wrap
+            .set_scale(ui_scale, self.game_state.ui_scale);
+
+        //Over the ui imgui cursors tell text fields and resize handles apart
+        let software_cursor = self.game_state.cursor_mode == CursorMode::Software;
+        let io = self.imgui_wrap.imgui.io_mut();
+        io.mouse_draw_cursor = software_cursor && io.want_capture_mouse;
+        let cursor_context = self.cursor_context();
+        let scene_size = self.scene_size();
+        let mouse_pos = self.scene_cursor();
+
+        //Also applies the reverted settings on the frame the window closes
+        let settings_open = self.settings.open;
+        let mut resume_path = None;
+        let mut replay_path = None;
         let ui: Ui = self.imgui_wrap.imgui.frame();
         {
             let main_menu = &mut self.main_menu;
 
             {
                 //Stat
-                let fps_before = self.game_state.fps.clone();
-                let mut_fps = &mut self.game_state.fps;
+                let fps = self.game_state.fps;
+                let use_indirect = &mut self.model_batch.use_indirect;
+                let use_depth_prepass = &mut self.frame_graph.use_depth_prepass;
+                let use_occlusion_culling = &mut self.model_batch.use_occlusion_culling;
                 let profiler_logic = &self.game_state.frame_zero.frame_profiler;
                 let profiler_render = &self.profiler;
                 let stats_window = imgui::Window::new(im_str!("Statistics"));
@@ -258,14 +475,18 @@ impl App {
                     .resizable(true)
                     .movable(false)
                     .build(&ui, || {
-                        imgui::Slider::new(im_str!("fps cap"), 1..=480).build(&ui, mut_fps);
+                        ui.checkbox(im_str!("indirect draws"), use_indirect);
+                        ui.checkbox(im_str!("depth pre-pass"), use_depth_prepass);
+                        if *use_depth_prepass {
+                            ui.checkbox(im_str!("occlusion culling"), use_occlusion_culling);
+                        }
                         ui.text(im_str!(
                             "render: {:?}",
                             profiler_render.get("frame_time").unwrap()
                         ));
                         ProgressBar::new(
                             profiler_render.get("frame_time").unwrap().as_secs_f32()
-                                / (1.0 / *mut_fps as f32),
+                                / (1.0 / fps as f32),
                         )
                         .build(&ui);
                         let mut others = profiler_render
@@ -307,11 +528,92 @@ impl App {
                                 .collect();
                             ui.text(im_str!(" {}: {:?}", name, dur));
                         }
+
+                        ui.separator();
+
+                        use crate::gpu_obj::gpu_memory;
+                        let to_mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+                        ui.text(im_str!("gpu memory: {:.1} MB", to_mb(gpu_memory::total())));
+                        for kind in gpu_memory::Kind::ALL.iter() {
+                            ui.text(im_str!(
+                                " {:<22}: {:.1} MB",
+                                kind.name(),
+                                to_mb(kind.allocated())
+                            ));
+                        }
                     });
 
-                if fps_before != *mut_fps {
-                    self.loop_helper = LoopHelper::builder().build_with_target_rate(*mut_fps as f64)
+                self.settings.draw(
+                    &ui,
+                    self.gpu.sc_desc.width as f32,
+                    self.gpu.sc_desc.height as f32,
+                );
+
+                //Told once after a crash, so that the report ends up in the bug report
+                let mut dismiss_crash_report = false;
+                if let Some(path) = &self.crash_report {
+                    imgui::Window::new(im_str!("Crash report"))
+                        .size([400.0, 120.0], imgui::Condition::FirstUseEver)
+                        .position(
+                            [
+                                (self.gpu.sc_desc.width as f32 - 400.0) / 2.0,
+                                (self.gpu.sc_desc.height as f32 - 120.0) / 2.0,
+                            ],
+                            imgui::Condition::FirstUseEver,
+                        )
+                        .build(&ui, || {
+                            ui.text(im_str!("The previous session crashed."));
+                            ui.text(im_str!("Please attach this file to your bug report:"));
+                            ui.text(im_str!("{}", path));
+                            dismiss_crash_report = ui.button(im_str!("Dismiss"), [100.0, 20.0]);
+                        });
+                }
+                if dismiss_crash_report {
+                    crate::crash::dismiss_pending_report();
+                    self.crash_report = None;
+                }
+
+                //Offered until answered, the next game writes its own saves
+                let mut discard = false;
+                if let (Some(path), MainMode::Home) = (&self.autosave.recovery, *main_menu) {
+                    imgui::Window::new(im_str!("Recovery"))
+                        .size([400.0, 120.0], imgui::Condition::FirstUseEver)
+                        .position(
+                            [
+                                (self.gpu.sc_desc.width as f32 - 400.0) / 2.0,
+                                (self.gpu.sc_desc.height as f32 - 120.0) / 2.0 + 130.0,
+                            ],
+                            imgui::Condition::FirstUseEver,
+                        )
+                        .build(&ui, || {
+                            ui.text(im_str!("The last game didn't end properly."));
+                            ui.text(im_str!("Resume it from {}?", path));
+                            if ui.button(im_str!("Resume"), [100.0, 20.0]) {
+                                resume_path = Some(path.clone());
+                            }
+                            ui.same_line(0.0);
+                            discard = ui.button(im_str!("Discard"), [100.0, 20.0]);
+                        });
                 }
+                if resume_path.is_some() || discard {
+                    self.autosave.recovery = None;
+                }
+
+                //Log viewer, same lines as the log file
+                let log_lines = &self.log_lines;
+                imgui::Window::new(im_str!("Log"))
+                    .size([600.0, 300.0], imgui::Condition::FirstUseEver)
+                    .position([3.0, 30.0], imgui::Condition::FirstUseEver)
+                    .collapsed(true, imgui::Condition::FirstUseEver)
+                    .build(&ui, || {
+                        if ui.small_button(im_str!("Clear")) {
+                            log_lines.clear();
+                        }
+                        ui.separator();
+                        for line in log_lines.snapshot().iter() {
+                            ui.text(im_str!("{}", line));
+                        }
+                    });
 
                 //Global info
                 if let Some(global_info) = self.global_info {
@@ -330,6 +632,15 @@ impl App {
                         .resizable(true)
                         .movable(false)
                         .build(&ui, || {
+                            if let Some(net_client) = global_info.net_client {
+                                let traffic = net_client.traffic();
+                                ui.text(im_str!(
+                                    "host: {:.0} B/s up, {:.0} B/s down, orders packed x{:.1}",
+                                    traffic.sent,
+                                    traffic.received,
+                                    traffic.commands_ratio
+                                ));
+                            }
                             ui.text(im_str!("{:#?}", global_info));
                         });
                 }
@@ -341,6 +652,8 @@ impl App {
 
                         let mut next_mode = MainMode::Home;
                         let mut exit = false;
+                        let mut show_settings = false;
+                        let render_style = &mut self.game_state.render_style;
                         home_window
                             // .size([w, h], imgui::Condition::Always)
                             .position(
@@ -359,8 +672,12 @@ impl App {
                             .collapsible(false)
                             .build(&ui, || {
                                 if ui.button(im_str!("Play"), [200.0_f32, 100.0]) {
-                                    next_mode = MainMode::Play;
+                                    next_mode = MainMode::Loading;
                                 }
+                                if ui.button(im_str!("Campaign"), [200.0_f32, 100.0]) {
+                                    next_mode = MainMode::Campaign;
+                                }
+                                render_style_radio(&ui, render_style);
                                 if ui.button(im_str!("Map Editor"), [200.0_f32, 100.0]) {
                                     next_mode = MainMode::MapEditor;
                                 }
@@ -370,6 +687,15 @@ impl App {
                                 if ui.button(im_str!("Multiplayer"), [200.0_f32, 100.0]) {
                                     next_mode = MainMode::MultiplayerLobby;
                                 }
+                                if ui.button(im_str!("Last replay"), [200.0_f32, 100.0]) {
+                                    replay_path = Replay::latest();
+                                    if replay_path.is_none() {
+                                        log::info!("No replay in {}", replay::REPLAY_DIR);
+                                    }
+                                }
+                                if ui.button(im_str!("Settings"), [200.0_f32, 100.0]) {
+                                    show_settings = true;
+                                }
                                 if ui.button(im_str!("Exit"), [200.0_f32, 100.0]) {
                                     exit = true;
                                 }
@@ -378,6 +704,40 @@ impl App {
                         if exit {
                             self.sender_to_event_loop.send(EventLoopMsg::Stop).unwrap();
                         }
+                        if show_settings {
+                            self.settings.show();
+                        }
+                        if self.main_menu != next_mode {
+                            self.mailbox.push(RenderEvent::ChangeMode {
+                                from: self.main_menu,
+                                to: next_mode,
+                            });
+                            self.main_menu = next_mode;
+                        }
+                    }
+                    MainMode::Campaign => {
+                        let choice = Self::draw_campaign_ui(
+                            &ui,
+                            &self.campaign,
+                            self.gpu.sc_desc.width as f32,
+                            self.gpu.sc_desc.height as f32,
+                        );
+                        let next_mode = match choice {
+                            Some(CampaignChoice::Start(path)) => {
+                                match trigger::Mission::open(&path) {
+                                    Ok(mission) => {
+                                        self.mission = Some(mission);
+                                        MainMode::Loading
+                                    }
+                                    Err(e) => {
+                                        log::error!("Could not read mission {}: {}", path, e);
+                                        MainMode::Campaign
+                                    }
+                                }
+                            }
+                            Some(CampaignChoice::Back) => MainMode::Home,
+                            None => MainMode::Campaign,
+                        };
                         if self.main_menu != next_mode {
                             self.mailbox.push(RenderEvent::ChangeMode {
                                 from: self.main_menu,
@@ -387,10 +747,74 @@ impl App {
                         }
                     }
                     MainMode::Play => {
-                        if let Some(me) = self.game_state.my_player() {
+                        self.hud.draw_labels(&ui);
+                        let leave = match self.mission.as_mut() {
+                            Some(mission) => mission.draw(
+                                &ui,
+                                self.gpu.sc_desc.width as f32,
+                                56.0 * self.hud.scale,
+                            ),
+                            None => false,
+                        };
+                        if leave {
+                            self.mailbox.push(RenderEvent::ChangeMode {
+                                from: self.main_menu,
+                                to: MainMode::Campaign,
+                            });
+                            self.main_menu = MainMode::Campaign;
+                        }
+
+                        if self.net_mode == NetMode::Spectator || self.replay.is_some() {
+                            let mut observed = self.game_state.observed_player_id;
+                            let mut perspective = self.game_state.perspective;
+                            let players = &self.game_state.frame_zero.players;
+                            imgui::Window::new(im_str!("Spectator"))
+                                .size([300.0, 200.0], imgui::Condition::FirstUseEver)
+                                .position([910.0, 3.0], imgui::Condition::FirstUseEver)
+                                .build(&ui, || {
+                                    if ui.radio_button_bool(
+                                        im_str!("All players"),
+                                        observed.is_none(),
+                                    ) {
+                                        observed = None;
+                                    }
+                                    for player in players.values() {
+                                        let label = im_str!(
+                                            "Player {} (team {}) metal {:.0}",
+                                            player.id,
+                                            player.team,
+                                            player.metal
+                                        );
+                                        if ui.radio_button_bool(&label, observed == Some(player.id))
+                                        {
+                                            observed = Some(player.id);
+                                        }
+                                    }
+                                    ui.checkbox(im_str!("player perspective"), &mut perspective);
+                                });
+                            self.game_state.observed_player_id = observed;
+                            self.game_state.perspective = perspective;
+                            Self::draw_caster_ui(&ui, &self.game_state);
+                        }
+                        if let Some(replay) = &self.replay {
+                            let messages = Self::draw_timeline_ui(
+                                &ui,
+                                replay,
+                                &self.game_state,
+                                self.global_info,
+                                self.gpu.sc_desc.width as f32,
+                            );
+                            for message in messages {
+                                let _ = self.sender_from_client_to_manager.try_send(message);
+                            }
+                        }
+
+                        let mut economy_overlay = self.game_state.economy_overlay;
+                        let mut heatmap_shown = self.heatmap.shown;
+                        if let Some(me) = self.game_state.viewed_player() {
                             let resource_window = imgui::Window::new(im_str!("Resources"));
                             resource_window
-                                .size([400.0, 120.0], imgui::Condition::FirstUseEver)
+                                .size([400.0, 150.0], imgui::Condition::FirstUseEver)
                                 .position([500.0, 3.0], imgui::Condition::FirstUseEver)
                                 .collapsed(false, imgui::Condition::FirstUseEver)
                                 .build(&ui, || {
@@ -398,7 +822,112 @@ impl App {
                                     ProgressBar::new((me.metal / 500.0) as f32).build(&ui);
                                     ui.text(im_str!("energy: {:.1}", me.energy));
                                     ProgressBar::new((me.energy / 500.0) as f32).build(&ui);
+                                    ui.checkbox(
+                                        im_str!("economy overlay (F4)"),
+                                        &mut economy_overlay,
+                                    );
+                                    ui.text(im_str!("heatmap (F6)"));
+                                    ui.same_line(0.0);
+                                    ui.radio_button(im_str!("off"), &mut heatmap_shown, None);
+                                    for &kind in heatmap::ALL.iter() {
+                                        ui.same_line(0.0);
+                                        ui.radio_button(
+                                            &im_str!("{}", heatmap::name(kind)),
+                                            &mut heatmap_shown,
+                                            Some(kind),
+                                        );
+                                    }
+                                });
+                        }
+                        self.game_state.economy_overlay = economy_overlay;
+                        self.heatmap.shown = heatmap_shown;
+
+                        if self.stress.open && self.net_mode == NetMode::Offline {
+                            let events =
+                                Self::draw_stress_ui(&ui, &mut self.stress, &self.game_state);
+                            for event in events {
+                                let _ = self
+                                    .sender_from_client_to_manager
+                                    .try_send(client::FromClient::PlayerInput(event));
+                            }
+                        }
+
+                        if self.inspector.open && self.net_mode == NetMode::Offline {
+                            let events =
+                                Self::draw_inspector_ui(&ui, &mut self.inspector, &self.game_state);
+                            for event in events {
+                                let _ = self
+                                    .sender_from_client_to_manager
+                                    .try_send(client::FromClient::PlayerInput(event));
+                            }
+                        }
+
+                        if self.time_travel.open && self.net_mode == NetMode::Offline {
+                            let before = self.time_travel.shown_number();
+                            let messages = Self::draw_time_travel_ui(
+                                &ui,
+                                &mut self.time_travel,
+                                &self.game_state,
+                                self.global_info,
+                            );
+                            for message in messages {
+                                let _ = self.sender_from_client_to_manager.try_send(message);
+                            }
+                            if self.time_travel.shown_number() != before {
+                                if let Some(frame) = self.time_travel.shown() {
+                                    self.game_state.handle_new_frame(frame);
+                                }
+                            }
+                        }
+
+                        if self.game_state.frame_zero.game_end.is_some() {
+                            self.mailbox.push(RenderEvent::ChangeMode {
+                                from: self.main_menu,
+                                to: MainMode::PostGame,
+                            });
+                            self.main_menu = MainMode::PostGame;
+                        }
+
+                        if let (Some(global_info), Some(id)) =
+                            (self.global_info, self.game_state.my_player_id)
+                        {
+                            let game_speed = global_info.manager.game_speed;
+                            let mut vote = None;
+                            imgui::Window::new(im_str!("Game speed"))
+                                .size([200.0, 60.0], imgui::Condition::FirstUseEver)
+                                .position([910.0, 3.0], imgui::Condition::FirstUseEver)
+                                .build(&ui, || {
+                                    const SPEEDS: [f32; 6] = [0.5, 1.0, 2.0, 3.0, 5.0, 10.0];
+                                    let index = SPEEDS
+                                        .iter()
+                                        .position(|s| *s >= game_speed.speed)
+                                        .unwrap_or(SPEEDS.len() - 1);
+                                    if ui.small_button(im_str!("-")) && index > 0 {
+                                        vote = Some((SPEEDS[index - 1], game_speed.paused));
+                                    }
+                                    ui.same_line(0.0);
+                                    ui.text(im_str!("x{}", game_speed.speed));
+                                    ui.same_line(0.0);
+                                    if ui.small_button(im_str!("+")) && index + 1 < SPEEDS.len() {
+                                        vote = Some((SPEEDS[index + 1], game_speed.paused));
+                                    }
+                                    ui.same_line(0.0);
+                                    let pause_label = if game_speed.paused {
+                                        im_str!("Resume")
+                                    } else {
+                                        im_str!("Pause")
+                                    };
+                                    if ui.small_button(pause_label) {
+                                        vote = Some((game_speed.speed, !game_speed.paused));
+                                    }
                                 });
+                            //Offline the vote is applied at once, in multiplayer it needs a majority
+                            if let Some((speed, paused)) = vote {
+                                let event = FrameEventFromPlayer::SpeedVote { id, speed, paused };
+                                let _ = self
+                                    .sender_from_client_to_manager
+                                    .try_send(client::FromClient::PlayerInput(event));
+                            }
                         }
 
                         let mut uitool = self.game_state.uitool;
@@ -411,7 +940,9 @@ impl App {
                             .cloned()
                             .collect();
 
-                        let can_be_built = &self.game_state.frame_zero.bot_defs;
+                        let game_state = &self.game_state;
+                        let can_be_built = &game_state.frame_zero.bot_defs;
+                        let mut stance = None;
 
                         let command_window = imgui::Window::new(im_str!("Command"));
                         command_window
@@ -419,7 +950,9 @@ impl App {
                             .position([3.0, 415.0], imgui::Condition::FirstUseEver)
                             .collapsed(false, imgui::Condition::FirstUseEver)
                             .build(&ui, || {
-                                for can_be_built in can_be_built {
+                                for can_be_built in
+                                    can_be_built.iter().filter(|(_, b)| !b.commander)
+                                {
                                     let txt = format!("Build {:?}", can_be_built.1.file_path);
                                     if ui.small_button(&im_str!("{}", txt)) {
                                         uitool = UiTool::Spawn(can_be_built.0.clone());
@@ -429,8 +962,219 @@ impl App {
                                 if ui.small_button(im_str!("Repair")) {
                                     uitool = UiTool::Repair;
                                 }
+                                ui.same_line(0.0);
+                                if ui.small_button(im_str!("Reclaim")) {
+                                    uitool = UiTool::Reclaim;
+                                }
+                                ui.same_line(0.0);
+                                if ui.small_button(im_str!("Patrol")) {
+                                    uitool = UiTool::Patrol;
+                                }
+                                ui.same_line(0.0);
+                                if ui.small_button(im_str!("Guard")) {
+                                    uitool = UiTool::Guard;
+                                }
+
+                                let frame_zero = &game_state.frame_zero;
+                                //Stances of the first selected unit, set on the whole selection
+                                let first = game_state
+                                    .selected
+                                    .iter()
+                                    .filter_map(|id| frame_zero.kbots.get(id))
+                                    .next();
+                                if let Some(first) = first {
+                                    ui.separator();
+                                    let mut fire = first.fire_stance;
+                                    ui.radio_button(
+                                        im_str!("Hold fire"),
+                                        &mut fire,
+                                        FireStance::HoldFire,
+                                    );
+                                    ui.same_line(0.0);
+                                    ui.radio_button(
+                                        im_str!("Return fire"),
+                                        &mut fire,
+                                        FireStance::ReturnFire,
+                                    );
+                                    ui.same_line(0.0);
+                                    ui.radio_button(
+                                        im_str!("Fire at will"),
+                                        &mut fire,
+                                        FireStance::FireAtWill,
+                                    );
+                                    let mut movement = first.move_stance;
+                                    ui.radio_button(
+                                        im_str!("Hold position"),
+                                        &mut movement,
+                                        MoveStance::HoldPosition,
+                                    );
+                                    ui.same_line(0.0);
+                                    ui.radio_button(
+                                        im_str!("Maneuver"),
+                                        &mut movement,
+                                        MoveStance::Maneuver,
+                                    );
+                                    ui.same_line(0.0);
+                                    ui.radio_button(
+                                        im_str!("Roam"),
+                                        &mut movement,
+                                        MoveStance::Roam,
+                                    );
+                                    if fire != first.fire_stance || movement != first.move_stance {
+                                        stance = Some((
+                                            Some(fire).filter(|&f| f != first.fire_stance),
+                                            Some(movement).filter(|&m| m != first.move_stance),
+                                        ));
+                                    }
+                                }
+
+                                let has_dgun = game_state
+                                    .selected
+                                    .iter()
+                                    .filter_map(|id| frame_zero.kbots.get(id))
+                                    .any(|kbot| {
+                                        frame_zero
+                                            .bot_defs
+                                            .get(&kbot.botdef_id)
+                                            .map(|botdef| botdef.dgun.is_some())
+                                            .unwrap_or(false)
+                                    });
+                                if has_dgun {
+                                    ui.separator();
+                                    if ui.small_button(im_str!("D-gun")) {
+                                        uitool = UiTool::DGun;
+                                    }
+                                }
+
+                                let transports: Vec<_> = game_state
+                                    .selected
+                                    .iter()
+                                    .filter_map(|id| frame_zero.kbots.get(id))
+                                    .filter_map(|kbot| {
+                                        frame_zero
+                                            .bot_defs
+                                            .get(&kbot.botdef_id)
+                                            .filter(|botdef| botdef.is_transport())
+                                            .map(|botdef| (kbot, botdef))
+                                    })
+                                    .collect();
+                                if !transports.is_empty() {
+                                    ui.separator();
+                                    if ui.small_button(im_str!("Load")) {
+                                        uitool = UiTool::Load;
+                                    }
+                                    ui.same_line(0.0);
+                                    if ui.small_button(im_str!("Unload")) {
+                                        uitool = UiTool::Unload;
+                                    }
+                                    ui.same_line(0.0);
+                                    if ui.small_button(im_str!("Ferry")) {
+                                        uitool = UiTool::Ferry;
+                                    }
+                                    for (kbot, botdef) in transports {
+                                        ui.text(im_str!(
+                                            "cargo {}/{}",
+                                            kbot.cargo.len(),
+                                            botdef.transport_capacity
+                                        ));
+                                        ProgressBar::new(
+                                            kbot.cargo.len() as f32
+                                                / botdef.transport_capacity as f32,
+                                        )
+                                        .build(&ui);
+                                    }
+                                }
+
+                                let strategics: Vec<_> = game_state
+                                    .selected
+                                    .iter()
+                                    .filter_map(|id| frame_zero.kbots.get(id))
+                                    .filter_map(|kbot| {
+                                        frame_zero.bot_defs.get(&kbot.botdef_id).and_then(
+                                            |botdef| {
+                                                botdef
+                                                    .strategic
+                                                    .stockpile_frames()
+                                                    .map(|frames| (kbot, botdef, frames))
+                                            },
+                                        )
+                                    })
+                                    .collect();
+                                if !strategics.is_empty() {
+                                    ui.separator();
+                                    let has_nuke = strategics.iter().any(|(kbot, botdef, _)| {
+                                        match botdef.strategic {
+                                            botdef::Strategic::Nuke { .. } => kbot.stockpile > 0,
+                                            _ => false,
+                                        }
+                                    });
+                                    if has_nuke && ui.small_button(im_str!("Nuke")) {
+                                        uitool = UiTool::Nuke;
+                                    }
+                                    for (kbot, _, stockpile_frames) in strategics {
+                                        ui.text(im_str!(
+                                            "stockpile {}/{}",
+                                            kbot.stockpile,
+                                            botdef::Strategic::MAX_STOCKPILE
+                                        ));
+                                        ProgressBar::new(
+                                            kbot.stockpile_progress as f32
+                                                / stockpile_frames as f32,
+                                        )
+                                        .build(&ui);
+                                    }
+                                }
                             });
 
+                        if let (Some((fire, movement)), Some(id)) =
+                            (stance, self.game_state.my_player_id)
+                        {
+                            let event = FrameEventFromPlayer::StanceOrder {
+                                id,
+                                selected: self.game_state.selected.clone(),
+                                fire,
+                                movement,
+                            };
+                            let _ = self
+                                .sender_from_client_to_manager
+                                .try_send(client::FromClient::PlayerInput(event));
+                        }
+
+                        let mut events =
+                            Self::draw_factory_ui(&ui, &mut self.factory, &self.game_state);
+                        events.extend(Self::draw_gift_ui(&ui, &self.game_state));
+                        for event in events {
+                            let _ = self
+                                .sender_from_client_to_manager
+                                .try_send(client::FromClient::PlayerInput(event));
+                        }
+
+                        let placement = Self::placement(&self.game_state);
+                        if let Some(placement) = placement.filter(|p| p.discount > 0.0) {
+                            let is_factory = match self.game_state.uitool {
+                                UiTool::Spawn(botdef_id) => self
+                                    .game_state
+                                    .frame_zero
+                                    .bot_defs
+                                    .get(&botdef_id)
+                                    .map(|botdef| botdef.is_factory())
+                                    .unwrap_or(false),
+                                _ => false,
+                            };
+                            if is_factory {
+                                ui.tooltip_text(format!(
+                                    "adjacency: -{:.0}% metal cost",
+                                    placement.discount * 100.0
+                                ));
+                            } else if !placement.touching.is_empty() {
+                                ui.tooltip_text(format!(
+                                    "adjacency: -{:.0}% metal cost for {} factories",
+                                    placement.discount * 100.0,
+                                    placement.touching.len()
+                                ));
+                            }
+                        }
+
                         if self.game_state.uitool != uitool {
                             log::debug!(
                                 "UiTool state from {:?} to {:?}",
@@ -438,30 +1182,183 @@ impl App {
                                 uitool
                             );
                             self.game_state.uitool = uitool;
+                            self.game_state.ferry_pickup = None;
                         }
                     }
-                    MainMode::MapEditor => {
-                        self.game_state
-                            .heightmap_editor
-                            .draw_ui(&ui, &mut self.heightmap_gpu);
+                    MainMode::PostGame => {
+                        self.hud.draw_labels(&ui);
+                        if let Some(game_end) = &self.game_state.frame_zero.game_end {
+                            let my_team = self.game_state.my_player().map(|me| me.team);
+                            let title = match (game_end.winner_team, my_team) {
+                                (None, _) => "Draw".to_owned(),
+                                (Some(team), Some(my_team)) if team == my_team => {
+                                    "Victory".to_owned()
+                                }
+                                (Some(_), Some(_)) => "Defeat".to_owned(),
+                                (Some(team), None) => format!("Team {} wins", team),
+                            };
+                            let players = &self.game_state.frame_zero.players;
+                            let mut back = false;
+                            imgui::Window::new(&im_str!("{}", title))
+                            .size([500.0, 500.0], imgui::Condition::FirstUseEver)
+                            .position([300.0, 150.0], imgui::Condition::FirstUseEver)
+                            .build(&ui, || {
+                                ui.text(im_str!(
+                                    "Game ended after {:.0} sec",
+                                    game_end.frame as f32 / 10.0
+                                ));
+                                ui.same_line(0.0);
+                                if ui.small_button(im_str!("Export stats")) {
+                                    let all_stats = players
+                                        .values()
+                                        .map(|p| (p.id.to_string(), &p.stats));
+                                    match stats::write_csv_file(stats::CSV_PATH, all_stats) {
+                                        Ok(()) => {
+                                            log::info!("Stats written to {}", stats::CSV_PATH)
+                                        }
+                                        Err(e) => log::error!("Can't write stats: {}", e),
+                                    }
+                                }
+                                ui.same_line(0.0);
+                                if ui.small_button(im_str!("Back")) {
+                                    back = true;
+                                }
+                                for player in players.values() {
+                                    let stats = &player.stats;
+                                    ui.separator();
+                                    ui.text(im_str!(
+                                        "Player {} (team {}){}",
+                                        player.id,
+                                        player.team,
+                                        if player.defeated { " defeated" } else { "" }
+                                    ));
+                                    ui.text(im_str!(
+                                        "units built {}, lost {}, metal spent {:.0}, damage dealt {}",
+                                        stats.units_built,
+                                        stats.units_lost,
+                                        stats.metal_spent,
+                                        stats.damage_dealt
+                                    ));
+
+                                    for series in stats::Series::ALL.iter() {
+                                        let values = stats.series(*series);
+                                        PlotLines::new(
+                                            &ui,
+                                            &im_str!("{}##{}", series.name(), player.id),
+                                            &values[..],
+                                        )
+                                        .graph_size([0.0, 60.0])
+                                        .build();
+                                    }
+                                }
+                            });
+                            if back {
+                                //Missions return to their campaign
+                                let next_mode = if self.mission.is_some() {
+                                    MainMode::Campaign
+                                } else {
+                                    MainMode::Home
+                                };
+                                self.mailbox.push(RenderEvent::ChangeMode {
+                                    from: self.main_menu,
+                                    to: next_mode,
+                                });
+                                self.main_menu = next_mode;
+                            }
+                        }
                     }
-                    MainMode::UnitEditor => {
-                        Self::draw_unit_editor_ui(
-                            &ui,
-                            &mut self.unit_editor,
-                            &mut self.unit_part_gpu,
-                        );
+                    MainMode::Loading => {
+                        let mut cancel = false;
+                        let waited = self.loading_since.elapsed().as_secs_f32();
+                        imgui::Window::new(im_str!("Loading"))
+                            .position(
+                                [
+                                    (self.gpu.sc_desc.width as f32) / 2.0,
+                                    (self.gpu.sc_desc.height as f32) / 2.0,
+                                ],
+                                imgui::Condition::Always,
+                            )
+                            .position_pivot([0.5, 0.5])
+                            .always_auto_resize(true)
+                            .resizable(false)
+                            .movable(false)
+                            .collapsible(false)
+                            .build(&ui, || {
+                                ui.text(im_str!("Waiting for the first frame"));
+                                ui.text(im_str!(
+                                    "Back to the menu in {:.0}s",
+                                    (LOADING_TIMEOUT_SEC - waited).max(0.0)
+                                ));
+                                if ui.button(im_str!("Cancel"), [200.0_f32, 40.0]) {
+                                    cancel = true;
+                                }
+                            });
+                        //Spectators have no player of their own
+                        let players = &self.game_state.frame_zero.players;
+                        let ready = self.game_state.frame_zero.game_end.is_none()
+                            && match self.game_state.my_player_id {
+                                Some(id) => players.contains_key(&id),
+                                None => !players.is_empty(),
+                            };
+                        //The host refused us, dropped, or never sent a frame with our player
+                        let lost = match self.global_info.and_then(|info| info.net_client) {
+                            Some(info) => match info.bind_state() {
+                                BindState::Error
+                                | BindState::Disconnected
+                                | BindState::Incompatible => true,
+                                BindState::Unknown | BindState::Success => false,
+                            },
+                            None => false,
+                        };
+                        let timed_out = !ready && waited > LOADING_TIMEOUT_SEC;
+                        if lost || timed_out {
+                            log::error!("No frame with our player, back to the menu");
+                        }
+                        let next_mode = if ready && !cancel {
+                            MainMode::Play
+                        } else if cancel || lost || timed_out {
+                            match self.net_mode {
+                                NetMode::Offline => MainMode::Home,
+                                _ => MainMode::MultiplayerLobby,
+                            }
+                        } else {
+                            MainMode::Loading
+                        };
+                        if self.main_menu != next_mode {
+                            self.mailbox.push(RenderEvent::ChangeMode {
+                                from: self.main_menu,
+                                to: next_mode,
+                            });
+                            self.main_menu = next_mode;
+                        }
+                    }
+                    MainMode::MapEditor => {
+                        self.game_state
+                            .heightmap_editor
+                            .draw_ui(&ui, &mut self.heightmap_gpu);
+                    }
+                    MainMode::UnitEditor => {
+                        Self::draw_unit_editor_ui(
+                            &ui,
+                            &mut self.unit_editor,
+                            &mut self.unit_part_gpu,
+                        );
                     }
                     MainMode::MultiplayerLobby => {
                         let w = 216.0;
-                        let h = 324.0;
+                        let h = 515.0;
                         let home_window = imgui::Window::new(im_str!("Multiplayer Lobby"));
 
                         let mut create_server = false;
                         let mut create_client = false;
+                        let mut create_spectator = false;
                         let mut disconnect_server = false;
                         let mut disconnect_client = false;
                         let mut next_mode = MainMode::MultiplayerLobby;
+                        let relay_addr = &mut self.relay_addr;
+                        let relay_room = &mut self.relay_room;
+                        let rollback = &mut self.rollback;
+                        let render_style = &mut self.game_state.render_style;
                         if let Some(global_info) = self.global_info {
                             home_window
                                 .size([w, h], imgui::Condition::Always)
@@ -484,6 +1381,12 @@ impl App {
                                             ui.button(im_str!("Start server"), [200.0_f32, 100.0]);
                                         create_client =
                                             ui.button(im_str!("Start client"), [200.0_f32, 100.0]);
+                                        create_spectator =
+                                            ui.button(im_str!("Spectate"), [200.0_f32, 100.0]);
+                                        ui.input_text(im_str!("Relay"), relay_addr).build();
+                                        ui.input_text(im_str!("Room"), relay_room).build();
+                                        ui.checkbox(im_str!("Rollback (1v1)"), rollback);
+                                        render_style_radio(&ui, render_style);
                                     } else if global_info.net_server.is_some() {
                                         disconnect_server = ui.button(
                                             im_str!("Disconnect server"),
@@ -500,12 +1403,57 @@ impl App {
                                         next_mode = MainMode::Home;
                                     }
                                 });
+
+                            //Debug conditions for the next connection, applied to received messages
+                            if global_info.net_server.is_none() && global_info.net_client.is_none()
+                            {
+                                let net_sim = &mut self.net_sim;
+                                imgui::Window::new(im_str!("Network simulation"))
+                                    .size([300.0, 130.0], imgui::Condition::FirstUseEver)
+                                    .position(
+                                        [
+                                            (self.gpu.sc_desc.width as f32 + w) / 2.0 + 10.0,
+                                            (self.gpu.sc_desc.height as f32 - h) / 2.0,
+                                        ],
+                                        imgui::Condition::FirstUseEver,
+                                    )
+                                    .build(&ui, || {
+                                        imgui::Slider::new(im_str!("latency ms"), 0..=1000)
+                                            .build(&ui, &mut net_sim.latency_ms);
+                                        imgui::Slider::new(im_str!("jitter ms"), 0..=500)
+                                            .build(&ui, &mut net_sim.jitter_ms);
+                                        imgui::Slider::new(im_str!("loss"), 0.0..=1.0)
+                                            .build(&ui, &mut net_sim.loss);
+                                        ui.checkbox(im_str!("reorder"), &mut net_sim.reorder);
+                                    });
+                                Self::draw_lobby_alliance_ui(
+                                    &ui,
+                                    &mut self.alliance,
+                                    [
+                                        (self.gpu.sc_desc.width as f32 + w) / 2.0 + 10.0,
+                                        (self.gpu.sc_desc.height as f32 - h) / 2.0 + 140.0,
+                                    ],
+                                );
+                            }
                         }
 
+                        let relay = if self.relay_addr.to_str().is_empty() {
+                            None
+                        } else {
+                            Some(crate::net_relay::Relay {
+                                addr: self.relay_addr.to_str().to_owned(),
+                                room: self.relay_room.to_str().to_owned(),
+                            })
+                        };
+
                         if create_server {
                             self.net_mode = NetMode::Server;
                             let e = client::FromClient::StartServer(client::StartServer {
                                 bind: "127.0.0.1:4567".to_owned(),
+                                relay: relay.clone(),
+                                net_sim: self.net_sim,
+                                rollback: self.rollback,
+                                host_slot: Some(0),
                             });
                             let _ = self.sender_from_client_to_manager.try_send(e);
                         }
@@ -514,6 +1462,17 @@ impl App {
                             self.net_mode = NetMode::Client;
                             let e = client::FromClient::StartClient(client::StartClient {
                                 bind: "127.0.0.1:4567".to_owned(),
+                                relay: relay.clone(),
+                                net_sim: self.net_sim,
+                            });
+                            let _ = self.sender_from_client_to_manager.try_send(e);
+                        }
+                        if create_spectator {
+                            self.net_mode = NetMode::Spectator;
+                            let e = client::FromClient::StartClient(client::StartClient {
+                                bind: "127.0.0.1:4567".to_owned(),
+                                relay: relay.clone(),
+                                net_sim: self.net_sim,
                             });
                             let _ = self.sender_from_client_to_manager.try_send(e);
                         }
@@ -541,302 +1500,502 @@ impl App {
             self.imgui_wrap
                 .platform
                 .prepare_render(&ui, &self.gpu.window);
+            if !ui.io().want_capture_mouse {
+                match self.game_state.cursor_mode {
+                    CursorMode::Hardware => {
+                        self.gpu.window.set_cursor_visible(true);
+                        self.gpu
+                            .window
+                            .set_cursor_icon(cursor_context.system_icon());
+                    }
+                    CursorMode::Software => self.gpu.window.set_cursor_visible(false),
+                }
+            }
         }
         self.profiler.mix("imgui_render", start.elapsed(), 20);
 
-        let frame = &self.gpu.swap_chain.get_next_texture();
-        let now = Instant::now();
-        // Pass
-        {
-            log::trace!("begin_render_pass");
-            let mut rpass = encoder_render.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[
-                    wgpu::RenderPassColorAttachmentDescriptor {
-                        attachment: &self.first_color_att_view,
-                        resolve_target: None,
-                        load_op: wgpu::LoadOp::Clear,
-                        store_op: wgpu::StoreOp::Store,
-                        clear_color: wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        },
-                    },
-                    wgpu::RenderPassColorAttachmentDescriptor {
-                        attachment: &self.position_att_view,
-                        resolve_target: None,
-                        load_op: wgpu::LoadOp::Clear,
-                        store_op: wgpu::StoreOp::Store,
-                        clear_color: wgpu::Color {
-                            r: -1.0,
-                            g: -1.0,
-                            b: -1.0,
-                            a: -1.0,
-                        },
-                    },
-                    wgpu::RenderPassColorAttachmentDescriptor {
-                        attachment: &self.normal_att_view,
-                        resolve_target: None,
-                        load_op: wgpu::LoadOp::Clear,
-                        store_op: wgpu::StoreOp::Store,
-                        clear_color: wgpu::Color {
-                            r: -1.0,
-                            g: -1.0,
-                            b: -1.0,
-                            a: -1.0,
-                        },
-                    },
-                ],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.forward_depth,
-                    depth_load_op: wgpu::LoadOp::Clear,
-                    depth_store_op: wgpu::StoreOp::Store,
-                    stencil_load_op: wgpu::LoadOp::Clear,
-                    stencil_store_op: wgpu::StoreOp::Store,
-                    clear_depth: 1.0,
-                    clear_stencil: 0,
-                }),
-            });
-
-            self.heightmap_gpu.render(&mut rpass, &self.bind_group);
-            for model_gpu_state in self.unit_part_gpu.states.iter_mut() {
-                match model_gpu_state {
-                    unit_part_gpu::ModelGpuState::Ready(model_gpu) => {
-                        model_gpu.render(&mut rpass, &self.bind_group);
+        let frame = self.gpu.swap_chain.get_next_texture();
+        let readback = if world {
+            let now = Instant::now();
+            self.shadow_map
+                .render(&mut encoder_render, &self.model_batch);
+            //Cpu encoding time of each pass, wgpu 0.4 exposes no timestamp queries for the gpu side
+            let frame_graph = &self.frame_graph;
+            for &pass in frame_graph.graph.order() {
+                let pass_start = Instant::now();
+                {
+                    profile_scope!(frame_graph.graph.name(pass));
+                    let mut rpass = frame_graph
+                        .graph
+                        .begin(pass, &mut encoder_render, &frame.view);
+                    let depth = frame_graph.depth_mode(pass);
+                    let oit = pass == frame_graph.transparent_oit;
+                    for (_, draw, pipeline_changed) in
+                        self.draw_list.layers(frame_graph.layers(pass))
+                    {
+                        if draw.recorded_in(frame_graph, pass) {
+                            record_draw!(self, &mut rpass, draw, pipeline_changed, depth, oit);
+                        }
                     }
-                    _ => {}
+                    if pass == frame_graph.ssr && frame_graph.use_ssr {
+                        self.ssr.render_trace(&mut rpass, &self.bind_group);
+                    } else if pass == frame_graph.ssr_composite && frame_graph.use_ssr {
+                        self.ssr.render_composite(&mut rpass);
+                    } else if pass == frame_graph.outline
+                        && self.game_state.render_style == RenderStyle::Toon
+                    {
+                        self.outline.render(&mut rpass, &self.bind_group);
+                    } else if pass == frame_graph.economy_overlay
+                        && self.game_state.economy_overlay
+                        && self.main_menu == MainMode::Play
+                    {
+                        self.economy_gpu
+                            .render_overlay(&mut rpass, &self.bind_group);
+                    } else if pass == frame_graph.heatmap
+                        && self.heatmap.shown.is_some()
+                        && self.main_menu == MainMode::Play
+                    {
+                        self.heatmap_gpu.render(&mut rpass, &self.bind_group);
+                    } else if pass == frame_graph.range_rings && self.main_menu == MainMode::Play {
+                        self.range_ring_gpu.render(&mut rpass, &self.bind_group);
+                    } else if pass == frame_graph.oit_composite && frame_graph.use_oit {
+                        self.oit_composite.render(&mut rpass);
+                    } else if pass == frame_graph.post {
+                        self.explosion_gpu.render(&mut rpass, &self.bind_group);
+                        self.postfx
+                            .render(&mut rpass, &self.gpu.device, &self.bind_group);
+                    } else if pass == frame_graph.dof && frame_graph.dof_quality != DofQuality::Off
+                    {
+                        self.dof.render(&mut rpass, &self.bind_group);
+                    } else if pass == frame_graph.fxaa && !frame_graph.use_taa {
+                        self.postfxaa
+                            .render(&mut rpass, &self.gpu.device, &self.bind_group);
+                    } else if pass == frame_graph.taa && frame_graph.use_taa {
+                        self.taa.render(&mut rpass, &self.bind_group);
+                    } else if pass == frame_graph.taa_copy && frame_graph.use_taa {
+                        self.taa_copy
+                            .render(&mut rpass, &self.gpu.device, &self.bind_group);
+                    } else if pass == frame_graph.grade {
+                        self.color_grading.render(&mut rpass);
+                    } else if pass == frame_graph.upscale {
+                        self.upscale.render(&mut rpass);
+                    } else if pass == frame_graph.copy {
+                        self.post_bicopy
+                            .render(&mut rpass, &self.gpu.device, &self.bind_group);
+                    }
+                }
+                //Between the pre-pass and the main pass, outside of the render passes
+                if pass == frame_graph.depth_prepass
+                    && frame_graph.use_depth_prepass
+                    && self.model_batch.occlusion_culling()
+                {
+                    self.hi_z.build(&mut encoder_render);
+                    self.model_batch
+                        .cull(&self.gpu.device, &mut encoder_render, &self.hi_z);
+                }
+                //Recording time on the cpu, wgpu 0.4 has no timestamp queries to time the gpu
+                self.profiler.mix(
+                    &format!("pass {} (cpu)", frame_graph.graph.name(pass)),
+                    pass_start.elapsed(),
+                    20,
+                );
+                if let Some(benchmark) = self.benchmark.as_mut() {
+                    benchmark.pass(frame_graph.graph.name(pass), pass_start.elapsed());
                 }
             }
-            self.kinematic_projectile_gpu
-                .render(&mut rpass, &self.bind_group);
-            self.arrow_gpu.render(&mut rpass, &self.bind_group);
-        }
 
-        //Transparent pass
-        {
-            log::trace!("begin_render_pass transparent");
-            let mut rpass = encoder_render.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &self.first_color_att_view,
-                    resolve_target: None,
-                    load_op: wgpu::LoadOp::Load,
-                    store_op: wgpu::StoreOp::Store,
-                    clear_color: wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
+            let render_pass_3d = now.elapsed();
+
+            self.profiler.mix("render_pass_3d", render_pass_3d, 20);
+
+            let cursor_sample_position = self
+                .gpu
+                .device
+                .create_buffer_mapped::<f32>(
+                    4,
+                    wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+                )
+                .finish();
+
+            let screen_center_sample_position = self
+                .gpu
+                .device
+                .create_buffer_mapped::<f32>(
+                    4,
+                    wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+                )
+                .finish();
+
+            encoder_render.copy_texture_to_buffer(
+                wgpu::TextureCopyView {
+                    texture: self.frame_graph.graph.texture_of(self.frame_graph.position),
+                    mip_level: 0,
+                    array_layer: 0,
+                    origin: wgpu::Origin3d {
+                        x: (mouse_pos[0].max(0.0) as u32).min(scene_size.0 - 1) as f32,
+                        y: (mouse_pos[1].max(0.0) as u32).min(scene_size.1 - 1) as f32,
+                        z: 0.0,
                     },
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.forward_depth,
-                    depth_load_op: wgpu::LoadOp::Load,
-                    depth_store_op: wgpu::StoreOp::Store,
-                    stencil_load_op: wgpu::LoadOp::Clear,
-                    stencil_store_op: wgpu::StoreOp::Store,
-                    clear_depth: 1.0,
-                    clear_stencil: 0,
-                }),
-            });
-
-            self.water_gpu.render(&mut rpass, &self.bind_group);
-        }
-
-        // Post pass
-        {
-            log::trace!("begin_post_render_pass");
-            let mut rpass = encoder_render.begin_render_pass(&wgpu::RenderPassDescriptor {
+                },
+                wgpu::BufferCopyView {
+                    buffer: &cursor_sample_position,
+                    offset: 0,
+                    row_pitch: 4 * 4,
+                    image_height: 1,
+                },
+                Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+            );
+
+            encoder_render.copy_texture_to_buffer(
+                wgpu::TextureCopyView {
+                    texture: self.frame_graph.graph.texture_of(self.frame_graph.position),
+                    mip_level: 0,
+                    array_layer: 0,
+                    origin: wgpu::Origin3d {
+                        x: (scene_size.0 / 2) as f32,
+                        y: (scene_size.1 / 2) as f32,
+                        z: 0.0,
+                    },
+                },
+                wgpu::BufferCopyView {
+                    buffer: &screen_center_sample_position,
+                    offset: 0,
+                    row_pitch: 4 * 4,
+                    image_height: 1,
+                },
+                Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth: 1,
+                },
+            );
+            Some((cursor_sample_position, screen_center_sample_position))
+        } else {
+            //Menus only clear the frame under imgui, no scene is updated nor drawn
+            encoder_render.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &self.first_color_att_view,
+                    attachment: &frame.view,
                     resolve_target: None,
-                    load_op: wgpu::LoadOp::Load,
+                    load_op: wgpu::LoadOp::Clear,
                     store_op: wgpu::StoreOp::Store,
-                    clear_color: wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    },
+                    clear_color: MENU_BACKGROUND,
                 }],
-
                 depth_stencil_attachment: None,
             });
+            None
+        };
+
+        self.imgui_wrap
+            .renderer
+            .render(ui, &self.gpu.device, &mut encoder_render, &frame.view)
+            .expect("Rendering failed");
+
+        self.staging_belt.finish(&self.gpu.device);
+        let start = Instant::now();
+        self.gpu.queue.submit(&[encoder_render.finish()]);
+        drop(frame);
+        self.profiler
+            .mix("device queue submit", start.elapsed(), 20);
+        crate::profiling::frame_mark();
 
-            self.explosion_gpu.render(&mut rpass, &self.bind_group);
-            self.postfx
-                .render(&mut rpass, &self.gpu.device, &self.bind_group);
+        if self.main_menu == MainMode::Play {
+            //Alt clicks are pings, not orders
+            if !self.handle_ping() {
+                self.handle_orders();
+            }
+            self.handle_alert_hotkey();
+            self.step_alliance();
+            self.step_autosave();
+        }
+        if let Some(path) = resume_path {
+            self.resume_autosave(&path);
+        }
+        if let Some(path) = replay_path {
+            self.start_replay(&path);
         }
 
-        //Post fxaa pass
-        {
-            log::trace!("begin_post_render_pass");
-            let mut rpass = encoder_render.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &self.secon_color_att_view,
-                    resolve_target: None,
-                    load_op: wgpu::LoadOp::Clear,
-                    store_op: wgpu::StoreOp::Store,
-                    clear_color: wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    },
-                }],
+        if settings_open {
+            let preview = self.settings.preview();
+            self.apply_settings(&preview);
+        }
 
-                depth_stencil_attachment: None,
-            });
+        self.input_state.update();
 
-            self.postfxaa
-                .render(&mut rpass, &self.gpu.device, &self.bind_group);
+        if let Some((cursor_sample_position, screen_center_sample_position)) = readback {
+            let tx = self.sender_to_client.clone();
+            cursor_sample_position.map_read_async(
+                0,
+                4 * 4,
+                move |e: BufferMapAsyncResult<&[f32]>| match e {
+                    Ok(e) => {
+                        log::trace!("BufferMapAsyncResult callback");
+                        let _ = tx.try_send(ToClient::MapReadAsyncMessage {
+                            vec: e.data.to_vec(),
+                            usage: "mouse_world_pos".to_owned(),
+                        });
+                    }
+                    Err(_) => {}
+                },
+            );
+
+            let tx = self.sender_to_client.clone();
+            screen_center_sample_position.map_read_async(
+                0,
+                4 * 4,
+                move |e: BufferMapAsyncResult<&[f32]>| match e {
+                    Ok(e) => {
+                        log::trace!("BufferMapAsyncResult callback");
+                        let _ = tx.try_send(ToClient::MapReadAsyncMessage {
+                            vec: e.data.to_vec(),
+                            usage: "screen_center_world_pos".to_owned(),
+                        });
+                    }
+                    Err(_) => {}
+                },
+            );
         }
+    }
 
-        //Custom Ui pass
-        {
-            log::trace!("begin curtom ui renderpass");
-            let mut rpass = encoder_render.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &self.secon_color_att_view,
-                    resolve_target: None,
-                    load_op: wgpu::LoadOp::Load,
-                    store_op: wgpu::StoreOp::Store,
-                    clear_color: wgpu::Color {
-                        r: 1.0,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    },
-                }],
+    ///Cursor in the pixels of the scene targets
+    fn scene_cursor(&self) -> [f32; 2] {
+        let scene_size = self.scene_size();
+        [
+            self.input_state.cursor_pos.0 as f32 * scene_size.0 as f32
+                / self.gpu.sc_desc.width as f32,
+            self.input_state.cursor_pos.1 as f32 * scene_size.1 as f32
+                / self.gpu.sc_desc.height as f32,
+        ]
+    }
 
-                depth_stencil_attachment: None,
-            });
+    ///Interpolation, hud and uploads of the states showing the scene
+    fn update_world(&mut self, sim_sec: f32, encoder_render: &mut wgpu::CommandEncoder) {
+        let shake = if self.main_menu == MainMode::Play && self.game_state.camera_shake {
+            camera::create_shake(
+                &self.game_state.position_smooth,
+                self.game_state.server_sec,
+                &self.game_state.active_effect_parts,
+            )
+        } else {
+            Matrix4::identity()
+        };
 
-            self.health_bar.render(&mut rpass, &self.bind_group);
-            self.unit_icon.render(&mut rpass, &self.bind_group);
-            self.line_gpu.render(&mut rpass, &self.bind_group);
-            self.cursor_icon.render(&mut rpass, &self.bind_group);
+        let view_proj = camera::create_view_proj(
+            self.gpu.sc_desc.width as f32 / self.gpu.sc_desc.height as f32,
+            self.game_state.near(),
+            &self.game_state.position_smooth,
+            &self.game_state.dir_smooth,
+            &shake,
+        );
+        if self.main_menu == MainMode::Play {
+            self.handle_play(sim_sec, encoder_render, &view_proj);
+            self.step_mission();
+            self.build_hud();
+        } else {
+            self.hud.clear();
         }
 
-        //Copy on frame view
-        {
-            log::trace!("copy on frame view render pass");
-            let mut rpass = encoder_render.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    resolve_target: None,
-                    load_op: wgpu::LoadOp::Load,
-                    store_op: wgpu::StoreOp::Store,
-                    clear_color: wgpu::Color {
-                        r: 1.0,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    },
-                }],
+        self.upload_to_gpu(&view_proj, encoder_render);
 
-                depth_stencil_attachment: None,
-            });
+        let heightmap_gpu_step_duration = time(|| {
+            self.heightmap_gpu
+                .step(&self.gpu.device, encoder_render, &mut self.staging_belt);
+        });
 
-            self.post_bicopy
-                .render(&mut rpass, &self.gpu.device, &self.bind_group);
-        }
+        self.profiler
+            .mix("heightmap_gpu_step", heightmap_gpu_step_duration, 20);
 
-        let render_pass_3d = now.elapsed();
+        //Window pixels to the pixels of the scene targets
+        let scene_size = self.scene_size();
+        let to_scene_x = scene_size.0 as f32 / self.gpu.sc_desc.width as f32;
+        let to_scene_y = scene_size.1 as f32 / self.gpu.sc_desc.height as f32;
+        let mouse_pos = self.scene_cursor();
+        let mut start_drag = mouse_pos;
 
-        self.profiler.mix("render_pass_3d", render_pass_3d, 20);
+        if let MainMode::Play = self.main_menu {
+            if let input_state::Drag::Dragging { x0, y0, .. } = self.input_state.drag {
+                start_drag = [x0 as f32 * to_scene_x, y0 as f32 * to_scene_y];
+            }
+        }
 
-        self.imgui_wrap
-            .renderer
-            .render(ui, &self.gpu.device, &mut encoder_render, &frame.view)
-            .expect("Rendering failed");
+        let radius = if self.main_menu == MainMode::MapEditor {
+            self.game_state.heightmap_editor.pen_radius as f32
+        } else {
+            0.0
+        };
 
-        let cursor_sample_position = self
-            .gpu
-            .device
-            .create_buffer_mapped::<f32>(
-                4,
-                wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+        let jitter = if self.frame_graph.use_taa {
+            camera::taa_jitter(self.frame_count as u32, scene_size)
+        } else {
+            [0.0; 4]
+        };
+        let mut camera_uniform = camera::CameraUniform {
+            mouse_pos,
+            resolution: [scene_size.0 as f32, scene_size.1 as f32],
+            inv_resolution: [1.0 / scene_size.0 as f32, 1.0 / scene_size.1 as f32],
+            start_drag,
+            pen_radius: radius,
+            pen_strength: self.game_state.heightmap_editor.pen_strength as f32,
+            hmap_size: [
+                self.heightmap_gpu.phy.width as f32,
+                self.heightmap_gpu.phy.height as f32,
+            ],
+            time: self.game_state.start_time.elapsed().as_secs_f32(),
+            toon_bands: self.game_state.render_style.bands(),
+            render_scale: to_scene_y,
+            jitter,
+            ..camera::create_camera_uniform(
+                (self.gpu.sc_desc.width, self.gpu.sc_desc.height),
+                self.game_state.near(),
+                &self.game_state.position_smooth,
+                &self.game_state.dir_smooth,
+                &shake,
             )
-            .finish();
+        };
+        camera_uniform.last_view_proj = self.last_view_proj.unwrap_or(camera_uniform.view_proj);
+        self.last_view_proj = Some(camera_uniform.view_proj);
+        self.ub_camera.update(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            &camera_uniform,
+        );
 
-        let screen_center_sample_position = self
-            .gpu
-            .device
-            .create_buffer_mapped::<f32>(
-                4,
-                wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
-            )
-            .finish();
-
-        encoder_render.copy_texture_to_buffer(
-            wgpu::TextureCopyView {
-                texture: &self.position_att,
-                mip_level: 0,
-                array_layer: 0,
-                origin: wgpu::Origin3d {
-                    x: self
-                        .input_state
-                        .cursor_pos
-                        .0
-                        .max(0)
-                        .min(self.gpu.sc_desc.width - 1) as f32,
-                    y: self
-                        .input_state
-                        .cursor_pos
-                        .1
-                        .max(0)
-                        .min(self.gpu.sc_desc.height - 1) as f32,
-                    z: 0.0,
-                },
-            },
-            wgpu::BufferCopyView {
-                buffer: &cursor_sample_position,
-                offset: 0,
-                row_pitch: 4 * 4,
-                image_height: 1,
-            },
-            Extent3d {
-                width: 1,
-                height: 1,
-                depth: 1,
-            },
+        self.shadow_map.update(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            &(shake
+                * camera::create_view(
+                    &self.game_state.position_smooth,
+                    &self.game_state.dir_smooth,
+                )),
+            self.gpu.sc_desc.width as f32 / self.gpu.sc_desc.height as f32,
+            camera::FOVY,
+            self.game_state.near(),
+        );
+        self.environment.update(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            &self.heightmap_gpu.phy.data.sky,
+        );
+        self.color_grading.update(
+            &self.gpu.device,
+            encoder_render,
+            &self.heightmap_gpu.phy.data.lut,
+        );
+        self.economy_gpu.update(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            &self.heightmap_gpu.phy,
+        );
+        if let Some((kind, cells)) = self.heatmap.grid(&self.game_state, &self.heightmap_gpu.phy) {
+            let phy = &self.heightmap_gpu.phy;
+            self.heatmap_gpu.update(
+                &self.gpu.device,
+                encoder_render,
+                &mut self.staging_belt,
+                (phy.width as f32, phy.height as f32),
+                kind,
+                &cells,
+            );
+        }
+        self.dof.update(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            self.frame_graph.dof_quality,
+            &self.game_state.position_smooth,
+            self.game_state.screen_center_world_pos,
+            scene_size.1,
         );
+        self.upscale.update(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            self.frame_graph.upscale_mode,
+        );
+        if self.frame_graph.aa_source() != self.aa_source {
+            self.aa_source = self.frame_graph.aa_source();
+            self.postfxaa
+                .update_last_pass_view(&self.gpu.device, self.frame_graph.view(self.aa_source));
+            self.taa.update_views(
+                &self.gpu.device,
+                self.frame_graph.view(self.aa_source),
+                self.frame_graph.view(self.frame_graph.velocity),
+                self.frame_graph.view(self.frame_graph.taa_history),
+            );
+        }
 
-        encoder_render.copy_texture_to_buffer(
-            wgpu::TextureCopyView {
-                texture: &self.position_att,
-                mip_level: 0,
-                array_layer: 0,
-                origin: wgpu::Origin3d {
-                    x: self.gpu.sc_desc.width as f32 / 2.0,
-                    y: self.gpu.sc_desc.height as f32 / 2.0,
-                    z: 0.0,
-                },
-            },
-            wgpu::BufferCopyView {
-                buffer: &screen_center_sample_position,
-                offset: 0,
-                row_pitch: 4 * 4,
-                image_height: 1,
-            },
-            Extent3d {
-                width: 1,
-                height: 1,
-                depth: 1,
-            },
+        self.heightmap_gpu.update_uniform(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            self.game_state.position_smooth.x,
+            self.game_state.position_smooth.y,
         );
 
-        let start = Instant::now();
-        self.gpu.queue.submit(&[encoder_render.finish()]);
-        self.profiler
-            .mix("device queue submit", start.elapsed(), 20);
+        let weather = if self.main_menu.has_camera() {
+            self.heightmap_gpu.phy.data.weather
+        } else {
+            crate::weather::Weather::clear()
+        };
+        let point_lights = if self.main_menu == MainMode::Play {
+            self.game_state.point_lights()
+        } else {
+            Vec::new()
+        };
+        self.point_light_gpu.update(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            &self.game_state.position_smooth,
+            &point_lights,
+        );
+
+        let server_sec = self.game_state.server_sec;
+        let shield_impacts: Vec<_> = self
+            .game_state
+            .explosions
+            .iter()
+            .map(|e| (e.position, server_sec - e.born_sec))
+            .collect();
+        self.shield_gpu.update_impacts(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            &shield_impacts,
+        );
+
+        self.weather_gpu.update_uniform(
+            &self.gpu.device,
+            encoder_render,
+            &mut self.staging_belt,
+            &self.game_state.position_smooth,
+            self.game_state.start_time.elapsed().as_secs_f32(),
+            &weather,
+        );
+
+        self.fill_draw_list();
+    }
 
-        //Handle right click
-        if let (true, Some(id), Some(mouse_world_pos)) = (
+    ///Right click orders of the player, in Play
+    fn handle_orders(&mut self) {
+        let gate = self.input_gate();
+        let allowed = match self.game_state.uitool {
+            UiTool::Spawn(_) => gate.build,
+            _ => gate.order,
+        };
+        if let (true, true, Some(id), Some(mouse_world_pos)) = (
             self.input_state
                 .mouse_trigger
                 .contains(&winit::event::MouseButton::Right),
+            allowed,
             self.game_state.my_player_id,
             self.game_state.mouse_world_pos,
         ) {
@@ -848,28 +2007,150 @@ impl App {
                 }],
 
                 UiTool::Spawn(id_to_con) => {
+                    let can_be_placed = self
+                        .game_state
+                        .frame_zero
+                        .bot_defs
+                        .get(&id_to_con)
+                        .map(|botdef| {
+                            let (x, y) = botdef.snap(mouse_world_pos.x, mouse_world_pos.y);
+                            botdef.can_be_placed(&self.heightmap_gpu.phy, x, y)
+                        })
+                        .unwrap_or(false);
+
+                    if can_be_placed {
+                        self.game_state.uitool = UiTool::None;
+
+                        vec![FrameEventFromPlayer::ConOrder {
+                            id,
+                            selected: self.game_state.selected.clone(),
+                            mouse_world_pos,
+                            botdef_id: id_to_con,
+                        }]
+                    } else {
+                        //Keep the tool so the player can try another spot
+                        log::info!("Can't build here");
+                        vec![]
+                    }
+                }
+
+                //Given on release by handle_area_order
+                UiTool::Repair | UiTool::Reclaim => {
+                    self.game_state.area_center = Some(mouse_world_pos);
+                    vec![]
+                }
+
+                UiTool::Patrol => {
+                    //Shift keeps the tool to add more waypoints
+                    let append = self
+                        .input_state
+                        .key_pressed
+                        .contains(&winit::event::VirtualKeyCode::LShift);
+                    if !append {
+                        self.game_state.uitool = UiTool::None;
+                    }
+
+                    vec![FrameEventFromPlayer::PatrolOrder {
+                        id,
+                        selected: self.game_state.selected.clone(),
+                        mouse_world_pos,
+                        append,
+                    }]
+                }
+
+                UiTool::Guard => {
+                    self.game_state.uitool = UiTool::None;
+
+                    let frame_zero = &self.game_state.frame_zero;
+                    let my_team = self.game_state.my_player().map(|me| me.team);
+                    let under = self
+                        .game_state
+                        .under_mouse
+                        .and_then(|under| frame_zero.kbots.get(&under))
+                        .filter(|kbot| Some(kbot.team) == my_team);
+                    match under {
+                        Some(kbot) => vec![FrameEventFromPlayer::GuardOrder {
+                            id,
+                            selected: self.game_state.selected.clone(),
+                            to_guard: kbot.id,
+                        }],
+                        None => {
+                            log::info!("Can only guard units of the team");
+                            vec![]
+                        }
+                    }
+                }
+
+                UiTool::Load => {
                     self.game_state.uitool = UiTool::None;
 
-                    vec![FrameEventFromPlayer::ConOrder {
+                    let frame_zero = &self.game_state.frame_zero;
+                    let can_be_loaded = self
+                        .game_state
+                        .under_mouse
+                        .and_then(|under| frame_zero.kbots.get(&under))
+                        .and_then(|kbot| frame_zero.bot_defs.get(&kbot.botdef_id))
+                        .map(|botdef| botdef.can_be_transported())
+                        .unwrap_or(false);
+
+                    match self.game_state.under_mouse {
+                        Some(under) if can_be_loaded => vec![FrameEventFromPlayer::LoadOrder {
+                            id,
+                            selected: self.game_state.selected.clone(),
+                            to_load: under,
+                        }],
+                        _ => {
+                            log::info!("Can't load this");
+                            vec![]
+                        }
+                    }
+                }
+
+                UiTool::Nuke => {
+                    self.game_state.uitool = UiTool::None;
+
+                    vec![FrameEventFromPlayer::NukeOrder {
                         id,
                         selected: self.game_state.selected.clone(),
                         mouse_world_pos,
-                        botdef_id: id_to_con,
                     }]
                 }
 
-                UiTool::Repair => {
+                UiTool::DGun => {
                     self.game_state.uitool = UiTool::None;
 
-                    if let Some(under) = self.game_state.under_mouse {
-                        vec![FrameEventFromPlayer::RepairOrder {
+                    vec![FrameEventFromPlayer::DGunOrder {
+                        id,
+                        selected: self.game_state.selected.clone(),
+                        mouse_world_pos,
+                    }]
+                }
+
+                UiTool::Ferry => match self.game_state.ferry_pickup.take() {
+                    None => {
+                        self.game_state.ferry_pickup = Some(mouse_world_pos);
+                        vec![]
+                    }
+                    Some(pickup) => {
+                        self.game_state.uitool = UiTool::None;
+
+                        vec![FrameEventFromPlayer::FerryOrder {
                             id,
                             selected: self.game_state.selected.clone(),
-                            to_repair: under,
+                            pickup,
+                            dropoff: mouse_world_pos,
                         }]
-                    } else {
-                        vec![]
                     }
+                },
+
+                UiTool::Unload => {
+                    self.game_state.uitool = UiTool::None;
+
+                    vec![FrameEventFromPlayer::UnloadOrder {
+                        id,
+                        selected: self.game_state.selected.clone(),
+                        mouse_world_pos,
+                    }]
                 }
                 _ => vec![],
             };
@@ -883,37 +2164,6 @@ impl App {
                     .try_send(client::FromClient::PlayerInput(order));
             }
         }
-
-        self.input_state.update();
-
-        let tx = self.sender_to_client.clone();
-        cursor_sample_position.map_read_async(0, 4 * 4, move |e: BufferMapAsyncResult<&[f32]>| {
-            match e {
-                Ok(e) => {
-                    log::trace!("BufferMapAsyncResult callback");
-                    let _ = tx.try_send(ToClient::MapReadAsyncMessage {
-                        vec: e.data.to_vec(),
-                        usage: "mouse_world_pos".to_owned(),
-                    });
-                }
-                Err(_) => {}
-            }
-        });
-
-        let tx = self.sender_to_client.clone();
-        screen_center_sample_position.map_read_async(
-            0,
-            4 * 4,
-            move |e: BufferMapAsyncResult<&[f32]>| match e {
-                Ok(e) => {
-                    log::trace!("BufferMapAsyncResult callback");
-                    let _ = tx.try_send(ToClient::MapReadAsyncMessage {
-                        vec: e.data.to_vec(),
-                        usage: "screen_center_world_pos".to_owned(),
-                    });
-                }
-                Err(_) => {}
-            },
-        );
+        self.handle_area_order();
     }
 }
@@ -0,0 +1,322 @@
+use super::client::*;
+use super::cursor::CursorMode;
+use crate::gpu_obj::dof::DofQuality;
+use crate::gpu_obj::upscale::Upscale;
+use crate::*;
+use imgui::*;
+use serde::{Deserialize, Serialize};
+use spin_sleep::LoopHelper;
+
+///Options of the settings screen, kept between sessions
+pub const SETTINGS_PATH: &str = "settings.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Graphics {
+    pub fps: u64,
+    pub render_scale: f32,
+    pub upscale: Upscale,
+    pub use_taa: bool,
+    pub use_ssr: bool,
+    pub use_oit: bool,
+    pub dof_quality: DofQuality,
+    pub impostor_distance: f32,
+    pub unit_icon_distance: f32,
+}
+
+impl Default for Graphics {
+    fn default() -> Self {
+        Graphics {
+            fps: 144,
+            render_scale: 1.0,
+            upscale: Upscale::Sharpen,
+            use_taa: false,
+            use_ssr: true,
+            use_oit: false,
+            dof_quality: DofQuality::Off,
+            impostor_distance: 100.0,
+            unit_icon_distance: 200.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Input {
+    pub cursor_mode: CursorMode,
+    pub camera_speed: f32,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input {
+            cursor_mode: CursorMode::Software,
+            camera_speed: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Gameplay {
+    pub camera_shake: bool,
+    pub ui_scale: f32,
+    ///Game seconds between autosaves, 0 to disable them
+    pub autosave_interval_sec: u32,
+    ///Autosaves kept on disk, the oldest are deleted
+    pub autosave_keep: u32,
+}
+
+impl Default for Gameplay {
+    fn default() -> Self {
+        Gameplay {
+            camera_shake: true,
+            ui_scale: 1.0,
+            autosave_interval_sec: 60,
+            autosave_keep: 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub graphics: Graphics,
+    pub input: Input,
+    pub gameplay: Gameplay,
+}
+
+impl Settings {
+    ///Defaults on the first run
+    pub fn open(path: &str) -> Self {
+        platform::read_json(path).unwrap_or_else(|e| {
+            log::info!("No settings read from {}: {}", path, e);
+            Settings::default()
+        })
+    }
+
+    pub fn save(&self, path: &str) {
+        if let Err(e) = platform::write_json(path, self) {
+            log::error!("Can't write settings {}: {}", path, e);
+        }
+    }
+
+    ///Written in the crash reports from now on
+    fn report(&self) {
+        crate::crash::set_settings(serde_json::to_string_pretty(self).unwrap_or_default());
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tab {
+    Graphics,
+    Audio,
+    Input,
+    Gameplay,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 4] = [Tab::Graphics, Tab::Audio, Tab::Input, Tab::Gameplay];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tab::Graphics => "Graphics",
+            Tab::Audio => "Audio",
+            Tab::Input => "Input",
+            Tab::Gameplay => "Gameplay",
+        }
+    }
+}
+
+enum SettingsChoice {
+    Apply,
+    Revert,
+    Close,
+}
+
+///Settings screen, edits a copy of the applied settings
+pub struct SettingsUi {
+    pub open: bool,
+    pub tab: Tab,
+    ///Saved and in use, restored by Revert
+    pub applied: Settings,
+    ///Graphics are previewed live, the rest waits for Apply
+    pub edit: Settings,
+}
+
+impl SettingsUi {
+    pub fn new() -> Self {
+        let applied = Settings::open(SETTINGS_PATH);
+        applied.report();
+        SettingsUi {
+            open: false,
+            tab: Tab::Graphics,
+            edit: applied.clone(),
+            applied,
+        }
+    }
+
+    pub fn show(&mut self) {
+        self.open = true;
+        self.edit = self.applied.clone();
+    }
+
+    ///Tabs, then Apply saves, Revert and Close drop the edits
+    pub fn draw(&mut self, ui: &Ui, width: f32, height: f32) {
+        if !self.open {
+            return;
+        }
+        let tab = &mut self.tab;
+        let edit = &mut self.edit;
+        let changed = *edit != self.applied;
+        let mut open = true;
+        let mut choice = None;
+        imgui::Window::new(im_str!("Settings"))
+            .size([420.0, 360.0], imgui::Condition::FirstUseEver)
+            .position(
+                [(width - 420.0) / 2.0, (height - 360.0) / 2.0],
+                imgui::Condition::FirstUseEver,
+            )
+            .collapsible(false)
+            .opened(&mut open)
+            .build(ui, || {
+                for (i, t) in Tab::ALL.iter().enumerate() {
+                    if i > 0 {
+                        ui.same_line(0.0);
+                    }
+                    ui.radio_button(&im_str!("{}", t.name()), tab, *t);
+                }
+                ui.separator();
+                match tab {
+                    Tab::Graphics => {
+                        let graphics = &mut edit.graphics;
+                        imgui::Slider::new(im_str!("fps cap"), 1..=480)
+                            .build(ui, &mut graphics.fps);
+                        imgui::Slider::new(im_str!("render scale"), 0.5..=2.0)
+                            .build(ui, &mut graphics.render_scale);
+                        ui.text(im_str!("upscale"));
+                        for &mode in Upscale::ALL.iter() {
+                            ui.same_line(0.0);
+                            ui.radio_button(
+                                &im_str!("{}", mode.name()),
+                                &mut graphics.upscale,
+                                mode,
+                            );
+                        }
+                        ui.checkbox(im_str!("temporal anti-aliasing"), &mut graphics.use_taa);
+                        ui.checkbox(im_str!("screen-space reflections"), &mut graphics.use_ssr);
+                        ui.checkbox(
+                            im_str!("order-independent transparency"),
+                            &mut graphics.use_oit,
+                        );
+                        ui.text(im_str!("tilt-shift depth of field"));
+                        for &quality in DofQuality::ALL.iter() {
+                            ui.same_line(0.0);
+                            ui.radio_button(
+                                &im_str!("{}", quality.name()),
+                                &mut graphics.dof_quality,
+                                quality,
+                            );
+                        }
+                        imgui::Slider::new(im_str!("impostor distance"), 10.0..=5000.0)
+                            .build(ui, &mut graphics.impostor_distance);
+                        imgui::Slider::new(im_str!("icon distance"), 10.0..=5000.0)
+                            .build(ui, &mut graphics.unit_icon_distance);
+                    }
+                    Tab::Audio => {
+                        ui.text_disabled(im_str!("No sound output yet"));
+                    }
+                    Tab::Input => {
+                        let input = &mut edit.input;
+                        ui.text(im_str!("cursor"));
+                        for &mode in CursorMode::ALL.iter() {
+                            ui.same_line(0.0);
+                            ui.radio_button(
+                                &im_str!("{}", mode.name()),
+                                &mut input.cursor_mode,
+                                mode,
+                            );
+                        }
+                        imgui::Slider::new(im_str!("camera speed"), 0.25..=4.0)
+                            .build(ui, &mut input.camera_speed);
+                    }
+                    Tab::Gameplay => {
+                        let gameplay = &mut edit.gameplay;
+                        ui.checkbox(im_str!("camera shake"), &mut gameplay.camera_shake);
+                        imgui::Slider::new(im_str!("ui scale"), 0.5..=2.0)
+                            .build(ui, &mut gameplay.ui_scale);
+                        imgui::Slider::new(im_str!("autosave every (s, 0 = off)"), 0..=600)
+                            .build(ui, &mut gameplay.autosave_interval_sec);
+                        imgui::Slider::new(im_str!("autosaves kept"), 1..=20)
+                            .build(ui, &mut gameplay.autosave_keep);
+                    }
+                }
+                ui.separator();
+                if ui.button(im_str!("Apply"), [100.0, 30.0]) && changed {
+                    choice = Some(SettingsChoice::Apply);
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Revert"), [100.0, 30.0]) {
+                    choice = Some(SettingsChoice::Revert);
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Close"), [100.0, 30.0]) {
+                    choice = Some(SettingsChoice::Close);
+                }
+                if changed {
+                    ui.text_disabled(im_str!("Unapplied changes are reverted on close"));
+                }
+            });
+        if !open {
+            choice = Some(SettingsChoice::Close);
+        }
+
+        match choice {
+            Some(SettingsChoice::Apply) => {
+                self.applied = self.edit.clone();
+                self.applied.save(SETTINGS_PATH);
+                self.applied.report();
+            }
+            Some(SettingsChoice::Revert) => {
+                self.edit = self.applied.clone();
+            }
+            Some(SettingsChoice::Close) => {
+                self.edit = self.applied.clone();
+                self.open = false;
+            }
+            None => {}
+        }
+    }
+
+    ///Edited graphics over the applied rest
+    pub fn preview(&self) -> Settings {
+        Settings {
+            graphics: self.edit.graphics.clone(),
+            ..self.applied.clone()
+        }
+    }
+}
+
+impl App {
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let graphics = &settings.graphics;
+        if self.game_state.fps != graphics.fps {
+            self.game_state.fps = graphics.fps;
+            self.loop_helper = LoopHelper::builder().build_with_target_rate(graphics.fps as f64);
+        }
+        self.frame_graph.render_scale = graphics.render_scale;
+        self.frame_graph.upscale_mode = graphics.upscale;
+        self.frame_graph.use_taa = graphics.use_taa;
+        self.frame_graph.use_ssr = graphics.use_ssr;
+        self.frame_graph.use_oit = graphics.use_oit;
+        self.frame_graph.dof_quality = graphics.dof_quality;
+        self.game_state.impostor_distance = graphics.impostor_distance;
+        self.game_state.unit_icon_distance = graphics.unit_icon_distance;
+
+        self.game_state.cursor_mode = settings.input.cursor_mode;
+        self.game_state.camera_speed = settings.input.camera_speed;
+
+        self.game_state.camera_shake = settings.gameplay.camera_shake;
+        self.game_state.ui_scale = settings.gameplay.ui_scale;
+    }
+}
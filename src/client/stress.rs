@@ -0,0 +1,118 @@
+use super::client::*;
+use super::game_state;
+use crate::botdef::BotDef;
+use crate::frame::{Formation, FrameEventFromPlayer};
+use crate::utils::Id;
+use imgui::*;
+
+///Debug window spawning units and projectiles to measure how the game scales, offline only
+pub struct StressUi {
+    pub open: bool,
+    pub thousands: f32,
+    pub formation: Formation,
+    ///None until a botdef of the frame is picked
+    pub botdef_id: Option<Id<BotDef>>,
+    ///Spawned for an enemy player instead of ours
+    pub enemy: bool,
+    pub projectiles_per_sec: i32,
+}
+
+impl StressUi {
+    pub fn new() -> Self {
+        StressUi {
+            open: false,
+            thousands: 1.0,
+            formation: Formation::Square,
+            botdef_id: None,
+            enemy: false,
+            projectiles_per_sec: 0,
+        }
+    }
+}
+
+impl App {
+    ///Events of the buttons pressed, spawned at the center of the screen
+    pub fn draw_stress_ui(
+        ui: &Ui,
+        stress: &mut StressUi,
+        game_state: &game_state::State,
+    ) -> Vec<FrameEventFromPlayer> {
+        let mut events = Vec::new();
+        let frame_zero = &game_state.frame_zero;
+        let me = match game_state.my_player() {
+            Some(me) => me,
+            None => return events,
+        };
+        if stress.botdef_id.is_none() {
+            stress.botdef_id = frame_zero.bot_defs.keys().next().copied();
+        }
+
+        imgui::Window::new(im_str!("Stress test (F9)"))
+            .size([320.0, 300.0], imgui::Condition::FirstUseEver)
+            .position([3.0, 450.0], imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(im_str!(
+                    "{} units, {} projectiles",
+                    frame_zero.kbots.len(),
+                    frame_zero.kinematic_projectiles.len()
+                ));
+                ui.separator();
+                for botdef in frame_zero.bot_defs.values() {
+                    let name = std::path::Path::new(&botdef.file_path)
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    ui.radio_button(
+                        &im_str!("{}##{}", name, botdef.id),
+                        &mut stress.botdef_id,
+                        Some(botdef.id),
+                    );
+                }
+                ui.text(im_str!("formation"));
+                for &formation in Formation::ALL.iter() {
+                    ui.same_line(0.0);
+                    ui.radio_button(
+                        &im_str!("{}", formation.name()),
+                        &mut stress.formation,
+                        formation,
+                    );
+                }
+                imgui::Slider::new(im_str!("thousands"), 0.1..=20.0)
+                    .build(ui, &mut stress.thousands);
+                ui.checkbox(im_str!("for the enemy"), &mut stress.enemy);
+                if ui.button(im_str!("Spawn at screen center"), [200.0, 20.0]) {
+                    let owner = if stress.enemy {
+                        frame_zero
+                            .players
+                            .values()
+                            .find(|p| p.team != me.team)
+                            .map(|p| p.id)
+                    } else {
+                        Some(me.id)
+                    };
+                    if let (Some(id), Some(botdef_id), Some(center)) =
+                        (owner, stress.botdef_id, game_state.screen_center_world_pos)
+                    {
+                        events.push(FrameEventFromPlayer::StressSpawn {
+                            id,
+                            botdef_id,
+                            center,
+                            count: (stress.thousands * 1000.0) as u32,
+                            formation: stress.formation,
+                        });
+                    }
+                }
+                ui.separator();
+                let before = stress.projectiles_per_sec;
+                imgui::Slider::new(im_str!("projectiles/s"), 0..=10000)
+                    .build(ui, &mut stress.projectiles_per_sec);
+                if stress.projectiles_per_sec != before {
+                    events.push(FrameEventFromPlayer::StressProjectiles {
+                        id: me.id,
+                        per_sec: stress.projectiles_per_sec.max(0) as u32,
+                    });
+                }
+            });
+        events
+    }
+}
@@ -0,0 +1,252 @@
+use super::client::*;
+use super::game_state;
+use crate::determinism;
+use crate::frame::{Frame, FrameEventFromPlayer};
+use crate::utils::Id;
+use crate::*;
+use fnv::FnvHashSet;
+use imgui::*;
+use std::collections::VecDeque;
+
+///Frames kept by the time travel debugger, 30 seconds of game
+pub const RING_FRAMES: usize = 300;
+///Lines of a diff shown, the rest is counted
+const MAX_DIFF_LINES: usize = 200;
+
+///Debug window going back through the last frames received, tick by tick, offline only
+pub struct TimeTravelUi {
+    pub open: bool,
+    ///Oldest first, filled while the window is open
+    frames: VecDeque<Frame>,
+    ///Index in frames of the tick shown, None follows the game
+    cursor: Option<usize>,
+    ///Number of the tick compared with the one shown
+    diff_with: i32,
+    diff: Vec<String>,
+}
+
+impl TimeTravelUi {
+    pub fn new() -> Self {
+        TimeTravelUi {
+            open: false,
+            frames: VecDeque::with_capacity(RING_FRAMES),
+            cursor: None,
+            diff_with: 0,
+            diff: Vec::new(),
+        }
+    }
+
+    ///A new frame goes back to following the game
+    pub fn record(&mut self, frame: &Frame) {
+        if self.frames.len() == RING_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame.clone());
+        self.cursor = None;
+    }
+
+    pub fn shown_number(&self) -> Option<i32> {
+        self.shown_ref().map(|frame| frame.number)
+    }
+
+    ///Frame to show after the cursor moved. The craters dug since stay on the terrain
+    pub fn shown(&self) -> Option<Frame> {
+        self.shown_ref().cloned()
+    }
+
+    fn shown_ref(&self) -> Option<&Frame> {
+        match self.cursor {
+            Some(i) => self.frames.get(i),
+            None => self.frames.back(),
+        }
+    }
+
+    fn frame(&self, number: i32) -> Option<&Frame> {
+        self.frames.iter().find(|f| f.number == number)
+    }
+}
+
+///Fields of a value that changed between two ticks, as path: before -> after
+fn diff_values(
+    path: &str,
+    before: &serde_json::Value,
+    after: &serde_json::Value,
+    lines: &mut Vec<String>,
+) {
+    use serde_json::Value;
+    match (before, after) {
+        (Value::Object(b), Value::Object(a)) => {
+            let keys: FnvHashSet<&String> = b.keys().chain(a.keys()).collect();
+            let mut keys: Vec<_> = keys.into_iter().collect();
+            keys.sort();
+            for key in keys {
+                let path = format!("{}.{}", path, key);
+                match (b.get(key), a.get(key)) {
+                    (Some(b), Some(a)) => diff_values(&path, b, a, lines),
+                    (b, a) => lines.push(format!("{}: {:?} -> {:?}", path, b, a)),
+                }
+            }
+        }
+        (b, a) if b != a => lines.push(format!("{}: {} -> {}", path, b, a)),
+        _ => {}
+    }
+}
+
+fn json<T: serde::Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+///Units, players and projectiles that differ between before and after
+fn diff_frames(before: &Frame, after: &Frame) -> Vec<String> {
+    let mut lines = vec![format!(
+        "checksum {:016x} -> {:016x}",
+        determinism::checksum(before),
+        determinism::checksum(after)
+    )];
+    let mut ids: Vec<_> = before.kbots.keys().chain(after.kbots.keys()).collect();
+    ids.sort_by_key(|id| id.value);
+    ids.dedup();
+    for id in ids {
+        match (before.kbots.get(id), after.kbots.get(id)) {
+            (Some(b), Some(a)) => {
+                diff_values(&format!("unit {}", id), &json(b), &json(a), &mut lines)
+            }
+            (Some(_), None) => lines.push(format!("unit {} removed", id)),
+            (None, Some(_)) => lines.push(format!("unit {} added", id)),
+            (None, None) => {}
+        }
+    }
+    for (id, player) in after.players.iter() {
+        if let Some(b) = before.players.get(id) {
+            diff_values(
+                &format!("player {} metal", id),
+                &json(&b.metal),
+                &json(&player.metal),
+                &mut lines,
+            );
+            diff_values(
+                &format!("player {} energy", id),
+                &json(&b.energy),
+                &json(&player.energy),
+                &mut lines,
+            );
+        }
+    }
+    lines.push(format!(
+        "projectiles {} -> {}, missiles {} -> {}",
+        before.kinematic_projectiles.len(),
+        after.kinematic_projectiles.len(),
+        before.missiles.len(),
+        after.missiles.len()
+    ));
+    lines
+}
+
+impl App {
+    ///Messages for the manager, the caller shows time_travel.shown() when the tick changed
+    pub fn draw_time_travel_ui(
+        ui: &Ui,
+        time_travel: &mut TimeTravelUi,
+        game_state: &game_state::State,
+        global_info: Option<manager::GlobalInfo>,
+    ) -> Vec<client::FromClient> {
+        let mut messages = Vec::new();
+        let game_speed = global_info.map(|info| info.manager.game_speed);
+        let paused = game_speed.map(|speed| speed.paused).unwrap_or(false);
+        let pause = |paused: bool| {
+            //Offline the vote is applied whoever the player is
+            client::FromClient::PlayerInput(FrameEventFromPlayer::SpeedVote {
+                id: Id::new(0),
+                speed: game_speed.map(|speed| speed.speed).unwrap_or(1.0),
+                paused,
+            })
+        };
+        let last = time_travel.frames.len().saturating_sub(1);
+        imgui::Window::new(im_str!("Time travel (F8)"))
+            .size([420.0, 500.0], imgui::Condition::FirstUseEver)
+            .position([3.0, 250.0], imgui::Condition::FirstUseEver)
+            .build(ui, || {
+                let (oldest, newest) = match (time_travel.frames.front(), time_travel.frames.back())
+                {
+                    (Some(oldest), Some(newest)) => (oldest.number, newest.number),
+                    _ => {
+                        ui.text(im_str!("Recording, no frame yet"));
+                        return;
+                    }
+                };
+                let cursor = time_travel.cursor.unwrap_or(last);
+                let shown = &time_travel.frames[cursor];
+                ui.text(im_str!("tick {} of {}..{}", shown.number, oldest, newest));
+                if ui.small_button(im_str!("<<")) {
+                    time_travel.cursor = Some(0);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("< step")) {
+                    time_travel.cursor = Some(cursor.saturating_sub(1));
+                }
+                ui.same_line(0.0);
+                //Past the newest frame, the simulation computes one more tick
+                if ui.small_button(im_str!("step >")) {
+                    if cursor < last {
+                        time_travel.cursor = Some(cursor + 1);
+                    } else {
+                        messages.push(client::FromClient::StepFrame);
+                    }
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!(">>")) {
+                    time_travel.cursor = None;
+                }
+                ui.same_line(0.0);
+                if ui.small_button(if paused {
+                    im_str!("Resume")
+                } else {
+                    im_str!("Pause")
+                }) {
+                    messages.push(pause(!paused));
+                }
+                //Going back stops the game, it would bring us back to the present
+                if time_travel.cursor.is_some() && !paused {
+                    messages.push(pause(true));
+                }
+
+                ui.separator();
+                let selected = game_state.selected.iter().next();
+                match selected.and_then(|id| shown.kbots.get(id)) {
+                    Some(kbot) => {
+                        if ui
+                            .collapsing_header(&im_str!("Unit {}", kbot.id))
+                            .default_open(true)
+                            .build()
+                        {
+                            let text = serde_json::to_string_pretty(kbot).unwrap_or_default();
+                            ui.text_wrapped(&im_str!("{}", text));
+                        }
+                    }
+                    None => ui.text(im_str!("Select a unit to inspect it")),
+                }
+
+                ui.separator();
+                imgui::Slider::new(im_str!("compare with tick"), oldest..=newest)
+                    .build(ui, &mut time_travel.diff_with);
+                if ui.small_button(im_str!("Diff")) {
+                    let number = shown.number;
+                    time_travel.diff = match time_travel.frame(time_travel.diff_with) {
+                        Some(other) if other.number <= number => diff_frames(other, shown),
+                        Some(other) => diff_frames(shown, other),
+                        None => vec![format!("tick {} is gone", time_travel.diff_with)],
+                    };
+                }
+                for line in time_travel.diff.iter().take(MAX_DIFF_LINES) {
+                    ui.text(im_str!("{}", line));
+                }
+                if time_travel.diff.len() > MAX_DIFF_LINES {
+                    ui.text(im_str!(
+                        "... {} more",
+                        time_travel.diff.len() - MAX_DIFF_LINES
+                    ));
+                }
+            });
+        messages
+    }
+}
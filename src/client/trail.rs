@@ -0,0 +1,106 @@
+use fnv::FnvHashMap;
+use na::{Point3, Vector3};
+use std::collections::VecDeque;
+
+///Sec a trail point stays visible
+pub const LIFE_TIME: f32 = 0.6;
+///m, width of a trail at its head, shrinks to 0 at its tail
+pub const WIDTH: f32 = 0.25;
+///m/frame, units moving slower than this leave no trail
+pub const MIN_SPEED: f32 = 0.6;
+///m, a point is sampled when the entity moved that much since the last one
+const MIN_SAMPLE_DIST: f32 = 0.3;
+
+struct Trail {
+    points: VecDeque<(Point3<f32>, f32)>,
+}
+
+///Recent position history of fast moving entities, keyed by their id value
+pub struct TrailSet {
+    trails: FnvHashMap<u64, Trail>,
+}
+
+impl TrailSet {
+    pub fn new() -> Self {
+        TrailSet {
+            trails: FnvHashMap::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.trails.clear();
+    }
+
+    pub fn record(&mut self, id: u64, position: Point3<f32>, sec: f32) {
+        let trail = self.trails.entry(id).or_insert_with(|| Trail {
+            points: VecDeque::new(),
+        });
+        let moved_enough = trail
+            .points
+            .back()
+            .map(|(last, _)| (position - last).magnitude() > MIN_SAMPLE_DIST)
+            .unwrap_or(true);
+        if moved_enough {
+            trail.points.push_back((position, sec));
+        }
+    }
+
+    ///Drop the expired points, trails of dead entities fade out on their own
+    pub fn expire(&mut self, sec: f32) {
+        for trail in self.trails.values_mut() {
+            while trail
+                .points
+                .front()
+                .map(|(_, born)| sec - born > LIFE_TIME)
+                .unwrap_or(false)
+            {
+                trail.points.pop_front();
+            }
+        }
+        self.trails.retain(|_, trail| !trail.points.is_empty());
+    }
+
+    ///Camera facing ribbons as a triangle list, see gpu_obj::trail::VERTEX_LEN
+    pub fn build_vertices(&self, camera_pos: &Point3<f32>, sec: f32, buf: &mut Vec<f32>) {
+        for trail in self.trails.values() {
+            let len = trail.points.len();
+            if len < 2 {
+                continue;
+            }
+            let mut sides = Vec::with_capacity(len);
+            for i in 0..len {
+                let (p, born) = trail.points[i];
+                let prev = trail.points[i.saturating_sub(1)].0;
+                let next = trail.points[(i + 1).min(len - 1)].0;
+                let dir = next - prev;
+                let to_camera = camera_pos - p;
+                let side = dir.cross(&to_camera);
+                let side = if side.magnitude() > 0.0001 {
+                    side.normalize()
+                } else {
+                    Vector3::new(0.0, 0.0, 0.0)
+                };
+                let life = ((sec - born) / LIFE_TIME).max(0.0).min(1.0);
+                sides.push((p, side * WIDTH * (1.0 - life), 1.0 - life));
+            }
+
+            for i in 0..len - 1 {
+                let (p0, s0, a0) = sides[i];
+                let (p1, s1, a1) = sides[i + 1];
+                let u0 = i as f32 / (len - 1) as f32;
+                let u1 = (i + 1) as f32 / (len - 1) as f32;
+                let corners = [
+                    (p0 - s0, u0, a0),
+                    (p0 + s0, u0, a0),
+                    (p1 - s1, u1, a1),
+                    (p1 - s1, u1, a1),
+                    (p0 + s0, u0, a0),
+                    (p1 + s1, u1, a1),
+                ];
+                for (p, u, a) in corners.iter() {
+                    buf.extend_from_slice(&[p.x, p.y, p.z, *u, *a]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,589 @@
+use super::client::*;
+use super::event_bus::Consumer;
+use super::heightmap_editor;
+use super::hud::HudElement;
+use super::uitool::UiTool;
+use crate::botdef::BotDef;
+use crate::frame::{Frame, FrameEventFromPlayer, Player};
+use crate::game_event::{GameEvent, GameEventKind};
+use crate::mobile::KBot;
+use crate::prefab::{Prefab, PrefabSpawn};
+use crate::*;
+use fnv::FnvHashMap;
+use imgui::*;
+use na::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+///Units counted by a condition, relative to the player
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Side {
+    Own,
+    Enemy,
+}
+
+///Holds or not for the current frame, every condition of a trigger must hold for it to fire
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Condition {
+    ///Seconds of game time since the trigger was armed, stopped while paused
+    Timer { sec: f32 },
+    ///At least count units of the side inside the rectangle, in world coordinates
+    Region {
+        min: [f32; 2],
+        max: [f32; 2],
+        side: Side,
+        count: usize,
+    },
+    ///Units of the side, only those of a botdef when named by its file stem
+    UnitCount {
+        side: Side,
+        #[serde(default)]
+        botdef: Option<String>,
+        #[serde(default)]
+        at_least: Option<usize>,
+        #[serde(default)]
+        at_most: Option<usize>,
+    },
+    ///At least count units selected
+    Selected { count: usize },
+    ///Such an event happened to the side since the previous check, ex: one of our units died
+    Happened { event: GameEventKind, side: Side },
+    ///Continue pressed under the last prompt
+    Acknowledged,
+}
+
+///Inputs of the player in Play, all allowed by default
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct InputGate {
+    pub camera: bool,
+    pub select: bool,
+    ///Every right click order but the construction ones
+    pub order: bool,
+    pub build: bool,
+}
+
+impl Default for InputGate {
+    fn default() -> Self {
+        InputGate {
+            camera: true,
+            select: true,
+            order: true,
+            build: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Action {
+    ///Votes the pause like the Game speed window
+    Pause(bool),
+    ///Text shown until the next objective or prompt
+    Objective(String),
+    ///Objective with a Continue button, see Condition::Acknowledged
+    Prompt(String),
+    ///Hud panels framed until the next highlight, empty to clear
+    Highlight(Vec<HudElement>),
+    Gate(InputGate),
+    ///Move order to every unit of the side, in world coordinates
+    Move {
+        side: Side,
+        to: [f32; 2],
+    },
+    ///Reinforcements or a base built during the mission, given to the first player of the side
+    SpawnPrefab(PlacedPrefab),
+    ///Ends the mission, unlocking MissionDef::unlocks in the campaign
+    Victory,
+    Defeat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Victory,
+    Defeat,
+}
+
+///Units of a side laid on a grid at the start of a mission
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StartGroup {
+    pub side: Side,
+    ///Path of the botdef json
+    pub botdef: String,
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    pub spacing: f32,
+}
+
+///Prefab of a side, see prefab::Prefab
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PlacedPrefab {
+    pub side: Side,
+    ///Path of the prefab json
+    pub prefab: String,
+    ///World xy of its origin
+    pub at: [f32; 2],
+    ///rad
+    #[serde(default)]
+    pub angle: f32,
+}
+
+impl PlacedPrefab {
+    ///Botdefs and units of the prefab, None when it can't be read
+    fn place(&self) -> Option<(Vec<BotDef>, Vec<PrefabSpawn>)> {
+        Prefab::open(&self.prefab)
+            .and_then(|prefab| prefab.place(self.at[0], self.at[1], self.angle))
+            .map_err(|e| log::error!("Could not place prefab {}: {}", self.prefab, e))
+            .ok()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TriggerDef {
+    pub name: String,
+    ///Armed once the trigger of that name fired, at the start otherwise
+    #[serde(default)]
+    pub after: Option<String>,
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MissionDef {
+    pub name: String,
+    ///Map directory, the one already loaded by default
+    #[serde(default)]
+    pub map: Option<String>,
+    ///Units of the match, the example game when empty
+    #[serde(default)]
+    pub start: Vec<StartGroup>,
+    ///Placed at the start along with the groups
+    #[serde(default)]
+    pub prefabs: Vec<PlacedPrefab>,
+    ///Where the camera starts, world xy
+    #[serde(default)]
+    pub camera: Option<[f32; 2]>,
+    ///Campaign missions unlocked by a victory, by path
+    #[serde(default)]
+    pub unlocks: Vec<String>,
+    pub triggers: Vec<TriggerDef>,
+}
+
+impl MissionDef {
+    pub fn open(path: &str) -> std::result::Result<Self, String> {
+        std::fs::File::open(path)
+            .map_err(|e| format!("{}", e))
+            .and_then(|file| {
+                serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}", e))
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TriggerState {
+    Waiting,
+    ///Game second it was armed at
+    Armed(f32),
+    Fired,
+}
+
+///Running mission: the triggers fire once each, their actions drive the ui of the player
+pub struct Mission {
+    def: MissionDef,
+    ///Json it was read from, its id in the campaign
+    pub path: String,
+    pub outcome: Option<Outcome>,
+    states: Vec<TriggerState>,
+    pub objective: Option<String>,
+    ///The objective waits for Continue
+    pub prompt: bool,
+    acknowledged: bool,
+    pub highlights: Vec<HudElement>,
+    pub gate: InputGate,
+}
+
+impl Mission {
+    pub fn open(path: &str) -> std::result::Result<Self, String> {
+        MissionDef::open(path).map(|def| Mission::new(def, path))
+    }
+
+    pub fn new(def: MissionDef, path: &str) -> Self {
+        let states = vec![TriggerState::Waiting; def.triggers.len()];
+        Mission {
+            def,
+            path: path.to_owned(),
+            outcome: None,
+            states,
+            objective: None,
+            prompt: false,
+            acknowledged: false,
+            highlights: Vec::new(),
+            gate: InputGate::default(),
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.acknowledged = true;
+        self.prompt = false;
+    }
+
+    ///Objective text centered at the top, with the Continue button of a prompt.
+    ///Once the mission is over, true when Back is pressed.
+    pub fn draw(&mut self, ui: &Ui, width: f32, top: f32) -> bool {
+        let objective = match (&self.objective, self.outcome) {
+            (_, Some(Outcome::Victory)) => "Mission accomplished",
+            (_, Some(Outcome::Defeat)) => "Mission failed",
+            (Some(objective), None) => objective.as_str(),
+            (None, None) => return false,
+        };
+        let prompt = self.prompt && self.outcome.is_none();
+        let over = self.outcome.is_some();
+        let mut acknowledged = false;
+        let mut back = false;
+        imgui::Window::new(&im_str!("{}", self.def.name))
+            .position([width / 2.0, top], imgui::Condition::Always)
+            .position_pivot([0.5, 0.0])
+            .always_auto_resize(true)
+            .resizable(false)
+            .movable(false)
+            .collapsible(false)
+            .build(ui, || {
+                ui.text(&im_str!("{}", objective));
+                if prompt && ui.button(im_str!("Continue"), [120.0, 0.0]) {
+                    acknowledged = true;
+                }
+                if over && ui.button(im_str!("Back"), [120.0, 0.0]) {
+                    back = true;
+                }
+            });
+        if acknowledged {
+            self.acknowledge();
+        }
+        back
+    }
+
+    fn fired(&self, name: &str) -> bool {
+        self.def
+            .triggers
+            .iter()
+            .zip(self.states.iter())
+            .any(|(trigger, state)| trigger.name == name && *state == TriggerState::Fired)
+    }
+}
+
+impl App {
+    ///Inputs allowed by the mission, all without one
+    pub fn input_gate(&self) -> InputGate {
+        self.mission
+            .as_ref()
+            .map(|mission| mission.gate)
+            .unwrap_or_default()
+    }
+
+    ///Arms and fires the triggers of the mission, in Play
+    pub fn step_mission(&mut self) {
+        //Drained even without a mission, a mission started later doesn't see the past events
+        let events = self.game_state.events.drain(Consumer::Triggers);
+        let mut mission = match self.mission.take() {
+            Some(mission) => mission,
+            None => return,
+        };
+        if mission.outcome.is_some() {
+            self.mission = Some(mission);
+            return;
+        }
+        let now = self.game_state.server_sec;
+
+        for i in 0..mission.states.len() {
+            if mission.outcome.is_some() {
+                break;
+            }
+            if mission.states[i] == TriggerState::Waiting {
+                let armed = match &mission.def.triggers[i].after {
+                    Some(after) => mission.fired(after),
+                    None => true,
+                };
+                if armed {
+                    mission.states[i] = TriggerState::Armed(now);
+                }
+            }
+            if let TriggerState::Armed(since) = mission.states[i] {
+                let trigger = &mission.def.triggers[i];
+                let holds = trigger.conditions.iter().all(|condition| {
+                    self.condition_holds(condition, now - since, mission.acknowledged, &events)
+                });
+                if holds {
+                    log::info!("Trigger {} of {}", trigger.name, mission.def.name);
+                    mission.states[i] = TriggerState::Fired;
+                    if trigger.conditions.contains(&Condition::Acknowledged) {
+                        mission.acknowledged = false;
+                    }
+                    let actions = trigger.actions.clone();
+                    for action in actions {
+                        self.apply_action(&mut mission, action);
+                    }
+                }
+            }
+        }
+
+        if !mission.gate.build {
+            if let UiTool::Spawn(_) = self.game_state.uitool {
+                self.game_state.uitool = UiTool::None;
+            }
+        }
+        self.mission = Some(mission);
+    }
+
+    fn condition_holds(
+        &self,
+        condition: &Condition,
+        armed_sec: f32,
+        acknowledged: bool,
+        events: &[GameEvent],
+    ) -> bool {
+        let my_team = match self.game_state.my_player() {
+            Some(me) => me.team,
+            None => return false,
+        };
+        let of_side = |team: u8, side: Side| match side {
+            Side::Own => team == my_team,
+            Side::Enemy => team != my_team,
+        };
+        let frame_zero = &self.game_state.frame_zero;
+
+        match condition {
+            Condition::Timer { sec } => armed_sec >= *sec,
+            Condition::Region {
+                min,
+                max,
+                side,
+                count,
+            } => {
+                frame_zero
+                    .kbots
+                    .values()
+                    .filter(|kbot| {
+                        of_side(kbot.team, *side)
+                            && kbot.position.x >= min[0]
+                            && kbot.position.x <= max[0]
+                            && kbot.position.y >= min[1]
+                            && kbot.position.y <= max[1]
+                    })
+                    .count()
+                    >= *count
+            }
+            Condition::UnitCount {
+                side,
+                botdef,
+                at_least,
+                at_most,
+            } => {
+                let count = frame_zero
+                    .kbots
+                    .values()
+                    .filter(|kbot| of_side(kbot.team, *side))
+                    .filter(|kbot| match botdef {
+                        Some(name) => frame_zero
+                            .bot_defs
+                            .get(&kbot.botdef_id)
+                            .and_then(|botdef| {
+                                std::path::Path::new(&botdef.file_path)
+                                    .file_stem()
+                                    .map(|stem| stem.to_string_lossy() == name.as_str())
+                            })
+                            .unwrap_or(false),
+                        None => true,
+                    })
+                    .count();
+                at_least.map(|n| count >= n).unwrap_or(true)
+                    && at_most.map(|n| count <= n).unwrap_or(true)
+            }
+            Condition::Selected { count } => self.game_state.selected.len() >= *count,
+            Condition::Happened { event, side } => events.iter().any(|e| {
+                e.kind() == *event
+                    && frame_zero
+                        .players
+                        .get(&e.player_id())
+                        .map(|player| of_side(player.team, *side))
+                        .unwrap_or(false)
+            }),
+            Condition::Acknowledged => acknowledged,
+        }
+    }
+
+    fn apply_action(&mut self, mission: &mut Mission, action: Action) {
+        match action {
+            Action::Pause(paused) => {
+                if let (Some(global_info), Some(id)) =
+                    (self.global_info, self.game_state.my_player_id)
+                {
+                    let speed = global_info.manager.game_speed.speed;
+                    let event = FrameEventFromPlayer::SpeedVote { id, speed, paused };
+                    let _ = self
+                        .sender_from_client_to_manager
+                        .try_send(client::FromClient::PlayerInput(event));
+                }
+            }
+            Action::Objective(text) => {
+                mission.objective = Some(text);
+                mission.prompt = false;
+            }
+            Action::Prompt(text) => {
+                mission.objective = Some(text);
+                mission.prompt = true;
+                mission.acknowledged = false;
+            }
+            Action::Highlight(elements) => mission.highlights = elements,
+            Action::Gate(gate) => mission.gate = gate,
+            Action::Move { side, to } => {
+                let my_team = self.game_state.my_player().map(|me| me.team).unwrap_or(0);
+                let frame_zero = &self.game_state.frame_zero;
+                let mouse_world_pos =
+                    Vector3::new(to[0], to[1], self.heightmap_gpu.phy.z(to[0], to[1]));
+                for player in frame_zero.players.values() {
+                    if (player.team == my_team) != (side == Side::Own) {
+                        continue;
+                    }
+                    let event = FrameEventFromPlayer::MoveOrder {
+                        id: player.id,
+                        selected: player.kbots.clone(),
+                        mouse_world_pos,
+                    };
+                    let _ = self
+                        .sender_from_client_to_manager
+                        .try_send(client::FromClient::PlayerInput(event));
+                }
+            }
+            Action::SpawnPrefab(placed) => {
+                let my_team = self.game_state.my_player().map(|me| me.team).unwrap_or(0);
+                let id = match placed.side {
+                    Side::Own => self.game_state.my_player_id,
+                    Side::Enemy => self
+                        .game_state
+                        .frame_zero
+                        .players
+                        .values()
+                        .filter(|player| player.team != my_team)
+                        .min_by_key(|player| player.slot)
+                        .map(|player| player.id),
+                };
+                if let (Some(id), Some((bot_defs, units))) = (id, placed.place()) {
+                    let event = FrameEventFromPlayer::SpawnPrefab {
+                        id,
+                        bot_defs,
+                        units,
+                    };
+                    let _ = self
+                        .sender_from_client_to_manager
+                        .try_send(client::FromClient::PlayerInput(event));
+                }
+            }
+            Action::Victory => {
+                mission.outcome = Some(Outcome::Victory);
+                mission.gate = InputGate::default();
+                self.campaign.complete(&mission.path, &mission.def.unlocks);
+            }
+            Action::Defeat => {
+                mission.outcome = Some(Outcome::Defeat);
+                mission.gate = InputGate::default();
+            }
+        }
+    }
+
+    ///Loads the map of the mission and places its start groups and prefabs, None without any
+    pub fn mission_frame(&mut self) -> Option<Frame> {
+        let def = match self.mission.as_ref() {
+            Some(mission) if !mission.def.start.is_empty() || !mission.def.prefabs.is_empty() => {
+                mission.def.clone()
+            }
+            _ => return None,
+        };
+        if let Some(map) = &def.map {
+            heightmap_editor::State::load(&mut self.heightmap_gpu, map);
+        }
+
+        let mut player_me = Player::new();
+        let mut player_enemy = Player::new();
+        player_enemy.team = 1;
+        player_enemy.slot = 1;
+        let mut kbots = FnvHashMap::default();
+        let mut bot_defs: FnvHashMap<_, BotDef> = FnvHashMap::default();
+
+        for group in def.start.iter() {
+            let botdef = match load_botdef(&group.botdef) {
+                Ok(botdef) => botdef,
+                Err(e) => {
+                    log::error!("Could not read botdef {}: {}", group.botdef, e);
+                    continue;
+                }
+            };
+            let player = match group.side {
+                Side::Own => &mut player_me,
+                Side::Enemy => &mut player_enemy,
+            };
+            let spacing = group.spacing.max(1.0);
+            let mut x = group.min[0];
+            while x <= group.max[0] {
+                let mut y = group.min[1];
+                while y <= group.max[1] {
+                    let z = self.heightmap_gpu.phy.z(x, y);
+                    let mut kbot = KBot::new(Point3::new(x, y, z), &botdef, player.id);
+                    kbot.team = player.team;
+                    player.kbots.insert(kbot.id);
+                    kbots.insert(kbot.id, kbot);
+                    y += spacing;
+                }
+                x += spacing;
+            }
+            bot_defs.insert(botdef.id, botdef);
+        }
+        for placed in def.prefabs.iter() {
+            let (prefab_defs, units) = match placed.place() {
+                Some(placed) => placed,
+                None => continue,
+            };
+            for botdef in prefab_defs {
+                bot_defs.entry(botdef.id).or_insert(botdef);
+            }
+            let player = match placed.side {
+                Side::Own => &mut player_me,
+                Side::Enemy => &mut player_enemy,
+            };
+            for unit in units.iter() {
+                if let Some(botdef) = bot_defs.get(&unit.botdef_id) {
+                    let kbot = unit.kbot(botdef, &self.heightmap_gpu.phy, player);
+                    player.kbots.insert(kbot.id);
+                    kbots.insert(kbot.id, kbot);
+                }
+            }
+        }
+        log::info!("Starting mission {} with {} bots", def.name, kbots.len());
+
+        if let Some([x, y]) = def.camera {
+            self.game_state.position = Point3::new(x, y, self.heightmap_gpu.phy.z(x, y) + 50.0);
+            self.game_state.dir = Vector3::new(0.0, 0.3, -1.0);
+        }
+
+        let mut players = FnvHashMap::default();
+        players.insert(player_me.id, player_me);
+        players.insert(player_enemy.id, player_enemy);
+        let moddef = moddef::ModDef {
+            units_id: bot_defs.keys().copied().collect(),
+            con_map: FnvHashMap::default(),
+        };
+        Some(Frame {
+            players,
+            moddef,
+            kbots,
+            heightmap_phy: Some(self.heightmap_gpu.phy.clone()),
+            bot_defs,
+            ..Frame::new()
+        })
+    }
+}
+
+fn load_botdef(path: &str) -> std::result::Result<BotDef, String> {
+    std::fs::File::open(path)
+        .map_err(|e| format!("{}", e))
+        .and_then(|file| {
+            serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}", e))
+        })
+}
@@ -6,7 +6,15 @@ pub enum UiTool {
     None,
     Move,
     Repair,
+    Reclaim,
+    Patrol,
     Guard,
     Attack,
+    Load,
+    Unload,
+    ///Two clicks, the pickup then the dropoff
+    Ferry,
+    Nuke,
+    DGun,
     Spawn(Id<botdef::BotDef>),
 }
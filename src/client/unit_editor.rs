@@ -36,8 +36,24 @@ impl UnitEditor {
             break_accel: 0.3,
             max_speed: 1.0,
             build_power: 10.0,
+            energy_income: 0.0,
             build_dist: 5.0,
             metal_cost: 100,
+            death_effect: "small_explosion".to_owned(),
+            shield_radius: 0.0,
+            weapon0: botdef::Weapon::Projectile,
+            anti_air: false,
+            movement: botdef::MovementClass::Ground,
+            cruise_altitude: 8.0,
+            shoreline_only: false,
+            transport_capacity: 0,
+            strategic: botdef::Strategic::None,
+            commander: false,
+            dgun: None,
+            death_explosion: None,
+            adjacency: botdef::Adjacency::None,
+            tier: 1,
+            unlocks_tier: 0,
             part_tree: root,
         };
 
@@ -61,6 +77,7 @@ impl UnitEditor {
                     ),
                     mesh_path: path,
                     mesh_index,
+                    material: Material::default(),
                 }),
                 placed_collider: None,
                 parent_to_self: Matrix4::identity(),
@@ -111,8 +128,24 @@ impl App {
                     break_accel,
                     max_speed,
                     build_power,
+                    energy_income,
                     build_dist,
                     metal_cost,
+                    death_effect,
+                    shield_radius,
+                    weapon0,
+                    anti_air,
+                    movement,
+                    cruise_altitude,
+                    shoreline_only,
+                    transport_capacity,
+                    strategic,
+                    commander,
+                    dgun,
+                    death_explosion,
+                    adjacency,
+                    tier,
+                    unlocks_tier,
                     part_tree,
                 } = &unit_editor.botdef;
 
@@ -174,6 +207,16 @@ impl App {
                     .max(100.0)
                     .build();
 
+                let mut energy_income_human = energy_income * to_sec;
+                ui.drag_float(
+                    im_str!("energy income (energy/sec)"),
+                    &mut energy_income_human,
+                )
+                .speed(0.01)
+                .min(0.0)
+                .max(1000.0)
+                .build();
+
                 let mut build_dist_ = build_dist.clone();
                 ui.drag_float(im_str!("build distance (m)"), &mut build_dist_)
                     .speed(0.01)
@@ -181,6 +224,138 @@ impl App {
                     .max(100.0)
                     .build();
 
+                let mut shield_radius_ = shield_radius.clone();
+                ui.drag_float(im_str!("shield radius (m)"), &mut shield_radius_)
+                    .speed(0.01)
+                    .min(0.0)
+                    .max(100.0)
+                    .build();
+
+                //Name of an effect of effect::EFFECTS_PATH
+                let mut death_effect_ = imgui::ImString::with_capacity(64);
+                death_effect_.push_str(death_effect);
+                ui.input_text(im_str!("death effect"), &mut death_effect_).build();
+
+                let mut weapon0_ = weapon0.clone();
+                ui.text(im_str!("weapon {:?}", weapon0));
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("swap weapon")) {
+                    weapon0_ = match weapon0 {
+                        botdef::Weapon::Projectile => botdef::Weapon::Beam {
+                            damage: 10,
+                            color: [1.0, 0.2, 0.2],
+                        },
+                        botdef::Weapon::Beam { .. } => botdef::Weapon::Projectile,
+                    };
+                }
+
+                let mut anti_air_ = *anti_air;
+                ui.checkbox(im_str!("anti air"), &mut anti_air_);
+
+                let mut commander_ = *commander;
+                ui.checkbox(im_str!("commander"), &mut commander_);
+
+                let mut dgun_ = dgun.clone();
+                let mut has_dgun = dgun.is_some();
+                if ui.checkbox(im_str!("d-gun"), &mut has_dgun) {
+                    dgun_ = if has_dgun {
+                        Some(botdef::DGun {
+                            damage: 5000,
+                            range: 12.0,
+                            width: 1.0,
+                            energy_cost: 500.0,
+                            reload_frames: 20,
+                        })
+                    } else {
+                        None
+                    };
+                }
+
+                let mut death_explosion_ = death_explosion.clone();
+                let mut has_death_explosion = death_explosion.is_some();
+                if ui.checkbox(im_str!("death explosion"), &mut has_death_explosion) {
+                    death_explosion_ = if has_death_explosion {
+                        Some(botdef::DeathExplosion {
+                            damage: 2500,
+                            radius: 15.0,
+                            crater_depth: 2.0,
+                        })
+                    } else {
+                        None
+                    };
+                }
+
+                let mut shoreline_only_ = *shoreline_only;
+                ui.checkbox(im_str!("shoreline only"), &mut shoreline_only_);
+
+                let mut transport_capacity_ = *transport_capacity as i32;
+                ui.drag_int(im_str!("transport capacity"), &mut transport_capacity_)
+                    .min(0)
+                    .max(32)
+                    .build();
+
+                let mut strategic_ = strategic.clone();
+                ui.text(im_str!("strategic {:?}", strategic));
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("swap strategic")) {
+                    strategic_ = match strategic {
+                        botdef::Strategic::None => botdef::Strategic::Nuke {
+                            stockpile_frames: 1200,
+                            damage: 3000,
+                            radius: 40.0,
+                        },
+                        botdef::Strategic::Nuke { .. } => botdef::Strategic::AntiNuke {
+                            stockpile_frames: 600,
+                            radius: 120.0,
+                        },
+                        botdef::Strategic::AntiNuke { .. } => botdef::Strategic::None,
+                    };
+                }
+
+                let mut movement_ = *movement;
+                ui.text(im_str!("movement {:?}", movement));
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("swap movement")) {
+                    movement_ = match movement {
+                        botdef::MovementClass::Ground => botdef::MovementClass::Air,
+                        botdef::MovementClass::Air => botdef::MovementClass::Naval,
+                        botdef::MovementClass::Naval => botdef::MovementClass::Amphibious,
+                        botdef::MovementClass::Amphibious => botdef::MovementClass::Ground,
+                    };
+                }
+
+                let mut adjacency_ = *adjacency;
+                ui.text(im_str!("adjacency {:?}", adjacency));
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("swap adjacency")) {
+                    adjacency_ = match adjacency {
+                        botdef::Adjacency::None => botdef::Adjacency::Storage,
+                        botdef::Adjacency::Storage => botdef::Adjacency::Generator,
+                        botdef::Adjacency::Generator => botdef::Adjacency::None,
+                    };
+                }
+
+                let max_tier = botdef::TIER_COST_MULTIPLIERS.len() as i32;
+                let mut tier_ = *tier as i32;
+                ui.drag_int(im_str!("tier"), &mut tier_)
+                    .min(1)
+                    .max(max_tier)
+                    .build();
+                let mut unlocks_tier_ = *unlocks_tier as i32;
+                ui.drag_int(im_str!("unlocks tier (0 = none)"), &mut unlocks_tier_)
+                    .min(0)
+                    .max(max_tier)
+                    .build();
+
+                let mut cruise_altitude_ = *cruise_altitude;
+                if *movement == botdef::MovementClass::Air {
+                    ui.drag_float(im_str!("cruise altitude (m)"), &mut cruise_altitude_)
+                        .speed(0.01)
+                        .min(1.0)
+                        .max(100.0)
+                        .build();
+                }
+
                 unit_editor.botdef.max_turn_rate = max_turn_rate_human * to_frame * to_rad;
                 unit_editor.botdef.turn_accel = turn_accel_human * to_frame * to_frame * to_rad;
                 unit_editor.botdef.max_speed = max_speed_human * to_frame;
@@ -188,7 +363,23 @@ impl App {
                 unit_editor.botdef.break_accel = break_accel_human * to_frame * to_frame;
                 unit_editor.botdef.max_life = life.max(0);
                 unit_editor.botdef.build_power = build_power_human * to_frame;
+                unit_editor.botdef.energy_income = energy_income_human * to_frame;
                 unit_editor.botdef.build_dist = build_dist_;
+                unit_editor.botdef.shield_radius = shield_radius_;
+                unit_editor.botdef.death_effect = death_effect_.to_str().to_owned();
+                unit_editor.botdef.weapon0 = weapon0_;
+                unit_editor.botdef.anti_air = anti_air_;
+                unit_editor.botdef.movement = movement_;
+                unit_editor.botdef.cruise_altitude = cruise_altitude_;
+                unit_editor.botdef.shoreline_only = shoreline_only_;
+                unit_editor.botdef.transport_capacity = transport_capacity_.max(0) as u32;
+                unit_editor.botdef.strategic = strategic_;
+                unit_editor.botdef.commander = commander_;
+                unit_editor.botdef.dgun = dgun_;
+                unit_editor.botdef.death_explosion = death_explosion_;
+                unit_editor.botdef.adjacency = adjacency_;
+                unit_editor.botdef.tier = tier_.max(1).min(max_tier) as u8;
+                unit_editor.botdef.unlocks_tier = unlocks_tier_.max(0).min(max_tier) as u8;
                 ui.separator();
                 Self::ui_part_tree(
                     ui,
@@ -431,6 +622,11 @@ impl App {
                                                             .mesh_path
                                                             .clone(),
                                                         mesh_index: old_placed_mesh.mesh_index,
+                                                        material: Self::ui_material(
+                                                            ui,
+                                                            format!("{:?}", c.id),
+                                                            old_placed_mesh.material,
+                                                        ),
                                                     };
                                                     Some(new_placed_mesh)
                                                 } else {
@@ -459,6 +655,36 @@ impl App {
         };
     }
 
+    fn ui_material(ui: &Ui, id: String, material: Material) -> Material {
+        ui.text(im_str!("material:"));
+        let mut emissive = material.emissive;
+        ui.drag_float(im_str!("emissive##{:?}", id).as_ref(), &mut emissive)
+            .speed(0.01)
+            .min(0.0)
+            .max(4.0)
+            .build();
+        let mut pulse_frequency = material.pulse_frequency;
+        ui.drag_float(
+            im_str!("pulse frequency##{:?}", id).as_ref(),
+            &mut pulse_frequency,
+        )
+        .speed(0.01)
+        .min(0.0)
+        .max(10.0)
+        .build();
+        let mut uv_scroll = material.uv_scroll;
+        ui.drag_float2(im_str!("uv scroll##{:?}", id).as_ref(), &mut uv_scroll)
+            .speed(0.01)
+            .min(-10.0)
+            .max(10.0)
+            .build();
+        Material {
+            emissive,
+            pulse_frequency,
+            uv_scroll,
+        }
+    }
+
     fn visit_dirs_for_add_child(
         dir: &FileTree,
         ui: &Ui,
@@ -479,7 +705,7 @@ impl App {
 
                     let (index, state) = unit_part_gpu.path_get_or_create_if_na(path.to_owned());
                     match state {
-                        ModelGpuState::Ready(_) | ModelGpuState::ToLoad(_) => {
+                        ModelGpuState::Ready(_) | ModelGpuState::ToLoad(..) => {
                             if ui.small_button(im_str!("add to parts##{:?}", path).as_ref()) {
                                 log::debug!("add to parts {:?}", path);
                                 unit_editor.add_to_parts(parent, path.clone(), index);
@@ -547,12 +773,14 @@ impl App {
                                     mesh_index,
                                     mesh_path: path.clone(),
                                     trans: old.trans.clone(),
+                                    material: old.material,
                                 });
                             } else {
                                 child.placed_mesh = Some(PlacedMesh {
                                     mesh_index,
                                     mesh_path: path.clone(),
                                     trans: Matrix4::identity(),
+                                    material: Material::default(),
                                 });
                             }
                         }
@@ -569,7 +797,7 @@ impl App {
                                 replace_exe(index);
                             }
                         }
-                        Some(ModelGpuState::Ready(_)) | Some(ModelGpuState::ToLoad(_)) => {
+                        Some(ModelGpuState::Ready(_)) | Some(ModelGpuState::ToLoad(..)) => {
                             if ui.small_button(im_str!("replace with this##{:?}", path).as_ref()) {
                                 log::debug!("replace with this {:?}", path);
                                 let index = unit_part_gpu.index_of_or_create_if_na(path.to_owned());
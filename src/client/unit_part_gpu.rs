@@ -1,11 +1,12 @@
 use crate::gpu_obj;
 use crate::model;
-use gpu_obj::model_gpu::ModelGpu;
+use gpu_obj::model_batch::{MeshId, MeshTextures};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 pub enum ModelGpuState {
-    ToLoad(model::TriangleList),
-    Ready(ModelGpu),
+    ToLoad(model::TriangleList, MeshTextures),
+    ///Uploaded in the ModelBatch of the client
+    Ready(MeshId),
     Error(String),
 }
 
@@ -36,6 +37,13 @@ impl UnitPartGpu {
             .map(move |index| &mut self.states[index])
     }
 
+    pub fn path_of(&self, index: usize) -> Option<&Path> {
+        self.path_to_index
+            .iter()
+            .find(|(_, i)| **i == index)
+            .map(|(path, _)| path.as_path())
+    }
+
     pub fn get(&self, index: usize) -> &ModelGpuState {
         &self.states[index]
     }
@@ -46,7 +54,7 @@ impl UnitPartGpu {
     fn load_at(&mut self, index: usize, path: PathBuf) {
         self.path_to_index.insert(path.clone(), index);
         let to_push = match crate::model::open_obj(path.to_str().unwrap()) {
-            Ok(triangle_list) => ModelGpuState::ToLoad(triangle_list.clone()),
+            Ok(triangle_list) => ModelGpuState::ToLoad(triangle_list, MeshTextures::open(&path)),
             Err(e) => ModelGpuState::Error(e),
         };
         self.states.push(to_push);
@@ -0,0 +1,118 @@
+use crate::logging::LogLines;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+pub const CRASH_DIR: &str = "crash_reports";
+///Holds the path of the last report until the player dismissed it
+const PENDING_PATH: &str = "crash_reports/pending.txt";
+///Log lines copied into a report
+const LOG_LINES_IN_REPORT: usize = 200;
+
+static TICK: AtomicI32 = AtomicI32::new(0);
+static ADAPTER_INFO: Mutex<String> = Mutex::new(String::new());
+static GPU_CONTEXT: Mutex<String> = Mutex::new(String::new());
+static SETTINGS: Mutex<String> = Mutex::new(String::new());
+
+///Last simulated frame number, written in the report
+pub fn set_tick(frame_number: i32) {
+    TICK.store(frame_number, Ordering::Relaxed);
+}
+
+pub fn set_adapter_info(info: String) {
+    if let Ok(mut adapter_info) = ADAPTER_INFO.lock() {
+        *adapter_info = info;
+    }
+}
+
+///Last gpu resource being created. wgpu panics on validation errors, the report then names the
+///shader or model at fault.
+pub fn set_gpu_context(context: String) {
+    if let Ok(mut gpu_context) = GPU_CONTEXT.lock() {
+        *gpu_context = context;
+    }
+}
+
+///Settings in use, serialized by the client
+pub fn set_settings(settings: String) {
+    if let Ok(mut current) = SETTINGS.lock() {
+        *current = settings;
+    }
+}
+
+///Panics of any thread write a report in CRASH_DIR, the default hook still prints the message
+pub fn install(log_lines: LogLines) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        match write_report(&panic_info.to_string(), &log_lines) {
+            Ok(path) => eprintln!("Crash report written to {}", path),
+            Err(e) => eprintln!("Could not write the crash report: {}", e),
+        }
+        default_hook(panic_info);
+    }));
+}
+
+fn write_report(panic: &str, log_lines: &LogLines) -> io::Result<String> {
+    std::fs::create_dir_all(CRASH_DIR)?;
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = format!("{}/crash-{}.txt", CRASH_DIR, timestamp);
+    let mut w = io::BufWriter::new(std::fs::File::create(&path)?);
+
+    writeln!(w, "oxidator {} crash report", env!("CARGO_PKG_VERSION"))?;
+    writeln!(
+        w,
+        "thread: {}",
+        std::thread::current().name().unwrap_or("unnamed")
+    )?;
+    writeln!(w, "panic: {}", panic)?;
+    writeln!(w, "tick: {}", TICK.load(Ordering::Relaxed))?;
+    //try_lock, the panic may come from a thread holding it
+    let adapter_info = ADAPTER_INFO
+        .try_lock()
+        .map(|info| info.clone())
+        .unwrap_or_default();
+    writeln!(w, "adapter: {}", adapter_info)?;
+    let gpu_context = GPU_CONTEXT
+        .try_lock()
+        .map(|context| context.clone())
+        .unwrap_or_default();
+    writeln!(w, "last gpu resource: {}", gpu_context)?;
+    writeln!(w, "os: {} {}", std::env::consts::OS, std::env::consts::ARCH)?;
+    writeln!(w, "args: {:?}", std::env::args().collect::<Vec<_>>())?;
+    let settings = SETTINGS
+        .try_lock()
+        .map(|settings| settings.clone())
+        .unwrap_or_default();
+    writeln!(w, "settings: {}", settings)?;
+
+    let backtrace = std::backtrace::Backtrace::force_capture();
+    writeln!(w, "\nbacktrace:\n{}", backtrace)?;
+
+    writeln!(w, "\nlast log lines:")?;
+    match log_lines.try_snapshot() {
+        Some(lines) => {
+            let skipped = lines.len().saturating_sub(LOG_LINES_IN_REPORT);
+            for line in lines.iter().skip(skipped) {
+                writeln!(w, "{}", line)?;
+            }
+        }
+        //The panic comes from a thread logging
+        None => writeln!(w, "unavailable, held by another thread")?,
+    }
+    w.flush()?;
+
+    std::fs::write(PENDING_PATH, &path)?;
+    Ok(path)
+}
+
+///Report of a previous session the player wasn't told about yet
+pub fn pending_report() -> Option<String> {
+    std::fs::read_to_string(PENDING_PATH).ok()
+}
+
+pub fn dismiss_pending_report() {
+    let _ = std::fs::remove_file(PENDING_PATH);
+}
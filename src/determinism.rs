@@ -0,0 +1,259 @@
+use crate::botdef;
+use crate::frame::{Formation, Frame, FrameEventFromPlayer};
+use crate::frame_server::FrameServerCache;
+use crate::heightmap_phy::HeightmapPhy;
+use crate::utils;
+use fnv::{FnvHashMap, FnvHasher};
+use na::Vector3;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+
+///One script per json, each with the checksum of its final state
+pub const SCRIPT_DIR: &str = "src/asset/determinism";
+const MAP: &str = "src/asset/map/map_example";
+const UNIT: &str = "src/asset/botdef/unit_example.json";
+const BUILDING: &str = "src/asset/botdef/building_example.json";
+
+///Orders by team rather than by id, the ids only exist once the game is created
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Command {
+    ///Every unit of the team
+    Move {
+        team: u8,
+        to: [f32; 2],
+    },
+    ///Example units for the first player of the team
+    Spawn {
+        team: u8,
+        at: [f32; 2],
+        count: u32,
+        formation: Formation,
+    },
+    Projectiles {
+        per_sec: u32,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedCommand {
+    pub tick: i32,
+    pub command: Command,
+}
+
+///Frame::example_game played for ticks frames, ids drawn from seed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Script {
+    pub seed: u64,
+    pub ticks: i32,
+    pub commands: Vec<TimedCommand>,
+    ///Hex of checksum, None until determinism update
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+impl Script {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}", e))?;
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}", e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| format!("{}", e))?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)
+            .map_err(|e| format!("{}", e))
+    }
+
+    ///Checksum of the last frame
+    pub fn run(&self, heightmap: &HeightmapPhy) -> u64 {
+        utils::seed_ids(Some(self.seed));
+        let unit = botdef::BotDef::open(UNIT).unwrap();
+        let building = botdef::BotDef::open(BUILDING).unwrap();
+        let unit_id = unit.id;
+        let mut frame = Frame::example_game(heightmap.clone(), unit, building);
+        let mut cache = FrameServerCache::new();
+        //Loads the heightmap in the cache the way the manager does
+        let mut events = vec![FrameEventFromPlayer::ReplaceFrame(frame.clone())];
+        for tick in 0..self.ticks {
+            for timed in self.commands.iter().filter(|c| c.tick == tick) {
+                events.extend(command_events(&timed.command, &frame, heightmap, unit_id));
+            }
+            frame = cache.next_frame(frame, std::mem::take(&mut events));
+        }
+        utils::seed_ids(None);
+        checksum(&frame)
+    }
+}
+
+fn command_events(
+    command: &Command,
+    frame: &Frame,
+    heightmap: &HeightmapPhy,
+    unit_id: utils::Id<botdef::BotDef>,
+) -> Vec<FrameEventFromPlayer> {
+    let mut players: Vec<_> = frame.players.values().collect();
+    players.sort_by_key(|p| p.id.value);
+    let at = |[x, y]: [f32; 2]| Vector3::new(x, y, heightmap.z(x, y));
+    match command {
+        Command::Move { team, to } => players
+            .iter()
+            .filter(|p| p.team == *team && !p.kbots.is_empty())
+            .map(|p| FrameEventFromPlayer::MoveOrder {
+                id: p.id,
+                selected: p.kbots.clone(),
+                mouse_world_pos: at(*to),
+            })
+            .collect(),
+        Command::Spawn {
+            team,
+            at: center,
+            count,
+            formation,
+        } => players
+            .iter()
+            .find(|p| p.team == *team)
+            .map(|p| FrameEventFromPlayer::StressSpawn {
+                id: p.id,
+                botdef_id: unit_id,
+                center: at(*center),
+                count: *count,
+                formation: *formation,
+            })
+            .into_iter()
+            .collect(),
+        Command::Projectiles { per_sec } => players
+            .first()
+            .map(|p| FrameEventFromPlayer::StressProjectiles {
+                id: p.id,
+                per_sec: *per_sec,
+            })
+            .into_iter()
+            .collect(),
+    }
+}
+
+///Units, projectiles, missiles and players in id order, so the layout of the maps doesn't count
+pub fn checksum(frame: &Frame) -> u64 {
+    let mut hasher = FnvHasher::default();
+    write(&mut hasher, &frame.number);
+    write(&mut hasher, &frame.game_end);
+    for kbot in sorted(&frame.kbots) {
+        write(&mut hasher, kbot);
+    }
+    for projectile in sorted(&frame.kinematic_projectiles) {
+        write(&mut hasher, projectile);
+    }
+    for missile in sorted(&frame.missiles) {
+        write(&mut hasher, missile);
+    }
+    for player in sorted(&frame.players) {
+        let mut kbots: Vec<_> = player.kbots.iter().map(|id| id.value).collect();
+        kbots.sort();
+        write(&mut hasher, &kbots);
+        write(
+            &mut hasher,
+            &(player.team, player.metal, player.energy, player.defeated),
+        );
+    }
+    hasher.finish()
+}
+
+fn sorted<K, V>(map: &FnvHashMap<utils::Id<K>, V>) -> Vec<&V> {
+    let mut values: Vec<_> = map.iter().collect();
+    values.sort_by_key(|(id, _)| id.value);
+    values.into_iter().map(|(_, v)| v).collect()
+}
+
+fn write<T: Serialize>(hasher: &mut FnvHasher, value: &T) {
+    hasher.write(&bincode::serialize(value).unwrap());
+}
+
+///Plays every script twice, compares the two checksums then the one of the script, or
+///replaces it when update. False if any script differs.
+pub fn run(update: bool) -> bool {
+    let mut heightmap = HeightmapPhy::new(2048, 2048);
+    heightmap.load(MAP);
+
+    let mut paths: Vec<_> = match std::fs::read_dir(SCRIPT_DIR) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map(|e| e == "json").unwrap_or(false))
+            .collect(),
+        Err(e) => {
+            log::error!("Can't read {}: {}", SCRIPT_DIR, e);
+            return false;
+        }
+    };
+    paths.sort();
+
+    let mut passed = true;
+    for path in paths {
+        let path = path.to_string_lossy().into_owned();
+        let mut script = match Script::open(&path) {
+            Ok(script) => script,
+            Err(e) => {
+                log::error!("Determinism {}: {}", path, e);
+                passed = false;
+                continue;
+            }
+        };
+        let first = format!("{:016x}", script.run(&heightmap));
+        let second = format!("{:016x}", script.run(&heightmap));
+        if first != second {
+            log::error!(
+                "Determinism {}: two runs ended in {} and {}",
+                path,
+                first,
+                second
+            );
+            passed = false;
+            continue;
+        }
+
+        if update {
+            script.checksum = Some(first.clone());
+            match script.save(&path) {
+                Ok(()) => log::info!("Determinism {}: checksum {} written", path, first),
+                Err(e) => {
+                    log::error!("Can't write {}: {}", path, e);
+                    passed = false;
+                }
+            }
+            continue;
+        }
+
+        match &script.checksum {
+            Some(expected) if *expected == first => log::info!("Determinism {}: ok", path),
+            Some(expected) => {
+                log::error!(
+                    "Determinism {}: checksum {} instead of {}",
+                    path,
+                    first,
+                    expected
+                );
+                passed = false;
+            }
+            None => {
+                log::error!("Determinism {}: no checksum, run determinism update", path);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_match_their_checksum() {
+        let mut heightmap = HeightmapPhy::new(2048, 2048);
+        heightmap.load(MAP);
+        for entry in std::fs::read_dir(SCRIPT_DIR).unwrap() {
+            let path = entry.unwrap().path().to_string_lossy().into_owned();
+            let script = Script::open(&path).unwrap();
+            let checksum = format!("{:016x}", script.run(&heightmap));
+            assert_eq!(Some(checksum), script.checksum, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use na::Vector3;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const EFFECTS_PATH: &str = "src/asset/effect/effects.json";
+
+///One building block of an effect, all durations are in sec and distances in m
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum EffectPart {
+    ///Billboard fireballs drawn by gpu_obj::explosion, randomly spread around the origin
+    Particles {
+        count: u32,
+        size: f32,
+        spread: f32,
+        life_time: f32,
+    },
+    LightFlash {
+        color: [f32; 3],
+        radius: f32,
+        life_time: f32,
+    },
+    CameraShake {
+        amplitude: f32,
+        ///Hz
+        frequency: f32,
+        ///fraction of the initial amplitude lost per sec
+        decay: f32,
+        ///No shake felt beyond this distance to the camera
+        radius: f32,
+    },
+    Decal {
+        size: f32,
+        life_time: f32,
+    },
+    Sound {
+        path: String,
+        volume: f32,
+    },
+}
+
+impl EffectPart {
+    pub fn life_time(&self) -> f32 {
+        match self {
+            EffectPart::Particles { life_time, .. }
+            | EffectPart::LightFlash { life_time, .. }
+            | EffectPart::Decal { life_time, .. } => *life_time,
+            EffectPart::CameraShake { decay, .. } => 1.0 / decay.max(0.01),
+            EffectPart::Sound { .. } => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct EffectDef {
+    pub name: String,
+    pub parts: Vec<EffectPart>,
+}
+
+impl EffectDef {
+    ///Particles offsets relative to the effect origin, with their size and life time
+    pub fn particles(&self) -> Vec<(Vector3<f32>, f32, f32)> {
+        let mut res = Vec::new();
+        for part in self.parts.iter() {
+            if let EffectPart::Particles {
+                count,
+                size,
+                spread,
+                life_time,
+            } = part
+            {
+                for i in 0..*count {
+                    let offset = if i == 0 {
+                        Vector3::new(0.0, 0.0, 0.0)
+                    } else {
+                        Vector3::new(
+                            rand::random::<f32>() - 0.5,
+                            rand::random::<f32>() - 0.5,
+                            rand::random::<f32>() * 0.5,
+                        ) * 2.0
+                            * *spread
+                    };
+                    let jitter = 0.75 + rand::random::<f32>() * 0.5;
+                    res.push((offset, size * jitter, life_time * jitter));
+                }
+            }
+        }
+        res
+    }
+}
+
+pub struct EffectLibrary {
+    defs: HashMap<String, EffectDef>,
+}
+
+impl EffectLibrary {
+    pub fn open(path: &str) -> Self {
+        let defs: std::result::Result<Vec<EffectDef>, String> = std::fs::File::open(path)
+            .map_err(|e| format!("{}", e))
+            .and_then(|file| {
+                serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}", e))
+            });
+
+        match defs {
+            Ok(defs) => {
+                log::info!("Loaded {} effects from {}", defs.len(), path);
+                EffectLibrary {
+                    defs: defs.into_iter().map(|d| (d.name.clone(), d)).collect(),
+                }
+            }
+            Err(e) => {
+                log::error!("Could not read effects {}: {}", path, e);
+                EffectLibrary {
+                    defs: HashMap::new(),
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EffectDef> {
+        let def = self.defs.get(name);
+        if def.is_none() {
+            log::warn!("Unknown effect {}", name);
+        }
+        def
+    }
+}
@@ -3,8 +3,11 @@ extern crate nalgebra as na;
 use crate::heightmap_phy;
 
 use crate::botdef;
+use crate::game_event::GameEvent;
 use crate::mobile;
 use crate::moddef;
+use crate::prefab::PrefabSpawn;
+use crate::stats::PlayerStats;
 use crate::utils;
 use fnv::{FnvHashMap, FnvHashSet};
 use na::{Point3, Vector3};
@@ -23,6 +26,18 @@ pub struct Player {
     pub team: u8,
     pub metal: f64,
     pub energy: f64,
+    ///Lost according to the Frame::win_condition, can't give orders anymore
+    pub defeated: bool,
+    pub stats: PlayerStats,
+    ///Highest botdef::BotDef::unlocks_tier of its completed units, 1 without any
+    #[serde(default = "default_tech_tier")]
+    pub tech_tier: u8,
+    ///Seat in the game, 0 for the host and 1 for the client. Stays the same when the team changes
+    #[serde(default)]
+    pub slot: u8,
+    ///Disconnected from the host, its units hold still until it reconnects
+    #[serde(default)]
+    pub away: bool,
 }
 
 impl Player {
@@ -34,10 +49,72 @@ impl Player {
             team: 0,
             metal: 500.0,
             energy: 500.0,
+            defeated: false,
+            stats: PlayerStats::new(),
+            tech_tier: 1,
+            slot: 0,
+            away: false,
         }
     }
 }
 
+fn default_tech_tier() -> u8 {
+    1
+}
+
+///Rules between the players of a team, chosen in the lobby
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Alliance {
+    ///Ennemy units out of the sight of the team are hidden
+    pub fog_of_war: bool,
+    ///Weapons also damage the units of the team
+    pub friendly_fire: bool,
+    ///Players give orders to the units of the whole team
+    pub shared_control: bool,
+}
+
+impl Alliance {
+    ///Teams can be changed during the first second
+    pub const TEAM_LOCK_FRAMES: i32 = 10;
+
+    pub fn controls(&self, player: &Player, kbot: &KBot) -> bool {
+        kbot.player_id == player.id || (self.shared_control && kbot.team == player.team)
+    }
+
+    ///Whether a unit of the team of the attacker takes the damage
+    pub fn hurts(&self, attacker_team: u8, kbot: &KBot) -> bool {
+        self.friendly_fire || kbot.team != attacker_team
+    }
+}
+
+impl Default for Alliance {
+    fn default() -> Self {
+        Alliance {
+            fog_of_war: false,
+            friendly_fire: true,
+            shared_control: false,
+        }
+    }
+}
+
+///How players are defeated
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum WinCondition {
+    ///Players lose when all their units with botdef::BotDef::commander are destroyed
+    CommanderDeath,
+    ///Players lose when they have no unit left
+    Annihilation,
+    ///Annihilation, and when the time is up the team with the best PlayerStats::score wins
+    TimedScore { frames: i32 },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct GameEnd {
+    pub frame: i32,
+    ///None for a draw
+    pub winner_team: Option<u8>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum FrameEventFromPlayer {
     RepairOrder {
@@ -45,6 +122,16 @@ pub enum FrameEventFromPlayer {
         selected: FnvHashSet<Id<KBot>>,
         to_repair: Id<KBot>,
     },
+    LoadOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        to_load: Id<KBot>,
+    },
+    UnloadOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        mouse_world_pos: Vector3<f32>,
+    },
     ConOrder {
         id: Id<Player>,
         selected: FnvHashSet<Id<KBot>>,
@@ -56,7 +143,168 @@ pub enum FrameEventFromPlayer {
         selected: FnvHashSet<Id<KBot>>,
         mouse_world_pos: Vector3<f32>,
     },
+    NukeOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        mouse_world_pos: Vector3<f32>,
+    },
+    ///Loop of the transports between the two points, see mobile::Command::Ferry
+    FerryOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        pickup: Vector3<f32>,
+        dropoff: Vector3<f32>,
+    },
+    DGunOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        mouse_world_pos: Vector3<f32>,
+    },
+    ///Patrol between the units and mouse_world_pos, or one more waypoint when append
+    PatrolOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        mouse_world_pos: Vector3<f32>,
+        append: bool,
+    },
+    GuardOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        to_guard: Id<KBot>,
+    },
+    ///Edit of the build queue of the selected factories
+    QueueOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        edit: mobile::QueueEdit,
+    },
+    ///None keeps the current stance
+    StanceOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        fire: Option<mobile::FireStance>,
+        movement: Option<mobile::MoveStance>,
+    },
+    ReclaimOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        to_reclaim: Id<KBot>,
+    },
+    ///Repair or reclaim of the units in a circle drawn on the ground
+    AreaOrder {
+        id: Id<Player>,
+        selected: FnvHashSet<Id<KBot>>,
+        kind: mobile::AreaKind,
+        center: Vector3<f32>,
+        radius: f32,
+    },
+    ///Debug, count units of botdef_id laid out around center
+    StressSpawn {
+        id: Id<Player>,
+        botdef_id: Id<botdef::BotDef>,
+        center: Vector3<f32>,
+        count: u32,
+        formation: Formation,
+    },
+    ///Debug, projectiles falling all over the map each second, 0 to stop
+    StressProjectiles {
+        id: Id<Player>,
+        per_sec: u32,
+    },
+    ///Handled by the manager, see manager::GameSpeed
+    SpeedVote {
+        id: Id<Player>,
+        speed: f32,
+        paused: bool,
+    },
+    ///Joins another team, only before Alliance::TEAM_LOCK_FRAMES
+    SetTeam {
+        id: Id<Player>,
+        team: u8,
+    },
+    ///Handled by the manager, relayed to the clients next to the frame, see Ping
+    Ping {
+        id: Id<Player>,
+        kind: PingKind,
+        position: Vector3<f32>,
+    },
+    ///Resources given to another player, capped by what the giver has
+    Gift {
+        id: Id<Player>,
+        to: Id<Player>,
+        metal: f64,
+        energy: f64,
+    },
+    ///Given by the host when the client of a player drops or reconnects
+    SetAway {
+        id: Id<Player>,
+        away: bool,
+    },
     ReplaceFrame(Frame),
+    ///Debug, change of a unit made in the entity inspector
+    DebugEdit {
+        id: Id<Player>,
+        kbot: Id<KBot>,
+        edit: DebugEdit,
+    },
+    ///Mission script, units of a prefab::Prefab given to the player, with the botdefs they need
+    SpawnPrefab {
+        id: Id<Player>,
+        bot_defs: Vec<botdef::BotDef>,
+        units: Vec<PrefabSpawn>,
+    },
+}
+
+impl FrameEventFromPlayer {
+    ///Player giving the order, None for events that are not orders
+    pub fn player_id(&self) -> Option<Id<Player>> {
+        match self {
+            FrameEventFromPlayer::RepairOrder { id, .. }
+            | FrameEventFromPlayer::LoadOrder { id, .. }
+            | FrameEventFromPlayer::UnloadOrder { id, .. }
+            | FrameEventFromPlayer::ConOrder { id, .. }
+            | FrameEventFromPlayer::MoveOrder { id, .. }
+            | FrameEventFromPlayer::NukeOrder { id, .. }
+            | FrameEventFromPlayer::DGunOrder { id, .. }
+            | FrameEventFromPlayer::FerryOrder { id, .. }
+            | FrameEventFromPlayer::PatrolOrder { id, .. }
+            | FrameEventFromPlayer::GuardOrder { id, .. }
+            | FrameEventFromPlayer::ReclaimOrder { id, .. }
+            | FrameEventFromPlayer::StanceOrder { id, .. }
+            | FrameEventFromPlayer::QueueOrder { id, .. }
+            | FrameEventFromPlayer::AreaOrder { id, .. }
+            | FrameEventFromPlayer::StressSpawn { id, .. }
+            | FrameEventFromPlayer::StressProjectiles { id, .. }
+            | FrameEventFromPlayer::DebugEdit { id, .. }
+            | FrameEventFromPlayer::SpawnPrefab { id, .. }
+            | FrameEventFromPlayer::SpeedVote { id, .. }
+            | FrameEventFromPlayer::Ping { id, .. }
+            | FrameEventFromPlayer::SetTeam { id, .. }
+            | FrameEventFromPlayer::Gift { id, .. } => Some(*id),
+            FrameEventFromPlayer::SetAway { .. } | FrameEventFromPlayer::ReplaceFrame(_) => None,
+        }
+    }
+
+    ///Units the order is given to, None for events that are not orders to units
+    pub fn selected_mut(&mut self) -> Option<&mut FnvHashSet<Id<KBot>>> {
+        match self {
+            FrameEventFromPlayer::RepairOrder { selected, .. }
+            | FrameEventFromPlayer::LoadOrder { selected, .. }
+            | FrameEventFromPlayer::UnloadOrder { selected, .. }
+            | FrameEventFromPlayer::ConOrder { selected, .. }
+            | FrameEventFromPlayer::MoveOrder { selected, .. }
+            | FrameEventFromPlayer::NukeOrder { selected, .. }
+            | FrameEventFromPlayer::DGunOrder { selected, .. }
+            | FrameEventFromPlayer::FerryOrder { selected, .. }
+            | FrameEventFromPlayer::PatrolOrder { selected, .. }
+            | FrameEventFromPlayer::GuardOrder { selected, .. }
+            | FrameEventFromPlayer::ReclaimOrder { selected, .. }
+            | FrameEventFromPlayer::StanceOrder { selected, .. }
+            | FrameEventFromPlayer::QueueOrder { selected, .. }
+            | FrameEventFromPlayer::AreaOrder { selected, .. } => Some(selected),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -96,6 +344,97 @@ impl ProfilerMap {
 pub struct DataToComputeNextFrame {
     pub old_frame: Frame,
     pub events: Vec<FrameEventFromPlayer>,
+    ///Not simulated, only carried to the remote clients
+    pub pings: Vec<Ping>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PingKind {
+    Attack,
+    Defend,
+    Assist,
+}
+
+///Map marker shown to the allies of its player, outside of the lockstep simulation
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Ping {
+    pub player_id: Id<Player>,
+    pub kind: PingKind,
+    pub position: Vector3<f32>,
+}
+
+///Value of a unit set by FrameEventFromPlayer::DebugEdit
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum DebugEdit {
+    ///Capped by the max life of the unit, 0 destroys it
+    Life(i32),
+    ///Put on the ground at x y
+    Position(Vector3<f32>),
+    ///Drops the current order and the move target
+    Stop,
+    Owner(Id<Player>),
+}
+
+///Layout of the units of FrameEventFromPlayer::StressSpawn
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Formation {
+    Square,
+    Line,
+    Ring,
+}
+
+impl Formation {
+    pub const ALL: [Formation; 3] = [Formation::Square, Formation::Line, Formation::Ring];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Formation::Square => "square",
+            Formation::Line => "line",
+            Formation::Ring => "ring",
+        }
+    }
+
+    ///Positions of count units around the center
+    pub fn offsets(&self, count: u32, spacing: f32) -> Vec<(f32, f32)> {
+        match self {
+            Formation::Square => {
+                let side = (count as f32).sqrt().ceil() as u32;
+                (0..count)
+                    .map(|i| {
+                        (
+                            ((i % side) as f32 - side as f32 / 2.0) * spacing,
+                            ((i / side) as f32 - side as f32 / 2.0) * spacing,
+                        )
+                    })
+                    .collect()
+            }
+            Formation::Line => (0..count)
+                .map(|i| ((i as f32 - count as f32 / 2.0) * spacing, 0.0))
+                .collect(),
+            Formation::Ring => {
+                //Concentric rings, each as full as its perimeter allows
+                let mut offsets = Vec::with_capacity(count as usize);
+                let mut radius = spacing * 4.0;
+                while offsets.len() < count as usize {
+                    let on_ring = ((std::f32::consts::PI * 2.0 * radius / spacing) as usize)
+                        .min(count as usize - offsets.len());
+                    for i in 0..on_ring {
+                        let angle = i as f32 / on_ring as f32 * std::f32::consts::PI * 2.0;
+                        offsets.push((angle.cos() * radius, angle.sin() * radius));
+                    }
+                    radius += spacing;
+                }
+                offsets
+            }
+        }
+    }
+}
+
+///Remote player events, with the number of the frame their player was seeing
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StampedInputs {
+    pub frame_number: i32,
+    pub events: Vec<FrameEventFromPlayer>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -107,20 +446,34 @@ pub struct FrameUpdate {
 pub struct Frame {
     // relevant to send to client on change
     pub kinematic_projectiles: FnvHashMap<Id<KinematicProjectile>, KinematicProjectile>,
+    pub missiles: FnvHashMap<Id<StrategicMissile>, StrategicMissile>,
     pub arrows: Vec<Arrow>,
     pub heightmap_phy: Option<heightmap_phy::HeightmapPhy>,
     pub players: FnvHashMap<Id<Player>, Player>,
     pub kbots: FnvHashMap<Id<KBot>, KBot>,
     pub moddef: moddef::ModDef,
+    pub win_condition: WinCondition,
+    #[serde(default)]
+    pub alliance: Alliance,
+    pub game_end: Option<GameEnd>,
+    ///Set by FrameEventFromPlayer::StressProjectiles
+    pub stress_projectiles_per_sec: u32,
     // relevant to send to client once
     pub bot_defs: FnvHashMap<Id<botdef::BotDef>, botdef::BotDef>,
     // relevant to send to client always
     pub number: i32,
-    pub explosions: Vec<ExplosionEvent>,
+    pub effects: Vec<EffectEvent>,
+    pub beams: Vec<BeamEvent>,
+    pub craters: Vec<CraterEvent>,
     pub kbots_dead: FnvHashSet<Id<KBot>>,
     pub kinematic_projectiles_dead: Vec<Id<KinematicProjectile>>,
     pub kinematic_projectiles_birth: Vec<KinematicProjectile>,
     pub frame_profiler: ProfilerMap,
+    ///What happened during the frame, for the client
+    pub events: Vec<GameEvent>,
+    ///Drawn once per game, the ids of the units and projectiles created by the simulation come
+    ///from it and the frame number, see ids_seed
+    pub id_seed: u64,
 }
 
 impl Frame {
@@ -129,16 +482,99 @@ impl Frame {
             number: 0,
             players: FnvHashMap::default(),
             moddef: moddef::ModDef::new(),
+            win_condition: WinCondition::Annihilation,
+            alliance: Alliance::default(),
+            game_end: None,
+            stress_projectiles_per_sec: 0,
             kbots: FnvHashMap::default(),
             kinematic_projectiles: FnvHashMap::default(),
+            missiles: FnvHashMap::default(),
             arrows: Vec::new(),
-            explosions: Vec::new(),
+            effects: Vec::new(),
+            beams: Vec::new(),
+            craters: Vec::new(),
+            events: Vec::new(),
             heightmap_phy: None,
             frame_profiler: ProfilerMap::new(),
             kbots_dead: FnvHashSet::default(),
             kinematic_projectiles_dead: Vec::new(),
             kinematic_projectiles_birth: Vec::new(),
             bot_defs: FnvHashMap::default(),
+            id_seed: utils::rand_seed(),
+        }
+    }
+
+    ///Of the ids drawn while simulating this frame. The same on every peer, when playing a replay
+    ///back and from a snapshot, so that the recorded orders find the units they name.
+    pub fn ids_seed(&self) -> u64 {
+        self.id_seed ^ (self.number as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+
+    ///Two teams facing each other, used by play and the dedicated server
+    pub fn example_game(
+        heightmap_phy: heightmap_phy::HeightmapPhy,
+        unit: botdef::BotDef,
+        building: botdef::BotDef,
+    ) -> Self {
+        let mut player_me = Player::new();
+        let mut player_ennemy = Player::new();
+        player_ennemy.team = 1;
+        player_ennemy.slot = 1;
+
+        let mut kbots = FnvHashMap::default();
+
+        for i in (100..300).step_by(4) {
+            for j in (100..500).step_by(4) {
+                let m =
+                    mobile::KBot::new(Point3::new(i as f32, j as f32, 100.0), &unit, player_me.id);
+                player_me.kbots.insert(m.id);
+                kbots.insert(m.id, m);
+            }
+        }
+
+        for i in (320..520).step_by(4) {
+            for j in (100..500).step_by(4) {
+                let mut m = mobile::KBot::new(
+                    Point3::new(i as f32, j as f32, 100.0),
+                    &unit,
+                    player_ennemy.id,
+                );
+                m.team = 1;
+                player_ennemy.kbots.insert(m.id);
+                kbots.insert(m.id, m);
+            }
+        }
+
+        log::info!("Starting a game with {} bots", kbots.len());
+
+        let mut players = FnvHashMap::default();
+        players.insert(player_me.id, player_me);
+        players.insert(player_ennemy.id, player_ennemy);
+
+        let mut bot_defs = FnvHashMap::default();
+        bot_defs.insert(unit.id, unit);
+        bot_defs.insert(building.id, building);
+
+        let moddef = moddef::ModDef {
+            units_id: bot_defs.keys().copied().collect(),
+            con_map: FnvHashMap::default(),
+        };
+
+        Frame {
+            players,
+            moddef,
+            kbots,
+            heightmap_phy: Some(heightmap_phy),
+            bot_defs,
+            ..Frame::new()
+        }
+    }
+
+    ///Makes the botdef buildable in this game, like the tech examples
+    pub fn add_bot_def(&mut self, botdef: botdef::BotDef) {
+        if !self.moddef.units_id.contains(&botdef.id) {
+            self.moddef.units_id.push(botdef.id);
         }
+        self.bot_defs.insert(botdef.id, botdef);
     }
 }
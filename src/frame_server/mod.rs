@@ -1,14 +1,23 @@
 use crate::frame::*;
 
 use crate::botdef;
+use crate::game_event::{GameEvent, Resource};
 use crate::heightmap_phy;
 use crate::mobile::*;
 use crate::utils::*;
 use crossbeam_channel::{Receiver, Sender};
 use fnv::{FnvHashMap, FnvHashSet};
 use na::{Matrix4, Point3, Vector2, Vector3};
+use rayon::prelude::*;
 use std::time::Instant;
 
+///Frames between two searches of an idle builder for a construction to assist
+const AUTO_ASSIST_PERIOD: u64 = 10;
+///Fraction of its metal cost repairing a unit from 0 to full life takes
+const REPAIR_COST_RATIO: f64 = 0.5;
+///Frames between two searches of an idle unit for an ennemy to chase
+const CHASE_PERIOD: u64 = 10;
+
 pub enum ToFrameServer {
     DataToComputeNextFrame(DataToComputeNextFrame),
 }
@@ -21,6 +30,8 @@ pub struct FrameServerCache {
     pub grid: Vec<Vec<Id<KBot>>>,
     pub small_grid: Vec<Vec<Id<KBot>>>,
     pub heightmap_phy: Option<heightmap_phy::HeightmapPhy>,
+    ///Per unit work of a tick, results are joined in iteration order to stay deterministic
+    pub threadpool: rayon::ThreadPool,
 }
 
 impl FrameServerCache {
@@ -37,6 +48,7 @@ impl FrameServerCache {
                         ToFrameServer::DataToComputeNextFrame(DataToComputeNextFrame {
                             old_frame,
                             events,
+                            ..
                         }) => {
                             let next_frame = fsc.next_frame(old_frame, events);
                             // let dur = utils::time(|| {
@@ -61,10 +73,15 @@ impl FrameServerCache {
             grid: Vec::new(),
             small_grid: Vec::new(),
             heightmap_phy: None,
+            threadpool: rayon::ThreadPoolBuilder::new()
+                .thread_name(|i| format!("simulation {}", i))
+                .build()
+                .unwrap(),
         }
     }
 
     pub fn next_frame(&mut self, old_frame: Frame, events: Vec<FrameEventFromPlayer>) -> Frame {
+        profile_scope!("next_frame");
         let mut frame_profiler = ProfilerMap::new();
         let start = std::time::Instant::now();
         log::trace!("Received frame {} to compute next frame", old_frame.number);
@@ -85,14 +102,44 @@ impl FrameServerCache {
 
         let mut frame = replacer.unwrap_or(old_frame);
         frame.number += 1;
+        seed_ids(Some(frame.ids_seed()));
         frame.kbots_dead.clear();
         frame.heightmap_phy = None;
-        frame.explosions.clear();
+        frame.effects.clear();
+        frame.beams.clear();
+        frame.craters.clear();
+        frame.events.clear();
         frame.kinematic_projectiles_birth.clear();
         frame.kinematic_projectiles_dead.clear();
+        //To report the stocks that run out, see detect_depleted
+        let stocks: Vec<_> = frame
+            .players
+            .values()
+            .map(|player| (player.id, player.metal, player.energy))
+            .collect();
 
         //TODO order event by player then by type before doing any effect. This step should be deterministic
-        for event in events {
+        for mut event in events {
+            //Spectators and defeated players can't give orders
+            if let Some(player_id) = event.player_id() {
+                let player = match frame.players.get(&player_id) {
+                    Some(player) if !player.defeated => player,
+                    _ => {
+                        log::warn!("Ignoring order from {}", player_id);
+                        continue;
+                    }
+                };
+                //Only units of the player, or of its team with shared control
+                if let Some(selected) = event.selected_mut() {
+                    let (kbots, alliance) = (&frame.kbots, &frame.alliance);
+                    selected.retain(|id| {
+                        kbots
+                            .get(id)
+                            .map(|kbot| alliance.controls(player, kbot))
+                            .unwrap_or(false)
+                    });
+                }
+            }
             match event {
                 FrameEventFromPlayer::MoveOrder {
                     id,
@@ -111,7 +158,39 @@ impl FrameServerCache {
                     //TODO Validate selected are owned by id && botdef_id is constructable by at least 1 selected
 
                     let botdef = frame.bot_defs.get(&botdef_id).unwrap();
-                    let mut m = KBot::new(Point3::from(mouse_world_pos), botdef, id);
+                    if botdef.commander {
+                        log::warn!("Player {} can't build the commander {}", id, botdef.id);
+                        continue;
+                    }
+                    let tech_tier = frame.players.get(&id).unwrap().tech_tier;
+                    if botdef.tier > tech_tier {
+                        log::warn!(
+                            "Player {} at tier {} can't build {} of tier {}",
+                            id,
+                            tech_tier,
+                            botdef.id,
+                            botdef.tier
+                        );
+                        continue;
+                    }
+                    let (x, y) = botdef.snap(mouse_world_pos.x, mouse_world_pos.y);
+                    let can_be_placed = self
+                        .heightmap_phy
+                        .as_ref()
+                        .map(|h| botdef.can_be_placed(h, x, y))
+                        .unwrap_or(false);
+                    if !can_be_placed {
+                        log::warn!("Can't place {} at {:?}", botdef.id, mouse_world_pos);
+                        continue;
+                    }
+
+                    let z = self
+                        .heightmap_phy
+                        .as_ref()
+                        .map(|h| h.z(x, y))
+                        .unwrap_or(mouse_world_pos.z);
+                    let position = Point3::new(x, y, z);
+                    let mut m = KBot::new(position, botdef, id);
                     m.team = frame.players.get(&id).unwrap().team;
                     m.con_completed = std::f32::MIN_POSITIVE;
                     m.life = 1;
@@ -138,10 +217,422 @@ impl FrameServerCache {
                         }
                     }
                 }
+
+                FrameEventFromPlayer::NukeOrder {
+                    selected,
+                    mouse_world_pos,
+                    ..
+                } => {
+                    //Only one launcher fires per order
+                    let mut launched = false;
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            if launched || kbot.stockpile == 0 {
+                                continue;
+                            }
+                            let botdef = frame.bot_defs.get(&kbot.botdef_id).unwrap();
+                            if let botdef::Strategic::Nuke { damage, radius, .. } = botdef.strategic
+                            {
+                                kbot.stockpile -= 1;
+                                let from = kbot.position + Vector3::new(0.0, 0.0, 2.0);
+                                let to = Point3::from(mouse_world_pos);
+                                let distance = (to.coords - from.coords).magnitude();
+                                let missile = StrategicMissile {
+                                    id: rand_id(),
+                                    owner: kbot.id,
+                                    team: kbot.team,
+                                    from,
+                                    to,
+                                    launch_frame: frame.number,
+                                    flight_frames: ((distance / 5.0) as i32).max(30),
+                                    apex: (distance * 0.5).max(60.0),
+                                    damage,
+                                    radius,
+                                };
+                                frame.effects.push(EffectEvent {
+                                    position: from,
+                                    name: "small_explosion".to_owned(),
+                                });
+                                frame.missiles.insert(missile.id, missile);
+                                launched = true;
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::DGunOrder {
+                    selected,
+                    mouse_world_pos,
+                    ..
+                } => {
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            let botdef = frame.bot_defs.get(&kbot.botdef_id).unwrap();
+                            if botdef.dgun.is_some() {
+                                kbot.current_command = Command::DGun(Point3::from(mouse_world_pos));
+                                kbot.chase_from = None;
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::StressSpawn {
+                    id,
+                    botdef_id,
+                    center,
+                    count,
+                    formation,
+                } => {
+                    let (botdef, heightmap) =
+                        match (frame.bot_defs.get(&botdef_id), &self.heightmap_phy) {
+                            (Some(botdef), Some(heightmap)) => (botdef, heightmap),
+                            _ => continue,
+                        };
+                    let player = frame.players.get_mut(&id).unwrap();
+                    for (dx, dy) in formation.offsets(count, botdef.radius * 3.0) {
+                        let x = (center.x + dx).max(0.0).min(heightmap.width as f32 - 1.0);
+                        let y = (center.y + dy).max(0.0).min(heightmap.height as f32 - 1.0);
+                        let mut kbot = KBot::new(Point3::new(x, y, heightmap.z(x, y)), botdef, id);
+                        kbot.team = player.team;
+                        player.kbots.insert(kbot.id);
+                        frame.kbots.insert(kbot.id, kbot);
+                    }
+                    log::info!("Stress spawn of {} {}", count, botdef.file_path);
+                }
+
+                FrameEventFromPlayer::SpawnPrefab {
+                    id,
+                    bot_defs,
+                    units,
+                } => {
+                    let heightmap = match &self.heightmap_phy {
+                        Some(heightmap) => heightmap,
+                        None => continue,
+                    };
+                    for botdef in bot_defs {
+                        if !frame.moddef.units_id.contains(&botdef.id) {
+                            frame.moddef.units_id.push(botdef.id);
+                        }
+                        frame.bot_defs.entry(botdef.id).or_insert(botdef);
+                    }
+                    let player = frame.players.get_mut(&id).unwrap();
+                    for unit in units.iter() {
+                        if let Some(botdef) = frame.bot_defs.get(&unit.botdef_id) {
+                            let kbot = unit.kbot(botdef, heightmap, player);
+                            player.kbots.insert(kbot.id);
+                            frame.kbots.insert(kbot.id, kbot);
+                        }
+                    }
+                    log::info!("Prefab of {} units for player {}", units.len(), id);
+                }
+
+                FrameEventFromPlayer::StressProjectiles { per_sec, .. } => {
+                    frame.stress_projectiles_per_sec = per_sec;
+                }
+
+                FrameEventFromPlayer::DebugEdit { kbot, edit, .. } => {
+                    let unit = match frame.kbots.get_mut(&kbot) {
+                        Some(unit) => unit,
+                        None => continue,
+                    };
+                    match edit {
+                        DebugEdit::Life(life) => {
+                            let max_life = frame
+                                .bot_defs
+                                .get(&unit.botdef_id)
+                                .map(|botdef| botdef.max_life)
+                                .unwrap_or(life);
+                            unit.life = life.max(0).min(max_life);
+                        }
+                        DebugEdit::Position(position) => {
+                            let (x, y) = match &self.heightmap_phy {
+                                Some(heightmap) => (
+                                    position.x.max(0.0).min(heightmap.width as f32 - 1.0),
+                                    position.y.max(0.0).min(heightmap.height as f32 - 1.0),
+                                ),
+                                None => (position.x, position.y),
+                            };
+                            let z = self
+                                .heightmap_phy
+                                .as_ref()
+                                .map(|h| h.z(x, y))
+                                .unwrap_or(position.z);
+                            unit.position = Point3::new(x, y, z);
+                            unit.speed = Vector3::new(0.0, 0.0, 0.0);
+                            unit.move_target = None;
+                        }
+                        DebugEdit::Stop => {
+                            unit.move_target = None;
+                            unit.chase_from = None;
+                            unit.current_command = Command::None;
+                        }
+                        DebugEdit::Owner(owner) => {
+                            let team = match frame.players.get(&owner) {
+                                Some(player) => player.team,
+                                None => continue,
+                            };
+                            let previous = unit.player_id;
+                            unit.player_id = owner;
+                            unit.team = team;
+                            if let Some(player) = frame.players.get_mut(&previous) {
+                                player.kbots.remove(&kbot);
+                            }
+                            if let Some(player) = frame.players.get_mut(&owner) {
+                                player.kbots.insert(kbot);
+                            }
+                        }
+                    }
+                    log::info!("Debug edit of unit {}", kbot);
+                }
+
+                FrameEventFromPlayer::SetTeam { id, team } => {
+                    if frame.number > Alliance::TEAM_LOCK_FRAMES {
+                        log::warn!("Player {} can't change team after the start", id);
+                        continue;
+                    }
+                    let player = frame.players.get_mut(&id).unwrap();
+                    player.team = team;
+                    for kbot_id in player.kbots.iter() {
+                        if let Some(kbot) = frame.kbots.get_mut(kbot_id) {
+                            kbot.team = team;
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::SetAway { id, away } => {
+                    let player = match frame.players.get_mut(&id) {
+                        Some(player) => player,
+                        None => continue,
+                    };
+                    player.away = away;
+                    if away {
+                        for kbot_id in player.kbots.iter() {
+                            if let Some(kbot) = frame.kbots.get_mut(kbot_id) {
+                                kbot.move_target = None;
+                                kbot.chase_from = None;
+                                kbot.current_command = Command::None;
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::Gift {
+                    id,
+                    to,
+                    metal,
+                    energy,
+                } => {
+                    if id == to {
+                        continue;
+                    }
+                    let giver = frame.players.get_mut(&id).unwrap();
+                    let metal = metal.max(0.0).min(giver.metal);
+                    let energy = energy.max(0.0).min(giver.energy);
+                    giver.metal -= metal;
+                    giver.energy -= energy;
+                    match frame.players.get_mut(&to) {
+                        Some(receiver) => {
+                            receiver.metal += metal;
+                            receiver.energy += energy;
+                        }
+                        //Nobody to receive, given back
+                        None => {
+                            let giver = frame.players.get_mut(&id).unwrap();
+                            giver.metal += metal;
+                            giver.energy += energy;
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::LoadOrder {
+                    id,
+                    selected,
+                    to_load,
+                } => {
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            if frame.bot_defs.get(&kbot.botdef_id).unwrap().is_transport() {
+                                kbot.current_command = Command::Load(to_load)
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::UnloadOrder {
+                    id,
+                    selected,
+                    mouse_world_pos,
+                } => {
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            if !kbot.cargo.is_empty() {
+                                kbot.current_command =
+                                    Command::Unload(Point3::from(mouse_world_pos))
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::FerryOrder {
+                    selected,
+                    pickup,
+                    dropoff,
+                    ..
+                } => {
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            let botdef = frame.bot_defs.get(&kbot.botdef_id).unwrap();
+                            if botdef.is_transport() {
+                                kbot.current_command = Command::Ferry {
+                                    pickup: Point3::from(pickup),
+                                    dropoff: Point3::from(dropoff),
+                                    unloading: !kbot.cargo.is_empty(),
+                                };
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::PatrolOrder {
+                    selected,
+                    mouse_world_pos,
+                    append,
+                    ..
+                } => {
+                    let waypoint = Point3::from(mouse_world_pos);
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            match &mut kbot.current_command {
+                                Command::Patrol { waypoints, .. } if append => {
+                                    waypoints.push(waypoint)
+                                }
+                                //Back and forth from where the unit stands
+                                command => {
+                                    *command = Command::Patrol {
+                                        waypoints: vec![kbot.position, waypoint],
+                                        next: 1,
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::QueueOrder { selected, edit, .. } => {
+                    let buildable = |item: &Id<botdef::BotDef>| {
+                        frame
+                            .bot_defs
+                            .get(item)
+                            .map(|botdef| !botdef.commander)
+                            .unwrap_or(false)
+                    };
+                    let known = match &edit {
+                        QueueEdit::Push(item) => buildable(item),
+                        QueueEdit::Replace(items) => items.iter().all(buildable),
+                        _ => true,
+                    };
+                    if !known {
+                        log::warn!("Unknown or commander botdef in {:?}", edit);
+                        continue;
+                    }
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            let is_factory =
+                                frame.bot_defs.get(&kbot.botdef_id).unwrap().is_factory();
+                            if is_factory {
+                                kbot.build_queue.edit(&edit);
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::StanceOrder {
+                    selected,
+                    fire,
+                    movement,
+                    ..
+                } => {
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            if let Some(fire) = fire {
+                                kbot.fire_stance = fire;
+                            }
+                            if let Some(movement) = movement {
+                                kbot.move_stance = movement;
+                                kbot.chase_from = None;
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::ReclaimOrder {
+                    selected,
+                    to_reclaim,
+                    ..
+                } => {
+                    //Cargo would be lost with its transport
+                    let team = match frame.kbots.get(&to_reclaim) {
+                        Some(kbot) if kbot.cargo.is_empty() => kbot.team,
+                        _ => continue,
+                    };
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            let is_builder =
+                                frame.bot_defs.get(&kbot.botdef_id).unwrap().build_power > 0.0;
+                            if is_builder && kbot.team == team && kbot.id != to_reclaim {
+                                kbot.current_command = Command::Reclaim(to_reclaim)
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::AreaOrder {
+                    selected,
+                    kind,
+                    center,
+                    radius,
+                    ..
+                } => {
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            let is_builder =
+                                frame.bot_defs.get(&kbot.botdef_id).unwrap().build_power > 0.0;
+                            if is_builder {
+                                kbot.current_command = Command::Area {
+                                    kind,
+                                    center: Point3::from(center),
+                                    radius,
+                                    target: None,
+                                }
+                            }
+                        }
+                    }
+                }
+
+                FrameEventFromPlayer::GuardOrder {
+                    selected, to_guard, ..
+                } => {
+                    let team = match frame.kbots.get(&to_guard) {
+                        Some(kbot) => kbot.team,
+                        None => continue,
+                    };
+                    for selected_raw_id in &selected {
+                        for kbot in frame.kbots.get_mut(selected_raw_id) {
+                            if kbot.team == team && kbot.id != to_guard {
+                                kbot.current_command = Command::Guard(to_guard)
+                            }
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
+        if let (true, Some(heightmap)) = (frame.stress_projectiles_per_sec > 0, &self.heightmap_phy)
+        {
+            rain_projectiles(&mut frame, heightmap);
+        }
+
         frame_profiler.add("1 handle_events", start.elapsed());
 
         let mut arrows = Vec::new();
@@ -162,12 +653,47 @@ impl FrameServerCache {
                 &mut frame.players,
                 &mut self.grid,
                 &mut self.small_grid,
-                &mut frame.explosions,
+                &mut frame.effects,
+                &mut frame.beams,
+                &mut frame.missiles,
+                &mut frame.craters,
+                &mut frame.events,
                 &frame.bot_defs,
+                &frame.alliance,
+                &self.threadpool,
             );
         }
+
+        if let Some(heightmap) = &mut self.heightmap_phy {
+            for crater in frame.craters.iter() {
+                heightmap.crater(
+                    crater.position.x,
+                    crater.position.y,
+                    crater.radius,
+                    crater.depth,
+                );
+                //Units in the crater fall to the new ground on next movement
+                for kbot in frame.kbots.values_mut() {
+                    if (kbot.position.coords - crater.position.coords)
+                        .xy()
+                        .magnitude()
+                        < crater.radius
+                    {
+                        kbot.grounded = false;
+                    }
+                }
+            }
+        }
+        detect_depleted(&mut frame, &stocks);
+        update_tech_tiers(&mut frame);
+        if frame.game_end.is_none() {
+            record_stats(&mut frame);
+            check_victory(&mut frame);
+        }
+
         frame_profiler.add("0 update_units", start_update_units.elapsed());
         frame_profiler.add("total", start.elapsed());
+        seed_ids(None);
         Frame {
             number: frame.number,
             frame_profiler,
@@ -177,6 +703,204 @@ impl FrameServerCache {
     }
 }
 
+///Stress test of the projectiles, scattered the same way on every peer
+fn rain_projectiles(frame: &mut Frame, heightmap: &heightmap_phy::HeightmapPhy) {
+    //Frames are a tenth of a second apart
+    let per_sec = frame.stress_projectiles_per_sec as i64;
+    let n = frame.number as i64;
+    let count = per_sec * (n + 1) / 10 - per_sec * n / 10;
+    for i in 0..count {
+        //splitmix64 of the frame, projectile and coordinate, in [0, 1)
+        let hash = |k: i64| {
+            let mut z = (((n * 1_000_003 + i) * 2 + k) as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            ((z ^ (z >> 31)) >> 40) as f32 / (1u64 << 24) as f32
+        };
+        let x = hash(0) * (heightmap.width as f32 - 1.0);
+        let y = hash(1) * (heightmap.height as f32 - 1.0);
+        let proj = KinematicProjectile {
+            id: rand_id(),
+            //Moved by this frame already, like the ones shot last frame
+            birth_frame: frame.number - 1,
+            death_frame: frame.number + 10,
+            position_at_birth: Point3::new(x, y, heightmap.z(x, y) + 15.0),
+            speed_per_frame_at_birth: Vector3::new(0.0, 0.0, -1.0),
+            accel_per_frame: Vector3::new(0.0, 0.0, -0.08),
+            radius: 0.25,
+            //Shot by no unit
+            owner: rand_id(),
+            position_cache: Vec::new(),
+            speed_cache: Vec::new(),
+        };
+        frame.kinematic_projectiles_birth.push(proj.clone());
+        frame.kinematic_projectiles.insert(proj.id, proj);
+    }
+}
+
+///Tier of each player from its completed upgrade buildings, lost again when they die
+fn update_tech_tiers(frame: &mut Frame) {
+    let mut tiers = FnvHashMap::<Id<Player>, u8>::default();
+    for kbot in frame
+        .kbots
+        .values()
+        .filter(|kbot| kbot.con_completed >= 1.0)
+    {
+        let unlocks_tier = frame.bot_defs.get(&kbot.botdef_id).unwrap().unlocks_tier;
+        let tier = tiers.entry(kbot.player_id).or_insert(1);
+        *tier = (*tier).max(unlocks_tier);
+    }
+    for player in frame.players.values_mut() {
+        let tier = tiers.get(&player.id).copied().unwrap_or(1);
+        if tier != player.tech_tier {
+            log::info!("Player {} reached tech tier {}", player.id, tier);
+            player.tech_tier = tier;
+        }
+    }
+}
+
+///Stocks emptied during the frame, from those of the players before it. A stalled stock stays
+///empty, so it is reported once until it is refilled.
+fn detect_depleted(frame: &mut Frame, stocks: &[(Id<Player>, f64, f64)]) {
+    for (id, metal, energy) in stocks {
+        if let Some(player) = frame.players.get(id) {
+            if *metal > 0.0 && player.metal <= 0.0 {
+                frame.events.push(GameEvent::ResourceDepleted {
+                    player_id: *id,
+                    resource: Resource::Metal,
+                });
+            }
+            if *energy > 0.0 && player.energy <= 0.0 {
+                frame.events.push(GameEvent::ResourceDepleted {
+                    player_id: *id,
+                    resource: Resource::Energy,
+                });
+            }
+        }
+    }
+}
+
+fn record_stats(frame: &mut Frame) {
+    profile_scope!("record_stats");
+    //Units in transports are still part of the army
+    let mut army_values = FnvHashMap::<Id<Player>, f32>::default();
+    for kbot in frame
+        .kbots
+        .values()
+        .flat_map(|kbot| std::iter::once(kbot).chain(kbot.cargo.iter()))
+    {
+        let metal_cost = frame
+            .bot_defs
+            .get(&kbot.botdef_id)
+            .unwrap()
+            .tier_metal_cost();
+        *army_values.entry(kbot.player_id).or_insert(0.0) +=
+            metal_cost * kbot.con_completed.min(1.0);
+    }
+
+    for player in frame.players.values_mut() {
+        let stats = &mut player.stats;
+        if stats.tick_count() == 0 {
+            stats.last_metal = player.metal;
+            stats.last_metal_spent = stats.metal_spent;
+        }
+        let income =
+            (player.metal - stats.last_metal) + (stats.metal_spent - stats.last_metal_spent);
+        stats.last_metal = player.metal;
+        stats.last_metal_spent = stats.metal_spent;
+
+        let row = [
+            stats.units_built as f32,
+            stats.units_lost as f32,
+            player.metal as f32,
+            income as f32,
+            army_values.get(&player.id).copied().unwrap_or(0.0),
+            stats.damage_dealt as f32,
+        ];
+        stats.push_row(row);
+    }
+}
+
+///Flag defeated players according to frame.win_condition and end the game when one team is left
+fn check_victory(frame: &mut Frame) {
+    profile_scope!("check_victory");
+    //Sandbox games with a single team never end
+    let teams: FnvHashSet<u8> = frame.players.values().map(|p| p.team).collect();
+    if teams.len() < 2 {
+        return;
+    }
+
+    //Units in transports are still alive
+    let mut alive = FnvHashMap::<Id<Player>, (u32, u32)>::default();
+    for kbot in frame
+        .kbots
+        .values()
+        .flat_map(|kbot| std::iter::once(kbot).chain(kbot.cargo.iter()))
+    {
+        let count = alive.entry(kbot.player_id).or_insert((0, 0));
+        count.0 += 1;
+        if frame.bot_defs.get(&kbot.botdef_id).unwrap().commander {
+            count.1 += 1;
+        }
+    }
+
+    let mut newly_defeated = Vec::new();
+    for player in frame.players.values_mut().filter(|p| !p.defeated) {
+        let (units, commanders) = alive.get(&player.id).copied().unwrap_or((0, 0));
+        let defeated = match frame.win_condition {
+            WinCondition::CommanderDeath => commanders == 0,
+            WinCondition::Annihilation | WinCondition::TimedScore { .. } => units == 0,
+        };
+        if defeated {
+            log::info!("Player {} is defeated", player.id);
+            player.defeated = true;
+            newly_defeated.push(player.id);
+        }
+    }
+
+    //The army of a defeated player self destructs
+    for kbot in frame.kbots.values_mut() {
+        if newly_defeated.contains(&kbot.player_id) {
+            kbot.life = 0;
+        }
+    }
+
+    let teams_alive: FnvHashSet<u8> = frame
+        .players
+        .values()
+        .filter(|p| !p.defeated)
+        .map(|p| p.team)
+        .collect();
+    let time_is_up = match frame.win_condition {
+        WinCondition::TimedScore { frames } => frame.number >= frames,
+        _ => false,
+    };
+
+    if teams_alive.len() <= 1 {
+        frame.game_end = Some(GameEnd {
+            frame: frame.number,
+            winner_team: teams_alive.iter().next().copied(),
+        });
+    } else if time_is_up {
+        let mut team_scores = FnvHashMap::<u8, f64>::default();
+        for player in frame.players.values().filter(|p| !p.defeated) {
+            *team_scores.entry(player.team).or_insert(0.0) += player.stats.score();
+        }
+        let best_team = team_scores
+            .iter()
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(team, _)| *team);
+        frame.game_end = Some(GameEnd {
+            frame: frame.number,
+            winner_team: best_team,
+        });
+    }
+
+    if let Some(game_end) = &frame.game_end {
+        log::info!("Game ended, winner team {:?}", game_end.winner_team);
+    }
+}
+
 pub fn update_mobile_target(
     mouse_world_pos: Vector3<f32>,
     selected: &FnvHashSet<Id<KBot>>,
@@ -246,6 +970,7 @@ pub fn update_mobile_target(
             log::trace!("New order for {}", mobile.id);
             mobile.move_target = Some(Point3::<f32>::from(spot[*spot_id]));
             mobile.current_command = Command::None;
+            mobile.chase_from = None;
         }
     }
 }
@@ -263,9 +988,16 @@ pub fn update_units(
     players: &mut FnvHashMap<Id<Player>, Player>,
     grid: &mut Vec<Vec<Id<KBot>>>,
     small_grid: &mut Vec<Vec<Id<KBot>>>,
-    explosions: &mut Vec<ExplosionEvent>,
+    effects: &mut Vec<EffectEvent>,
+    beams: &mut Vec<BeamEvent>,
+    missiles: &mut FnvHashMap<Id<StrategicMissile>, StrategicMissile>,
+    craters: &mut Vec<CraterEvent>,
+    events: &mut Vec<GameEvent>,
     bot_defs: &FnvHashMap<Id<botdef::BotDef>, botdef::BotDef>,
+    alliance: &Alliance,
+    threadpool: &rayon::ThreadPool,
 ) {
+    profile_scope!("update_units");
     let start = std::time::Instant::now();
     let cell_size = 4;
     let grid_w = (heightmap_phy.width / cell_size) as usize;
@@ -310,6 +1042,7 @@ pub fn update_units(
 
     //AABB for kbot and proj
     {
+        profile_scope!("small_grid and projectiles");
         let start = std::time::Instant::now();
         let cell_size = 4;
         let grid_w = (heightmap_phy.width / cell_size) as usize;
@@ -362,6 +1095,10 @@ pub fn update_units(
         //Projectile move compute
         {
             for proj in kinematic_projectiles.values_mut() {
+                let owner = kbots.get(&proj.owner);
+                let owner_bonus = owner.map(|owner| owner.veterancy_bonus()).unwrap_or(1.0);
+                let owner_player = owner.map(|owner| owner.player_id);
+                let owner_team = owner.map(|owner| owner.team);
                 let current_pos = proj.position_at(frame_count - 1);
                 let next_pos = proj.position_at(frame_count);
 
@@ -392,6 +1129,13 @@ pub fn update_units(
 
                         'bot_test: for kbot_id in kbots_in_proximity.iter() {
                             let kbot = kbots.get_mut(kbot_id).unwrap();
+                            //Goes through allies without friendly fire
+                            let spared = owner_team
+                                .map(|team| !alliance.hurts(team, kbot))
+                                .unwrap_or(false);
+                            if spared {
+                                continue 'bot_test;
+                            }
                             let distance_to_target =
                                 (kbot.position.coords - current_interp).magnitude();
 
@@ -399,12 +1143,18 @@ pub fn update_units(
                             let kbot_radius = bot_defs.get(&kbot.botdef_id).unwrap().radius;
                             if distance_to_target < (kbot_radius + proj.radius) {
                                 //Colission between Kbot and projectile
-                                kbot.life = (kbot.life - 10).max(0);
+                                let dealt = kbot.take_damage(10, proj.owner, owner_bonus);
+                                if let Some(player) =
+                                    owner_player.and_then(|id| players.get_mut(&id))
+                                {
+                                    player.stats.damage_dealt += dealt as i64;
+                                }
                                 proj.death_frame = frame_count;
-                                explosions.push(ExplosionEvent {
+                                events.push(GameEvent::ProjectileImpact {
                                     position: Point3::from(current_interp),
-                                    size: 0.5,
-                                    life_time: 0.8,
+                                    target: kbot.id,
+                                    player_id: kbot.player_id,
+                                    damage: dealt,
                                 });
                                 break 'interp;
                             }
@@ -426,6 +1176,7 @@ pub fn update_units(
 
     //Projectile fire compute
     {
+        profile_scope!("kbot_fire");
         let teams: FnvHashSet<_> = players.values().map(|p| p.team).collect();
 
         let start = std::time::Instant::now();
@@ -448,45 +1199,69 @@ pub fn update_units(
         let start = std::time::Instant::now();
         struct Shot {
             bot: Id<KBot>,
+            target_id: Id<KBot>,
             target: Vector3<f32>,
         };
 
-        let mut shots = Vec::new();
-
-        for (me, me_kbot) in kbots.iter() {
-            if me_kbot.con_completed == 1.0 {
-                let grid_pos = grid_pos(me_kbot);
+        //Targets are searched in parallel, shots are then applied in order
+        let grid: &Vec<Vec<Id<KBot>>> = grid;
+        let kbots_ref: &FnvHashMap<Id<KBot>, KBot> = kbots;
+        let shots: Vec<Shot> = threadpool.install(|| {
+            kbots_ref
+                .par_iter()
+                .filter_map(|(me, me_kbot)| {
+                    if me_kbot.con_completed != 1.0 || me_kbot.fire_stance == FireStance::HoldFire {
+                        return None;
+                    }
+                    let grid_pos = grid_pos(me_kbot);
 
-                let my_team = id_to_team.get(me).unwrap();
+                    let my_team = id_to_team.get(me).unwrap();
 
-                // let ennemies_in_cell = &team_to_ennemy_grid.get(my_team).unwrap()[grid_pos];
+                    // let ennemies_in_cell = &team_to_ennemy_grid.get(my_team).unwrap()[grid_pos];
 
-                let mut ennemies_in_cell: Vec<Id<KBot>> = grid[grid_pos].clone();
-                let to_remove = ennemies_in_cell.iter().position(|e| e == me).unwrap();
-                ennemies_in_cell.remove(to_remove);
+                    let mut ennemies_in_cell: Vec<Id<KBot>> = grid[grid_pos].clone();
+                    let to_remove = ennemies_in_cell.iter().position(|e| e == me).unwrap();
+                    ennemies_in_cell.remove(to_remove);
 
-                let can_shoot =// *my_team == 0&&
-                 frame_count - me_kbot.frame_last_shot > me_kbot.reload_frame_count;
-                if can_shoot {
+                    let can_shoot =// *my_team == 0&&
+                     frame_count - me_kbot.frame_last_shot > me_kbot.reload_frame_count;
+                    if !can_shoot {
+                        return None;
+                    }
                     //We choose the first ennemy in the cell, we could sort by distance or something else here
                     //TODO Configurable strategy
-                    'meloop: for potential_ennemy in ennemies_in_cell {
+                    let anti_air = bot_defs.get(&me_kbot.botdef_id).unwrap().anti_air;
+                    let return_fire_only = me_kbot.fire_stance == FireStance::ReturnFire;
+                    for potential_ennemy in ennemies_in_cell {
+                        if return_fire_only && me_kbot.last_attacker != Some(potential_ennemy) {
+                            continue;
+                        }
                         if id_to_team.get(&potential_ennemy).unwrap() != my_team {
-                            let ennemy_kbot = kbots.get(&potential_ennemy).unwrap();
-                            if (ennemy_kbot.position.coords - me_kbot.position.coords).magnitude()
-                                < 6.0
-                            {
-                                shots.push(Shot {
+                            let ennemy_kbot = kbots_ref.get(&potential_ennemy).unwrap();
+                            //Only anti air weapons can shoot flying units
+                            if ennemy_kbot.altitude > 0.0 && !anti_air {
+                                continue;
+                            }
+                            let to_ennemy = ennemy_kbot.position.coords - me_kbot.position.coords;
+                            //Range is horizontal when flying units are involved
+                            let distance = if ennemy_kbot.altitude > 0.0 || me_kbot.altitude > 0.0 {
+                                to_ennemy.xy().magnitude()
+                            } else {
+                                to_ennemy.magnitude()
+                            };
+                            if distance < botdef::WEAPON_RANGE {
+                                return Some(Shot {
                                     bot: *me,
+                                    target_id: potential_ennemy,
                                     target: ennemy_kbot.position.coords,
                                 });
-                                break 'meloop;
                             }
                         }
                     }
-                }
-            }
-        }
+                    None
+                })
+                .collect()
+        });
 
         for shot in shots.iter() {
             let kbot = kbots.get_mut(&shot.bot).unwrap();
@@ -494,20 +1269,43 @@ pub fn update_units(
 
             kbot.weapon0_dir = dir;
             kbot.frame_last_shot = frame_count;
-            let kbot_radius = bot_defs.get(&kbot.botdef_id).unwrap().radius;
-            let proj = KinematicProjectile {
-                id: rand_id(),
-                birth_frame: frame_count,
-                death_frame: frame_count + 6,
-                position_at_birth: kbot.position + dir * (kbot_radius + 0.25 + 0.01),
-                speed_per_frame_at_birth: dir * 2.0 + Vector3::new(0.0, 0.0, 0.2),
-                accel_per_frame: Vector3::new(0.0, 0.0, -0.08),
-                radius: 0.25,
-                position_cache: Vec::new(),
-                speed_cache: Vec::new(),
-            };
-            kinematic_projectiles_birth.push(proj.clone());
-            kinematic_projectiles.insert(proj.id, proj);
+            let bonus = kbot.veterancy_bonus();
+            let player_id = kbot.player_id;
+            let botdef = bot_defs.get(&kbot.botdef_id).unwrap();
+            let kbot_radius = botdef.radius;
+            match &botdef.weapon0 {
+                botdef::Weapon::Projectile => {
+                    let proj = KinematicProjectile {
+                        id: rand_id(),
+                        birth_frame: frame_count,
+                        death_frame: frame_count + 6,
+                        position_at_birth: kbot.position + dir * (kbot_radius + 0.25 + 0.01),
+                        speed_per_frame_at_birth: dir * 2.0 + Vector3::new(0.0, 0.0, 0.2),
+                        accel_per_frame: Vector3::new(0.0, 0.0, -0.08),
+                        radius: 0.25,
+                        owner: shot.bot,
+                        position_cache: Vec::new(),
+                        speed_cache: Vec::new(),
+                    };
+                    kinematic_projectiles_birth.push(proj.clone());
+                    kinematic_projectiles.insert(proj.id, proj);
+                }
+                botdef::Weapon::Beam { damage, color } => {
+                    beams.push(BeamEvent {
+                        from: kbot.position
+                            + dir * (kbot_radius + 0.01)
+                            + Vector3::new(0.0, 0.0, 0.5),
+                        to: Point3::from(shot.target) + Vector3::new(0.0, 0.0, 0.3),
+                        color: *color,
+                    });
+                    if let Some(target) = kbots.get_mut(&shot.target_id) {
+                        let dealt = target.take_damage(*damage, shot.bot, bonus);
+                        if let Some(player) = players.get_mut(&player_id) {
+                            player.stats.damage_dealt += dealt as i64;
+                        }
+                    }
+                }
+            }
         }
         frame_profiler.add("07  kbot_fire", start.elapsed());
     }
@@ -523,11 +1321,83 @@ pub fn update_units(
         to: Id<KBot>,
     }
     let mut build_throughputs = Vec::new();
+    struct ReclaimPart {
+        amount: f64,
+        player: Id<Player>,
+        to: Id<KBot>,
+    }
+    let mut reclaim_parts = Vec::new();
+    let mut factory_starts = Vec::new();
     //Build compute
     for (id, mobile) in kbots.iter_mut() {
         if mobile.con_completed >= 1.0 {
             // Look at current_command, change move_target if necessary
             match mobile.current_command {
+                //Idle factories start the next item of their queue in front of them
+                Command::None if !mobile.build_queue.items.is_empty() => {
+                    let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+                    let tech_tier = players
+                        .get(&mobile.player_id)
+                        .map(|player| player.tech_tier)
+                        .unwrap_or(1);
+                    let item = mobile.build_queue.pop().unwrap();
+                    match bot_defs.get(&item) {
+                        Some(to_build) if to_build.tier <= tech_tier => {
+                            let forward = Vector2::new(mobile.dir.x, mobile.dir.y)
+                                .try_normalize(0.001)
+                                .unwrap_or(Vector2::new(1.0, 0.0))
+                                * (botdef.radius + to_build.radius + 0.5);
+                            let clamp = |k: f32| {
+                                Vector2::new(
+                                    (mobile.position.x + forward.x * k)
+                                        .max(0.0)
+                                        .min(heightmap_phy.width as f32 - 1.0),
+                                    (mobile.position.y + forward.y * k)
+                                        .max(0.0)
+                                        .min(heightmap_phy.height as f32 - 1.0),
+                                )
+                            };
+                            //Walks further once completed, out of the way of the next one
+                            factory_starts.push((*id, item, clamp(1.0), clamp(3.0)));
+                        }
+                        _ => log::debug!("{} skips {} of a locked tier", id, item),
+                    }
+                }
+                //Idle builders help the constructions of the team in reach, a few at a time
+                Command::None
+                    if mobile.move_target.is_none()
+                        && (frame_count as u64 + id.value) % AUTO_ASSIST_PERIOD == 0 =>
+                {
+                    let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+                    if botdef.build_power <= 0.0 {
+                        continue;
+                    }
+                    let reach = (botdef.build_dist / cell_size as f32).ceil() as i32;
+                    let (cx, cy) = (
+                        mobile.position.x as i32 / cell_size as i32,
+                        mobile.position.y as i32 / cell_size as i32,
+                    );
+                    let mut to_assist = None;
+                    'cells: for y in (cy - reach).max(0)..=(cy + reach).min(grid_h as i32 - 1) {
+                        for x in (cx - reach).max(0)..=(cx + reach).min(grid_w as i32 - 1) {
+                            for other in grid[x as usize + y as usize * grid_w].iter() {
+                                let other = mobiles2.get(other).unwrap();
+                                let dist =
+                                    (other.position.coords - mobile.position.coords).magnitude();
+                                if other.team == mobile.team
+                                    && other.con_completed < 1.0
+                                    && dist <= botdef.build_dist
+                                {
+                                    to_assist = Some(other.id);
+                                    break 'cells;
+                                }
+                            }
+                        }
+                    }
+                    if let Some(to_assist) = to_assist {
+                        mobile.current_command = Command::Build(to_assist);
+                    }
+                }
                 Command::Build(to_build) => match mobiles2.get(&to_build) {
                     Some(to_build) => {
                         if to_build.con_completed < 1.0 {
@@ -581,11 +1451,205 @@ pub fn update_units(
                     }
                     None => {}
                 },
+                //Helps what the guarded unit builds or repairs, else repairs it, else follows it
+                Command::Guard(guarded) => match mobiles2.get(&guarded) {
+                    Some(guarded) => {
+                        let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+                        let helped = match guarded.current_command {
+                            Command::Build(to_help) | Command::Repair(to_help) => {
+                                mobiles2.get(&to_help)
+                            }
+                            _ => None,
+                        };
+                        let to_help = helped
+                            .into_iter()
+                            .chain(std::iter::once(guarded))
+                            .filter(|_| botdef.build_power > 0.0)
+                            .find(|to_help| {
+                                to_help.con_completed < 1.0
+                                    || to_help.life
+                                        < bot_defs.get(&to_help.botdef_id).unwrap().max_life
+                            });
+                        match to_help {
+                            Some(to_help) => {
+                                let dist =
+                                    (to_help.position.coords - mobile.position.coords).magnitude();
+                                if dist <= botdef.build_dist {
+                                    mobile.move_target = None;
+                                    build_throughputs.push(BuildPart {
+                                        amount: botdef.build_power as f64,
+                                        repair: to_help.con_completed >= 1.0,
+                                        player: mobile.player_id,
+                                        from: *id,
+                                        to: to_help.id,
+                                    })
+                                } else {
+                                    mobile.move_target = Some(to_help.position);
+                                }
+                            }
+                            None => {
+                                let dist = (guarded.position.coords - mobile.position.coords)
+                                    .xy()
+                                    .magnitude();
+                                //Close enough to shoot at what attacks the guarded unit
+                                mobile.move_target = if dist > botdef::WEAPON_RANGE * 0.5
+                                    && !mobile.is_engaged(frame_count)
+                                {
+                                    Some(guarded.position)
+                                } else {
+                                    None
+                                };
+                            }
+                        }
+                    }
+                    None => {
+                        mobile.current_command = Command::None;
+                        mobile.move_target = None;
+                    }
+                },
+                Command::Reclaim(to_reclaim) => match mobiles2.get(&to_reclaim) {
+                    Some(to_reclaim) => {
+                        let dist =
+                            (to_reclaim.position.coords - mobile.position.coords).magnitude();
+                        let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+                        if dist <= botdef.build_dist {
+                            mobile.move_target = None;
+                            reclaim_parts.push(ReclaimPart {
+                                amount: botdef.build_power as f64,
+                                player: mobile.player_id,
+                                to: to_reclaim.id,
+                            })
+                        } else {
+                            mobile.move_target = Some(to_reclaim.position);
+                        }
+                    }
+                    None => {
+                        mobile.current_command = Command::None;
+                        mobile.move_target = None;
+                    }
+                },
+                Command::Area {
+                    kind,
+                    center,
+                    radius,
+                    target,
+                } => {
+                    let team = mobile.team;
+                    let needs_work = |kbot: &&KBot| {
+                        let botdef = bot_defs.get(&kbot.botdef_id).unwrap();
+                        kbot.team == team
+                            && (kbot.position.coords - center.coords).xy().magnitude() <= radius
+                            && match kind {
+                                AreaKind::Repair => {
+                                    kbot.con_completed < 1.0 || kbot.life < botdef.max_life
+                                }
+                                AreaKind::Reclaim => match kbot.current_command {
+                                    Command::Area {
+                                        kind: AreaKind::Reclaim,
+                                        ..
+                                    } => false,
+                                    _ => !botdef.commander && kbot.cargo.is_empty(),
+                                },
+                            }
+                    };
+                    //Keeps its target while it needs work, then goes for the closest one
+                    let position = mobile.position;
+                    let distance =
+                        |kbot: &&KBot| (kbot.position.coords - position.coords).magnitude();
+                    let next = target
+                        .and_then(|target| mobiles2.get(&target))
+                        .filter(needs_work)
+                        .or_else(|| {
+                            mobiles2.values().filter(needs_work).min_by(|a, b| {
+                                distance(a)
+                                    .partial_cmp(&distance(b))
+                                    .unwrap_or(std::cmp::Ordering::Equal)
+                            })
+                        });
+                    match next {
+                        Some(next) => {
+                            mobile.current_command = Command::Area {
+                                kind,
+                                center,
+                                radius,
+                                target: Some(next.id),
+                            };
+                            let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+                            if distance(&next) <= botdef.build_dist {
+                                mobile.move_target = None;
+                                match kind {
+                                    AreaKind::Repair => build_throughputs.push(BuildPart {
+                                        amount: botdef.build_power as f64,
+                                        repair: next.con_completed >= 1.0,
+                                        player: mobile.player_id,
+                                        from: *id,
+                                        to: next.id,
+                                    }),
+                                    AreaKind::Reclaim => reclaim_parts.push(ReclaimPart {
+                                        amount: botdef.build_power as f64,
+                                        player: mobile.player_id,
+                                        to: next.id,
+                                    }),
+                                }
+                            } else {
+                                mobile.move_target = Some(next.position);
+                            }
+                        }
+                        None => {
+                            mobile.current_command = Command::None;
+                            mobile.move_target = None;
+                        }
+                    }
+                }
                 _ => {}
             }
         }
     }
 
+    for (factory_id, item, at, exit) in factory_starts {
+        let factory = kbots.get_mut(&factory_id).unwrap();
+        let position = Point3::new(at.x, at.y, heightmap_phy.z(at.x, at.y));
+        let mut kbot = KBot::new(position, bot_defs.get(&item).unwrap(), factory.player_id);
+        kbot.team = factory.team;
+        kbot.move_target = Some(Point3::new(exit.x, exit.y, heightmap_phy.z(exit.x, exit.y)));
+        kbot.con_completed = std::f32::MIN_POSITIVE;
+        kbot.life = 1;
+        factory.current_command = Command::Build(kbot.id);
+        if let Some(player) = players.get_mut(&kbot.player_id) {
+            player.kbots.insert(kbot.id);
+        }
+        kbots.insert(kbot.id, kbot);
+    }
+
+    //Factories pay less with the storages and generators of their owner touching them
+    let mut discounts = FnvHashMap::<Id<KBot>, f64>::default();
+    for part in build_throughputs.iter().filter(|part| !part.repair) {
+        if discounts.contains_key(&part.from) {
+            continue;
+        }
+        let discount = match kbots.get(&part.from) {
+            Some(factory) => {
+                let neighbors = grid[grid_pos(factory)]
+                    .iter()
+                    .filter_map(|id| kbots.get(id))
+                    .filter(|other| {
+                        other.player_id == factory.player_id && other.con_completed >= 1.0
+                    })
+                    .map(|other| {
+                        let botdef = bot_defs.get(&other.botdef_id).unwrap();
+                        (botdef, other.position.x, other.position.y)
+                    });
+                bot_defs
+                    .get(&factory.botdef_id)
+                    .unwrap()
+                    .adjacency_discount(factory.position.x, factory.position.y, neighbors)
+                    as f64
+            }
+            None => 0.0,
+        };
+        discounts.insert(part.from, discount);
+    }
+
     //Compute resource usage for each player
     struct ResourceUsage {
         metal: f64,
@@ -606,7 +1670,12 @@ pub fn update_units(
             energy: 0.0,
         });
         *stat = ResourceUsage {
-            metal: stat.metal + if *repair { 0.0 } else { *amount as f64 },
+            metal: stat.metal
+                + if *repair {
+                    *amount * REPAIR_COST_RATIO
+                } else {
+                    *amount
+                },
             energy: 0.0,
         };
     }
@@ -658,185 +1727,779 @@ pub fn update_units(
     {
         let kbot = kbots.get_mut(&to).unwrap();
         let botdef = bot_defs.get(&kbot.botdef_id).unwrap();
-        let metal_available = amount * usage_props_max.get(&player).unwrap().metal;
-        let metal_needed = if repair {
-            0.0
-        } else {
-            (1.0 - kbot.con_completed as f64) * botdef.metal_cost as f64
-        };
-        let mut metal_used = metal_available;
-        if metal_needed > metal_available {
-            let metal_built =
-                metal_available + kbot.con_completed as f64 * botdef.metal_cost as f64;
-
-            kbot.con_completed = (metal_built / botdef.metal_cost as f64) as f32;
+        let was_completed = kbot.con_completed >= 1.0;
+        let metal_cost = botdef.tier_metal_cost() as f64;
+        let lambda = if repair {
+            //Only the missing life is paid for, the rest of the charge goes back
+            let metal_charged =
+                amount * REPAIR_COST_RATIO * usage_props_max.get(&player).unwrap().metal;
+            let missing = 1.0 - kbot.life as f64 / botdef.max_life as f64;
+            let metal_used = metal_charged.min(missing.max(0.0) * metal_cost * REPAIR_COST_RATIO);
+            let player = players.get_mut(&player).unwrap();
+            player.metal += metal_charged - metal_used;
+            player.stats.metal_spent += metal_used;
+            (metal_used / (metal_cost * REPAIR_COST_RATIO)) as f32
         } else {
-            let metal_not_used = metal_available - metal_needed;
-            metal_used = metal_available - metal_not_used;
-            if !repair {
+            let metal_cost = metal_cost * (1.0 - discounts.get(&from).copied().unwrap_or(0.0));
+            let metal_available = amount * usage_props_max.get(&player).unwrap().metal;
+            let metal_needed = (1.0 - kbot.con_completed as f64) * metal_cost;
+            let mut metal_used = metal_available;
+            if metal_needed > metal_available {
+                let metal_built = metal_available + kbot.con_completed as f64 * metal_cost;
+
+                kbot.con_completed = (metal_built / metal_cost) as f32;
+            } else {
+                let metal_not_used = metal_available - metal_needed;
+                metal_used = metal_available - metal_not_used;
                 players.get_mut(&player).unwrap().metal += metal_not_used;
                 kbot.con_completed = 1.0;
             }
-        }
-        let lambda = if repair {
-            amount as f32
-        } else {
-            metal_used as f32
-        } / botdef.metal_cost as f32;
+            let stats = &mut players.get_mut(&player).unwrap().stats;
+            stats.metal_spent += metal_used;
+            if !was_completed && kbot.con_completed >= 1.0 {
+                stats.units_built += 1;
+                events.push(GameEvent::BuildingCompleted {
+                    id: kbot.id,
+                    botdef_id: kbot.botdef_id,
+                    player_id: kbot.player_id,
+                    position: kbot.position,
+                });
+            }
+            (metal_used / metal_cost) as f32
+        };
         kbot.life = ((kbot.life as f32 + lambda * botdef.max_life as f32).ceil() as i32)
             .min((botdef.max_life as f32 * kbot.con_completed).ceil() as i32);
     }
 
-    frame_profiler.add("01b build compute", start.elapsed());
+    //Reclaim undoes construction, the metal goes back to the reclaimer
+    for ReclaimPart { amount, player, to } in reclaim_parts {
+        //Several reclaimers may have finished the same unit this frame
+        let kbot = match kbots.get_mut(&to) {
+            Some(kbot) => kbot,
+            None => continue,
+        };
+        let botdef = bot_defs.get(&kbot.botdef_id).unwrap();
+        let metal_cost = botdef.tier_metal_cost() as f64;
+        let metal_left = kbot.con_completed as f64 * metal_cost;
+        let metal = amount.min(metal_left);
+        kbot.con_completed = ((metal_left - metal) / metal_cost) as f32;
+        kbot.life = kbot
+            .life
+            .min((botdef.max_life as f32 * kbot.con_completed).ceil() as i32);
+        if let Some(player) = players.get_mut(&player) {
+            player.metal += metal;
+        }
+        if kbot.con_completed <= 0.0 {
+            kbots.remove(&to);
+            kbots_dead.insert(to);
+        }
+    }
 
-    //Movement compute
+    frame_profiler.add("01b build compute", start.elapsed());
 
+    //Transport compute
+    let mut loads = Vec::new();
+    let mut unloads = Vec::new();
     for (id, mobile) in kbots.iter_mut() {
         if mobile.con_completed >= 1.0 {
-            if mobile.speed.magnitude_squared() > 0.001
-                || mobile.move_target.is_some()
-                || !mobile.grounded
-            {
-                let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
-                let grid_pos = grid_pos(mobile);
-                let mut neighbors_id: Vec<Id<KBot>> = grid[grid_pos].clone();
-                let to_remove = neighbors_id.iter().position(|e| e == id).unwrap();
-                neighbors_id.remove(to_remove);
-
-                let avoidance_force = avoid_neighbors_force(mobile, neighbors_id, &mobiles2) * 0.3;
-
-                let TargetForce {
-                    target_force,
-                    stop_tracking,
-                } = to_target_force(mobile, botdef);
-
-                // arrows.push(Arrow {
-                //     position: mobile.position,
-                //     color: [target_force.norm(), 0.0, 0.0, 0.0],
-                //     end: mobile.position
-                //         + Vector3::new(target_force.x * 2.0, target_force.y * 2.0, 0.0),
-                // });
-
-                // arrows.push(Arrow {
-                //     position: mobile.position,
-                //     color: [0.0, avoidance_force.norm(), 0.0, 0.0],
-                //     end: mobile.position
-                //         + Vector3::new(avoidance_force.x * 2.0, avoidance_force.y * 2.0, 0.0),
-                // });
-
-                if stop_tracking {
-                    mobile.move_target = None;
+            let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+            match mobile.current_command {
+                Command::Load(to_load) => match mobiles2.get(&to_load) {
+                    Some(to_load)
+                        if mobile.cargo.len() < botdef.transport_capacity as usize
+                            && to_load.player_id == mobile.player_id
+                            && to_load.con_completed >= 1.0
+                            && bot_defs
+                                .get(&to_load.botdef_id)
+                                .unwrap()
+                                .can_be_transported() =>
+                    {
+                        let dist = (to_load.position.coords - mobile.position.coords)
+                            .xy()
+                            .magnitude();
+                        if dist <= botdef.radius * 2.0 + 1.0 {
+                            loads.push((*id, to_load.id));
+                            mobile.current_command = Command::None;
+                            mobile.move_target = None;
+                        } else {
+                            mobile.move_target = Some(to_load.position);
+                        }
+                    }
+                    _ => {
+                        mobile.current_command = Command::None;
+                    }
+                },
+                Command::Unload(destination) => {
+                    let dist = (destination.coords - mobile.position.coords)
+                        .xy()
+                        .magnitude();
+                    if dist <= botdef.radius * 2.0 + 1.0 {
+                        unloads.push(*id);
+                        mobile.current_command = Command::None;
+                        mobile.move_target = None;
+                    } else {
+                        mobile.move_target = Some(destination);
+                    }
                 }
-
-                let dir = avoidance_force + target_force;
-                let dir_intensity = (avoidance_force.norm() + target_force.norm())
-                    .max(0.0)
-                    .min(1.0);
-
-                //Clamp in cone
-                let wanted_angle: Angle = dir.into();
-                let current_angle = mobile.angle;
-
-                fn clamp_abs(x: f32, max_abs: f32) -> f32 {
-                    let sign = x.signum();
-                    sign * (x.abs().min(max_abs))
+                Command::Ferry {
+                    pickup,
+                    dropoff,
+                    unloading: true,
+                } => {
+                    let dist = (dropoff.coords - mobile.position.coords).xy().magnitude();
+                    if dist <= botdef.radius * 2.0 + 1.0 {
+                        unloads.push(*id);
+                        mobile.move_target = None;
+                        mobile.current_command = Command::Ferry {
+                            pickup,
+                            dropoff,
+                            unloading: false,
+                        };
+                    } else {
+                        mobile.move_target = Some(dropoff);
+                    }
                 }
+                Command::Ferry {
+                    pickup,
+                    dropoff,
+                    unloading: false,
+                } => {
+                    //Closest unit waiting at the pickup
+                    let reach = (FERRY_RADIUS / cell_size as f32).ceil() as i32;
+                    let (cx, cy) = (
+                        pickup.x as i32 / cell_size as i32,
+                        pickup.y as i32 / cell_size as i32,
+                    );
+                    let mut closest: Option<(f32, &KBot)> = None;
+                    let full = mobile.cargo.len() >= botdef.transport_capacity as usize;
+                    //Cells also hold the units of their 8 neighbors, one in 3 is enough
+                    for y in ((cy - reach).max(0)..=(cy + reach).min(grid_h as i32 - 1)).step_by(3)
+                    {
+                        for x in
+                            ((cx - reach).max(0)..=(cx + reach).min(grid_w as i32 - 1)).step_by(3)
+                        {
+                            for other in grid[x as usize + y as usize * grid_w].iter() {
+                                let other = match mobiles2.get(other) {
+                                    Some(other) => other,
+                                    None => continue,
+                                };
+                                let waiting = !full
+                                    && other.player_id == mobile.player_id
+                                    && other.con_completed >= 1.0
+                                    && other.current_command == Command::None
+                                    && other.move_target.is_none()
+                                    && other.altitude <= 0.0
+                                    && (other.position.coords - pickup.coords).xy().magnitude()
+                                        <= FERRY_RADIUS
+                                    && bot_defs.get(&other.botdef_id).unwrap().can_be_transported();
+                                if !waiting {
+                                    continue;
+                                }
+                                let dist = (other.position.coords - mobile.position.coords)
+                                    .xy()
+                                    .magnitude();
+                                if closest.map(|(d, _)| dist < d).unwrap_or(true) {
+                                    closest = Some((dist, other));
+                                }
+                            }
+                        }
+                    }
+                    match closest {
+                        Some((dist, to_load)) => {
+                            if dist <= botdef.radius * 2.0 + 1.0 {
+                                loads.push((*id, to_load.id));
+                                mobile.move_target = None;
+                            } else {
+                                mobile.move_target = Some(to_load.position);
+                            }
+                        }
+                        //Leaves with what it has, or waits at the pickup
+                        None if !mobile.cargo.is_empty() => {
+                            mobile.current_command = Command::Ferry {
+                                pickup,
+                                dropoff,
+                                unloading: true,
+                            };
+                        }
+                        None => {
+                            let dist = (pickup.coords - mobile.position.coords).xy().magnitude();
+                            mobile.move_target = if dist > botdef.radius * 2.0 + 1.0 {
+                                Some(pickup)
+                            } else {
+                                None
+                            };
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 
-                let diff = (wanted_angle - (current_angle + mobile.angular_velocity.into())).rad;
-
-                mobile.angular_velocity = clamp_abs(
-                    mobile.angular_velocity + clamp_abs(diff, botdef.turn_accel),
-                    botdef.max_turn_rate,
-                );
+    for (transport_id, to_load) in loads {
+        //Several transports may have picked the same unit this frame
+        if let Some(mut cargo) = kbots.remove(&to_load) {
+            cargo.move_target = None;
+            cargo.current_command = Command::None;
+            cargo.speed = Vector3::new(0.0, 0.0, 0.0);
+            kbots.get_mut(&transport_id).unwrap().cargo.push(cargo);
+        }
+    }
 
-                let new_angle = current_angle + mobile.angular_velocity.into();
-                // current_angle.clamp_around(wanted_angle, mobile.angular_velocity.into());
-                mobile.angle = new_angle;
-                let new_dir: Vector2<f32> = new_angle.into();
-                mobile.dir = Vector3::new(new_dir.x, new_dir.y, 0.0);
+    for transport_id in unloads {
+        let transport = kbots.get_mut(&transport_id).unwrap();
+        let center = transport.position;
+        let cargo = std::mem::replace(&mut transport.cargo, Vec::new());
+        let count = cargo.len();
+        for (i, mut kbot) in cargo.into_iter().enumerate() {
+            //Spread the cargo on a ring around the transport, on ground it can stand on
+            let movement = bot_defs.get(&kbot.botdef_id).unwrap().movement;
+            let angle = i as f32 / count as f32 * 2.0 * std::f32::consts::PI;
+            let mut spot = (center.x, center.y);
+            for ring in 1..4 {
+                let x = (center.x + angle.cos() * 2.0 * ring as f32)
+                    .max(0.0)
+                    .min(heightmap_phy.width as f32 - 1.0);
+                let y = (center.y + angle.sin() * 2.0 * ring as f32)
+                    .max(0.0)
+                    .min(heightmap_phy.height as f32 - 1.0);
+                if movement.can_traverse(heightmap_phy, x, y) {
+                    spot = (x, y);
+                    break;
+                }
+            }
+            kbot.position = Point3::new(spot.0, spot.1, heightmap_phy.z_linear(spot.0, spot.1));
+            kbot.grounded = false;
+            kbots.insert(kbot.id, kbot);
+        }
+    }
 
-                //TODO drift factor ?
-                //drift = 1 (adherence = 0)
-                // mobile.speed = mobile.speed + mobile.dir * botdef.accel * dir_intensity;
-                //drift = 0 (adherence = 1)
+    frame_profiler.add("01c transport compute", start.elapsed());
 
-                let speed_scalar = mobile.speed.xy().magnitude();
-                let thrust = if speed_scalar > 0.01 {
-                    dir.normalize().dot(&(mobile.speed.xy() / speed_scalar))
-                } else {
-                    1.0
-                };
+    let start = std::time::Instant::now();
+    //Patrol compute
+    for mobile in kbots.values_mut() {
+        if mobile.con_completed < 1.0 {
+            continue;
+        }
+        let engaged = mobile.is_engaged(frame_count);
+        if let Command::Patrol { waypoints, next } = &mut mobile.current_command {
+            //Holds its position while fighting, then resumes the loop
+            if engaged {
+                mobile.move_target = None;
+                continue;
+            }
+            let radius = bot_defs.get(&mobile.botdef_id).unwrap().radius;
+            let to = waypoints[*next % waypoints.len()];
+            if (to.coords - mobile.position.coords).xy().magnitude() <= radius * 2.0 + 1.0 {
+                *next = (*next + 1) % waypoints.len();
+            }
+            mobile.move_target = Some(waypoints[*next % waypoints.len()]);
+        }
+    }
+    frame_profiler.add("01d patrol compute", start.elapsed());
 
-                let accel = if mobile.move_target != None && thrust > 0.0 {
-                    botdef.accel * dir_intensity * thrust
+    let start = std::time::Instant::now();
+    //Stance compute, idle units chase the ennemies they see and may come back
+    for (id, mobile) in kbots.iter_mut() {
+        let leash = mobile.move_stance.leash();
+        if !mobile.is_idle()
+            || leash <= 0.0
+            || mobile.con_completed < 1.0
+            || mobile.fire_stance == FireStance::HoldFire
+            || (frame_count as u64 + id.value) % CHASE_PERIOD != 0
+        {
+            continue;
+        }
+        let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+        if botdef.max_speed <= 0.0 {
+            continue;
+        }
+        let from = mobile.chase_from.unwrap_or(mobile.position);
+        let reach = (leash / cell_size as f32).ceil() as i32;
+        let (cx, cy) = (
+            from.x as i32 / cell_size as i32,
+            from.y as i32 / cell_size as i32,
+        );
+        let mut closest: Option<(f32, Point3<f32>)> = None;
+        //Cells also hold the units of their 8 neighbors, one in 3 is enough
+        for y in ((cy - reach).max(0)..=(cy + reach).min(grid_h as i32 - 1)).step_by(3) {
+            for x in ((cx - reach).max(0)..=(cx + reach).min(grid_w as i32 - 1)).step_by(3) {
+                for other in grid[x as usize + y as usize * grid_w].iter() {
+                    let other = match mobiles2.get(other) {
+                        Some(other) => other,
+                        None => continue,
+                    };
+                    let wanted = match mobile.fire_stance {
+                        FireStance::ReturnFire => mobile.last_attacker == Some(other.id),
+                        _ => other.team != mobile.team,
+                    };
+                    let seen = (other.position.coords - from.coords).xy().magnitude() <= leash;
+                    //Can't reach flying units without an anti air weapon
+                    if !wanted || !seen || (other.altitude > 0.0 && !botdef.anti_air) {
+                        continue;
+                    }
+                    let dist = (other.position.coords - mobile.position.coords)
+                        .xy()
+                        .magnitude();
+                    if closest.map(|(d, _)| dist < d).unwrap_or(true) {
+                        closest = Some((dist, other.position));
+                    }
+                }
+            }
+        }
+        match closest {
+            Some((dist, position)) => {
+                mobile.chase_from = Some(from);
+                //Stops once in range to shoot
+                mobile.move_target = if dist > botdef::WEAPON_RANGE * 0.8 {
+                    Some(position)
                 } else {
-                    -botdef.break_accel * thrust.abs()
+                    None
                 };
+            }
+            None => {
+                if mobile.move_stance == MoveStance::Maneuver {
+                    mobile.move_target = mobile.chase_from;
+                } else if mobile.chase_from.is_some() {
+                    mobile.move_target = None;
+                }
+                mobile.chase_from = None;
+            }
+        }
+    }
+    frame_profiler.add("01e stance compute", start.elapsed());
+
+    //Strategic compute
+    for kbot in kbots.values_mut() {
+        let strategic = &bot_defs.get(&kbot.botdef_id).unwrap().strategic;
+        if let Some(stockpile_frames) = strategic.stockpile_frames() {
+            if kbot.con_completed >= 1.0 && kbot.stockpile < botdef::Strategic::MAX_STOCKPILE {
+                kbot.stockpile_progress += 1;
+                if kbot.stockpile_progress >= stockpile_frames {
+                    kbot.stockpile += 1;
+                    kbot.stockpile_progress = 0;
+                }
+            }
+        }
+    }
 
-                // arrows.push(Arrow {
-                //     position: mobile.position + Vector3::new(0.0, 0.0, 2.0),
-                //     color: [0.0, 0.0, accel, 0.0],
-                //     end: mobile.position
-                //         + Vector3::new(dir.x, dir.y, 0.0) * 4.0
-                //         + Vector3::new(0.0, 0.0, 2.0),
-                // });
-
-                // arrows.push(Arrow {
-                //     position: mobile.position + Vector3::new(0.0, 0.0, 1.0),
-                //     color: [0.0, 0.0, accel, 0.0],
-                //     end: mobile.position + mobile.dir * accel * 4.0 + Vector3::new(0.0, 0.0, 1.0),
-                // });
-
-                mobile.speed = mobile.dir * (accel + mobile.speed.magnitude()).max(0.0);
+    let mut missiles_dead = Vec::new();
+    for missile in missiles.values() {
+        let position = missile.position_at(frame_count as f32);
+
+        //Anti nukes only catch missiles on their way down
+        if missile.progress_at(frame_count as f32) > 0.5 {
+            let interceptor = kbots.values_mut().find(|kbot| {
+                kbot.team != missile.team
+                    && kbot.stockpile > 0
+                    && match bot_defs.get(&kbot.botdef_id).unwrap().strategic {
+                        botdef::Strategic::AntiNuke { radius, .. } => {
+                            (kbot.position.coords - position.coords).xy().magnitude() < radius
+                        }
+                        _ => false,
+                    }
+            });
+            if let Some(interceptor) = interceptor {
+                interceptor.stockpile -= 1;
+                missiles_dead.push(missile.id);
+                effects.push(EffectEvent {
+                    position,
+                    name: "interception".to_owned(),
+                });
+                continue;
+            }
+        }
 
-                let speed = mobile.speed.magnitude();
-                if speed > botdef.max_speed {
-                    mobile.speed /= speed / botdef.max_speed;
+        if frame_count >= missile.impact_frame() {
+            missiles_dead.push(missile.id);
+            effects.push(EffectEvent {
+                position: missile.to,
+                name: "nuke".to_owned(),
+            });
+            craters.push(CraterEvent {
+                position: missile.to,
+                radius: missile.radius * 0.3,
+                depth: 6.0,
+            });
+            let owner = kbots.get(&missile.owner);
+            let owner_bonus = owner.map(|owner| owner.veterancy_bonus()).unwrap_or(1.0);
+            let owner_player = owner.map(|owner| owner.player_id);
+            for kbot in kbots.values_mut() {
+                let distance = (kbot.position.coords - missile.to.coords).magnitude();
+                if distance < missile.radius && alliance.hurts(missile.team, kbot) {
+                    let falloff = 1.0 - distance / missile.radius;
+                    let dealt = kbot.take_damage(
+                        (missile.damage as f32 * falloff) as i32,
+                        missile.owner,
+                        owner_bonus,
+                    );
+                    if let Some(player) = owner_player.and_then(|id| players.get_mut(&id)) {
+                        player.stats.damage_dealt += dealt as i64;
+                    }
                 }
+            }
+        }
+    }
+    for id in missiles_dead.iter() {
+        missiles.remove(id);
+    }
 
-                mobile.position += mobile.speed;
-                mobile.position.x = mobile
-                    .position
-                    .x
-                    .max(0.0)
-                    .min(heightmap_phy.width as f32 - 1.0);
-                mobile.position.y = mobile
-                    .position
-                    .y
-                    .max(0.0)
-                    .min(heightmap_phy.height as f32 - 1.0);
-                mobile.position.z = heightmap_phy.z_linear(mobile.position.x, mobile.position.y);
-                mobile.grounded = true;
-                mobile.up = heightmap_phy.normal(mobile.position.x, mobile.position.y);
+    frame_profiler.add("01d strategic compute", start.elapsed());
 
-                let y = -mobile.dir.cross(&mobile.up);
-                let x = y.cross(&mobile.up);
-                mobile.dir = x;
+    let start = std::time::Instant::now();
+    for kbot in kbots.values() {
+        let energy_income = bot_defs.get(&kbot.botdef_id).unwrap().energy_income;
+        if energy_income > 0.0 && kbot.con_completed >= 1.0 {
+            if let Some(player) = players.get_mut(&kbot.player_id) {
+                player.energy += energy_income as f64;
+            }
+        }
+    }
 
-                mobile.weapon0_dir = (mobile.weapon0_dir + mobile.dir).normalize();
-                //w = v/r
-                mobile.wheel0_angle += mobile.speed.norm() / 0.5;
+    //D-gun compute, the unit walks in range then fires once it is reloaded and paid for
+    let mut dgun_shots = Vec::new();
+    for (id, kbot) in kbots.iter_mut() {
+        let to = match kbot.current_command {
+            Command::DGun(to) if kbot.con_completed >= 1.0 => to,
+            _ => continue,
+        };
+        let dgun = match &bot_defs.get(&kbot.botdef_id).unwrap().dgun {
+            Some(dgun) => dgun,
+            None => {
+                kbot.current_command = Command::None;
+                continue;
+            }
+        };
+        let to_target = (to.coords - kbot.position.coords).xy();
+        if to_target.magnitude() > dgun.range {
+            kbot.move_target = Some(to);
+            continue;
+        }
+        kbot.move_target = None;
+        let player = match players.get_mut(&kbot.player_id) {
+            Some(player) => player,
+            None => continue,
+        };
+        //The order waits for the reload and the energy
+        if frame_count - kbot.frame_last_dgun < dgun.reload_frames
+            || player.energy < dgun.energy_cost
+        {
+            continue;
+        }
+        player.energy -= dgun.energy_cost;
+        kbot.frame_last_dgun = frame_count;
+        kbot.current_command = Command::None;
+        let dir = to_target
+            .try_normalize(0.001)
+            .unwrap_or(Vector2::new(kbot.dir.x, kbot.dir.y));
+        kbot.weapon0_dir = Vector3::new(dir.x, dir.y, 0.0);
+        dgun_shots.push((*id, dir));
+    }
+    for (owner, dir) in dgun_shots {
+        let kbot = kbots.get(&owner).unwrap();
+        let (from, bonus, player_id, team) = (
+            kbot.position,
+            kbot.veterancy_bonus(),
+            kbot.player_id,
+            kbot.team,
+        );
+        let dgun = bot_defs
+            .get(&kbot.botdef_id)
+            .unwrap()
+            .dgun
+            .as_ref()
+            .unwrap();
+        let end = from.coords.xy() + dir * dgun.range;
+        let end = Point3::new(end.x, end.y, heightmap_phy.z(end.x, end.y));
+        beams.push(BeamEvent {
+            from: from + Vector3::new(0.0, 0.0, 0.5),
+            to: end + Vector3::new(0.0, 0.0, 0.3),
+            color: [1.0, 0.5, 0.1],
+        });
+        effects.push(EffectEvent {
+            position: end,
+            name: "small_explosion".to_owned(),
+        });
+        //Friends and foes alike unless friendly fire is off, flying units are above the line of
+        //fire
+        let mut dealt = 0;
+        for kbot in kbots.values_mut() {
+            if kbot.id == owner || kbot.altitude > 0.0 || !alliance.hurts(team, kbot) {
+                continue;
+            }
+            let radius = bot_defs.get(&kbot.botdef_id).unwrap().radius;
+            let rel = (kbot.position.coords - from.coords).xy();
+            let along = rel.dot(&dir);
+            let across = (rel - dir * along).magnitude();
+            if along >= 0.0 && along <= dgun.range + radius && across <= dgun.width + radius {
+                dealt += kbot.take_damage(dgun.damage, owner, bonus);
             }
         }
+        if let Some(player) = players.get_mut(&player_id) {
+            player.stats.damage_dealt += dealt as i64;
+        }
     }
+    frame_profiler.add("01f dgun compute", start.elapsed());
+
+    //Movement compute, each unit only writes itself and reads the mobiles2 snapshot
+    let grid: &Vec<Vec<Id<KBot>>> = grid;
+    threadpool.install(|| {
+        profile_scope!("movement");
+        kbots.par_iter_mut().for_each(|(id, mobile)| {
+            if mobile.con_completed >= 1.0 {
+                if mobile.speed.magnitude_squared() > 0.001
+                    || mobile.move_target.is_some()
+                    || !mobile.grounded
+                {
+                    let botdef = bot_defs.get(&mobile.botdef_id).unwrap();
+                    let grid_pos = grid_pos(mobile);
+                    let mut neighbors_id: Vec<Id<KBot>> = grid[grid_pos].clone();
+                    let to_remove = neighbors_id.iter().position(|e| e == id).unwrap();
+                    neighbors_id.remove(to_remove);
+                    //Flying units and ground units don't collide
+                    let is_air = botdef.movement == botdef::MovementClass::Air;
+                    neighbors_id.retain(|e| {
+                        let other = mobiles2.get(e).unwrap();
+                        let other_is_air = bot_defs.get(&other.botdef_id).unwrap().movement
+                            == botdef::MovementClass::Air;
+                        other_is_air == is_air
+                    });
+
+                    let avoidance_force =
+                        avoid_neighbors_force(mobile, neighbors_id, &mobiles2) * 0.3;
+
+                    let TargetForce {
+                        target_force,
+                        stop_tracking,
+                    } = to_target_force(mobile, botdef);
+
+                    // arrows.push(Arrow {
+                    //     position: mobile.position,
+                    //     color: [target_force.norm(), 0.0, 0.0, 0.0],
+                    //     end: mobile.position
+                    //         + Vector3::new(target_force.x * 2.0, target_force.y * 2.0, 0.0),
+                    // });
+
+                    // arrows.push(Arrow {
+                    //     position: mobile.position,
+                    //     color: [0.0, avoidance_force.norm(), 0.0, 0.0],
+                    //     end: mobile.position
+                    //         + Vector3::new(avoidance_force.x * 2.0, avoidance_force.y * 2.0, 0.0),
+                    // });
+
+                    if stop_tracking {
+                        mobile.move_target = None;
+                    }
+
+                    let dir = avoidance_force + target_force;
+                    let dir_intensity = (avoidance_force.norm() + target_force.norm())
+                        .max(0.0)
+                        .min(1.0);
+
+                    //Clamp in cone
+                    let wanted_angle: Angle = dir.into();
+                    let current_angle = mobile.angle;
+
+                    fn clamp_abs(x: f32, max_abs: f32) -> f32 {
+                        let sign = x.signum();
+                        sign * (x.abs().min(max_abs))
+                    }
+
+                    let diff =
+                        (wanted_angle - (current_angle + mobile.angular_velocity.into())).rad;
+
+                    mobile.angular_velocity = clamp_abs(
+                        mobile.angular_velocity + clamp_abs(diff, botdef.turn_accel),
+                        botdef.max_turn_rate,
+                    );
+
+                    let new_angle = current_angle + mobile.angular_velocity.into();
+                    // current_angle.clamp_around(wanted_angle, mobile.angular_velocity.into());
+                    mobile.angle = new_angle;
+                    let new_dir: Vector2<f32> = new_angle.into();
+                    mobile.dir = Vector3::new(new_dir.x, new_dir.y, 0.0);
+
+                    //TODO drift factor ?
+                    //drift = 1 (adherence = 0)
+                    // mobile.speed = mobile.speed + mobile.dir * botdef.accel * dir_intensity;
+                    //drift = 0 (adherence = 1)
+
+                    let speed_scalar = mobile.speed.xy().magnitude();
+                    let thrust = if speed_scalar > 0.01 {
+                        dir.normalize().dot(&(mobile.speed.xy() / speed_scalar))
+                    } else {
+                        1.0
+                    };
+
+                    //Aircraft can't move on the ground, they first take off
+                    let can_move = !is_air || mobile.altitude > botdef.cruise_altitude * 0.5;
+                    let accel = if can_move && mobile.move_target != None && thrust > 0.0 {
+                        botdef.accel * dir_intensity * thrust
+                    } else {
+                        -botdef.break_accel * thrust.abs()
+                    };
+
+                    // arrows.push(Arrow {
+                    //     position: mobile.position + Vector3::new(0.0, 0.0, 2.0),
+                    //     color: [0.0, 0.0, accel, 0.0],
+                    //     end: mobile.position
+                    //         + Vector3::new(dir.x, dir.y, 0.0) * 4.0
+                    //         + Vector3::new(0.0, 0.0, 2.0),
+                    // });
+
+                    // arrows.push(Arrow {
+                    //     position: mobile.position + Vector3::new(0.0, 0.0, 1.0),
+                    //     color: [0.0, 0.0, accel, 0.0],
+                    //     end: mobile.position + mobile.dir * accel * 4.0 + Vector3::new(0.0, 0.0, 1.0),
+                    // });
+
+                    mobile.speed = mobile.dir * (accel + mobile.speed.magnitude()).max(0.0);
+
+                    let speed = mobile.speed.magnitude();
+                    if speed > botdef.max_speed {
+                        mobile.speed /= speed / botdef.max_speed;
+                    }
+
+                    let previous_position = mobile.position;
+                    mobile.position += mobile.speed;
+                    mobile.position.x = mobile
+                        .position
+                        .x
+                        .max(0.0)
+                        .min(heightmap_phy.width as f32 - 1.0);
+                    mobile.position.y = mobile
+                        .position
+                        .y
+                        .max(0.0)
+                        .min(heightmap_phy.height as f32 - 1.0);
+
+                    //Stop at the border of the domain of the movement class, ships at the shore...
+                    if !botdef.movement.can_traverse(
+                        heightmap_phy,
+                        mobile.position.x,
+                        mobile.position.y,
+                    ) {
+                        mobile.position = previous_position;
+                        mobile.speed = Vector3::new(0.0, 0.0, 0.0);
+                        mobile.move_target = None;
+                    }
+                    let ground_z = heightmap_phy.z_linear(mobile.position.x, mobile.position.y);
+                    if is_air {
+                        //Take off when there is something to do, land when idle
+                        let wants_to_fly =
+                            mobile.move_target.is_some() || mobile.current_command != Command::None;
+                        let target_altitude = if wants_to_fly {
+                            botdef.cruise_altitude
+                        } else {
+                            0.0
+                        };
+                        let climb_rate = 0.3;
+                        mobile.altitude += (target_altitude - mobile.altitude)
+                            .max(-climb_rate)
+                            .min(climb_rate);
+                        mobile.position.z = ground_z + mobile.altitude;
+                        mobile.grounded = mobile.altitude <= 0.0;
+
+                        //Bank into turns, proportionally to the turn rate
+                        let max_bank = 0.6;
+                        mobile.bank =
+                            -mobile.angular_velocity / botdef.max_turn_rate.max(0.0001) * max_bank;
+                        let roll = na::Rotation3::from_axis_angle(
+                            &na::Unit::new_normalize(mobile.dir),
+                            mobile.bank,
+                        );
+                        mobile.up = roll * Vector3::new(0.0, 0.0, 1.0);
+                    } else if botdef.movement == botdef::MovementClass::Naval {
+                        mobile.position.z = ground_z.max(heightmap_phy::WATER_LEVEL);
+                        mobile.grounded = true;
+                        mobile.up = Vector3::new(0.0, 0.0, 1.0);
+                    } else {
+                        mobile.position.z = ground_z;
+                        mobile.grounded = true;
+                        mobile.up = heightmap_phy.normal(mobile.position.x, mobile.position.y);
+
+                        let y = -mobile.dir.cross(&mobile.up);
+                        let x = y.cross(&mobile.up);
+                        mobile.dir = x;
+                    }
+
+                    mobile.weapon0_dir = (mobile.weapon0_dir + mobile.dir).normalize();
+                    //w = v/r
+                    mobile.wheel0_angle += mobile.speed.norm() / 0.5;
+                }
+            }
+        });
+    });
     frame_profiler.add("02  movement", start.elapsed());
 
     //Remove dead kbot
+    let mut killers = Vec::new();
+    let mut explosions = Vec::new();
     for (id, kbot) in kbots.iter() {
         if kbot.life <= 0 {
             kbots_dead.insert(*id);
-
-            explosions.push(ExplosionEvent {
-                position: Point3::from(kbot.position),
-                size: 1.0,
-                life_time: 1.2,
+            if let Some(player) = players.get_mut(&kbot.player_id) {
+                player.stats.units_lost += 1 + kbot.cargo.len() as u32;
+            }
+            killers.extend(kbot.last_attacker.map(|killer| (killer, kbot.team)));
+
+            let botdef = bot_defs.get(&kbot.botdef_id).unwrap();
+            events.push(GameEvent::UnitDied {
+                id: *id,
+                botdef_id: kbot.botdef_id,
+                player_id: kbot.player_id,
+                position: kbot.position,
+                killer: kbot.last_attacker,
             });
+            if let Some(explosion) = &botdef.death_explosion {
+                explosions.push((*id, kbot.team, kbot.position, explosion));
+            }
+
+            //Cargo goes down with its transport
+            for cargo in kbot.cargo.iter() {
+                kbots_dead.insert(cargo.id);
+                events.push(GameEvent::UnitDied {
+                    id: cargo.id,
+                    botdef_id: cargo.botdef_id,
+                    player_id: cargo.player_id,
+                    position: kbot.position,
+                    killer: kbot.last_attacker,
+                });
+            }
         }
     }
 
     for id in kbots_dead.iter() {
         kbots.remove(id);
     }
+
+    //Units killed by a blast explode on the next frame, chains take a few frames
+    for (id, team, position, explosion) in explosions {
+        craters.push(CraterEvent {
+            position,
+            radius: explosion.radius / 3.0,
+            depth: explosion.crater_depth,
+        });
+        for kbot in kbots.values_mut() {
+            let distance = (kbot.position.coords - position.coords).magnitude();
+            if distance < explosion.radius && alliance.hurts(team, kbot) {
+                let falloff = 1.0 - distance / explosion.radius;
+                kbot.take_damage((explosion.damage as f32 * falloff) as i32, id, 1.0);
+            }
+        }
+    }
+
+    //Veterancy, only surviving killers are credited, friendly fire doesn't count
+    for (killer, victim_team) in killers {
+        if let Some(kbot) = kbots.get_mut(&killer).filter(|k| k.team != victim_team) {
+            let rank = kbot.rank();
+            kbot.kills += 1;
+            if kbot.rank() > rank {
+                log::debug!("{} promoted to rank {}", kbot.id, kbot.rank());
+            }
+        }
+    }
 }
 
 fn avoid_neighbors_force(
@@ -0,0 +1,77 @@
+use crate::botdef::BotDef;
+use crate::frame::Player;
+use crate::mobile::KBot;
+use crate::utils::Id;
+use na::Point3;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Resource {
+    Metal,
+    Energy,
+}
+
+///Something that happened during a frame of the simulation, see Frame::events. The simulation
+///only reports them, the client hands them to the systems presenting the game.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum GameEvent {
+    ///Destroyed, the units it carried die with it. Reclaimed units are gone without dying.
+    UnitDied {
+        id: Id<KBot>,
+        botdef_id: Id<BotDef>,
+        player_id: Id<Player>,
+        position: Point3<f32>,
+        ///Last unit that damaged it
+        killer: Option<Id<KBot>>,
+    },
+    ///A kinematic projectile hit a unit
+    ProjectileImpact {
+        position: Point3<f32>,
+        target: Id<KBot>,
+        ///Owner of the target
+        player_id: Id<Player>,
+        damage: i32,
+    },
+    ///A construction was finished: a building, or a unit out of its factory
+    BuildingCompleted {
+        id: Id<KBot>,
+        botdef_id: Id<BotDef>,
+        player_id: Id<Player>,
+        position: Point3<f32>,
+    },
+    ///The stock of a player ran out during the frame, what it is spent on stalls
+    ResourceDepleted {
+        player_id: Id<Player>,
+        resource: Resource,
+    },
+}
+
+///Variant of a GameEvent, what its consumers subscribe to
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GameEventKind {
+    UnitDied,
+    ProjectileImpact,
+    BuildingCompleted,
+    ResourceDepleted,
+}
+
+impl GameEvent {
+    pub fn kind(&self) -> GameEventKind {
+        match self {
+            GameEvent::UnitDied { .. } => GameEventKind::UnitDied,
+            GameEvent::ProjectileImpact { .. } => GameEventKind::ProjectileImpact,
+            GameEvent::BuildingCompleted { .. } => GameEventKind::BuildingCompleted,
+            GameEvent::ResourceDepleted { .. } => GameEventKind::ResourceDepleted,
+        }
+    }
+
+    ///Player it happened to: owner of the unit or of the stock
+    pub fn player_id(&self) -> Id<Player> {
+        match self {
+            GameEvent::UnitDied { player_id, .. }
+            | GameEvent::ProjectileImpact { player_id, .. }
+            | GameEvent::BuildingCompleted { player_id, .. }
+            | GameEvent::ResourceDepleted { player_id, .. } => *player_id,
+        }
+    }
+}
@@ -1,48 +1,80 @@
-use crate::gpu_obj::glsl_compiler;
+use crate::gpu_obj::shaders::{self, Defines};
+use std::collections::BTreeMap;
 use std::fs::{self, DirEntry};
 use std::io;
 use std::path::Path;
-use std::slice;
 
+///Writes src/shader/compiled: every shader without defines, the variants of
+///shaders::variants() and the manifest of their sources. Needs a glsl compiler feature.
 pub fn compile_all_glsl() {
-    println!("Compile all glsl");
+    log::info!("Compile all shaders");
 
-    let path = std::path::Path::new("./src/shader/");
-    let cb = |de: &DirEntry| {
-        let path_to_read = de.path();
-        let ext = path_to_read.extension().unwrap().to_str().unwrap();
-
-        if !ext.contains("spirv") {
-            println!("compiling {:?}", path_to_read);
-            let spirv = glsl_compiler::load(path_to_read.to_str().unwrap()).unwrap();
-
-            let file_name = path_to_read.file_name().unwrap();
-            let mut path_to_write = path_to_read.parent().unwrap().to_path_buf();
-            path_to_write.push("compiled");
-            path_to_write.push(file_name);
-            let path_to_write = path_to_write.with_extension(format!("{}.spirv", ext));
+    let mut manifest = BTreeMap::new();
+    let mut compile = |rel_path: &str, defines: &Defines| {
+        log::info!("compiling {} {:?}", rel_path, defines);
+        let code = match fs::read_to_string(rel_path) {
+            Ok(code) => code,
+            Err(e) => {
+                log::error!("{} : {}", rel_path, e);
+                return;
+            }
+        };
+        let spirv = match shaders::load_with_defines(rel_path, defines) {
+            Ok(spirv) => spirv,
+            Err(e) => {
+                log::error!("{}", e);
+                return;
+            }
+        };
+        let path_to_write = shaders::precompiled_path(rel_path, defines);
+        log::info!("write to {:?}", path_to_write);
 
-            println!("write to {:?}", path_to_write);
+        let slice_u8: Vec<u8> = spirv
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        match fs::write(&path_to_write, slice_u8) {
+            Ok(()) => {
+                let file_name = path_to_write.file_name().unwrap().to_string_lossy();
+                manifest.insert(file_name.into_owned(), shaders::source_hash(&code));
+            }
+            Err(e) => log::error!("{:?} : {}", path_to_write, e),
+        }
+    };
 
-            let slice_u8: Vec<u8> = spirv
-                .iter()
-                .map(|w| w.to_le_bytes().iter().copied().collect::<Vec<u8>>())
-                .flatten()
-                .collect();
+    let mut sources = Vec::new();
+    visit_dirs(Path::new("./src/shader/"), &mut |de: &DirEntry| {
+        sources.push(de.path())
+    })
+    .unwrap();
+    sources.sort();
+    for source in sources.iter() {
+        compile(&source.to_string_lossy(), &Defines::new());
+    }
+    for (rel_path, defines) in shaders::variants() {
+        compile(rel_path, &defines);
+    }
 
-            std::fs::write(path_to_write, slice_u8).unwrap();
+    match serde_json::to_string_pretty(&manifest) {
+        Ok(json) => {
+            if let Err(e) = fs::write(shaders::MANIFEST, json + "\n") {
+                log::error!("{} : {}", shaders::MANIFEST, e);
+            }
         }
-    };
-    visit_dirs(path, &cb).unwrap();
+        Err(e) => log::error!("{} : {}", shaders::MANIFEST, e),
+    }
 }
 
-fn visit_dirs(dir: &Path, cb: &dyn Fn(&DirEntry)) -> io::Result<()> {
+///Shader sources, the compiled directory left out
+fn visit_dirs(dir: &Path, cb: &mut dyn FnMut(&DirEntry)) -> io::Result<()> {
     if dir.is_dir() {
         for entry in fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
             if path.is_dir() {
-                visit_dirs(&path, cb)?;
+                if path.file_name() != Some("compiled".as_ref()) {
+                    visit_dirs(&path, cb)?;
+                }
             } else {
                 cb(&entry);
             }
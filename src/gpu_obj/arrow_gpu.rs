@@ -1,4 +1,7 @@
-use super::glsl_compiler;
+use super::instance_buffer::InstanceBuffer;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::taa;
 use crate::model;
 use wgpu::Device;
 use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat};
@@ -7,7 +10,7 @@ pub struct ArrowGpu {
     vertex_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
     index_count: usize,
-    instance_buf: wgpu::Buffer,
+    instance_buf: InstanceBuffer,
     instance_count: u32,
     pipeline: wgpu::RenderPipeline,
 }
@@ -33,14 +36,7 @@ impl ArrowGpu {
             .create_buffer_mapped(index_data.len(), wgpu::BufferUsage::INDEX)
             .fill_from_slice(&index_data);
 
-        let positions: Vec<f32> = Vec::new();
-
-        let instance_buf = device
-            .create_buffer_mapped(
-                positions.len(),
-                wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-            )
-            .fill_from_slice(&positions);
+        let instance_buf = InstanceBuffer::new(device);
 
         let pipeline = Self::create_pipeline(device, main_bind_group_layout, format).unwrap();;
 
@@ -49,7 +45,7 @@ impl ArrowGpu {
             index_buf,
             index_count: index_data.len(),
             instance_buf,
-            instance_count: positions.len() as u32 / 3,
+            instance_count: 0,
             pipeline,
         }
     }
@@ -58,14 +54,14 @@ impl ArrowGpu {
         device: &Device,
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
-    ) -> glsl_compiler::Result<wgpu::RenderPipeline> {
+    ) -> shaders::Result<wgpu::RenderPipeline> {
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[&main_bind_group_layout],
         });
         let vertex_size = std::mem::size_of::<model::Vertex>();
         // Create the render pipeline
-        let vs_bytes = glsl_compiler::load("./src/shader/arrow.vert")?;
-        let fs_bytes = glsl_compiler::load("./src/shader/arrow.frag")?;
+        let vs_bytes = shaders::load("./src/shader/arrow.vert")?;
+        let fs_bytes = shaders::load("./src/shader/arrow.frag")?;
 
         let vs_module = device.create_shader_module(&vs_bytes);
         let fs_module = device.create_shader_module(&fs_bytes);
@@ -101,7 +97,13 @@ impl ArrowGpu {
                     write_mask: wgpu::ColorWrite::ALL,
                 },
                 wgpu::ColorStateDescriptor {
-                    format: wgpu::TextureFormat::Rg16Float,
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                },
+                wgpu::ColorStateDescriptor {
+                    format: taa::VELOCITY_FORMAT,
                     color_blend: wgpu::BlendDescriptor::REPLACE,
                     alpha_blend: wgpu::BlendDescriptor::REPLACE,
                     write_mask: wgpu::ColorWrite::ALL,
@@ -180,20 +182,27 @@ impl ArrowGpu {
             rpass.set_pipeline(&self.pipeline);
             rpass.set_bind_group(0, main_bind_group, &[]);
             rpass.set_index_buffer(&self.index_buf, 0);
-            rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0), (&self.instance_buf, 0)]);
+            rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0), (self.instance_buf.buffer(), 0)]);
             rpass.draw_indexed(0..self.index_count as u32, 0, 0..self.instance_count as u32);
         }
     }
 
-    pub fn update_instance(&mut self, instance_attr: &[f32], device: &wgpu::Device) {
+    pub fn update_instance(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
         log::trace!("ArrowGpu update_instance");
-        let temp_buf = device
-            .create_buffer_mapped(instance_attr.len(), wgpu::BufferUsage::VERTEX)
-            .fill_from_slice(instance_attr);
-
-        std::mem::replace(&mut self.instance_buf, temp_buf);
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
         self.instance_count = instance_attr.len() as u32 / 20;
     }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
 }
 
 impl super::trait_gpu::TraitGpu for ArrowGpu {
@@ -0,0 +1,363 @@
+use super::gpu_memory::Allocation;
+use super::shaders;
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass};
+use wgpu::{TextureFormat, TextureView};
+
+///Side of the neutral LUT, and of the LUTs exported for editing
+pub const NEUTRAL_SIZE: u32 = 16;
+///sRGB encoded values, interpolated as stored so that the neutral LUT is exact
+const LUT_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+///Last stage of the post chain: remaps the final color through a 3D LUT chosen per map.
+///LUTs are png strips of size * size by size texels, the blue slices side by side: texel
+///(r, g, b) is at x = r + b * size, y = g. Export the neutral strip, grade a screenshot with it
+///in any image editor, and the graded strip is the LUT.
+pub struct ColorGrading {
+    pipeline: wgpu::RenderPipeline,
+    color_bind_group_layout: BindGroupLayout,
+    lut_bind_group_layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    color_bind_group: BindGroup,
+    lut_bind_group: BindGroup,
+    _lut: Lut,
+    ///Path of the LUT in use, None for the neutral one. None until the first update.
+    loaded: Option<Option<String>>,
+}
+
+struct Lut {
+    view: TextureView,
+    _texture: wgpu::Texture,
+    _allocation: Allocation,
+}
+
+impl ColorGrading {
+    pub fn new(
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        format: TextureFormat,
+        color: &TextureView,
+    ) -> Self {
+        log::trace!("ColorGrading new");
+        let layout = |dimension| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension,
+                        },
+                    },
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler,
+                    },
+                ],
+            })
+        };
+        let color_bind_group_layout = layout(wgpu::TextureViewDimension::D2);
+        let lut_bind_group_layout = layout(wgpu::TextureViewDimension::D3);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+
+        let color_bind_group =
+            Self::create_bind_group(device, &color_bind_group_layout, &sampler, color);
+        let lut = Lut::new(device, encoder, NEUTRAL_SIZE, &Self::neutral(NEUTRAL_SIZE));
+        let lut_bind_group =
+            Self::create_bind_group(device, &lut_bind_group_layout, &sampler, &lut.view);
+        let pipeline = Self::create_pipeline(
+            device,
+            &color_bind_group_layout,
+            &lut_bind_group_layout,
+            format,
+        )
+        .unwrap();
+
+        ColorGrading {
+            pipeline,
+            color_bind_group_layout,
+            lut_bind_group_layout,
+            sampler,
+            color_bind_group,
+            lut_bind_group,
+            _lut: lut,
+            loaded: None,
+        }
+    }
+
+    ///After a resize
+    pub fn update_views(&mut self, device: &Device, color: &TextureView) {
+        self.color_bind_group =
+            Self::create_bind_group(device, &self.color_bind_group_layout, &self.sampler, color);
+    }
+
+    ///Loads the LUT of the map again when its path changed, the neutral one if it has none or it
+    ///can't be read
+    pub fn update(&mut self, device: &Device, encoder: &mut CommandEncoder, lut: &Option<String>) {
+        if self.loaded.as_ref() == Some(lut) {
+            return;
+        }
+        let (size, texels) = match lut {
+            Some(path) => match Self::open(path) {
+                Ok(lut) => lut,
+                Err(e) => {
+                    log::error!("Could not read LUT {}: {}", path, e);
+                    (NEUTRAL_SIZE, Self::neutral(NEUTRAL_SIZE))
+                }
+            },
+            None => (NEUTRAL_SIZE, Self::neutral(NEUTRAL_SIZE)),
+        };
+        log::debug!("ColorGrading LUT {:?} of size {}", lut, size);
+        let new_lut = Lut::new(device, encoder, size, &texels);
+        self.lut_bind_group = Self::create_bind_group(
+            device,
+            &self.lut_bind_group_layout,
+            &self.sampler,
+            &new_lut.view,
+        );
+        self._lut = new_lut;
+        self.loaded = Some(lut.clone());
+    }
+
+    ///Writes the neutral strip, to be graded in an external tool
+    pub fn write_neutral(path: &str) -> std::io::Result<()> {
+        use std::fs::File;
+        use std::io::BufWriter;
+
+        let file = File::create(path)?;
+        let w = BufWriter::new(file);
+        let mut encoder = png::Encoder::new(w, NEUTRAL_SIZE * NEUTRAL_SIZE, NEUTRAL_SIZE);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&Self::neutral_strip(NEUTRAL_SIZE))?;
+        Ok(())
+    }
+
+    ///RGBA8 strip where every texel maps to itself
+    fn neutral_strip(size: u32) -> Vec<u8> {
+        let value = |i: u32| (i as f32 / (size - 1) as f32 * 255.0).round() as u8;
+        let mut strip = Vec::with_capacity((size * size * size * 4) as usize);
+        for g in 0..size {
+            for b in 0..size {
+                for r in 0..size {
+                    strip.extend_from_slice(&[value(r), value(g), value(b), 255]);
+                }
+            }
+        }
+        strip
+    }
+
+    ///Texels of the neutral LUT, in upload order
+    fn neutral(size: u32) -> Vec<u8> {
+        Self::strip_to_texels(&Self::neutral_strip(size), size, 4)
+    }
+
+    ///Strip rows to the r, then g, then b order of the 3D texture
+    fn strip_to_texels(strip: &[u8], size: u32, channels: u32) -> Vec<u8> {
+        let mut texels = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let i = ((g * size * size + b * size + r) * channels) as usize;
+                    texels.extend_from_slice(&[strip[i], strip[i + 1], strip[i + 2], 255]);
+                }
+            }
+        }
+        texels
+    }
+
+    ///Size and texels of a LUT strip, 8 bits RGB or RGBA
+    fn open(path: &str) -> Result<(u32, Vec<u8>), String> {
+        use std::fs::File;
+
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
+        let channels = match (info.color_type, info.bit_depth) {
+            (png::ColorType::RGB, png::BitDepth::Eight) => 3,
+            (png::ColorType::RGBA, png::BitDepth::Eight) => 4,
+            (color, depth) => return Err(format!("{:?} {:?} is not 8 bits RGB(A)", color, depth)),
+        };
+        let size = info.height;
+        if size < 2 || info.width != size * size {
+            return Err(format!(
+                "{}x{} is not a size * size by size strip",
+                info.width, size
+            ));
+        }
+        let mut strip = vec![0; info.buffer_size()];
+        reader.next_frame(&mut strip).map_err(|e| e.to_string())?;
+        Ok((size, Self::strip_to_texels(&strip, size, channels)))
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        view: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        color_bind_group_layout: &BindGroupLayout,
+        lut_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[color_bind_group_layout, lut_bind_group_layout],
+        });
+
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/color_grading.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the grade pass
+    pub fn render(&self, rpass: &mut RenderPass) {
+        log::trace!("ColorGrading render");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.color_bind_group, &[]);
+        rpass.set_bind_group(1, &self.lut_bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for ColorGrading {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        _main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            &self.color_bind_group_layout,
+            &self.lut_bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
+
+impl Lut {
+    ///texels are RGBA8, r then g then b
+    fn new(device: &Device, encoder: &mut CommandEncoder, size: u32, texels: &[u8]) -> Self {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth: size,
+        };
+        let desc = wgpu::TextureDescriptor {
+            size: extent,
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: LUT_FORMAT,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        };
+        let texture = device.create_texture(&desc);
+
+        let temp_buf = device
+            .create_buffer_mapped(texels.len(), wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(texels);
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &temp_buf,
+                offset: 0,
+                row_pitch: 4 * size,
+                image_height: size,
+            },
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            },
+            extent,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: LUT_FORMAT,
+            dimension: wgpu::TextureViewDimension::D3,
+            aspect: wgpu::TextureAspect::All,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            array_layer_count: 1,
+        });
+        Lut {
+            view,
+            _texture: texture,
+            _allocation: Allocation::texture(&desc),
+        }
+    }
+}
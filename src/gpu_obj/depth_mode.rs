@@ -0,0 +1,48 @@
+///Depth state of the opaque pipelines. With the depth pre-pass on, the opaque geometry is drawn
+///twice: depth only first, then shaded where it is the nearest surface, so that hidden fragments
+///of dense scenes are never shaded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DepthMode {
+    ///No pre-pass: test and write
+    Write,
+    ///The pre-pass: depth only, no color target
+    PrePass,
+    ///Main pass after the pre-pass: equal test, depth is already written
+    Equal,
+}
+
+impl DepthMode {
+    ///In pipeline_index order
+    pub const ALL: [DepthMode; 3] = [DepthMode::Write, DepthMode::PrePass, DepthMode::Equal];
+
+    pub fn pipeline_index(self) -> usize {
+        self as usize
+    }
+
+    pub fn depth_stencil_state(self) -> wgpu::DepthStencilStateDescriptor {
+        let (depth_write_enabled, depth_compare) = match self {
+            DepthMode::Write | DepthMode::PrePass => (true, wgpu::CompareFunction::Less),
+            DepthMode::Equal => (false, wgpu::CompareFunction::Equal),
+        };
+        wgpu::DepthStencilStateDescriptor {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled,
+            depth_compare,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }
+    }
+
+    ///None in the pre-pass
+    pub fn color_states<'a>(
+        self,
+        states: &'a [wgpu::ColorStateDescriptor],
+    ) -> &'a [wgpu::ColorStateDescriptor] {
+        match self {
+            DepthMode::PrePass => &[],
+            DepthMode::Write | DepthMode::Equal => states,
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::UniformBuffer;
+use bytemuck::{Pod, Zeroable};
+use na::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass};
+use wgpu::{TextureFormat, TextureView};
+
+///Below this distance to the focus point there is no blur
+const ZOOM_NEAR: f32 = 250.0;
+///From this distance to the focus point the blur is the strongest
+const ZOOM_FAR: f32 = 1200.0;
+///Largest blur radius, as a fraction of the height of the screen
+const MAX_RADIUS: f32 = 0.012;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum DofQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl DofQuality {
+    pub const ALL: [DofQuality; 4] = [
+        DofQuality::Off,
+        DofQuality::Low,
+        DofQuality::Medium,
+        DofQuality::High,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DofQuality::Off => "off",
+            DofQuality::Low => "low",
+            DofQuality::Medium => "medium",
+            DofQuality::High => "high",
+        }
+    }
+
+    ///Samples gathered around each pixel
+    fn taps(self) -> u32 {
+        match self {
+            DofQuality::Off => 0,
+            DofQuality::Low => 12,
+            DofQuality::Medium => 24,
+            DofQuality::High => 48,
+        }
+    }
+}
+
+///Block of dof.frag
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct DofUniform {
+    ///World position in focus, w unused
+    focus: [f32; 4],
+    ///Blur radius in pixels, taps, unused, unused
+    radius_taps: [f32; 4],
+}
+
+///Tilt-shift depth of field: sharp at the terrain under the center of the screen, blurred in
+///front of and behind it, more as the camera zooms out so the battlefield looks like a miniature.
+pub struct Dof {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    linear: wgpu::Sampler,
+    nearest: wgpu::Sampler,
+    uniform: UniformBuffer<DofUniform>,
+    bind_group: BindGroup,
+}
+
+impl Dof {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        color: &TextureView,
+        position: &TextureView,
+    ) -> Self {
+        log::trace!("Dof new");
+        let texture = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Sampler,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                texture(0),
+                texture(1),
+                sampler(2),
+                sampler(3),
+                UniformBuffer::<DofUniform>::layout_binding(4, wgpu::ShaderStage::FRAGMENT),
+            ],
+        });
+
+        let create_sampler = |filter| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                lod_min_clamp: -100.0,
+                lod_max_clamp: 100.0,
+                compare_function: wgpu::CompareFunction::Always,
+            })
+        };
+        let linear = create_sampler(wgpu::FilterMode::Linear);
+        let nearest = create_sampler(wgpu::FilterMode::Nearest);
+        let uniform = UniformBuffer::new(device, &DofUniform::zeroed());
+
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &linear,
+            &nearest,
+            &uniform,
+            color,
+            position,
+        );
+        let pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, &bind_group_layout, format)
+                .unwrap();
+        Dof {
+            pipeline,
+            bind_group_layout,
+            linear,
+            nearest,
+            uniform,
+            bind_group,
+        }
+    }
+
+    ///After a resize
+    pub fn update_views(&mut self, device: &Device, color: &TextureView, position: &TextureView) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.linear,
+            &self.nearest,
+            &self.uniform,
+            color,
+            position,
+        );
+    }
+
+    ///focus is the world position under the center of the screen, if known
+    pub fn update(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        quality: DofQuality,
+        camera: &Point3<f32>,
+        focus: Option<Vector3<f32>>,
+        screen_height: u32,
+    ) {
+        let mut uniform = DofUniform::zeroed();
+        if let Some(focus) = focus {
+            let distance = (focus - camera.coords).norm();
+            let zoom = ((distance - ZOOM_NEAR) / (ZOOM_FAR - ZOOM_NEAR))
+                .max(0.0)
+                .min(1.0);
+            uniform.focus = [focus.x, focus.y, focus.z, 0.0];
+            uniform.radius_taps = [
+                zoom * MAX_RADIUS * screen_height as f32,
+                quality.taps() as f32,
+                0.0,
+                0.0,
+            ];
+        }
+        self.uniform.update(device, encoder, belt, &uniform);
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        linear: &wgpu::Sampler,
+        nearest: &wgpu::Sampler,
+        uniform: &UniformBuffer<DofUniform>,
+        color: &TextureView,
+        position: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(position),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(linear),
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(nearest),
+                },
+                uniform.binding(4),
+            ],
+        })
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[main_bind_group_layout, bind_group_layout],
+        });
+
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/dof.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the dof pass
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("Dof render");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, main_bind_group, &[]);
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for Dof {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            main_bind_group_layout,
+            &self.bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
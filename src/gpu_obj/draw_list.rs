@@ -0,0 +1,73 @@
+///Drawn in this order: each pass of the render graph draws some consecutive layers
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Layer {
+    OpaqueTerrain,
+    OpaqueModels,
+    Decals,
+    Transparent,
+    Effects,
+    Ui,
+}
+
+///Orders by layer, then pipeline, then material, so that draws sharing state are consecutive.
+///Field order matters for the derived Ord.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct SortKey {
+    pub layer: Layer,
+    pub pipeline: u16,
+    pub material: u32,
+}
+
+impl SortKey {
+    pub fn new(layer: Layer, pipeline: u16, material: u32) -> Self {
+        SortKey {
+            layer,
+            pipeline,
+            material,
+        }
+    }
+}
+
+///Draws of a frame, refilled every frame without reallocating
+pub struct DrawList<T> {
+    draws: Vec<(SortKey, T)>,
+}
+
+impl<T: Copy> DrawList<T> {
+    pub fn new() -> Self {
+        DrawList { draws: Vec::new() }
+    }
+
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+
+    pub fn push(&mut self, key: SortKey, draw: T) {
+        self.draws.push((key, draw));
+    }
+
+    ///Stable, draws with equal keys keep their push order
+    pub fn sort(&mut self) {
+        self.draws.sort_by_key(|(key, _)| *key);
+    }
+
+    ///Draws of the given layers in sorted order, with whether the pipeline changed since the
+    ///previous one
+    pub fn layers<'a>(
+        &'a self,
+        layers: &'a [Layer],
+    ) -> impl Iterator<Item = (SortKey, T, bool)> + 'a
+    where
+        T: 'a,
+    {
+        let mut previous: Option<(Layer, u16)> = None;
+        self.draws
+            .iter()
+            .filter(move |(key, _)| layers.contains(&key.layer))
+            .map(move |&(key, draw)| {
+                let state = Some((key.layer, key.pipeline));
+                let changed = std::mem::replace(&mut previous, state) != state;
+                (key, draw, changed)
+            })
+    }
+}
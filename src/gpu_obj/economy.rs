@@ -0,0 +1,358 @@
+use super::instance_buffer::InstanceBuffer;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::UniformBuffer;
+use crate::heightmap_phy::{HeightmapPhy, MetalSpot};
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass};
+use wgpu::{TextureFormat, TextureView};
+
+///Spots sent to the overlay, must match economy_overlay.frag
+pub const MAX_SPOTS: usize = 64;
+///Floats per decal: ground position xyz, metal/frame
+pub const INSTANCE_LEN: usize = 4;
+
+///MetalSpots block of economy_overlay.frag
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct MetalSpotsUniform {
+    ///Only x is used
+    spot_count: [f32; 4],
+    ///x, y, extraction radius, metal/frame
+    spot_pos_radius_metal: [[f32; 4]; MAX_SPOTS],
+}
+
+///Metal spots of the map, always drawn as glowing decals on the ground, and the economy overlay
+///toggled by the player: a grid over the terrain tinted by the metal an extractor would get in
+///each cell. Players have an energy stock but nothing produces or spends it yet, so there is no
+///energy network to draw.
+pub struct EconomyGpu {
+    decal_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+    overlay_bind_group_layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    overlay_bind_group: BindGroup,
+    uniform: UniformBuffer<MetalSpotsUniform>,
+    instance_buf: InstanceBuffer,
+    instance_count: u32,
+    ///Spots uploaded, to upload again only when the map changes
+    spots: Vec<MetalSpot>,
+}
+
+impl EconomyGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        position: &TextureView,
+    ) -> Self {
+        log::trace!("EconomyGpu new");
+        let overlay_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    UniformBuffer::<MetalSpotsUniform>::layout_binding(
+                        0,
+                        wgpu::ShaderStage::FRAGMENT,
+                    ),
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                        },
+                    },
+                    wgpu::BindGroupLayoutBinding {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler,
+                    },
+                ],
+            });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+
+        let uniform = UniformBuffer::new(device, &MetalSpotsUniform::zeroed());
+        let overlay_bind_group = Self::create_overlay_bind_group(
+            device,
+            &overlay_bind_group_layout,
+            &uniform,
+            &sampler,
+            position,
+        );
+        let decal_pipeline =
+            Self::create_decal_pipeline(device, main_bind_group_layout, format).unwrap();
+        let overlay_pipeline = Self::create_overlay_pipeline(
+            device,
+            main_bind_group_layout,
+            &overlay_bind_group_layout,
+            format,
+        )
+        .unwrap();
+
+        EconomyGpu {
+            decal_pipeline,
+            overlay_pipeline,
+            overlay_bind_group_layout,
+            sampler,
+            overlay_bind_group,
+            uniform,
+            instance_buf: InstanceBuffer::new(device),
+            instance_count: 0,
+            spots: Vec::new(),
+        }
+    }
+
+    ///After a resize
+    pub fn update_views(&mut self, device: &Device, position: &TextureView) {
+        self.overlay_bind_group = Self::create_overlay_bind_group(
+            device,
+            &self.overlay_bind_group_layout,
+            &self.uniform,
+            &self.sampler,
+            position,
+        );
+    }
+
+    fn create_overlay_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform: &UniformBuffer<MetalSpotsUniform>,
+        sampler: &wgpu::Sampler,
+        position: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                uniform.binding(0),
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(position),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    ///Uploads the spots of the map again when they changed
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        phy: &HeightmapPhy,
+    ) {
+        if self.spots == phy.data.metal_spots {
+            return;
+        }
+        log::trace!("EconomyGpu update");
+        self.spots = phy.data.metal_spots.clone();
+
+        let mut data = MetalSpotsUniform::zeroed();
+        let mut instance_attr = Vec::with_capacity(self.spots.len() * INSTANCE_LEN);
+        for (i, spot) in self.spots.iter().enumerate() {
+            let (x, y) = (spot.x as f32, spot.y as f32);
+            if i < MAX_SPOTS {
+                data.spot_pos_radius_metal[i] =
+                    [x, y, MetalSpot::EXTRACTION_RADIUS, spot.metal_per_frame];
+            }
+            instance_attr.extend_from_slice(&[x, y, phy.safe_z(x, y), spot.metal_per_frame]);
+        }
+        if self.spots.len() > MAX_SPOTS {
+            log::warn!(
+                "{} metal spots, the overlay only shows the first {}",
+                self.spots.len(),
+                MAX_SPOTS
+            );
+        }
+        data.spot_count[0] = self.spots.len().min(MAX_SPOTS) as f32;
+        self.uniform.update(device, encoder, belt, &data);
+        self.instance_buf
+            .update(&instance_attr, device, encoder, belt);
+        self.instance_count = self.spots.len() as u32;
+    }
+
+    fn create_decal_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&main_bind_group_layout],
+        });
+        let vs_bytes = shaders::load("./src/shader/metal_spot.vert")?;
+        let fs_bytes = shaders::load("./src/shader/metal_spot.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            //Additive, the spots glow
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: (4 * INSTANCE_LEN) as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &[wgpu::VertexAttributeDescriptor {
+                    format: wgpu::VertexFormat::Float4,
+                    offset: 0,
+                    shader_location: 0,
+                }],
+            }],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    fn create_overlay_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        overlay_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[main_bind_group_layout, overlay_bind_group_layout],
+        });
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/economy_overlay.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the transparent pass, with the decals
+    pub fn render_decals(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("EconomyGpu render_decals");
+        if self.instance_count > 0 {
+            rpass.set_pipeline(&self.decal_pipeline);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.set_vertex_buffers(0, &[(self.instance_buf.buffer(), 0)]);
+            rpass.draw(0..4, 0..self.instance_count);
+        }
+    }
+
+    ///In the economy_overlay pass
+    pub fn render_overlay(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("EconomyGpu render_overlay");
+        rpass.set_pipeline(&self.overlay_pipeline);
+        rpass.set_bind_group(0, main_bind_group, &[]);
+        rpass.set_bind_group(1, &self.overlay_bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for EconomyGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_decal_pipeline(device, main_bind_group_layout, format) {
+            Ok(pipeline) => self.decal_pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+        match Self::create_overlay_pipeline(
+            device,
+            main_bind_group_layout,
+            &self.overlay_bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.overlay_pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
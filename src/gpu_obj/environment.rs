@@ -0,0 +1,283 @@
+use super::gpu_memory::Allocation;
+use super::shaders::{self, Defines};
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::DynamicUniformBuffer;
+use crate::sky::Sky;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroupLayout, CommandEncoder, Device, TextureView};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+///Width of a face of the irradiance cube, it is very low frequency
+const IRRADIANCE_SIZE: u32 = 16;
+///Width of a face of the first specular level
+const SPECULAR_SIZE: u32 = 64;
+///Roughness goes from 0 at level 0 to 1 at the last level, must match ENV_SPECULAR_LEVELS
+const SPECULAR_LEVELS: u32 = 5;
+///One draw per face of the irradiance cube and per face and level of the specular cube
+const BAKE_DRAWS: usize = 6 * (1 + SPECULAR_LEVELS as usize);
+
+///Block of environment_bake.frag
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct BakeUniform {
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+    ground: [f32; 4],
+    ///Cube face, roughness
+    face_roughness: [f32; 4],
+}
+
+struct Cube {
+    view: TextureView,
+    ///Per level, per face
+    face_views: Vec<Vec<TextureView>>,
+    _texture: wgpu::Texture,
+    _allocation: Allocation,
+}
+
+///Image based ambient light: an irradiance cube for the diffuse part and a cube prefiltered per
+///roughness in its mip levels for the specular part, baked on the gpu from the sky of the map
+///whenever it changes.
+pub struct Environment {
+    irradiance: Cube,
+    specular: Cube,
+    sampler: wgpu::Sampler,
+    bake_uniform: DynamicUniformBuffer<BakeUniform>,
+    bake_bind_group: wgpu::BindGroup,
+    irradiance_pipeline: wgpu::RenderPipeline,
+    specular_pipeline: wgpu::RenderPipeline,
+    ///None until the first bake
+    baked: Option<Sky>,
+}
+
+impl Environment {
+    pub fn new(device: &Device) -> Self {
+        log::trace!("Environment new");
+        let irradiance = Cube::new(device, IRRADIANCE_SIZE, 1);
+        let specular = Cube::new(device, SPECULAR_SIZE, SPECULAR_LEVELS);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+
+        let bake_uniform = DynamicUniformBuffer::new(device, BAKE_DRAWS);
+        let bake_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[DynamicUniformBuffer::<BakeUniform>::layout_binding(
+                    0,
+                    wgpu::ShaderStage::FRAGMENT,
+                )],
+            });
+        let bake_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bake_bind_group_layout,
+            bindings: &[bake_uniform.binding(0)],
+        });
+        let irradiance_pipeline =
+            Self::create_pipeline(device, &bake_bind_group_layout, "IRRADIANCE").unwrap();
+        let specular_pipeline =
+            Self::create_pipeline(device, &bake_bind_group_layout, "SPECULAR").unwrap();
+
+        Environment {
+            irradiance,
+            specular,
+            sampler,
+            bake_uniform,
+            bake_bind_group,
+            irradiance_pipeline,
+            specular_pipeline,
+            baked: None,
+        }
+    }
+
+    ///Sampler, irradiance cube and specular cube, from binding first
+    pub fn layout_bindings(first: u32) -> [wgpu::BindGroupLayoutBinding; 3] {
+        let cube = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::Cube,
+            },
+        };
+        [
+            wgpu::BindGroupLayoutBinding {
+                binding: first,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler,
+            },
+            cube(first + 1),
+            cube(first + 2),
+        ]
+    }
+
+    pub fn bindings(&self, first: u32) -> [wgpu::Binding; 3] {
+        [
+            wgpu::Binding {
+                binding: first,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::Binding {
+                binding: first + 1,
+                resource: wgpu::BindingResource::TextureView(&self.irradiance.view),
+            },
+            wgpu::Binding {
+                binding: first + 2,
+                resource: wgpu::BindingResource::TextureView(&self.specular.view),
+            },
+        ]
+    }
+
+    ///Bakes the cubes again when the sky differs from the last one baked
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        sky: &Sky,
+    ) {
+        if self.baked.as_ref() == Some(sky) {
+            return;
+        }
+        log::debug!("Environment bake {:?}", sky);
+        let color = |c: [f32; 3]| [c[0], c[1], c[2], 0.0];
+        let uniform = |face: usize, roughness: f32| BakeUniform {
+            zenith: color(sky.zenith),
+            horizon: color(sky.horizon),
+            ground: color(sky.ground),
+            face_roughness: [face as f32, roughness, 0.0, 0.0],
+        };
+
+        self.bake_uniform.clear();
+        let mut draws = Vec::with_capacity(BAKE_DRAWS);
+        for (face, view) in self.irradiance.face_views[0].iter().enumerate() {
+            let offset = self.bake_uniform.push(&uniform(face, 0.0)).unwrap();
+            draws.push((&self.irradiance_pipeline, view, offset));
+        }
+        for (level, face_views) in self.specular.face_views.iter().enumerate() {
+            let roughness = level as f32 / (SPECULAR_LEVELS - 1) as f32;
+            for (face, view) in face_views.iter().enumerate() {
+                let offset = self.bake_uniform.push(&uniform(face, roughness)).unwrap();
+                draws.push((&self.specular_pipeline, view, offset));
+            }
+        }
+        self.bake_uniform.upload(device, encoder, belt);
+
+        for (pipeline, target, offset) in draws {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: target,
+                    resolve_target: None,
+                    load_op: wgpu::LoadOp::Clear,
+                    store_op: wgpu::StoreOp::Store,
+                    clear_color: wgpu::Color::BLACK,
+                }],
+                depth_stencil_attachment: None,
+            });
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, &self.bake_bind_group, &[offset]);
+            rpass.draw(0..4, 0..1);
+        }
+        self.baked = Some(*sky);
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        bake_bind_group_layout: &BindGroupLayout,
+        flag: &str,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[bake_bind_group_layout],
+        });
+
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load_with_defines(
+            "./src/shader/environment_bake.frag",
+            &Defines::new().flag(flag),
+        )?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+}
+
+impl Cube {
+    fn new(device: &Device, size: u32, level_count: u32) -> Self {
+        let desc = wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth: 1,
+            },
+            array_layer_count: 6,
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        };
+        let texture = device.create_texture(&desc);
+        let view = |dimension, base_mip_level, level_count, base_array_layer, array_layer_count| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                format: FORMAT,
+                dimension,
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level,
+                level_count,
+                base_array_layer,
+                array_layer_count,
+            })
+        };
+        let face_views = (0..level_count)
+            .map(|level| {
+                (0..6)
+                    .map(|face| view(wgpu::TextureViewDimension::D2, level, 1, face, 1))
+                    .collect()
+            })
+            .collect();
+        Cube {
+            view: view(wgpu::TextureViewDimension::Cube, 0, level_count, 0, 6),
+            face_views,
+            _texture: texture,
+            _allocation: Allocation::texture(&desc),
+        }
+    }
+}
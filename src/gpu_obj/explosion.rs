@@ -1,15 +1,19 @@
-use super::glsl_compiler;
+use super::gpu_memory::Allocation;
+use super::instance_buffer::InstanceBuffer;
+use super::shaders;
+use super::staging_belt::StagingBelt;
 use crate::model;
 use wgpu::Device;
 use wgpu::{BindGroup, BindGroupLayout, RenderPass, Texture, TextureFormat, TextureView};
 
 pub struct ExplosionGpu {
-    instance_buf: wgpu::Buffer,
+    instance_buf: InstanceBuffer,
     instance_count: u32,
     pipeline: wgpu::RenderPipeline,
     bind_group_layout: BindGroupLayout,
     bind_group: BindGroup,
     noise_texture: Texture,
+    _noise_texture_mem: Allocation,
 }
 
 impl ExplosionGpu {
@@ -30,7 +34,7 @@ impl ExplosionGpu {
             height: size,
             depth: 1,
         };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
+        let texture_desc = wgpu::TextureDescriptor {
             size: texture_extent,
             array_layer_count: 1,
             mip_level_count: 1,
@@ -38,7 +42,8 @@ impl ExplosionGpu {
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-        });
+        };
+        let texture = device.create_texture(&texture_desc);
 
         let temp_buf = device
             .create_buffer_mapped(texels.len(), wgpu::BufferUsage::COPY_SRC)
@@ -116,14 +121,7 @@ impl ExplosionGpu {
             normal_att,
         );
 
-        let positions: Vec<f32> = Vec::new();
-
-        let instance_buf = device
-            .create_buffer_mapped(
-                positions.len(),
-                wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-            )
-            .fill_from_slice(&positions);
+        let instance_buf = InstanceBuffer::new(device);
 
         let pipeline =
             Self::create_pipeline(device, &bind_group_layout, main_bind_group_layout, format)
@@ -136,6 +134,7 @@ impl ExplosionGpu {
             bind_group,
             bind_group_layout,
             noise_texture: texture,
+            _noise_texture_mem: Allocation::texture(&texture_desc),
         }
     }
 
@@ -235,13 +234,13 @@ impl ExplosionGpu {
         bind_group_layout: &BindGroupLayout,
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
-    ) -> glsl_compiler::Result<wgpu::RenderPipeline> {
+    ) -> shaders::Result<wgpu::RenderPipeline> {
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[&main_bind_group_layout, &bind_group_layout],
         });
         // Create the render pipeline
-        let vs_bytes = glsl_compiler::load("./src/shader/explosion.vert")?;
-        let fs_bytes = glsl_compiler::load("./src/shader/explosion.frag")?;
+        let vs_bytes = shaders::load("./src/shader/explosion.vert")?;
+        let fs_bytes = shaders::load("./src/shader/explosion.frag")?;
 
         let vs_module = device.create_shader_module(&vs_bytes);
         let fs_module = device.create_shader_module(&fs_bytes);
@@ -337,22 +336,29 @@ impl ExplosionGpu {
         log::trace!("ExplosionGpu render");
         if self.instance_count > 0 {
             rpass.set_pipeline(&self.pipeline);
-            rpass.set_vertex_buffers(0, &[(&self.instance_buf, 0)]);
+            rpass.set_vertex_buffers(0, &[(self.instance_buf.buffer(), 0)]);
             rpass.set_bind_group(0, main_bind_group, &[]);
             rpass.set_bind_group(1, &self.bind_group, &[]);
             rpass.draw(0..4, 0..self.instance_count as u32);
         }
     }
 
-    pub fn update_instance(&mut self, instance_attr: &[f32], device: &wgpu::Device) {
+    pub fn update_instance(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
         log::trace!("ExplosionGpu update_instance");
-        let temp_buf = device
-            .create_buffer_mapped(instance_attr.len(), wgpu::BufferUsage::VERTEX)
-            .fill_from_slice(instance_attr);
-
-        std::mem::replace(&mut self.instance_buf, temp_buf);
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
         self.instance_count = instance_attr.len() as u32 / 6;
     }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
 }
 
 impl super::trait_gpu::TraitGpu for ExplosionGpu {
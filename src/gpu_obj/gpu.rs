@@ -11,7 +11,7 @@ pub struct WgpuState {
 }
 
 impl WgpuState {
-    pub fn new(window: winit::window::Window) -> Self {
+    pub fn new(window: winit::window::Window, backends: wgpu::BackendBit) -> Self {
         let (hidpi_factor, size, surface) = {
             let hidpi_factor = window.hidpi_factor();
 
@@ -24,9 +24,9 @@ impl WgpuState {
 
         let adapter = wgpu::Adapter::request(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::HighPerformance,
-            backends: wgpu::BackendBit::PRIMARY,
+            backends,
         })
-        .unwrap();
+        .expect("No adapter for the requested backends");
 
         let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
             extensions: wgpu::Extensions {
@@ -43,6 +43,10 @@ impl WgpuState {
             present_mode: wgpu::PresentMode::NoVsync,
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        crate::crash::set_adapter_info(format!(
+            "high performance, backends {:?}, {}x{} swap chain, hidpi {}",
+            backends, sc_desc.width, sc_desc.height, hidpi_factor
+        ));
 
         // let physical_wanted = winit::dpi::PhysicalSize {
         //     width: 1281.0,
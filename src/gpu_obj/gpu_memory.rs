@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+///Memory allocated on the gpu by the game, for the statistics window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Vertex,
+    Index,
+    Instance,
+    ///Draw arguments read by the gpu
+    Indirect,
+    Texture,
+    ///Upload chunks of the staging belt
+    Staging,
+}
+
+static VERTEX: AtomicU64 = AtomicU64::new(0);
+static INDEX: AtomicU64 = AtomicU64::new(0);
+static INSTANCE: AtomicU64 = AtomicU64::new(0);
+static INDIRECT: AtomicU64 = AtomicU64::new(0);
+static TEXTURE: AtomicU64 = AtomicU64::new(0);
+static STAGING: AtomicU64 = AtomicU64::new(0);
+
+impl Kind {
+    pub const ALL: [Kind; 6] = [
+        Kind::Vertex,
+        Kind::Index,
+        Kind::Instance,
+        Kind::Indirect,
+        Kind::Texture,
+        Kind::Staging,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Vertex => "vertex",
+            Kind::Index => "index",
+            Kind::Instance => "instance",
+            Kind::Indirect => "indirect",
+            Kind::Texture => "texture",
+            Kind::Staging => "staging",
+        }
+    }
+
+    fn counter(&self) -> &'static AtomicU64 {
+        match self {
+            Kind::Vertex => &VERTEX,
+            Kind::Index => &INDEX,
+            Kind::Instance => &INSTANCE,
+            Kind::Indirect => &INDIRECT,
+            Kind::Texture => &TEXTURE,
+            Kind::Staging => &STAGING,
+        }
+    }
+
+    ///Bytes currently allocated
+    pub fn allocated(&self) -> u64 {
+        self.counter().load(Ordering::Relaxed)
+    }
+}
+
+pub fn total() -> u64 {
+    Kind::ALL.iter().map(|kind| kind.allocated()).sum()
+}
+
+///Counted while alive, kept next to the gpu resource it describes
+pub struct Allocation {
+    kind: Kind,
+    bytes: u64,
+}
+
+impl Allocation {
+    pub fn new(kind: Kind, bytes: u64) -> Self {
+        kind.counter().fetch_add(bytes, Ordering::Relaxed);
+        Allocation { kind, bytes }
+    }
+
+    pub fn texture(desc: &wgpu::TextureDescriptor) -> Self {
+        Self::new(Kind::Texture, texture_bytes(desc))
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.kind.counter().fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+///Size of all the mip levels and layers, without the driver's padding
+pub fn texture_bytes(desc: &wgpu::TextureDescriptor) -> u64 {
+    let texel_bytes = match desc.format {
+        wgpu::TextureFormat::R8Unorm => 1,
+        wgpu::TextureFormat::Rg16Float => 4,
+        wgpu::TextureFormat::Rgba16Float => 8,
+        wgpu::TextureFormat::Rgba32Float => 16,
+        _ => 4,
+    };
+    let layer_bytes: u64 = (0..desc.mip_level_count)
+        .map(|mip| {
+            let width = (desc.size.width >> mip).max(1) as u64;
+            let height = (desc.size.height >> mip).max(1) as u64;
+            width * height * desc.size.depth as u64 * texel_bytes
+        })
+        .sum();
+    layer_bytes * desc.array_layer_count as u64 * desc.sample_count as u64
+}
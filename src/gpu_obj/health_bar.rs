@@ -1,10 +1,12 @@
-use super::glsl_compiler;
+use super::instance_buffer::InstanceBuffer;
+use super::shaders;
+use super::staging_belt::StagingBelt;
 use crate::model;
 use wgpu::Device;
 use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat};
 
 pub struct HealthBarGpu {
-    instance_buf: wgpu::Buffer,
+    instance_buf: InstanceBuffer,
     instance_count: u32,
     pipeline: wgpu::RenderPipeline,
 }
@@ -17,14 +19,7 @@ impl HealthBarGpu {
     ) -> Self {
         log::trace!("HealthBarGpu new");
 
-        let positions: Vec<f32> = Vec::new();
-
-        let instance_buf = device
-            .create_buffer_mapped(
-                positions.len(),
-                wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-            )
-            .fill_from_slice(&positions);
+        let instance_buf = InstanceBuffer::new(device);
 
         let pipeline = Self::create_pipeline(device, main_bind_group_layout, format).unwrap();
 
@@ -39,13 +34,13 @@ impl HealthBarGpu {
         device: &Device,
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
-    ) -> glsl_compiler::Result<wgpu::RenderPipeline> {
+    ) -> shaders::Result<wgpu::RenderPipeline> {
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[&main_bind_group_layout],
         });
         // Create the render pipeline
-        let vs_bytes = glsl_compiler::load("./src/shader/health_bar.vert")?;
-        let fs_bytes = glsl_compiler::load("./src/shader/health_bar.frag")?;
+        let vs_bytes = shaders::load("./src/shader/health_bar.vert")?;
+        let fs_bytes = shaders::load("./src/shader/health_bar.frag")?;
 
         let vs_module = device.create_shader_module(&vs_bytes);
         let fs_module = device.create_shader_module(&fs_bytes);
@@ -121,21 +116,28 @@ impl HealthBarGpu {
         log::trace!("HealthBarGpu render");
         if self.instance_count > 0 {
             rpass.set_pipeline(&self.pipeline);
-            rpass.set_vertex_buffers(0, &[(&self.instance_buf, 0)]);
+            rpass.set_vertex_buffers(0, &[(self.instance_buf.buffer(), 0)]);
             rpass.set_bind_group(0, main_bind_group, &[]);
             rpass.draw(0..4, 0..self.instance_count as u32);
         }
     }
 
-    pub fn update_instance(&mut self, instance_attr: &[f32], device: &wgpu::Device) {
+    pub fn update_instance(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
         log::trace!("HealthBarGpu update_instance");
-        let temp_buf = device
-            .create_buffer_mapped(instance_attr.len(), wgpu::BufferUsage::VERTEX)
-            .fill_from_slice(instance_attr);
-
-        std::mem::replace(&mut self.instance_buf, temp_buf);
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
         self.instance_count = instance_attr.len() as u32 / 7;
     }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
 }
 
 impl super::trait_gpu::TraitGpu for HealthBarGpu {
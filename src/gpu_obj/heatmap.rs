@@ -0,0 +1,291 @@
+use super::gpu_memory::Allocation;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::UniformBuffer;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass};
+use wgpu::{TextureFormat, TextureView};
+
+///Cells on each side of the grid, which spans the whole map
+pub const GRID_SIZE: usize = 128;
+
+///What a grid shows, its colors in heatmap.frag
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeatmapKind {
+    PathCost,
+    Threat,
+    Vision,
+    Placement,
+}
+
+///Heatmap block of heatmap.frag
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct HeatmapUniform {
+    ///Width and height of the map in m, kind, cells on a side
+    map_size_kind: [f32; 4],
+}
+
+///A grid of values from 0 to 1 tinting the cells of the terrain under it, from the position
+///buffer. Negative cells are left untouched.
+pub struct HeatmapGpu {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: BindGroup,
+    uniform: UniformBuffer<HeatmapUniform>,
+    grid: wgpu::Texture,
+    grid_view: TextureView,
+    _grid_mem: Allocation,
+}
+
+impl HeatmapGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        position: &TextureView,
+    ) -> Self {
+        log::trace!("HeatmapGpu new");
+        let texture_binding = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                UniformBuffer::<HeatmapUniform>::layout_binding(0, wgpu::ShaderStage::FRAGMENT),
+                texture_binding(1),
+                texture_binding(2),
+                wgpu::BindGroupLayoutBinding {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+
+        let grid_desc = wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: GRID_SIZE as u32,
+                height: GRID_SIZE as u32,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        };
+        let grid = device.create_texture(&grid_desc);
+        let grid_view = grid.create_default_view();
+
+        let uniform = UniformBuffer::new(device, &HeatmapUniform::zeroed());
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform,
+            &sampler,
+            position,
+            &grid_view,
+        );
+        let pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, &bind_group_layout, format)
+                .unwrap();
+
+        HeatmapGpu {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            bind_group,
+            uniform,
+            grid,
+            grid_view,
+            _grid_mem: Allocation::texture(&grid_desc),
+        }
+    }
+
+    ///After a resize
+    pub fn update_views(&mut self, device: &Device, position: &TextureView) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform,
+            &self.sampler,
+            position,
+            &self.grid_view,
+        );
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform: &UniformBuffer<HeatmapUniform>,
+        sampler: &wgpu::Sampler,
+        position: &TextureView,
+        grid: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                uniform.binding(0),
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(position),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(grid),
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    ///cells holds GRID_SIZE rows of GRID_SIZE values, the first row at y = 0
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        map_size: (f32, f32),
+        kind: HeatmapKind,
+        cells: &[f32],
+    ) {
+        log::trace!("HeatmapGpu update");
+        assert_eq!(cells.len(), GRID_SIZE * GRID_SIZE);
+        let kind = match kind {
+            HeatmapKind::PathCost => 0.0,
+            HeatmapKind::Threat => 1.0,
+            HeatmapKind::Vision => 2.0,
+            HeatmapKind::Placement => 3.0,
+        };
+        let data = HeatmapUniform {
+            map_size_kind: [map_size.0, map_size.1, kind, GRID_SIZE as f32],
+        };
+        self.uniform.update(device, encoder, belt, &data);
+        belt.write_texture(
+            device,
+            encoder,
+            wgpu::TextureCopyView {
+                texture: &self.grid,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            },
+            wgpu::Extent3d {
+                width: GRID_SIZE as u32,
+                height: GRID_SIZE as u32,
+                depth: 1,
+            },
+            cells,
+        );
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[main_bind_group_layout, bind_group_layout],
+        });
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/heatmap.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the heatmap pass
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("HeatmapGpu render");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, main_bind_group, &[]);
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for HeatmapGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            main_bind_group_layout,
+            &self.bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
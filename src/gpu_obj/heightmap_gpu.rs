@@ -1,22 +1,38 @@
-use super::glsl_compiler;
+use super::depth_mode::DepthMode;
+use super::gpu_memory::{Allocation, Kind};
 use super::heightmap_helper;
+use super::point_light;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::taa;
 use crate::heightmap_phy;
 
+use std::ops::Range;
 use wgpu::{BindGroup, BindGroupLayout, RenderPass, RenderPipeline, Texture, TextureFormat};
 use wgpu::{CommandEncoder, Device};
 
 const ZONE_SIZE_MIP0: usize = 64;
 const UPDATE_PER_STEP: usize = 300;
 const MIP_COUNT: u32 = 5;
+///Enough for the coarsest level to cover a 16384 map from any camera position
+const MAX_LEVELS: usize = 10;
 pub const MAX_Z: f32 = 511.0;
 
 pub struct HeightmapGpu {
-    pipeline: RenderPipeline,
+    ///One per DepthMode
+    pipelines: Vec<RenderPipeline>,
     bind_group_layout: BindGroupLayout,
     bind_group: BindGroup,
     vertex_buf: wgpu::Buffer,
+    ///Clipmap level of each instance
+    level_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
-    index_count: usize,
+    clipmap_full: Range<u32>,
+    clipmap_rings: [Range<u32>; 4],
+    ///Levels drawn, the coarsest one covers the whole map
+    level_count: usize,
+    ///Camera position of the last uniform update, to pick the hole of each ring
+    cam_pos: [f32; 2],
     pub phy: heightmap_phy::HeightmapPhy,
     ring_size: u32,
     texture: Texture,
@@ -26,6 +42,7 @@ pub struct HeightmapGpu {
     zone_to_update_mip1: Vec<i32>,
     zone_to_update_mip2: Vec<i32>,
     mip4_to_update: bool,
+    _memory: Vec<Allocation>,
 }
 
 impl HeightmapGpu {
@@ -123,7 +140,7 @@ impl HeightmapGpu {
             compare_function: wgpu::CompareFunction::Always,
         });
 
-        let (texture_view_lod, texture_lod) = {
+        let (texture_view_lod, texture_lod, texture_lod_mem) = {
             let width = phy.width as u32 / ZONE_SIZE_MIP0 as u32;
             let height = phy.height as u32 / ZONE_SIZE_MIP0 as u32;
 
@@ -133,7 +150,7 @@ impl HeightmapGpu {
                 height,
                 depth: 1,
             };
-            let texture = device.create_texture(&wgpu::TextureDescriptor {
+            let texture_desc = wgpu::TextureDescriptor {
                 size: texture_extent,
                 array_layer_count: 1,
                 mip_level_count: 1,
@@ -141,7 +158,8 @@ impl HeightmapGpu {
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::R32Float,
                 usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-            });
+            };
+            let texture = device.create_texture(&texture_desc);
 
             let mut texels = vec![0_f32; size as usize];
 
@@ -170,7 +188,11 @@ impl HeightmapGpu {
                 texture_extent,
             );
 
-            (texture.create_default_view(), texture)
+            (
+                texture.create_default_view(),
+                texture,
+                Allocation::texture(&texture_desc),
+            )
         };
 
         let sampler_lod = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -193,7 +215,7 @@ impl HeightmapGpu {
             depth: 1,
         };
 
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
+        let texture_desc = wgpu::TextureDescriptor {
             size: texture_extent,
             array_layer_count: 1,
             mip_level_count: MIP_COUNT,
@@ -201,7 +223,9 @@ impl HeightmapGpu {
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::R32Float,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
-        });
+        };
+        let texture = device.create_texture(&texture_desc);
+        let texture_mem = Allocation::texture(&texture_desc);
 
         let temp_buf = device
             .create_buffer_mapped(phy.texels.len(), wgpu::BufferUsage::COPY_SRC)
@@ -350,8 +374,8 @@ impl HeightmapGpu {
             ],
         });
 
-        let pipeline =
-            Self::create_pipeline(device, &bind_group_layout, main_bind_group_layout, format)
+        let pipelines =
+            Self::create_pipelines(device, &bind_group_layout, main_bind_group_layout, format)
                 .unwrap();
 
         // Create bind group
@@ -392,9 +416,9 @@ impl HeightmapGpu {
             ],
         });
 
-        let (vertex_data, height_index_data) =
-            heightmap_helper::create_vertex_index_rings(ring_size);
-        //            heightmap::create_vertices_indices(width, height, 0.0);
+        let clipmap = heightmap_helper::create_clipmap(ring_size);
+        let vertex_data = clipmap.vertices;
+        let height_index_data = clipmap.indices;
         let vertex_buf = device
             .create_buffer_mapped(vertex_data.len(), wgpu::BufferUsage::VERTEX)
             .fill_from_slice(&vertex_data);
@@ -403,7 +427,30 @@ impl HeightmapGpu {
             .create_buffer_mapped(height_index_data.len(), wgpu::BufferUsage::INDEX)
             .fill_from_slice(&height_index_data);
 
-        let index_count = height_index_data.len();
+        let level_data: Vec<f32> = (0..MAX_LEVELS).map(|level| level as f32).collect();
+        let level_buf = device
+            .create_buffer_mapped(level_data.len(), wgpu::BufferUsage::VERTEX)
+            .fill_from_slice(&level_data);
+
+        //Level l covers ring_size / 2 * 2^l units on each side of the camera
+        let map_size = phy.width.max(phy.height) as f32;
+        let mut level_count = 1;
+        while level_count < MAX_LEVELS
+            && (ring_size / 2) as f32 * 2.0_f32.powi(level_count as i32 - 1) < 2.0 * map_size
+        {
+            level_count += 1;
+        }
+
+        let memory = vec![
+            texture_mem,
+            texture_lod_mem,
+            Allocation::new(Kind::Vertex, std::mem::size_of_val(&vertex_data[..]) as u64),
+            Allocation::new(Kind::Vertex, std::mem::size_of_val(&level_data[..]) as u64),
+            Allocation::new(
+                Kind::Index,
+                std::mem::size_of_val(&height_index_data[..]) as u64,
+            ),
+        ];
 
         let mut zone_to_update_mip0 = Vec::new();
 
@@ -429,12 +476,16 @@ impl HeightmapGpu {
         }
 
         HeightmapGpu {
-            pipeline,
+            pipelines,
             bind_group,
             bind_group_layout,
             vertex_buf,
+            level_buf,
             index_buf,
-            index_count,
+            clipmap_full: clipmap.full,
+            clipmap_rings: clipmap.rings,
+            level_count,
+            cam_pos: [0.0, 0.0],
             phy,
             ring_size,
             texture,
@@ -444,15 +495,38 @@ impl HeightmapGpu {
             zone_to_update_mip1,
             zone_to_update_mip2,
             mip4_to_update: false,
+            _memory: memory,
         }
     }
 
+    ///One per DepthMode, in DepthMode::ALL order
+    pub fn create_pipelines(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<Vec<RenderPipeline>> {
+        DepthMode::ALL
+            .iter()
+            .map(|&depth| {
+                Self::create_pipeline(
+                    device,
+                    bind_group_layout,
+                    main_bind_group_layout,
+                    format,
+                    depth,
+                )
+            })
+            .collect()
+    }
+
     pub fn create_pipeline(
         device: &Device,
         bind_group_layout: &BindGroupLayout,
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
-    ) -> glsl_compiler::Result<wgpu::RenderPipeline> {
+        depth: DepthMode,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
         // Create pipeline layout
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -460,8 +534,11 @@ impl HeightmapGpu {
         });
 
         // Create the render pipeline
-        let vs_bytes = glsl_compiler::load("./src/shader/heightmap.vert")?;
-        let fs_bytes = glsl_compiler::load("./src/shader/heightmap.frag")?;
+        let vs_bytes = shaders::load("./src/shader/heightmap.vert")?;
+        let fs_bytes = shaders::load_with_defines(
+            "./src/shader/heightmap.frag",
+            &point_light::shader_defines(),
+        )?;
         let vs_module = device.create_shader_module(&vs_bytes);
         let fs_module = device.create_shader_module(&fs_bytes);
 
@@ -471,10 +548,14 @@ impl HeightmapGpu {
                 module: &vs_module,
                 entry_point: "main",
             },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module,
-                entry_point: "main",
-            }),
+            fragment_stage: if depth == DepthMode::PrePass {
+                None
+            } else {
+                Some(wgpu::ProgrammableStageDescriptor {
+                    module: &fs_module,
+                    entry_point: "main",
+                })
+            },
             rasterization_state: Some(wgpu::RasterizationStateDescriptor {
                 front_face: wgpu::FrontFace::Ccw,
                 cull_mode: wgpu::CullMode::Back,
@@ -483,7 +564,7 @@ impl HeightmapGpu {
                 depth_bias_clamp: 0.0,
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[
+            color_states: depth.color_states(&[
                 wgpu::ColorStateDescriptor {
                     format,
                     color_blend: wgpu::BlendDescriptor::REPLACE,
@@ -497,38 +578,47 @@ impl HeightmapGpu {
                     write_mask: wgpu::ColorWrite::ALL,
                 },
                 wgpu::ColorStateDescriptor {
-                    format: wgpu::TextureFormat::Rg16Float,
+                    format: wgpu::TextureFormat::Rgba16Float,
                     color_blend: wgpu::BlendDescriptor::REPLACE,
                     alpha_blend: wgpu::BlendDescriptor::REPLACE,
                     write_mask: wgpu::ColorWrite::ALL,
                 },
-            ],
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
+                wgpu::ColorStateDescriptor {
+                    format: taa::VELOCITY_FORMAT,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
+                },
+            ]),
+            depth_stencil_state: Some(depth.depth_stencil_state()),
             index_format: wgpu::IndexFormat::Uint32,
-            vertex_buffers: &[wgpu::VertexBufferDescriptor {
-                stride: std::mem::size_of::<heightmap_helper::Vertex>() as wgpu::BufferAddress,
-                step_mode: wgpu::InputStepMode::Vertex,
-                attributes: &[
-                    wgpu::VertexAttributeDescriptor {
-                        format: wgpu::VertexFormat::Float2,
-                        offset: 0,
-                        shader_location: 0,
-                    },
-                    wgpu::VertexAttributeDescriptor {
+            vertex_buffers: &[
+                wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<heightmap_helper::Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float,
+                            offset: 4 * 2,
+                            shader_location: 1,
+                        },
+                    ],
+                },
+                wgpu::VertexBufferDescriptor {
+                    stride: std::mem::size_of::<f32>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Instance,
+                    attributes: &[wgpu::VertexAttributeDescriptor {
                         format: wgpu::VertexFormat::Float,
-                        offset: 4 * 2,
-                        shader_location: 1,
-                    },
-                ],
-            }],
+                        offset: 0,
+                        shader_location: 2,
+                    }],
+                },
+            ],
             sample_count: 1,
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
@@ -541,6 +631,7 @@ impl HeightmapGpu {
         mip: u32,
         device: &Device,
         encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
         texture: &Texture,
         texels: &[f32],
         texels_width: u32,
@@ -570,17 +661,9 @@ impl HeightmapGpu {
             }
         }
 
-        let temp_buf = device
-            .create_buffer_mapped(texels2.len(), wgpu::BufferUsage::COPY_SRC)
-            .fill_from_slice(&texels2);
-
-        encoder.copy_buffer_to_texture(
-            wgpu::BufferCopyView {
-                buffer: &temp_buf,
-                offset: 0,
-                row_pitch: 4 * width,
-                image_height: height,
-            },
+        belt.write_texture(
+            device,
+            encoder,
             wgpu::TextureCopyView {
                 texture,
                 mip_level: mip,
@@ -592,49 +675,65 @@ impl HeightmapGpu {
                 },
             },
             texture_extent,
+            &texels2,
         );
     }
 
-    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup, depth: DepthMode) {
         log::trace!("HeightmapGpu render");
-        rpass.set_pipeline(&self.pipeline);
+        rpass.set_pipeline(&self.pipelines[depth.pipeline_index()]);
         rpass.set_bind_group(0, main_bind_group, &[]);
         rpass.set_bind_group(1, &self.bind_group, &[]);
         rpass.set_index_buffer(&self.index_buf, 0);
-        rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0)]);
-        rpass.draw_indexed(0..(self.index_count) as u32, 0, 0..1);
+        rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0), (&self.level_buf, 0)]);
+        for level in 0..self.level_count as u32 {
+            let indices = if level == 0 {
+                self.clipmap_full.clone()
+            } else {
+                //Same snapping as heightmap.vert, the finer level sits on a grid twice as fine
+                let spacing = 2.0_f32.powi(level as i32);
+                let shift = |cam: f32| {
+                    let finer = (cam / spacing).floor() * spacing;
+                    let own = (cam / (2.0 * spacing)).floor() * 2.0 * spacing;
+                    if finer > own {
+                        1
+                    } else {
+                        0
+                    }
+                };
+                let (dx, dy) = (shift(self.cam_pos[0]), shift(self.cam_pos[1]));
+                self.clipmap_rings[dx + 2 * dy].clone()
+            };
+            rpass.draw_indexed(indices, 0, level..level + 1);
+        }
     }
 
     pub fn update_uniform(
         &mut self,
         device: &Device,
         encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
         camera_x: f32,
         camera_y: f32,
     ) {
         log::trace!("HeightmapGpu update_uniform");
         //Map size
+        self.cam_pos = [
+            camera_x.max(0.0).min(self.phy.width as u32 as f32),
+            camera_y.max(0.0).min(self.phy.height as u32 as f32),
+        ];
         let map_size_cam_pos = [
             self.phy.width as u32 as f32,
             self.phy.height as u32 as f32,
             self.ring_size as f32,
-            (camera_x.max(0.0).min(self.phy.width as u32 as f32) / 1.0),
-            (camera_y.max(0.0).min(self.phy.height as u32 as f32) / 1.0),
+            self.cam_pos[0],
+            self.cam_pos[1],
         ];
 
-        let uniform_buf = device
-            .create_buffer_mapped(
-                5,
-                wgpu::BufferUsage::UNIFORM
-                    | wgpu::BufferUsage::COPY_DST
-                    | wgpu::BufferUsage::COPY_SRC,
-            )
-            .fill_from_slice(&map_size_cam_pos);
-
-        encoder.copy_buffer_to_buffer(&uniform_buf, 0, &self.uniform_buf, 0, 20);
+        belt.write_buffer(device, encoder, &self.uniform_buf, 0, &map_size_cam_pos);
     }
 
-    pub fn step(&mut self, device: &Device, encoder: &mut CommandEncoder) {
+    pub fn step(&mut self, device: &Device, encoder: &mut CommandEncoder, belt: &mut StagingBelt) {
         let mut update_left = UPDATE_PER_STEP;
         if self.mip4_to_update {
             self.mip4_to_update = false;
@@ -644,6 +743,7 @@ impl HeightmapGpu {
                     mip,
                     device,
                     encoder,
+                    belt,
                     &self.texture,
                     &self.phy.texels,
                     self.phy.width as u32,
@@ -695,6 +795,7 @@ impl HeightmapGpu {
                         2,
                         device,
                         encoder,
+                        belt,
                         &self.texture,
                         &self.phy.texels,
                         self.phy.width as u32,
@@ -747,6 +848,7 @@ impl HeightmapGpu {
                         1,
                         device,
                         encoder,
+                        belt,
                         &self.texture,
                         &self.phy.texels,
                         self.phy.width as u32,
@@ -800,6 +902,7 @@ impl HeightmapGpu {
                         0,
                         device,
                         encoder,
+                        belt,
                         &self.texture,
                         &self.phy.texels,
                         self.phy.width as u32,
@@ -836,17 +939,9 @@ impl HeightmapGpu {
             }
         }
 
-        let temp_buf = device
-            .create_buffer_mapped(lod.len(), wgpu::BufferUsage::COPY_SRC)
-            .fill_from_slice(&lod);
-
-        encoder.copy_buffer_to_texture(
-            wgpu::BufferCopyView {
-                buffer: &temp_buf,
-                offset: 0,
-                row_pitch: 4 * width,
-                image_height: height,
-            },
+        belt.write_texture(
+            device,
+            encoder,
             wgpu::TextureCopyView {
                 texture: &self.texture_lod,
                 mip_level: 0,
@@ -858,6 +953,7 @@ impl HeightmapGpu {
                 },
             },
             texture_extent,
+            &lod,
         );
     }
 
@@ -894,13 +990,13 @@ impl super::trait_gpu::TraitGpu for HeightmapGpu {
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
     ) {
-        match Self::create_pipeline(
+        match Self::create_pipelines(
             device,
             &self.bind_group_layout,
             main_bind_group_layout,
             format,
         ) {
-            Ok(pipeline) => self.pipeline = pipeline,
+            Ok(pipelines) => self.pipelines = pipelines,
             Err(x) => log::error!("{}", x),
         };
     }
@@ -0,0 +1,241 @@
+use super::gpu_memory::Allocation;
+use super::mipmap;
+use super::shaders;
+use wgpu::{BindGroupLayout, CommandEncoder, Device, TextureView};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+///Hierarchical depth of the depth pre-pass: each texel holds the farthest depth of the texels it
+///covers in the previous level. Level 0 is half the screen, reduced from the depth buffer.
+///Something whose nearest depth is behind the farthest depth over its screen rect is hidden.
+pub struct HiZ {
+    bind_group_layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pipeline: wgpu::RenderPipeline,
+    pyramid: Pyramid,
+}
+
+///Screen sized part, recreated on resize
+struct Pyramid {
+    view: TextureView,
+    ///Per level: its view, as target, and the bind group of its source
+    levels: Vec<(TextureView, wgpu::BindGroup)>,
+    _texture: wgpu::Texture,
+    _allocation: Allocation,
+}
+
+impl HiZ {
+    ///depth is the Depth32Float of the pre-pass, of the size of the screen
+    pub fn new(device: &Device, depth: &TextureView, width: u32, height: u32) -> Self {
+        log::trace!("HiZ new");
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &Self::layout_bindings(0, wgpu::ShaderStage::FRAGMENT),
+        });
+        //Read with texelFetch only
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+        let pipeline = Self::create_pipeline(device, &bind_group_layout).unwrap();
+        let pyramid = Pyramid::new(device, &bind_group_layout, &sampler, depth, width, height);
+        HiZ {
+            bind_group_layout,
+            sampler,
+            pipeline,
+            pyramid,
+        }
+    }
+
+    ///The depth view changes with the size of the screen
+    pub fn resize(&mut self, device: &Device, depth: &TextureView, width: u32, height: u32) {
+        self.pyramid = Pyramid::new(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            depth,
+            width,
+            height,
+        );
+    }
+
+    ///Every level, as a texture2D and its sampler, from binding first
+    pub fn layout_bindings(
+        first: u32,
+        visibility: wgpu::ShaderStage,
+    ) -> [wgpu::BindGroupLayoutBinding; 2] {
+        [
+            wgpu::BindGroupLayoutBinding {
+                binding: first,
+                visibility,
+                ty: wgpu::BindingType::SampledTexture {
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2,
+                },
+            },
+            wgpu::BindGroupLayoutBinding {
+                binding: first + 1,
+                visibility,
+                ty: wgpu::BindingType::Sampler,
+            },
+        ]
+    }
+
+    pub fn bindings(&self, first: u32) -> [wgpu::Binding; 2] {
+        [
+            wgpu::Binding {
+                binding: first,
+                resource: wgpu::BindingResource::TextureView(&self.pyramid.view),
+            },
+            wgpu::Binding {
+                binding: first + 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    ///After the depth pre-pass
+    pub fn build(&self, encoder: &mut CommandEncoder) {
+        log::trace!("HiZ build");
+        for (target, bind_group) in &self.pyramid.levels {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: target,
+                    resolve_target: None,
+                    load_op: wgpu::LoadOp::Clear,
+                    store_op: wgpu::StoreOp::Store,
+                    clear_color: wgpu::Color::BLACK,
+                }],
+                depth_stencil_attachment: None,
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, bind_group, &[]);
+            rpass.draw(0..4, 0..1);
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[bind_group_layout],
+        });
+
+        //Same fullscreen strip as the mip levels
+        let vs_bytes = shaders::load("./src/shader/mipmap.vert")?;
+        let fs_bytes = shaders::load("./src/shader/hi_z.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format: FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+}
+
+impl Pyramid {
+    fn new(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        depth: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        let level_count = mipmap::level_count(width, height);
+        let desc = wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        };
+        let texture = device.create_texture(&desc);
+        let view = |base_mip_level, level_count| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                format: FORMAT,
+                dimension: wgpu::TextureViewDimension::D2,
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level,
+                level_count,
+                base_array_layer: 0,
+                array_layer_count: 1,
+            })
+        };
+
+        let level_views: Vec<TextureView> = (0..level_count).map(|l| view(l, 1)).collect();
+        let mut bind_groups = Vec::with_capacity(level_views.len());
+        for level in 0..level_views.len() {
+            let source = if level == 0 {
+                depth
+            } else {
+                &level_views[level - 1]
+            };
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: bind_group_layout,
+                bindings: &[
+                    wgpu::Binding {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::Binding {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+            });
+            bind_groups.push(bind_group);
+        }
+        let levels = level_views.into_iter().zip(bind_groups).collect();
+
+        Pyramid {
+            view: view(0, level_count),
+            levels,
+            _texture: texture,
+            _allocation: Allocation::texture(&desc),
+        }
+    }
+}
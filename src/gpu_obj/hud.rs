@@ -0,0 +1,322 @@
+use super::gpu_memory::Allocation;
+use super::instance_buffer::InstanceBuffer;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::UniformBuffer;
+use crate::utils::ImageRGBA8;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass, TextureFormat};
+
+///Floats per quad: min and max in pixels, min and max texture coordinates, rgba tint
+pub const INSTANCE_LEN: usize = 12;
+///White rows added under the icons of the atlas, sampled by Sprite::Solid
+const SOLID_ROWS: u32 = 4;
+
+///Region of the hud atlas
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sprite {
+    ///Plain tint color
+    Solid,
+    ///Cell of cursor_icons.png, a 4 by 4 atlas
+    Icon(u32, u32),
+}
+
+///Ortho block of hud.vert
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct HudUniform {
+    ///Pixels with y down to clip space
+    ortho: [[f32; 4]; 4],
+}
+
+///Screen space quads of the in-game ui, tinted regions of one atlas texture
+pub struct HudGpu {
+    instance_buf: InstanceBuffer,
+    instance_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    uniform: UniformBuffer<HudUniform>,
+    ///Height of the icons in the atlas, the solid rows are under them
+    icons_h: u32,
+    _atlas: wgpu::Texture,
+    _atlas_mem: Allocation,
+}
+
+impl HudGpu {
+    pub fn new(
+        init_encoder: &mut CommandEncoder,
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        icons: ImageRGBA8,
+    ) -> Self {
+        log::trace!("HudGpu new");
+
+        let mut texels = icons.data;
+        texels.extend(std::iter::repeat(255).take((4 * icons.w * SOLID_ROWS) as usize));
+        let texture_extent = wgpu::Extent3d {
+            width: icons.w,
+            height: icons.h + SOLID_ROWS,
+            depth: 1,
+        };
+        let texture_desc = wgpu::TextureDescriptor {
+            size: texture_extent,
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        };
+        let texture = device.create_texture(&texture_desc);
+
+        let temp_buf = device
+            .create_buffer_mapped(texels.len(), wgpu::BufferUsage::COPY_SRC)
+            .fill_from_slice(&texels);
+        init_encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &temp_buf,
+                offset: 0,
+                row_pitch: 4 * icons.w,
+                image_height: icons.h + SOLID_ROWS,
+            },
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            },
+            texture_extent,
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                UniformBuffer::<HudUniform>::layout_binding(0, wgpu::ShaderStage::VERTEX),
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+
+        let uniform = UniformBuffer::new(device, &HudUniform::zeroed());
+        let atlas_view = texture.create_default_view();
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            bindings: &[
+                uniform.binding(0),
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, &bind_group_layout, format)
+                .unwrap();
+
+        HudGpu {
+            instance_buf: InstanceBuffer::new(device),
+            instance_count: 0,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform,
+            icons_h: icons.h,
+            _atlas: texture,
+            _atlas_mem: Allocation::texture(&texture_desc),
+        }
+    }
+
+    ///Min and max texture coordinates of a sprite
+    pub fn uv(&self, sprite: Sprite) -> ([f32; 2], [f32; 2]) {
+        let atlas_h = (self.icons_h + SOLID_ROWS) as f32;
+        match sprite {
+            Sprite::Solid => {
+                //Middle of the white rows, away from the linear filtering of the icons
+                let v = (self.icons_h as f32 + SOLID_ROWS as f32 / 2.0) / atlas_h;
+                ([0.5, v], [0.5, v])
+            }
+            Sprite::Icon(x, y) => {
+                let cell_h = 0.25 * self.icons_h as f32 / atlas_h;
+                let min = [x as f32 * 0.25, y as f32 * cell_h];
+                (min, [min[0] + 0.25, min[1] + cell_h])
+            }
+        }
+    }
+
+    ///Quads laid out as INSTANCE_LEN floats, for a window of width by height pixels
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        width: u32,
+        height: u32,
+        quads: &[f32],
+    ) {
+        log::trace!("HudGpu update");
+        self.instance_count = (quads.len() / INSTANCE_LEN) as u32;
+        if self.instance_count == 0 {
+            return;
+        }
+        let (w, h) = (width as f32, height as f32);
+        let data = HudUniform {
+            ortho: [
+                [2.0 / w, 0.0, 0.0, 0.0],
+                [0.0, 2.0 / h, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [-1.0, -1.0, 0.0, 1.0],
+            ],
+        };
+        self.uniform.update(device, encoder, belt, &data);
+        self.instance_buf.update(quads, device, encoder, belt);
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[main_bind_group_layout, bind_group_layout],
+        });
+        let vs_bytes = shaders::load("./src/shader/hud.vert")?;
+        let fs_bytes = shaders::load("./src/shader/hud.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: (4 * INSTANCE_LEN) as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &[
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float2,
+                        offset: 0,
+                        shader_location: 0,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float2,
+                        offset: 4 * 2,
+                        shader_location: 1,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float2,
+                        offset: 4 * 4,
+                        shader_location: 2,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float2,
+                        offset: 4 * 6,
+                        shader_location: 3,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float4,
+                        offset: 4 * 8,
+                        shader_location: 4,
+                    },
+                ],
+            }],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("HudGpu render");
+        if self.instance_count > 0 {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_vertex_buffers(0, &[(self.instance_buf.buffer(), 0)]);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.set_bind_group(1, &self.bind_group, &[]);
+            rpass.draw(0..4, 0..self.instance_count);
+        }
+    }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
+}
+
+impl super::trait_gpu::TraitGpu for HudGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            main_bind_group_layout,
+            &self.bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
@@ -76,8 +76,8 @@ impl Renderer {
         clear_color: Option<Color>,
     ) -> Renderer {
         let (vs_code, fs_code) = get_program_link();
-        let vs_raw = super::glsl_compiler::load(vs_code).unwrap();
-        let fs_raw = super::glsl_compiler::load(fs_code).unwrap();
+        let vs_raw = super::shaders::load(vs_code).unwrap();
+        let fs_raw = super::shaders::load(fs_code).unwrap();
         Self::new_impl(imgui, device, queue, format, clear_color, vs_raw, fs_raw)
     }
 
@@ -281,6 +281,7 @@ impl Renderer {
                 &draw_list,
                 draw_data.display_pos,
                 draw_data.framebuffer_scale,
+                [fb_width, fb_height],
             )?;
         }
 
@@ -295,6 +296,7 @@ impl Renderer {
         draw_list: &DrawList,
         clip_off: [f32; 2],
         clip_scale: [f32; 2],
+        fb_size: [f32; 2],
     ) -> RendererResult<()> {
         let mut start = 0;
 
@@ -308,12 +310,19 @@ impl Renderer {
         for cmd in draw_list.commands() {
             match cmd {
                 Elements { count, cmd_params } => {
+                    // Clip rect in framebuffer pixels, clamped to the framebuffer: wgpu rejects
+                    // scissors reaching outside of the attachment.
                     let clip_rect = [
-                        (cmd_params.clip_rect[0] - clip_off[0]) * clip_scale[0],
-                        (cmd_params.clip_rect[1] - clip_off[1]) * clip_scale[1],
-                        (cmd_params.clip_rect[2] - clip_off[0]) * clip_scale[0],
-                        (cmd_params.clip_rect[3] - clip_off[1]) * clip_scale[1],
+                        ((cmd_params.clip_rect[0] - clip_off[0]) * clip_scale[0]).max(0.0),
+                        ((cmd_params.clip_rect[1] - clip_off[1]) * clip_scale[1]).max(0.0),
+                        ((cmd_params.clip_rect[2] - clip_off[0]) * clip_scale[0]).min(fb_size[0]),
+                        ((cmd_params.clip_rect[3] - clip_off[1]) * clip_scale[1]).min(fb_size[1]),
                     ];
+                    let end = start + count as u32;
+                    if clip_rect[2] <= clip_rect[0] || clip_rect[3] <= clip_rect[1] {
+                        start = end;
+                        continue;
+                    }
 
                     // Set the current texture bind group on the renderpass.
                     let texture_id = cmd_params.texture_id.into();
@@ -325,15 +334,14 @@ impl Renderer {
 
                     // Set scissors on the renderpass.
                     let scissors = (
-                        clip_rect[0].max(0.0).floor() as u32,
-                        clip_rect[1].max(0.0).floor() as u32,
-                        (clip_rect[2] - clip_rect[0]).abs().ceil() as u32,
-                        (clip_rect[3] - clip_rect[1]).abs().ceil() as u32,
+                        clip_rect[0].floor() as u32,
+                        clip_rect[1].floor() as u32,
+                        (clip_rect[2] - clip_rect[0].floor()).ceil() as u32,
+                        (clip_rect[3] - clip_rect[1].floor()).ceil() as u32,
                     );
                     rpass.set_scissor_rect(scissors.0, scissors.1, scissors.2, scissors.3);
 
                     // Draw the current batch of vertices with the renderpass.
-                    let end = start + count as u32;
                     rpass.draw_indexed(start..end, 0, 0..1);
                     start = end;
                 }
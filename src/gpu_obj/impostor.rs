@@ -0,0 +1,391 @@
+use super::instance_buffer::InstanceBuffer;
+use super::mipmap::MipGenerator;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::taa;
+use super::texture_array::TextureArray;
+use crate::botdef::BotDef;
+use crate::model;
+use crate::utils::{Id, ImageRGBA8};
+use fnv::FnvHashMap;
+use na::{Matrix4, Point3, Vector3};
+use wgpu::{BindGroup, BindGroupLayout, Device, RenderPass, TextureFormat};
+
+///Directions around the z axis a unit is baked from, in the cells of its layer
+pub const ANGLES: u32 = 16;
+///Cells per row of a layer
+const GRID: u32 = 4;
+const CELL_SIZE: u32 = 64;
+const ATLAS_SIZE: u32 = GRID * CELL_SIZE;
+///Unit types that can be baked, the others stay models at any distance
+const ATLAS_LAYERS: u32 = 64;
+///Elevation of the bake camera in radians, close to the camera of a match
+const ELEVATION: f32 = 1.0;
+///Floats of an instance: position and yaw, last position and radius, layer and team
+pub const INSTANCE_LEN: usize = 10;
+
+///Part of a unit to bake
+pub struct BakeMesh<'a> {
+    ///From the part to the unit
+    pub trans: Matrix4<f32>,
+    pub vertices: &'a [model::Vertex],
+    pub indices: &'a [u32],
+}
+
+///Layer of a unit type in the atlas
+#[derive(Clone, Copy, Debug)]
+pub struct Baked {
+    pub layer: u32,
+    ///Half the side of the billboard, every part fits in it
+    pub radius: f32,
+}
+
+///Far units drawn as billboards. Each unit type is baked once on the cpu, from ANGLES directions
+///around it, into a layer of the atlas: unit space normal in rgb and coverage in alpha. A
+///billboard picks the cell of the direction it is seen from and is lit as the models are, so
+///the sun stays right whatever the unit faces.
+///Drawn in the main pass, testing and writing depth as the arrows do.
+pub struct ImpostorGpu {
+    atlas: TextureArray,
+    ///None when the unit type can't be baked: nothing to draw or the atlas is full
+    baked: FnvHashMap<Id<BotDef>, Option<Baked>>,
+    ///Written by the next upload
+    pending: Vec<(u32, ImageRGBA8)>,
+    next_layer: u32,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    instance_buf: InstanceBuffer,
+    instance_count: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ImpostorGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        log::trace!("ImpostorGpu new");
+        let atlas = TextureArray::new(device, TextureFormat::Rgba8Unorm, ATLAS_SIZE, ATLAS_LAYERS);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                TextureArray::layer_binding(0),
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(atlas.view()),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, &bind_group_layout, format)
+                .unwrap();
+        ImpostorGpu {
+            atlas,
+            baked: FnvHashMap::default(),
+            pending: Vec::new(),
+            next_layer: 0,
+            bind_group_layout,
+            bind_group,
+            instance_buf: InstanceBuffer::new(device),
+            instance_count: 0,
+            pipeline,
+        }
+    }
+
+    ///Unit types baked so far, None for the ones that can't be
+    pub fn baked(&self) -> &FnvHashMap<Id<BotDef>, Option<Baked>> {
+        &self.baked
+    }
+
+    ///Once per unit type, when all of its meshes are loaded
+    pub fn bake(&mut self, botdef: Id<BotDef>, meshes: &[BakeMesh]) {
+        let baked = if self.next_layer >= ATLAS_LAYERS {
+            log::warn!("ImpostorGpu: no layer left for {}", botdef);
+            None
+        } else {
+            rasterize(meshes).map(|(img, radius)| {
+                let layer = self.next_layer;
+                self.next_layer += 1;
+                self.pending.push((layer, img));
+                Baked { layer, radius }
+            })
+        };
+        log::debug!("ImpostorGpu bake {} {:?}", botdef, baked);
+        self.baked.insert(botdef, baked);
+    }
+
+    ///Writes the layers baked since the last upload
+    pub fn upload(
+        &mut self,
+        device: &Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        mips: &mut MipGenerator,
+    ) {
+        for (layer, img) in self.pending.drain(..) {
+            match self.atlas.write_image(device, encoder, belt, layer, &img) {
+                Ok(()) => self.atlas.generate_mips(device, encoder, mips, layer),
+                Err(e) => log::warn!("ImpostorGpu layer {}: {}", layer, e),
+            }
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[main_bind_group_layout, bind_group_layout],
+        });
+        let vs_bytes = shaders::load("./src/shader/impostor.vert")?;
+        let fs_bytes = shaders::load("./src/shader/impostor.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let replace = |format| wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[
+                replace(format),
+                replace(TextureFormat::Rgba32Float),
+                replace(TextureFormat::Rgba16Float),
+                replace(taa::VELOCITY_FORMAT),
+            ],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: (4 * INSTANCE_LEN) as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &[
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float4,
+                        offset: 0,
+                        shader_location: 0,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float4,
+                        offset: 4 * 4,
+                        shader_location: 1,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float2,
+                        offset: 4 * 8,
+                        shader_location: 2,
+                    },
+                ],
+            }],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("ImpostorGpu render");
+        if self.instance_count > 0 {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_vertex_buffers(0, &[(self.instance_buf.buffer(), 0)]);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.set_bind_group(1, &self.bind_group, &[]);
+            rpass.draw(0..4, 0..self.instance_count);
+        }
+    }
+
+    pub fn update_instance(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        log::trace!("ImpostorGpu update_instance");
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
+        self.instance_count = (instance_attr.len() / INSTANCE_LEN) as u32;
+    }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
+}
+
+impl super::trait_gpu::TraitGpu for ImpostorGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            main_bind_group_layout,
+            &self.bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
+
+///Orthographic views of meshes from each angle, in the cells of a layer. The image is seen with
+///the same basis as the billboards of impostor.vert: right is forward cross z, up is right cross
+///forward. None if there is nothing to draw.
+fn rasterize(meshes: &[BakeMesh]) -> Option<(ImageRGBA8, f32)> {
+    let triangles: Vec<[(Vector3<f32>, Vector3<f32>); 3]> = meshes
+        .iter()
+        .flat_map(|mesh| {
+            mesh.indices.chunks_exact(3).map(move |tri| {
+                let corner = |i: u32| {
+                    let vertex = &mesh.vertices[i as usize];
+                    let [x, y, z, _] = vertex.position();
+                    let [nx, ny, nz] = vertex.normal();
+                    (
+                        mesh.trans.transform_point(&Point3::new(x, y, z)).coords,
+                        mesh.trans.transform_vector(&Vector3::new(nx, ny, nz)),
+                    )
+                };
+                [corner(tri[0]), corner(tri[1]), corner(tri[2])]
+            })
+        })
+        .collect();
+    let radius = triangles
+        .iter()
+        .flat_map(|tri| tri.iter().map(|(pos, _)| pos.norm()))
+        .fold(0.0_f32, f32::max);
+    if radius <= 0.0 {
+        return None;
+    }
+
+    let mut data = vec![0_u8; (ATLAS_SIZE * ATLAS_SIZE * 4) as usize];
+    let cell = CELL_SIZE as f32;
+    for angle in 0..ANGLES {
+        let yaw = angle as f32 * std::f32::consts::PI * 2.0 / ANGLES as f32;
+        let to_camera = Vector3::new(
+            yaw.cos() * ELEVATION.cos(),
+            yaw.sin() * ELEVATION.cos(),
+            ELEVATION.sin(),
+        );
+        let forward = -to_camera;
+        let right = forward.cross(&Vector3::z()).normalize();
+        let up = right.cross(&forward);
+        //Pixel coordinates in the cell, and nearness to the camera
+        let project = |pos: &Vector3<f32>| {
+            (
+                (pos.dot(&right) / radius * 0.5 + 0.5) * cell,
+                (0.5 - pos.dot(&up) / radius * 0.5) * cell,
+                pos.dot(&to_camera),
+            )
+        };
+
+        let mut depth = vec![std::f32::MIN; (CELL_SIZE * CELL_SIZE) as usize];
+        let cell_x = (angle % GRID) * CELL_SIZE;
+        let cell_y = (angle / GRID) * CELL_SIZE;
+        for tri in triangles.iter() {
+            let p = [project(&tri[0].0), project(&tri[1].0), project(&tri[2].0)];
+            let area =
+                (p[1].0 - p[0].0) * (p[2].1 - p[0].1) - (p[2].0 - p[0].0) * (p[1].1 - p[0].1);
+            if area.abs() < std::f32::EPSILON {
+                continue;
+            }
+            let min_x = p.iter().map(|p| p.0).fold(cell, f32::min).max(0.0) as u32;
+            let max_x = p.iter().map(|p| p.0).fold(0.0, f32::max).min(cell - 1.0) as u32;
+            let min_y = p.iter().map(|p| p.1).fold(cell, f32::min).max(0.0) as u32;
+            let max_y = p.iter().map(|p| p.1).fold(0.0, f32::max).min(cell - 1.0) as u32;
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                    let edge = |a: (f32, f32, f32), b: (f32, f32, f32)| {
+                        ((b.0 - a.0) * (py - a.1) - (px - a.0) * (b.1 - a.1)) / area
+                    };
+                    //Barycentric weights, both windings are drawn
+                    let w0 = edge(p[1], p[2]);
+                    let w1 = edge(p[2], p[0]);
+                    let w2 = edge(p[0], p[1]);
+                    if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                        continue;
+                    }
+                    let z = w0 * p[0].2 + w1 * p[1].2 + w2 * p[2].2;
+                    let i = (y * CELL_SIZE + x) as usize;
+                    if z <= depth[i] {
+                        continue;
+                    }
+                    depth[i] = z;
+                    let normal = (tri[0].1 * w0 + tri[1].1 * w1 + tri[2].1 * w2)
+                        .try_normalize(std::f32::EPSILON)
+                        .unwrap_or(to_camera);
+                    let texel = (((cell_y + y) * ATLAS_SIZE + cell_x + x) * 4) as usize;
+                    for (c, n) in normal.iter().enumerate() {
+                        data[texel + c] = ((n * 0.5 + 0.5) * 255.0).round() as u8;
+                    }
+                    data[texel + 3] = 255;
+                }
+            }
+        }
+    }
+    Some((
+        ImageRGBA8 {
+            w: ATLAS_SIZE,
+            h: ATLAS_SIZE,
+            data,
+        },
+        radius,
+    ))
+}
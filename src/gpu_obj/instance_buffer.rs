@@ -0,0 +1,94 @@
+use super::gpu_memory::{Allocation, Kind};
+use super::staging_belt::StagingBelt;
+
+///In f32
+const MIN_CAPACITY: usize = 1024;
+///Updates using less than a quarter of the capacity before it is halved
+const SHRINK_AFTER_UPDATES: u32 = 300;
+
+///Vertex buffer of per instance attributes, reallocated only when the instances don't fit anymore.
+///Capacity doubles when growing, and is halved after a sustained low usage.
+pub struct InstanceBuffer {
+    buf: wgpu::Buffer,
+    ///Added to VERTEX | COPY_DST
+    usage: wgpu::BufferUsage,
+    ///In f32
+    capacity: usize,
+    low_usage_updates: u32,
+    _allocation: Allocation,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self::with_usage(device, wgpu::BufferUsage::empty())
+    }
+
+    ///Ex: STORAGE for a buffer also read or written by a compute pass
+    pub fn with_usage(device: &wgpu::Device, usage: wgpu::BufferUsage) -> Self {
+        let (buf, allocation) = Self::allocate(device, usage, MIN_CAPACITY);
+        InstanceBuffer {
+            buf,
+            usage,
+            capacity: MIN_CAPACITY,
+            low_usage_updates: 0,
+            _allocation: allocation,
+        }
+    }
+
+    fn allocate(
+        device: &wgpu::Device,
+        usage: wgpu::BufferUsage,
+        capacity: usize,
+    ) -> (wgpu::Buffer, Allocation) {
+        let size = capacity as u64 * 4;
+        let buf = device.create_buffer(&wgpu::BufferDescriptor {
+            size,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST | usage,
+        });
+        (buf, Allocation::new(Kind::Instance, size))
+    }
+
+    fn reallocate(&mut self, device: &wgpu::Device, capacity: usize) {
+        log::debug!(
+            "InstanceBuffer reallocate {} -> {} floats",
+            self.capacity,
+            capacity
+        );
+        let (buf, allocation) = Self::allocate(device, self.usage, capacity);
+        self.buf = buf;
+        self._allocation = allocation;
+        self.capacity = capacity;
+        self.low_usage_updates = 0;
+    }
+
+    pub fn update(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        self.reserve(device, instance_attr.len());
+        belt.write_buffer(device, encoder, &self.buf, 0, instance_attr);
+    }
+
+    ///Makes room for len floats without writing them, for a buffer filled by the gpu.
+    ///The content is lost when reallocated.
+    pub fn reserve(&mut self, device: &wgpu::Device, len: usize) {
+        if len > self.capacity {
+            let capacity = len.max(self.capacity * 2);
+            self.reallocate(device, capacity);
+        } else if len < self.capacity / 4 && self.capacity > MIN_CAPACITY {
+            self.low_usage_updates += 1;
+            if self.low_usage_updates > SHRINK_AFTER_UPDATES {
+                self.reallocate(device, (self.capacity / 2).max(MIN_CAPACITY));
+            }
+        } else {
+            self.low_usage_updates = 0;
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buf
+    }
+}
@@ -1,17 +1,52 @@
 pub mod arrow_gpu;
+pub mod beam;
 pub mod blit_texture;
+pub mod color_grading;
+pub mod depth_mode;
+pub mod dof;
+pub mod draw_list;
+pub mod economy;
+pub mod environment;
 pub mod explosion;
-pub mod glsl_compiler;
 pub mod gpu;
+pub mod gpu_memory;
+pub mod ground_shadow;
 pub mod health_bar;
+pub mod heatmap;
 pub mod heightmap_gpu;
 mod heightmap_helper;
+pub mod hi_z;
+pub mod hud;
 pub mod imgui_wgpu;
+pub mod impostor;
+pub mod instance_buffer;
 pub mod line;
+pub mod mipmap;
+pub mod model_batch;
 pub mod model_gpu;
+pub mod nanolathe;
+pub mod oit;
+pub mod pipeline_cache;
+pub mod point_light;
 pub mod post_fx;
 pub mod post_fxaa;
+pub mod range_ring;
+pub mod render_graph;
+pub mod shader_reflect;
+pub mod shaders;
+pub mod shadow_map;
+pub mod shield;
+pub mod ssr;
+pub mod staging_belt;
+pub mod taa;
+pub mod texture_array;
 pub mod texture_view_bicopy;
+pub mod toon;
+pub mod trail;
 pub mod trait_gpu;
+pub mod uniform_buffer;
 pub mod unit_icon;
+pub mod upscale;
 pub mod water;
+pub mod waypoint;
+pub mod weather;
@@ -0,0 +1,579 @@
+use super::depth_mode::DepthMode;
+use super::gpu_memory::{Allocation, Kind};
+use super::hi_z::HiZ;
+use super::instance_buffer::InstanceBuffer;
+use super::mipmap::MipGenerator;
+use super::model_gpu::{ModelGpu, INSTANCE_LEN};
+use super::pipeline_cache::PipelineCache;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::texture_array::TextureArray;
+use super::uniform_buffer::UniformBuffer;
+use crate::model;
+use crate::texture_file::TextureFile;
+use crate::utils::ImageRGBA8;
+use bytemuck::{Pod, Zeroable};
+use na::Matrix4;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use wgpu::{BindGroup, BindGroupLayout, Device, RenderPass, TextureFormat};
+
+///Index of a mesh in a ModelBatch
+pub type MeshId = usize;
+
+///u32 of a DrawIndexedIndirect: index_count, instance_count, first_index, base_vertex,
+///first_instance
+const INDIRECT_LEN: usize = 5;
+///Invocations per workgroup of model_cull.comp
+const CULL_GROUP_SIZE: u32 = 64;
+
+///Width and height of the unit textures
+const TEXTURE_SIZE: u32 = 256;
+///Layer 0 is the default: white albedo, flat normal
+const TEXTURE_LAYERS: u32 = 64;
+const DEFAULT_ALBEDO: [u8; 4] = [255, 255, 255, 255];
+const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+///Optional textures of a mesh, next to its obj: foo.obj has foo.png and foo_normal.png.
+///A .ktx2 or .dds of the same name (see texture_file::pack_textures) is preferred to the png.
+#[derive(Default)]
+pub struct MeshTextures {
+    pub albedo: Option<ImageRGBA8>,
+    pub normal: Option<ImageRGBA8>,
+}
+
+impl MeshTextures {
+    pub fn open(mesh_path: &Path) -> Self {
+        let open = |suffix: &str| {
+            let stem = mesh_path.file_stem()?.to_str()?;
+            for ext in &["ktx2", "dds"] {
+                let path = mesh_path.with_file_name(format!("{}{}.{}", stem, suffix, ext));
+                if path.exists() {
+                    match TextureFile::open(&path).and_then(|file| file.to_image()) {
+                        Ok(img) => return Some(img),
+                        Err(e) => log::warn!("{}", e),
+                    }
+                }
+            }
+            let path = mesh_path.with_file_name(format!("{}{}.png", stem, suffix));
+            if path.exists() {
+                Some(ImageRGBA8::open(path.to_str()?))
+            } else {
+                None
+            }
+        };
+        MeshTextures {
+            albedo: open(""),
+            normal: open("_normal"),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.albedo.is_none() && self.normal.is_none()
+    }
+}
+
+struct Mesh {
+    first_index: u32,
+    index_count: u32,
+    base_vertex: i32,
+    ///Instances of this frame
+    instance_attr: Vec<f32>,
+    ///In instance_buf, set by upload
+    instances: Range<u32>,
+    ///In the albedo and normal arrays
+    texture_layer: u32,
+    ///Bounding sphere around the origin, for the occlusion culling
+    radius: f32,
+}
+
+///Cull block of model_cull.comp
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct CullUniform {
+    view_proj: [[f32; 4]; 4],
+    instance_count: u32,
+    _pad: [u32; 3],
+}
+
+struct Buffers {
+    vertex_buf: wgpu::Buffer,
+    index_buf: wgpu::Buffer,
+    ///One DrawIndexedIndirect per mesh, in mesh order. STORAGE so a culling pass can write the
+    ///instance counts.
+    indirect_buf: wgpu::Buffer,
+    ///Written by the culling pass, the instance counts start at 0
+    culled_indirect_buf: wgpu::Buffer,
+    ///Radius of each mesh
+    radius_buf: wgpu::Buffer,
+    _vertex_mem: Allocation,
+    _index_mem: Allocation,
+    _indirect_mem: Allocation,
+    _culled_indirect_mem: Allocation,
+    _radius_mem: Allocation,
+}
+
+///Meshes drawn with the ModelGpu pipeline, stored in shared vertex and index buffers.
+///The instances of all meshes go in one buffer: a frame binds the pipeline and the buffers once,
+///then each mesh is a draw_indexed of its ranges, or a draw_indexed_indirect from the shared
+///indirect buffer.
+///Textures are layers of two arrays in the main bind group, an instance gives its layer.
+///With the depth pre-pass, a compute pass can test the instances against its HiZ: the main pass
+///then draws the visible instances only, compacted in culled_instance_buf.
+pub struct ModelBatch {
+    meshes: Vec<Mesh>,
+    vertex_data: Vec<model::Vertex>,
+    index_data: Vec<u32>,
+    ///None when meshes were added since the last upload
+    buffers: Option<Buffers>,
+    instance_buf: InstanceBuffer,
+    ///Instances of every mesh, reused between frames
+    instance_attr: Vec<f32>,
+    ///Commands of every mesh, reused between frames
+    indirect: Vec<u32>,
+    ///Draw from the indirect buffer, false for backends without indirect first_instance
+    pub use_indirect: bool,
+    ///Mesh of each instance, reused between frames
+    instance_mesh: Vec<u32>,
+    instance_mesh_buf: InstanceBuffer,
+    culled_instance_buf: InstanceBuffer,
+    cull_uniform: UniformBuffer<CullUniform>,
+    cull_bind_group_layout: BindGroupLayout,
+    cull_pipeline: wgpu::ComputePipeline,
+    ///Needs the depth pre-pass and the indirect draws
+    pub use_occlusion_culling: bool,
+    albedo: TextureArray,
+    normal: TextureArray,
+    ///Written by the next upload
+    pending_textures: Vec<(u32, MeshTextures)>,
+    next_texture_layer: u32,
+    ///One per DepthMode
+    pipelines: Vec<Rc<wgpu::RenderPipeline>>,
+}
+
+impl ModelBatch {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        pipelines: &mut PipelineCache,
+    ) -> Self {
+        log::trace!("ModelBatch new");
+        let pipelines =
+            ModelGpu::shared_pipelines(device, main_bind_group_layout, format, pipelines).unwrap();
+        let storage = |binding, readonly| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly,
+            },
+        };
+        let [hi_z_texture, hi_z_sampler] = HiZ::layout_bindings(6, wgpu::ShaderStage::COMPUTE);
+        let cull_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    UniformBuffer::<CullUniform>::layout_binding(0, wgpu::ShaderStage::COMPUTE),
+                    storage(1, true),
+                    storage(2, true),
+                    storage(3, true),
+                    storage(4, false),
+                    storage(5, false),
+                    hi_z_texture,
+                    hi_z_sampler,
+                ],
+            });
+        let cull_pipeline = Self::create_cull_pipeline(device, &cull_bind_group_layout).unwrap();
+        ModelBatch {
+            meshes: Vec::new(),
+            vertex_data: Vec::new(),
+            index_data: Vec::new(),
+            buffers: None,
+            instance_buf: InstanceBuffer::with_usage(device, wgpu::BufferUsage::STORAGE),
+            instance_attr: Vec::new(),
+            indirect: Vec::new(),
+            use_indirect: true,
+            instance_mesh: Vec::new(),
+            instance_mesh_buf: InstanceBuffer::with_usage(device, wgpu::BufferUsage::STORAGE),
+            culled_instance_buf: InstanceBuffer::with_usage(device, wgpu::BufferUsage::STORAGE),
+            cull_uniform: UniformBuffer::new(device, &CullUniform::zeroed()),
+            cull_bind_group_layout,
+            cull_pipeline,
+            use_occlusion_culling: false,
+            albedo: TextureArray::new(
+                device,
+                TextureFormat::Rgba8UnormSrgb,
+                TEXTURE_SIZE,
+                TEXTURE_LAYERS,
+            ),
+            normal: TextureArray::new(
+                device,
+                TextureFormat::Rgba8Unorm,
+                TEXTURE_SIZE,
+                TEXTURE_LAYERS,
+            ),
+            pending_textures: vec![(0, MeshTextures::default())],
+            next_texture_layer: 1,
+            pipelines,
+        }
+    }
+
+    ///The shared buffers are rebuilt on the next upload. A replaced model keeps its old mesh in
+    ///them, only the unit editor reloads models.
+    ///A mesh without textures, or added once the arrays are full, uses the default layer.
+    pub fn add_mesh(
+        &mut self,
+        triangle_list: &model::TriangleList,
+        textures: MeshTextures,
+    ) -> MeshId {
+        log::debug!(
+            "ModelBatch add_mesh {} vertices {} indices",
+            triangle_list.vertex_data.len(),
+            triangle_list.index_data.len()
+        );
+        let texture_layer = if textures.is_empty() {
+            0
+        } else if self.next_texture_layer < TEXTURE_LAYERS {
+            self.next_texture_layer += 1;
+            self.pending_textures
+                .push((self.next_texture_layer - 1, textures));
+            self.next_texture_layer - 1
+        } else {
+            log::warn!("ModelBatch: no texture layer left, mesh uses the default textures");
+            0
+        };
+        let radius = triangle_list
+            .vertex_data
+            .iter()
+            .map(|vertex| {
+                let [x, y, z, _] = vertex.position();
+                (x * x + y * y + z * z).sqrt()
+            })
+            .fold(0.0_f32, f32::max);
+        self.meshes.push(Mesh {
+            first_index: self.index_data.len() as u32,
+            index_count: triangle_list.index_data.len() as u32,
+            base_vertex: self.vertex_data.len() as i32,
+            instance_attr: Vec::new(),
+            instances: 0..0,
+            texture_layer,
+            radius,
+        });
+        self.vertex_data
+            .extend_from_slice(&triangle_list.vertex_data);
+        self.index_data.extend_from_slice(&triangle_list.index_data);
+        self.buffers = None;
+        self.meshes.len() - 1
+    }
+
+    ///Replaces the instances of mesh, uploaded by the next upload
+    pub fn set_instances(&mut self, mesh: MeshId, instance_attr: Vec<f32>) {
+        self.meshes[mesh].instance_attr = instance_attr;
+    }
+
+    ///Vertices and indices of mesh, the indices start at the first vertex
+    pub fn mesh_data(&self, mesh: MeshId) -> (&[model::Vertex], &[u32]) {
+        let mesh = &self.meshes[mesh];
+        let first_index = mesh.first_index as usize;
+        (
+            &self.vertex_data[mesh.base_vertex as usize..],
+            &self.index_data[first_index..first_index + mesh.index_count as usize],
+        )
+    }
+
+    ///Layer of the textures of mesh, written in each of its instances
+    pub fn texture_layer(&self, mesh: MeshId) -> u32 {
+        self.meshes[mesh].texture_layer
+    }
+
+    pub fn albedo_view(&self) -> &wgpu::TextureView {
+        self.albedo.view()
+    }
+
+    pub fn normal_view(&self) -> &wgpu::TextureView {
+        self.normal.view()
+    }
+
+    pub fn clear_instances(&mut self) {
+        for mesh in self.meshes.iter_mut() {
+            mesh.instance_attr.clear();
+            mesh.instances = 0..0;
+        }
+    }
+
+    pub fn has_instances(&self) -> bool {
+        self.meshes
+            .iter()
+            .any(|mesh| mesh.instances.end > mesh.instances.start)
+    }
+
+    ///view_proj is the camera of the occlusion culling
+    pub fn upload(
+        &mut self,
+        device: &Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        mips: &mut MipGenerator,
+        view_proj: &Matrix4<f32>,
+    ) {
+        if self.buffers.is_none() && !self.vertex_data.is_empty() {
+            let indirect_size = (self.meshes.len() * INDIRECT_LEN * 4) as u64;
+            let radius: Vec<f32> = self.meshes.iter().map(|mesh| mesh.radius).collect();
+            let indirect_desc = wgpu::BufferDescriptor {
+                size: indirect_size,
+                usage: wgpu::BufferUsage::INDIRECT
+                    | wgpu::BufferUsage::STORAGE
+                    | wgpu::BufferUsage::COPY_DST,
+            };
+            self.buffers = Some(Buffers {
+                vertex_buf: device
+                    .create_buffer_mapped(self.vertex_data.len(), wgpu::BufferUsage::VERTEX)
+                    .fill_from_slice(&self.vertex_data),
+                index_buf: device
+                    .create_buffer_mapped(self.index_data.len(), wgpu::BufferUsage::INDEX)
+                    .fill_from_slice(&self.index_data),
+                indirect_buf: device.create_buffer(&indirect_desc),
+                culled_indirect_buf: device.create_buffer(&indirect_desc),
+                radius_buf: device
+                    .create_buffer_mapped(radius.len(), wgpu::BufferUsage::STORAGE)
+                    .fill_from_slice(&radius),
+                _vertex_mem: Allocation::new(
+                    Kind::Vertex,
+                    std::mem::size_of_val(&self.vertex_data[..]) as u64,
+                ),
+                _index_mem: Allocation::new(
+                    Kind::Index,
+                    std::mem::size_of_val(&self.index_data[..]) as u64,
+                ),
+                _indirect_mem: Allocation::new(Kind::Indirect, indirect_size),
+                _culled_indirect_mem: Allocation::new(Kind::Indirect, indirect_size),
+                _radius_mem: Allocation::new(Kind::Vertex, radius.len() as u64 * 4),
+            });
+        }
+
+        for (layer, textures) in self.pending_textures.drain(..) {
+            for (array, img, default) in &[
+                (&self.albedo, &textures.albedo, DEFAULT_ALBEDO),
+                (&self.normal, &textures.normal, FLAT_NORMAL),
+            ] {
+                let written = match img {
+                    Some(img) => array
+                        .write_image(device, encoder, belt, layer, img)
+                        .map_err(|e| log::warn!("ModelBatch texture layer {}: {}", layer, e))
+                        .is_ok(),
+                    None => false,
+                };
+                if !written {
+                    array.write_color(device, encoder, belt, layer, *default);
+                }
+                array.generate_mips(device, encoder, mips, layer);
+            }
+        }
+
+        self.instance_attr.clear();
+        self.instance_mesh.clear();
+        for (index, mesh) in self.meshes.iter_mut().enumerate() {
+            let start = (self.instance_attr.len() / INSTANCE_LEN) as u32;
+            self.instance_attr.extend_from_slice(&mesh.instance_attr);
+            mesh.instances = start..(self.instance_attr.len() / INSTANCE_LEN) as u32;
+            self.instance_mesh
+                .extend(std::iter::repeat(index as u32).take(mesh.instances.len()));
+        }
+        self.instance_buf
+            .update(&self.instance_attr, device, encoder, belt);
+        //Inputs of cull, written even when it's off: it can be turned on before the next upload
+        self.instance_mesh_buf.update(
+            bytemuck::cast_slice(&self.instance_mesh),
+            device,
+            encoder,
+            belt,
+        );
+        self.culled_instance_buf
+            .reserve(device, self.instance_attr.len());
+        self.cull_uniform.update(
+            device,
+            encoder,
+            belt,
+            &CullUniform {
+                view_proj: (*view_proj).into(),
+                instance_count: self.instance_mesh.len() as u32,
+                _pad: [0; 3],
+            },
+        );
+
+        if let Some(buffers) = &self.buffers {
+            self.indirect.clear();
+            for mesh in self.meshes.iter() {
+                self.indirect.extend_from_slice(&[
+                    mesh.index_count,
+                    mesh.instances.end - mesh.instances.start,
+                    mesh.first_index,
+                    mesh.base_vertex as u32,
+                    mesh.instances.start,
+                ]);
+            }
+            belt.write_buffer(
+                device,
+                encoder,
+                &buffers.indirect_buf,
+                0,
+                bytemuck::cast_slice(&self.indirect),
+            );
+            for command in self.indirect.chunks_exact_mut(INDIRECT_LEN) {
+                command[1] = 0;
+            }
+            belt.write_buffer(
+                device,
+                encoder,
+                &buffers.culled_indirect_buf,
+                0,
+                bytemuck::cast_slice(&self.indirect),
+            );
+        }
+    }
+
+    ///Culling is on and the main pass draws the culled instances
+    pub fn occlusion_culling(&self) -> bool {
+        self.use_occlusion_culling && self.use_indirect
+    }
+
+    ///After the depth pre-pass and the build of hi_z. Writes the instances not hidden behind it
+    ///in culled_instance_buf, and their counts in culled_indirect_buf.
+    pub fn cull(&self, device: &Device, encoder: &mut wgpu::CommandEncoder, hi_z: &HiZ) {
+        let buffers = match &self.buffers {
+            Some(buffers) if self.occlusion_culling() && !self.instance_mesh.is_empty() => buffers,
+            _ => return,
+        };
+        log::trace!("ModelBatch cull");
+        let storage = |binding, buffer, len: usize| wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Buffer {
+                buffer,
+                range: 0..len as u64 * 4,
+            },
+        };
+        let [hi_z_texture, hi_z_sampler] = hi_z.bindings(6);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.cull_bind_group_layout,
+            bindings: &[
+                self.cull_uniform.binding(0),
+                storage(1, self.instance_buf.buffer(), self.instance_attr.len()),
+                storage(2, self.instance_mesh_buf.buffer(), self.instance_mesh.len()),
+                storage(3, &buffers.radius_buf, self.meshes.len()),
+                storage(
+                    4,
+                    &buffers.culled_indirect_buf,
+                    self.meshes.len() * INDIRECT_LEN,
+                ),
+                storage(
+                    5,
+                    self.culled_instance_buf.buffer(),
+                    self.instance_attr.len(),
+                ),
+                hi_z_texture,
+                hi_z_sampler,
+            ],
+        });
+        let instance_count = self.instance_mesh.len() as u32;
+        let mut cpass = encoder.begin_compute_pass();
+        cpass.set_pipeline(&self.cull_pipeline);
+        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.dispatch(
+            (instance_count + CULL_GROUP_SIZE - 1) / CULL_GROUP_SIZE,
+            1,
+            1,
+        );
+    }
+
+    fn create_cull_pipeline(
+        device: &Device,
+        cull_bind_group_layout: &BindGroupLayout,
+    ) -> shaders::Result<wgpu::ComputePipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[cull_bind_group_layout],
+        });
+        let cs_bytes = shaders::load("./src/shader/model_cull.comp")?;
+        let cs_module = device.create_shader_module(&cs_bytes);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            layout: &pipeline_layout,
+            compute_stage: wgpu::ProgrammableStageDescriptor {
+                module: &cs_module,
+                entry_point: "main",
+            },
+        });
+        Ok(pipeline)
+    }
+
+    ///bind_pipeline is false when the previous draw already used the ModelGpu pipeline of depth.
+    ///After the pre-pass, draws the instances kept by cull.
+    pub fn render(
+        &self,
+        rpass: &mut RenderPass,
+        main_bind_group: &BindGroup,
+        bind_pipeline: bool,
+        depth: DepthMode,
+    ) {
+        log::trace!("ModelBatch render");
+        if self.buffers.is_none() {
+            return;
+        }
+        if bind_pipeline {
+            rpass.set_pipeline(&self.pipelines[depth.pipeline_index()]);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+        }
+        self.draw(rpass, depth == DepthMode::Equal && self.occlusion_culling());
+    }
+
+    ///Buffers and draws only, for the pipelines taking the model vertex layout (see
+    ///ModelGpu::vertex_buffers). Every instance, culled or not.
+    pub fn render_geometry(&self, rpass: &mut RenderPass) {
+        self.draw(rpass, false);
+    }
+
+    fn draw(&self, rpass: &mut RenderPass, culled: bool) {
+        let buffers = match &self.buffers {
+            Some(buffers) => buffers,
+            None => return,
+        };
+        let (instance_buf, indirect_buf) = if culled {
+            (&self.culled_instance_buf, &buffers.culled_indirect_buf)
+        } else {
+            (&self.instance_buf, &buffers.indirect_buf)
+        };
+        rpass.set_index_buffer(&buffers.index_buf, 0);
+        rpass.set_vertex_buffers(0, &[(&buffers.vertex_buf, 0), (instance_buf.buffer(), 0)]);
+        //wgpu has no multi_draw_indexed_indirect: one indirect draw per mesh, all reading the
+        //same buffer
+        for (index, mesh) in self
+            .meshes
+            .iter()
+            .enumerate()
+            .filter(|(_, mesh)| mesh.instances.end > mesh.instances.start)
+        {
+            if self.use_indirect {
+                rpass.draw_indexed_indirect(indirect_buf, (index * INDIRECT_LEN * 4) as u64);
+            } else {
+                rpass.draw_indexed(
+                    mesh.first_index..mesh.first_index + mesh.index_count,
+                    mesh.base_vertex,
+                    mesh.instances.clone(),
+                );
+            }
+        }
+    }
+
+    ///Clear pipelines first, as for ModelGpu::reload_shared_pipeline
+    pub fn reload_shared_pipeline(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+        pipelines: &mut PipelineCache,
+    ) {
+        match ModelGpu::shared_pipelines(device, main_bind_group_layout, format, pipelines) {
+            Ok(pipelines) => self.pipelines = pipelines,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
@@ -1,16 +1,92 @@
-use super::glsl_compiler;
+use super::depth_mode::DepthMode;
+use super::gpu_memory::{Allocation, Kind};
+use super::instance_buffer::InstanceBuffer;
+use super::pipeline_cache::PipelineCache;
+use super::point_light;
+use super::shaders::{self, Defines};
+use super::staging_belt::StagingBelt;
+use super::taa;
 use crate::model;
+use std::rc::Rc;
 use wgpu::Device;
 use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat};
 
+///Floats per instance: position, euler angles, bitpacked highlight/team, con_completed,
+///then the unit::Material emissive, pulse frequency and uv scroll, and the texture layer in the
+///arrays of the ModelBatch, then the position and euler angles of the last frame
+pub const INSTANCE_LEN: usize = 19;
+
+const VERTEX_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 3] = [
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float4,
+        offset: 0,
+        shader_location: 0,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float3,
+        offset: 4 * 4,
+        shader_location: 1,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float2,
+        offset: 4 * 7,
+        shader_location: 2,
+    },
+];
+
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttributeDescriptor; 8] = [
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float3,
+        offset: 0,
+        shader_location: 3,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float3,
+        offset: 4 * 3,
+        shader_location: 4,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float,
+        offset: 4 * 6,
+        shader_location: 5,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float,
+        offset: 4 * 7,
+        shader_location: 6,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float4,
+        offset: 4 * 8,
+        shader_location: 7,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float,
+        offset: 4 * 12,
+        shader_location: 8,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float3,
+        offset: 4 * 13,
+        shader_location: 9,
+    },
+    wgpu::VertexAttributeDescriptor {
+        format: wgpu::VertexFormat::Float3,
+        offset: 4 * 16,
+        shader_location: 10,
+    },
+];
+
 pub struct ModelGpu {
-    pub instance_attr_cpu_buf: Vec<f32>,
     vertex_buf: wgpu::Buffer,
     index_buf: wgpu::Buffer,
     index_count: usize,
-    instance_buf: wgpu::Buffer,
+    instance_buf: InstanceBuffer,
     instance_count: u32,
-    pipeline: wgpu::RenderPipeline,
+    ///One per DepthMode
+    pipelines: Vec<Rc<wgpu::RenderPipeline>>,
+    _vertex_mem: Allocation,
+    _index_mem: Allocation,
 }
 
 impl ModelGpu {
@@ -19,6 +95,7 @@ impl ModelGpu {
         device: &Device,
         format: TextureFormat,
         main_bind_group_layout: &BindGroupLayout,
+        pipelines: &mut PipelineCache,
     ) -> Self {
         log::trace!("ModelGpu new");
         // Create the vertex and index buffers
@@ -34,25 +111,53 @@ impl ModelGpu {
             .create_buffer_mapped(index_data.len(), wgpu::BufferUsage::INDEX)
             .fill_from_slice(&index_data);
 
-        let positions: Vec<f32> = Vec::new();
+        let vertex_mem =
+            Allocation::new(Kind::Vertex, std::mem::size_of_val(&vertex_data[..]) as u64);
+        let index_mem = Allocation::new(Kind::Index, std::mem::size_of_val(&index_data[..]) as u64);
 
-        let instance_buf = device
-            .create_buffer_mapped(
-                positions.len(),
-                wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-            )
-            .fill_from_slice(&positions);
+        let instance_buf = InstanceBuffer::new(device);
 
-        let pipeline = Self::create_pipeline(device, main_bind_group_layout, format).unwrap();;
+        let pipelines =
+            Self::shared_pipelines(device, main_bind_group_layout, format, pipelines).unwrap();
 
         ModelGpu {
-            instance_attr_cpu_buf: Vec::new(),
             vertex_buf,
             index_buf,
             index_count: index_data.len(),
             instance_buf,
-            instance_count: positions.len() as u32 / 3,
-            pipeline,
+            instance_count: 0,
+            pipelines,
+            _vertex_mem: vertex_mem,
+            _index_mem: index_mem,
+        }
+    }
+
+    ///Every model is drawn with the same pipelines, one per DepthMode in DepthMode::ALL order
+    pub fn shared_pipelines(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+        pipelines: &mut PipelineCache,
+    ) -> shaders::Result<Vec<Rc<wgpu::RenderPipeline>>> {
+        DepthMode::ALL
+            .iter()
+            .map(|&depth| {
+                let name = match depth {
+                    DepthMode::Equal => "cube_instanced_equal",
+                    DepthMode::Write | DepthMode::PrePass => "cube_instanced",
+                };
+                pipelines.get_or_create(name, &Self::shader_defines(depth), |defines| {
+                    Self::create_pipeline(device, main_bind_group_layout, format, defines, depth)
+                })
+            })
+            .collect()
+    }
+
+    ///The pre-pass keeps the fragment stage: units under construction dissolve with discard
+    pub fn shader_defines(depth: DepthMode) -> Defines {
+        match depth {
+            DepthMode::PrePass => point_light::shader_defines().flag("DEPTH_PREPASS"),
+            DepthMode::Write | DepthMode::Equal => point_light::shader_defines(),
         }
     }
 
@@ -60,14 +165,15 @@ impl ModelGpu {
         device: &Device,
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
-    ) -> glsl_compiler::Result<wgpu::RenderPipeline> {
+        defines: &Defines,
+        depth: DepthMode,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[&main_bind_group_layout],
         });
-        let vertex_size = std::mem::size_of::<model::Vertex>();
         // Create the render pipeline
-        let vs_bytes = glsl_compiler::load("./src/shader/cube_instanced.vert")?;
-        let fs_bytes = glsl_compiler::load("./src/shader/cube_instanced.frag")?;
+        let vs_bytes = shaders::load_with_defines("./src/shader/cube_instanced.vert", defines)?;
+        let fs_bytes = shaders::load_with_defines("./src/shader/cube_instanced.frag", defines)?;
 
         let vs_module = device.create_shader_module(&vs_bytes);
         let fs_module = device.create_shader_module(&fs_bytes);
@@ -89,7 +195,7 @@ impl ModelGpu {
                 depth_bias_clamp: 0.0,
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[
+            color_states: depth.color_states(&[
                 wgpu::ColorStateDescriptor {
                     format: format,
                     color_blend: wgpu::BlendDescriptor::REPLACE,
@@ -103,71 +209,21 @@ impl ModelGpu {
                     write_mask: wgpu::ColorWrite::ALL,
                 },
                 wgpu::ColorStateDescriptor {
-                    format: wgpu::TextureFormat::Rg16Float,
+                    format: wgpu::TextureFormat::Rgba16Float,
                     color_blend: wgpu::BlendDescriptor::REPLACE,
                     alpha_blend: wgpu::BlendDescriptor::REPLACE,
                     write_mask: wgpu::ColorWrite::ALL,
                 },
-            ],
-            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
-                stencil_read_mask: 0,
-                stencil_write_mask: 0,
-            }),
-            index_format: wgpu::IndexFormat::Uint32,
-            vertex_buffers: &[
-                wgpu::VertexBufferDescriptor {
-                    stride: vertex_size as wgpu::BufferAddress,
-                    step_mode: wgpu::InputStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float4,
-                            offset: 0,
-                            shader_location: 0,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float3,
-                            offset: 4 * 4,
-                            shader_location: 1,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float2,
-                            offset: 4 * 7,
-                            shader_location: 2,
-                        },
-                    ],
-                },
-                wgpu::VertexBufferDescriptor {
-                    stride: (4 * 8) as wgpu::BufferAddress,
-                    step_mode: wgpu::InputStepMode::Instance,
-                    attributes: &[
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float3,
-                            offset: 0,
-                            shader_location: 3,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float3,
-                            offset: 4 * 3,
-                            shader_location: 4,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float,
-                            offset: 4 * 6,
-                            shader_location: 5,
-                        },
-                        wgpu::VertexAttributeDescriptor {
-                            format: wgpu::VertexFormat::Float,
-                            offset: 4 * 7,
-                            shader_location: 6,
-                        },
-                    ],
+                wgpu::ColorStateDescriptor {
+                    format: taa::VELOCITY_FORMAT,
+                    color_blend: wgpu::BlendDescriptor::REPLACE,
+                    alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                    write_mask: wgpu::ColorWrite::ALL,
                 },
-            ],
+            ]),
+            depth_stencil_state: Some(depth.depth_stencil_state()),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &Self::vertex_buffers(),
             sample_count: 1,
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
@@ -175,35 +231,38 @@ impl ModelGpu {
         Ok(pipeline)
     }
 
-    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+    ///Vertices then instances, for the pipelines drawing the models
+    pub fn vertex_buffers() -> [wgpu::VertexBufferDescriptor<'static>; 2] {
+        [
+            wgpu::VertexBufferDescriptor {
+                stride: std::mem::size_of::<model::Vertex>() as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &VERTEX_ATTRIBUTES,
+            },
+            wgpu::VertexBufferDescriptor {
+                stride: (4 * INSTANCE_LEN) as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &INSTANCE_ATTRIBUTES,
+            },
+        ]
+    }
+
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup, depth: DepthMode) {
         log::trace!("ModelGpu render");
         if self.instance_count > 0 {
-            rpass.set_pipeline(&self.pipeline);
+            rpass.set_pipeline(&self.pipelines[depth.pipeline_index()]);
             rpass.set_bind_group(0, main_bind_group, &[]);
-            rpass.set_index_buffer(&self.index_buf, 0);
-            rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0), (&self.instance_buf, 0)]);
-            rpass.draw_indexed(0..self.index_count as u32, 0, 0..self.instance_count as u32);
+            self.render_same_state(rpass);
         }
     }
 
-    pub fn update_instance_dirty(&mut self, instance_attr: &[f32], device: &wgpu::Device) {
-        log::trace!("ModelGpu update_instance");
-        let temp_buf = device
-            .create_buffer_mapped(instance_attr.len(), wgpu::BufferUsage::VERTEX)
-            .fill_from_slice(instance_attr);
-
-        std::mem::replace(&mut self.instance_buf, temp_buf);
-        self.instance_count = instance_attr.len() as u32 / 8;
-    }
-
-    pub fn update_instance_dirty_own_buffer(&mut self, device: &wgpu::Device) {
-        log::trace!("ModelGpu update_instance");
-        let temp_buf = device
-            .create_buffer_mapped(self.instance_attr_cpu_buf.len(), wgpu::BufferUsage::VERTEX)
-            .fill_from_slice(&self.instance_attr_cpu_buf);
-
-        std::mem::replace(&mut self.instance_buf, temp_buf);
-        self.instance_count = self.instance_attr_cpu_buf.len() as u32 / 8;
+    ///When the previous draw was a model: all share the pipelines and the main bind group
+    pub fn render_same_state(&self, rpass: &mut RenderPass) {
+        if self.instance_count > 0 {
+            rpass.set_index_buffer(&self.index_buf, 0);
+            rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0), (self.instance_buf.buffer(), 0)]);
+            rpass.draw_indexed(0..self.index_count as u32, 0, 0..self.instance_count as u32);
+        }
     }
 
     pub fn update_instance(
@@ -211,41 +270,34 @@ impl ModelGpu {
         instance_attr: &[f32],
         device: &wgpu::Device,
         encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
     ) {
         log::trace!("ModelGpu update_instance");
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
+        self.instance_count = (instance_attr.len() / INSTANCE_LEN) as u32;
+    }
 
-        for (i, chunk) in instance_attr.chunks(1800).enumerate() {
-            let temp_buf = device
-                .create_buffer_mapped(
-                    chunk.len(),
-                    wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_SRC,
-                )
-                .fill_from_slice(chunk);
-
-            encoder.copy_buffer_to_buffer(
-                &temp_buf,
-                0,
-                &self.instance_buf,
-                i as u64 * 1800_u64,
-                chunk.len() as u64 * 4,
-            );
-        }
-        // let temp_buf = device
-        //     .create_buffer_mapped(
-        //         instance_attr.len(),
-        //         wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_SRC,
-        //     )
-        //     .fill_from_slice(instance_attr);
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
 
-        // encoder.copy_buffer_to_buffer(
-        //     &temp_buf,
-        //     0,
-        //     &self.instance_buf,
-        //     0,
-        //     instance_attr.len() as u64 * 4,
-        // );
+    pub fn has_instances(&self) -> bool {
+        self.instance_count > 0
+    }
 
-        self.instance_count = instance_attr.len() as u32 / 8;
+    ///Clear pipelines first, the first model reloading compiles the shaders for all of them
+    pub fn reload_shared_pipeline(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+        pipelines: &mut PipelineCache,
+    ) {
+        match Self::shared_pipelines(device, main_bind_group_layout, format, pipelines) {
+            Ok(pipelines) => self.pipelines = pipelines,
+            Err(x) => log::error!("{}", x),
+        };
     }
 }
 
@@ -256,8 +308,16 @@ impl super::trait_gpu::TraitGpu for ModelGpu {
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
     ) {
-        match Self::create_pipeline(device, main_bind_group_layout, format) {
-            Ok(pipeline) => self.pipeline = pipeline,
+        let pipelines: shaders::Result<Vec<_>> = DepthMode::ALL
+            .iter()
+            .map(|&depth| {
+                let defines = Self::shader_defines(depth);
+                Self::create_pipeline(device, main_bind_group_layout, format, &defines, depth)
+                    .map(Rc::new)
+            })
+            .collect();
+        match pipelines {
+            Ok(pipelines) => self.pipelines = pipelines,
             Err(x) => log::error!("{}", x),
         };
     }
@@ -0,0 +1,155 @@
+use super::instance_buffer::InstanceBuffer;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use wgpu::Device;
+use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat};
+
+///Particles flowing along each stream, must match nanolathe.vert
+pub const PARTICLES_PER_STREAM: u32 = 24;
+///Floats per stream: from xyz, seed, to xyz, team
+pub const INSTANCE_LEN: usize = 8;
+
+///Particle streams going from constructors to the unit they build or repair
+pub struct NanolatheGpu {
+    instance_buf: InstanceBuffer,
+    instance_count: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl NanolatheGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        log::trace!("NanolatheGpu new");
+
+        let instance_buf = InstanceBuffer::new(device);
+
+        let pipeline = Self::create_pipeline(device, main_bind_group_layout, format).unwrap();
+
+        NanolatheGpu {
+            instance_buf,
+            instance_count: 0,
+            pipeline,
+        }
+    }
+
+    pub fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&main_bind_group_layout],
+        });
+        // Create the render pipeline
+        let vs_bytes = shaders::load("./src/shader/nanolathe.vert")?;
+        let fs_bytes = shaders::load("./src/shader/nanolathe.frag")?;
+
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                //Additive, the stream glows over what is behind
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: (4 * INSTANCE_LEN) as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &[
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float4,
+                        offset: 0,
+                        shader_location: 0,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float4,
+                        offset: 4 * 4,
+                        shader_location: 1,
+                    },
+                ],
+            }],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("NanolatheGpu render");
+        if self.instance_count > 0 {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_vertex_buffers(0, &[(self.instance_buf.buffer(), 0)]);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            //Two triangles per particle
+            rpass.draw(0..6 * PARTICLES_PER_STREAM, 0..self.instance_count);
+        }
+    }
+
+    pub fn update_instance(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        log::trace!("NanolatheGpu update_instance");
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
+        self.instance_count = (instance_attr.len() / INSTANCE_LEN) as u32;
+    }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
+}
+
+impl super::trait_gpu::TraitGpu for NanolatheGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(device, main_bind_group_layout, format) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
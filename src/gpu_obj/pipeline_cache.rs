@@ -0,0 +1,38 @@
+use super::shaders::{self, Defines};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+///Pipelines shared by the gpu objects drawn with the same shaders, one per shader variant
+pub struct PipelineCache {
+    pipelines: HashMap<(&'static str, Defines), Rc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        PipelineCache {
+            pipelines: HashMap::new(),
+        }
+    }
+
+    ///name identifies how create builds the pipeline, defines the variant of its shaders
+    pub fn get_or_create(
+        &mut self,
+        name: &'static str,
+        defines: &Defines,
+        create: impl FnOnce(&Defines) -> shaders::Result<wgpu::RenderPipeline>,
+    ) -> shaders::Result<Rc<wgpu::RenderPipeline>> {
+        let key = (name, defines.clone());
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return Ok(pipeline.clone());
+        }
+        log::debug!("PipelineCache new {} {:?}", name, defines);
+        let pipeline = Rc::new(create(defines)?);
+        self.pipelines.insert(key, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    ///On shader reload, objects keep their pipeline until they get it again
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+    }
+}
@@ -0,0 +1,91 @@
+use super::shaders::Defines;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::UniformBuffer;
+use bytemuck::{Pod, Zeroable};
+use na::Point3;
+use wgpu::{CommandEncoder, Device};
+
+///Given to the shaders declaring PointLights as MAX_LIGHTS
+pub const MAX_POINT_LIGHTS: usize = 64;
+///Lights further than this from the camera are culled
+pub const MAX_DISTANCE: f32 = 600.0;
+
+///PointLights block of the shaders
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct PointLightsUniform {
+    ///Only x is used
+    pub light_count: [f32; 4],
+    pub light_pos_radius: [[f32; 4]; MAX_POINT_LIGHTS],
+    pub light_color_intensity: [[f32; 4]; MAX_POINT_LIGHTS],
+}
+
+///Defines of the shaders declaring PointLights
+pub fn shader_defines() -> Defines {
+    Defines::new().value("MAX_LIGHTS", MAX_POINT_LIGHTS)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: Point3<f32>,
+    pub radius: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+///Owns the PointLights uniform buffer, bound in the main bind group at binding 3
+pub struct PointLightGpu {
+    pub uniform: UniformBuffer<PointLightsUniform>,
+    pub light_count: usize,
+}
+
+impl PointLightGpu {
+    pub fn new(device: &Device) -> Self {
+        log::trace!("PointLightGpu new");
+        let uniform = UniformBuffer::new(device, &PointLightsUniform::zeroed());
+
+        PointLightGpu {
+            uniform,
+            light_count: 0,
+        }
+    }
+
+    ///Keeps the MAX_POINT_LIGHTS closest lights to the camera
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        camera_pos: &Point3<f32>,
+        lights: &[PointLight],
+    ) {
+        log::trace!("PointLightGpu update");
+        let mut culled: Vec<(f32, &PointLight)> = lights
+            .iter()
+            .map(|l| ((l.position.coords - camera_pos.coords).magnitude(), l))
+            .filter(|(d, l)| *d < MAX_DISTANCE + l.radius)
+            .collect();
+        culled.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        culled.truncate(MAX_POINT_LIGHTS);
+        self.light_count = culled.len();
+
+        let mut data = PointLightsUniform::zeroed();
+        data.light_count[0] = culled.len() as f32;
+        for (i, (_, light)) in culled.iter().enumerate() {
+            data.light_pos_radius[i] = [
+                light.position.x,
+                light.position.y,
+                light.position.z,
+                light.radius,
+            ];
+            data.light_color_intensity[i] = [
+                light.color[0],
+                light.color[1],
+                light.color[2],
+                light.intensity,
+            ];
+        }
+
+        self.uniform.update(device, encoder, belt, &data);
+    }
+}
@@ -1,4 +1,4 @@
-use super::glsl_compiler;
+use super::shaders;
 use wgpu::Device;
 use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat, TextureView};
 
@@ -103,14 +103,14 @@ impl PostFxaa {
         bind_group_layout: &BindGroupLayout,
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
-    ) -> glsl_compiler::Result<wgpu::RenderPipeline> {
+    ) -> shaders::Result<wgpu::RenderPipeline> {
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[&main_bind_group_layout, &bind_group_layout],
         });
 
         // Create the render pipeline
-        let vs_bytes = glsl_compiler::load("./src/shader/post.vert")?;
-        let fs_bytes = glsl_compiler::load("./src/shader/post_fxaa.frag")?;
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/post_fxaa.frag")?;
         let vs_module = device.create_shader_module(&vs_bytes);
         let fs_module = device.create_shader_module(&fs_bytes);
 
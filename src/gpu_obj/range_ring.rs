@@ -0,0 +1,256 @@
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::UniformBuffer;
+use bytemuck::{Pod, Zeroable};
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass};
+use wgpu::{TextureFormat, TextureView};
+
+///Rings drawn at once, must match range_ring.frag
+pub const MAX_RINGS: usize = 64;
+
+///What a ring shows, its color in range_ring.frag
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RingKind {
+    Weapon,
+    Shield,
+    Build,
+    ///Interception radius of an anti nuke
+    AntiNuke,
+    ///Around the buildings touching the one being placed, see botdef::Adjacency
+    Adjacency,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RangeRing {
+    pub x: f32,
+    pub y: f32,
+    ///m
+    pub radius: f32,
+    pub kind: RingKind,
+}
+
+///RangeRings block of range_ring.frag
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct RangeRingsUniform {
+    ///Only x is used
+    ring_count: [f32; 4],
+    ///x, y, radius, kind
+    center_radius_kind: [[f32; 4]; MAX_RINGS],
+}
+
+///Range circles projected on whatever was drawn, from the position buffer, so they follow the
+///terrain at any slope
+pub struct RangeRingGpu {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: BindGroup,
+    uniform: UniformBuffer<RangeRingsUniform>,
+    ring_count: usize,
+}
+
+impl RangeRingGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        position: &TextureView,
+    ) -> Self {
+        log::trace!("RangeRingGpu new");
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                UniformBuffer::<RangeRingsUniform>::layout_binding(0, wgpu::ShaderStage::FRAGMENT),
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+
+        let uniform = UniformBuffer::new(device, &RangeRingsUniform::zeroed());
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &uniform, &sampler, position);
+        let pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, &bind_group_layout, format)
+                .unwrap();
+
+        RangeRingGpu {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            bind_group,
+            uniform,
+            ring_count: 0,
+        }
+    }
+
+    ///After a resize
+    pub fn update_views(&mut self, device: &Device, position: &TextureView) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform,
+            &self.sampler,
+            position,
+        );
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform: &UniformBuffer<RangeRingsUniform>,
+        sampler: &wgpu::Sampler,
+        position: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                uniform.binding(0),
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(position),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    ///Keeps the first MAX_RINGS rings
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        rings: &[RangeRing],
+    ) {
+        log::trace!("RangeRingGpu update");
+        self.ring_count = rings.len().min(MAX_RINGS);
+        if self.ring_count == 0 {
+            return;
+        }
+        let mut data = RangeRingsUniform::zeroed();
+        data.ring_count[0] = self.ring_count as f32;
+        for (i, ring) in rings.iter().take(MAX_RINGS).enumerate() {
+            let kind = match ring.kind {
+                RingKind::Weapon => 0.0,
+                RingKind::Shield => 1.0,
+                RingKind::Build => 2.0,
+                RingKind::AntiNuke => 3.0,
+                RingKind::Adjacency => 4.0,
+            };
+            data.center_radius_kind[i] = [ring.x, ring.y, ring.radius, kind];
+        }
+        self.uniform.update(device, encoder, belt, &data);
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[main_bind_group_layout, bind_group_layout],
+        });
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/range_ring.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the range_rings pass, nothing without rings
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("RangeRingGpu render");
+        if self.ring_count > 0 {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.set_bind_group(1, &self.bind_group, &[]);
+            rpass.draw(0..4, 0..1);
+        }
+    }
+}
+
+impl super::trait_gpu::TraitGpu for RangeRingGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            main_bind_group_layout,
+            &self.bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
@@ -0,0 +1,410 @@
+use super::gpu_memory::Allocation;
+use wgpu::{CommandEncoder, RenderPass, TextureFormat, TextureUsage, TextureView};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TextureId(usize);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PassId(usize);
+
+///Value written by the first pass of the frame writing a texture
+#[derive(Clone, Copy)]
+pub enum Clear {
+    Color(wgpu::Color),
+    Depth(f32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Texture(TextureId),
+    ///Swap chain view, given when the pass begins
+    Frame,
+}
+
+///Screen sized texture owned by the graph
+struct TextureDesc {
+    name: &'static str,
+    format: TextureFormat,
+    usage: TextureUsage,
+    clear: Clear,
+    ///Read by the next frame or outside the graph: kept alive for the whole frame, never aliased
+    persistent: bool,
+    ///At the window size instead of the scene size
+    window_sized: bool,
+}
+
+///Attachments written by a render pass and textures it samples or copies
+pub struct Pass {
+    name: &'static str,
+    colors: Vec<Target>,
+    depth: Option<TextureId>,
+    reads: Vec<TextureId>,
+    ///Set by compile, per color attachment then for depth
+    clear_colors: Vec<bool>,
+    clear_depth: bool,
+}
+
+impl Pass {
+    pub fn new(name: &'static str) -> Self {
+        Pass {
+            name,
+            colors: Vec::new(),
+            depth: None,
+            reads: Vec::new(),
+            clear_colors: Vec::new(),
+            clear_depth: false,
+        }
+    }
+
+    ///Color attachments are bound in call order, as the locations of the fragment shaders
+    pub fn color(mut self, texture: TextureId) -> Self {
+        self.colors.push(Target::Texture(texture));
+        self
+    }
+
+    pub fn frame(mut self) -> Self {
+        self.colors.push(Target::Frame);
+        self
+    }
+
+    pub fn depth(mut self, texture: TextureId) -> Self {
+        self.depth = Some(texture);
+        self
+    }
+
+    ///Runs after every pass writing texture
+    pub fn read(mut self, texture: TextureId) -> Self {
+        self.reads.push(texture);
+        self
+    }
+
+    fn writes(&self, texture: TextureId) -> bool {
+        self.colors.contains(&Target::Texture(texture)) || self.depth == Some(texture)
+    }
+
+    fn uses(&self, texture: TextureId) -> bool {
+        self.writes(texture) || self.reads.contains(&texture)
+    }
+}
+
+struct Physical {
+    texture: wgpu::Texture,
+    view: TextureView,
+    _allocation: Allocation,
+}
+
+///The passes of a frame declare what they read and write. The graph orders them, skips the ones
+///contributing to nothing, picks clear or load for each attachment and allocates the textures,
+///sharing memory between textures not alive at the same time.
+pub struct RenderGraph {
+    textures: Vec<TextureDesc>,
+    passes: Vec<Pass>,
+    order: Vec<PassId>,
+    ///Index in physical of each texture, None if no pass uses it
+    slot_of: Vec<Option<usize>>,
+    physical: Vec<Physical>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        RenderGraph {
+            textures: Vec::new(),
+            passes: Vec::new(),
+            order: Vec::new(),
+            slot_of: Vec::new(),
+            physical: Vec::new(),
+        }
+    }
+
+    ///OUTPUT_ATTACHMENT is added to usage
+    pub fn texture(
+        &mut self,
+        name: &'static str,
+        format: TextureFormat,
+        usage: TextureUsage,
+        clear: Clear,
+    ) -> TextureId {
+        self.textures.push(TextureDesc {
+            name,
+            format,
+            usage: usage | TextureUsage::OUTPUT_ATTACHMENT,
+            clear,
+            persistent: false,
+            window_sized: false,
+        });
+        TextureId(self.textures.len() - 1)
+    }
+
+    ///For textures read by the next frame (ex: sampled before being written again) or copied
+    ///after the passes
+    pub fn persist(&mut self, texture: TextureId) {
+        self.textures[texture.0].persistent = true;
+    }
+
+    ///For textures drawn after the scene is upscaled to the window (ex: the ui)
+    pub fn window_sized(&mut self, texture: TextureId) {
+        self.textures[texture.0].window_sized = true;
+    }
+
+    pub fn add_pass(&mut self, pass: Pass) -> PassId {
+        self.passes.push(pass);
+        PassId(self.passes.len() - 1)
+    }
+
+    ///Call once every pass is added and on each resize. Views given out before are invalidated.
+    ///The textures are scene sized but the window_sized ones.
+    pub fn compile(
+        &mut self,
+        device: &wgpu::Device,
+        window_size: (u32, u32),
+        scene_size: (u32, u32),
+    ) {
+        self.order = self.sorted_passes();
+
+        //First pass in order writing a texture clears it
+        let mut written = vec![false; self.textures.len()];
+        for &PassId(index) in &self.order {
+            let pass = &mut self.passes[index];
+            let mut first_write = |target: Target| match target {
+                Target::Texture(TextureId(t)) => !std::mem::replace(&mut written[t], true),
+                Target::Frame => true,
+            };
+            pass.clear_colors = pass.colors.iter().map(|&c| first_write(c)).collect();
+            pass.clear_depth = pass
+                .depth
+                .map(|d| first_write(Target::Texture(d)))
+                .unwrap_or(false);
+        }
+
+        self.allocate(device, window_size, scene_size);
+    }
+
+    ///Topological order, declaration order between independent passes. Writers of a texture run
+    ///in declaration order, readers after all of them.
+    fn sorted_passes(&self) -> Vec<PassId> {
+        let count = self.passes.len();
+        let mut after: Vec<Vec<usize>> = vec![Vec::new(); count];
+        for t in 0..self.textures.len() {
+            let texture = TextureId(t);
+            let writers: Vec<usize> = (0..count)
+                .filter(|&p| self.passes[p].writes(texture))
+                .collect();
+            for pair in writers.windows(2) {
+                after[pair[1]].push(pair[0]);
+            }
+            for reader in (0..count).filter(|&p| self.passes[p].reads.contains(&texture)) {
+                after[reader].extend(writers.iter().filter(|&&w| w != reader));
+            }
+        }
+
+        //Only passes writing the frame or a persistent texture, and the ones they depend on
+        let mut needed = vec![false; count];
+        let mut stack: Vec<usize> = (0..count)
+            .filter(|&p| {
+                let pass = &self.passes[p];
+                pass.colors.contains(&Target::Frame)
+                    || (0..self.textures.len())
+                        .any(|t| self.textures[t].persistent && pass.writes(TextureId(t)))
+            })
+            .collect();
+        while let Some(p) = stack.pop() {
+            if !std::mem::replace(&mut needed[p], true) {
+                stack.extend(after[p].iter().copied());
+            }
+        }
+
+        let mut order = Vec::new();
+        let mut done = vec![false; count];
+        while order.len() < needed.iter().filter(|n| **n).count() {
+            let next = (0..count).find(|&p| {
+                needed[p] && !done[p] && after[p].iter().all(|&d| done[d] || !needed[d])
+            });
+            match next {
+                Some(p) => {
+                    done[p] = true;
+                    order.push(PassId(p));
+                }
+                None => panic!("RenderGraph: cycle between the passes"),
+            }
+        }
+        for p in (0..count).filter(|&p| !needed[p]) {
+            log::debug!("RenderGraph: pass {} skipped", self.passes[p].name);
+        }
+        order
+    }
+
+    fn allocate(&mut self, device: &wgpu::Device, window_size: (u32, u32), scene_size: (u32, u32)) {
+        //Position in order of the first and last pass using each texture
+        let lifetimes: Vec<Option<(usize, usize)>> = (0..self.textures.len())
+            .map(|t| {
+                let mut positions = self
+                    .order
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, pass)| self.passes[pass.0].uses(TextureId(t)))
+                    .map(|(i, _)| i);
+                let first = positions.next()?;
+                let last = positions.last().unwrap_or(first);
+                if self.textures[t].persistent {
+                    Some((0, usize::max_value()))
+                } else {
+                    Some((first, last))
+                }
+            })
+            .collect();
+
+        let mut by_first_use: Vec<usize> = (0..self.textures.len())
+            .filter(|&t| lifetimes[t].is_some())
+            .collect();
+        by_first_use.sort_by_key(|&t| lifetimes[t].map(|(first, _)| first));
+
+        //Per slot: the texture it was created for and the last use of its current holder
+        let mut slots: Vec<(usize, usize)> = Vec::new();
+        self.slot_of = vec![None; self.textures.len()];
+        for t in by_first_use {
+            let (first, last) = lifetimes[t].unwrap();
+            let desc = &self.textures[t];
+            let reusable = slots.iter().position(|&(owner, free_after)| {
+                let owner = &self.textures[owner];
+                !desc.persistent
+                    && !owner.persistent
+                    && owner.format == desc.format
+                    && owner.usage == desc.usage
+                    && owner.window_sized == desc.window_sized
+                    && free_after < first
+            });
+            let slot = match reusable {
+                Some(slot) => {
+                    log::debug!(
+                        "RenderGraph: {} aliases {}",
+                        desc.name,
+                        self.textures[slots[slot].0].name
+                    );
+                    slots[slot].1 = last;
+                    slot
+                }
+                None => {
+                    slots.push((t, last));
+                    slots.len() - 1
+                }
+            };
+            self.slot_of[t] = Some(slot);
+        }
+
+        self.physical = slots
+            .iter()
+            .map(|&(t, _)| {
+                let (width, height) = if self.textures[t].window_sized {
+                    window_size
+                } else {
+                    scene_size
+                };
+                let desc = wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth: 1,
+                    },
+                    array_layer_count: 1,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.textures[t].format,
+                    usage: self.textures[t].usage,
+                };
+                let texture = device.create_texture(&desc);
+                Physical {
+                    view: texture.create_default_view(),
+                    texture,
+                    _allocation: Allocation::texture(&desc),
+                }
+            })
+            .collect();
+    }
+
+    ///Passes to record this frame, in order
+    pub fn order(&self) -> &[PassId] {
+        &self.order
+    }
+
+    pub fn name(&self, pass: PassId) -> &'static str {
+        self.passes[pass.0].name
+    }
+
+    fn physical(&self, texture: TextureId) -> &Physical {
+        match self.slot_of[texture.0] {
+            Some(slot) => &self.physical[slot],
+            None => panic!(
+                "RenderGraph: {} is used by no pass",
+                self.textures[texture.0].name
+            ),
+        }
+    }
+
+    pub fn view(&self, texture: TextureId) -> &TextureView {
+        &self.physical(texture).view
+    }
+
+    pub fn texture_of(&self, texture: TextureId) -> &wgpu::Texture {
+        &self.physical(texture).texture
+    }
+
+    pub fn begin<'a>(
+        &'a self,
+        pass: PassId,
+        encoder: &'a mut CommandEncoder,
+        frame: &'a TextureView,
+    ) -> RenderPass<'a> {
+        let pass = &self.passes[pass.0];
+        log::trace!("RenderGraph begin {}", pass.name);
+        let load_op = |clear: bool| {
+            if clear {
+                wgpu::LoadOp::Clear
+            } else {
+                wgpu::LoadOp::Load
+            }
+        };
+        let color_attachments: Vec<_> = pass
+            .colors
+            .iter()
+            .zip(pass.clear_colors.iter())
+            .map(|(target, &clear)| {
+                let (attachment, clear_color) = match target {
+                    Target::Texture(texture) => (
+                        self.view(*texture),
+                        match self.textures[texture.0].clear {
+                            Clear::Color(color) => color,
+                            Clear::Depth(_) => wgpu::Color::BLACK,
+                        },
+                    ),
+                    Target::Frame => (frame, wgpu::Color::BLACK),
+                };
+                wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment,
+                    resolve_target: None,
+                    load_op: load_op(clear),
+                    store_op: wgpu::StoreOp::Store,
+                    clear_color,
+                }
+            })
+            .collect();
+        let depth_stencil_attachment =
+            pass.depth
+                .map(|texture| wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: self.view(texture),
+                    depth_load_op: load_op(pass.clear_depth),
+                    depth_store_op: wgpu::StoreOp::Store,
+                    stencil_load_op: wgpu::LoadOp::Clear,
+                    stencil_store_op: wgpu::StoreOp::Store,
+                    clear_depth: match self.textures[texture.0].clear {
+                        Clear::Depth(depth) => depth,
+                        Clear::Color(_) => 1.0,
+                    },
+                    clear_stencil: 0,
+                });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &color_attachments,
+            depth_stencil_attachment,
+        })
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use wgpu::{BindGroupLayoutBinding, BindingType, ShaderStage, TextureViewDimension};
+
+//SPIR-V opcodes, decorations and storage classes read here
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+#[derive(Debug, Clone)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub ty: BindingType,
+}
+
+enum Type {
+    Sampler,
+    Image {
+        dimension: TextureViewDimension,
+        multisampled: bool,
+        storage: bool,
+    },
+    Struct,
+    Array(u32),
+    Pointer {
+        storage_class: u32,
+        pointee: u32,
+    },
+}
+
+///Resources a SPIR-V module declares, with their set and binding
+pub fn reflect(spirv: &[u32]) -> Vec<ReflectedBinding> {
+    let mut types = HashMap::new();
+    let mut variables = Vec::new();
+    let mut sets = HashMap::new();
+    let mut bindings = HashMap::new();
+    let mut buffer_blocks = Vec::new();
+
+    //Skips the 5 words header
+    let mut i = 5;
+    while i < spirv.len() {
+        let word_count = (spirv[i] >> 16) as usize;
+        let opcode = spirv[i] & 0xffff;
+        if word_count == 0 || i + word_count > spirv.len() {
+            log::warn!("shader_reflect: truncated spirv");
+            break;
+        }
+        let ops = &spirv[i + 1..i + word_count];
+        match opcode {
+            OP_TYPE_SAMPLER => {
+                types.insert(ops[0], Type::Sampler);
+            }
+            OP_TYPE_IMAGE => {
+                let arrayed = ops[4] == 1;
+                let dimension = match (ops[2], arrayed) {
+                    (0, _) => TextureViewDimension::D1,
+                    (1, false) => TextureViewDimension::D2,
+                    (1, true) => TextureViewDimension::D2Array,
+                    (2, _) => TextureViewDimension::D3,
+                    (3, false) => TextureViewDimension::Cube,
+                    _ => TextureViewDimension::CubeArray,
+                };
+                types.insert(
+                    ops[0],
+                    Type::Image {
+                        dimension,
+                        multisampled: ops[5] == 1,
+                        storage: ops[6] == 2,
+                    },
+                );
+            }
+            OP_TYPE_STRUCT => {
+                types.insert(ops[0], Type::Struct);
+            }
+            OP_TYPE_ARRAY => {
+                types.insert(ops[0], Type::Array(ops[1]));
+            }
+            OP_TYPE_POINTER => {
+                types.insert(
+                    ops[0],
+                    Type::Pointer {
+                        storage_class: ops[1],
+                        pointee: ops[2],
+                    },
+                );
+            }
+            OP_VARIABLE => variables.push((ops[0], ops[1])),
+            OP_DECORATE => match ops[1] {
+                DECORATION_DESCRIPTOR_SET => {
+                    sets.insert(ops[0], ops[2]);
+                }
+                DECORATION_BINDING => {
+                    bindings.insert(ops[0], ops[2]);
+                }
+                DECORATION_BUFFER_BLOCK => buffer_blocks.push(ops[0]),
+                _ => {}
+            },
+            _ => {}
+        }
+        i += word_count;
+    }
+
+    let mut reflected = Vec::new();
+    for (type_id, id) in variables {
+        let (set, binding) = match (sets.get(&id), bindings.get(&id)) {
+            (Some(set), Some(binding)) => (*set, *binding),
+            _ => continue,
+        };
+        let (storage_class, mut pointee) = match types.get(&type_id) {
+            Some(Type::Pointer {
+                storage_class,
+                pointee,
+            }) => (*storage_class, *pointee),
+            _ => continue,
+        };
+        while let Some(Type::Array(element)) = types.get(&pointee) {
+            pointee = *element;
+        }
+        let ty = match (storage_class, types.get(&pointee)) {
+            (STORAGE_UNIFORM, Some(Type::Struct)) if buffer_blocks.contains(&pointee) => {
+                BindingType::StorageBuffer {
+                    dynamic: false,
+                    readonly: false,
+                }
+            }
+            (STORAGE_UNIFORM, Some(Type::Struct)) => BindingType::UniformBuffer { dynamic: false },
+            (STORAGE_STORAGE_BUFFER, Some(Type::Struct)) => BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: false,
+            },
+            (STORAGE_UNIFORM_CONSTANT, Some(Type::Sampler)) => BindingType::Sampler,
+            (
+                STORAGE_UNIFORM_CONSTANT,
+                Some(Type::Image {
+                    dimension,
+                    storage: true,
+                    ..
+                }),
+            ) => BindingType::StorageTexture {
+                dimension: *dimension,
+            },
+            (
+                STORAGE_UNIFORM_CONSTANT,
+                Some(Type::Image {
+                    dimension,
+                    multisampled,
+                    ..
+                }),
+            ) => BindingType::SampledTexture {
+                multisampled: *multisampled,
+                dimension: *dimension,
+            },
+            _ => {
+                log::warn!(
+                    "shader_reflect: unsupported resource at set {} binding {}",
+                    set,
+                    binding
+                );
+                continue;
+            }
+        };
+        reflected.push(ReflectedBinding { set, binding, ty });
+    }
+    reflected.sort_by_key(|r| (r.set, r.binding));
+    reflected
+}
+
+///Layout of one set as declared by all the stages of a pipeline, visible to the stages using it
+pub fn layout_bindings(set: u32, stages: &[(&[u32], ShaderStage)]) -> Vec<BindGroupLayoutBinding> {
+    let mut layout: Vec<BindGroupLayoutBinding> = Vec::new();
+    for (spirv, stage) in stages {
+        for reflected in reflect(spirv).into_iter().filter(|r| r.set == set) {
+            match layout.iter_mut().find(|b| b.binding == reflected.binding) {
+                Some(existing) => existing.visibility |= *stage,
+                None => layout.push(BindGroupLayoutBinding {
+                    binding: reflected.binding,
+                    visibility: *stage,
+                    ty: reflected.ty,
+                }),
+            }
+        }
+    }
+    layout.sort_by_key(|b| b.binding);
+    layout
+}
+
+pub fn create_bind_group_layout(
+    device: &wgpu::Device,
+    set: u32,
+    stages: &[(&[u32], ShaderStage)],
+) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        bindings: &layout_bindings(set, stages),
+    })
+}
+
+///Logs the differences between a layout written on the rust side and what the shaders use.
+///Returns false if the shaders need a binding the layout doesn't provide.
+pub fn validate(
+    name: &str,
+    set: u32,
+    declared: &[BindGroupLayoutBinding],
+    stages: &[(&[u32], ShaderStage)],
+) -> bool {
+    let mut valid = true;
+    for used in layout_bindings(set, stages) {
+        match declared.iter().find(|b| b.binding == used.binding) {
+            None => {
+                log::error!(
+                    "{}: shaders use set {} binding {} ({:?}), missing from the layout",
+                    name,
+                    set,
+                    used.binding,
+                    used.ty
+                );
+                valid = false;
+            }
+            Some(declared) if declared.ty != used.ty => {
+                log::error!(
+                    "{}: set {} binding {} is {:?} in the layout, {:?} in the shaders",
+                    name,
+                    set,
+                    used.binding,
+                    declared.ty,
+                    used.ty
+                );
+                valid = false;
+            }
+            Some(declared) if !declared.visibility.contains(used.visibility) => {
+                log::error!(
+                    "{}: set {} binding {} is not visible to all the stages using it",
+                    name,
+                    set,
+                    used.binding
+                );
+                valid = false;
+            }
+            _ => {}
+        }
+    }
+    valid
+}
@@ -0,0 +1,430 @@
+#[cfg(feature = "use_shaderc")]
+use shaderc;
+
+#[allow(dead_code)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+///The stage comes from the extension before .wgsl for wgsl sources, ex: water.vert.wgsl
+fn str_to_shader_stage(str: &str) -> ShaderStage {
+    let str = str.trim_end_matches(".wgsl");
+    if str.ends_with("vert") {
+        ShaderStage::Vertex
+    } else if str.ends_with("frag") {
+        ShaderStage::Fragment
+    } else {
+        ShaderStage::Compute
+    }
+}
+
+///Compiled shaders, by source file name and cache key. Stale entries are left to the user to delete.
+#[cfg(not(feature = "use_spirv"))]
+pub const CACHE_DIR: &str = "shader_cache";
+#[cfg(feature = "use_shaderc")]
+const GLSL_COMPILER: &str = "shaderc 0.6";
+#[cfg(feature = "use_glsl_to_spirv")]
+const GLSL_COMPILER: &str = "glsl_to_spirv 0.1";
+#[cfg(not(feature = "use_spirv"))]
+const WGSL_COMPILER: &str = "naga 0.2";
+
+#[cfg(not(feature = "use_spirv"))]
+enum Frontend {
+    Glsl,
+    Wgsl,
+}
+
+#[cfg(not(feature = "use_spirv"))]
+fn str_to_frontend(str: &str) -> Frontend {
+    if str.ends_with(".wgsl") {
+        Frontend::Wgsl
+    } else {
+        Frontend::Glsl
+    }
+}
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::slice;
+
+///Preprocessor defines of a shader variant, ex: MAX_LIGHTS=64 or SKINNED.
+///Ordered, so that equal sets hash the same when used in a pipeline cache key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Defines {
+    defines: BTreeMap<String, String>,
+}
+
+impl Defines {
+    pub fn new() -> Self {
+        Defines::default()
+    }
+
+    pub fn flag(mut self, name: &str) -> Self {
+        self.defines.insert(name.to_owned(), String::new());
+        self
+    }
+
+    pub fn value(mut self, name: &str, value: impl ToString) -> Self {
+        self.defines.insert(name.to_owned(), value.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defines.is_empty()
+    }
+
+    ///Part of the file name of a precompiled variant, ex: DEPTH_PREPASS.MAX_LIGHTS_64
+    fn file_key(&self) -> String {
+        self.defines
+            .iter()
+            .map(|(name, value)| {
+                if value.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}_{}", name, value)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    ///Inserted after the #version line, which glsl requires first
+    #[cfg(not(feature = "use_spirv"))]
+    fn inject(&self, glsl_code: &str) -> String {
+        let header: String = self
+            .defines
+            .iter()
+            .map(|(name, value)| format!("#define {} {}\n", name, value))
+            .collect();
+        match glsl_code.find("#version") {
+            Some(version) => {
+                let line_end = glsl_code[version..]
+                    .find('\n')
+                    .map(|i| version + i + 1)
+                    .unwrap_or(glsl_code.len());
+                format!(
+                    "{}{}#line 2\n{}",
+                    &glsl_code[..line_end],
+                    header,
+                    &glsl_code[line_end..]
+                )
+            }
+            None => format!("{}#line 1\n{}", header, glsl_code),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ShaderCompilationError>;
+
+#[derive(Debug, Clone)]
+pub struct ShaderCompilationError {
+    pub msg: String,
+}
+
+impl fmt::Display for ShaderCompilationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "shader compilation error {}", self.msg)
+    }
+}
+
+impl error::Error for ShaderCompilationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+///Hashes of the sources of the precompiled shaders, by spirv file name, written by the compile
+///command along with them
+pub const MANIFEST: &str = "./src/shader/compiled/manifest.json";
+
+///Carriage returns are left out, so that checkouts converting line endings match
+pub fn source_hash(code: &str) -> String {
+    use std::hash::Hasher;
+    let mut hasher = fnv::FnvHasher::default();
+    for byte in code.bytes().filter(|&byte| byte != b'\r') {
+        hasher.write_u8(byte);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+///Define sets the pipelines load, precompiled along with the shaders without defines
+pub fn variants() -> Vec<(&'static str, Defines)> {
+    use super::depth_mode::DepthMode;
+    use super::model_gpu::ModelGpu;
+    let mut variants = Vec::new();
+    for &depth in &[DepthMode::Write, DepthMode::PrePass] {
+        let defines = ModelGpu::shader_defines(depth);
+        variants.push(("./src/shader/cube_instanced.vert", defines.clone()));
+        variants.push(("./src/shader/cube_instanced.frag", defines));
+    }
+    variants.push((
+        "./src/shader/heightmap.frag",
+        super::point_light::shader_defines(),
+    ));
+    for &flag in &["IRRADIANCE", "SPECULAR"] {
+        variants.push((
+            "./src/shader/environment_bake.frag",
+            Defines::new().flag(flag),
+        ));
+    }
+    variants.push(("./src/shader/trail.frag", super::oit::shader_defines(true)));
+    variants.push(("./src/shader/shield.frag", super::oit::shader_defines(true)));
+    variants
+}
+
+///compiled/<name>.spirv next to the source, compiled/<name>.<defines>.spirv for a variant
+pub fn precompiled_path(rel_path: &str, defines: &Defines) -> std::path::PathBuf {
+    let path = std::path::Path::new(rel_path);
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("shader");
+    let spirv_name = if defines.is_empty() {
+        format!("{}.spirv", file_name)
+    } else {
+        format!("{}.{}.spirv", file_name, defines.file_key())
+    };
+    path.parent()
+        .unwrap_or_else(|| std::path::Path::new("."))
+        .join("compiled")
+        .join(spirv_name)
+}
+
+pub fn load(rel_path: &str) -> Result<Vec<u32>> {
+    load_with_defines(rel_path, &Defines::new())
+}
+
+#[cfg(not(feature = "use_spirv"))]
+pub fn load_with_defines(rel_path: &str, defines: &Defines) -> Result<Vec<u32>> {
+    crate::crash::set_gpu_context(format!("shader {} {:?}", rel_path, defines));
+    let code = std::fs::read_to_string(std::path::Path::new(rel_path)).map_err(|e| {
+        ShaderCompilationError {
+            msg: format!("{} {}", rel_path, e),
+        }
+    })?;
+    let frontend = str_to_frontend(rel_path);
+    let cache_path = cache_path(rel_path, &code, defines, &frontend);
+    if let Some(spirv) = read_cache(&cache_path) {
+        log::debug!("shader cache : {} from {:?}", rel_path, cache_path);
+        return Ok(spirv);
+    }
+
+    let spirv = match frontend {
+        Frontend::Glsl => compile_glsl(
+            rel_path,
+            &defines.inject(&code),
+            str_to_shader_stage(rel_path),
+        )?,
+        Frontend::Wgsl => {
+            if !defines.is_empty() {
+                log::warn!("{} : wgsl has no preprocessor, defines ignored", rel_path);
+            }
+            compile_wgsl(rel_path, &code)?
+        }
+    };
+    write_cache(&cache_path, &spirv);
+    Ok(spirv)
+}
+
+///Keyed by a hash of the source, the defines and the compiler, so that edits miss the cache
+#[cfg(not(feature = "use_spirv"))]
+fn cache_path(
+    rel_path: &str,
+    code: &str,
+    defines: &Defines,
+    frontend: &Frontend,
+) -> std::path::PathBuf {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = fnv::FnvHasher::default();
+    code.hash(&mut hasher);
+    defines.hash(&mut hasher);
+    match frontend {
+        Frontend::Glsl => GLSL_COMPILER.hash(&mut hasher),
+        Frontend::Wgsl => WGSL_COMPILER.hash(&mut hasher),
+    }
+    let file_name = std::path::Path::new(rel_path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("shader");
+    std::path::Path::new(CACHE_DIR).join(format!("{}.{:016x}.spirv", file_name, hasher.finish()))
+}
+
+#[cfg(not(feature = "use_spirv"))]
+fn read_cache(path: &std::path::Path) -> Option<Vec<u32>> {
+    use std::convert::TryInto;
+    let bytes = std::fs::read(path).ok()?;
+    if bytes.is_empty() || bytes.len() % 4 != 0 {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+#[cfg(not(feature = "use_spirv"))]
+fn write_cache(path: &std::path::Path, spirv: &[u32]) {
+    let bytes: Vec<u8> = spirv
+        .iter()
+        .flat_map(|w| w.to_le_bytes().to_vec())
+        .collect();
+    let written = std::fs::create_dir_all(CACHE_DIR).and_then(|_| std::fs::write(path, bytes));
+    if let Err(e) = written {
+        log::warn!("shader cache : could not write {:?} {}", path, e);
+    }
+}
+
+#[cfg(feature = "use_glsl_to_spirv")]
+fn compile_glsl(rel_path: &str, glsl_code: &str, stage: ShaderStage) -> Result<Vec<u32>> {
+    log::info!("glsl_to_spirv : compiling {}", rel_path);
+    let ty = match stage {
+        ShaderStage::Vertex => glsl_to_spirv::ShaderType::Vertex,
+        ShaderStage::Fragment => glsl_to_spirv::ShaderType::Fragment,
+        ShaderStage::Compute => glsl_to_spirv::ShaderType::Compute,
+    };
+
+    Ok(
+        wgpu::read_spirv(glsl_to_spirv::compile(glsl_code, ty).map_err(|e| {
+            ShaderCompilationError {
+                msg: format!("{}", e),
+            }
+        })?)
+        .map_err(|e| ShaderCompilationError {
+            msg: format!("{}", e),
+        })?,
+    )
+    //   wgpu::read_spirv(glsl_to_spirv::compile(glsl_code, ty).unwrap()).unwrap()
+}
+
+#[cfg(feature = "use_shaderc")]
+fn compile_glsl(rel_path: &str, glsl_code: &str, stage: ShaderStage) -> Result<Vec<u32>> {
+    log::info!("shaderc : compiling {}", rel_path);
+
+    let ty = match stage {
+        ShaderStage::Vertex => shaderc::ShaderKind::Vertex,
+        ShaderStage::Fragment => shaderc::ShaderKind::Fragment,
+        ShaderStage::Compute => shaderc::ShaderKind::Compute,
+    };
+
+    let mut compiler = shaderc::Compiler::new().unwrap();
+    let mut options = shaderc::CompileOptions::new().unwrap();
+    options.add_macro_definition("EP", Some("main"));
+    let binary_result = compiler
+        .compile_into_spirv(glsl_code, ty, rel_path, "main", Some(&options))
+        .map_err(|e| ShaderCompilationError {
+            msg: format!("{}", e),
+        })?;
+
+    Ok(binary_result.as_binary().to_owned())
+}
+
+#[cfg(all(feature = "use_wgsl", not(feature = "use_spirv")))]
+fn compile_wgsl(rel_path: &str, wgsl_code: &str) -> Result<Vec<u32>> {
+    log::info!("naga : compiling {}", rel_path);
+    let module = naga::front::wgsl::parse_str(wgsl_code).map_err(|e| ShaderCompilationError {
+        msg: format!("{:?}", e),
+    })?;
+    let mut writer =
+        naga::back::spv::Writer::new(&module.header, naga::back::spv::WriterFlags::NONE);
+    Ok(writer.write(&module))
+}
+
+#[cfg(not(any(feature = "use_wgsl", feature = "use_spirv")))]
+fn compile_wgsl(rel_path: &str, _wgsl_code: &str) -> Result<Vec<u32>> {
+    Err(ShaderCompilationError {
+        msg: format!("{} : wgsl sources need the use_wgsl feature", rel_path),
+    })
+}
+
+///Precompiled shaders, glsl and wgsl sources alike, are read from compiled/, see
+///precompiled_path. The compile command writes a variant for each define set of variants().
+#[cfg(feature = "use_spirv")]
+pub fn load_with_defines(rel_path: &str, defines: &Defines) -> Result<Vec<u32>> {
+    crate::crash::set_gpu_context(format!("shader {} {:?}", rel_path, defines));
+    let spirv_path = precompiled_path(rel_path, defines);
+    log::trace!("spirv : reading {:?}", spirv_path);
+    let spirv = std::fs::read(&spirv_path).map_err(|e| ShaderCompilationError {
+        msg: format!(
+            "{} {:?} : {}, missing from shaders::variants() or not compiled",
+            rel_path, spirv_path, e
+        ),
+    })?;
+    if spirv.is_empty() || spirv.len() % 4 != 0 {
+        return Err(ShaderCompilationError {
+            msg: format!("{:?} : not spirv", spirv_path),
+        });
+    }
+
+    use std::convert::TryInto;
+    let vec_u32: Vec<u32> = spirv
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+
+    Ok(vec_u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu_obj::depth_mode::DepthMode;
+    use crate::gpu_obj::model_gpu::ModelGpu;
+
+    #[test]
+    fn variant_file_names() {
+        let path = precompiled_path(
+            "./src/shader/cube_instanced.frag",
+            &ModelGpu::shader_defines(DepthMode::PrePass),
+        );
+        assert!(path.ends_with("compiled/cube_instanced.frag.DEPTH_PREPASS.MAX_LIGHTS_64.spirv"));
+        let path = precompiled_path("./src/shader/post.vert", &Defines::new());
+        assert!(path.ends_with("compiled/post.vert.spirv"));
+    }
+
+    ///Run the compile command when it fails
+    #[test]
+    fn precompiled_shaders_match_their_sources() {
+        let json = std::fs::read_to_string(MANIFEST).unwrap();
+        let manifest: BTreeMap<String, String> = serde_json::from_str(&json).unwrap();
+
+        let mut shaders: Vec<(String, Defines)> = std::fs::read_dir("./src/shader")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .map(|path| (path.to_string_lossy().into_owned(), Defines::new()))
+            .collect();
+        shaders.extend(
+            variants()
+                .into_iter()
+                .map(|(path, defines)| (path.to_owned(), defines)),
+        );
+
+        let mut wrong = Vec::new();
+        for (path, defines) in shaders.iter() {
+            let spirv = precompiled_path(path, defines);
+            let name = spirv.file_name().unwrap().to_string_lossy().into_owned();
+            let code = std::fs::read_to_string(path).unwrap();
+            if !spirv.exists() {
+                wrong.push(format!("{} missing", name));
+            } else if manifest.get(&name) != Some(&source_hash(&code)) {
+                wrong.push(format!("{} stale", name));
+            }
+        }
+        assert!(wrong.is_empty(), "{:?}", wrong);
+    }
+
+    #[cfg(feature = "use_spirv")]
+    #[test]
+    fn missing_variant_is_an_error() {
+        for (path, defines) in variants() {
+            assert!(load_with_defines(path, &defines).is_ok(), "{}", path);
+        }
+        let defines = Defines::new().flag("NOT_A_VARIANT");
+        assert!(load_with_defines("./src/shader/trail.frag", &defines).is_err());
+    }
+}
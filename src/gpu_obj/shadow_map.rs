@@ -0,0 +1,331 @@
+use super::gpu_memory::Allocation;
+use super::model_batch::ModelBatch;
+use super::model_gpu::ModelGpu;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::{DynamicUniformBuffer, UniformBuffer};
+use bytemuck::{Pod, Zeroable};
+use na::{Matrix4, Point3, Vector3};
+use wgpu::{BindGroupLayout, CommandEncoder, Device, TextureView};
+
+pub const CASCADES: usize = 4;
+///Width and height of each cascade
+const SIZE: u32 = 2048;
+///Shadows end this far from the camera, the last cascade stops there
+const SHADOW_DISTANCE: f32 = 3000.0;
+///Between a logarithmic (1.0) and a uniform (0.0) split of the distance
+const SPLIT_LAMBDA: f32 = 0.75;
+///Casters this far behind the bounding sphere of a cascade, toward the sun, still cast
+const CASTER_MARGIN: f32 = 500.0;
+///From the sun to the ground, as the light position of the lit shaders
+const SUN_DIR: [f32; 3] = [10000.0, -1000.0, -12000.0];
+
+///Shadows block of the shaders, bound in the main bind group
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct ShadowUniform {
+    pub light_view_proj: [[[f32; 4]; 4]; CASCADES],
+    ///View depth where each cascade ends
+    pub cascade_splits: [f32; 4],
+}
+
+///Cascade block of the caster shader
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct CasterUniform {
+    light_view_proj: [[f32; 4]; 4],
+}
+
+///Directional shadows of the sun in CASCADES depth layers, each fit to a slice of the camera
+///frustum: the near slices are small and sharp, the last one covers SHADOW_DISTANCE.
+///Units cast, terrain and units receive.
+pub struct ShadowMap {
+    layer_views: Vec<TextureView>,
+    array_view: TextureView,
+    sampler: wgpu::Sampler,
+    pub uniform: UniformBuffer<ShadowUniform>,
+    casters: DynamicUniformBuffer<CasterUniform>,
+    caster_offsets: Vec<wgpu::BufferAddress>,
+    caster_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    _texture: wgpu::Texture,
+    _allocation: Allocation,
+}
+
+impl ShadowMap {
+    pub fn new(device: &Device) -> Self {
+        log::trace!("ShadowMap new");
+        let desc = wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            },
+            array_layer_count: CASCADES as u32,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        };
+        let texture = device.create_texture(&desc);
+        let view = |dimension, base_array_layer, array_layer_count| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                dimension,
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer,
+                array_layer_count,
+            })
+        };
+        let layer_views = (0..CASCADES as u32)
+            .map(|layer| view(wgpu::TextureViewDimension::D2, layer, 1))
+            .collect();
+        let array_view = view(wgpu::TextureViewDimension::D2Array, 0, CASCADES as u32);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::LessEqual,
+        });
+
+        let casters = DynamicUniformBuffer::new(device, CASCADES);
+        let caster_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[DynamicUniformBuffer::<CasterUniform>::layout_binding(
+                    0,
+                    wgpu::ShaderStage::VERTEX,
+                )],
+            });
+        let caster_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &caster_bind_group_layout,
+            bindings: &[casters.binding(0)],
+        });
+        let pipeline = Self::create_pipeline(device, &caster_bind_group_layout).unwrap();
+
+        ShadowMap {
+            layer_views,
+            array_view,
+            sampler,
+            uniform: UniformBuffer::new(device, &ShadowUniform::zeroed()),
+            casters,
+            caster_offsets: Vec::new(),
+            caster_bind_group,
+            pipeline,
+            _texture: texture,
+            _allocation: Allocation::texture(&desc),
+        }
+    }
+
+    ///Shadows block, comparison sampler and the cascades, from binding first
+    pub fn layout_bindings(first: u32) -> [wgpu::BindGroupLayoutBinding; 3] {
+        [
+            UniformBuffer::<ShadowUniform>::layout_binding(first, wgpu::ShaderStage::FRAGMENT),
+            wgpu::BindGroupLayoutBinding {
+                binding: first + 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Sampler,
+            },
+            wgpu::BindGroupLayoutBinding {
+                binding: first + 2,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::SampledTexture {
+                    multisampled: false,
+                    dimension: wgpu::TextureViewDimension::D2Array,
+                },
+            },
+        ]
+    }
+
+    pub fn bindings(&self, first: u32) -> [wgpu::Binding; 3] {
+        [
+            self.uniform.binding(first),
+            wgpu::Binding {
+                binding: first + 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::Binding {
+                binding: first + 2,
+                resource: wgpu::BindingResource::TextureView(&self.array_view),
+            },
+        ]
+    }
+
+    ///Fits the cascades to the camera frustum. view is the view matrix of the camera.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        view: &Matrix4<f32>,
+        aspect_ratio: f32,
+        fovy: f32,
+        near: f32,
+    ) {
+        let inv_view = view.try_inverse().unwrap_or_else(Matrix4::identity);
+        let splits = cascade_splits(near, SHADOW_DISTANCE);
+
+        let mut uniform = ShadowUniform::zeroed();
+        self.casters.clear();
+        self.caster_offsets.clear();
+        let mut slice_near = near;
+        for (cascade, &slice_far) in splits.iter().enumerate() {
+            let light_view_proj = fit_cascade(&inv_view, aspect_ratio, fovy, slice_near, slice_far);
+            uniform.light_view_proj[cascade] = light_view_proj.into();
+            uniform.cascade_splits[cascade] = slice_far;
+            if let Some(offset) = self.casters.push(&CasterUniform {
+                light_view_proj: light_view_proj.into(),
+            }) {
+                self.caster_offsets.push(offset);
+            }
+            slice_near = slice_far;
+        }
+        self.uniform.update(device, encoder, belt, &uniform);
+        self.casters.upload(device, encoder, belt);
+    }
+
+    ///One depth pass per cascade, before the passes sampling them
+    pub fn render(&self, encoder: &mut CommandEncoder, model_batch: &ModelBatch) {
+        log::trace!("ShadowMap render");
+        for (view, offset) in self.layer_views.iter().zip(self.caster_offsets.iter()) {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: view,
+                    depth_load_op: wgpu::LoadOp::Clear,
+                    depth_store_op: wgpu::StoreOp::Store,
+                    stencil_load_op: wgpu::LoadOp::Clear,
+                    stencil_store_op: wgpu::StoreOp::Store,
+                    clear_depth: 1.0,
+                    clear_stencil: 0,
+                }),
+            });
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &self.caster_bind_group, &[*offset]);
+            model_batch.render_geometry(&mut rpass);
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        caster_bind_group_layout: &BindGroupLayout,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[caster_bind_group_layout],
+        });
+        let vs_bytes = shaders::load("./src/shader/shadow_caster.vert")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            //Depth only
+            fragment_stage: None,
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 2,
+                depth_bias_slope_scale: 2.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &ModelGpu::vertex_buffers(),
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+}
+
+///View depth where each cascade ends, mixing the logarithmic and uniform splits
+fn cascade_splits(near: f32, far: f32) -> [f32; CASCADES] {
+    let mut splits = [far; CASCADES];
+    for (i, split) in splits.iter_mut().enumerate() {
+        let t = (i + 1) as f32 / CASCADES as f32;
+        let log = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        *split = SPLIT_LAMBDA * log + (1.0 - SPLIT_LAMBDA) * uniform;
+    }
+    splits
+}
+
+///Orthographic projection of the sun covering the bounding sphere of a frustum slice.
+///The sphere keeps its size when the camera turns and its center moves by whole texels,
+///so the shadow edges don't shimmer.
+fn fit_cascade(
+    inv_view: &Matrix4<f32>,
+    aspect_ratio: f32,
+    fovy: f32,
+    slice_near: f32,
+    slice_far: f32,
+) -> Matrix4<f32> {
+    let tan_y = (fovy / 2.0).tan();
+    let tan_x = tan_y * aspect_ratio;
+    let mut corners = Vec::with_capacity(8);
+    for &depth in &[slice_near, slice_far] {
+        for &(x, y) in &[(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            let view_corner = Point3::new(x * tan_x * depth, y * tan_y * depth, -depth);
+            corners.push(inv_view.transform_point(&view_corner));
+        }
+    }
+    let center = corners
+        .iter()
+        .fold(Vector3::zeros(), |sum, corner| sum + corner.coords)
+        / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|corner| (corner.coords - center).magnitude())
+        .fold(0.0_f32, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let sun_dir = Vector3::from(SUN_DIR).normalize();
+    let up = Vector3::new(0.0, 1.0, 0.0);
+    let sun_rotation = Matrix4::look_at_rh(&Point3::origin(), &Point3::from(sun_dir), &up);
+    let texel = 2.0 * radius / SIZE as f32;
+    let mut center_ls = sun_rotation.transform_point(&Point3::from(center));
+    center_ls.x = (center_ls.x / texel).floor() * texel;
+    center_ls.y = (center_ls.y / texel).floor() * texel;
+    let center = sun_rotation
+        .try_inverse()
+        .unwrap()
+        .transform_point(&center_ls);
+
+    let eye = center - sun_dir * (radius + CASTER_MARGIN);
+    let light_view = Matrix4::look_at_rh(&eye, &center, &up);
+    let light_proj = Matrix4::new_orthographic(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        0.0,
+        2.0 * radius + CASTER_MARGIN,
+    );
+    //Depth from [-1, 1] to [0, 1]
+    let mx_correction: Matrix4<f32> = Matrix4::new(
+        1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.0, 0.0, 0.0, 1.0,
+    );
+    mx_correction * light_proj * light_view
+}
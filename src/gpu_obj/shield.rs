@@ -0,0 +1,302 @@
+use super::instance_buffer::InstanceBuffer;
+use super::oit;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use crate::model;
+use na::Point3;
+use wgpu::Device;
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, RenderPass, TextureFormat};
+
+///Must match the array size in shield.frag
+pub const MAX_IMPACTS: usize = 16;
+///Sec during which an impact ripples on the shield
+pub const IMPACT_LIFE_TIME: f32 = 1.0;
+//impact count vec4, then one vec4 position/age per impact
+const UNIFORM_LEN: usize = 4 + MAX_IMPACTS * 4;
+///Floats per shield: center xyz, radius
+const INSTANCE_LEN: usize = 4;
+
+///Translucent bubbles around shielded units, drawn in the transparent pass
+pub struct ShieldGpu {
+    vertex_buf: wgpu::Buffer,
+    index_buf: wgpu::Buffer,
+    index_count: usize,
+    instance_buf: InstanceBuffer,
+    instance_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    ///Writes the targets of the oit pass
+    oit_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    uniform_buf: wgpu::Buffer,
+}
+
+impl ShieldGpu {
+    pub fn new(
+        triangle_list: &model::TriangleList,
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        log::trace!("ShieldGpu new");
+        let model::TriangleList {
+            vertex_data,
+            index_data,
+        } = triangle_list;
+        let vertex_buf = device
+            .create_buffer_mapped(vertex_data.len(), wgpu::BufferUsage::VERTEX)
+            .fill_from_slice(&vertex_data);
+
+        let index_buf = device
+            .create_buffer_mapped(index_data.len(), wgpu::BufferUsage::INDEX)
+            .fill_from_slice(&index_data);
+
+        let instance_buf = InstanceBuffer::new(device);
+
+        let uniform_buf = device
+            .create_buffer_mapped(
+                UNIFORM_LEN,
+                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            )
+            .fill_from_slice(&[0.0_f32; UNIFORM_LEN]);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[wgpu::BindGroupLayoutBinding {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &uniform_buf,
+                    range: 0..(UNIFORM_LEN * 4) as wgpu::BufferAddress,
+                },
+            }],
+        });
+
+        let pipeline = Self::create_pipeline(
+            device,
+            &bind_group_layout,
+            main_bind_group_layout,
+            format,
+            false,
+        )
+        .unwrap();
+        let oit_pipeline = Self::create_pipeline(
+            device,
+            &bind_group_layout,
+            main_bind_group_layout,
+            format,
+            true,
+        )
+        .unwrap();
+
+        ShieldGpu {
+            vertex_buf,
+            index_buf,
+            index_count: index_data.len(),
+            instance_buf,
+            instance_count: 0,
+            pipeline,
+            oit_pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform_buf,
+        }
+    }
+
+    pub fn create_pipeline(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+        oit: bool,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&main_bind_group_layout, &bind_group_layout],
+        });
+        let vertex_size = std::mem::size_of::<model::Vertex>();
+        // Create the render pipeline
+        let vs_bytes = shaders::load("./src/shader/shield.vert")?;
+        let fs_bytes =
+            shaders::load_with_defines("./src/shader/shield.frag", &oit::shader_defines(oit))?;
+
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+        let blended = [wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }];
+        let color_states = if oit {
+            &oit::COLOR_STATES[..]
+        } else {
+            &blended[..]
+        };
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::Back,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states,
+            //Read the depth of the opaque pass so terrain and units cut the bubble,
+            //but don't write it so bubbles don't hide each other
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[
+                wgpu::VertexBufferDescriptor {
+                    stride: vertex_size as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float4,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float3,
+                            offset: 4 * 4,
+                            shader_location: 1,
+                        },
+                        wgpu::VertexAttributeDescriptor {
+                            format: wgpu::VertexFormat::Float2,
+                            offset: 4 * 7,
+                            shader_location: 2,
+                        },
+                    ],
+                },
+                wgpu::VertexBufferDescriptor {
+                    stride: (4 * INSTANCE_LEN) as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Instance,
+                    attributes: &[wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float4,
+                        offset: 0,
+                        shader_location: 3,
+                    }],
+                },
+            ],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///oit in the order-independent transparent pass
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup, oit: bool) {
+        log::trace!("ShieldGpu render");
+        if self.instance_count > 0 {
+            rpass.set_pipeline(if oit {
+                &self.oit_pipeline
+            } else {
+                &self.pipeline
+            });
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.set_bind_group(1, &self.bind_group, &[]);
+            rpass.set_index_buffer(&self.index_buf, 0);
+            rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0), (self.instance_buf.buffer(), 0)]);
+            rpass.draw_indexed(0..self.index_count as u32, 0, 0..self.instance_count);
+        }
+    }
+
+    pub fn update_instance(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        log::trace!("ShieldGpu update_instance");
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
+        self.instance_count = (instance_attr.len() / INSTANCE_LEN) as u32;
+    }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
+
+    ///Impacts are (position, age in sec), only the MAX_IMPACTS most recent are kept
+    pub fn update_impacts(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        impacts: &[(Point3<f32>, f32)],
+    ) {
+        log::trace!("ShieldGpu update_impacts");
+        let mut recent: Vec<&(Point3<f32>, f32)> = impacts
+            .iter()
+            .filter(|(_, age)| *age < IMPACT_LIFE_TIME)
+            .collect();
+        recent.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        recent.truncate(MAX_IMPACTS);
+
+        let mut data = vec![0.0_f32; UNIFORM_LEN];
+        data[0] = recent.len() as f32;
+        data[1] = IMPACT_LIFE_TIME;
+        for (i, (position, age)) in recent.iter().enumerate() {
+            data[4 + i * 4..4 + i * 4 + 4]
+                .copy_from_slice(&[position.x, position.y, position.z, *age]);
+        }
+
+        belt.write_buffer(device, encoder, &self.uniform_buf, 0, &data);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for ShieldGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        let create = |oit| {
+            Self::create_pipeline(
+                device,
+                &self.bind_group_layout,
+                main_bind_group_layout,
+                format,
+                oit,
+            )
+        };
+        match (create(false), create(true)) {
+            (Ok(pipeline), Ok(oit_pipeline)) => {
+                self.pipeline = pipeline;
+                self.oit_pipeline = oit_pipeline;
+            }
+            (Err(x), _) | (_, Err(x)) => log::error!("{}", x),
+        };
+    }
+}
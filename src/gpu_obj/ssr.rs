@@ -0,0 +1,288 @@
+use super::shaders;
+use wgpu::{BindGroup, BindGroupLayout, Device, RenderPass, TextureFormat, TextureView};
+
+///Reflected radiance and the roughness of the reflecting surface, see ssr.frag
+pub const REFLECTION_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+///Screen-space reflections. The trace pass marches the reflected ray of every glossy pixel, and
+///of the water plane in front of them, through the depth of the position G-buffer and shades
+///hits with the last frame. Misses keep the environment cube. The composite pass blurs the
+///reflections of the opaque surfaces by their roughness and adds them, water samples them itself.
+pub struct Ssr {
+    trace_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    trace_bind_group_layout: BindGroupLayout,
+    composite_bind_group_layout: BindGroupLayout,
+    nearest: wgpu::Sampler,
+    linear: wgpu::Sampler,
+    trace_bind_group: BindGroup,
+    composite_bind_group: BindGroup,
+}
+
+impl Ssr {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        position: &TextureView,
+        normal: &TextureView,
+        last_color: &TextureView,
+        reflection: &TextureView,
+    ) -> Self {
+        log::trace!("Ssr new");
+        let texture = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Sampler,
+        };
+        let trace_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[texture(0), texture(1), texture(2), sampler(3), sampler(4)],
+            });
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[texture(0), sampler(1)],
+            });
+
+        let create_sampler = |filter| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                lod_min_clamp: -100.0,
+                lod_max_clamp: 100.0,
+                compare_function: wgpu::CompareFunction::Always,
+            })
+        };
+        //Nearest for the G-buffer, interpolating positions across edges makes false hits
+        let nearest = create_sampler(wgpu::FilterMode::Nearest);
+        let linear = create_sampler(wgpu::FilterMode::Linear);
+
+        let (trace_pipeline, composite_pipeline) = Self::create_pipelines(
+            device,
+            main_bind_group_layout,
+            &trace_bind_group_layout,
+            &composite_bind_group_layout,
+            format,
+        )
+        .unwrap();
+
+        let (trace_bind_group, composite_bind_group) = Self::create_bind_groups(
+            device,
+            &trace_bind_group_layout,
+            &composite_bind_group_layout,
+            &nearest,
+            &linear,
+            position,
+            normal,
+            last_color,
+            reflection,
+        );
+
+        Ssr {
+            trace_pipeline,
+            composite_pipeline,
+            trace_bind_group_layout,
+            composite_bind_group_layout,
+            nearest,
+            linear,
+            trace_bind_group,
+            composite_bind_group,
+        }
+    }
+
+    ///After a resize
+    pub fn update_views(
+        &mut self,
+        device: &Device,
+        position: &TextureView,
+        normal: &TextureView,
+        last_color: &TextureView,
+        reflection: &TextureView,
+    ) {
+        let (trace_bind_group, composite_bind_group) = Self::create_bind_groups(
+            device,
+            &self.trace_bind_group_layout,
+            &self.composite_bind_group_layout,
+            &self.nearest,
+            &self.linear,
+            position,
+            normal,
+            last_color,
+            reflection,
+        );
+        self.trace_bind_group = trace_bind_group;
+        self.composite_bind_group = composite_bind_group;
+    }
+
+    fn create_bind_groups(
+        device: &Device,
+        trace_bind_group_layout: &BindGroupLayout,
+        composite_bind_group_layout: &BindGroupLayout,
+        nearest: &wgpu::Sampler,
+        linear: &wgpu::Sampler,
+        position: &TextureView,
+        normal: &TextureView,
+        last_color: &TextureView,
+        reflection: &TextureView,
+    ) -> (BindGroup, BindGroup) {
+        let texture = |binding, view| wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        };
+        let sampler = |binding, sampler| wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Sampler(sampler),
+        };
+        let trace_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: trace_bind_group_layout,
+            bindings: &[
+                texture(0, position),
+                texture(1, normal),
+                texture(2, last_color),
+                sampler(3, nearest),
+                sampler(4, linear),
+            ],
+        });
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: composite_bind_group_layout,
+            bindings: &[texture(0, reflection), sampler(1, nearest)],
+        });
+        (trace_bind_group, composite_bind_group)
+    }
+
+    fn create_pipelines(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        trace_bind_group_layout: &BindGroupLayout,
+        composite_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<(wgpu::RenderPipeline, wgpu::RenderPipeline)> {
+        let trace = Self::create_pipeline(
+            device,
+            &[main_bind_group_layout, trace_bind_group_layout],
+            "./src/shader/ssr.frag",
+            wgpu::ColorStateDescriptor {
+                format: REFLECTION_FORMAT,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            },
+        )?;
+        //Added to the shaded color, which keeps its alpha
+        let composite = Self::create_pipeline(
+            device,
+            &[composite_bind_group_layout],
+            "./src/shader/ssr_composite.frag",
+            wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                write_mask: wgpu::ColorWrite::ALL,
+            },
+        )?;
+        Ok((trace, composite))
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        bind_group_layouts: &[&BindGroupLayout],
+        fragment: &str,
+        color_state: wgpu::ColorStateDescriptor,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor { bind_group_layouts });
+
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load(fragment)?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[color_state],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the ssr pass
+    pub fn render_trace(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("Ssr render_trace");
+        rpass.set_pipeline(&self.trace_pipeline);
+        rpass.set_bind_group(0, main_bind_group, &[]);
+        rpass.set_bind_group(1, &self.trace_bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+
+    ///In the ssr_composite pass
+    pub fn render_composite(&self, rpass: &mut RenderPass) {
+        log::trace!("Ssr render_composite");
+        rpass.set_pipeline(&self.composite_pipeline);
+        rpass.set_bind_group(0, &self.composite_bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for Ssr {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipelines(
+            device,
+            main_bind_group_layout,
+            &self.trace_bind_group_layout,
+            &self.composite_bind_group_layout,
+            format,
+        ) {
+            Ok((trace_pipeline, composite_pipeline)) => {
+                self.trace_pipeline = trace_pipeline;
+                self.composite_pipeline = composite_pipeline;
+            }
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
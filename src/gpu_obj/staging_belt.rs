@@ -0,0 +1,162 @@
+use super::gpu_memory::{Allocation, Kind};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wgpu::BufferMapAsyncResult;
+
+///In f32, a chunk holds the uploads of several objects
+const CHUNK_LEN: usize = 256 * 1024;
+///In f32, copies start on 16 bytes: enough for buffers and every texture format used
+const ALIGN: usize = 4;
+///Frames a submitted chunk waits before being written again, so that mapping it doesn't stall
+const FRAMES_IN_FLIGHT: usize = 2;
+
+struct Chunk {
+    buf: wgpu::Buffer,
+    ///In f32
+    capacity: usize,
+    ///Written this frame, copied into the mapped buffer by finish
+    data: Vec<f32>,
+    _allocation: Allocation,
+}
+
+///Dynamic uploads (instances, uniforms, texture updates) go through a few recycled buffers
+///instead of a new mapped buffer per upload.
+///Writes record their copy right away, the data reaches the gpu in finish, just before submit.
+pub struct StagingBelt {
+    active: Vec<Chunk>,
+    in_flight: VecDeque<Vec<Chunk>>,
+    free: Vec<Chunk>,
+}
+
+impl StagingBelt {
+    pub fn new() -> Self {
+        StagingBelt {
+            active: Vec::new(),
+            in_flight: VecDeque::new(),
+            free: Vec::new(),
+        }
+    }
+
+    ///Returns the chunk and the offset in f32 where len floats can be written
+    fn allocate(&mut self, device: &wgpu::Device, len: usize) -> (&mut Chunk, usize) {
+        let fits = |chunk: &Chunk| align(chunk.data.len()) + len <= chunk.capacity;
+        let index = match self.active.iter().position(fits) {
+            Some(index) => index,
+            None => {
+                let chunk = match self.free.iter().position(fits) {
+                    Some(index) => self.free.swap_remove(index),
+                    None => {
+                        let capacity = CHUNK_LEN.max(align(len));
+                        log::debug!("StagingBelt new chunk of {} floats", capacity);
+                        Chunk {
+                            buf: device.create_buffer(&wgpu::BufferDescriptor {
+                                size: capacity as u64 * 4,
+                                usage: wgpu::BufferUsage::MAP_WRITE | wgpu::BufferUsage::COPY_SRC,
+                            }),
+                            capacity,
+                            data: Vec::with_capacity(capacity),
+                            _allocation: Allocation::new(Kind::Staging, capacity as u64 * 4),
+                        }
+                    }
+                };
+                self.active.push(chunk);
+                self.active.len() - 1
+            }
+        };
+        let chunk = &mut self.active[index];
+        let offset = align(chunk.data.len());
+        chunk.data.resize(offset, 0.0);
+        (chunk, offset)
+    }
+
+    pub fn write_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        target_offset: wgpu::BufferAddress,
+        data: &[f32],
+    ) {
+        if data.is_empty() {
+            return;
+        }
+        let (chunk, offset) = self.allocate(device, data.len());
+        chunk.data.extend_from_slice(data);
+        encoder.copy_buffer_to_buffer(
+            &chunk.buf,
+            offset as u64 * 4,
+            target,
+            target_offset,
+            data.len() as u64 * 4,
+        );
+    }
+
+    ///Rows of data are tightly packed, as for a BufferCopyView with row_pitch = 4 * width
+    pub fn write_texture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: wgpu::TextureCopyView,
+        extent: wgpu::Extent3d,
+        data: &[f32],
+    ) {
+        if data.is_empty() {
+            return;
+        }
+        let (chunk, offset) = self.allocate(device, data.len());
+        chunk.data.extend_from_slice(data);
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &chunk.buf,
+                offset: offset as u64 * 4,
+                row_pitch: (data.len() as u32 / (extent.height * extent.depth)) * 4,
+                image_height: extent.height,
+            },
+            target,
+            extent,
+        );
+    }
+
+    ///Call before submitting the encoders given to the writes of this frame
+    pub fn finish(&mut self, device: &wgpu::Device) {
+        let mut written = Vec::new();
+        for chunk in self.active.iter_mut() {
+            let data = std::mem::replace(&mut chunk.data, Vec::new());
+            let done = Arc::new(AtomicBool::new(false));
+            let done_cb = done.clone();
+            chunk.buf.map_write_async(
+                0,
+                data.len() as u64 * 4,
+                move |e: BufferMapAsyncResult<&mut [f32]>| {
+                    if let Ok(e) = e {
+                        e.data.copy_from_slice(&data);
+                        done_cb.store(true, Ordering::Release);
+                    }
+                },
+            );
+            written.push(done);
+        }
+
+        device.poll(false);
+        if !written.iter().all(|done| done.load(Ordering::Acquire)) {
+            log::debug!("StagingBelt waiting for the gpu");
+            device.poll(true);
+        }
+        for chunk in self.active.iter_mut() {
+            chunk.buf.unmap();
+        }
+
+        self.in_flight
+            .push_back(std::mem::replace(&mut self.active, Vec::new()));
+        while self.in_flight.len() > FRAMES_IN_FLIGHT {
+            if let Some(chunks) = self.in_flight.pop_front() {
+                self.free.extend(chunks);
+            }
+        }
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + ALIGN - 1) / ALIGN * ALIGN
+}
@@ -0,0 +1,206 @@
+use super::shaders;
+use wgpu::{BindGroup, BindGroupLayout, Device, RenderPass, TextureFormat, TextureView};
+
+///Uv offset of each pixel since the last frame, written by the opaque pipelines of the main pass
+pub const VELOCITY_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+///Temporal anti-aliasing: the opaque geometry is jittered inside the pixel every frame (see
+///camera::taa_jitter) and the resolve blends the frame with the history reprojected through the
+///motion vectors. The history is clamped to the colors around the pixel so that it doesn't ghost.
+pub struct Taa {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    linear: wgpu::Sampler,
+    nearest: wgpu::Sampler,
+    bind_group: BindGroup,
+}
+
+impl Taa {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+        color: &TextureView,
+        velocity: &TextureView,
+        history: &TextureView,
+    ) -> Self {
+        log::trace!("Taa new");
+        let texture = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2,
+            },
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::Sampler,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[texture(0), texture(1), texture(2), sampler(3), sampler(4)],
+        });
+
+        let create_sampler = |filter| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                lod_min_clamp: -100.0,
+                lod_max_clamp: 100.0,
+                compare_function: wgpu::CompareFunction::Always,
+            })
+        };
+        let linear = create_sampler(wgpu::FilterMode::Linear);
+        let nearest = create_sampler(wgpu::FilterMode::Nearest);
+
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &linear,
+            &nearest,
+            color,
+            velocity,
+            history,
+        );
+        let pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, &bind_group_layout, format)
+                .unwrap();
+        Taa {
+            pipeline,
+            bind_group_layout,
+            linear,
+            nearest,
+            bind_group,
+        }
+    }
+
+    ///After a resize, or when the color to resolve changes
+    pub fn update_views(
+        &mut self,
+        device: &Device,
+        color: &TextureView,
+        velocity: &TextureView,
+        history: &TextureView,
+    ) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.linear,
+            &self.nearest,
+            color,
+            velocity,
+            history,
+        );
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        linear: &wgpu::Sampler,
+        nearest: &wgpu::Sampler,
+        color: &TextureView,
+        velocity: &TextureView,
+        history: &TextureView,
+    ) -> BindGroup {
+        let texture = |binding, view| wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::TextureView(view),
+        };
+        let sampler = |binding, sampler| wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Sampler(sampler),
+        };
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                texture(0, color),
+                texture(1, velocity),
+                texture(2, history),
+                sampler(3, linear),
+                sampler(4, nearest),
+            ],
+        })
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[main_bind_group_layout, bind_group_layout],
+        });
+
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/taa.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the taa pass
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("Taa render");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, main_bind_group, &[]);
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for Taa {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            main_bind_group_layout,
+            &self.bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
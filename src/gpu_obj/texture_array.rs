@@ -0,0 +1,165 @@
+use super::gpu_memory::Allocation;
+use super::mipmap::{self, MipGenerator};
+use super::staging_belt::StagingBelt;
+use crate::utils::ImageRGBA8;
+use wgpu::{TextureFormat, TextureView};
+
+///Square rgba8 layers of the same size in one texture2d_array, sampled with a layer index.
+///Its bind group never changes: the capacity is fixed at creation.
+///Each layer has a full mip chain, generated after its level 0 is written.
+pub struct TextureArray {
+    texture: wgpu::Texture,
+    view: TextureView,
+    format: TextureFormat,
+    size: u32,
+    layers: u32,
+    levels: u32,
+    _allocation: Allocation,
+}
+
+impl TextureArray {
+    pub fn new(device: &wgpu::Device, format: TextureFormat, size: u32, layers: u32) -> Self {
+        log::trace!("TextureArray new {}x{}x{}", size, size, layers);
+        let levels = mipmap::level_count(size, size);
+        let desc = wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth: 1,
+            },
+            array_layer_count: layers,
+            mip_level_count: levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::SAMPLED
+                | wgpu::TextureUsage::COPY_DST
+                | wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+        };
+        let texture = device.create_texture(&desc);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format,
+            dimension: wgpu::TextureViewDimension::D2Array,
+            aspect: wgpu::TextureAspect::All,
+            base_mip_level: 0,
+            level_count: levels,
+            base_array_layer: 0,
+            array_layer_count: layers,
+        });
+        TextureArray {
+            texture,
+            view,
+            format,
+            size,
+            layers,
+            levels,
+            _allocation: Allocation::texture(&desc),
+        }
+    }
+
+    pub fn layer_binding(binding: u32) -> wgpu::BindGroupLayoutBinding {
+        wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                multisampled: false,
+                dimension: wgpu::TextureViewDimension::D2Array,
+            },
+        }
+    }
+
+    pub fn view(&self) -> &TextureView {
+        &self.view
+    }
+
+    ///Fills layer with one rgba color
+    pub fn write_color(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        layer: u32,
+        rgba: [u8; 4],
+    ) {
+        let texel = f32::from_bits(u32::from_ne_bytes(rgba));
+        let data = vec![texel; (self.size * self.size) as usize];
+        self.write(device, encoder, belt, layer, &data);
+    }
+
+    ///Err if img isn't rgba8 of the size of the layers
+    pub fn write_image(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        layer: u32,
+        img: &ImageRGBA8,
+    ) -> Result<(), String> {
+        if img.w != self.size || img.h != self.size {
+            return Err(format!(
+                "{}x{} image, layers are {}x{}",
+                img.w, img.h, self.size, self.size
+            ));
+        }
+        if img.data.len() != (img.w * img.h * 4) as usize {
+            return Err("image is not rgba8".to_owned());
+        }
+        //The belt uploads f32: each texel is carried as the bits of one
+        let data: Vec<f32> = img
+            .data
+            .chunks_exact(4)
+            .map(|p| f32::from_bits(u32::from_ne_bytes([p[0], p[1], p[2], p[3]])))
+            .collect();
+        self.write(device, encoder, belt, layer, &data);
+        Ok(())
+    }
+
+    ///Call after writing layer
+    pub fn generate_mips(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        mips: &mut MipGenerator,
+        layer: u32,
+    ) {
+        mips.generate(
+            device,
+            encoder,
+            &self.texture,
+            self.format,
+            self.levels,
+            layer,
+        );
+    }
+
+    fn write(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        layer: u32,
+        data: &[f32],
+    ) {
+        assert!(layer < self.layers);
+        belt.write_texture(
+            device,
+            encoder,
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                array_layer: layer,
+                origin: wgpu::Origin3d {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size,
+                height: self.size,
+                depth: 1,
+            },
+            data,
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use super::oit;
+use super::shaders;
+use wgpu::Device;
+use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat};
+
+///Floats per vertex: position xyz, along the trail, alpha
+pub const VERTEX_LEN: usize = 5;
+
+///Ribbons behind missiles and fast units, rebuilt on the cpu every frame into one vertex buffer
+pub struct TrailGpu {
+    vertex_buf: wgpu::Buffer,
+    vertex_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    ///Writes the targets of the oit pass
+    oit_pipeline: wgpu::RenderPipeline,
+}
+
+impl TrailGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        log::trace!("TrailGpu new");
+
+        let positions: Vec<f32> = Vec::new();
+
+        let vertex_buf = device
+            .create_buffer_mapped(
+                positions.len(),
+                wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            )
+            .fill_from_slice(&positions);
+
+        let pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, format, false).unwrap();
+        let oit_pipeline =
+            Self::create_pipeline(device, main_bind_group_layout, format, true).unwrap();
+
+        TrailGpu {
+            vertex_buf,
+            vertex_count: 0,
+            pipeline,
+            oit_pipeline,
+        }
+    }
+
+    pub fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+        oit: bool,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&main_bind_group_layout],
+        });
+        // Create the render pipeline
+        let vs_bytes = shaders::load("./src/shader/trail.vert")?;
+        let fs_bytes =
+            shaders::load_with_defines("./src/shader/trail.frag", &oit::shader_defines(oit))?;
+
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+        let blended = [wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }];
+        let color_states = if oit {
+            &oit::COLOR_STATES[..]
+        } else {
+            &blended[..]
+        };
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states,
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: (4 * VERTEX_LEN) as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Vertex,
+                attributes: &[
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float3,
+                        offset: 0,
+                        shader_location: 0,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float,
+                        offset: 4 * 3,
+                        shader_location: 1,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float,
+                        offset: 4 * 4,
+                        shader_location: 2,
+                    },
+                ],
+            }],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///oit in the order-independent transparent pass
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup, oit: bool) {
+        log::trace!("TrailGpu render");
+        if self.vertex_count > 0 {
+            rpass.set_pipeline(if oit {
+                &self.oit_pipeline
+            } else {
+                &self.pipeline
+            });
+            rpass.set_vertex_buffers(0, &[(&self.vertex_buf, 0)]);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.draw(0..self.vertex_count, 0..1);
+        }
+    }
+
+    pub fn update_vertices(&mut self, vertex_attr: &[f32], device: &wgpu::Device) {
+        log::trace!("TrailGpu update_vertices");
+        let temp_buf = device
+            .create_buffer_mapped(vertex_attr.len(), wgpu::BufferUsage::VERTEX)
+            .fill_from_slice(vertex_attr);
+
+        self.vertex_buf = temp_buf;
+        self.vertex_count = (vertex_attr.len() / VERTEX_LEN) as u32;
+    }
+}
+
+impl super::trait_gpu::TraitGpu for TrailGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        let create = |oit| Self::create_pipeline(device, main_bind_group_layout, format, oit);
+        match (create(false), create(true)) {
+            (Ok(pipeline), Ok(oit_pipeline)) => {
+                self.pipeline = pipeline;
+                self.oit_pipeline = oit_pipeline;
+            }
+            (Err(x), _) | (_, Err(x)) => log::error!("{}", x),
+        };
+    }
+}
@@ -0,0 +1,161 @@
+use super::staging_belt::StagingBelt;
+use bytemuck::Pod;
+use std::marker::PhantomData;
+
+///Uniform block holding a T. T is #[repr(C)] and already laid out as std140:
+///f32 members, vec2 as [f32; 2] on 8 bytes, vec4 as [f32; 4] and mat4 as [[f32; 4]; 4] on 16 bytes,
+///explicit padding up to a multiple of 16 bytes.
+pub struct UniformBuffer<T: Pod> {
+    buf: wgpu::Buffer,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> UniformBuffer<T> {
+    pub fn new(device: &wgpu::Device, value: &T) -> Self {
+        assert_eq!(
+            std::mem::size_of::<T>() % 16,
+            0,
+            "{} is not padded to 16 bytes",
+            std::any::type_name::<T>()
+        );
+        let data = Self::as_floats(value);
+        let buf = device
+            .create_buffer_mapped(
+                data.len(),
+                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            )
+            .fill_from_slice(data);
+        UniformBuffer {
+            buf,
+            _marker: PhantomData,
+        }
+    }
+
+    fn as_floats(value: &T) -> &[f32] {
+        bytemuck::cast_slice(std::slice::from_ref(value))
+    }
+
+    pub fn size() -> wgpu::BufferAddress {
+        std::mem::size_of::<T>() as wgpu::BufferAddress
+    }
+
+    pub fn update(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        value: &T,
+    ) {
+        belt.write_buffer(device, encoder, &self.buf, 0, Self::as_floats(value));
+    }
+
+    pub fn layout_binding(
+        binding: u32,
+        visibility: wgpu::ShaderStage,
+    ) -> wgpu::BindGroupLayoutBinding {
+        wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+        }
+    }
+
+    pub fn binding(&self, binding: u32) -> wgpu::Binding {
+        wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Buffer {
+                buffer: &self.buf,
+                range: 0..Self::size(),
+            },
+        }
+    }
+}
+
+///Minimum dynamic offset alignment allowed by the backends
+const DYNAMIC_OFFSET_ALIGN: usize = 256;
+
+///One buffer holding a T per object drawn, each draw binds the same bind group with the offset
+///returned by push, instead of every object owning a buffer and a bind group.
+pub struct DynamicUniformBuffer<T: Pod> {
+    buf: wgpu::Buffer,
+    ///In f32, values are DYNAMIC_OFFSET_ALIGN apart
+    data: Vec<f32>,
+    capacity: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> DynamicUniformBuffer<T> {
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        assert_eq!(
+            std::mem::size_of::<T>() % 16,
+            0,
+            "{} is not padded to 16 bytes",
+            std::any::type_name::<T>()
+        );
+        let buf = device.create_buffer(&wgpu::BufferDescriptor {
+            size: (capacity * Self::stride()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        DynamicUniformBuffer {
+            buf,
+            data: Vec::with_capacity(capacity * Self::stride() / 4),
+            capacity,
+            _marker: PhantomData,
+        }
+    }
+
+    ///In bytes
+    fn stride() -> usize {
+        let size = std::mem::size_of::<T>();
+        (size + DYNAMIC_OFFSET_ALIGN - 1) / DYNAMIC_OFFSET_ALIGN * DYNAMIC_OFFSET_ALIGN
+    }
+
+    ///Forget the values pushed for the previous frame
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    ///Returns the dynamic offset to give to set_bind_group, None when full
+    pub fn push(&mut self, value: &T) -> Option<wgpu::BufferAddress> {
+        let index = self.data.len() * 4 / Self::stride();
+        if index >= self.capacity {
+            return None;
+        }
+        self.data
+            .extend_from_slice(bytemuck::cast_slice(std::slice::from_ref(value)));
+        self.data.resize((index + 1) * Self::stride() / 4, 0.0);
+        Some((index * Self::stride()) as wgpu::BufferAddress)
+    }
+
+    ///Uploads every value pushed since clear
+    pub fn upload(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        belt.write_buffer(device, encoder, &self.buf, 0, &self.data);
+    }
+
+    pub fn layout_binding(
+        binding: u32,
+        visibility: wgpu::ShaderStage,
+    ) -> wgpu::BindGroupLayoutBinding {
+        wgpu::BindGroupLayoutBinding {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::UniformBuffer { dynamic: true },
+        }
+    }
+
+    ///The range covers one T, the dynamic offset selects which
+    pub fn binding(&self, binding: u32) -> wgpu::Binding {
+        wgpu::Binding {
+            binding,
+            resource: wgpu::BindingResource::Buffer {
+                buffer: &self.buf,
+                range: 0..std::mem::size_of::<T>() as wgpu::BufferAddress,
+            },
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use super::uniform_buffer::UniformBuffer;
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, Device, RenderPass};
+use wgpu::{TextureFormat, TextureView};
+
+///Filter from the scene resolution to the window one
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Upscale {
+    Bilinear,
+    ///Bilinear then contrast adaptive sharpening, recovers the edges softened by a low scale
+    Sharpen,
+}
+
+impl Upscale {
+    pub const ALL: [Upscale; 2] = [Upscale::Bilinear, Upscale::Sharpen];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Upscale::Bilinear => "bilinear",
+            Upscale::Sharpen => "sharpen",
+        }
+    }
+
+    ///Weight of the negative lobe in upscale.frag, 0 for a plain bilinear filter
+    fn sharpness(self) -> f32 {
+        match self {
+            Upscale::Bilinear => 0.0,
+            Upscale::Sharpen => 0.8,
+        }
+    }
+}
+
+///Upscale block of upscale.frag
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct UpscaleUniform {
+    sharpness: f32,
+    _padding: [f32; 3],
+}
+
+///Resamples the graded scene to the window size, under the ui
+pub struct UpscaleGpu {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: BindGroup,
+    uniform: UniformBuffer<UpscaleUniform>,
+    ///Uploaded to the uniform, None before the first update
+    mode: Option<Upscale>,
+}
+
+impl UpscaleGpu {
+    pub fn new(device: &Device, format: TextureFormat, color: &TextureView) -> Self {
+        log::trace!("UpscaleGpu new");
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutBinding {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                    },
+                },
+                wgpu::BindGroupLayoutBinding {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler,
+                },
+                UniformBuffer::<UpscaleUniform>::layout_binding(2, wgpu::ShaderStage::FRAGMENT),
+            ],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare_function: wgpu::CompareFunction::Always,
+        });
+
+        let uniform = UniformBuffer::new(device, &UpscaleUniform::zeroed());
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &sampler, &uniform, color);
+        let pipeline = Self::create_pipeline(device, &bind_group_layout, format).unwrap();
+
+        UpscaleGpu {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            bind_group,
+            uniform,
+            mode: None,
+        }
+    }
+
+    ///After a resize
+    pub fn update_views(&mut self, device: &Device, color: &TextureView) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.uniform,
+            color,
+        );
+    }
+
+    ///Uploads the filter when it changed
+    pub fn update(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        mode: Upscale,
+    ) {
+        if self.mode == Some(mode) {
+            return;
+        }
+        let data = UpscaleUniform {
+            sharpness: mode.sharpness(),
+            _padding: [0.0; 3],
+        };
+        self.uniform.update(device, encoder, belt, &data);
+        self.mode = Some(mode);
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform: &UniformBuffer<UpscaleUniform>,
+        color: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(color),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                uniform.binding(2),
+            ],
+        })
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[bind_group_layout],
+        });
+
+        let vs_bytes = shaders::load("./src/shader/post.vert")?;
+        let fs_bytes = shaders::load("./src/shader/upscale.frag")?;
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor::REPLACE,
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: None,
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    ///In the upscale pass
+    pub fn render(&self, rpass: &mut RenderPass) {
+        log::trace!("UpscaleGpu render");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        rpass.draw(0..4, 0..1);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for UpscaleGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        _main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(device, &self.bind_group_layout, format) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
@@ -1,4 +1,4 @@
-use super::glsl_compiler;
+use super::shaders;
 use crate::model;
 use wgpu::Device;
 use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat, TextureView};
@@ -14,8 +14,7 @@ impl WaterGpu {
         device: &Device,
         format: TextureFormat,
         main_bind_group_layout: &BindGroupLayout,
-        last_pass_view: &TextureView,
-        current_position_att: &TextureView,
+        reflection: &TextureView,
     ) -> Self {
         log::trace!("WaterGpu new");
 
@@ -34,28 +33,10 @@ impl WaterGpu {
                     visibility: wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::Sampler,
                 },
-                wgpu::BindGroupLayoutBinding {
-                    binding: 2,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::SampledTexture {
-                        multisampled: false,
-                        dimension: wgpu::TextureViewDimension::D2,
-                    },
-                },
-                wgpu::BindGroupLayoutBinding {
-                    binding: 3,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler,
-                },
             ],
         });
 
-        let bind_group = Self::create_bind_group(
-            device,
-            &bind_group_layout,
-            last_pass_view,
-            current_position_att,
-        );
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, reflection);
 
         let pipeline =
             Self::create_pipeline(device, &bind_group_layout, main_bind_group_layout, format)
@@ -67,25 +48,14 @@ impl WaterGpu {
         }
     }
 
-    pub fn update_bind_group(
-        &mut self,
-        device: &Device,
-        last_pass_view: &TextureView,
-        current_position_att: &TextureView,
-    ) {
-        self.bind_group = Self::create_bind_group(
-            device,
-            &self.bind_group_layout,
-            last_pass_view,
-            current_position_att,
-        );
+    pub fn update_bind_group(&mut self, device: &Device, reflection: &TextureView) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, reflection);
     }
 
     pub fn create_bind_group(
         device: &Device,
         bind_group_layout: &BindGroupLayout,
-        last_pass_view: &TextureView,
-        current_position_att: &TextureView,
+        reflection: &TextureView,
     ) -> BindGroup {
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -99,37 +69,17 @@ impl WaterGpu {
             compare_function: wgpu::CompareFunction::Always,
         });
 
-        let sampler_pos_att = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare_function: wgpu::CompareFunction::Always,
-        });
-
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: bind_group_layout,
             bindings: &[
                 wgpu::Binding {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(last_pass_view),
+                    resource: wgpu::BindingResource::TextureView(reflection),
                 },
                 wgpu::Binding {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&sampler),
                 },
-                wgpu::Binding {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(current_position_att),
-                },
-                wgpu::Binding {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&sampler_pos_att),
-                },
             ],
         })
     }
@@ -139,14 +89,14 @@ impl WaterGpu {
         bind_group_layout: &BindGroupLayout,
         main_bind_group_layout: &BindGroupLayout,
         format: TextureFormat,
-    ) -> glsl_compiler::Result<wgpu::RenderPipeline> {
+    ) -> shaders::Result<wgpu::RenderPipeline> {
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[&main_bind_group_layout, &bind_group_layout],
         });
         let vertex_size = std::mem::size_of::<model::Vertex>();
         // Create the render pipeline
-        let vs_bytes = glsl_compiler::load("./src/shader/water.vert")?;
-        let fs_bytes = glsl_compiler::load("./src/shader/water.frag")?;
+        let vs_bytes = shaders::load("./src/shader/water.vert")?;
+        let fs_bytes = shaders::load("./src/shader/water.frag")?;
 
         let vs_module = device.create_shader_module(&vs_bytes);
         let fs_module = device.create_shader_module(&fs_bytes);
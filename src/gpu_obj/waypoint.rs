@@ -0,0 +1,214 @@
+use super::instance_buffer::InstanceBuffer;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use crate::heightmap_phy::{HeightmapPhy, WATER_LEVEL};
+use na::Point3;
+use wgpu::Device;
+use wgpu::{BindGroup, BindGroupLayout, RenderPass, TextureFormat};
+
+///Floats per piece: start xyz, end xyz, distance along the path at start, kind.
+///Markers have the same start and end.
+pub const INSTANCE_LEN: usize = 8;
+///m, paths are cut in pieces this long at most, each following the terrain
+pub const PIECE_LEN: f32 = 2.0;
+///Pieces of a leg of the path at most, long legs have longer pieces
+const MAX_PIECES: f32 = 64.0;
+///Height of the pieces over the ground
+const OVER_GROUND: f32 = 0.3;
+
+///What a waypoint marker shows, its color in waypoint.frag
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WaypointKind {
+    Move,
+    Unload,
+    Patrol,
+    Ferry,
+}
+
+impl WaypointKind {
+    fn value(self) -> f32 {
+        match self {
+            WaypointKind::Move => 0.0,
+            WaypointKind::Unload => 1.0,
+            WaypointKind::Patrol => 2.0,
+            WaypointKind::Ferry => 3.0,
+        }
+    }
+}
+
+///Waypoints of the orders as markers on the ground, linked by animated dashed lines following
+///the terrain
+pub struct WaypointGpu {
+    instance_buf: InstanceBuffer,
+    instance_count: u32,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl WaypointGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        log::trace!("WaypointGpu new");
+
+        let instance_buf = InstanceBuffer::new(device);
+
+        let pipeline = Self::create_pipeline(device, main_bind_group_layout, format).unwrap();
+
+        WaypointGpu {
+            instance_buf,
+            instance_count: 0,
+            pipeline,
+        }
+    }
+
+    pub fn create_pipeline(
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&main_bind_group_layout],
+        });
+        // Create the render pipeline
+        let vs_bytes = shaders::load("./src/shader/waypoint.vert")?;
+        let fs_bytes = shaders::load("./src/shader/waypoint.frag")?;
+
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: (4 * INSTANCE_LEN) as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &[
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float3,
+                        offset: 0,
+                        shader_location: 0,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float3,
+                        offset: 4 * 3,
+                        shader_location: 1,
+                    },
+                    wgpu::VertexAttributeDescriptor {
+                        format: wgpu::VertexFormat::Float2,
+                        offset: 4 * 6,
+                        shader_location: 2,
+                    },
+                ],
+            }],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("WaypointGpu render");
+        if self.instance_count > 0 {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_vertex_buffers(0, &[(self.instance_buf.buffer(), 0)]);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.draw(0..4, 0..self.instance_count);
+        }
+    }
+
+    pub fn update_instance(
+        &mut self,
+        instance_attr: &[f32],
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        log::trace!("WaypointGpu update_instance");
+        self.instance_buf
+            .update(instance_attr, device, encoder, belt);
+        self.instance_count = (instance_attr.len() / INSTANCE_LEN) as u32;
+    }
+
+    pub fn clear_instance(&mut self) {
+        self.instance_count = 0;
+    }
+
+    ///Appends to out the pieces from the unit along the waypoints, and a marker on each
+    pub fn push_path(
+        out: &mut Vec<f32>,
+        phy: &HeightmapPhy,
+        start: &Point3<f32>,
+        waypoints: &[(Point3<f32>, WaypointKind)],
+    ) {
+        let ground =
+            |x: f32, y: f32| Point3::new(x, y, phy.z_linear(x, y).max(WATER_LEVEL) + OVER_GROUND);
+        let mut from = ground(start.x, start.y);
+        let mut dist = 0.0;
+        for (waypoint, kind) in waypoints {
+            let to = ground(waypoint.x, waypoint.y);
+            let len = (to.xy() - from.xy()).magnitude();
+            let pieces = (len / PIECE_LEN).ceil().max(1.0).min(MAX_PIECES);
+            let mut a = from;
+            for i in 1..=pieces as usize {
+                let t = i as f32 / pieces;
+                let b = ground(from.x + (to.x - from.x) * t, from.y + (to.y - from.y) * t);
+                out.extend_from_slice(&[a.x, a.y, a.z, b.x, b.y, b.z, dist, -1.0]);
+                dist += (b.coords - a.coords).magnitude();
+                a = b;
+            }
+            out.extend_from_slice(&[to.x, to.y, to.z, to.x, to.y, to.z, 0.0, kind.value()]);
+            from = to;
+        }
+    }
+}
+
+impl super::trait_gpu::TraitGpu for WaypointGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(device, main_bind_group_layout, format) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
@@ -0,0 +1,199 @@
+use super::shader_reflect;
+use super::shaders;
+use super::staging_belt::StagingBelt;
+use crate::weather::{Weather, WeatherKind};
+use na::Point3;
+use wgpu::Device;
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, RenderPass, TextureFormat};
+
+///Side of the cube around the camera where particles live, in meters
+const BOX_SIZE: f32 = 120.0;
+const UNIFORM_LEN: usize = 12;
+
+pub struct WeatherGpu {
+    instance_count: u32,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    bind_group: BindGroup,
+    uniform_buf: wgpu::Buffer,
+}
+
+impl WeatherGpu {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        main_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        log::trace!("WeatherGpu new");
+
+        let uniform_buf = device
+            .create_buffer_mapped(
+                UNIFORM_LEN,
+                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            )
+            .fill_from_slice(&[0.0_f32; UNIFORM_LEN]);
+
+        //Set 1 is declared by the shaders only
+        let vs_bytes = shaders::load("./src/shader/weather.vert").unwrap();
+        let fs_bytes = shaders::load("./src/shader/weather.frag").unwrap();
+        let bind_group_layout = shader_reflect::create_bind_group_layout(
+            device,
+            1,
+            &[
+                (&vs_bytes, wgpu::ShaderStage::VERTEX),
+                (&fs_bytes, wgpu::ShaderStage::FRAGMENT),
+            ],
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &uniform_buf,
+                    range: 0..(UNIFORM_LEN * 4) as wgpu::BufferAddress,
+                },
+            }],
+        });
+
+        let pipeline =
+            Self::create_pipeline(device, &bind_group_layout, main_bind_group_layout, format)
+                .unwrap();
+
+        WeatherGpu {
+            instance_count: 0,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            uniform_buf,
+        }
+    }
+
+    pub fn create_pipeline(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> shaders::Result<wgpu::RenderPipeline> {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&main_bind_group_layout, &bind_group_layout],
+        });
+        // Create the render pipeline
+        let vs_bytes = shaders::load("./src/shader/weather.vert")?;
+        let fs_bytes = shaders::load("./src/shader/weather.frag")?;
+
+        let vs_module = device.create_shader_module(&vs_bytes);
+        let fs_module = device.create_shader_module(&fs_bytes);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &pipeline_layout,
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: wgpu::BlendDescriptor {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+                stencil_read_mask: 0,
+                stencil_write_mask: 0,
+            }),
+            index_format: wgpu::IndexFormat::Uint32,
+            vertex_buffers: &[],
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+        Ok(pipeline)
+    }
+
+    pub fn render(&self, rpass: &mut RenderPass, main_bind_group: &BindGroup) {
+        log::trace!("WeatherGpu render");
+        if self.instance_count > 0 {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, main_bind_group, &[]);
+            rpass.set_bind_group(1, &self.bind_group, &[]);
+            rpass.draw(0..4, 0..self.instance_count);
+        }
+    }
+
+    ///Particles are not simulated on the cpu: their position is a function of
+    ///the instance index, the time, and the wind, wrapped in a box following the camera.
+    pub fn update_uniform(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        belt: &mut StagingBelt,
+        camera_pos: &Point3<f32>,
+        time_sec: f32,
+        weather: &Weather,
+    ) {
+        log::trace!("WeatherGpu update_uniform");
+        self.instance_count = weather.particle_count();
+
+        let kind = match weather.kind {
+            WeatherKind::Clear => 0.0,
+            WeatherKind::Rain => 1.0,
+            WeatherKind::Snow => 2.0,
+        };
+
+        let data: [f32; UNIFORM_LEN] = [
+            camera_pos.x,
+            camera_pos.y,
+            camera_pos.z,
+            time_sec,
+            weather.wind.x,
+            weather.wind.y,
+            weather.fall_speed(),
+            BOX_SIZE,
+            kind,
+            weather.intensity,
+            0.0,
+            0.0,
+        ];
+
+        belt.write_buffer(device, encoder, &self.uniform_buf, 0, &data);
+    }
+}
+
+impl super::trait_gpu::TraitGpu for WeatherGpu {
+    fn reload_shader(
+        &mut self,
+        device: &Device,
+        main_bind_group_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) {
+        match Self::create_pipeline(
+            device,
+            &self.bind_group_layout,
+            main_bind_group_layout,
+            format,
+        ) {
+            Ok(pipeline) => self.pipeline = pipeline,
+            Err(x) => log::error!("{}", x),
+        };
+    }
+}
@@ -1,9 +1,21 @@
+use crate::sky;
+use crate::weather;
 use na::Vector3;
 use serde::{Deserialize, Serialize};
 
+///Height of the sea, must match water.vert
+pub const WATER_LEVEL: f32 = 40.0;
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Data {
     pub metal_spots: Vec<MetalSpot>,
+    #[serde(default)]
+    pub weather: weather::Weather,
+    #[serde(default)]
+    pub sky: sky::Sky,
+    ///Color grading LUT png, see color_grading.rs
+    #[serde(default)]
+    pub lut: Option<String>,
 }
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct HeightmapPhy {
@@ -14,9 +26,14 @@ pub struct HeightmapPhy {
 }
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct MetalSpot {
-    metal_per_frame: f32,
-    x: usize,
-    y: usize,
+    pub metal_per_frame: f32,
+    pub x: usize,
+    pub y: usize,
+}
+
+impl MetalSpot {
+    ///m, an extractor built this close to the spot gets its metal
+    pub const EXTRACTION_RADIUS: f32 = 24.0;
 }
 
 trait HeightMapPhyUsize {
@@ -43,10 +60,48 @@ impl HeightmapPhy {
             height,
             data: Data {
                 metal_spots: Vec::new(),
+                weather: weather::Weather::clear(),
+                sky: sky::Sky::day(),
+                lut: None,
             },
         }
     }
 
+    ///Reads height.png (16 bits) and data.json from a map folder
+    pub fn load(&mut self, path: &str) {
+        use byteorder::{BigEndian, ReadBytesExt};
+        use std::fs::File;
+        use std::io::Cursor;
+        let height_path = format!("{}/height.png", path);
+        let mut decoder = png::Decoder::new(File::open(&height_path).unwrap());
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        log::debug!("info: {:?}", info.width);
+        log::debug!("height: {:?}", info.height);
+        log::debug!("bit depth: {:?}", info.bit_depth);
+        log::debug!("buffer size: {:?}", info.buffer_size());
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        // Transform buffer into 16 bits slice.
+        let mut buffer_u16 = vec![0; (info.width * info.height) as usize];
+        let mut buffer_cursor = Cursor::new(buf);
+        buffer_cursor
+            .read_u16_into::<BigEndian>(&mut buffer_u16)
+            .unwrap();
+
+        for i in 0..self.width * self.height {
+            self.texels[i as usize] = buffer_u16[i as usize] as f32 / (65535.0 / 511.0);
+        }
+
+        let json_path = format!("{}/data.json", path);
+        if let Ok(file) = File::open(&json_path) {
+            match serde_json::from_reader(std::io::BufReader::new(file)) {
+                Ok(data) => self.data = data,
+                Err(e) => log::error!("Could not read {}: {}", json_path, e),
+            }
+        }
+    }
+
     ///unsafe nearest interpolation
     #[inline]
     pub fn z(&self, x: f32, y: f32) -> f32 {
@@ -85,6 +140,48 @@ impl HeightmapPhy {
         z
     }
 
+    pub fn is_water(&self, x: f32, y: f32) -> bool {
+        self.z_linear(x, y) < WATER_LEVEL
+    }
+
+    ///Both water and land can be found within radius of (x, y)
+    pub fn is_shoreline(&self, x: f32, y: f32, radius: f32) -> bool {
+        let mut water = self.is_water(x, y);
+        let mut land = !water;
+        for i in 0..8 {
+            let angle = i as f32 * std::f32::consts::PI / 4.0;
+            if self.is_water(x + angle.cos() * radius, y + angle.sin() * radius) {
+                water = true;
+            } else {
+                land = true;
+            }
+        }
+        water && land
+    }
+
+    ///Dig a bowl shaped crater, returns the modified rect (min_x, min_y, width, height)
+    pub fn crater(&mut self, x: f32, y: f32, radius: f32, depth: f32) -> (u32, u32, u32, u32) {
+        let min_x = (x - radius).max(0.0) as usize;
+        let min_y = (y - radius).max(0.0) as usize;
+        let max_x = ((x + radius).ceil() as usize).min(self.width - 1);
+        let max_y = ((y + radius).ceil() as usize).min(self.height - 1);
+        for j in min_y..=max_y {
+            for i in min_x..=max_x {
+                let d = ((i as f32 - x).powi(2) + (j as f32 - y).powi(2)).sqrt() / radius;
+                if d < 1.0 {
+                    let index = i + j * self.width;
+                    self.texels[index] = (self.texels[index] - depth * (1.0 - d * d)).max(0.0);
+                }
+            }
+        }
+        (
+            min_x as u32,
+            min_y as u32,
+            (max_x - min_x) as u32,
+            (max_y - min_y) as u32,
+        )
+    }
+
     ///safe normal interpolation
     pub fn normal(&self, x: f32, y: f32) -> Vector3<f32> {
         let x = x.max(1.0).min(self.width as f32 - 2.0);
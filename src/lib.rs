@@ -0,0 +1,105 @@
+//!Engine of oxidator: simulation, instanced model renderer, shader compilation and the game
+//!client built on them. The binary in main.rs only parses the command line and wires the threads.
+//!The renderer and the client need the client feature, bin/dedicated.rs builds without it.
+#[macro_use]
+pub mod profiling;
+
+pub mod botdef;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod crash;
+pub mod determinism;
+mod effect;
+pub mod frame;
+pub mod frame_server;
+pub mod game_event;
+#[cfg(feature = "client")]
+pub mod glsl;
+#[cfg(feature = "client")]
+pub mod gpu_obj;
+pub mod heightmap_phy;
+pub mod logging;
+pub mod manager;
+pub mod mobile;
+#[cfg(feature = "client")]
+pub mod model;
+pub mod moddef;
+mod net_client;
+pub mod net_protocol;
+pub mod net_relay;
+pub mod net_sim;
+mod net_server;
+pub mod platform;
+pub mod prefab;
+#[cfg(feature = "client")]
+mod procedural_texels;
+pub mod replay;
+mod rollback;
+mod sky;
+mod stats;
+pub mod texture_file;
+mod unit;
+mod validation;
+mod vision;
+mod weather;
+
+pub mod utils;
+extern crate byteorder;
+extern crate crossbeam_channel;
+extern crate nalgebra as na;
+#[cfg(feature = "use_shaderc")]
+extern crate shaderc;
+#[macro_use]
+extern crate typename;
+extern crate base_62;
+extern crate rayon;
+extern crate spin_sleep;
+pub struct StartClient {
+    pub bind: String,
+    ///Used if bind can't be reached
+    pub relay: Option<net_relay::Relay>,
+    pub net_sim: net_sim::NetSim,
+}
+
+pub struct StartServer {
+    pub bind: String,
+    ///Also accept clients through this relay
+    pub relay: Option<net_relay::Relay>,
+    pub net_sim: net_sim::NetSim,
+    ///Resimulate late remote inputs, see rollback.rs
+    pub rollback: bool,
+    ///Slot of the player of this host, None on a dedicated one
+    pub host_slot: Option<u8>,
+}
+
+///To the manager, from the client or from the dedicated server
+pub enum FromClient {
+    PlayerInput(frame::FrameEventFromPlayer),
+    StartServer(StartServer),
+    StartClient(StartClient),
+    DisconnectServer,
+    DisconnectClient,
+    ///Watched instead of the current game until a ReplaceFrame
+    PlayReplay(replay::Replay),
+    ///Frame of the replay to go to, backward or forward
+    SeekReplay(i32),
+    ///One more frame computed while paused, offline only
+    StepFrame,
+}
+
+#[derive(Debug)]
+pub enum ToClient {
+    MapReadAsyncMessage {
+        vec: Vec<f32>,
+        usage: String,
+    },
+    NewFrame(frame::Frame),
+    GlobalInfo(manager::GlobalInfo),
+    Pings(Vec<frame::Ping>),
+    ///The host quit, this client hosts the game now
+    HostMigrated,
+}
+
+pub enum EventLoopMsg {
+    Stop,
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+///One line with an env_logger filter, ex: info,oxidator::frame_server=debug
+pub const CONFIG_PATH: &str = "log_filter.txt";
+pub const LOG_DIR: &str = "logs";
+const LOG_NAME: &str = "oxidator";
+///Size at which the log file is rotated
+const MAX_FILE_BYTES: u64 = 5 * 1024 * 1024;
+///Rotated files kept, oxidator.1.log being the most recent
+const MAX_ROTATED: usize = 3;
+///Lines kept for the in game log viewer
+const MAX_LINES: usize = 500;
+
+///Most recent log lines, shared with the client for the log viewer
+#[derive(Clone)]
+pub struct LogLines {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogLines {
+    fn new() -> Self {
+        LogLines {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(MAX_LINES))),
+        }
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn snapshot(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    ///None while another thread holds the lines, as in a panic hook
+    pub fn try_snapshot(&self) -> Option<Vec<String>> {
+        let lines = self.lines.try_lock().ok()?;
+        Some(lines.iter().cloned().collect())
+    }
+
+    pub fn clear(&self) {
+        self.lines.lock().unwrap().clear();
+    }
+}
+
+struct RotatingFile {
+    file: Option<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open() -> Self {
+        let _ = fs::create_dir_all(LOG_DIR);
+        let path = Self::path(0);
+        let written = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .ok();
+        RotatingFile { file, written }
+    }
+
+    fn path(index: usize) -> String {
+        if index == 0 {
+            format!("{}/{}.log", LOG_DIR, LOG_NAME)
+        } else {
+            format!("{}/{}.{}.log", LOG_DIR, LOG_NAME, index)
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.written > MAX_FILE_BYTES {
+            self.rotate();
+        }
+        if let Some(file) = &mut self.file {
+            if writeln!(file, "{}", line).is_ok() {
+                self.written += line.len() as u64 + 1;
+            }
+        }
+    }
+
+    fn rotate(&mut self) {
+        self.file = None;
+        for index in (0..MAX_ROTATED).rev() {
+            let _ = fs::rename(Self::path(index), Self::path(index + 1));
+        }
+        *self = Self::open();
+    }
+}
+
+///env_logger decides what is logged, records then also go to the file and the log viewer
+struct Logger {
+    console: env_logger::Logger,
+    file: Mutex<RotatingFile>,
+    lines: LogLines,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.console.matches(record) {
+            return;
+        }
+        self.console.log(record);
+        let line = format!("{:5} {} {}", record.level(), record.target(), record.args());
+        self.file.lock().unwrap().write_line(&line);
+        self.lines.push(line);
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        if let Some(file) = &mut self.file.lock().unwrap().file {
+            let _ = file.flush();
+        }
+    }
+}
+
+///Filter priority: cli (--log=<filter>), then RUST_LOG, then CONFIG_PATH, errors only otherwise
+pub fn init(cli_filter: Option<String>) -> LogLines {
+    let filter = cli_filter
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .or_else(|| {
+            fs::read_to_string(CONFIG_PATH)
+                .ok()
+                .map(|s| s.trim().to_owned())
+        })
+        .unwrap_or_else(|| "error".to_owned());
+
+    let console = env_logger::Builder::new().parse_filters(&filter).build();
+    let lines = LogLines::new();
+    let max_level = console.filter();
+    let logger = Logger {
+        console,
+        file: Mutex::new(RotatingFile::open()),
+        lines: lines.clone(),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+    lines
+}
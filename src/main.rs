@@ -1,57 +1,213 @@
-mod botdef;
-mod client;
-mod frame;
-mod frame_server;
-mod glsl;
-mod gpu_obj;
-mod heightmap_phy;
-mod manager;
-mod mobile;
-mod model;
-mod moddef;
-mod net_client;
-mod net_server;
-mod procedural_texels;
-mod unit;
-
-mod utils;
-extern crate byteorder;
-extern crate crossbeam_channel;
-extern crate nalgebra as na;
-#[cfg(feature = "use_shaderc")]
-extern crate shaderc;
-#[macro_use]
-extern crate typename;
-extern crate base_62;
-extern crate rayon;
-extern crate spin_sleep;
+//!Game binary: command line, windows and threads, the engine is in lib.rs
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use crossbeam_channel::unbounded;
-use spin_sleep::LoopHelper;
+use oxidator::*;
 use winit::event::Event;
 use winit::event_loop::ControlFlow;
-#[derive(Debug)]
-pub enum ToClient {
-    MapReadAsyncMessage { vec: Vec<f32>, usage: String },
-    NewFrame(frame::Frame),
-    GlobalInfo(manager::GlobalInfo),
+
+///Window and adapter, common to the commands that open a window
+struct Display {
+    ///Logical size of the window, winit default when None
+    size: Option<(f64, f64)>,
+    backends: wgpu::BackendBit,
 }
 
-pub enum EventLoopMsg {
-    Stop,
+///Screen shown once the window is open
+enum Start {
+    Home,
+    Skirmish {
+        map: Option<String>,
+        ai: u32,
+        win_condition: frame::WinCondition,
+    },
+    Editor {
+        unit: bool,
+    },
+    Benchmark,
+    Replay(String),
 }
-use std::env;
+
 fn main() {
-    env_logger::init();
-    if let Some(x) = env::args().skip(1).next() {
-        if x == "compile" {
-            glsl::compile_all_glsl();
+    let matches = App::new("oxidator")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("RTS engine, opens the home menu without a command")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("log")
+                .long("log")
+                .takes_value(true)
+                .global(true)
+                .help("env_logger filter, ex: info,oxidator::frame_server=debug"),
+        )
+        .arg(
+            Arg::with_name("width")
+                .long("width")
+                .takes_value(true)
+                .global(true)
+                .help("Window width"),
+        )
+        .arg(
+            Arg::with_name("height")
+                .long("height")
+                .takes_value(true)
+                .global(true)
+                .help("Window height"),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["primary", "vulkan", "metal", "dx12", "dx11"])
+                .help("Graphics api, the best one of the platform by default"),
+        )
+        .subcommand(
+            SubCommand::with_name("play")
+                .about("Skirmish against enemy armies, they only return fire until a computer player exists")
+                .arg(
+                    Arg::with_name("map")
+                        .long("map")
+                        .takes_value(true)
+                        .help("Map directory, ex: src/asset/map/map_example"),
+                )
+                .arg(
+                    Arg::with_name("ai")
+                        .long("ai")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Number of enemy players"),
+                )
+                .arg(
+                    Arg::with_name("win")
+                        .long("win")
+                        .takes_value(true)
+                        .possible_values(&["annihilation", "commander"])
+                        .default_value("annihilation")
+                        .help("Players lose with their last unit or with their last commander"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Plays back a recorded game")
+                .arg(Arg::with_name("file").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("editor")
+                .about("Map editor")
+                .arg(
+                    Arg::with_name("unit")
+                        .long("unit")
+                        .help("Unit editor instead"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("bench").about(
+            "Fixed battle along a fixed camera path, report written to benchmark_report.json",
+        ))
+        .subcommand(SubCommand::with_name("compile").about("Compiles the glsl shaders to spirv"))
+        .subcommand(
+            SubCommand::with_name("pack_textures")
+                .about("Packs the textures of a directory")
+                .arg(Arg::with_name("dir").default_value("./src/asset")),
+        )
+        .subcommand(
+            SubCommand::with_name("relay")
+                .about("Relay for hosts behind a router")
+                .arg(Arg::with_name("bind").default_value("0.0.0.0:4568")),
+        )
+        .subcommand(
+            SubCommand::with_name("golden")
+                .about("Compares reference scenes with src/asset/golden")
+                .arg(Arg::with_name("update").possible_values(&["update"])),
+        )
+        .subcommand(
+            SubCommand::with_name("determinism")
+                .about("Replays the scripts of src/asset/determinism")
+                .arg(Arg::with_name("update").possible_values(&["update"])),
+        )
+        .get_matches();
+
+    //Global options may follow the command
+    let (command, sub) = matches.subcommand();
+    let global = |name: &str| {
+        sub.and_then(|sub| sub.value_of(name))
+            .or_else(|| matches.value_of(name))
+    };
+    let log_lines = logging::init(global("log").map(|s| s.to_owned()));
+    crash::install(log_lines.clone());
+
+    let display = Display {
+        size: match (global("width"), global("height")) {
+            (Some(w), Some(h)) => match (w.parse(), h.parse()) {
+                (Ok(w), Ok(h)) => Some((w, h)),
+                _ => {
+                    log::error!("Invalid window size {}x{}", w, h);
+                    None
+                }
+            },
+            _ => None,
+        },
+        backends: match global("backend") {
+            Some("vulkan") => wgpu::BackendBit::VULKAN,
+            Some("metal") => wgpu::BackendBit::METAL,
+            Some("dx12") => wgpu::BackendBit::DX12,
+            Some("dx11") => wgpu::BackendBit::DX11,
+            _ => wgpu::BackendBit::PRIMARY,
+        },
+    };
+    let sub = sub.cloned().unwrap_or_else(ArgMatches::default);
+
+    match command {
+        "play" => {
+            let ai = sub
+                .value_of("ai")
+                .and_then(|ai| ai.parse().ok())
+                .unwrap_or(1);
+            let map = sub.value_of("map").map(|map| map.to_owned());
+            let win_condition = match sub.value_of("win") {
+                Some("commander") => frame::WinCondition::CommanderDeath,
+                _ => frame::WinCondition::Annihilation,
+            };
+            do_the_thing(
+                log_lines,
+                display,
+                Start::Skirmish {
+                    map,
+                    ai,
+                    win_condition,
+                },
+            );
+        }
+        "replay" => do_the_thing(
+            log_lines,
+            display,
+            Start::Replay(sub.value_of("file").unwrap().to_owned()),
+        ),
+        "editor" => do_the_thing(
+            log_lines,
+            display,
+            Start::Editor {
+                unit: sub.is_present("unit"),
+            },
+        ),
+        "bench" => do_the_thing(log_lines, display, Start::Benchmark),
+        "compile" => glsl::compile_all_glsl(),
+        "pack_textures" => {
+            texture_file::pack_textures(std::path::Path::new(sub.value_of("dir").unwrap()))
+        }
+        "relay" => net_relay::run(sub.value_of("bind").unwrap()),
+        "golden" => {
+            let passed = run_golden(log_lines, display, sub.is_present("update"));
+            std::process::exit(if passed { 0 } else { 1 });
         }
-    } else {
-        do_the_thing();
+        "determinism" => {
+            let passed = determinism::run(sub.is_present("update"));
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        _ => do_the_thing(log_lines, display, Start::Home),
     }
 }
 
-fn do_the_thing() {
+fn do_the_thing(log_lines: logging::LogLines, display: Display, start: Start) {
     let (s_to_frame_server, r_to_frame_server) = unbounded::<frame_server::ToFrameServer>();
     let (s_from_frame_server, r_from_frame_server) = unbounded::<frame_server::FromFrameServer>();
 
@@ -70,7 +226,10 @@ fn do_the_thing() {
 
     let (s_to_event_loop, r_to_event_loop) = unbounded::<EventLoopMsg>();
     let event_loop = winit::event_loop::EventLoop::new();
-    let builder = winit::window::WindowBuilder::new();
+    let mut builder = winit::window::WindowBuilder::new();
+    if let Some((width, height)) = display.size {
+        builder = builder.with_inner_size(winit::dpi::LogicalSize::new(width, height));
+    }
     let window = builder.build(&event_loop).unwrap();
 
     let mut client = client::App::new(
@@ -79,7 +238,23 @@ fn do_the_thing() {
         r_to_client,
         s_to_event_loop,
         s_from_client_to_manager,
+        log_lines,
+        display.backends,
     );
+    if display.size.is_some() {
+        client.keep_window_size();
+    }
+    match start {
+        Start::Home => {}
+        Start::Skirmish {
+            map,
+            ai,
+            win_condition,
+        } => client.start_skirmish(map, ai, win_condition),
+        Start::Editor { unit } => client.start_editor(unit),
+        Start::Benchmark => client.start_benchmark(),
+        Start::Replay(path) => client.start_replay(&path),
+    }
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent { .. } => {
@@ -97,3 +272,27 @@ fn do_the_thing() {
         _ => {}
     });
 }
+
+///Renders the reference scenes in a hidden window, see client::golden
+fn run_golden(log_lines: logging::LogLines, display: Display, update: bool) -> bool {
+    //No manager, the scenes are handed to the client directly
+    let (s_from_client_to_manager, _r_from_client_to_manager) = unbounded::<client::FromClient>();
+    let (s_to_client, r_to_client) = unbounded::<ToClient>();
+    let (s_to_event_loop, _r_to_event_loop) = unbounded::<EventLoopMsg>();
+    let event_loop = winit::event_loop::EventLoop::new();
+    let window = winit::window::WindowBuilder::new()
+        .with_visible(false)
+        .build(&event_loop)
+        .unwrap();
+
+    let mut client = client::App::new(
+        window,
+        s_to_client,
+        r_to_client,
+        s_to_event_loop,
+        s_from_client_to_manager,
+        log_lines,
+        display.backends,
+    );
+    client.run_golden(update)
+}
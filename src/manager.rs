@@ -1,13 +1,29 @@
-use crate::client;
 use crate::frame;
+use crate::frame::{FrameEventFromPlayer, Ping, Player, StampedInputs};
 use crate::frame_server;
+use crate::heightmap_phy::HeightmapPhy;
 use crate::net_client;
+use crate::net_protocol::Migration;
+use crate::net_relay::Relay;
 use crate::net_server;
-use crate::ToClient;
+use crate::net_sim::NetSim;
+use crate::platform::Instant;
+use crate::replay::{Playback, Replay};
+use crate::rollback::{self, Rollback};
+use crate::utils::Id;
+use crate::validation;
+use crate::{FromClient, StartClient, StartServer, ToClient};
 use crossbeam_channel::{Receiver, Sender};
+use fnv::FnvHashMap;
 use net_client::NetClient;
 use net_server::NetServer;
 use spin_sleep::LoopHelper;
+
+///Time given to a successor to host before the next one is tried
+const MIGRATION_WAIT_SEC: f32 = 3.0;
+///Time between two connections to a successor that doesn't listen yet
+const MIGRATION_RETRY_SEC: f32 = 0.5;
+
 pub struct Manager {}
 
 impl Manager {
@@ -15,7 +31,7 @@ impl Manager {
         s_to_client_from_root_manager: Sender<crate::ToClient>,
         s_to_frame_server: Sender<frame_server::ToFrameServer>,
         r_from_frame_server: Receiver<frame_server::FromFrameServer>,
-        r_from_client: Receiver<client::FromClient>,
+        r_from_client: Receiver<FromClient>,
     ) -> () {
         let _ = std::thread::Builder::new()
             .name("manager".to_string())
@@ -23,6 +39,7 @@ impl Manager {
                 let mut global_info = GlobalInfo {
                     manager: ManagerInfo {
                         loop_time: std::time::Duration::from_millis(0),
+                        game_speed: GameSpeed::new(),
                     },
                     net_client: None,
                     net_server: None,
@@ -35,54 +52,329 @@ impl Manager {
                         frame::DataToComputeNextFrame {
                             old_frame: frame0.clone(),
                             events: Vec::new(),
+                            pings: Vec::new(),
                         },
                     ));
                 let _ = s_to_client_from_root_manager.send(ToClient::NewFrame(frame0));
 
                 let mut loop_helper = LoopHelper::builder().build_with_target_rate(10.0_f64);
+                let mut speed_votes = FnvHashMap::default();
+                let mut host_ids = Vec::new();
+                //Orders given during a pause wait for the next frame
+                let mut player_inputs = Vec::new();
+                let mut remote_inputs = Vec::new();
+                //Sent with the next frame, whatever the frame they were given at
+                let mut pings = Vec::new();
+                let mut players_count = 0;
+                let mut rollback = None;
+                //Last remote frame shown to the local player, when client
+                let mut shown_frame_number = 0;
+                //Game being played, written when it ends or is left
+                let mut recording: Option<Replay> = None;
+                let mut playback: Option<Playback> = None;
+                let mut pending_seek = None;
+                //Heightmap with the craters of the game joined, shown once caught up
+                let mut caught_up_heightmap = None;
+                //Where the client joined, and who hosts if the host quits
+                let mut joined: Option<(String, Option<Relay>, NetSim)> = None;
+                let mut migration: Option<Migration> = None;
+                //Host joined and the slot it gave us, asked back when joining it again
+                let mut seated: Option<(String, u8)> = None;
+                //Successor tried, since when and its last connection, once the host is lost
+                let mut candidate: (usize, Option<Instant>, Option<Instant>) = (0, None, None);
+                //Heightmap of the local frame server when client, a successor records from it
+                let mut heightmap: Option<HeightmapPhy> = None;
                 loop {
                     log::trace!("loop sleep");
-                    loop_helper.loop_sleep();
+                    let seeking = playback.as_ref().map(|p| p.is_seeking()).unwrap_or(false);
+                    if !seeking {
+                        loop_helper.loop_sleep();
+                    }
                     global_info.manager.loop_time = loop_helper.loop_start();
-                    log::trace!("receive");
-                    //Receiving new frame
-                    let mut frame = match r_from_frame_server.recv() {
-                        Ok(frame_server::FromFrameServer::NewFrame(new_frame)) => new_frame,
-                        _ => panic!("frame_server disconnected"),
-                    };
+                    profile_scope!("manager tick");
 
                     //Receiving local player event
-                    let mut player_inputs = Vec::new();
+                    let mut local_votes = Vec::new();
+                    let mut step = false;
                     for from_client in r_from_client.try_iter() {
-                        use client::FromClient;
                         match from_client {
+                            FromClient::PlayerInput(FrameEventFromPlayer::SpeedVote {
+                                id,
+                                speed,
+                                paused,
+                            }) if !net.is_client() => local_votes.push((id, speed, paused)),
+                            FromClient::PlayerInput(FrameEventFromPlayer::Ping {
+                                id,
+                                kind,
+                                position,
+                            }) if !net.is_client() => pings.push(Ping {
+                                player_id: id,
+                                kind,
+                                position,
+                            }),
+                            FromClient::PlayerInput(FrameEventFromPlayer::ReplaceFrame(frame)) => {
+                                //Another game, or back to the menu
+                                playback = None;
+                                player_inputs.push(FrameEventFromPlayer::ReplaceFrame(frame));
+                            }
                             FromClient::PlayerInput(event) => player_inputs.push(event),
-                            FromClient::StartClient(client::StartClient { bind }) => {
-                                net = Net::IsClient(NetClient::new(&bind))
+                            FromClient::StartClient(StartClient {
+                                bind,
+                                relay,
+                                net_sim,
+                            }) => {
+                                if let Some(done) = recording.take() {
+                                    save_replay(&done);
+                                }
+                                let seat = seated
+                                    .as_ref()
+                                    .filter(|(host, _)| *host == bind)
+                                    .map(|(_, seat)| *seat);
+                                net = Net::IsClient(NetClient::new(
+                                    &bind,
+                                    relay.clone(),
+                                    net_sim,
+                                    seat,
+                                ));
+                                joined = Some((bind, relay, net_sim));
+                                migration = None;
                             }
-                            FromClient::StartServer(client::StartServer { bind }) => {
-                                net = Net::IsServer(NetServer::new(&bind))
+                            FromClient::StartServer(StartServer {
+                                bind,
+                                relay,
+                                net_sim,
+                                rollback: with_rollback,
+                                host_slot,
+                            }) => {
+                                net =
+                                    Net::IsServer(NetServer::new(&bind, relay, net_sim, host_slot));
+                                rollback = if with_rollback {
+                                    Some(Rollback::new())
+                                } else {
+                                    None
+                                };
                             }
                             FromClient::DisconnectServer => {
                                 if let Net::IsServer(net_server) = &mut net {
                                     net_server.kill();
                                     global_info.net_server = None;
                                     net = Net::Offline;
+                                    rollback = None;
                                 }
                             }
+                            FromClient::PlayReplay(replay) => {
+                                if let Some(done) = recording.take() {
+                                    save_replay(&done);
+                                }
+                                player_inputs
+                                    .push(FrameEventFromPlayer::ReplaceFrame(replay.start.clone()));
+                                playback = Some(Playback::new(replay));
+                                global_info.manager.game_speed = GameSpeed::new();
+                            }
+                            FromClient::SeekReplay(to) => pending_seek = Some(to),
+                            FromClient::StepFrame => step = !net.is_client(),
                             FromClient::DisconnectClient => {
                                 if let Net::IsClient(net_client) = &mut net {
                                     net_client.kill();
                                     global_info.net_client = None;
                                     net = Net::Offline;
+                                    joined = None;
+                                }
+                            }
+                        }
+                    }
+
+                    //If local is server : Extend with remote players, their speed votes are for us
+                    let mut remote_votes = Vec::new();
+                    if let Net::IsServer(server) = &mut net {
+                        for stamped in server.collect_remote_players_inputs() {
+                            let mut events = Vec::new();
+                            for event in stamped.events {
+                                match event {
+                                    FrameEventFromPlayer::SpeedVote { id, speed, paused } => {
+                                        remote_votes.push((id, speed, paused))
+                                    }
+                                    FrameEventFromPlayer::Ping { id, kind, position } => pings
+                                        .push(Ping {
+                                            player_id: id,
+                                            kind,
+                                            position,
+                                        }),
+                                    event => events.push(event),
+                                }
+                            }
+                            remote_inputs.push(StampedInputs {
+                                frame_number: stamped.frame_number,
+                                events,
+                            });
+                        }
+                        for peer_event in server.take_peer_events() {
+                            match peer_event {
+                                //Late or back, the game is sent from its latest snapshot
+                                net_server::PeerEvent::Joined(peer) => {
+                                    if let Some(recording) = &recording {
+                                        match recording.catch_up().encode() {
+                                            Ok(bytes) => server.send_catch_up(peer, bytes),
+                                            Err(e) => log::error!("Can't send catch up: {}", e),
+                                        }
+                                    }
+                                }
+                                net_server::PeerEvent::Away { player, away } => player_inputs
+                                    .push(FrameEventFromPlayer::SetAway { id: player, away }),
+                            }
+                        }
+                    }
+
+                    //If local is client : A game joined late is simulated up to the host frame
+                    if let Net::IsClient(net_client) = &mut net {
+                        if let Some(replay) = net_client.take_catch_up() {
+                            player_inputs
+                                .push(FrameEventFromPlayer::ReplaceFrame(replay.start.clone()));
+                            let last_frame = replay.last_frame;
+                            let mut catch_up = Playback::new(replay);
+                            catch_up.seek_to = Some(last_frame);
+                            playback = Some(catch_up);
+                        }
+                    }
+
+                    //Offline the player decides alone, otherwise a majority is needed
+                    let game_speed = &mut global_info.manager.game_speed;
+                    let previous_speed = *game_speed;
+                    match net {
+                        Net::Offline => {
+                            for (_, speed, paused) in local_votes {
+                                game_speed.set(speed, paused);
+                            }
+                        }
+                        Net::IsServer(_) => {
+                            host_ids.extend(local_votes.iter().map(|v| v.0));
+                            for (id, speed, paused) in local_votes.into_iter().chain(remote_votes) {
+                                speed_votes.insert(id, (speed, paused));
+                            }
+                            if let Some((speed, paused)) =
+                                GameSpeed::elected(&speed_votes, &host_ids, players_count)
+                            {
+                                game_speed.set(speed, paused);
+                                speed_votes.clear();
+                            }
+                        }
+                        Net::IsClient(_) => {}
+                    }
+                    if game_speed.speed != previous_speed.speed {
+                        log::info!("Game speed x{}", game_speed.speed);
+                        loop_helper = LoopHelper::builder()
+                            .build_with_target_rate(10.0_f64 * game_speed.speed as f64);
+                    }
+
+                    //Seeking still goes on while paused
+                    let seeking = pending_seek.is_some()
+                        || playback.as_ref().map(|p| p.is_seeking()).unwrap_or(false);
+                    if game_speed.paused && !seeking && !step {
+                        let _ =
+                            s_to_client_from_root_manager.send(ToClient::GlobalInfo(global_info));
+                        continue;
+                    }
+
+                    log::trace!("receive");
+                    //Receiving new frame
+                    let mut frame = match r_from_frame_server.recv() {
+                        Ok(frame_server::FromFrameServer::NewFrame(new_frame)) => new_frame,
+                        _ => panic!("frame_server disconnected"),
+                    };
+                    players_count = frame.players.len();
+                    if let (true, Some(heightmap)) = (net.is_client(), &mut heightmap) {
+                        for crater in frame.craters.iter() {
+                            heightmap.crater(
+                                crater.position.x,
+                                crater.position.y,
+                                crater.radius,
+                                crater.depth,
+                            );
+                        }
+                    }
+                    crate::crash::set_tick(frame.number);
+
+                    if frame.game_end.is_some() {
+                        if let Some(done) = recording.take() {
+                            save_replay(&done);
+                        }
+                    }
+                    let mut caught_up = false;
+                    if let Some(playback) = &mut playback {
+                        //The client takes the heightmap of a frame as a whole
+                        if let Some(heightmap) = playback.observe(&frame) {
+                            frame.heightmap_phy = Some(heightmap);
+                        }
+                        if net.is_client() && !playback.is_seeking() {
+                            log::info!("Caught up at frame {}", frame.number);
+                            caught_up_heightmap = frame.heightmap_phy.take();
+                            caught_up = true;
+                        }
+                        if let Some(to) = pending_seek.take() {
+                            if let Some(snapshot) = playback.seek(frame.number, to) {
+                                player_inputs
+                                    .push(FrameEventFromPlayer::ReplaceFrame(snapshot.clone()));
+                                frame = snapshot;
+                            }
+                        }
+                        if frame.number >= playback.replay.last_frame && !net.is_client() {
+                            global_info.manager.game_speed.paused = true;
+                        }
+                    }
+                    if caught_up {
+                        playback = None;
+                    }
+
+                    //Impossible remote orders are dropped before the simulation sees them
+                    let mut stamped = std::mem::replace(&mut remote_inputs, Vec::new());
+                    for inputs in stamped.iter_mut() {
+                        inputs
+                            .events
+                            .retain(|event| match validation::check(&frame, event) {
+                                Ok(()) => true,
+                                Err(reason) => {
+                                    log::warn!(
+                                        "Rejected order of player {:?}: {}",
+                                        event.player_id(),
+                                        reason
+                                    );
+                                    false
                                 }
+                            });
+                    }
+
+                    //Late remote events are applied at the frame their player saw, see rollback.rs
+
+                    match &mut rollback {
+                        Some(rollback) if players_count <= rollback::MAX_PLAYERS => {
+                            let (resimulated, on_time) =
+                                rollback.resimulate(stamped, &frame, |data| {
+                                    //The replay holds the late orders too
+                                    if let Some(recording) = &mut recording {
+                                        recording.correct(&data);
+                                    }
+                                    let _ = s_to_frame_server.send(
+                                        frame_server::ToFrameServer::DataToComputeNextFrame(data),
+                                    );
+                                    match r_from_frame_server.recv() {
+                                        Ok(frame_server::FromFrameServer::NewFrame(new_frame)) => {
+                                            new_frame
+                                        }
+                                        _ => panic!("frame_server disconnected"),
+                                    }
+                                });
+                            if let Some(resimulated) = resimulated {
+                                frame = resimulated;
                             }
+                            player_inputs.extend(on_time);
                         }
+                        _ => player_inputs.extend(stamped.into_iter().flat_map(|s| s.events)),
                     }
 
                     //If local is client : Send player events
                     if let Net::IsClient(net_client) = &mut net {
                         net_client.send_player_inputs(
+                            shown_frame_number,
                             player_inputs
                                 .iter()
                                 .filter(|e| match e {
@@ -93,42 +385,172 @@ impl Manager {
                                 .collect(),
                         );
                     }
-                    //If local is server : Extend with remote players
-                    else if let Net::IsServer(server) = &mut net {
-                        player_inputs.extend(server.collect_remote_players_inputs());
+
+                    //A replay gives the recorded orders instead of those of the players
+                    let mut events = std::mem::replace(&mut player_inputs, Vec::new());
+                    if let Some(playback) = &playback {
+                        let mut base = frame.number;
+                        let mut replaced = Vec::new();
+                        for event in events {
+                            if let FrameEventFromPlayer::ReplaceFrame(replacer) = &event {
+                                base = replacer.number;
+                                replaced.push(event);
+                            }
+                        }
+                        replaced.extend(playback.events(base));
+                        events = replaced;
                     }
 
                     //Frame is now complete and ready to be sent
                     let mut data_to_compute_next_frame = frame::DataToComputeNextFrame {
                         old_frame: frame.clone(),
-                        events: player_inputs,
+                        events,
+                        pings: std::mem::replace(&mut pings, Vec::new()),
                     };
 
+                    //New games are recorded from their first frame
+                    if !net.is_client() && playback.is_none() {
+                        for event in data_to_compute_next_frame.events.iter() {
+                            if let FrameEventFromPlayer::ReplaceFrame(start) = event {
+                                if let Some(done) = recording.take() {
+                                    save_replay(&done);
+                                }
+                                if !start.players.is_empty() {
+                                    recording = Some(Replay::new(start.clone()));
+                                }
+                            }
+                        }
+                        if let Some(recording) = &mut recording {
+                            recording.record(&data_to_compute_next_frame);
+                        }
+                    }
+
                     //If local is client : Get remote frame (TEMPORARY TOTAL BYPASS OF LOCAL FRAME_SERVER)
+                    let catching_up = playback.as_ref().map(|p| p.is_seeking()).unwrap_or(false);
+                    let mut host_lost = false;
                     if let Net::IsClient(net_client) = &mut net {
-                        data_to_compute_next_frame =
-                            net_client.collect_data_to_compute_next_frame().unwrap();
-
-                        frame = data_to_compute_next_frame.old_frame.clone();
+                        if let Some(latest) = net_client.take_migration() {
+                            migration = Some(latest);
+                        }
+                        //Remote frames wait for the end of the catch up
+                        if !catching_up {
+                            match net_client.collect_data_to_compute_next_frame() {
+                                Some(data) => {
+                                    data_to_compute_next_frame = data;
+                                    frame = data_to_compute_next_frame.old_frame.clone();
+                                    frame.heightmap_phy = caught_up_heightmap.take();
+                                    shown_frame_number = frame.number;
+                                    candidate = (0, None, None);
+                                }
+                                //The frame computed locally keeps the game going meanwhile
+                                None => host_lost = true,
+                            }
+                        }
+                    }
+                    if host_lost {
+                        match (&joined, &migration) {
+                            (Some((bind, relay, net_sim)), Some(migration)) => {
+                                let (index, since, tried) = &mut candidate;
+                                let waited = since.get_or_insert_with(Instant::now);
+                                if waited.elapsed().as_secs_f32() > MIGRATION_WAIT_SEC {
+                                    *index += 1;
+                                    *since = Some(Instant::now());
+                                    *tried = None;
+                                }
+                                //Our player stays ours
+                                let seat = seated.as_ref().map(|(_, seat)| *seat);
+                                if *index == migration.rank {
+                                    log::info!("Host lost, hosting the game");
+                                    let port = bind.rsplit(':').next().unwrap_or_default();
+                                    let bind = format!("0.0.0.0:{}", port);
+                                    global_info.net_client = None;
+                                    let _ =
+                                        s_to_client_from_root_manager.send(ToClient::HostMigrated);
+                                    //Recorded from here, for the replay and the late clients
+                                    let mut start = frame.clone();
+                                    start.heightmap_phy = heightmap.take();
+                                    let mut resumed = Replay::new(start);
+                                    resumed.record(&data_to_compute_next_frame);
+                                    recording = Some(resumed);
+                                    net = Net::IsServer(NetServer::new(
+                                        &bind,
+                                        relay.clone(),
+                                        *net_sim,
+                                        seat,
+                                    ));
+                                } else if let Some(addr) = migration.successors.get(*index) {
+                                    //One connection at a time, the successor may not listen yet
+                                    let retry = tried
+                                        .map(|at| at.elapsed().as_secs_f32() > MIGRATION_RETRY_SEC)
+                                        .unwrap_or(true);
+                                    if retry {
+                                        log::info!("Host lost, joining {}", addr);
+                                        *tried = Some(Instant::now());
+                                        net = Net::IsClient(NetClient::new(
+                                            addr,
+                                            relay.clone(),
+                                            *net_sim,
+                                            seat,
+                                        ));
+                                    }
+                                } else {
+                                    log::error!("Host lost, no successor left");
+                                    global_info.net_client = None;
+                                    net = Net::Offline;
+                                }
+                            }
+                            _ => {
+                                log::error!("Host lost");
+                                global_info.net_client = None;
+                                net = Net::Offline;
+                            }
+                        }
+                        if !net.is_client() {
+                            joined = None;
+                            migration = None;
+                        }
                     }
                     //If local is server : Broadcast to remotes
                     else if let Net::IsServer(server) = &mut net {
+                        if let Some(rollback) = &mut rollback {
+                            rollback.record(&data_to_compute_next_frame);
+                        }
                         server.broadcast_data_to_compute_next_frame(
                             data_to_compute_next_frame.clone(),
                         );
                     }
 
-                    //Sending to local frame_server and local client
+                    if net.is_client() {
+                        for event in data_to_compute_next_frame.events.iter() {
+                            if let FrameEventFromPlayer::ReplaceFrame(start) = event {
+                                heightmap = start.heightmap_phy.clone();
+                            }
+                        }
+                    }
+
+                    //Sending to local frame_server and local client, pings are only for the client
+                    let frame_pings =
+                        std::mem::replace(&mut data_to_compute_next_frame.pings, Vec::new());
                     let _ = s_to_frame_server.send(
                         frame_server::ToFrameServer::DataToComputeNextFrame(
                             data_to_compute_next_frame,
                         ),
                     );
-                    let _ = s_to_client_from_root_manager.send(ToClient::NewFrame(frame));
+                    if !frame_pings.is_empty() {
+                        let _ = s_to_client_from_root_manager.send(ToClient::Pings(frame_pings));
+                    }
+                    //Only the frame sought is shown
+                    if !playback.as_ref().map(|p| p.is_seeking()).unwrap_or(false) {
+                        let _ = s_to_client_from_root_manager.send(ToClient::NewFrame(frame));
+                    }
 
                     //Gathering and sending GlobalInfo
                     if let Net::IsClient(net_client) = &mut net {
-                        global_info.net_client = Some(net_client.get_info());
+                        let info = net_client.get_info();
+                        if let (Some(seat), Some((bind, _, _))) = (info.seat(), &joined) {
+                            seated = Some((bind.clone(), seat));
+                        }
+                        global_info.net_client = Some(info);
                     } else if let Net::IsServer(server) = &mut net {
                         global_info.net_server = Some(server.get_info());
                     }
@@ -138,15 +560,83 @@ impl Manager {
     }
 }
 
+fn save_replay(replay: &Replay) {
+    //Nothing was ordered, not worth a file
+    if replay.events.is_empty() {
+        return;
+    }
+    let path = Replay::new_path();
+    match replay.save(&path) {
+        Ok(()) => log::info!("Replay written to {}", path),
+        Err(e) => log::error!("Can't write replay {}: {}", path, e),
+    }
+}
+
 enum Net {
     Offline,
     IsServer(NetServer),
     IsClient(NetClient),
 }
 
+impl Net {
+    fn is_client(&self) -> bool {
+        match self {
+            Net::IsClient(_) => true,
+            _ => false,
+        }
+    }
+}
+
+///Simulation ticks per second are scaled by speed, no tick happens while paused
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameSpeed {
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl GameSpeed {
+    pub const MIN: f32 = 0.5;
+    pub const MAX: f32 = 10.0;
+
+    pub fn new() -> Self {
+        GameSpeed {
+            speed: 1.0,
+            paused: false,
+        }
+    }
+
+    fn set(&mut self, speed: f32, paused: bool) {
+        self.speed = speed.max(Self::MIN).min(Self::MAX);
+        self.paused = paused;
+    }
+
+    ///A vote wins with more than half of the players, or exactly half when the host is part of it
+    fn elected(
+        votes: &FnvHashMap<Id<Player>, (f32, bool)>,
+        host_ids: &[Id<Player>],
+        players_count: usize,
+    ) -> Option<(f32, bool)> {
+        for (speed, paused) in votes.values() {
+            let supporters: Vec<_> = votes
+                .iter()
+                .filter(|(_, vote)| *vote == &(*speed, *paused))
+                .map(|(id, _)| id)
+                .collect();
+            let with_host = supporters.iter().any(|id| host_ids.contains(id));
+            if supporters.len() * 2 > players_count
+                || (with_host && supporters.len() * 2 >= players_count)
+            {
+                return Some((*speed, *paused));
+            }
+        }
+        None
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ManagerInfo {
     loop_time: std::time::Duration,
+    pub game_speed: GameSpeed,
 }
 
 ///Info about all the components of this program
@@ -8,11 +8,19 @@ use std::path::{Path, PathBuf};
 use typename::TypeName;
 use utils::Id;
 
+///Named effect triggered by the simulation, see effect::EffectLibrary
 #[derive(Clone, TypeName, Debug, Serialize, Deserialize, PartialEq)]
-pub struct ExplosionEvent {
+pub struct EffectEvent {
     pub position: Point3<f32>,
-    pub size: f32,
-    pub life_time: f32,
+    pub name: String,
+}
+
+///Shot of a botdef::Weapon::Beam, lasting one frame
+#[derive(Clone, TypeName, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BeamEvent {
+    pub from: Point3<f32>,
+    pub to: Point3<f32>,
+    pub color: [f32; 3],
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
@@ -109,6 +117,77 @@ pub enum Command {
     None,
     Build(Id<KBot>),
     Repair(Id<KBot>),
+    ///Pick up a unit, transports only
+    Load(Id<KBot>),
+    ///Drop all the cargo around a point, transports only
+    Unload(Point3<f32>),
+    ///Loop through the waypoints from next, stopping to fight ennemies met on the way
+    Patrol {
+        waypoints: Vec<Point3<f32>>,
+        next: usize,
+    },
+    ///Follow a unit of the team, fighting near it and helping its construction or repair
+    Guard(Id<KBot>),
+    ///Take a unit of the team apart, getting back the metal it holds
+    Reclaim(Id<KBot>),
+    ///Every unit of the team in the circle one after the other, target is the current one
+    Area {
+        kind: AreaKind,
+        center: Point3<f32>,
+        radius: f32,
+        target: Option<Id<KBot>>,
+    },
+    ///Walk in range of the point and fire the botdef::DGun at it once
+    DGun(Point3<f32>),
+    ///Carry the idle units waiting within FERRY_RADIUS of pickup to dropoff, over and over,
+    ///transports only
+    Ferry {
+        pickup: Point3<f32>,
+        dropoff: Point3<f32>,
+        ///On the way to dropoff
+        unloading: bool,
+    },
+}
+
+///m around the pickup point of a Command::Ferry where units wait for a transport
+pub const FERRY_RADIUS: f32 = 8.0;
+
+///When a unit opens fire on its own
+#[derive(Clone, Copy, TypeName, Debug, Serialize, Deserialize, PartialEq)]
+pub enum FireStance {
+    HoldFire,
+    ///Only at the last unit that damaged it
+    ReturnFire,
+    FireAtWill,
+}
+
+///How far an idle unit goes after the ennemies it sees
+#[derive(Clone, Copy, TypeName, Debug, Serialize, Deserialize, PartialEq)]
+pub enum MoveStance {
+    HoldPosition,
+    ///Chases close ennemies, then comes back to where it stood
+    Maneuver,
+    ///Chases further and stays where the chase ended
+    Roam,
+}
+
+impl MoveStance {
+    ///m from where the unit stood when the chase began, 0 when it never moves
+    pub fn leash(self) -> f32 {
+        match self {
+            MoveStance::HoldPosition => 0.0,
+            MoveStance::Maneuver => botdef::WEAPON_RANGE * 2.0,
+            MoveStance::Roam => botdef::WEAPON_RANGE * 4.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, TypeName, Debug, Serialize, Deserialize, PartialEq)]
+pub enum AreaKind {
+    ///Finishes and repairs the units
+    Repair,
+    ///Reclaims the units, except commanders, loaded transports and the builders reclaiming
+    Reclaim,
 }
 
 #[derive(Clone, TypeName, Debug, Serialize, Deserialize, PartialEq)]
@@ -122,15 +201,36 @@ pub struct KBot {
     pub up: Vector3<f32>,
     pub move_target: Option<Point3<f32>>,
     pub current_command: Command,
+    pub fire_stance: FireStance,
+    pub move_stance: MoveStance,
+    ///Where a unit chasing because of its MoveStance started, None when not chasing
+    pub chase_from: Option<Point3<f32>>,
     pub life: i32,
     pub con_completed: f32,
     pub player_id: Id<Player>,
     pub team: u8,
     pub grounded: bool,
+    ///m above the ground, always 0 for ground units
+    pub altitude: f32,
+    ///rad, roll of flying units in turns
+    pub bank: f32,
     pub frame_last_shot: i32,
+    pub frame_last_dgun: i32,
     pub weapon0_dir: Vector3<f32>,
     pub wheel0_angle: f32,
     pub reload_frame_count: i32,
+    ///Units carried by a transport, they are out of the world until unloaded
+    pub cargo: Vec<KBot>,
+    ///Ennemies destroyed by this unit, see KBot::rank
+    pub kills: u32,
+    ///Who dealt the last damage, credited with the kill on death
+    pub last_attacker: Option<Id<KBot>>,
+    ///Shots ready to fire of a botdef::Strategic weapon
+    pub stockpile: u32,
+    ///Frames spent building the next stockpiled shot
+    pub stockpile_progress: i32,
+    ///Units started by a factory when it is idle
+    pub build_queue: BuildQueue,
     pub botdef_id: Id<botdef::BotDef>,
 }
 
@@ -146,18 +246,138 @@ impl KBot {
             up: Vector3::new(0.0, 0.0, 1.0),
             move_target: None,
             current_command: Command::None,
+            fire_stance: FireStance::FireAtWill,
+            move_stance: MoveStance::Maneuver,
+            chase_from: None,
             id: utils::rand_id(),
             frame_last_shot: 0,
+            frame_last_dgun: 0,
             reload_frame_count: 3,
             weapon0_dir: Vector3::new(1.0, 0.0, 0.0),
             wheel0_angle: 0.0,
             life: botdef.max_life,
             con_completed: 1.0,
             grounded: false,
+            altitude: 0.0,
+            bank: 0.0,
+            cargo: Vec::new(),
+            kills: 0,
+            last_attacker: None,
+            stockpile: 0,
+            stockpile_progress: 0,
+            build_queue: BuildQueue::new(),
             botdef_id: botdef.id,
             angular_velocity: 0.0,
         }
     }
+
+    ///Veterancy rank, from 0 for fresh units to VETERANCY_KILLS.len()
+    pub fn rank(&self) -> usize {
+        VETERANCY_KILLS
+            .iter()
+            .filter(|&&kills| self.kills >= kills)
+            .count()
+    }
+
+    ///No command nor move order, chasing from a stance doesn't count
+    pub fn is_idle(&self) -> bool {
+        self.current_command == Command::None
+            && (self.move_target.is_none() || self.chase_from.is_some())
+    }
+
+    ///Multiplier applied to damage dealt, damage received is divided by it
+    pub fn veterancy_bonus(&self) -> f32 {
+        1.0 + 0.15 * self.rank() as f32
+    }
+
+    ///Apply damage from an attacker, taking the veterancy of both into account
+    ///Returns the life actually lost
+    pub fn take_damage(&mut self, damage: i32, attacker: Id<KBot>, attacker_bonus: f32) -> i32 {
+        let damage = (damage as f32 * attacker_bonus / self.veterancy_bonus()).round() as i32;
+        let life_before = self.life;
+        self.life = (self.life - damage.max(1)).max(0);
+        self.last_attacker = Some(attacker);
+        life_before - self.life
+    }
+
+    ///Shot recently, patrolling and guarding units hold their position while engaged
+    pub fn is_engaged(&self, frame_count: i32) -> bool {
+        self.frame_last_shot > 0 && frame_count - self.frame_last_shot <= ENGAGED_FRAMES
+    }
+}
+
+///Units a factory builds one after the other, next to itself
+#[derive(Clone, TypeName, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BuildQueue {
+    pub items: Vec<Id<botdef::BotDef>>,
+    ///Started items go back at the end instead of leaving the queue
+    pub repeat: bool,
+}
+
+impl BuildQueue {
+    pub fn new() -> Self {
+        BuildQueue {
+            items: Vec::new(),
+            repeat: false,
+        }
+    }
+
+    ///Takes the next item to start
+    pub fn pop(&mut self) -> Option<Id<botdef::BotDef>> {
+        if self.items.is_empty() {
+            return None;
+        }
+        let item = self.items.remove(0);
+        if self.repeat {
+            self.items.push(item);
+        }
+        Some(item)
+    }
+
+    ///Out of range indices are ignored, the client may have seen an older queue
+    pub fn edit(&mut self, edit: &QueueEdit) {
+        match edit {
+            QueueEdit::Push(item) => self.items.push(*item),
+            QueueEdit::Remove(i) if *i < self.items.len() => {
+                self.items.remove(*i);
+            }
+            QueueEdit::Move { from, to } if *from < self.items.len() && *to < self.items.len() => {
+                let item = self.items.remove(*from);
+                self.items.insert(*to, item);
+            }
+            QueueEdit::Replace(items) => self.items = items.clone(),
+            QueueEdit::Repeat(repeat) => self.repeat = *repeat,
+            _ => {}
+        }
+    }
+}
+
+///Change of a BuildQueue, sent by the player like other orders
+#[derive(Clone, TypeName, Debug, Serialize, Deserialize, PartialEq)]
+pub enum QueueEdit {
+    Push(Id<botdef::BotDef>),
+    Remove(usize),
+    Move {
+        from: usize,
+        to: usize,
+    },
+    ///Applies a template
+    Replace(Vec<Id<botdef::BotDef>>),
+    Repeat(bool),
+}
+
+///Kills needed to reach each veterancy rank
+pub const VETERANCY_KILLS: [u32; 3] = [2, 5, 10];
+
+///Frames after the last shot a unit is still considered fighting
+pub const ENGAGED_FRAMES: i32 = 20;
+
+///Transform and joint angles a kbot is drawn with
+#[derive(Clone, Copy, Debug)]
+pub struct Pose {
+    pub trans: Matrix4<f32>,
+    pub weapon0_dir: Vector3<f32>,
+    pub wheel0_angle: f32,
 }
 
 pub struct ClientKbot {
@@ -172,6 +392,8 @@ pub struct ClientKbot {
     pub is_in_screen: bool,
     pub distance_to_camera: f32,
     pub screen_pos: Vector2<f32>,
+    ///Pose drawn last frame, None if it was not on screen. For the motion vectors.
+    pub last_pose: Option<Pose>,
 }
 
 impl ClientKbot {
@@ -186,6 +408,19 @@ impl ClientKbot {
             is_in_screen: false,
             distance_to_camera: 0.0,
             screen_pos: Vector2::new(0.0, 0.0),
+            last_pose: None,
+        }
+    }
+
+    ///None when not on screen
+    pub fn pose(&self) -> Option<Pose> {
+        match self.trans {
+            Some(trans) if self.is_in_screen => Some(Pose {
+                trans,
+                weapon0_dir: self.weapon0_dir,
+                wheel0_angle: self.wheel0_angle,
+            }),
+            _ => None,
         }
     }
 }
@@ -199,6 +434,8 @@ pub struct KinematicProjectile {
     pub speed_per_frame_at_birth: Vector3<f32>,
     pub accel_per_frame: Vector3<f32>,
     pub radius: f32,
+    ///Unit that fired it
+    pub owner: Id<KBot>,
 
     pub position_cache: Vec<Point3<f32>>,
     pub speed_cache: Vec<Vector3<f32>>,
@@ -239,6 +476,50 @@ impl KinematicProjectile {
     }
 }
 
+///Strategic missile on a high arc, see botdef::Strategic::Nuke
+#[derive(Clone, TypeName, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StrategicMissile {
+    pub id: Id<StrategicMissile>,
+    pub owner: Id<KBot>,
+    pub team: u8,
+    pub from: Point3<f32>,
+    pub to: Point3<f32>,
+    pub launch_frame: i32,
+    pub flight_frames: i32,
+    ///m above the straight line between from and to at mid flight
+    pub apex: f32,
+    pub damage: i32,
+    pub radius: f32,
+}
+
+impl StrategicMissile {
+    ///Frame can be fractional for client interpolation
+    pub fn position_at(&self, frame: f32) -> Point3<f32> {
+        let t = self.progress_at(frame);
+        let height = 4.0 * t * (1.0 - t) * self.apex;
+        self.from + (self.to - self.from) * t + Vector3::new(0.0, 0.0, height)
+    }
+
+    ///0 at launch, 1 at impact
+    pub fn progress_at(&self, frame: f32) -> f32 {
+        ((frame - self.launch_frame as f32) / self.flight_frames as f32)
+            .max(0.0)
+            .min(1.0)
+    }
+
+    pub fn impact_frame(&self) -> i32 {
+        self.launch_frame + self.flight_frames
+    }
+}
+
+///Terrain lowered by an explosion, applied to both the server and client heightmaps
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CraterEvent {
+    pub position: Point3<f32>,
+    pub radius: f32,
+    pub depth: f32,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Arrow {
     pub position: Point3<f32>,
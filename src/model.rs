@@ -5,6 +5,16 @@ pub struct Vertex {
     _tex_coord: [f32; 2],
 }
 
+impl Vertex {
+    pub fn position(&self) -> [f32; 4] {
+        self._pos
+    }
+
+    pub fn normal(&self) -> [f32; 3] {
+        self._nor
+    }
+}
+
 #[derive(Clone)]
 pub struct TriangleList {
     pub vertex_data: Vec<Vertex>,
@@ -1,26 +1,53 @@
 use crate::frame::*;
+use crate::net_protocol::{
+    is_drop, Capabilities, ClientPacket, CommandDelta, Handshake, Migration, ServerPacket, Traffic,
+    TrafficMeter,
+};
+use crate::net_relay::{self, Relay};
+use crate::net_sim::{DelayQueue, NetSim};
+use crate::platform::{self, Instant};
+use crate::replay::Replay;
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use spin_sleep::LoopHelper;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::net::TcpListener;
-use std::net::TcpStream;
 
-#[derive(Debug, Clone, Copy)]
+///A host that doesn't answer the Hello by then is given up
+const HELLO_TIMEOUT_SEC: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BindState {
     Unknown,
     Success,
     Error,
     Disconnected,
+    ///Refused by the host or another protocol version, the reason is logged
+    Incompatible,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct NetClientInfo {
     bind_state: BindState,
+    ///With the host
+    traffic: Traffic,
+    ///Slot of our player, given by the host
+    seat: Option<u8>,
+}
+
+impl NetClientInfo {
+    pub fn bind_state(&self) -> BindState {
+        self.bind_state
+    }
+
+    pub fn seat(&self) -> Option<u8> {
+        self.seat
+    }
+
+    pub fn traffic(&self) -> Traffic {
+        self.traffic
+    }
 }
 
 pub enum ToNetClientInner {
-    PlayerInput(Vec<FrameEventFromPlayer>),
+    PlayerInput(StampedInputs),
 }
 
 pub enum FromNetClientInner {
@@ -33,16 +60,21 @@ pub struct NetClient {
     info: NetClientInfo,
     r_info: Receiver<NetClientInfo>,
     s_kill: Sender<()>,
+    r_catch_up: Receiver<Replay>,
+    r_migration: Receiver<Migration>,
 }
 
 impl NetClient {
-    pub fn new(bind: &str) -> Self {
+    ///seat is the slot played before losing the host, asked back to it
+    pub fn new(bind: &str, relay: Option<Relay>, net_sim: NetSim, seat: Option<u8>) -> Self {
         let (s_to, r_to) = unbounded::<ToNetClientInner>();
         let (s_from, r_from) = unbounded::<FromNetClientInner>();
 
         let (s_info, r_info) = unbounded::<NetClientInfo>();
 
         let (s_kill, r_kill) = unbounded::<()>();
+        let (s_catch_up, r_catch_up) = unbounded::<Replay>();
+        let (s_migration, r_migration) = unbounded::<Migration>();
 
         let bind_addr = bind.to_owned();
         std::thread::spawn(move || {
@@ -50,26 +82,61 @@ impl NetClient {
             let r = r_to;
             let s_info = s_info;
 
-            match TcpStream::connect(bind_addr) {
+            match connect(&bind_addr, relay.as_ref()) {
                 Ok(mut stream) => {
                     s_info
                         .try_send(NetClientInfo {
                             bind_state: BindState::Success,
+                            traffic: Traffic::default(),
+                            seat: None,
                         })
                         .unwrap();
 
-                    let _ = stream.set_read_timeout(Some(std::time::Duration::from_millis(2)));
-                    let _ = stream.set_nodelay(true);
                     log::info!("Connection established!");
 
+                    let mut local = Handshake::local();
+                    local.seat = seat;
+                    let mut meter = TrafficMeter::new();
+                    let _ = meter.send(&mut stream, &ClientPacket::Hello(local.clone()));
+                    let hello_sent = Instant::now();
+                    //Known once the host answered
+                    let mut capabilities = None;
+                    let mut seat = None;
+                    let mut delta = CommandDelta::new();
+                    let mut delayed = DelayQueue::new(net_sim);
                     let mut loop_helper = LoopHelper::builder().build_with_target_rate(100.0_f64);
                     'streamloop: loop {
                         loop_helper.loop_sleep();
                         loop_helper.loop_start();
                         match r.try_recv() {
-                            Ok(ToNetClientInner::PlayerInput(fe)) => {
+                            Ok(ToNetClientInner::PlayerInput(mut fe)) => {
                                 log::trace!("stream: Sending local player input to remote server");
-                                bincode::serialize_into(&mut stream, &fe).unwrap();
+                                let common = capabilities.unwrap_or(Capabilities::NONE);
+                                //A host without them would not know the event
+                                if !common.contains(Capabilities::PINGS) {
+                                    fe.events.retain(|e| match e {
+                                        FrameEventFromPlayer::Ping { .. } => false,
+                                        _ => true,
+                                    });
+                                }
+                                let raw = bincode::serialized_size(&fe).unwrap_or(0) as usize;
+                                let packet = if common.contains(Capabilities::DELTA) {
+                                    let packed = delta.encode(&fe);
+                                    meter.commands(raw, packed.len());
+                                    ClientPacket::PackedInputs(packed)
+                                } else {
+                                    meter.commands(raw, raw);
+                                    ClientPacket::Inputs(fe)
+                                };
+                                if let Err(e) = meter.send(&mut stream, &packet) {
+                                    log::warn!("Host lost: {}", e);
+                                    let _ = s_info.try_send(NetClientInfo {
+                                        bind_state: BindState::Disconnected,
+                                        traffic: Traffic::default(),
+                                        seat,
+                                    });
+                                    break 'streamloop;
+                                }
                             }
                             _ => {
                                 log::trace!("no player input to send");
@@ -77,22 +144,105 @@ impl NetClient {
                         }
 
                         log::trace!("read");
-                        let result_bincode: bincode::Result<DataToComputeNextFrame> =
-                            bincode::deserialize_from(&mut stream);
-                        match result_bincode {
-                            Ok(data) => {
+                        let result: std::io::Result<Option<ServerPacket>> =
+                            meter.try_recv(&mut stream);
+                        let refused = match result {
+                            Ok(Some(ServerPacket::Hello(remote))) => match local.accept(&remote) {
+                                Ok(common) => {
+                                    log::info!(
+                                        "Host runs oxidator {} (protocol {})",
+                                        remote.build,
+                                        remote.version
+                                    );
+                                    capabilities = Some(common);
+                                    None
+                                }
+                                Err(reason) => Some(reason),
+                            },
+                            Ok(Some(ServerPacket::Refused(reason))) => Some(reason),
+                            Ok(Some(ServerPacket::Frame(data))) => {
                                 log::trace!("   Receive Frame from remote server");
-                                let _ =
-                                    s.try_send(FromNetClientInner::DataToComputeNextFrame(data));
+                                delayed.push(data);
+                                None
+                            }
+                            Ok(Some(ServerPacket::Seat(slot))) => {
+                                log::info!("Playing slot {}", slot);
+                                seat = Some(slot);
+                                let _ = s_info.try_send(NetClientInfo {
+                                    bind_state: BindState::Success,
+                                    traffic: meter.last,
+                                    seat,
+                                });
+                                None
+                            }
+                            Ok(Some(ServerPacket::CatchUp(bytes))) => {
+                                match Replay::decode(&bytes) {
+                                    Ok(replay) => {
+                                        log::info!(
+                                            "Catching up from frame {} to {}",
+                                            replay.start.number,
+                                            replay.last_frame
+                                        );
+                                        let _ = s_catch_up.try_send(replay);
+                                    }
+                                    Err(e) => log::error!("Unreadable catch up: {}", e),
+                                }
+                                None
+                            }
+                            Ok(Some(ServerPacket::Migration(migration))) => {
+                                let _ = s_migration.try_send(migration);
+                                None
+                            }
+                            Err(ref e) if is_drop(e) => {
+                                log::warn!("Host lost: {}", e);
+                                let _ = s_info.try_send(NetClientInfo {
+                                    bind_state: BindState::Disconnected,
+                                    traffic: Traffic::default(),
+                                    seat,
+                                });
+                                break 'streamloop;
+                            }
+                            _ if capabilities.is_none()
+                                && hello_sent.elapsed().as_secs_f32() > HELLO_TIMEOUT_SEC =>
+                            {
+                                log::error!("No answer from {}", bind_addr);
+                                let _ = s_info.try_send(NetClientInfo {
+                                    bind_state: BindState::Error,
+                                    traffic: Traffic::default(),
+                                    seat,
+                                });
+                                break 'streamloop;
                             }
                             x => {
                                 log::trace!("   Error read {:?}", x);
+                                None
                             }
+                        };
+                        if let Some(reason) = refused {
+                            log::error!("Can't join {}: {}", bind_addr, reason);
+                            let _ = s_info.try_send(NetClientInfo {
+                                bind_state: BindState::Incompatible,
+                                traffic: Traffic::default(),
+                                seat,
+                            });
+                            break 'streamloop;
+                        }
+                        for data in delayed.pop_ready() {
+                            let _ = s.try_send(FromNetClientInner::DataToComputeNextFrame(data));
+                        }
+                        if meter.tick() {
+                            let _ = s_info.try_send(NetClientInfo {
+                                bind_state: BindState::Success,
+                                traffic: meter.last,
+                                seat,
+                            });
                         }
 
                         if let Ok(()) = r_kill.try_recv() {
                             let _ = s_info.try_send(NetClientInfo {
                                 bind_state: BindState::Disconnected,
+                                traffic: Traffic::default(),
+                                seat,
                             });
                             break 'streamloop;
                         }
@@ -103,6 +253,8 @@ impl NetClient {
                     s_info
                         .try_send(NetClientInfo {
                             bind_state: BindState::Error,
+                            traffic: Traffic::default(),
+                            seat: None,
                         })
                         .unwrap();
                 }
@@ -114,8 +266,12 @@ impl NetClient {
             r_info,
             info: NetClientInfo {
                 bind_state: BindState::Unknown,
+                traffic: Traffic::default(),
+                seat: None,
             },
             s_kill,
+            r_catch_up,
+            r_migration,
         }
     }
 
@@ -123,6 +279,7 @@ impl NetClient {
         self.s_kill.try_send(()).unwrap();
     }
 
+    ///None once the host is lost
     pub fn collect_data_to_compute_next_frame(&mut self) -> Option<DataToComputeNextFrame> {
         if self.r.is_empty() {
             match self.r.recv() {
@@ -136,12 +293,30 @@ impl NetClient {
         }
     }
 
-    pub fn send_player_inputs(&mut self, player_inputs: Vec<FrameEventFromPlayer>) {
+    ///Game joined late, received before the frames following it
+    pub fn take_catch_up(&mut self) -> Option<Replay> {
+        self.r_catch_up.try_iter().last()
+    }
+
+    ///Latest successors of the host sent by it
+    pub fn take_migration(&mut self) -> Option<Migration> {
+        self.r_migration.try_iter().last()
+    }
+
+    ///frame_number is the last remote frame shown to the player
+    pub fn send_player_inputs(
+        &mut self,
+        frame_number: i32,
+        player_inputs: Vec<FrameEventFromPlayer>,
+    ) {
         if player_inputs.len() > 0 {
             log::trace!("net_client: Sending local player input to remote server");
             let _ = self
                 .s
-                .try_send(ToNetClientInner::PlayerInput(player_inputs));
+                .try_send(ToNetClientInner::PlayerInput(StampedInputs {
+                    frame_number,
+                    events: player_inputs,
+                }));
         }
     }
 
@@ -153,3 +328,23 @@ impl NetClient {
         self.info
     }
 }
+
+///Direct connection first, the relay is the fallback when the host is behind a router
+fn connect(bind: &str, relay: Option<&Relay>) -> std::io::Result<platform::Connection> {
+    match (platform::connect(bind), relay) {
+        (Ok(stream), _) => Ok(stream),
+        //The relay speaks raw tcp, out of reach of a browser
+        #[cfg(not(target_arch = "wasm32"))]
+        (Err(e), Some(relay)) => {
+            log::info!(
+                "Direct connection failed ({}), trying relay {}",
+                e,
+                relay.addr
+            );
+            let stream = net_relay::join_via(relay)?;
+            platform::prepare(&stream);
+            Ok(stream)
+        }
+        (Err(e), _) => Err(e),
+    }
+}
@@ -0,0 +1,493 @@
+//!Packets between the host and its clients, and the handshake opening every connection.
+//!Each packet is length prefixed (one WebSocket message in a browser). Packets are bincode,
+//!which has no field names nor skippable unknown kinds: an older build can't read any changed
+//!packet, so each change raises VERSION and MIN_COMPATIBLE with it, and the handshake refuses
+//!peers of another version with the reason instead of desyncing.
+use crate::frame::{DataToComputeNextFrame, FrameEventFromPlayer, StampedInputs};
+use crate::platform::{Instant, Transport};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+///Raised by every change of the packets
+pub const VERSION: u32 = 9;
+///Oldest version whose packets this build still reads, only its own one
+pub const MIN_COMPATIBLE: u32 = VERSION;
+const MAGIC: [u8; 4] = *b"OXID";
+
+///Optional features, used only when both peers have them
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    ///Ping events are relayed to allies
+    pub const PINGS: Capabilities = Capabilities(1);
+    ///The host tells each client its slot with ServerPacket::Seat
+    pub const SEATS: Capabilities = Capabilities(2);
+    ///Inputs are sent as ClientPacket::PackedInputs, see CommandDelta
+    pub const DELTA: Capabilities = Capabilities(4);
+    ///A client joining a started game reads ServerPacket::CatchUp
+    pub const CATCH_UP: Capabilities = Capabilities(8);
+    ///A client is ranked to host with ServerPacket::Migration, see Migration
+    pub const MIGRATION: Capabilities = Capabilities(16);
+
+    ///Those of this build
+    pub fn local() -> Self {
+        Capabilities(
+            Capabilities::PINGS.0
+                | Capabilities::SEATS.0
+                | Capabilities::DELTA.0
+                | Capabilities::CATCH_UP.0
+                | Capabilities::MIGRATION.0,
+        )
+    }
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn common(self, other: Capabilities) -> Self {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+///First packet sent by each side
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Handshake {
+    magic: [u8; 4],
+    pub version: u32,
+    pub min_compatible: u32,
+    pub capabilities: Capabilities,
+    ///Crate version, for the error messages
+    pub build: String,
+    ///Slot a client played before it lost the host, given back unless someone took it.
+    ///Last, so that older builds still read the rest and refuse with the reason
+    pub seat: Option<u8>,
+}
+
+impl Handshake {
+    pub fn local() -> Self {
+        Handshake {
+            magic: MAGIC,
+            version: VERSION,
+            min_compatible: MIN_COMPATIBLE,
+            capabilities: Capabilities::local(),
+            build: env!("CARGO_PKG_VERSION").to_owned(),
+            seat: None,
+        }
+    }
+
+    ///Capabilities of both peers, or why they can't play together
+    pub fn accept(&self, remote: &Handshake) -> Result<Capabilities, String> {
+        if remote.magic != MAGIC {
+            return Err("the peer is not an oxidator game".to_owned());
+        }
+        if remote.version < self.min_compatible {
+            return Err(format!(
+                "the peer runs oxidator {} (protocol {}), protocol {} or newer is needed",
+                remote.build, remote.version, self.min_compatible
+            ));
+        }
+        if self.version < remote.min_compatible {
+            return Err(format!(
+                "the peer runs oxidator {} and needs protocol {} or newer, this build has {}",
+                remote.build, remote.min_compatible, self.version
+            ));
+        }
+        Ok(self.capabilities.common(remote.capabilities))
+    }
+}
+
+///From a client to the host
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ClientPacket {
+    ///First packet of the connection
+    Hello(Handshake),
+    Inputs(StampedInputs),
+    ///Inputs encoded by CommandDelta, once both peers have Capabilities::DELTA
+    PackedInputs(Vec<u8>),
+}
+
+///From the host to a client
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ServerPacket {
+    ///Answer to the Hello of the client, before any frame
+    Hello(Handshake),
+    ///Why the client can't join, the connection is closed after it
+    Refused(String),
+    Frame(DataToComputeNextFrame),
+    ///Slot of the player of the client, once both peers have Capabilities::SEATS
+    Seat(u8),
+    ///Game joined after its start, a replay::Replay from the latest snapshot, see Replay::encode.
+    ///Only to clients with Capabilities::CATCH_UP
+    CatchUp(Vec<u8>),
+    ///Who hosts the game if the host quits, sent when the peers change.
+    ///Only to clients with Capabilities::MIGRATION
+    Migration(Migration),
+}
+
+///Peers in election order, the first one still there becomes the host and the others join it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Migration {
+    ///Place of the receiver in successors
+    pub rank: usize,
+    ///Address to join each peer once it hosts, empty when it is only reachable through the relay
+    pub successors: Vec<String>,
+}
+
+///Read or write errors meaning the peer is gone, unlike a timeout or a bad packet
+pub fn is_drop(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    match e.kind() {
+        UnexpectedEof | ConnectionReset | ConnectionAborted | BrokenPipe => true,
+        _ => false,
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn get_varint(bytes: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = bytes.split_first().ok_or_else(truncated)?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(truncated())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if bytes.len() < n {
+        return Err(truncated());
+    }
+    let (taken, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(taken)
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated packed inputs")
+}
+
+///Packets shorter than that are not worth deflating
+const DEFLATE_MIN_BYTES: usize = 64;
+
+///Command stream of a client, both ends keep the orders of the previous packet. Each order is sent
+///as its bytes, or as the bytes changed from a previous order: most ticks repeat the same
+///selection with another target.
+pub struct CommandDelta {
+    frame_number: i32,
+    previous: Vec<Vec<u8>>,
+}
+
+impl CommandDelta {
+    pub fn new() -> Self {
+        CommandDelta {
+            frame_number: 0,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn encode(&mut self, inputs: &StampedInputs) -> Vec<u8> {
+        let mut packed = Vec::new();
+        let frame_delta = (inputs.frame_number - self.frame_number) as i64;
+        //Zigzag, small negative deltas stay short
+        put_varint(
+            &mut packed,
+            ((frame_delta << 1) ^ (frame_delta >> 63)) as u64,
+        );
+        put_varint(&mut packed, inputs.events.len() as u64);
+        let mut current = Vec::with_capacity(inputs.events.len());
+        for event in inputs.events.iter() {
+            let bytes = bincode::serialize(event).unwrap();
+            //Previous order sharing the longest prefix and suffix
+            let best = self
+                .previous
+                .iter()
+                .enumerate()
+                .map(|(i, old)| {
+                    let prefix = common_prefix(old, &bytes);
+                    let suffix = common_prefix_rev(&old[prefix..], &bytes[prefix..]);
+                    (i, prefix, suffix)
+                })
+                .max_by_key(|(_, prefix, suffix)| prefix + suffix);
+            match best {
+                Some((i, prefix, suffix)) if prefix + suffix > 8 => {
+                    put_varint(&mut packed, i as u64 + 1);
+                    put_varint(&mut packed, prefix as u64);
+                    put_varint(&mut packed, suffix as u64);
+                    let middle = &bytes[prefix..bytes.len() - suffix];
+                    put_varint(&mut packed, middle.len() as u64);
+                    packed.extend_from_slice(middle);
+                }
+                _ => {
+                    put_varint(&mut packed, 0);
+                    put_varint(&mut packed, bytes.len() as u64);
+                    packed.extend_from_slice(&bytes);
+                }
+            }
+            current.push(bytes);
+        }
+        self.frame_number = inputs.frame_number;
+        self.previous = current;
+
+        let mut out = vec![0];
+        if packed.len() >= DEFLATE_MIN_BYTES {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+            let deflated = encoder
+                .write_all(&packed)
+                .and_then(|_| encoder.finish())
+                .unwrap_or_default();
+            if !deflated.is_empty() && deflated.len() < packed.len() {
+                out[0] = 1;
+                out.extend_from_slice(&deflated);
+                return out;
+            }
+        }
+        out.extend_from_slice(&packed);
+        out
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> io::Result<StampedInputs> {
+        let (flag, rest) = bytes.split_first().ok_or_else(truncated)?;
+        let mut inflated = Vec::new();
+        let mut bytes = if *flag == 1 {
+            DeflateDecoder::new(rest).read_to_end(&mut inflated)?;
+            &inflated[..]
+        } else {
+            rest
+        };
+        let zigzag = get_varint(&mut bytes)? as i64;
+        let frame_number = self.frame_number + ((zigzag >> 1) ^ -(zigzag & 1)) as i32;
+        let count = get_varint(&mut bytes)? as usize;
+        let mut current = Vec::with_capacity(count.min(1024));
+        let mut events = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            let reference = get_varint(&mut bytes)? as usize;
+            let event_bytes = if reference == 0 {
+                let len = get_varint(&mut bytes)? as usize;
+                take(&mut bytes, len)?.to_vec()
+            } else {
+                let old = self.previous.get(reference - 1).ok_or_else(truncated)?;
+                let prefix = get_varint(&mut bytes)? as usize;
+                let suffix = get_varint(&mut bytes)? as usize;
+                let len = get_varint(&mut bytes)? as usize;
+                if prefix + suffix > old.len() {
+                    return Err(truncated());
+                }
+                let mut event_bytes = old[..prefix].to_vec();
+                event_bytes.extend_from_slice(take(&mut bytes, len)?);
+                event_bytes.extend_from_slice(&old[old.len() - suffix..]);
+                event_bytes
+            };
+            let event: FrameEventFromPlayer = bincode::deserialize(&event_bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            events.push(event);
+            current.push(event_bytes);
+        }
+        self.frame_number = frame_number;
+        self.previous = current;
+        Ok(StampedInputs {
+            frame_number,
+            events,
+        })
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+fn common_prefix_rev(a: &[u8], b: &[u8]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+///Bytes/sec exchanged with a peer over the last second
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Traffic {
+    pub sent: f32,
+    pub received: f32,
+    ///Size of the orders before the delta compression over their size once packed
+    pub commands_ratio: f32,
+}
+
+pub struct TrafficMeter {
+    start: Instant,
+    sent: usize,
+    received: usize,
+    commands_raw: usize,
+    commands_packed: usize,
+    pub last: Traffic,
+}
+
+impl TrafficMeter {
+    pub fn new() -> Self {
+        TrafficMeter {
+            start: Instant::now(),
+            sent: 0,
+            received: 0,
+            commands_raw: 0,
+            commands_packed: 0,
+            last: Traffic::default(),
+        }
+    }
+
+    pub fn send<T: Serialize>(
+        &mut self,
+        stream: &mut impl Transport,
+        message: &T,
+    ) -> io::Result<()> {
+        let bytes = bincode::serialize(message)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        self.send_encoded(stream, &bytes)
+    }
+
+    pub fn send_encoded(&mut self, stream: &mut impl Transport, bytes: &[u8]) -> io::Result<()> {
+        self.sent += bytes.len();
+        stream.send_encoded(bytes)
+    }
+
+    pub fn try_recv<T: DeserializeOwned>(
+        &mut self,
+        stream: &mut impl Transport,
+    ) -> io::Result<Option<T>> {
+        match stream.try_recv_encoded()? {
+            Some(bytes) => {
+                self.received += bytes.len();
+                bincode::deserialize(&bytes)
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    ///Orders of raw bytes as plain inputs, sent or received as packed bytes
+    pub fn commands(&mut self, raw: usize, packed: usize) {
+        self.commands_raw += raw;
+        self.commands_packed += packed;
+    }
+
+    ///True once a second, when last is updated
+    pub fn tick(&mut self) -> bool {
+        let elapsed = self.start.elapsed().as_secs_f32();
+        if elapsed < 1.0 {
+            return false;
+        }
+        self.last = Traffic {
+            sent: self.sent as f32 / elapsed,
+            received: self.received as f32 / elapsed,
+            commands_ratio: if self.commands_packed > 0 {
+                self.commands_raw as f32 / self.commands_packed as f32
+            } else {
+                1.0
+            },
+        };
+        *self = TrafficMeter {
+            last: self.last,
+            ..TrafficMeter::new()
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Id;
+    use na::Vector3;
+
+    fn move_order(player: u64, units: std::ops::Range<u64>, x: f32) -> FrameEventFromPlayer {
+        FrameEventFromPlayer::MoveOrder {
+            id: Id::new(player),
+            selected: units.map(Id::new).collect(),
+            mouse_world_pos: Vector3::new(x, 200.0, 10.0),
+        }
+    }
+
+    ///Packs each packet on the client side and reads it back on the host side
+    fn round_trip(packets: &[StampedInputs]) {
+        let mut client = CommandDelta::new();
+        let mut host = CommandDelta::new();
+        for inputs in packets {
+            let packed = client.encode(inputs);
+            assert_eq!(&host.decode(&packed).unwrap(), inputs);
+        }
+    }
+
+    #[test]
+    fn empty_inputs() {
+        round_trip(&[
+            StampedInputs {
+                frame_number: 10,
+                events: Vec::new(),
+            },
+            StampedInputs {
+                frame_number: 11,
+                events: vec![move_order(1, 0..3, 50.0)],
+            },
+            StampedInputs {
+                frame_number: 12,
+                events: Vec::new(),
+            },
+            StampedInputs {
+                frame_number: 12,
+                events: vec![move_order(1, 0..3, 60.0)],
+            },
+        ]);
+    }
+
+    #[test]
+    fn repeated_orders() {
+        let packets: Vec<_> = (0..5)
+            .map(|i| StampedInputs {
+                frame_number: 100 + i,
+                events: vec![move_order(1, 0..20, 50.0 + i as f32); 3],
+            })
+            .collect();
+        round_trip(&packets);
+
+        //Orders already sent are only referenced
+        let mut client = CommandDelta::new();
+        let first = client.encode(&packets[0]).len();
+        let second = client.encode(&packets[1]).len();
+        assert!(second < first, "{} bytes after {}", second, first);
+    }
+
+    #[test]
+    fn reordered_orders() {
+        let a = move_order(1, 0..10, 50.0);
+        let b = move_order(1, 10..20, 80.0);
+        let c = move_order(2, 20..30, -5.0);
+        round_trip(&[
+            StampedInputs {
+                frame_number: 5,
+                events: vec![a.clone(), b.clone(), c.clone()],
+            },
+            StampedInputs {
+                frame_number: 6,
+                events: vec![c.clone(), a.clone(), b.clone()],
+            },
+            StampedInputs {
+                frame_number: 4,
+                events: vec![b, c, a],
+            },
+        ]);
+    }
+}
@@ -0,0 +1,151 @@
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+///Rendezvous and relay server reachable by everyone, for hosts that can't forward a port.
+///Both the host and the joiners connect to it, it then pipes their bytes together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relay {
+    pub addr: String,
+    ///Several hosts can share a relay, joiners pick one by its room name
+    pub room: String,
+}
+
+///First message of every connection to the relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Hello {
+    ///Control connection of a host, kept open to receive notices
+    Host {
+        room: String,
+    },
+    Join {
+        room: String,
+    },
+    ///New connection of a host, answering Notice::Joiner
+    Accept {
+        token: u64,
+    },
+}
+
+///Relay to host, on the control connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Notice {
+    Joiner { token: u64 },
+}
+
+#[derive(Default)]
+struct RelayState {
+    hosts: HashMap<String, TcpStream>,
+    ///Joiners waiting for their host to Accept
+    pending: HashMap<u64, TcpStream>,
+}
+
+///Blocking, runs the relay server forever
+pub fn run(bind: &str) {
+    let listener = match TcpListener::bind(bind) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Relay could not bind {}: {}", bind, e);
+            return;
+        }
+    };
+    log::info!("Relay listening on {}", bind);
+    let state = Arc::new(Mutex::new(RelayState::default()));
+    for stream in listener.incoming() {
+        if let Ok(stream) = stream {
+            let state = state.clone();
+            std::thread::spawn(move || handle_connection(stream, &state));
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, state: &Mutex<RelayState>) {
+    let _ = stream.set_nodelay(true);
+    let hello: bincode::Result<Hello> = bincode::deserialize_from(&mut stream);
+    match hello {
+        Ok(Hello::Host { room }) => {
+            log::info!("Relay: host of room {}", room);
+            state.lock().unwrap().hosts.insert(room, stream);
+        }
+        Ok(Hello::Join { room }) => {
+            let mut state = state.lock().unwrap();
+            let token = rand::random::<u64>();
+            let notified = match state.hosts.get_mut(&room) {
+                Some(host) => bincode::serialize_into(host, &Notice::Joiner { token }).is_ok(),
+                None => false,
+            };
+            if notified {
+                log::info!("Relay: joiner {} for room {}", token, room);
+                state.pending.insert(token, stream);
+            } else {
+                log::info!("Relay: no host for room {}", room);
+                state.hosts.remove(&room);
+            }
+        }
+        Ok(Hello::Accept { token }) => {
+            let joiner = state.lock().unwrap().pending.remove(&token);
+            if let Some(joiner) = joiner {
+                splice(stream, joiner);
+            }
+        }
+        Err(e) => log::debug!("Relay: bad hello {:?}", e),
+    }
+}
+
+///Copies bytes both ways until one side closes
+fn splice(a: TcpStream, b: TcpStream) {
+    let (mut a_read, mut b_write) = match (a.try_clone(), b.try_clone()) {
+        (Ok(a_read), Ok(b_write)) => (a_read, b_write),
+        _ => return,
+    };
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut a_read, &mut b_write);
+        let _ = b_write.shutdown(std::net::Shutdown::Both);
+    });
+    let (mut b_read, mut a_write) = (b, a);
+    let _ = std::io::copy(&mut b_read, &mut a_write);
+    let _ = a_write.shutdown(std::net::Shutdown::Both);
+}
+
+///Host side: registers the room, then opens one stream per joiner, sent to s_of_stream
+pub fn host_via(relay: &Relay, s_of_stream: Sender<TcpStream>) -> std::io::Result<()> {
+    let mut control = TcpStream::connect(&relay.addr)?;
+    bincode::serialize_into(
+        &mut control,
+        &Hello::Host {
+            room: relay.room.clone(),
+        },
+    )
+    .map_err(to_io)?;
+    loop {
+        let notice: Notice = bincode::deserialize_from(&mut control).map_err(to_io)?;
+        match notice {
+            Notice::Joiner { token } => {
+                let mut stream = TcpStream::connect(&relay.addr)?;
+                bincode::serialize_into(&mut stream, &Hello::Accept { token }).map_err(to_io)?;
+                if s_of_stream.send(stream).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+///Joiner side: the returned stream behaves as if directly connected to the host
+pub fn join_via(relay: &Relay) -> std::io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(&relay.addr)?;
+    bincode::serialize_into(
+        &mut stream,
+        &Hello::Join {
+            room: relay.room.clone(),
+        },
+    )
+    .map_err(to_io)?;
+    Ok(stream)
+}
+
+fn to_io(e: bincode::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e)
+}
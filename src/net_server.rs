@@ -1,7 +1,14 @@
 use crate::frame::*;
+use crate::net_protocol::{
+    is_drop, Capabilities, ClientPacket, CommandDelta, Handshake, Migration, ServerPacket, Traffic,
+    TrafficMeter,
+};
+use crate::net_relay::{self, Relay};
+use crate::net_sim::{DelayQueue, NetSim};
+use crate::utils::Id;
 use crossbeam_channel::{unbounded, Receiver, Sender};
+use fnv::{FnvHashMap, FnvHashSet};
 use spin_sleep::LoopHelper;
-use std::io::prelude::*;
 use std::net::TcpListener;
 use std::net::TcpStream;
 
@@ -12,18 +19,35 @@ pub enum BindState {
     Error,
 }
 
+///Clients whose traffic is shown
+const MAX_LISTED_PEERS: usize = 8;
+
 #[derive(Debug, Clone, Copy)]
 pub struct NetServerInfo {
     bind_state: BindState,
     number_of_client_connected: usize,
+    ///Traffic with each client, in connection order
+    peers: [Option<Traffic>; MAX_LISTED_PEERS],
 }
 
 pub enum ToNetServerInner {
     DataToComputeNextFrame(DataToComputeNextFrame),
+    ///Encoded replay::Replay for the given peer
+    CatchUp(u32, Vec<u8>),
 }
 
 pub enum FromNetServerInner {
-    PlayerInputs(Vec<FrameEventFromPlayer>),
+    PlayerInputs(Vec<StampedInputs>),
+    Peer(PeerEvent),
+}
+
+///Connections and drops of the clients
+#[derive(Debug, Clone, Copy)]
+pub enum PeerEvent {
+    ///Handshake accepted, the peer waits for its catch up
+    Joined(u32),
+    ///The client of a player dropped, or came back
+    Away { player: Id<Player>, away: bool },
 }
 
 pub struct NetServer {
@@ -31,15 +55,20 @@ pub struct NetServer {
     r_inner: Receiver<FromNetServerInner>,
     info: NetServerInfo,
     r_info: Receiver<NetServerInfo>,
+    ///Received with the inputs, until taken
+    peer_events: Vec<PeerEvent>,
 }
 
 impl NetServer {
-    pub fn new(bind: &str) -> Self {
+    ///host_slot is played on this host and never given to a client
+    pub fn new(bind: &str, relay: Option<Relay>, net_sim: NetSim, host_slot: Option<u8>) -> Self {
         let (s_to, r_to) = unbounded::<ToNetServerInner>();
         let (s_from, r_from) = unbounded::<FromNetServerInner>();
 
         let (s_info, r_info) = unbounded::<NetServerInfo>();
         let bind_addr = bind.to_owned();
+        //Successors host on the same port
+        let port = bind.rsplit(':').next().unwrap_or_default().to_owned();
         std::thread::spawn(move || {
             let r = r_to;
             let s = s_from;
@@ -48,19 +77,48 @@ impl NetServer {
             let mut info = NetServerInfo {
                 bind_state: BindState::Unknown,
                 number_of_client_connected: 0,
+                peers: [None; MAX_LISTED_PEERS],
             };
 
-            let mut net_streams = Vec::new();
+            let mut net_streams: Vec<NetStream> = Vec::new();
+            let mut next_peer = 0;
+            //Players whose client dropped
+            let mut away = FnvHashSet::default();
+            //Player of each slot, from the last frame sent
+            let mut slots = FnvHashMap::default();
+            //Peers in the last Migration sent
+            let mut ranking = Vec::new();
             //Thread that will give us the connected clients
             let (s_bind_state, r_bind_state) = unbounded::<BindState>();
             let (s_of_net_stream, r_of_net_stream) = unbounded::<NetStream>();
+            //Clients that can't reach us directly come through the relay
+            if let Some(relay) = relay {
+                let s_of_net_stream = s_of_net_stream.clone();
+                std::thread::spawn(move || {
+                    let (s_of_stream, r_of_stream) = unbounded::<TcpStream>();
+                    std::thread::spawn(move || {
+                        for stream in r_of_stream.iter() {
+                            log::info!("Connection established through relay!");
+                            let _ = s_of_net_stream.try_send(NetStream::new(stream, net_sim));
+                        }
+                    });
+                    if let Err(e) = net_relay::host_via(&relay, s_of_stream) {
+                        log::error!("Relay {} lost: {}", relay.addr, e);
+                    }
+                });
+            }
             std::thread::spawn(move || match TcpListener::bind(bind_addr) {
                 Ok(listener) => {
                     s_bind_state.send(BindState::Success).unwrap();
                     for stream in listener.incoming() {
                         let stream = stream.unwrap();
                         log::info!("Connection established!");
-                        let net_stream = NetStream::new(stream);
+                        let addr = stream
+                            .peer_addr()
+                            .map(|addr| format!("{}:{}", addr.ip(), port))
+                            .unwrap_or_default();
+                        let mut net_stream = NetStream::new(stream, net_sim);
+                        net_stream.addr = addr;
                         s_of_net_stream.try_send(net_stream).unwrap();
                     }
                 }
@@ -81,34 +139,119 @@ impl NetServer {
                 loop_helper.loop_start();
                 let net_streams = &mut net_streams;
                 match r_of_net_stream.try_recv() {
-                    Ok(net_stream) => {
-                        log::info!("Connection taken care of");
+                    Ok(mut net_stream) => {
+                        log::info!("Connection taken care of (peer {})", next_peer);
+                        net_stream.peer = next_peer;
+                        next_peer += 1;
                         net_streams.push(net_stream);
                     }
                     _ => {}
                 }
 
                 //Block on waiting new frames
-                match r.try_recv() {
-                    Ok(ToNetServerInner::DataToComputeNextFrame(data)) => {
-                        let bytes = bincode::serialize(&data).unwrap();
-                        for net_stream in net_streams.iter_mut() {
-                            net_stream.send_data_to_compute_next_frame(bytes.clone())
+                for msg in r.try_iter() {
+                    match msg {
+                        ToNetServerInner::DataToComputeNextFrame(data) => {
+                            slots = data
+                                .old_frame
+                                .players
+                                .values()
+                                .map(|p| (p.slot, p.id))
+                                .collect();
+                            let bytes = bincode::serialize(&ServerPacket::Frame(data)).unwrap();
+                            for net_stream in net_streams.iter_mut() {
+                                net_stream.send_data_to_compute_next_frame(bytes.clone())
+                            }
+                        }
+                        ToNetServerInner::CatchUp(peer, replay) => {
+                            let bytes = bincode::serialize(&ServerPacket::CatchUp(replay)).unwrap();
+                            for net_stream in net_streams.iter_mut().filter(|n| n.peer == peer) {
+                                net_stream.send_catch_up(bytes.clone());
+                            }
                         }
                     }
-                    _ => {}
                 }
 
                 //Player input
                 let mut player_inputs = Vec::new();
+                let mut taken: Vec<u8> = net_streams.iter().filter_map(|n| n.seat).collect();
+                taken.extend(host_slot);
+                let left: Vec<u8> = slots
+                    .iter()
+                    .filter(|(_, player)| away.contains(*player))
+                    .map(|(seat, _)| *seat)
+                    .collect();
                 for net_stream in net_streams.iter_mut() {
+                    net_stream.player = net_stream.seat.and_then(|seat| slots.get(&seat).cloned());
                     player_inputs.extend(net_stream.collect_remote_player_input());
+                    if net_stream.joined {
+                        net_stream.joined = false;
+                        net_stream.accepted = true;
+                        if let Some(seat) = seat_for(net_stream.asked_seat, &taken, &left) {
+                            log::info!("Peer {} seated in slot {}", net_stream.peer, seat);
+                            taken.push(seat);
+                            net_stream.seat = Some(seat);
+                            net_stream.send_seat(seat);
+                        }
+                        let _ = s
+                            .try_send(FromNetServerInner::Peer(PeerEvent::Joined(net_stream.peer)));
+                    }
+                    if let Some(player) = net_stream.player {
+                        if !net_stream.closed && away.remove(&player) {
+                            log::info!("Player {} is back", player);
+                            let _ = s.try_send(FromNetServerInner::Peer(PeerEvent::Away {
+                                player,
+                                away: false,
+                            }));
+                        }
+                    }
+                }
+                for net_stream in net_streams.iter().filter(|n| n.closed) {
+                    if let Some(player) = net_stream.player {
+                        log::warn!("Player {} dropped", player);
+                        away.insert(player);
+                        let _ = s.try_send(FromNetServerInner::Peer(PeerEvent::Away {
+                            player,
+                            away: true,
+                        }));
+                    }
+                }
+                net_streams.retain(|net_stream| !net_stream.closed);
+
+                //Players first, then spectators, in connection order. Peers without the
+                //capability would neither read their rank nor host
+                let mut order: Vec<usize> = (0..net_streams.len())
+                    .filter(|i| {
+                        let net_stream = &net_streams[*i];
+                        net_stream.accepted
+                            && net_stream.capabilities.contains(Capabilities::MIGRATION)
+                    })
+                    .collect();
+                order.sort_by_key(|i| net_streams[*i].player.is_none());
+                let new_ranking: Vec<u32> = order.iter().map(|i| net_streams[*i].peer).collect();
+                if new_ranking != ranking {
+                    let successors: Vec<String> =
+                        order.iter().map(|i| net_streams[*i].addr.clone()).collect();
+                    for (rank, i) in order.iter().enumerate() {
+                        let migration = Migration {
+                            rank,
+                            successors: successors.clone(),
+                        };
+                        let bytes =
+                            bincode::serialize(&ServerPacket::Migration(migration)).unwrap();
+                        net_streams[*i].send_migration(bytes);
+                    }
+                    ranking = new_ranking;
                 }
 
                 let _ = s.try_send(FromNetServerInner::PlayerInputs(player_inputs));
 
                 //Info update
                 info.number_of_client_connected = net_streams.len();
+                info.peers = [None; MAX_LISTED_PEERS];
+                for (peer, net_stream) in info.peers.iter_mut().zip(net_streams.iter()) {
+                    *peer = Some(net_stream.traffic);
+                }
                 s_info.try_send(info).unwrap();
             }
         });
@@ -118,17 +261,20 @@ impl NetServer {
             info: NetServerInfo {
                 bind_state: BindState::Unknown,
                 number_of_client_connected: 0,
+                peers: [None; MAX_LISTED_PEERS],
             },
             r_info,
+            peer_events: Vec::new(),
         }
     }
     pub fn kill(&mut self) {}
 
-    pub fn collect_remote_players_inputs(&mut self) -> Vec<FrameEventFromPlayer> {
+    pub fn collect_remote_players_inputs(&mut self) -> Vec<StampedInputs> {
         let mut pis = Vec::new();
         for msg in self.r_inner.try_iter() {
             match msg {
                 FromNetServerInner::PlayerInputs(player_inputs) => pis.extend(player_inputs),
+                FromNetServerInner::Peer(event) => self.peer_events.push(event),
             }
         }
         pis
@@ -140,6 +286,17 @@ impl NetServer {
             .try_send(ToNetServerInner::DataToComputeNextFrame(data));
     }
 
+    pub fn take_peer_events(&mut self) -> Vec<PeerEvent> {
+        std::mem::replace(&mut self.peer_events, Vec::new())
+    }
+
+    ///Sent to the peer before its next frame
+    pub fn send_catch_up(&mut self, peer: u32, replay: Vec<u8>) {
+        let _ = self
+            .s_inner
+            .try_send(ToNetServerInner::CatchUp(peer, replay));
+    }
+
     pub fn get_info(&mut self) -> NetServerInfo {
         let last = self.r_info.try_iter().last();
         if let Some(info) = last {
@@ -149,21 +306,56 @@ impl NetServer {
     }
 }
 
+///Slot of a joining peer: the one it asks back unless someone plays it, else the lowest one
+///nobody plays nor left
+fn seat_for(asked: Option<u8>, taken: &[u8], left: &[u8]) -> Option<u8> {
+    match asked {
+        Some(seat) if !taken.contains(&seat) => Some(seat),
+        _ => (0..=u8::max_value()).find(|s| !taken.contains(s) && !left.contains(s)),
+    }
+}
+
 enum ToNetStream {
     DataToComputeNextFrame(Vec<u8>),
+    CatchUp(Vec<u8>),
+    Seat(u8),
+    Migration(Vec<u8>),
 }
 
 enum FromNetStream {
-    PlayerInput(Vec<FrameEventFromPlayer>),
+    PlayerInput(StampedInputs),
+    Traffic(Traffic),
+    ///With the slot the client asks back
+    Accepted(Capabilities, Option<u8>),
+    ///Refused at the handshake, or dropped
+    Closed,
 }
 
 struct NetStream {
     r: Receiver<FromNetStream>,
     s: Sender<ToNetStream>,
+    ///Rank of the connection
+    peer: u32,
+    ///To join it once it hosts, empty through the relay
+    addr: String,
+    ///In its slot, None for a spectator or before the first frame
+    player: Option<Id<Player>>,
+    ///Slot of its player, given once accepted
+    seat: Option<u8>,
+    ///Slot it played before it lost the host
+    asked_seat: Option<u8>,
+    ///Accepted, not yet reported
+    joined: bool,
+    ///Handshake done, ranked in the Migration
+    accepted: bool,
+    ///Of both peers, known once accepted
+    capabilities: Capabilities,
+    closed: bool,
+    traffic: Traffic,
 }
 
 impl NetStream {
-    fn new(stream: TcpStream) -> Self {
+    fn new(stream: TcpStream, net_sim: NetSim) -> Self {
         let (s_to, r_to) = unbounded::<ToNetStream>();
         let (s_from, r_from) = unbounded::<FromNetStream>();
 
@@ -173,46 +365,158 @@ impl NetStream {
             let _ = stream.set_nodelay(true);
             let r = r_to;
             let s = s_from;
+            let local = Handshake::local();
+            //No frame nor input before the client said hello
+            let mut accepted = false;
+            let mut capabilities = Capabilities::NONE;
+            let mut meter = TrafficMeter::new();
+            let mut delta = CommandDelta::new();
+            let mut delayed = DelayQueue::new(net_sim);
             let mut loop_helper = LoopHelper::builder().build_with_target_rate(100.0_f64);
             loop {
                 loop_helper.loop_sleep();
                 loop_helper.loop_start();
                 log::trace!("read");
-                let result_bincode: bincode::Result<Vec<FrameEventFromPlayer>> =
-                    bincode::deserialize_from(&mut stream);
-                match result_bincode {
-                    Ok(player_inputs) => {
+                let result: std::io::Result<Option<ClientPacket>> = meter.try_recv(&mut stream);
+                match result {
+                    Ok(Some(ClientPacket::Hello(remote))) => match local.accept(&remote) {
+                        Ok(common) => {
+                            log::info!(
+                                "Client runs oxidator {} (protocol {})",
+                                remote.build,
+                                remote.version
+                            );
+                            let _ = meter.send(&mut stream, &ServerPacket::Hello(local.clone()));
+                            accepted = true;
+                            capabilities = common;
+                            let _ = s.try_send(FromNetStream::Accepted(common, remote.seat));
+                        }
+                        Err(reason) => {
+                            log::warn!("Client refused: {}", reason);
+                            let _ = meter.send(&mut stream, &ServerPacket::Refused(reason));
+                            let _ = s.try_send(FromNetStream::Closed);
+                            return;
+                        }
+                    },
+                    Ok(Some(ClientPacket::Inputs(player_inputs))) if accepted => {
                         log::trace!(
                             "   Receive player_inputs ({}) from remote client",
-                            player_inputs.len()
+                            player_inputs.events.len()
                         );
-                        let _ = s.try_send(FromNetStream::PlayerInput(player_inputs));
+                        let raw = bincode::serialized_size(&player_inputs).unwrap_or(0) as usize;
+                        meter.commands(raw, raw);
+                        delayed.push(player_inputs);
+                    }
+                    Ok(Some(ClientPacket::PackedInputs(packed))) if accepted => {
+                        match delta.decode(&packed) {
+                            Ok(player_inputs) => {
+                                let raw = bincode::serialized_size(&player_inputs).unwrap_or(0);
+                                meter.commands(raw as usize, packed.len());
+                                delayed.push(player_inputs);
+                            }
+                            //The following ones would be decoded against the wrong orders
+                            Err(e) => {
+                                log::error!("Client sent unreadable inputs: {}", e);
+                                let _ = s.try_send(FromNetStream::Closed);
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) if is_drop(&e) => {
+                        log::warn!("Client dropped: {}", e);
+                        let _ = s.try_send(FromNetStream::Closed);
+                        return;
                     }
                     x => {
                         log::trace!("   Error read {:?}", x);
                     }
                 }
+                for player_inputs in delayed.pop_ready() {
+                    let _ = s.try_send(FromNetStream::PlayerInput(player_inputs));
+                }
 
-                //Send last frame to remote player
-                if !r.is_empty() {
-                    match r.try_iter().last().unwrap() {
-                        ToNetStream::DataToComputeNextFrame(data) => {
-                            log::debug!("Send frame to remote player ({} bytes)", data.len());
-                            let _ = stream.write_all(&data).unwrap();
-                            stream.flush().unwrap();
+                //Send catch up and last frame to remote player
+                let mut last_frame = None;
+                let mut sent = Ok(());
+                for msg in r.try_iter() {
+                    match msg {
+                        ToNetStream::CatchUp(data)
+                            if capabilities.contains(Capabilities::CATCH_UP) =>
+                        {
+                            log::info!("Send catch up to remote player ({} bytes)", data.len());
+                            sent = sent.and_then(|_| meter.send_encoded(&mut stream, &data));
+                        }
+                        //Older clients would not read it
+                        ToNetStream::Seat(seat) if capabilities.contains(Capabilities::SEATS) => {
+                            sent = sent
+                                .and_then(|_| meter.send(&mut stream, &ServerPacket::Seat(seat)));
+                        }
+                        ToNetStream::Migration(data)
+                            if capabilities.contains(Capabilities::MIGRATION) =>
+                        {
+                            sent = sent.and_then(|_| meter.send_encoded(&mut stream, &data));
                         }
+                        ToNetStream::DataToComputeNextFrame(data) if accepted => {
+                            last_frame = Some(data)
+                        }
+                        _ => {}
                     }
                 }
+                if let Some(data) = last_frame {
+                    log::debug!("Send frame to remote player ({} bytes)", data.len());
+                    sent = sent.and_then(|_| meter.send_encoded(&mut stream, &data));
+                }
+                if let Err(e) = sent {
+                    log::warn!("Client dropped: {}", e);
+                    let _ = s.try_send(FromNetStream::Closed);
+                    return;
+                }
+                if meter.tick() {
+                    let _ = s.try_send(FromNetStream::Traffic(meter.last));
+                }
             }
         });
-        NetStream { s: s_to, r: r_from }
+        NetStream {
+            s: s_to,
+            r: r_from,
+            peer: 0,
+            addr: String::new(),
+            player: None,
+            seat: None,
+            asked_seat: None,
+            joined: false,
+            accepted: false,
+            capabilities: Capabilities::NONE,
+            closed: false,
+            traffic: Traffic::default(),
+        }
     }
 
-    pub fn collect_remote_player_input(&mut self) -> Vec<FrameEventFromPlayer> {
+    pub fn collect_remote_player_input(&mut self) -> Vec<StampedInputs> {
         let mut pis = Vec::new();
         for msg in self.r.try_iter() {
             match msg {
-                FromNetStream::PlayerInput(player_inputs) => pis.extend(player_inputs),
+                FromNetStream::PlayerInput(mut player_inputs) => {
+                    //A client only speaks for the player of the slot we gave it
+                    let player = self.player;
+                    player_inputs
+                        .events
+                        .retain(|event| match event.player_id() {
+                            Some(id) if Some(id) == player => true,
+                            id => {
+                                log::warn!("Peer {} sent an event of player {:?}", self.peer, id);
+                                false
+                            }
+                        });
+                    pis.push(player_inputs)
+                }
+                FromNetStream::Traffic(traffic) => self.traffic = traffic,
+                FromNetStream::Accepted(capabilities, asked_seat) => {
+                    self.joined = true;
+                    self.capabilities = capabilities;
+                    self.asked_seat = asked_seat;
+                }
+                FromNetStream::Closed => self.closed = true,
             }
         }
         pis
@@ -220,4 +524,42 @@ impl NetStream {
     pub fn send_data_to_compute_next_frame(&mut self, data: Vec<u8>) {
         let _ = self.s.try_send(ToNetStream::DataToComputeNextFrame(data));
     }
+
+    pub fn send_catch_up(&mut self, data: Vec<u8>) {
+        let _ = self.s.try_send(ToNetStream::CatchUp(data));
+    }
+
+    pub fn send_seat(&mut self, seat: u8) {
+        let _ = self.s.try_send(ToNetStream::Seat(seat));
+    }
+
+    pub fn send_migration(&mut self, data: Vec<u8>) {
+        let _ = self.s.try_send(ToNetStream::Migration(data));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn players_reconnecting_in_swapped_order_get_their_seats() {
+        //The host plays slot 0
+        let a = seat_for(None, &[0], &[]).unwrap();
+        let b = seat_for(None, &[0, a], &[]).unwrap();
+        assert_eq!((a, b), (1, 2));
+
+        //Both drop, a newcomer doesn't take their slots
+        let newcomer = seat_for(None, &[0], &[a, b]).unwrap();
+        assert_eq!(newcomer, 3);
+
+        let b_back = seat_for(Some(b), &[0, newcomer], &[a, b]).unwrap();
+        let a_back = seat_for(Some(a), &[0, newcomer, b_back], &[a]).unwrap();
+        assert_eq!((a_back, b_back), (a, b));
+    }
+
+    #[test]
+    fn taken_seat_is_not_given_back() {
+        assert_eq!(seat_for(Some(0), &[0, 1], &[]), Some(2));
+    }
 }
@@ -0,0 +1,83 @@
+use std::time::{Duration, Instant};
+
+///Minimum tcp retransmission timeout, paid again by a lost message
+const RESEND_MS: u32 = 200;
+
+///Debug conditions applied to received net messages, to test lockstep stalls between local peers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetSim {
+    pub latency_ms: u32,
+    ///Random extra latency, up to this value
+    pub jitter_ms: u32,
+    ///Probability for a message to be lost once. Streams are tcp, so it arrives after a resend.
+    pub loss: f32,
+    ///Jitter may deliver a message before an older one
+    pub reorder: bool,
+}
+
+impl NetSim {
+    pub fn none() -> Self {
+        NetSim {
+            latency_ms: 0,
+            jitter_ms: 0,
+            loss: 0.0,
+            reorder: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        *self != Self::none()
+    }
+
+    fn delay(&self) -> Duration {
+        let mut ms = self.latency_ms;
+        if self.jitter_ms > 0 {
+            ms += rand::random::<u32>() % (self.jitter_ms + 1);
+        }
+        if rand::random::<f32>() < self.loss {
+            ms += RESEND_MS.max(2 * self.latency_ms);
+        }
+        Duration::from_millis(ms as u64)
+    }
+}
+
+///Holds messages until their simulated arrival
+pub struct DelayQueue<T> {
+    sim: NetSim,
+    queue: Vec<(Instant, T)>,
+    last_release: Instant,
+}
+
+impl<T> DelayQueue<T> {
+    pub fn new(sim: NetSim) -> Self {
+        DelayQueue {
+            sim,
+            queue: Vec::new(),
+            last_release: Instant::now(),
+        }
+    }
+
+    pub fn push(&mut self, msg: T) {
+        let mut release = Instant::now() + self.sim.delay();
+        if !self.sim.reorder {
+            release = release.max(self.last_release);
+        }
+        self.last_release = self.last_release.max(release);
+        self.queue.push((release, msg));
+    }
+
+    ///Messages that arrived, in arrival order
+    pub fn pop_ready(&mut self) -> Vec<T> {
+        if !self.sim.is_active() {
+            return self.queue.drain(..).map(|(_, msg)| msg).collect();
+        }
+        let now = Instant::now();
+        self.queue.sort_by_key(|(release, _)| *release);
+        let ready = self
+            .queue
+            .iter()
+            .take_while(|(release, _)| *release <= now)
+            .count();
+        self.queue.drain(..ready).map(|(_, msg)| msg).collect()
+    }
+}
@@ -0,0 +1,57 @@
+//!File io, timing and transport of the client, native or in a browser (wasm32)
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(target_arch = "wasm32")]
+mod web;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{connect, prepare, storage, Connection, Instant};
+#[cfg(target_arch = "wasm32")]
+pub use web::{connect, storage, Connection, Instant};
+
+///Small text files kept between sessions, like settings.json
+pub trait Storage {
+    fn read_to_string(&self, path: &str) -> io::Result<String>;
+    fn write(&self, path: &str, contents: &str) -> io::Result<()>;
+}
+
+///Bincode messages to and from a remote peer, one packet each, see net_protocol
+pub trait Transport {
+    ///Message already serialized, when the same one goes to several peers
+    fn send_encoded(&mut self, bytes: &[u8]) -> io::Result<()>;
+    ///None when nothing arrived yet
+    fn try_recv_encoded(&mut self) -> io::Result<Option<Vec<u8>>>;
+
+    fn send<T: Serialize>(&mut self, message: &T) -> io::Result<()> {
+        let bytes = bincode::serialize(message)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        self.send_encoded(&bytes)
+    }
+
+    ///Trailing bytes are fields appended by a newer build
+    fn try_recv<T: DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        match self.try_recv_encoded()? {
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None => Ok(None),
+        }
+    }
+}
+
+///Json of path in the storage of the platform
+pub fn read_json<T: DeserializeOwned>(path: &str) -> Result<T, String> {
+    let text = storage()
+        .read_to_string(path)
+        .map_err(|e| format!("{}", e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}", e))
+}
+
+pub fn write_json<T: Serialize>(path: &str, value: &T) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(value).map_err(|e| format!("{}", e))?;
+    storage().write(path, &text).map_err(|e| format!("{}", e))
+}
@@ -0,0 +1,84 @@
+use super::{Storage, Transport};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+pub use std::time::Instant;
+
+///Files relative to the working directory
+pub struct Files;
+
+impl Storage for Files {
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &str, contents: &str) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+}
+
+pub fn storage() -> Files {
+    Files
+}
+
+pub type Connection = TcpStream;
+
+///A longer packet means the peer speaks another protocol
+const MAX_PACKET_BYTES: usize = 256 << 20;
+///Wait for the rest of a packet once its length arrived
+const PACKET_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+///Each message is its length in 4 bytes then its bincode
+impl Transport for TcpStream {
+    fn send_encoded(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.write_all(bytes)?;
+        self.flush()
+    }
+
+    fn try_recv_encoded(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut length = [0; 4];
+        match self.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        }
+        let length = u32::from_le_bytes(length) as usize;
+        if length > MAX_PACKET_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "packet of {} bytes, the peer speaks another protocol",
+                    length
+                ),
+            ));
+        }
+        let timeout = self.read_timeout()?;
+        self.set_read_timeout(Some(PACKET_TIMEOUT))?;
+        let mut payload = vec![0; length];
+        let read = self.read_exact(&mut payload);
+        self.set_read_timeout(timeout)?;
+        read?;
+        Ok(Some(payload))
+    }
+}
+
+///Tcp to the host, with a short read timeout so that try_recv doesn't block the net loop
+pub fn connect(addr: &str) -> io::Result<Connection> {
+    let addr = addr
+        .parse::<std::net::SocketAddr>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, addr.to_owned()))?;
+    let stream = TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(2))?;
+    prepare(&stream);
+    Ok(stream)
+}
+
+///Also applied to streams obtained elsewhere, like through the relay
+pub fn prepare(stream: &TcpStream) {
+    let _ = stream.set_read_timeout(Some(std::time::Duration::from_millis(2)));
+    let _ = stream.set_nodelay(true);
+}
@@ -0,0 +1,109 @@
+use super::{Storage, Transport};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+///std::time::Instant panics in the browser, performance.now is used instead
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Instant {
+    ms: f64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        let ms = web_sys::window()
+            .and_then(|window| window.performance())
+            .map(|performance| performance.now())
+            .unwrap_or(0.0);
+        Instant { ms }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64((Instant::now().ms - self.ms).max(0.0) / 1000.0)
+    }
+}
+
+///The path is the key in the localStorage of the page
+pub struct LocalStorage;
+
+impl LocalStorage {
+    fn get(&self) -> io::Result<web_sys::Storage> {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok())
+            .flatten()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no localStorage"))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        self.get()?
+            .get_item(path)
+            .map_err(to_io)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.to_owned()))
+    }
+
+    fn write(&self, path: &str, contents: &str) -> io::Result<()> {
+        self.get()?.set_item(path, contents).map_err(to_io)
+    }
+}
+
+pub fn storage() -> LocalStorage {
+    LocalStorage
+}
+
+///One bincode message per binary WebSocket message
+pub struct Connection {
+    socket: WebSocket,
+    received: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    ///Sent once the socket is open
+    pending: Vec<Vec<u8>>,
+}
+
+impl Transport for Connection {
+    fn send_encoded(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.pending.push(bytes.to_vec());
+        if self.socket.ready_state() == WebSocket::OPEN {
+            for bytes in self.pending.drain(..) {
+                self.socket.send_with_u8_array(&bytes).map_err(to_io)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn try_recv_encoded(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.received.borrow_mut().pop_front())
+    }
+}
+
+///WebSocket to ws://addr, the browser can't open raw tcp streams
+pub fn connect(addr: &str) -> io::Result<Connection> {
+    let socket = WebSocket::new(&format!("ws://{}", addr)).map_err(to_io)?;
+    socket.set_binary_type(BinaryType::Arraybuffer);
+    let received = Rc::new(RefCell::new(VecDeque::new()));
+    let received_in_callback = received.clone();
+    let on_message = Closure::wrap(Box::new(move |e: MessageEvent| {
+        if let Ok(buffer) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+            received_in_callback
+                .borrow_mut()
+                .push_back(js_sys::Uint8Array::new(&buffer).to_vec());
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    //Lives as long as the page
+    on_message.forget();
+    Ok(Connection {
+        socket,
+        received,
+        pending: Vec::new(),
+    })
+}
+
+fn to_io(e: JsValue) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
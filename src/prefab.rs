@@ -0,0 +1,143 @@
+use crate::botdef::BotDef;
+use crate::frame::Player;
+use crate::heightmap_phy::HeightmapPhy;
+use crate::mobile::{Angle, BuildQueue, FireStance, KBot, MoveStance};
+use crate::utils::Id;
+use na::{Point3, Rotation2, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+///State a unit of a prefab starts in, instead of the one of a fresh unit
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Preset {
+    ///Below 1 the unit is a construction site waiting for builders
+    pub con_completed: f32,
+    ///Part of the max_life of the botdef, a construction site has at most its con_completed
+    pub life: f32,
+    pub fire_stance: FireStance,
+    pub move_stance: MoveStance,
+    ///Paths of the botdefs queued by a factory
+    pub build_queue: Vec<String>,
+    pub repeat: bool,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset {
+            con_completed: 1.0,
+            life: 1.0,
+            fire_stance: FireStance::FireAtWill,
+            move_stance: MoveStance::Maneuver,
+            build_queue: Vec::new(),
+            repeat: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PrefabUnit {
+    ///Path of the botdef json
+    pub botdef: String,
+    ///m from the origin of the prefab, turned with it
+    pub offset: [f32; 2],
+    ///rad, from the angle of the prefab
+    #[serde(default)]
+    pub angle: f32,
+    #[serde(default)]
+    pub preset: Preset,
+}
+
+///Units laid out together, ex: a base already built for a mission. Read from src/asset/prefab.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Prefab {
+    pub name: String,
+    pub units: Vec<PrefabUnit>,
+}
+
+///Unit of a prefab placed on the map, see FrameEventFromPlayer::SpawnPrefab
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PrefabSpawn {
+    pub botdef_id: Id<BotDef>,
+    pub x: f32,
+    pub y: f32,
+    ///rad
+    pub angle: f32,
+    pub con_completed: f32,
+    pub life: f32,
+    pub fire_stance: FireStance,
+    pub move_stance: MoveStance,
+    pub build_queue: BuildQueue,
+}
+
+impl Prefab {
+    pub fn open(path: &str) -> std::result::Result<Self, String> {
+        std::fs::File::open(path)
+            .map_err(|e| format!("{}", e))
+            .and_then(|file| {
+                serde_json::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}", e))
+            })
+    }
+
+    ///Units with the origin of the prefab at x, y and turned by angle rad, with the botdefs
+    ///they need. Fails when a botdef can't be read.
+    pub fn place(
+        &self,
+        x: f32,
+        y: f32,
+        angle: f32,
+    ) -> std::result::Result<(Vec<BotDef>, Vec<PrefabSpawn>), String> {
+        let mut bot_defs: Vec<BotDef> = Vec::new();
+        let mut load = |path: &str| -> std::result::Result<Id<BotDef>, String> {
+            if let Some(botdef) = bot_defs.iter().find(|b| b.file_path == path) {
+                return Ok(botdef.id);
+            }
+            let botdef = BotDef::open(path).map_err(|e| format!("{}: {}", path, e))?;
+            let id = botdef.id;
+            bot_defs.push(botdef);
+            Ok(id)
+        };
+        let rotation = Rotation2::new(angle);
+        let mut spawns = Vec::with_capacity(self.units.len());
+        for unit in self.units.iter() {
+            let offset = rotation * Vector2::new(unit.offset[0], unit.offset[1]);
+            let preset = &unit.preset;
+            let mut build_queue = BuildQueue::new();
+            for path in preset.build_queue.iter() {
+                build_queue.items.push(load(path)?);
+            }
+            build_queue.repeat = preset.repeat;
+            spawns.push(PrefabSpawn {
+                botdef_id: load(&unit.botdef)?,
+                x: x + offset.x,
+                y: y + offset.y,
+                angle: angle + unit.angle,
+                con_completed: preset.con_completed.max(std::f32::MIN_POSITIVE).min(1.0),
+                life: preset.life.max(0.0).min(1.0),
+                fire_stance: preset.fire_stance,
+                move_stance: preset.move_stance,
+                build_queue,
+            });
+        }
+        Ok((bot_defs, spawns))
+    }
+}
+
+impl PrefabSpawn {
+    ///On the ground, inside the map
+    pub fn kbot(&self, botdef: &BotDef, heightmap: &HeightmapPhy, player: &Player) -> KBot {
+        let x = self.x.max(0.0).min(heightmap.width as f32 - 1.0);
+        let y = self.y.max(0.0).min(heightmap.height as f32 - 1.0);
+        let mut kbot = KBot::new(Point3::new(x, y, heightmap.z(x, y)), botdef, player.id);
+        kbot.team = player.team;
+        kbot.angle = Angle::new(self.angle);
+        kbot.dir = Vector3::new(self.angle.cos(), self.angle.sin(), 0.0);
+        kbot.weapon0_dir = kbot.dir;
+        kbot.con_completed = self.con_completed;
+        kbot.life =
+            ((botdef.max_life as f32 * self.life.min(self.con_completed)).ceil() as i32).max(1);
+        kbot.fire_stance = self.fire_stance;
+        kbot.move_stance = self.move_stance;
+        kbot.build_queue = self.build_queue.clone();
+        kbot
+    }
+}
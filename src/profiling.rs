@@ -0,0 +1,14 @@
+///Span covering the rest of the enclosing block, shown in the tracy viewer when built
+///with the profile_tracy feature, compiled out otherwise
+macro_rules! profile_scope {
+    ($name:expr) => {
+        #[cfg(feature = "profile_tracy")]
+        let _profile_scope = tracy_client::Span::new($name, module_path!(), file!(), line!(), 0);
+    };
+}
+
+///End of a rendered frame, for the frame graph of the viewer
+pub fn frame_mark() {
+    #[cfg(feature = "profile_tracy")]
+    tracy_client::finish_continuous_frame!();
+}
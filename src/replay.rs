@@ -0,0 +1,387 @@
+use crate::frame::{DataToComputeNextFrame, Frame, FrameEventFromPlayer};
+use crate::heightmap_phy::HeightmapPhy;
+use crate::mobile::CraterEvent;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use fnv::FnvHashMap;
+use serde::{Deserialize, Serialize};
+
+pub const REPLAY_DIR: &str = "replays";
+///Frames between two snapshots, 30 sec
+pub const SNAPSHOT_FRAMES: i32 = 300;
+///Written ahead of each replay and autosave, raised by every change of what they hold as bincode
+///can't read the files of another one
+pub const VERSION: u32 = 4;
+
+///Game start and the orders given, the frames are simulated again to watch it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    ///First frame, with its heightmap
+    pub start: Frame,
+    ///Orders applied when computing the frame following each frame number
+    pub events: FnvHashMap<i32, Vec<FrameEventFromPlayer>>,
+    pub last_frame: i32,
+    ///Whole frames every SNAPSHOT_FRAMES, oldest first, seeking simulates from the nearest one.
+    ///Without their heightmap, see heightmap_at. Their id_seed and number give the ids drawn
+    ///next, see Frame::ids_seed.
+    #[serde(default)]
+    pub snapshots: Vec<Frame>,
+    ///Every crater of the game with the number of its frame, oldest first
+    #[serde(default)]
+    pub craters: Vec<(i32, CraterEvent)>,
+}
+
+impl Replay {
+    ///start carries its heightmap, with its own craters already dug for a game going on
+    pub fn new(start: Frame) -> Self {
+        Replay {
+            last_frame: start.number,
+            start,
+            events: FnvHashMap::default(),
+            snapshots: Vec::new(),
+            craters: Vec::new(),
+        }
+    }
+
+    ///Orders of data, keyed by the frame they are applied to, the replaced one when there is one.
+    ///The craters and a snapshot of the old frame are kept too, unless it is replaced.
+    pub fn record(&mut self, data: &DataToComputeNextFrame) {
+        let mut base = data.old_frame.number;
+        let mut replaced = false;
+        let mut events = Vec::new();
+        for event in data.events.iter() {
+            match event {
+                FrameEventFromPlayer::ReplaceFrame(frame) => {
+                    base = frame.number;
+                    replaced = true;
+                }
+                //Only changes the pace of the game
+                FrameEventFromPlayer::SpeedVote { .. } => {}
+                event => events.push(event.clone()),
+            }
+        }
+        if !replaced {
+            self.keep(&data.old_frame);
+        }
+        if !events.is_empty() {
+            self.events.insert(base, events);
+        }
+        self.last_frame = base + 1;
+    }
+
+    ///Records again a frame the rollback simulated again with late remote orders. Rollbacks
+    ///don't cross craters nor frame replacements, only the orders and the snapshot change.
+    pub fn correct(&mut self, data: &DataToComputeNextFrame) {
+        let number = data.old_frame.number;
+        let events: Vec<_> = data
+            .events
+            .iter()
+            .filter(|event| match event {
+                FrameEventFromPlayer::SpeedVote { .. } => false,
+                _ => true,
+            })
+            .cloned()
+            .collect();
+        if events.is_empty() {
+            self.events.remove(&number);
+        } else {
+            self.events.insert(number, events);
+        }
+        if let Some(snapshot) = self.snapshots.iter_mut().find(|s| s.number == number) {
+            *snapshot = data.old_frame.clone();
+            snapshot.heightmap_phy = None;
+        }
+    }
+
+    fn keep(&mut self, frame: &Frame) {
+        let number = frame.number;
+        //Those of the start frame are already in its heightmap
+        if number > self.start.number {
+            self.craters
+                .extend(frame.craters.iter().map(|crater| (number, crater.clone())));
+        }
+        let newest = self.snapshots.last().unwrap_or(&self.start).number;
+        if number >= newest + SNAPSHOT_FRAMES {
+            let mut snapshot = frame.clone();
+            snapshot.heightmap_phy = None;
+            self.snapshots.push(snapshot);
+        }
+    }
+
+    ///Start heightmap with the craters dug until the frame number
+    pub fn heightmap_at(&self, number: i32) -> Option<HeightmapPhy> {
+        let mut heightmap = self.start.heightmap_phy.clone()?;
+        for (_, crater) in self.craters.iter().take_while(|(n, _)| *n <= number) {
+            heightmap.crater(
+                crater.position.x,
+                crater.position.y,
+                crater.radius,
+                crater.depth,
+            );
+        }
+        Some(heightmap)
+    }
+
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}", e))?;
+        Self::decode(&bytes)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let _ = std::fs::create_dir_all(REPLAY_DIR);
+        std::fs::write(path, self.encode()?).map_err(|e| format!("{}", e))
+    }
+
+    ///Gzipped bincode of VERSION then the replay, as in the files and the catch up of a joining
+    ///client
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        bincode::serialize_into(&mut encoder, &VERSION).map_err(|e| format!("{}", e))?;
+        bincode::serialize_into(&mut encoder, self).map_err(|e| format!("{}", e))?;
+        encoder.finish().map_err(|e| format!("{}", e))
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut decoder = GzDecoder::new(bytes);
+        let version: u32 = bincode::deserialize_from(&mut decoder).map_err(|e| format!("{}", e))?;
+        if version != VERSION {
+            return Err(format!(
+                "replay version {}, this build reads {}",
+                version, VERSION
+            ));
+        }
+        bincode::deserialize_from(decoder).map_err(|e| format!("{}", e))
+    }
+
+    ///What a client joining now needs: the latest snapshot with its heightmap, then the orders and
+    ///craters since
+    pub fn catch_up(&self) -> Replay {
+        let mut start = self.snapshots.last().unwrap_or(&self.start).clone();
+        let number = start.number;
+        start.heightmap_phy = self.heightmap_at(number);
+        Replay {
+            start,
+            events: self
+                .events
+                .iter()
+                .filter(|(base, _)| **base >= number)
+                .map(|(base, events)| (*base, events.clone()))
+                .collect(),
+            last_frame: self.last_frame,
+            snapshots: Vec::new(),
+            craters: self
+                .craters
+                .iter()
+                .filter(|(n, _)| *n > number)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    ///Timestamped path in REPLAY_DIR
+    pub fn new_path() -> String {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        format!("{}/replay-{:012}.bin", REPLAY_DIR, timestamp)
+    }
+
+    ///Latest replay written, names hold a fixed width timestamp
+    pub fn latest() -> Option<String> {
+        let mut paths: Vec<String> = std::fs::read_dir(REPLAY_DIR)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().to_string_lossy().into_owned())
+            .filter(|path| path.ends_with(".bin"))
+            .collect();
+        paths.sort();
+        paths.pop()
+    }
+}
+
+///Replay being watched by the manager
+pub struct Playback {
+    pub replay: Replay,
+    ///Frames are simulated without waiting nor being shown until this one
+    pub seek_to: Option<i32>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback {
+            replay,
+            seek_to: None,
+        }
+    }
+
+    pub fn events(&self, base: i32) -> Vec<FrameEventFromPlayer> {
+        self.replay.events.get(&base).cloned().unwrap_or_default()
+    }
+
+    ///Once the frame sought is reached, returns the heightmap for the client, which missed the
+    ///craters on the way
+    pub fn observe(&mut self, frame: &Frame) -> Option<HeightmapPhy> {
+        if self.seek_to.map(|to| frame.number >= to).unwrap_or(false) {
+            self.seek_to = None;
+            return self.replay.heightmap_at(frame.number);
+        }
+        None
+    }
+
+    ///Snapshot to restart from to reach the frame to, None when simulating forward from current
+    ///is shorter
+    pub fn seek(&mut self, current: i32, to: i32) -> Option<Frame> {
+        let to = to.max(self.replay.start.number).min(self.replay.last_frame);
+        self.seek_to = Some(to);
+        let snapshot = std::iter::once(&self.replay.start)
+            .chain(self.replay.snapshots.iter())
+            .rev()
+            .find(|s| s.number <= to)?;
+        if to >= current && snapshot.number <= current {
+            return None;
+        }
+        let mut snapshot = snapshot.clone();
+        snapshot.heightmap_phy = self.replay.heightmap_at(snapshot.number);
+        Some(snapshot)
+    }
+
+    pub fn is_seeking(&self) -> bool {
+        self.seek_to.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::botdef::BotDef;
+    use crate::determinism::checksum;
+    use crate::frame::Player;
+    use crate::frame_server::FrameServerCache;
+    use crate::mobile::KBot;
+    use crate::utils::Id;
+    use na::{Point3, Vector3};
+
+    const UNIT: &str = "src/asset/botdef/unit_example.json";
+    ///Past the first snapshot
+    const TICKS: i32 = SNAPSHOT_FRAMES + 60;
+
+    ///Two players of a few builders on a small flat map
+    fn small_game() -> Frame {
+        let unit = BotDef::open(UNIT).unwrap();
+        let mut frame = Frame::new();
+        for team in 0..2 {
+            let mut player = Player::new();
+            player.team = team;
+            player.slot = team;
+            for i in 0..4 {
+                let position = Point3::new(50.0 + i as f32 * 5.0, 50.0 + team as f32 * 300.0, 50.0);
+                let mut kbot = KBot::new(position, &unit, player.id);
+                kbot.team = team;
+                player.kbots.insert(kbot.id);
+                frame.kbots.insert(kbot.id, kbot);
+            }
+            frame.players.insert(player.id, player);
+        }
+        frame.add_bot_def(unit);
+        frame.heightmap_phy = Some(HeightmapPhy::new(512, 512));
+        frame
+    }
+
+    ///Builds a unit before and after the first snapshot, then moves the units built, which only
+    ///works if the ids drawn are the recorded ones
+    fn orders(tick: i32, frame: &Frame, start: &Frame) -> Vec<FrameEventFromPlayer> {
+        let mut players: Vec<_> = frame.players.values().collect();
+        players.sort_by_key(|p| p.team);
+        let player = players[0];
+        let mut kbots: Vec<_> = player.kbots.iter().cloned().collect();
+        kbots.sort_by_key(|id| id.value);
+        let built: Vec<Id<KBot>> = kbots
+            .iter()
+            .filter(|id| !start.kbots.contains_key(id))
+            .cloned()
+            .collect();
+        let botdef_id = *frame.bot_defs.keys().next().unwrap();
+        match tick {
+            5 | 305 => vec![FrameEventFromPlayer::ConOrder {
+                id: player.id,
+                selected: kbots.iter().take(2).cloned().collect(),
+                mouse_world_pos: Vector3::new(60.0, 60.0 + tick as f32 / 10.0, 50.0),
+                botdef_id,
+            }],
+            60 | 340 => vec![FrameEventFromPlayer::MoveOrder {
+                id: player.id,
+                selected: built.into_iter().collect(),
+                mouse_world_pos: Vector3::new(200.0, 150.0, 50.0),
+            }],
+            _ => Vec::new(),
+        }
+    }
+
+    ///Recorded the way the manager does, with the frame reached
+    fn record() -> (Replay, Frame) {
+        let start = small_game();
+        let mut replay = Replay::new(start.clone());
+        let mut cache = FrameServerCache::new();
+        let mut frame = start.clone();
+        let mut events = vec![FrameEventFromPlayer::ReplaceFrame(start.clone())];
+        for tick in 0..TICKS {
+            events.extend(orders(tick, &frame, &start));
+            let data = DataToComputeNextFrame {
+                old_frame: frame,
+                events: std::mem::take(&mut events),
+                pings: Vec::new(),
+            };
+            replay.record(&data);
+            frame = cache.next_frame(data.old_frame, data.events);
+        }
+        (replay, frame)
+    }
+
+    ///Simulated from frame with the recorded orders, as the manager plays a replay back
+    fn play_back(playback: &Playback, from: Frame) -> Frame {
+        let mut cache = FrameServerCache::new();
+        let mut frame = from.clone();
+        let mut events = vec![FrameEventFromPlayer::ReplaceFrame(from)];
+        while frame.number < playback.replay.last_frame {
+            events.extend(playback.events(frame.number));
+            frame = cache.next_frame(frame, std::mem::take(&mut events));
+        }
+        frame
+    }
+
+    #[test]
+    fn playback_matches_the_game() {
+        let (replay, live) = record();
+        let start = replay.start.clone();
+        let built = live.kbots.len() - start.kbots.len();
+        assert_eq!(built, 2);
+        let played = play_back(&Playback::new(replay), start);
+        assert_eq!(played.number, live.number);
+        assert_eq!(checksum(&played), checksum(&live));
+    }
+
+    #[test]
+    fn seeking_matches_a_straight_playback() {
+        let (replay, live) = record();
+        assert_eq!(replay.snapshots.len(), 1);
+        let mut playback = Playback::new(replay);
+        let start = playback.replay.start.clone();
+        let straight = play_back(&playback, start);
+        let snapshot = playback.seek(0, live.number).unwrap();
+        assert_eq!(snapshot.number, SNAPSHOT_FRAMES);
+        let sought = play_back(&playback, snapshot);
+        assert_eq!(checksum(&sought), checksum(&straight));
+        assert_eq!(checksum(&sought), checksum(&live));
+    }
+
+    #[test]
+    fn catch_up_matches_the_game() {
+        let (replay, live) = record();
+        let catch_up = Replay::decode(&replay.catch_up().encode().unwrap()).unwrap();
+        assert_eq!(catch_up.start.number, SNAPSHOT_FRAMES);
+        let start = catch_up.start.clone();
+        let caught_up = play_back(&Playback::new(catch_up), start);
+        assert_eq!(checksum(&caught_up), checksum(&live));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::frame::{DataToComputeNextFrame, Frame, FrameEventFromPlayer, StampedInputs};
+use std::collections::VecDeque;
+
+///One second of simulation
+pub const ROLLBACK_TICKS: usize = 10;
+///The host pays a resimulation for each late input, only worth it in small games
+pub const MAX_PLAYERS: usize = 2;
+
+///Host side rollback. The last ticks are kept so that late remote inputs are applied at the frame
+///following the one their player saw, the ticks after it are then simulated again.
+pub struct Rollback {
+    ///Oldest first, each entry produced the frame following its old_frame
+    history: VecDeque<DataToComputeNextFrame>,
+}
+
+impl Rollback {
+    pub fn new() -> Self {
+        Rollback {
+            history: VecDeque::with_capacity(ROLLBACK_TICKS + 1),
+        }
+    }
+
+    pub fn record(&mut self, data: &DataToComputeNextFrame) {
+        self.history.push_back(data.clone());
+        while self.history.len() > ROLLBACK_TICKS {
+            self.history.pop_front();
+        }
+    }
+
+    ///Returns the resimulated current frame if any input was late, and the events left for the next tick.
+    ///compute must run the simulation synchronously.
+    pub fn resimulate(
+        &mut self,
+        stamped: Vec<StampedInputs>,
+        current: &Frame,
+        mut compute: impl FnMut(DataToComputeNextFrame) -> Frame,
+    ) -> (Option<Frame>, Vec<FrameEventFromPlayer>) {
+        let mut on_time = Vec::new();
+        let mut late = Vec::new();
+        for StampedInputs {
+            frame_number,
+            events,
+        } in stamped
+        {
+            match self.index_of(frame_number + 1) {
+                Some(index) if self.can_rewind(index, current) => late.push((index, events)),
+                _ => on_time.extend(events),
+            }
+        }
+
+        let first = match late.iter().map(|(index, _)| *index).min() {
+            Some(first) => first,
+            None => return (None, on_time),
+        };
+        for (index, events) in late {
+            self.history[index].events.extend(events);
+        }
+
+        let mut frame = None;
+        for data in self.history.iter_mut().skip(first) {
+            if let Some(previous) = frame.take() {
+                data.old_frame = previous;
+            }
+            frame = Some(compute(data.clone()));
+        }
+        log::debug!("Rolled back {} ticks", self.history.len() - first);
+        (frame, on_time)
+    }
+
+    ///Entry computing the frame after frame_number, clamped to the oldest one. None if not late.
+    fn index_of(&self, frame_number: i32) -> Option<usize> {
+        let oldest = self.history.front()?.old_frame.number;
+        let newest = self.history.back()?.old_frame.number;
+        if frame_number > newest {
+            None
+        } else {
+            Some((frame_number.max(oldest) - oldest) as usize)
+        }
+    }
+
+    ///Frame replacements and craters on the heightmap aren't undone by a resimulation
+    fn can_rewind(&self, index: usize, current: &Frame) -> bool {
+        let replaced = self.history.iter().skip(index).any(|data| {
+            data.events.iter().any(|event| match event {
+                FrameEventFromPlayer::ReplaceFrame(_) => true,
+                _ => false,
+            })
+        });
+        let cratered = self
+            .history
+            .iter()
+            .skip(index + 1)
+            .any(|data| !data.old_frame.craters.is_empty());
+        !replaced && !cratered && current.craters.is_empty()
+    }
+}
@@ -28,6 +28,9 @@ layout(set = 0, binding = 0) uniform Locals {
     float pen_radius;
     float pen_strength;
     vec2 hmap_size;
+    float time;
+    mat4 last_view_proj;
+    vec4 jitter;
 };
 
 void main() {
@@ -47,5 +50,6 @@ void main() {
     mat4 t = mat4(mata,matb,matc,matd);
 
     gl_Position = cor_proj_view *t* vec4(pos,1.0);
+    gl_Position.xy += jitter.xy * gl_Position.w;
 //    world_pos = a_Pos.xyz +a_off;
 }
@@ -0,0 +1,49 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 1) in vec4 v_color;
+layout(location = 2) flat in int v_is_glow;
+layout(location = 3) in float v_length;
+layout(location = 4) in float v_seed;
+
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+void main() {
+    float intensity;
+    if (v_is_glow == 0) {
+        //White hot core fading to the beam color on the sides
+        float across = abs(v_TexCoord.x - 0.5) * 2.0;
+        float core = 1.0 - smoothstep(0.0, 0.3, across);
+        float halo = 1.0 - across;
+
+        //Energy pulses running from the shooter to the target
+        float along = v_TexCoord.y * v_length;
+        float pulse = 0.75 + 0.25 * sin(along * 6.0 - time * 40.0 + v_seed);
+
+        intensity = (halo * halo * pulse + core);
+        o_Target = vec4(mix(v_color.rgb, vec3(1.0), core), intensity * v_color.a);
+    } else {
+        float d = length(v_TexCoord - vec2(0.5)) * 2.0;
+        if (d > 1.0) {
+            discard;
+        }
+        float flicker = 0.85 + 0.15 * sin(time * 50.0 + v_seed);
+        intensity = (1.0 - d) * (1.0 - d) * flicker;
+        o_Target = vec4(mix(v_color.rgb, vec3(1.0), intensity * 0.5), intensity * v_color.a);
+    }
+}
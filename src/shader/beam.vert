@@ -0,0 +1,67 @@
+#version 450
+
+layout(location = 0) in vec4 from_width;
+layout(location = 1) in vec4 to_alpha;
+layout(location = 2) in vec4 color_seed;
+
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out vec4 v_color;
+layout(location = 2) flat out int v_is_glow;
+layout(location = 3) out float v_length;
+layout(location = 4) out float v_seed;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+void main() {
+    vec3 from = from_width.xyz;
+    vec3 to = to_alpha.xyz;
+    float width = from_width.w;
+    v_color = vec4(color_seed.rgb, to_alpha.w);
+    v_seed = color_seed.w;
+    v_length = length(to - from);
+
+    int corner = gl_VertexIndex % 6;
+    v_is_glow = gl_VertexIndex / 6;
+
+    vec2 tc = vec2(0.0);
+    switch(corner) {
+        case 0: tc = vec2(0.0, 0.0); break;
+        case 1: tc = vec2(1.0, 0.0); break;
+        case 2: tc = vec2(0.0, 1.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+        case 4: tc = vec2(1.0, 0.0); break;
+        case 5: tc = vec2(1.0, 1.0); break;
+    }
+    v_TexCoord = tc;
+
+    mat3 rot = mat3(u_View);
+    vec3 camera_pos = -u_View[3].xyz * rot;
+
+    vec3 world_pos;
+    if (v_is_glow == 0) {
+        //Quad along the beam, turned to face the camera
+        vec3 dir = to - from;
+        vec3 side = normalize(cross(dir, camera_pos - from));
+        world_pos = mix(from, to, tc.y) + side * (tc.x - 0.5) * width;
+    } else {
+        vec3 cam_right = vec3(u_View[0][0], u_View[1][0], u_View[2][0]);
+        vec3 cam_up = vec3(u_View[0][1], u_View[1][1], u_View[2][1]);
+        float size = width * 3.0;
+        world_pos = to + (tc.x - 0.5) * size * cam_right + (tc.y - 0.5) * size * cam_up;
+    }
+
+    gl_Position = cor_proj_view * vec4(world_pos, 1.0);
+}
@@ -0,0 +1,32 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform texture2D t_color;
+layout(set = 0, binding = 1) uniform sampler s_color;
+
+//sRGB encoded, see color_grading.rs
+layout(set = 1, binding = 0) uniform texture3D t_lut;
+layout(set = 1, binding = 1) uniform sampler s_lut;
+
+vec3 linear_to_srgb(vec3 c) {
+    return mix(12.92 * c, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+}
+
+void main() {
+    vec4 color = texture(sampler2D(t_color, s_color), v_TexCoord);
+    //The target is sRGB too, the color is decoded when sampled and encoded when written
+    vec3 encoded = linear_to_srgb(clamp(color.rgb, 0.0, 1.0));
+
+    //Texel centers: 0 and 1 land on the first and the last texel
+    float size = float(textureSize(sampler3D(t_lut, s_lut), 0).x);
+    vec3 coord = encoded * ((size - 1.0) / size) + 0.5 / size;
+    vec3 graded = texture(sampler3D(t_lut, s_lut), coord).rgb;
+
+    o_Target = vec4(srgb_to_linear(graded), color.a);
+}
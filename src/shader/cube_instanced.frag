@@ -1,15 +1,26 @@
 #version 450
 
+#ifndef MAX_LIGHTS
+#define MAX_LIGHTS 64
+#endif
+
 layout(location = 0) in vec2 v_TexCoord;
 layout(location = 1) in vec3 world_pos;
 layout(location = 2) in float v_selected;
 layout(location = 3) in float v_team;
 layout(location = 4) in float v_con_completed;
 layout(location = 5) in vec3 v_world_normal;
+layout(location = 6) in float v_emissive;
+layout(location = 7) in float v_texture_layer;
+layout(location = 8) in vec4 v_clip;
+layout(location = 9) in vec4 v_last_clip;
 
 layout(location = 0) out vec4 o_Target;
 layout(location = 1) out vec4 position_att;
-layout(location = 2) out vec2 o_normal;
+//World normal and roughness
+layout(location = 2) out vec4 o_normal;
+//Uv offset since the last frame
+layout(location = 3) out vec2 o_velocity;
 layout(set = 0, binding = 0) uniform Locals {
     mat4 cor_proj_view;
     mat4 u_View;
@@ -22,12 +33,130 @@ layout(set = 0, binding = 0) uniform Locals {
     float pen_radius;
     float pen_strength;
     vec2 hmap_size;
+    float time;
+    float toon_bands;
 };
-layout(set = 0, binding = 1) uniform texture2D t_Color;
 layout(set = 0, binding = 2) uniform sampler s_Color;
 
+layout(set = 0, binding = 3) uniform PointLights {
+    vec4 light_count;
+    vec4 light_pos_radius[MAX_LIGHTS];
+    vec4 light_color_intensity[MAX_LIGHTS];
+};
+layout(set = 0, binding = 4) uniform texture2DArray t_albedo;
+layout(set = 0, binding = 5) uniform texture2DArray t_normal;
+layout(set = 0, binding = 6) uniform Shadows {
+    mat4 light_view_proj[4];
+    vec4 cascade_splits;
+};
+layout(set = 0, binding = 7) uniform sampler s_shadow;
+layout(set = 0, binding = 8) uniform texture2DArray t_shadow;
+layout(set = 0, binding = 9) uniform sampler s_environment;
+layout(set = 0, binding = 10) uniform textureCube t_irradiance;
+layout(set = 0, binding = 11) uniform textureCube t_specular;
+
+//The meshes carry no material: every unit is painted metal
+const float METALLIC = 0.6;
+const float ROUGHNESS = 0.35;
+//Mip levels of t_specular, one roughness each
+const float ENV_SPECULAR_LEVELS = 5.0;
+
+float cascade_shadow(int cascade, vec3 pos) {
+    vec4 light_pos = light_view_proj[cascade] * vec4(pos, 1.0);
+    vec3 ndc = light_pos.xyz / light_pos.w;
+    //Ndc y points down in wgpu 0.4, as v does
+    vec2 uv = ndc.xy * 0.5 + 0.5;
+    float lit = texture(sampler2DArrayShadow(t_shadow, s_shadow), vec4(uv, cascade, ndc.z - 0.0005));
+    bool outside = any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0;
+    return outside ? 1.0 : lit;
+}
+
+//1 when lit by the sun. The cascade is picked by view depth and blended into the next one
+//over the last tenth of its range. Samples stay out of branches for the derivatives.
+float sun_shadow(vec3 pos) {
+    float depth = -(u_View * vec4(pos, 1.0)).z;
+    int cascade = 3;
+    for (int i = 3; i >= 0; i--) {
+        if (depth < cascade_splits[i]) {
+            cascade = i;
+        }
+    }
+    int next = min(cascade + 1, 3);
+    float start = cascade == 0 ? 0.0 : cascade_splits[cascade - 1];
+    float end = cascade_splits[cascade];
+    float blend = cascade == 3 ? 0.0 : smoothstep(mix(start, end, 0.9), end, depth);
+    float shadow = mix(cascade_shadow(cascade, pos), cascade_shadow(next, pos), blend);
+    return mix(shadow, 1.0, step(cascade_splits[3], depth));
+}
+
+//Tangent frame from the screen derivatives, the meshes have no tangents
+vec3 perturb_normal(vec3 normal, vec3 pos, vec2 uv, vec3 tangent_normal) {
+    vec3 dp1 = dFdx(pos);
+    vec3 dp2 = dFdy(pos);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+    float invmax = inversesqrt(max(dot(t, t), dot(b, b)));
+    if (isinf(invmax) || isnan(invmax)) {
+        return normal;
+    }
+    return normalize(mat3(t * invmax, b * invmax, normal) * tangent_normal);
+}
+
+vec3 point_lights(vec3 pos, vec3 normal, vec3 diffuse) {
+    vec3 acc = vec3(0);
+    for (int i = 0; i < int(light_count.x); i++) {
+        vec3 to_light = light_pos_radius[i].xyz - pos;
+        float d = length(to_light);
+        float radius = light_pos_radius[i].w;
+        if (d < radius) {
+            float att = 1.0 - d / radius;
+            float lambertian = max(dot(normal, to_light / max(d, 0.001)), 0.0);
+            acc += light_color_intensity[i].rgb * light_color_intensity[i].a * att * att * lambertian * diffuse;
+        }
+    }
+    return acc;
+}
+
+//Ambient light of the sky: diffuse from the irradiance cube, specular from the prefiltered cube
+//weighted by a roughness aware fresnel, in place of a split sum lookup table
+vec3 environment(vec3 normal, vec3 view_dir, vec3 albedo) {
+    vec3 f0 = mix(vec3(0.04), albedo, METALLIC);
+    float n_dot_v = max(dot(normal, view_dir), 0.0);
+    vec3 fresnel = f0 + (max(vec3(1.0 - ROUGHNESS), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+    vec3 irradiance = texture(samplerCube(t_irradiance, s_environment), normal).rgb;
+    float lod = ROUGHNESS * (ENV_SPECULAR_LEVELS - 1.0);
+    vec3 r = reflect(-view_dir, normal);
+    vec3 prefiltered = textureLod(samplerCube(t_specular, s_environment), r, lod).rgb;
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - METALLIC) * irradiance * albedo;
+    return diffuse + prefiltered * fresnel;
+}
+
+//Lighting bands of the toon style, smooth when there are none
+float toon(float light) {
+    return toon_bands > 0.0 ? round(light * toon_bands) / toon_bands : light;
+}
+
+float hash(vec3 p) {
+    p = fract(p * 0.3183099 + 0.1);
+    p *= 17.0;
+    return fract(p.x * p.y * p.z * (p.x + p.y + p.z));
+}
+
 void main() {
-    vec4 tex = texture(sampler2D(t_Color, s_Color), v_TexCoord);
+#ifdef DEPTH_PREPASS
+    //Depth only: the same dissolve as below
+    if (v_con_completed < 0.9999 && hash(floor(world_pos * 8.0)) > v_con_completed * 1.1 - 0.05) {
+        discard;
+    }
+    return;
+#endif
+    vec3 uv_layer = vec3(v_TexCoord, v_texture_layer);
+    vec4 tex = texture(sampler2DArray(t_albedo, s_Color), uv_layer);
+    vec3 tangent_normal = texture(sampler2DArray(t_normal, s_Color), uv_layer).xyz * 2.0 - 1.0;
 
     position_att = vec4(world_pos, v_selected );
 
@@ -44,59 +173,51 @@ void main() {
 
     vec3 diffuse= mix(tex.xyz, color,0.5);;
        //blinn phong
-    const vec3 ambientColor = vec3(0.05);
     const vec3 diffuseColor = vec3(1.0, 1.0, 1.0);
     const vec3 specColor = vec3(0.2);
     vec3 lightPos = vec3(-10000,1000,12000);
 
     vec3 vertPos = world_pos;
     vec3 lightDir = normalize(lightPos - vertPos);
-    vec3 normal = v_world_normal;
+    vec3 normal = perturb_normal(normalize(v_world_normal), vertPos, v_TexCoord, tangent_normal);
+    mat3 rot = mat3(u_View);
+    vec3 camera_pos = -u_View[3].xyz*rot;
+    vec3 viewDir = normalize( camera_pos - vertPos);
 
-    float lambertian = max(dot(lightDir,normal), 0.0);
+    float shadow = sun_shadow(vertPos);
+    float lambertian = max(dot(lightDir,normal), 0.0) * shadow;
     float specular = 0.0;
 
     if(lambertian > 0.0) {
-        mat3 rot = mat3(u_View);
-        vec3 camera_pos = -u_View[3].xyz*rot;
-        vec3 viewDir = normalize( camera_pos - vertPos);
         vec3 halfDir = normalize(lightDir + viewDir);
         float specAngle = max(dot(halfDir, normal), 0.0);
-        specular = 1.0*pow(specAngle, 32.0);
+        specular = shadow * pow(specAngle, 32.0);
     }
+    lambertian = toon(lambertian);
+    specular = toon(specular);
     
-    vec3 phong = vec3(ambientColor +
+    vec3 phong = environment(normal, viewDir, diffuse) +
     lambertian* diffuse +
-    specular*specColor);
+    specular*specColor;
+
+    phong += point_lights(vertPos, normal, diffuse);
+    phong += diffuse * v_emissive;
 
 
 
     if(v_con_completed < 0.9999){
-        bool diag = int((gl_FragCoord.x +gl_FragCoord.y )/3.0) %2 == 0;
-        bool hori = int((gl_FragCoord.y)/2.0) %2==0;
-
-        bool hatch = true;
-        if (length(phong) <0.5){
-            hatch = diag||hori;
-        }else if (length(phong) <0.8){
-            hatch = diag;
-        } else {
-            hatch = false;
-        } 
-
-        float m = 0.3;
-        float f = 1+m;
-        if ( hatch){
-            f= 1-m;
+        //Dissolve reveal: fragments appear as the build progresses,
+        //with a glowing nanolathe edge at the frontier
+        float noise = hash(floor(world_pos * 8.0));
+        float reveal = v_con_completed * 1.1 - 0.05;
+        if (noise > reveal) {
+            discard;
         }
-
-        phong = mix(vec3(ambientColor*f +
-        lambertian* diffuse*f +
-        specular*specColor*f) , vec3(0,v_con_completed,0), hatch ? 1.0:0.0);
+        float edge = 1.0 - smoothstep(0.0, 0.08, reveal - noise);
+        phong = mix(phong * 0.6 + vec3(0.0, 0.15, 0.05), vec3(0.3, 1.0, 0.4) * 2.0, edge);
     }
-  
-    
 
-    o_normal = normal.xy;
+    o_normal = vec4(normal, ROUGHNESS);
+    o_velocity = (v_clip.xy / v_clip.w - v_last_clip.xy / v_last_clip.w) * 0.5;
     o_Target = vec4(phong, 1.0);
 }
@@ -8,6 +8,11 @@ layout(location = 3) in vec3 inst_pos;
 layout(location = 4) in vec3 inst_euler;
 layout(location = 5) in float bitpack_selected_team_na_na;
 layout(location = 6) in float con_completed;
+//emissive, pulse frequency, uv scroll
+layout(location = 7) in vec4 inst_material;
+layout(location = 8) in float inst_texture_layer;
+layout(location = 9) in vec3 inst_last_pos;
+layout(location = 10) in vec3 inst_last_euler;
 
 
 layout(location = 0) out vec2 v_TexCoord;
@@ -17,6 +22,11 @@ layout(location = 2) out float v_selected;
 layout(location = 3) out float v_team;
 layout(location = 4) out float v_con_completed;
 layout(location = 5) out vec3 v_world_normal;
+layout(location = 6) out float v_emissive;
+layout(location = 7) out float v_texture_layer;
+//Unjittered, for the motion vectors
+layout(location = 8) out vec4 v_clip;
+layout(location = 9) out vec4 v_last_clip;
 layout(set = 0, binding = 0) uniform Locals {
     mat4 cor_proj_view;
     mat4 u_View;
@@ -29,44 +39,56 @@ layout(set = 0, binding = 0) uniform Locals {
     float pen_radius;
     float pen_strength;
     vec2 hmap_size;
+    float time;
+    mat4 last_view_proj;
+    vec4 jitter;
 };
 
+//Drawn again with an equal depth test after the depth pre-pass
+invariant gl_Position;
+
+mat4 transform(vec3 pos, vec3 euler) {
+    float sr = sin(euler.x);
+    float cr = cos(euler.x);
+    float sp = sin(euler.y);
+    float cp = cos(euler.y);
+    float sy = sin(euler.z);
+    float cy = cos(euler.z);
+    return mat4(
+        cy * cp,                 sy * cp,                -sp                ,0,
+        cy * sp * sr - sy * cr,  sy * sp * sr + cy * cr, cp * sr            ,0,
+        cy * sp * cr + sy * sr,  sy * sp * cr - cy * sr, cp * cr            ,0,
+        pos.x,                   pos.y                 , pos.z              ,1);
+}
+
 void main() {
-    v_TexCoord = a_TexCoord;
+    v_TexCoord = a_TexCoord + inst_material.zw * time;
+
+    float pulse = inst_material.y > 0.0 ? 0.5 + 0.5 * sin(6.2831853 * inst_material.y * time) : 1.0;
+    v_emissive = inst_material.x * pulse;
+    v_texture_layer = inst_texture_layer;
 
     v_selected=  floor(bitpack_selected_team_na_na/100.0);
     v_team = round(bitpack_selected_team_na_na-v_selected*100.0);
     // v_selected = bitpack_selected_team_na_na <= 0.0 ? 1.0 : 0.0;
     v_con_completed = con_completed;
 
-    float sr = sin(inst_euler.x);
-    float cr = cos(inst_euler.x);
-    float sp = sin(inst_euler.y);
-    float cp = cos(inst_euler.y);
-    float sy = sin(inst_euler.z);
-    float cy = cos(inst_euler.z);
-
     // mat4 t = mat4(
     //     cy * cp,  cy * sp * sr - sy * cr,  cy * sp * cr + sy * sr,  inst_pos.x, 
     //     sy * cp,  sy * sp * sr + cy * cr,  sy * sp * cr - cy * sr,  inst_pos.y,
     //              -sp,            cp * sr,            cp * cr,  inst_pos.z, 
     //              0,0,0,1);
 
-    mat4 t = mat4(
-        cy * cp,                 sy * cp,                -sp                ,0, 
-        cy * sp * sr - sy * cr,  sy * sp * sr + cy * cr, cp * sr            ,0,
-        cy * sp * cr + sy * sr,  sy * sp * cr - cy * sr, cp * cr            ,0, 
-        inst_pos.x,              inst_pos.y            , inst_pos.z         ,1);
-
-    mat3 tn = mat3(
-    cy * cp,                 sy * cp,                -sp                ,
-    cy * sp * sr - sy * cr,  sy * sp * sr + cy * cr, cp * sr            ,
-    cy * sp * cr + sy * sr,  sy * sp * cr - cy * sr, cp * cr            ); 
-             
+    mat4 t = transform(inst_pos, inst_euler);
+    mat3 tn = mat3(t);
 
     vec4 world_pos4 = t * a_Pos;
     world_pos = world_pos4.xyz/world_pos4.w;
-    gl_Position = cor_proj_view*vec4(world_pos+vec3(0.0),1.0);//  cor_proj_view * t *a_Pos;
+    v_clip = cor_proj_view * vec4(world_pos, 1.0);
+    vec4 last_world_pos4 = transform(inst_last_pos, inst_last_euler) * a_Pos;
+    v_last_clip = last_view_proj * vec4(last_world_pos4.xyz / last_world_pos4.w, 1.0);
+    gl_Position = v_clip;
+    gl_Position.xy += jitter.xy * gl_Position.w;
 
     v_world_normal = tn* a_normal;
  
@@ -0,0 +1,68 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+};
+
+layout(set = 1, binding = 0) uniform texture2D t_color;
+layout(set = 1, binding = 1) uniform texture2D t_position;
+layout(set = 1, binding = 2) uniform sampler s_linear;
+layout(set = 1, binding = 3) uniform sampler s_nearest;
+layout(set = 1, binding = 4) uniform Dof {
+    vec4 focus;
+    //Blur radius in pixels, taps
+    vec4 radius_taps;
+};
+
+const float GOLDEN_ANGLE = 2.39996;
+//Relative distance to the focus plane at which the blur is the largest
+const float FOCUS_RANGE = 0.35;
+
+float view_distance(vec3 world) {
+    return length((u_View * vec4(world, 1.0)).xyz);
+}
+
+//Blur radius in pixels at uv, nothing drawn counts as far away
+float circle_of_confusion(vec2 uv, float focus_distance) {
+    vec4 pos = texture(sampler2D(t_position, s_nearest), uv);
+    if (pos.w < -0.5) {
+        return radius_taps.x;
+    }
+    float offset = abs(view_distance(pos.xyz) - focus_distance) / focus_distance;
+    return radius_taps.x * clamp(offset / FOCUS_RANGE, 0.0, 1.0);
+}
+
+void main() {
+    vec4 color = texture(sampler2D(t_color, s_linear), v_TexCoord);
+    int taps = int(radius_taps.y);
+    if (radius_taps.x < 0.5 || taps == 0) {
+        o_Target = color;
+        return;
+    }
+    float focus_distance = max(view_distance(focus.xyz), 1.0);
+    float coc = circle_of_confusion(v_TexCoord, focus_distance);
+
+    //Gather on a golden angle spiral. A tap counts if its own blur reaches this pixel, so sharp
+    //foreground does not bleed over the blurred background.
+    vec3 sum = color.rgb;
+    float weight = 1.0;
+    for (int i = 0; i < taps; i++) {
+        float r = sqrt((float(i) + 0.5) / float(taps)) * radius_taps.x;
+        float angle = float(i) * GOLDEN_ANGLE;
+        vec2 uv = v_TexCoord + vec2(cos(angle), sin(angle)) * r * inv_resolution;
+        float tap_coc = circle_of_confusion(uv, focus_distance);
+        float w = clamp(min(tap_coc, coc + 1.0) - r + 1.0, 0.0, 1.0);
+        sum += texture(sampler2D(t_color, s_linear), uv).rgb * w;
+        weight += w;
+    }
+    o_Target = vec4(sum / weight, color.a);
+}
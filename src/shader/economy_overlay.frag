@@ -0,0 +1,54 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+//Must match economy::MAX_SPOTS
+#define MAX_SPOTS 64
+
+layout(set = 1, binding = 0) uniform MetalSpots {
+    vec4 spot_count;
+    //x, y, extraction radius, metal/frame
+    vec4 spot_pos_radius_metal[MAX_SPOTS];
+};
+layout(set = 1, binding = 1) uniform texture2D t_position;
+layout(set = 1, binding = 2) uniform sampler s_nearest;
+
+//m, side of a cell of the grid
+const float CELL_SIZE = 16.0;
+//metal/frame of a fully tinted cell
+const float FULL_METAL = 2.0;
+const vec3 GRID_COLOR = vec3(0.9);
+const vec3 EMPTY_COLOR = vec3(0.05, 0.05, 0.1);
+const vec3 COVERED_COLOR = vec3(0.3, 0.8, 1.0);
+
+void main() {
+    vec4 pos = texture(sampler2D(t_position, s_nearest), v_TexCoord);
+    if (pos.w < -0.5) {
+        discard;
+    }
+
+    //Metal an extractor would get at the center of the cell, and the edge of each coverage
+    vec2 center = (floor(pos.xy / CELL_SIZE) + 0.5) * CELL_SIZE;
+    float metal = 0.0;
+    float edge = 0.0;
+    for (int i = 0; i < int(spot_count.x); i++) {
+        vec4 spot = spot_pos_radius_metal[i];
+        if (distance(center, spot.xy) < spot.z) {
+            metal += spot.w;
+        }
+        edge = max(edge, 1.0 - smoothstep(0.0, 0.6, abs(distance(pos.xy, spot.xy) - spot.z)));
+    }
+
+    vec2 cell = fract(pos.xy / CELL_SIZE);
+    vec2 to_line = min(cell, 1.0 - cell) * CELL_SIZE;
+    float line = 1.0 - smoothstep(0.0, 0.3, min(to_line.x, to_line.y));
+
+    vec3 color = metal > 0.0 ? mix(EMPTY_COLOR, COVERED_COLOR, min(metal / FULL_METAL, 1.0)) : EMPTY_COLOR;
+    float alpha = metal > 0.0 ? 0.4 : 0.2;
+    color = mix(color, GRID_COLOR, line * 0.5);
+    alpha = max(alpha, line * 0.4);
+    color = mix(color, COVERED_COLOR, edge);
+    alpha = max(alpha, edge * 0.8);
+    o_Target = vec4(color, alpha);
+}
@@ -0,0 +1,83 @@
+#version 450
+
+//Bakes one face of the environment cubes from the sky gradient.
+//IRRADIANCE: cosine weighted integral over the hemisphere of the texel direction.
+//SPECULAR: ggx prefiltered radiance for the roughness of the level, with n = v = r.
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Bake {
+    vec4 zenith;
+    vec4 horizon;
+    vec4 ground;
+    vec4 face_roughness;
+};
+
+const float PI = 3.14159265;
+const uint SAMPLES = 256;
+
+//Cube face directions as sampled, v pointing down
+vec3 face_direction(int face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    switch (face) {
+        case 0: return vec3(1.0, -st.y, -st.x);
+        case 1: return vec3(-1.0, -st.y, st.x);
+        case 2: return vec3(st.x, 1.0, st.y);
+        case 3: return vec3(st.x, -1.0, -st.y);
+        case 4: return vec3(st.x, -st.y, 1.0);
+        default: return vec3(-st.x, -st.y, -1.0);
+    }
+}
+
+//z is up in the world
+vec3 sky(vec3 dir) {
+    float up = dir.z;
+    if (up >= 0.0) {
+        return mix(horizon.rgb, zenith.rgb, sqrt(up));
+    }
+    return mix(horizon.rgb, ground.rgb, sqrt(min(-up * 4.0, 1.0)));
+}
+
+vec2 hammersley(uint i) {
+    uint bits = bitfieldReverse(i);
+    return vec2(float(i) / float(SAMPLES), float(bits) * 2.3283064365386963e-10);
+}
+
+mat3 tangent_frame(vec3 n) {
+    vec3 up = abs(n.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 t = normalize(cross(up, n));
+    return mat3(t, cross(n, t), n);
+}
+
+void main() {
+    vec3 n = normalize(face_direction(int(face_roughness.x), v_TexCoord));
+    mat3 frame = tangent_frame(n);
+    vec3 sum = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0; i < SAMPLES; i++) {
+        vec2 xi = hammersley(i);
+#ifdef IRRADIANCE
+        //Cosine weighted: the pdf cancels the cosine
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt(1.0 - xi.y);
+        float sin_theta = sqrt(xi.y);
+        vec3 l = frame * vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+        sum += sky(l);
+        weight += 1.0;
+#else
+        float a = face_roughness.y * face_roughness.y;
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+        float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        vec3 h = frame * vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+        vec3 l = reflect(-n, h);
+        float n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            sum += sky(l) * n_dot_l;
+            weight += n_dot_l;
+        }
+#endif
+    }
+    o_Target = vec4(sum / max(weight, 1e-4), 1.0);
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 1) in float v_alpha;
+
+layout(location = 0) out vec4 o_Target;
+
+void main() {
+    float d = length(v_TexCoord - vec2(0.5)) * 2.0;
+    if (d > 1.0) {
+        discard;
+    }
+    o_Target = vec4(vec3(0.0), (1.0 - d * d) * v_alpha);
+}
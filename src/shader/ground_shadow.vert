@@ -0,0 +1,39 @@
+#version 450
+
+layout(location = 0) in vec4 ground_pos_radius;
+layout(location = 1) in float alpha;
+
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out float v_alpha;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+void main() {
+    vec2 tc = vec2(0.0);
+    switch(gl_VertexIndex) {
+        case 0: tc = vec2(1.0, 0.0); break;
+        case 1: tc = vec2(1.0, 1.0); break;
+        case 2: tc = vec2(0.0, 0.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+    }
+    v_TexCoord = tc;
+    v_alpha = alpha;
+
+    //Flat quad slightly above the ground
+    float radius = ground_pos_radius.w;
+    vec3 world_pos = ground_pos_radius.xyz + vec3((tc - vec2(0.5)) * 2.0 * radius, 0.05);
+    gl_Position = cor_proj_view * vec4(world_pos, 1.0);
+}
@@ -26,6 +26,15 @@ void main() {
     if(v_type <= 0.0){
     }else if (v_type <= 1.0){
         color = vec3(0.5 + 0.13*sin(v_life*6.28*5));
+    }else {
+        //Rank chevrons, v_life is the rank
+        float x = fract(v_TexCoord.x * v_life);
+        float center_y = 0.3 + 0.5 * (1.0 - abs(x - 0.5) * 2.0);
+        if (abs(v_TexCoord.y - center_y) > 0.18){
+            discard;
+        }
+        o_Target = vec4(1.0, 0.8, 0.2, v_alpha);
+        return;
     }
     if (v_TexCoord.x > v_life){
         color= vec3(0);
@@ -0,0 +1,53 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 1, binding = 0) uniform Heatmap {
+    //width, height of the map, kind, cells on a side
+    vec4 map_size_kind;
+};
+layout(set = 1, binding = 1) uniform texture2D t_position;
+layout(set = 1, binding = 2) uniform texture2D t_grid;
+layout(set = 1, binding = 3) uniform sampler s_nearest;
+
+//Indexed by heatmap::HeatmapKind, the colors of 0 and 1
+const vec3 LOW_COLORS[4] = vec3[](
+    vec3(0.2, 0.8, 0.3),
+    vec3(1.0, 0.8, 0.2),
+    vec3(0.05, 0.05, 0.2),
+    vec3(1.0, 0.2, 0.2)
+);
+const vec3 HIGH_COLORS[4] = vec3[](
+    vec3(1.0, 0.15, 0.1),
+    vec3(1.0, 0.1, 0.1),
+    vec3(0.9, 0.9, 0.5),
+    vec3(0.2, 1.0, 0.4)
+);
+const vec3 GRID_COLOR = vec3(0.9);
+
+void main() {
+    vec4 pos = texture(sampler2D(t_position, s_nearest), v_TexCoord);
+    if (pos.w < -0.5) {
+        discard;
+    }
+
+    vec2 uv = pos.xy / map_size_kind.xy;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThanEqual(uv, vec2(1.0)))) {
+        discard;
+    }
+    float value = texture(sampler2D(t_grid, s_nearest), uv).r;
+    if (value < 0.0) {
+        discard;
+    }
+
+    vec2 cell = fract(uv * map_size_kind.w);
+    vec2 cell_size = map_size_kind.xy / map_size_kind.w;
+    vec2 to_line = min(cell, 1.0 - cell) * cell_size;
+    float line = 1.0 - smoothstep(0.0, 0.3, min(to_line.x, to_line.y));
+
+    int kind = int(map_size_kind.z);
+    vec3 color = mix(LOW_COLORS[kind], HIGH_COLORS[kind], clamp(value, 0.0, 1.0));
+    color = mix(color, GRID_COLOR, line * 0.4);
+    o_Target = vec4(color, max(0.4, line * 0.4));
+}
@@ -1,12 +1,19 @@
 #version 450
 
+#ifndef MAX_LIGHTS
+#define MAX_LIGHTS 64
+#endif
+
 layout(location = 0) in vec2 v_TexCoord;
 layout(location = 1) in vec3 color;
 layout(location = 2) in float min_lod;
 layout(location = 3) in float max_mip;
 layout(location = 0) out vec4 o_Target;
 layout(location = 1) out vec4 o_position_att;
-layout(location = 2) out vec2 o_normal;
+//World normal and roughness
+layout(location = 2) out vec4 o_normal;
+//Uv offset since the last frame, the terrain only moves with the camera
+layout(location = 3) out vec2 o_velocity;
 
 layout(set = 0, binding = 0) uniform Locals {
     mat4 cor_proj_view;
@@ -20,12 +27,78 @@ layout(set = 0, binding = 0) uniform Locals {
     float pen_radius;
     float pen_strength;
     vec2 hmap_size;
+    float time;
+    float toon_bands;
+    mat4 last_view_proj;
+    vec4 jitter;
 };
 
 
 layout(set = 0, binding = 1) uniform texture2D t_Color;
 layout(set = 0, binding = 2) uniform sampler s_Color;
 
+layout(set = 0, binding = 3) uniform PointLights {
+    vec4 light_count;
+    vec4 light_pos_radius[MAX_LIGHTS];
+    vec4 light_color_intensity[MAX_LIGHTS];
+};
+layout(set = 0, binding = 6) uniform Shadows {
+    mat4 light_view_proj[4];
+    vec4 cascade_splits;
+};
+layout(set = 0, binding = 7) uniform sampler s_shadow;
+layout(set = 0, binding = 8) uniform texture2DArray t_shadow;
+layout(set = 0, binding = 9) uniform sampler s_environment;
+layout(set = 0, binding = 10) uniform textureCube t_irradiance;
+
+float cascade_shadow(int cascade, vec3 pos) {
+    vec4 light_pos = light_view_proj[cascade] * vec4(pos, 1.0);
+    vec3 ndc = light_pos.xyz / light_pos.w;
+    //Ndc y points down in wgpu 0.4, as v does
+    vec2 uv = ndc.xy * 0.5 + 0.5;
+    float lit = texture(sampler2DArrayShadow(t_shadow, s_shadow), vec4(uv, cascade, ndc.z - 0.0005));
+    bool outside = any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0;
+    return outside ? 1.0 : lit;
+}
+
+//1 when lit by the sun. The cascade is picked by view depth and blended into the next one
+//over the last tenth of its range. Samples stay out of branches for the derivatives.
+float sun_shadow(vec3 pos) {
+    float depth = -(u_View * vec4(pos, 1.0)).z;
+    int cascade = 3;
+    for (int i = 3; i >= 0; i--) {
+        if (depth < cascade_splits[i]) {
+            cascade = i;
+        }
+    }
+    int next = min(cascade + 1, 3);
+    float start = cascade == 0 ? 0.0 : cascade_splits[cascade - 1];
+    float end = cascade_splits[cascade];
+    float blend = cascade == 3 ? 0.0 : smoothstep(mix(start, end, 0.9), end, depth);
+    float shadow = mix(cascade_shadow(cascade, pos), cascade_shadow(next, pos), blend);
+    return mix(shadow, 1.0, step(cascade_splits[3], depth));
+}
+
+//Lighting bands of the toon style, smooth when there are none
+float toon(float light) {
+    return toon_bands > 0.0 ? round(light * toon_bands) / toon_bands : light;
+}
+
+vec3 point_lights(vec3 pos, vec3 normal, vec3 diffuse) {
+    vec3 acc = vec3(0);
+    for (int i = 0; i < int(light_count.x); i++) {
+        vec3 to_light = light_pos_radius[i].xyz - pos;
+        float d = length(to_light);
+        float radius = light_pos_radius[i].w;
+        if (d < radius) {
+            float att = 1.0 - d / radius;
+            float lambertian = max(dot(normal, to_light / max(d, 0.001)), 0.0);
+            acc += light_color_intensity[i].rgb * light_color_intensity[i].a * att * att * lambertian * diffuse;
+        }
+    }
+    return acc;
+}
+
 layout(set = 1, binding = 0) uniform MapCfg {
     float width;
     float height;
@@ -39,7 +112,6 @@ layout(set = 1, binding = 3) uniform texture2D height_tex;
 layout(set = 1, binding = 4) uniform sampler height_sampler;
 
 
-const vec3 ambientColor = vec3(0.05);
 const vec3 diffuseColor = vec3(1.0, 1.0, 1.0);
 const vec3 specColor = vec3(0.2);
 
@@ -59,9 +131,23 @@ float slope_of(vec3 normal){
     return 1-asin(normal.z)/(3.141592/2.0);
 }
 
+//Slopes over which the top-down projection fades into the tri-planar one
+const float TRIPLANAR_START = 30/90.0;
+const float TRIPLANAR_END = 45/90.0;
+
+//Checker projected along the three axes, weighted by the normal, so cliff faces are not stretched
+vec4 triplanar_checker(vec3 pos, vec3 normal){
+    vec3 weights = pow(abs(normal), vec3(4.0));
+    weights /= weights.x + weights.y + weights.z;
+    vec4 x = texture(sampler2D(t_Color_checker, s_Color_checker), pos.yz / 2.0 + vec2(0.5/2.0));
+    vec4 y = texture(sampler2D(t_Color_checker, s_Color_checker), pos.xz / 2.0 + vec2(0.5/2.0));
+    vec4 z = texture(sampler2D(t_Color_checker, s_Color_checker), pos.xy / 2.0 + vec2(0.5/2.0));
+    return x * weights.x + y * weights.y + z * weights.z;
+}
+
 void main() {
     vec4 tex = texture(sampler2D(t_Color, s_Color), v_TexCoord);
-    vec4 tex_checker = texture(sampler2D(t_Color_checker, s_Color_checker),
+    vec4 top_checker = texture(sampler2D(t_Color_checker, s_Color_checker),
     (v_TexCoord) * vec2(width/2.0,height/2.0) + vec2(0.5/2.0));
 
     vec2 pos_xy = v_TexCoord* vec2(width,height);
@@ -75,6 +161,9 @@ void main() {
     vec3 normal = normal_at(v_TexCoord,lod);
     float slope = slope_of(normal);
 
+    vec4 tex_checker = mix(top_checker, triplanar_checker(pos, normal),
+        smoothstep(TRIPLANAR_START, TRIPLANAR_END, slope));
+
     vec3 diffuse=  mix(vec3(0.5,0.4,0.3),tex_checker.xyz,0.041);
 
     float ground_end= 1/90.0;
@@ -92,7 +181,7 @@ void main() {
         diffuse= grass_color;
     }
     if (slope > 45/90.0){
-        diffuse = vec3(0.5);
+        diffuse = mix(vec3(0.5), tex_checker.xyz, 0.041);
     }
 
     //blinn phong
@@ -101,7 +190,8 @@ void main() {
     vec3 vertPos = pos;
     vec3 lightDir = normalize(lightPos - vertPos);
 
-    float lambertian = max(dot(lightDir,normal), 0.0);
+    float shadow = sun_shadow(vertPos);
+    float lambertian = max(dot(lightDir,normal), 0.0) * shadow;
     float specular = 0.0;
 
     if(lambertian > 0.0) {
@@ -110,12 +200,18 @@ void main() {
         vec3 viewDir = normalize( camera_pos - vertPos);
         vec3 halfDir = normalize(lightDir + viewDir);
         float specAngle = max(dot(halfDir, normal), 0.0);
-        specular = 1.0*pow(specAngle, 32.0);
+        specular = shadow * pow(specAngle, 32.0);
     }
+    lambertian = toon(lambertian);
+    specular = toon(specular);
     
-    vec3 phong = vec3(ambientColor +
+    //Ambient light of the sky, the ground is rough and not metallic
+    vec3 ambient = texture(samplerCube(t_irradiance, s_environment), normal).rgb * diffuse;
+    vec3 phong = ambient +
     lambertian* diffuse +
-    specular*specColor);
+    specular*specColor;
+
+    phong += point_lights(vertPos, normal, diffuse);
 
 
     // phong =mix(color, phong,0.1);
@@ -128,7 +224,10 @@ void main() {
         phong= vec3(0.1);
     }
 
-    o_normal = normal.xy;
+    o_normal = vec4(normal, 1.0);
+    vec4 clip = cor_proj_view * vec4(pos, 1.0);
+    vec4 last_clip = last_view_proj * vec4(pos, 1.0);
+    o_velocity = (clip.xy / clip.w - last_clip.xy / last_clip.w) * 0.5;
     o_position_att = vec4(pos, 0.0);
     o_Target =   vec4(phong,1.0);
 }
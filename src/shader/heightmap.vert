@@ -1,8 +1,10 @@
 #version 450
 
 
+//Cell of the clipmap grid
 layout(location = 0) in vec2 a_Pos;
-layout(location = 1) in float mip;
+layout(location = 1) in float a_skirt;
+layout(location = 2) in float level;
 
 layout(location = 0) out vec2 v_TexCoord;
 layout(location = 1) out vec3 color;
@@ -21,6 +23,9 @@ layout(set = 0, binding = 0) uniform Locals {
     float pen_radius;
     float pen_strength;
     vec2 hmap_size;
+    float time;
+    mat4 last_view_proj;
+    vec4 jitter;
 };
 
 layout(set = 1, binding = 0) uniform MapCfg {
@@ -38,26 +43,28 @@ layout(set = 1, binding = 4) uniform sampler height_sampler;
 layout(set = 1, binding = 5) uniform texture2D height_lod_tex;
 layout(set = 1, binding = 6) uniform sampler height_lod_sampler;
 
-float floor_res(int res, float val ){
-    return floor(int(val)/res)*res;
-}
+//Depth of the skirts under the edge of a level, in cells of that level
+const float SKIRT_DEPTH = 2.0;
 
-void main() {
+//Drawn again with an equal depth test after the depth pre-pass
+invariant gl_Position;
 
-    int res = int(round(pow(2.0,(mip))));
-    vec2 cam_pos  =   vec2(floor_res(res,cam_x), floor_res(res,cam_y)); //vec2(266.987); //
+void main() {
+    //Each level snaps to a grid twice as coarse as its cells, so the level above always
+    //lines up with it
+    float spacing = exp2(level);
+    vec2 cam_pos = floor(vec2(cam_x, cam_y) / (2.0 * spacing)) * 2.0 * spacing;
     vec2 dim = hmap_size ;
-    vec2 pos_xy =  a_Pos.xy+cam_pos + vec2(0.5);
+    vec2 pos_xy =  a_Pos.xy * spacing + cam_pos + vec2(0.5);
     v_TexCoord =pos_xy/dim;
     min_lod =  texture(sampler2D(height_lod_tex, height_lod_sampler),v_TexCoord).r;
-    max_mip  = max(mip, min_lod);
+    max_mip  = max(level, min_lod);
     float z =  textureLod(sampler2D(height_tex, height_sampler),v_TexCoord, max_mip).r;
     vec3 pos = vec3(pos_xy,z);
 
     color= vec3(max_mip/4);
     float rock_bottom = -40.0;
-    float f = fract(max_mip);
-    float stride = pow(2,ceil(max_mip));
+    float stride = max(spacing, pow(2,ceil(max_mip)));
    
 
    
@@ -105,6 +112,8 @@ void main() {
    
 
     v_TexCoord =pos_xy/dim;
+    pos.z -= a_skirt * SKIRT_DEPTH * spacing;
 
     gl_Position = cor_proj_view * ( vec4(pos,1.0) );
+    gl_Position.xy += jitter.xy * gl_Position.w;
 }
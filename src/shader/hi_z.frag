@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) out float o_depth;
+
+//Previous level, or the depth buffer for level 0
+layout(set = 0, binding = 0) uniform texture2D t_source;
+layout(set = 0, binding = 1) uniform sampler s_source;
+
+void main() {
+    ivec2 source_size = textureSize(sampler2D(t_source, s_source), 0);
+    ivec2 size = max(source_size / 2, ivec2(1));
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    //Texels covered in the source, 3 wide on the last row or column of an odd size
+    ivec2 first = texel * source_size / size;
+    ivec2 last = min(((texel + 1) * source_size + size - 1) / size, source_size) - 1;
+
+    float depth = 0.0;
+    for (int y = first.y; y <= last.y; y++) {
+        for (int x = first.x; x <= last.x; x++) {
+            depth = max(depth, texelFetch(sampler2D(t_source, s_source), ivec2(x, y), 0).r);
+        }
+    }
+    o_depth = depth;
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 1) in vec4 v_color;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 1, binding = 1) uniform texture2D t_atlas;
+layout(set = 1, binding = 2) uniform sampler s_atlas;
+
+void main() {
+    o_Target = texture(sampler2D(t_atlas, s_atlas), v_TexCoord) * v_color;
+}
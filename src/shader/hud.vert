@@ -0,0 +1,32 @@
+#version 450
+
+//min_px, max_px in window pixels, y down
+layout(location = 0) in vec2 min_px;
+layout(location = 1) in vec2 max_px;
+
+//min_tex [0,1]
+layout(location = 2) in vec2 min_tex;
+layout(location = 3) in vec2 max_tex;
+layout(location = 4) in vec4 color;
+
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out vec4 v_color;
+
+layout(set = 1, binding = 0) uniform Ortho {
+    mat4 ortho;
+};
+
+void main() {
+    vec2 tc = vec2(0.0);
+    switch(gl_VertexIndex) {
+        case 0: tc = vec2(1.0, 0.0); break;
+        case 1: tc = vec2(1.0, 1.0); break;
+        case 2: tc = vec2(0.0, 0.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+    }
+
+    v_TexCoord = min_tex + tc * (max_tex - min_tex);
+    v_color = color;
+    vec2 px = min_px + tc * (max_px - min_px);
+    gl_Position = ortho * vec4(px, 0.5, 1.0);
+}
@@ -0,0 +1,78 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec3 world_pos;
+layout(location = 2) in float v_layer;
+layout(location = 3) in float v_yaw;
+layout(location = 4) in float v_selected;
+layout(location = 5) in float v_team;
+layout(location = 6) in vec4 v_clip;
+layout(location = 7) in vec4 v_last_clip;
+
+layout(location = 0) out vec4 o_Target;
+layout(location = 1) out vec4 position_att;
+//World normal and roughness
+layout(location = 2) out vec4 o_normal;
+//Uv offset since the last frame
+layout(location = 3) out vec2 o_velocity;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+    float toon_bands;
+};
+
+//Unit space normal in rgb, coverage in alpha
+layout(set = 1, binding = 0) uniform texture2DArray t_impostor;
+layout(set = 1, binding = 1) uniform sampler s_impostor;
+
+//As the models, see cube_instanced.frag
+const float ROUGHNESS = 0.35;
+const float AMBIENT = 0.35;
+
+//Lighting bands of the toon style, smooth when there are none
+float toon(float light) {
+    return toon_bands > 0.0 ? round(light * toon_bands) / toon_bands : light;
+}
+
+void main() {
+    vec4 texel = texture(sampler2DArray(t_impostor, s_impostor), vec3(v_uv, v_layer));
+    if (texel.a < 0.5) {
+        discard;
+    }
+    vec3 local_normal = normalize(texel.xyz * 2.0 - 1.0);
+    float c = cos(v_yaw);
+    float s = sin(v_yaw);
+    vec3 normal = vec3(
+        c * local_normal.x - s * local_normal.y,
+        s * local_normal.x + c * local_normal.y,
+        local_normal.z
+    );
+
+    vec3 color = vec3(1);
+    if (v_team == 0.0) {
+        color = vec3(0, 0.3, 1);
+    } else if (v_team < 1.1) {
+        color = vec3(1, 0.0, 0);
+    }
+    vec3 diffuse = mix(vec3(1.0), color, 0.5);
+
+    vec3 lightPos = vec3(-10000, 1000, 12000);
+    vec3 lightDir = normalize(lightPos - world_pos);
+    float lambertian = toon(max(dot(lightDir, normal), 0.0));
+
+    position_att = vec4(world_pos, v_selected);
+    o_normal = vec4(normal, ROUGHNESS);
+    o_velocity = (v_clip.xy / v_clip.w - v_last_clip.xy / v_last_clip.w) * 0.5;
+    o_Target = vec4(diffuse * (AMBIENT + lambertian), 1.0);
+}
@@ -0,0 +1,76 @@
+#version 450
+
+//Position and yaw of the unit
+layout(location = 0) in vec4 inst_pos_yaw;
+//Position of the last frame, half the side of the billboard
+layout(location = 1) in vec4 inst_last_pos_radius;
+//Layer in the atlas, bitpacked selection and team as in cube_instanced.vert
+layout(location = 2) in vec2 inst_layer_team;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec3 world_pos;
+layout(location = 2) out float v_layer;
+layout(location = 3) out float v_yaw;
+layout(location = 4) out float v_selected;
+layout(location = 5) out float v_team;
+//Unjittered, for the motion vectors
+layout(location = 6) out vec4 v_clip;
+layout(location = 7) out vec4 v_last_clip;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+    float toon_bands;
+    mat4 last_view_proj;
+    vec4 jitter;
+};
+
+//Cells baked around the unit, see impostor.rs
+const float ANGLES = 16.0;
+const float GRID = 4.0;
+
+void main() {
+    vec3 center = inst_pos_yaw.xyz;
+    float radius = inst_last_pos_radius.w;
+    v_yaw = inst_pos_yaw.w;
+    v_layer = inst_layer_team.x;
+    v_selected = floor(inst_layer_team.y / 100.0);
+    v_team = round(inst_layer_team.y - v_selected * 100.0);
+
+    vec2 tc = vec2(0.0);
+    switch(gl_VertexIndex) {
+        case 0: tc = vec2(1.0, 0.0); break;
+        case 1: tc = vec2(1.0, 1.0); break;
+        case 2: tc = vec2(0.0, 0.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+    }
+
+    //Faces the camera with the basis of the bake
+    mat3 rot = mat3(u_View);
+    vec3 camera_pos = -u_View[3].xyz * rot;
+    vec3 forward = normalize(center - camera_pos);
+    vec3 right = normalize(cross(forward, vec3(0.0, 0.0, 1.0)));
+    vec3 up = cross(right, forward);
+
+    //Cell of the direction the unit is seen from, in the frame of the unit
+    float angle = atan(-forward.y, -forward.x) - v_yaw;
+    float cell = mod(round(angle / (6.2831853 / ANGLES)), ANGLES);
+    v_uv = (vec2(mod(cell, GRID), floor(cell / GRID)) + tc) / GRID;
+
+    vec3 offset = (right * (tc.x * 2.0 - 1.0) + up * (1.0 - tc.y * 2.0)) * radius;
+    world_pos = center + offset;
+    v_clip = cor_proj_view * vec4(world_pos, 1.0);
+    v_last_clip = last_view_proj * vec4(inst_last_pos_radius.xyz + offset, 1.0);
+    gl_Position = v_clip;
+    gl_Position.xy += jitter.xy * gl_Position.w;
+}
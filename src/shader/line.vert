@@ -25,6 +25,9 @@ layout(set = 0, binding = 0) uniform Locals {
     float pen_radius;
     float pen_strength;
     vec2 hmap_size;
+    float time;
+    float toon_bands;
+    float render_scale;
 };
 
 void main() {
@@ -32,7 +35,8 @@ void main() {
     v_max = max;
     v_type = type;
     v_count = count;
-    v_l = length(max*resolution-min*resolution);
+    //Drawn on the window sized ui target, resolution is the scene one
+    v_l = length(max*resolution-min*resolution)/render_scale;
     v_w = 8;
 
     vec2 tc = vec2(0.0);
@@ -45,7 +49,7 @@ void main() {
     v_TexCoord = tc;
 
     vec2 u = normalize(max-min);
-    vec2 ortho = vec2(u.y,-u.x)*inv_resolution*v_w/2.0;
+    vec2 ortho = vec2(u.y,-u.x)*inv_resolution*render_scale*v_w/2.0;
 
 
     vec2 a = min -ortho;
@@ -0,0 +1,36 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 1) in float v_metal;
+
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+const vec3 GLOW_COLOR = vec3(0.3, 0.8, 1.0);
+
+void main() {
+    float d = length(v_TexCoord - vec2(0.5)) * 2.0;
+    if (d > 1.0) {
+        discard;
+    }
+    //Bright core and a ring, richer spots glow brighter
+    float core = 1.0 - smoothstep(0.0, 0.4, d);
+    float ring = 1.0 - smoothstep(0.0, 0.08, abs(d - 0.75));
+    float pulse = 0.8 + 0.2 * sin(time * 3.0);
+    float strength = clamp(0.5 + v_metal, 0.5, 1.5);
+    o_Target = vec4(GLOW_COLOR, (core + ring * 0.7) * pulse * strength * 0.6);
+}
@@ -0,0 +1,40 @@
+#version 450
+
+layout(location = 0) in vec4 ground_pos_metal;
+
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out float v_metal;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+//m
+const float DECAL_RADIUS = 6.0;
+
+void main() {
+    vec2 tc = vec2(0.0);
+    switch(gl_VertexIndex) {
+        case 0: tc = vec2(1.0, 0.0); break;
+        case 1: tc = vec2(1.0, 1.0); break;
+        case 2: tc = vec2(0.0, 0.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+    }
+    v_TexCoord = tc;
+    v_metal = ground_pos_metal.w;
+
+    //Flat quad slightly above the ground, spots are placed on flat ground
+    vec3 world_pos = ground_pos_metal.xyz + vec3((tc - vec2(0.5)) * 2.0 * DECAL_RADIUS, 0.1);
+    gl_Position = cor_proj_view * vec4(world_pos, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+//Previous level, sampled between its texels: a box filter of 2x2 texels
+layout(set = 0, binding = 0) uniform texture2D t_level;
+layout(set = 0, binding = 1) uniform sampler s_level;
+
+void main() {
+    o_Target = texture(sampler2D(t_level, s_level), v_TexCoord);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 1) in float v_alpha;
+layout(location = 2) in float v_team;
+
+layout(location = 0) out vec4 o_Target;
+
+void main() {
+    float d = length(v_TexCoord - vec2(0.5)) * 2.0;
+    if (d > 1.0) {
+        discard;
+    }
+
+    //Green nano particles, slightly tinted with the team color
+    vec3 team_color = v_team == 0.0 ? vec3(0.0, 0.3, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 color = mix(vec3(0.3, 1.0, 0.4), team_color, 0.2);
+
+    o_Target = vec4(color, (1.0 - d * d) * v_alpha);
+}
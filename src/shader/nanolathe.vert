@@ -0,0 +1,72 @@
+#version 450
+
+layout(location = 0) in vec4 from_seed;
+layout(location = 1) in vec4 to_team;
+
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out float v_alpha;
+layout(location = 2) out float v_team;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+const int PARTICLES_PER_STREAM = 24;
+
+vec3 hash3(uint n) {
+    n = (n << 13U) ^ n;
+    n = n * (n * n * 15731U + 789221U) + 1376312589U;
+    uvec3 k = n * uvec3(n, n * 16807U, n * 48271U);
+    return vec3(k & uvec3(0x7fffffffU)) / float(0x7fffffff);
+}
+
+void main() {
+    int particle = gl_VertexIndex / 6;
+    int corner = gl_VertexIndex % 6;
+
+    vec3 from = from_seed.xyz;
+    vec3 to = to_team.xyz;
+    v_team = to_team.w;
+
+    vec3 rnd = hash3(uint(particle) + uint(from_seed.w * 1000.0));
+
+    //Each particle travels from the nozzle to the target, then loops
+    float t = fract(time * 0.8 + float(particle) / float(PARTICLES_PER_STREAM) + rnd.x * 0.1);
+
+    //The stream widens in the middle and converges on both ends
+    float spread = sin(t * 3.141592) * 0.4;
+    vec3 jitter = (rnd - vec3(0.5)) * 2.0 * spread;
+    vec3 world_pos = mix(from, to, t) + jitter;
+
+    vec2 tc = vec2(0.0);
+    switch(corner) {
+        case 0: tc = vec2(0.0, 0.0); break;
+        case 1: tc = vec2(1.0, 0.0); break;
+        case 2: tc = vec2(0.0, 1.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+        case 4: tc = vec2(1.0, 0.0); break;
+        case 5: tc = vec2(1.0, 1.0); break;
+    }
+    v_TexCoord = tc;
+
+    //Fade in when leaving the nozzle and out when reaching the target
+    v_alpha = smoothstep(0.0, 0.1, t) * (1.0 - smoothstep(0.85, 1.0, t));
+
+    vec3 cam_right = vec3(u_View[0][0], u_View[1][0], u_View[2][0]);
+    vec3 cam_up = vec3(u_View[0][1], u_View[1][1], u_View[2][1]);
+    float size = 0.08 + 0.06 * rnd.y;
+    vec3 offset = (tc.x - 0.5) * 2.0 * size * cam_right + (tc.y - 0.5) * 2.0 * size * cam_up;
+
+    gl_Position = cor_proj_view * vec4(world_pos + offset, 1.0);
+}
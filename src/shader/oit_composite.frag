@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform texture2D t_accum;
+layout(set = 0, binding = 1) uniform texture2D t_reveal;
+layout(set = 0, binding = 2) uniform sampler s_oit;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    float reveal = texelFetch(sampler2D(t_reveal, s_oit), texel, 0).r;
+    //No transparent here
+    if (reveal >= 1.0) {
+        discard;
+    }
+    vec4 accum = texelFetch(sampler2D(t_accum, s_oit), texel, 0);
+    vec3 average = accum.rgb / clamp(accum.a, 1e-4, 5e4);
+    o_Target = vec4(average, 1.0 - reveal);
+}
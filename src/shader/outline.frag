@@ -0,0 +1,55 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+};
+
+layout(set = 1, binding = 0) uniform texture2D t_position;
+layout(set = 1, binding = 1) uniform texture2D t_normal;
+layout(set = 1, binding = 2) uniform sampler s_nearest;
+
+const vec3 OUTLINE_COLOR = vec3(0.05, 0.05, 0.08);
+//Relative depth jump that draws a line, so that the width stays the same at any zoom
+const float DEPTH_THRESHOLD = 0.02;
+//Cosine under which two normals draw a line
+const float NORMAL_THRESHOLD = 0.7;
+
+//View depth, nothing drawn counts as far away
+float view_depth(vec4 pos) {
+    return pos.w < -0.5 ? 1e9 : -(u_View * vec4(pos.xyz, 1.0)).z;
+}
+
+void main() {
+    vec4 pos = texture(sampler2D(t_position, s_nearest), v_TexCoord);
+    vec3 normal = texture(sampler2D(t_normal, s_nearest), v_TexCoord).xyz;
+    float depth = view_depth(pos);
+
+    //The nearer side of an edge draws it, so the line stays on the silhouette of the unit
+    float edge = 0.0;
+    vec2 offsets[4] = vec2[](vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1));
+    for (int i = 0; i < 4; i++) {
+        vec2 uv = v_TexCoord + offsets[i] * inv_resolution;
+        vec4 other_pos = texture(sampler2D(t_position, s_nearest), uv);
+        vec3 other_normal = texture(sampler2D(t_normal, s_nearest), uv).xyz;
+        float other_depth = view_depth(other_pos);
+        if (other_depth - depth > depth * DEPTH_THRESHOLD) {
+            edge = 1.0;
+        }
+        if (pos.w > -0.5 && other_pos.w > -0.5 && dot(normal, other_normal) < NORMAL_THRESHOLD) {
+            edge = max(edge, 0.6);
+        }
+    }
+    if (pos.w < -0.5) {
+        edge = 0.0;
+    }
+    o_Target = vec4(OUTLINE_COLOR, edge);
+}
@@ -0,0 +1,68 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+//Must match range_ring::MAX_RINGS
+#define MAX_RINGS 64
+
+layout(set = 1, binding = 0) uniform RangeRings {
+    vec4 ring_count;
+    //x, y, radius, kind
+    vec4 center_radius_kind[MAX_RINGS];
+};
+layout(set = 1, binding = 1) uniform texture2D t_position;
+layout(set = 1, binding = 2) uniform sampler s_nearest;
+
+//Indexed by range_ring::RingKind
+const vec3 RING_COLORS[5] = vec3[](
+    vec3(1.0, 0.25, 0.2),
+    vec3(0.3, 0.6, 1.0),
+    vec3(0.3, 1.0, 0.4),
+    vec3(1.0, 0.9, 0.3),
+    vec3(0.8, 0.4, 1.0)
+);
+
+void main() {
+    vec4 pos = texture(sampler2D(t_position, s_nearest), v_TexCoord);
+    if (pos.w < -0.5) {
+        discard;
+    }
+
+    //Line width in pixels: world units covered by a pixel at this depth
+    float depth = -(u_View * vec4(pos.xyz, 1.0)).z;
+    float pixel = 2.0 * depth / (u_proj[1][1] * resolution.y);
+    float width = 1.5 * pixel;
+
+    vec4 color = vec4(0.0);
+    for (int i = 0; i < int(ring_count.x); i++) {
+        vec4 ring = center_radius_kind[i];
+        float d = abs(distance(pos.xy, ring.xy) - ring.z);
+        float line = 1.0 - smoothstep(width, width + pixel, d);
+        //Faint fill inside, so overlapping ranges stay readable
+        float fill = distance(pos.xy, ring.xy) < ring.z ? 0.06 : 0.0;
+        float alpha = max(line * 0.9, fill);
+        if (alpha > color.a) {
+            color = vec4(RING_COLORS[int(ring.w)], alpha);
+        }
+    }
+    if (color.a <= 0.0) {
+        discard;
+    }
+    o_Target = color;
+}
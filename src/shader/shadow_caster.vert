@@ -0,0 +1,29 @@
+#version 450
+
+layout(location = 0) in vec4 a_Pos;
+
+layout(location = 3) in vec3 inst_pos;
+layout(location = 4) in vec3 inst_euler;
+
+layout(set = 0, binding = 0) uniform Cascade {
+    mat4 light_view_proj;
+};
+
+void main() {
+    float sr = sin(inst_euler.x);
+    float cr = cos(inst_euler.x);
+    float sp = sin(inst_euler.y);
+    float cp = cos(inst_euler.y);
+    float sy = sin(inst_euler.z);
+    float cy = cos(inst_euler.z);
+
+    //Same transform as cube_instanced.vert
+    mat4 t = mat4(
+        cy * cp,                 sy * cp,                -sp                ,0, 
+        cy * sp * sr - sy * cr,  sy * sp * sr + cy * cr, cp * sr            ,0,
+        cy * sp * cr + sy * sr,  sy * sp * cr - cy * sr, cp * cr            ,0, 
+        inst_pos.x,              inst_pos.y            , inst_pos.z         ,1);
+
+    vec4 world_pos4 = t * a_Pos;
+    gl_Position = light_view_proj * vec4(world_pos4.xyz / world_pos4.w, 1.0);
+}
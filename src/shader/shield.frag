@@ -0,0 +1,75 @@
+#version 450
+
+layout(location = 0) in vec3 world_pos;
+layout(location = 1) in vec3 v_world_normal;
+layout(location = 2) in float v_radius;
+
+#ifdef OIT
+layout(location = 0) out vec4 o_accum;
+layout(location = 1) out float o_reveal;
+#else
+layout(location = 0) out vec4 o_Target;
+#endif
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+layout(set = 1, binding = 0) uniform Impacts {
+    //count, life time
+    vec4 impact_count;
+    //position, age
+    vec4 impacts[16];
+};
+
+//Weighted blended order-independent transparency: the weight favors the near and opaque
+//fragments, see oit.rs
+void write_color(vec4 color) {
+#ifdef OIT
+    float weight = clamp(pow(min(1.0, color.a * 10.0) + 0.01, 3.0) * 1e8
+        * pow(1.0 - gl_FragCoord.z * 0.9, 3.0), 1e-2, 3e3);
+    o_accum = vec4(color.rgb * color.a, color.a) * weight;
+    o_reveal = color.a;
+#else
+    o_Target = color;
+#endif
+}
+
+void main() {
+    mat3 rot = mat3(u_View);
+    vec3 camera_pos = -u_View[3].xyz * rot;
+    vec3 view_dir = normalize(camera_pos - world_pos);
+    vec3 normal = normalize(v_world_normal);
+
+    float fresnel = pow(1.0 - abs(dot(normal, view_dir)), 3.0);
+
+    //A ring grows from each impact point and fades with age
+    float ripple = 0.0;
+    float life_time = impact_count.y;
+    for (int i = 0; i < int(impact_count.x); i++) {
+        float age = impacts[i].w;
+        float d = length(world_pos - impacts[i].xyz);
+        if (d < v_radius * 1.5) {
+            float front = age / life_time * v_radius * 1.5;
+            float ring = 1.0 - smoothstep(0.0, 0.3, abs(d - front));
+            ripple += ring * (1.0 - age / life_time);
+        }
+    }
+
+    float shimmer = 0.5 + 0.5 * sin(time * 2.0 + world_pos.z * 4.0);
+    vec3 color = vec3(0.35, 0.6, 1.0);
+    float alpha = 0.04 + fresnel * (0.5 + 0.1 * shimmer) + ripple * 0.6;
+
+    write_color(vec4(color + vec3(ripple * 0.5), clamp(alpha, 0.0, 0.9)));
+}
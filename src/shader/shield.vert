@@ -0,0 +1,35 @@
+#version 450
+
+layout(location = 0) in vec4 a_Pos;
+layout(location = 1) in vec3 a_normal;
+layout(location = 2) in vec2 a_TexCoord;
+
+layout(location = 3) in vec4 inst_center_radius;
+
+layout(location = 0) out vec3 world_pos;
+layout(location = 1) out vec3 v_world_normal;
+layout(location = 2) out float v_radius;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+void main() {
+    //The sphere mesh is projected on the unit sphere, then scaled to the shield radius
+    vec3 dir = normalize(a_Pos.xyz);
+    v_world_normal = dir;
+    v_radius = inst_center_radius.w;
+    world_pos = inst_center_radius.xyz + dir * inst_center_radius.w;
+    gl_Position = cor_proj_view * vec4(world_pos, 1.0);
+}
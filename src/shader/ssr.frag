@@ -0,0 +1,131 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+//rgb: reflected radiance, a: roughness of the reflecting surface
+//Opaque surfaces store the difference to the environment cube their shading already added,
+//weighted by their fresnel. Water stores the whole radiance, a is WATER_MARK.
+//a < 0: no reflection
+layout(location = 0) out vec4 o_reflection;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+};
+layout(set = 0, binding = 9) uniform sampler s_environment;
+layout(set = 0, binding = 11) uniform textureCube t_specular;
+
+layout(set = 1, binding = 0) uniform texture2D t_position;
+layout(set = 1, binding = 1) uniform texture2D t_normal;
+//Last frame, this frame is not shaded yet where the rays land
+layout(set = 1, binding = 2) uniform texture2D t_last_color;
+layout(set = 1, binding = 3) uniform sampler s_nearest;
+layout(set = 1, binding = 4) uniform sampler s_linear;
+
+//Must match heightmap_phy.rs and water.vert
+const float WATER_LEVEL = 40.0;
+const float WATER_ROUGHNESS = 0.05;
+const float WATER_MARK = 2.0;
+//Rougher surfaces only reflect the environment cube
+const float MAX_ROUGHNESS = 0.6;
+//Must match cube_instanced.frag
+const float ENV_SPECULAR_LEVELS = 5.0;
+const vec3 F0 = vec3(0.3);
+const int STEPS = 48;
+const int REFINE_STEPS = 5;
+
+vec2 project(vec3 view_pos) {
+    vec4 clip = u_proj * vec4(view_pos, 1.0);
+    return clip.xy / clip.w * 0.5 + 0.5;
+}
+
+//View depth of the scene at uv, 1 where nothing was drawn
+float scene_depth(vec2 uv) {
+    vec4 pos = texture(sampler2D(t_position, s_nearest), uv);
+    if (pos.w < -0.5) {
+        return 1.0;
+    }
+    return (u_View * vec4(pos.xyz, 1.0)).z;
+}
+
+//Confidence of the hit, 0 on a miss
+float trace(vec3 origin, vec3 dir, out vec2 hit_uv) {
+    float step_len = max(2.0, -origin.z * 0.015);
+    vec3 current = origin;
+    vec3 delta = dir * step_len;
+    for (int i = 0; i < STEPS; i++) {
+        current += delta;
+        vec2 uv = project(current);
+        if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || current.z > 0.0) {
+            return 0.0;
+        }
+        float depth = scene_depth(uv);
+        float behind = depth - current.z;
+        //In front of the ray is fine, too far behind it is an occluder, not a hit
+        if (behind > 0.0 && behind < step_len * 2.0 && depth < 0.5) {
+            //Overshot, go back with a binary search
+            for (int k = 0; k < REFINE_STEPS; k++) {
+                delta *= 0.5;
+                current += scene_depth(project(current)) > current.z ? -delta : delta;
+            }
+            hit_uv = project(current);
+            //Fade near the screen borders and at the end of the march
+            vec2 border = abs(hit_uv - 0.5) * 2.0;
+            float edge = 1.0 - pow(max(border.x, border.y), 4.0);
+            return clamp(edge, 0.0, 1.0) * (1.0 - float(i) / float(STEPS));
+        }
+        delta *= 1.05;
+    }
+    return 0.0;
+}
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 pos_att = texelFetch(sampler2D(t_position, s_nearest), texel, 0);
+    if (pos_att.w < -0.5) {
+        o_reflection = vec4(-1.0);
+        return;
+    }
+    vec4 normal_roughness = texelFetch(sampler2D(t_normal, s_nearest), texel, 0);
+
+    mat3 rot = mat3(u_View);
+    vec3 camera_pos = -u_View[3].xyz * rot;
+    vec3 surface = pos_att.xyz;
+    vec3 normal = normal_roughness.xyz;
+    float roughness = normal_roughness.w;
+    bool water = surface.z < WATER_LEVEL && camera_pos.z > WATER_LEVEL;
+    if (water) {
+        vec3 to_surface = surface - camera_pos;
+        surface = camera_pos + to_surface * (WATER_LEVEL - camera_pos.z) / to_surface.z;
+        normal = vec3(0.0, 0.0, 1.0);
+        roughness = WATER_ROUGHNESS;
+    } else if (roughness < 0.0 || roughness > MAX_ROUGHNESS) {
+        o_reflection = vec4(-1.0);
+        return;
+    }
+
+    vec3 view_pos = (u_View * vec4(surface, 1.0)).xyz;
+    vec3 view_dir = normalize(view_pos);
+    vec3 reflected = normalize(reflect(view_dir, normalize(rot * normal)));
+    vec3 world_reflected = transpose(rot) * reflected;
+    float lod = roughness * (ENV_SPECULAR_LEVELS - 1.0);
+    vec3 env = textureLod(samplerCube(t_specular, s_environment), world_reflected, lod).rgb;
+
+    vec2 hit_uv = vec2(0.0);
+    float confidence = trace(view_pos, reflected, hit_uv);
+    vec3 hit = texture(sampler2D(t_last_color, s_linear), hit_uv).rgb;
+    //Glossier surfaces trust the trace more, it ignores the roughness
+    confidence *= 1.0 - roughness / MAX_ROUGHNESS * 0.5;
+
+    if (water) {
+        o_reflection = vec4(mix(env, hit, confidence), WATER_MARK);
+    } else {
+        float n_dot_v = max(dot(normal, -transpose(rot) * view_dir), 0.0);
+        vec3 fresnel = F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(1.0 - n_dot_v, 5.0);
+        o_reflection = vec4((hit - env) * confidence * fresnel, roughness);
+    }
+}
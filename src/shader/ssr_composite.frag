@@ -0,0 +1,49 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform texture2D t_reflection;
+layout(set = 0, binding = 1) uniform sampler s_reflection;
+
+//Blur radius in pixels of the roughest reflecting surface, see ssr.frag
+const float MAX_BLUR = 16.0;
+const float MAX_ROUGHNESS = 0.6;
+
+const vec2 POISSON[8] = vec2[](
+    vec2(-0.613, 0.617),
+    vec2(0.170, -0.040),
+    vec2(-0.299, 0.791),
+    vec2(0.645, 0.493),
+    vec2(-0.651, 0.717),
+    vec2(0.421, 0.027),
+    vec2(-0.817, -0.271),
+    vec2(-0.705, -0.668)
+);
+
+bool opaque(vec4 reflection) {
+    return reflection.a >= 0.0 && reflection.a <= 1.0;
+}
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    ivec2 size = textureSize(sampler2D(t_reflection, s_reflection), 0);
+    vec4 center = texelFetch(sampler2D(t_reflection, s_reflection), texel, 0);
+    //Water and the rough surfaces have nothing to add
+    if (!opaque(center)) {
+        discard;
+    }
+    //Blur by roughness, only over the reflections of opaque surfaces
+    float radius = center.a / MAX_ROUGHNESS * MAX_BLUR;
+    vec3 sum = center.rgb;
+    float count = 1.0;
+    for (int i = 0; i < 8; i++) {
+        ivec2 tap = clamp(texel + ivec2(POISSON[i] * radius), ivec2(0), size - 1);
+        vec4 reflection = texelFetch(sampler2D(t_reflection, s_reflection), tap, 0);
+        if (opaque(reflection)) {
+            sum += reflection.rgb;
+            count += 1.0;
+        }
+    }
+    o_Target = vec4(sum / count, 0.0);
+}
@@ -0,0 +1,69 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+};
+
+layout(set = 1, binding = 0) uniform texture2D t_color;
+layout(set = 1, binding = 1) uniform texture2D t_velocity;
+//Resolved last frame
+layout(set = 1, binding = 2) uniform texture2D t_history;
+layout(set = 1, binding = 3) uniform sampler s_linear;
+layout(set = 1, binding = 4) uniform sampler s_nearest;
+
+//Weight of this frame, the rest comes from the history
+const float CURRENT_WEIGHT = 0.1;
+
+//Clamping in YCoCg keeps the box tight around the luma
+vec3 rgb_to_ycocg(vec3 c) {
+    return vec3(
+        0.25 * c.r + 0.5 * c.g + 0.25 * c.b,
+        0.5 * c.r - 0.5 * c.b,
+        -0.25 * c.r + 0.5 * c.g - 0.25 * c.b
+    );
+}
+
+vec3 ycocg_to_rgb(vec3 c) {
+    return vec3(c.x + c.y - c.z, c.x + c.z, c.x - c.y - c.z);
+}
+
+void main() {
+    vec3 current = texture(sampler2D(t_color, s_nearest), v_TexCoord).rgb;
+
+    //Box of the colors around the pixel, and the longest motion among them so that the edges of
+    //moving units follow them
+    vec3 box_min = rgb_to_ycocg(current);
+    vec3 box_max = box_min;
+    vec2 velocity = vec2(0.0);
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 uv = v_TexCoord + vec2(x, y) * inv_resolution;
+            vec3 c = rgb_to_ycocg(texture(sampler2D(t_color, s_nearest), uv).rgb);
+            box_min = min(box_min, c);
+            box_max = max(box_max, c);
+            vec2 v = texture(sampler2D(t_velocity, s_nearest), uv).xy;
+            if (dot(v, v) > dot(velocity, velocity)) {
+                velocity = v;
+            }
+        }
+    }
+
+    vec2 history_uv = v_TexCoord - velocity;
+    if (any(lessThan(history_uv, vec2(0.0))) || any(greaterThan(history_uv, vec2(1.0)))) {
+        o_Target = vec4(current, 1.0);
+        return;
+    }
+    vec3 history = rgb_to_ycocg(texture(sampler2D(t_history, s_linear), history_uv).rgb);
+    history = ycocg_to_rgb(clamp(history, box_min, box_max));
+
+    o_Target = vec4(mix(history, current, CURRENT_WEIGHT), 1.0);
+}
@@ -0,0 +1,30 @@
+#version 450
+
+layout(location = 0) in float v_along;
+layout(location = 1) in float v_alpha;
+
+#ifdef OIT
+layout(location = 0) out vec4 o_accum;
+layout(location = 1) out float o_reveal;
+#else
+layout(location = 0) out vec4 o_Target;
+#endif
+
+//Weighted blended order-independent transparency: the weight favors the near and opaque
+//fragments, see oit.rs
+void write_color(vec4 color) {
+#ifdef OIT
+    float weight = clamp(pow(min(1.0, color.a * 10.0) + 0.01, 3.0) * 1e8
+        * pow(1.0 - gl_FragCoord.z * 0.9, 3.0), 1e-2, 3e3);
+    o_accum = vec4(color.rgb * color.a, color.a) * weight;
+    o_reveal = color.a;
+#else
+    o_Target = color;
+#endif
+}
+
+void main() {
+    //Hot near the head, grey smoke towards the tail
+    vec3 color = mix(vec3(0.6), vec3(1.0, 0.8, 0.5), v_along * v_along);
+    write_color(vec4(color, v_alpha * 0.6));
+}
@@ -0,0 +1,29 @@
+#version 450
+
+layout(location = 0) in vec3 a_Pos;
+layout(location = 1) in float a_along;
+layout(location = 2) in float a_alpha;
+
+layout(location = 0) out float v_along;
+layout(location = 1) out float v_alpha;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+void main() {
+    v_along = a_along;
+    v_alpha = a_alpha;
+    gl_Position = cor_proj_view * vec4(a_Pos, 1.0);
+}
@@ -22,6 +22,9 @@ layout(set = 0, binding = 0) uniform Locals {
     float pen_radius;
     float pen_strength;
     vec2 hmap_size;
+    float time;
+    float toon_bands;
+    float render_scale;
 };
 
 void main() {
@@ -38,8 +41,9 @@ void main() {
     }
     v_TexCoord = tc;
 
-    vec2 min = -inv_resolution*size;
-    vec2 max = inv_resolution*size;
+    //Drawn on the window sized ui target, resolution is the scene one
+    vec2 min = -inv_resolution*size*render_scale;
+    vec2 max = inv_resolution*size*render_scale;
 
     vec2 pos =   center  ;
      switch(gl_VertexIndex) {
@@ -0,0 +1,43 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform texture2D t_color;
+layout(set = 0, binding = 1) uniform sampler s_color;
+
+layout(set = 0, binding = 2) uniform Upscale {
+    //0 for bilinear only, see upscale::Upscale::sharpness
+    float sharpness;
+};
+
+vec3 fetch(vec2 uv) {
+    return texture(sampler2D(t_color, s_color), uv).rgb;
+}
+
+void main() {
+    vec4 color = texture(sampler2D(t_color, s_color), v_TexCoord);
+    if (sharpness <= 0.0) {
+        o_Target = color;
+        return;
+    }
+
+    //Contrast adaptive sharpening on the cross of source texels around the pixel: the negative
+    //lobe is limited so that no channel leaves the [min, max] of the neighbourhood
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(t_color, s_color), 0));
+    vec3 n = fetch(v_TexCoord + vec2(0.0, -texel.y));
+    vec3 s = fetch(v_TexCoord + vec2(0.0, texel.y));
+    vec3 w = fetch(v_TexCoord + vec2(-texel.x, 0.0));
+    vec3 e = fetch(v_TexCoord + vec2(texel.x, 0.0));
+    vec3 c = color.rgb;
+
+    vec3 mn = min(c, min(min(n, s), min(w, e)));
+    vec3 mx = max(c, max(max(n, s), max(w, e)));
+    vec3 headroom = min(mn, 1.0 - mx) / max(mx, 1e-4);
+    vec3 amount = sqrt(clamp(headroom, 0.0, 1.0));
+    //Between -1/8 and -1/5 like the AMD filter, scaled by the sharpness
+    vec3 lobe = -amount * mix(0.125, 0.2, sharpness) * sharpness;
+
+    vec3 sharpened = (c + (n + s + w + e) * lobe) / (1.0 + 4.0 * lobe);
+    o_Target = vec4(clamp(sharpened, 0.0, 1.0), color.a);
+}
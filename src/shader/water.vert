@@ -30,6 +30,7 @@ void main() {
     v_TexCoord = tc;
 
     v_floor_lwall_fwall_rwall = gl_InstanceIndex;
+    //Must match heightmap_phy::WATER_LEVEL
     float water_level = 40; 
     vec3 pos = vec3(0); 
  
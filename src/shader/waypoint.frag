@@ -0,0 +1,50 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 1) in float v_dist;
+layout(location = 2) in float v_kind;
+
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+//m, a dash and its gap
+const float DASH_LEN = 1.5;
+//m/s, dashes run toward the waypoints
+const float DASH_SPEED = 3.0;
+const vec3 LINE_COLOR = vec3(0.2, 1.0, 0.5);
+//Indexed by waypoint::WaypointKind
+const vec3 MARKER_COLORS[4] = vec3[](vec3(0.2, 1.0, 0.5), vec3(1.0, 0.8, 0.2), vec3(0.3, 0.6, 1.0), vec3(0.8, 0.4, 1.0));
+
+void main() {
+    if (v_kind < -0.5) {
+        if (fract((v_dist - time * DASH_SPEED) / DASH_LEN) > 0.5) {
+            discard;
+        }
+        float edge = 1.0 - abs(v_TexCoord.y - 0.5) * 2.0;
+        o_Target = vec4(LINE_COLOR, 0.8 * smoothstep(0.0, 0.4, edge));
+    } else {
+        //Ring with a dot at its center
+        float d = length(v_TexCoord - vec2(0.5)) * 2.0;
+        float ring = 1.0 - smoothstep(0.0, 0.12, abs(d - 0.75));
+        float dot = 1.0 - smoothstep(0.2, 0.3, d);
+        float alpha = max(ring, dot);
+        if (alpha <= 0.0) {
+            discard;
+        }
+        o_Target = vec4(MARKER_COLORS[int(v_kind)], alpha * 0.9);
+    }
+}
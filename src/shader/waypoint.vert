@@ -0,0 +1,54 @@
+#version 450
+
+layout(location = 0) in vec3 a_start;
+layout(location = 1) in vec3 a_end;
+//Distance along the path at start, kind: -1 for a piece of line, else waypoint::WaypointKind
+layout(location = 2) in vec2 a_dist_kind;
+
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out float v_dist;
+layout(location = 2) out float v_kind;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+    float time;
+};
+
+//m
+const float LINE_WIDTH = 0.35;
+const float MARKER_RADIUS = 1.2;
+
+void main() {
+    vec2 tc = vec2(0.0);
+    switch(gl_VertexIndex) {
+        case 0: tc = vec2(1.0, 0.0); break;
+        case 1: tc = vec2(1.0, 1.0); break;
+        case 2: tc = vec2(0.0, 0.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+    }
+    v_TexCoord = tc;
+    v_kind = a_dist_kind.y;
+
+    vec3 world_pos;
+    if (a_dist_kind.y < -0.5) {
+        //Ribbon lying on the ground from start to end
+        vec3 along = a_end - a_start;
+        vec2 side = normalize(vec2(-along.y, along.x) + vec2(1e-6)) * LINE_WIDTH * 0.5;
+        world_pos = mix(a_start, a_end, tc.x) + vec3(side * (tc.y * 2.0 - 1.0), 0.0);
+        v_dist = a_dist_kind.x + length(along) * tc.x;
+    } else {
+        world_pos = a_start + vec3((tc - vec2(0.5)) * 2.0 * MARKER_RADIUS, 0.0);
+        v_dist = 0.0;
+    }
+    gl_Position = cor_proj_view * vec4(world_pos, 1.0);
+}
@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in vec2 v_TexCoord;
+layout(location = 1) in float v_alpha;
+layout(location = 2) flat in int v_kind;
+
+layout(location = 0) out vec4 o_Target;
+
+void main() {
+    vec4 color;
+    if (v_kind == 1) {
+        float fade = v_TexCoord.y;
+        color = vec4(0.7, 0.75, 0.85, 0.35 * fade * v_alpha);
+    } else {
+        float d = length(v_TexCoord - vec2(0.5)) * 2.0;
+        if (d > 1.0) {
+            discard;
+        }
+        color = vec4(vec3(0.95), (1.0 - d * d) * 0.9 * v_alpha);
+    }
+    o_Target = color;
+}
@@ -0,0 +1,81 @@
+#version 450
+
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out float v_alpha;
+layout(location = 2) flat out int v_kind;
+
+layout(set = 0, binding = 0) uniform Locals {
+    mat4 cor_proj_view;
+    mat4 u_View;
+    mat4 u_proj;
+    mat4 u_Normal;
+    vec2 mouse_pos;
+    vec2 resolution;
+    vec2 inv_resolution;
+    vec2 start_drag;
+    float pen_radius;
+    float pen_strength;
+    vec2 hmap_size;
+};
+
+layout(set = 1, binding = 0) uniform WeatherCfg {
+    vec3 cam_pos;
+    float time;
+    vec2 wind;
+    float fall_speed;
+    float box_size;
+    float kind;
+    float intensity;
+    vec2 _pad;
+};
+
+vec3 hash3(uint n) {
+    n = (n << 13U) ^ n;
+    n = n * (n * n * 15731U + 789221U) + 1376312589U;
+    uvec3 k = n * uvec3(n, n * 16807U, n * 48271U);
+    return vec3(k & uvec3(0x7fffffffU)) / float(0x7fffffff);
+}
+
+void main() {
+    v_kind = int(round(kind));
+    vec3 rnd = hash3(uint(gl_InstanceIndex));
+
+    //Snow flakes wander a bit on top of the wind
+    vec3 velocity = vec3(wind, -fall_speed * (0.8 + 0.4 * rnd.z));
+    if (v_kind == 2) {
+        velocity.xy += 0.6 * vec2(sin(time * 1.3 + rnd.x * 40.0), cos(time * 1.1 + rnd.y * 40.0));
+    }
+
+    //Wrap the particle in a box centered on the camera
+    vec3 corner = cam_pos - vec3(box_size * 0.5);
+    vec3 p = rnd * box_size + velocity * time;
+    vec3 world_pos = corner + mod(p - corner, vec3(box_size));
+
+    vec2 tc = vec2(0.0);
+    switch(gl_VertexIndex) {
+        case 0: tc = vec2(1.0, 0.0); break;
+        case 1: tc = vec2(1.0, 1.0); break;
+        case 2: tc = vec2(0.0, 0.0); break;
+        case 3: tc = vec2(0.0, 1.0); break;
+    }
+    v_TexCoord = tc;
+
+    vec3 cam_right = vec3(u_View[0][0], u_View[1][0], u_View[2][0]);
+    vec3 cam_up = vec3(u_View[0][1], u_View[1][1], u_View[2][1]);
+
+    vec3 offset;
+    if (v_kind == 1) {
+        //Rain drops are stretched along their velocity
+        vec3 axis = normalize(velocity) * 0.6;
+        vec3 side = cam_right * 0.015;
+        offset = (tc.x - 0.5) * 2.0 * side + (tc.y - 0.5) * 2.0 * axis;
+    } else {
+        float size = 0.06 + 0.04 * rnd.x;
+        offset = (tc.x - 0.5) * 2.0 * size * cam_right + (tc.y - 0.5) * 2.0 * size * cam_up;
+    }
+
+    float dist = length(world_pos - cam_pos);
+    v_alpha = clamp(1.0 - dist / (box_size * 0.5), 0.0, 1.0);
+
+    gl_Position = cor_proj_view * vec4(world_pos + offset, 1.0);
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+///Gradient the environment lighting is baked from, per map. Linear colors, the sun itself is
+///left out: its direct light is shaded separately.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Sky {
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+    ///Below the horizon, light bounced by the ground
+    pub ground: [f32; 3],
+}
+
+impl Sky {
+    pub fn day() -> Self {
+        Sky {
+            zenith: [0.12, 0.22, 0.45],
+            horizon: [0.35, 0.4, 0.45],
+            ground: [0.08, 0.07, 0.05],
+        }
+    }
+
+    pub fn presets() -> Vec<(&'static str, Sky)> {
+        vec![
+            ("Day", Self::day()),
+            (
+                "Dusk",
+                Sky {
+                    zenith: [0.06, 0.06, 0.18],
+                    horizon: [0.45, 0.22, 0.1],
+                    ground: [0.05, 0.03, 0.02],
+                },
+            ),
+            (
+                "Overcast",
+                Sky {
+                    zenith: [0.25, 0.26, 0.28],
+                    horizon: [0.2, 0.21, 0.22],
+                    ground: [0.06, 0.06, 0.06],
+                },
+            ),
+        ]
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self::day()
+    }
+}
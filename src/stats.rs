@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+pub const CSV_PATH: &str = "match_stats.csv";
+
+///Time series recorded for each player every simulation tick
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Series {
+    UnitsBuilt,
+    UnitsLost,
+    ///Stock
+    Metal,
+    ///metal/frame, stock variation plus metal spent during the tick
+    MetalIncome,
+    ///Metal cost of the alive units, weighted by con_completed
+    ArmyValue,
+    DamageDealt,
+}
+
+impl Series {
+    pub const ALL: [Series; 6] = [
+        Series::UnitsBuilt,
+        Series::UnitsLost,
+        Series::Metal,
+        Series::MetalIncome,
+        Series::ArmyValue,
+        Series::DamageDealt,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Series::UnitsBuilt => "units built",
+            Series::UnitsLost => "units lost",
+            Series::Metal => "metal",
+            Series::MetalIncome => "metal income",
+            Series::ArmyValue => "army value",
+            Series::DamageDealt => "damage dealt",
+        }
+    }
+}
+
+///Gathered during the match for the end of game screen
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PlayerStats {
+    pub units_built: u32,
+    pub units_lost: u32,
+    pub metal_spent: f64,
+    pub damage_dealt: i64,
+    ///metal_spent at the previous tick, to derive the income
+    pub last_metal_spent: f64,
+    ///Stock at the previous tick, to derive the income
+    pub last_metal: f64,
+    ///One row of Series::ALL.len() values per tick, flattened to stay compact
+    rows: Vec<f32>,
+}
+
+impl PlayerStats {
+    pub fn new() -> Self {
+        PlayerStats {
+            units_built: 0,
+            units_lost: 0,
+            metal_spent: 0.0,
+            damage_dealt: 0,
+            last_metal_spent: 0.0,
+            last_metal: 0.0,
+            rows: Vec::new(),
+        }
+    }
+
+    ///Used to decide the winner of frame::WinCondition::TimedScore
+    pub fn score(&self) -> f64 {
+        self.metal_spent + self.damage_dealt as f64
+    }
+
+    ///Values in the order of Series::ALL
+    pub fn push_row(&mut self, row: [f32; 6]) {
+        self.rows.extend_from_slice(&row);
+    }
+
+    pub fn tick_count(&self) -> usize {
+        self.rows.len() / Series::ALL.len()
+    }
+
+    pub fn series(&self, series: Series) -> Vec<f32> {
+        let column = Series::ALL.iter().position(|s| *s == series).unwrap();
+        self.rows
+            .iter()
+            .skip(column)
+            .step_by(Series::ALL.len())
+            .copied()
+            .collect()
+    }
+
+    ///One line per tick, the header is written by write_csv_header
+    fn write_csv(&self, w: &mut impl Write, player: &str) -> io::Result<()> {
+        for (tick, row) in self.rows.chunks(Series::ALL.len()).enumerate() {
+            write!(w, "{},{}", player, tick)?;
+            for value in row {
+                write!(w, ",{}", value)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    fn write_csv_header(w: &mut impl Write) -> io::Result<()> {
+        write!(w, "player,tick")?;
+        for series in Series::ALL.iter() {
+            write!(w, ",{}", series.name())?;
+        }
+        writeln!(w)
+    }
+}
+
+///Series of every player in a single csv file, for external analysis
+pub fn write_csv_file<'a>(
+    path: &str,
+    players: impl Iterator<Item = (String, &'a PlayerStats)>,
+) -> io::Result<()> {
+    let mut w = io::BufWriter::new(std::fs::File::create(path)?);
+    PlayerStats::write_csv_header(&mut w)?;
+    for (player, stats) in players {
+        stats.write_csv(&mut w, &player)?;
+    }
+    w.flush()
+}
@@ -0,0 +1,365 @@
+use crate::utils::ImageRGBA8;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+use std::path::Path;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+///Pixel data of a texture file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Rgba8 {
+        srgb: bool,
+    },
+    ///Two channels, for normal maps
+    Bc5,
+    Bc7 {
+        srgb: bool,
+    },
+}
+
+impl Format {
+    ///Bytes of a level, blocks of 4x4 texels for the compressed formats
+    fn level_bytes(&self, width: u32, height: u32) -> usize {
+        match self {
+            Format::Rgba8 { .. } => (width * height * 4) as usize,
+            Format::Bc5 | Format::Bc7 { .. } => {
+                (((width + 3) / 4) * ((height + 3) / 4) * 16) as usize
+            }
+        }
+    }
+
+    fn from_vk(vk_format: u32) -> Option<Format> {
+        match vk_format {
+            37 => Some(Format::Rgba8 { srgb: false }),
+            43 => Some(Format::Rgba8 { srgb: true }),
+            141 => Some(Format::Bc5),
+            145 => Some(Format::Bc7 { srgb: false }),
+            146 => Some(Format::Bc7 { srgb: true }),
+            _ => None,
+        }
+    }
+
+    fn to_vk(&self) -> u32 {
+        match self {
+            Format::Rgba8 { srgb: false } => 37,
+            Format::Rgba8 { srgb: true } => 43,
+            Format::Bc5 => 141,
+            Format::Bc7 { srgb: false } => 145,
+            Format::Bc7 { srgb: true } => 146,
+        }
+    }
+
+    fn from_dxgi(dxgi_format: u32) -> Option<Format> {
+        match dxgi_format {
+            28 => Some(Format::Rgba8 { srgb: false }),
+            29 => Some(Format::Rgba8 { srgb: true }),
+            83 => Some(Format::Bc5),
+            98 => Some(Format::Bc7 { srgb: false }),
+            99 => Some(Format::Bc7 { srgb: true }),
+            _ => None,
+        }
+    }
+}
+
+///2D texture with its mip chain, read from a KTX2 or DDS container
+pub struct TextureFile {
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+    ///Level 0 first
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureFile {
+    pub fn open(path: &Path) -> Result<TextureFile, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        let file = match path.extension().and_then(|e| e.to_str()) {
+            Some("ktx2") => Self::parse_ktx2(&bytes),
+            Some("dds") => Self::parse_dds(&bytes),
+            _ => Err("not a .ktx2 or .dds".to_owned()),
+        };
+        file.map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    ///Full mip chain of img, each level a box filter of the previous one
+    pub fn from_image(img: &ImageRGBA8, srgb: bool) -> TextureFile {
+        let mut levels = vec![img.data.clone()];
+        let (mut width, mut height) = (img.w, img.h);
+        while width > 1 || height > 1 {
+            let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+            let previous = levels.last().unwrap();
+            let mut level = Vec::with_capacity((next_width * next_height * 4) as usize);
+            for y in 0..next_height {
+                for x in 0..next_width {
+                    for c in 0..4 {
+                        let texel = |x: u32, y: u32| {
+                            let (x, y) = (x.min(width - 1), y.min(height - 1));
+                            previous[((y * width + x) * 4 + c) as usize] as u32
+                        };
+                        let sum = texel(x * 2, y * 2)
+                            + texel(x * 2 + 1, y * 2)
+                            + texel(x * 2, y * 2 + 1)
+                            + texel(x * 2 + 1, y * 2 + 1);
+                        level.push(((sum + 2) / 4) as u8);
+                    }
+                }
+            }
+            levels.push(level);
+            width = next_width;
+            height = next_height;
+        }
+        TextureFile {
+            format: Format::Rgba8 { srgb },
+            width: img.w,
+            height: img.h,
+            levels,
+        }
+    }
+
+    ///Level 0 as an image, only for rgba8: wgpu 0.4 has no BC texture formats
+    pub fn to_image(&self) -> Result<ImageRGBA8, String> {
+        match self.format {
+            Format::Rgba8 { .. } => Ok(ImageRGBA8 {
+                w: self.width,
+                h: self.height,
+                data: self.levels[0].clone(),
+            }),
+            format => Err(format!("{:?} can't be uploaded", format)),
+        }
+    }
+
+    fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    fn parse_ktx2(bytes: &[u8]) -> Result<TextureFile, String> {
+        if bytes.len() < 80 || bytes[..12] != KTX2_IDENTIFIER {
+            return Err("not a ktx2 file".to_owned());
+        }
+        let mut cursor = Cursor::new(&bytes[12..]);
+        let mut read = || cursor.read_u32::<LittleEndian>().map_err(|e| e.to_string());
+        let vk_format = read()?;
+        let _type_size = read()?;
+        let width = read()?;
+        let height = read()?;
+        let depth = read()?;
+        let layers = read()?;
+        let faces = read()?;
+        let level_count = read()?.max(1);
+        let supercompression = read()?;
+        if depth > 1 || layers > 1 || faces > 1 {
+            return Err("only 2D textures are supported".to_owned());
+        }
+        if supercompression != 0 {
+            return Err(format!("supercompression scheme {}", supercompression));
+        }
+        let format = Format::from_vk(vk_format).ok_or_else(|| format!("vkFormat {}", vk_format))?;
+
+        //Level index follows the dfd, kvd and sgd offsets
+        let mut cursor = Cursor::new(bytes);
+        cursor.set_position(80);
+        let mut file = TextureFile {
+            format,
+            width,
+            height,
+            levels: Vec::new(),
+        };
+        for level in 0..level_count as usize {
+            let mut read = || cursor.read_u64::<LittleEndian>().map_err(|e| e.to_string());
+            let offset = read()? as usize;
+            let length = read()? as usize;
+            let _uncompressed_length = read()?;
+            let (w, h) = file.level_size(level);
+            if length != format.level_bytes(w, h) || offset + length > bytes.len() {
+                return Err(format!("level {} has {} bytes", level, length));
+            }
+            file.levels.push(bytes[offset..offset + length].to_vec());
+        }
+        Ok(file)
+    }
+
+    fn parse_dds(bytes: &[u8]) -> Result<TextureFile, String> {
+        if bytes.len() < 128 || &bytes[..4] != b"DDS " {
+            return Err("not a dds file".to_owned());
+        }
+        let mut cursor = Cursor::new(bytes);
+        let mut read_at = |offset: u64| {
+            cursor.set_position(offset);
+            cursor.read_u32::<LittleEndian>().map_err(|e| e.to_string())
+        };
+        let height = read_at(12)?;
+        let width = read_at(16)?;
+        let level_count = read_at(28)?.max(1);
+        let pixel_flags = read_at(80)?;
+        let four_cc = read_at(84)?;
+        let bit_count = read_at(88)?;
+        let red_mask = read_at(92)?;
+
+        const DDPF_FOURCC: u32 = 0x4;
+        const DDPF_RGB: u32 = 0x40;
+        let four_cc = four_cc.to_le_bytes();
+        let (format, data_start) = if pixel_flags & DDPF_FOURCC != 0 {
+            match &four_cc {
+                b"DX10" => {
+                    let dxgi_format = read_at(128)?;
+                    let format = Format::from_dxgi(dxgi_format)
+                        .ok_or_else(|| format!("dxgi format {}", dxgi_format))?;
+                    (format, 148)
+                }
+                b"ATI2" | b"BC5U" => (Format::Bc5, 128),
+                _ => return Err(format!("fourCC {:?}", String::from_utf8_lossy(&four_cc))),
+            }
+        } else if pixel_flags & DDPF_RGB != 0 && bit_count == 32 && red_mask == 0xff {
+            (Format::Rgba8 { srgb: false }, 128)
+        } else {
+            return Err("unsupported pixel format".to_owned());
+        };
+
+        let mut file = TextureFile {
+            format,
+            width,
+            height,
+            levels: Vec::new(),
+        };
+        let mut offset = data_start;
+        for level in 0..level_count as usize {
+            let (w, h) = file.level_size(level);
+            let length = format.level_bytes(w, h);
+            if offset + length > bytes.len() {
+                return Err(format!("level {} is truncated", level));
+            }
+            file.levels.push(bytes[offset..offset + length].to_vec());
+            offset += length;
+        }
+        Ok(file)
+    }
+
+    ///KTX2 without supercompression, levels stored from the smallest
+    pub fn write_ktx2(&self, path: &Path) -> std::io::Result<()> {
+        let mut out = KTX2_IDENTIFIER.to_vec();
+        let dfd = self.data_format_descriptor()?;
+        let level_index_len = self.levels.len() * 24;
+        let dfd_offset = 80 + level_index_len;
+        let mut data_offset = dfd_offset + dfd.len();
+
+        for value in &[
+            self.format.to_vk(),
+            1,
+            self.width,
+            self.height,
+            0,
+            0,
+            1,
+            self.levels.len() as u32,
+            0,
+            dfd_offset as u32,
+            dfd.len() as u32,
+            0,
+            0,
+        ] {
+            out.write_u32::<LittleEndian>(*value)?;
+        }
+        //No supercompression global data
+        out.write_u64::<LittleEndian>(0)?;
+        out.write_u64::<LittleEndian>(0)?;
+
+        let mut offsets = vec![0; self.levels.len()];
+        for (level, data) in self.levels.iter().enumerate().rev() {
+            data_offset = (data_offset + 15) / 16 * 16;
+            offsets[level] = data_offset;
+            data_offset += data.len();
+        }
+        for (level, data) in self.levels.iter().enumerate() {
+            out.write_u64::<LittleEndian>(offsets[level] as u64)?;
+            out.write_u64::<LittleEndian>(data.len() as u64)?;
+            out.write_u64::<LittleEndian>(data.len() as u64)?;
+        }
+        out.extend_from_slice(&dfd);
+        for (level, data) in self.levels.iter().enumerate().rev() {
+            out.resize(offsets[level], 0);
+            out.extend_from_slice(data);
+        }
+        std::fs::write(path, out)
+    }
+
+    ///Basic descriptor block of the format, only rgba8 is written
+    fn data_format_descriptor(&self) -> std::io::Result<Vec<u8>> {
+        let srgb = match self.format {
+            Format::Rgba8 { srgb } => srgb,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("no ktx2 descriptor of {:?}", self.format),
+                ))
+            }
+        };
+        let block_size = 24 + 4 * 16;
+        let mut dfd = Vec::new();
+        let mut write = |value: u32| dfd.write_u32::<LittleEndian>(value).unwrap();
+        write(4 + block_size);
+        //Khronos vendor, basic descriptor type
+        write(0);
+        write(2 | (block_size << 16));
+        //RGBSDA model, BT709 primaries, transfer function, straight alpha
+        let transfer = if srgb { 2 } else { 1 };
+        write(1 | (1 << 8) | (transfer << 16));
+        //1x1x1 texel blocks
+        write(0);
+        write(4);
+        write(0);
+        //R, G, B then A (channel 15), 8 bits each
+        for (channel, id) in [0u32, 1, 2, 15].iter().enumerate() {
+            write((channel as u32 * 8) | (7 << 16) | (*id << 24));
+            write(0);
+            write(0);
+            write(255);
+        }
+        Ok(dfd)
+    }
+}
+
+///Command line step: every png under dir gets a .ktx2 next to it with its mip chain.
+///Files ending in _normal are linear, the others sRGB.
+///Without a BC encoder nor BC support in wgpu 0.4, levels stay rgba8.
+pub fn pack_textures(dir: &Path) {
+    log::info!("Pack textures of {:?}", dir);
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("{:?}: {}", dir, e);
+            return;
+        }
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            pack_textures(&path);
+        } else if path.extension().and_then(|e| e.to_str()) == Some("png") {
+            let mut img = ImageRGBA8::open(path.to_str().unwrap());
+            if img.data.len() == (img.w * img.h * 3) as usize {
+                img.data = img
+                    .data
+                    .chunks_exact(3)
+                    .flat_map(|p| vec![p[0], p[1], p[2], 255])
+                    .collect();
+            }
+            if img.data.len() != (img.w * img.h * 4) as usize {
+                log::warn!("{:?}: not an 8 bits rgb or rgba png, skipped", path);
+                continue;
+            }
+            let srgb = !path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(|s| s.ends_with("_normal"))
+                .unwrap_or(false);
+            let file = TextureFile::from_image(&img, srgb);
+            let out = path.with_extension("ktx2");
+            log::info!("write {} levels to {:?}", file.levels.len(), out);
+            if let Err(e) = file.write_ktx2(&out) {
+                log::error!("{:?}: {}", out, e);
+            }
+        }
+    }
+}
@@ -1,8 +1,5 @@
-use super::client::*;
-use crate::model::*;
 use crate::utils::FileTree;
 use crate::*;
-use gpu_obj::model_gpu::ModelGpu;
 use na::{Matrix4, Point3, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -13,6 +10,19 @@ pub struct PlacedMesh {
     pub trans: Matrix4<f32>,
     pub mesh_path: PathBuf,
     pub mesh_index: usize,
+    #[serde(default)]
+    pub material: Material,
+}
+
+///Shading flags of a placed mesh, for engine exhausts, radar dishes and nanolathe beams
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Material {
+    ///Light added on top of the lit color, 0 for none
+    pub emissive: f32,
+    ///Hz of the emissive pulse, 0 for a steady glow
+    pub pulse_frequency: f32,
+    ///Texture coordinates offset per sec
+    pub uv_scroll: [f32; 2],
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
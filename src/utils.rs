@@ -105,8 +105,33 @@ impl<T> IdBase for Id<T> {
     type Type = T;
 }
 
+thread_local! {
+    ///Set by seed_ids, thread_rng otherwise
+    static ID_RNG: std::cell::RefCell<Option<rand::rngs::StdRng>> = std::cell::RefCell::new(None);
+}
+
+///Ids drawn afterwards on this thread are the same on every run, see determinism.
+///None goes back to thread_rng
+pub fn seed_ids(seed: Option<u64>) {
+    use rand::SeedableRng;
+    ID_RNG.with(|rng| *rng.borrow_mut() = seed.map(rand::rngs::StdRng::seed_from_u64));
+}
+
 pub fn rand_id<T>() -> Id<T> {
-    Id::new(rand::prelude::random())
+    use rand::Rng;
+    ID_RNG.with(|rng| match rng.borrow_mut().as_mut() {
+        Some(rng) => Id::new(rng.gen()),
+        None => Id::new(rand::prelude::random()),
+    })
+}
+
+///Drawn like the ids, for the id_seed of a new game
+pub fn rand_seed() -> u64 {
+    use rand::Rng;
+    ID_RNG.with(|rng| match rng.borrow_mut().as_mut() {
+        Some(rng) => rng.gen(),
+        None => rand::prelude::random(),
+    })
 }
 
 pub fn rand_id_unsafe() -> String {
@@ -0,0 +1,446 @@
+use crate::botdef::Strategic;
+use crate::frame::{Frame, FrameEventFromPlayer, Player};
+use crate::mobile::{KBot, QueueEdit};
+use crate::utils::Id;
+use crate::vision::Vision;
+use fnv::FnvHashSet;
+use na::Vector3;
+
+///m, largest circle of an AreaOrder
+pub const MAX_AREA_RADIUS: f32 = 200.0;
+
+///Checks a remote order against what its player can do in frame, before the host simulates it.
+///The simulation trusts its events: an unknown unit or botdef would make it panic, and a unit of
+///another player would obey.
+pub fn check(frame: &Frame, event: &FrameEventFromPlayer) -> Result<(), String> {
+    use FrameEventFromPlayer::*;
+    let id = match event.player_id() {
+        Some(id) => id,
+        None => return Err("only the host gives it".to_owned()),
+    };
+    let player = frame
+        .players
+        .get(&id)
+        .ok_or_else(|| "unknown player".to_owned())?;
+    match event {
+        SpeedVote { .. } | Ping { .. } => return Ok(()),
+        StressSpawn { .. } | StressProjectiles { .. } | DebugEdit { .. } => {
+            return Err("debug orders are offline only".to_owned())
+        }
+        SpawnPrefab { .. } => return Err("only missions spawn prefabs".to_owned()),
+        _ => {}
+    }
+    if player.defeated {
+        return Err("defeated".to_owned());
+    }
+    let selected = match event {
+        RepairOrder { selected, .. }
+        | LoadOrder { selected, .. }
+        | UnloadOrder { selected, .. }
+        | ConOrder { selected, .. }
+        | MoveOrder { selected, .. }
+        | NukeOrder { selected, .. }
+        | DGunOrder { selected, .. }
+        | FerryOrder { selected, .. }
+        | PatrolOrder { selected, .. }
+        | GuardOrder { selected, .. }
+        | ReclaimOrder { selected, .. }
+        | StanceOrder { selected, .. }
+        | QueueOrder { selected, .. }
+        | AreaOrder { selected, .. } => controlled(frame, player, selected)?,
+        _ => Vec::new(),
+    };
+
+    match event {
+        RepairOrder { to_repair, .. } => {
+            let target = target(frame, player, *to_repair)?;
+            in_build_range(frame, &selected, target.position.coords)
+        }
+        ReclaimOrder { to_reclaim, .. } => {
+            let target = target(frame, player, *to_reclaim)?;
+            in_build_range(frame, &selected, target.position.coords)
+        }
+        LoadOrder { to_load, .. } => target(frame, player, *to_load).map(|_| ()),
+        GuardOrder { to_guard, .. } => target(frame, player, *to_guard).map(|_| ()),
+        ConOrder {
+            mouse_world_pos,
+            botdef_id,
+            ..
+        } => {
+            finite(mouse_world_pos)?;
+            let botdef = frame
+                .bot_defs
+                .get(botdef_id)
+                .ok_or_else(|| format!("unknown botdef {}", botdef_id))?;
+            if botdef.commander {
+                return Err("commanders can't be built".to_owned());
+            }
+            if botdef.tier > player.tech_tier {
+                return Err(format!(
+                    "{} of tier {} above tier {}",
+                    botdef_id, botdef.tier, player.tech_tier
+                ));
+            }
+            in_build_range(frame, &selected, *mouse_world_pos)
+        }
+        UnloadOrder {
+            mouse_world_pos, ..
+        }
+        | MoveOrder {
+            mouse_world_pos, ..
+        }
+        | PatrolOrder {
+            mouse_world_pos, ..
+        } => finite(mouse_world_pos),
+        FerryOrder {
+            pickup, dropoff, ..
+        } => finite(pickup).and_then(|_| finite(dropoff)),
+        NukeOrder {
+            mouse_world_pos, ..
+        } => {
+            finite(mouse_world_pos)?;
+            let armed = selected.iter().any(|kbot| {
+                kbot.stockpile > 0
+                    && match frame.bot_defs.get(&kbot.botdef_id).map(|b| &b.strategic) {
+                        Some(Strategic::Nuke { .. }) => true,
+                        _ => false,
+                    }
+            });
+            if armed {
+                Ok(())
+            } else {
+                Err("no nuke in stock".to_owned())
+            }
+        }
+        DGunOrder {
+            mouse_world_pos, ..
+        } => {
+            finite(mouse_world_pos)?;
+            let cost = selected
+                .iter()
+                .filter_map(|kbot| frame.bot_defs.get(&kbot.botdef_id))
+                .filter_map(|botdef| botdef.dgun.as_ref())
+                .map(|dgun| dgun.energy_cost)
+                .fold(None, |min: Option<f64>, cost| {
+                    Some(min.map(|min| min.min(cost)).unwrap_or(cost))
+                });
+            match cost {
+                None => Err("no dgun selected".to_owned()),
+                Some(cost) if player.energy < cost => Err(format!(
+                    "{:.0} energy for a dgun of {:.0}",
+                    player.energy, cost
+                )),
+                Some(_) => Ok(()),
+            }
+        }
+        QueueOrder { edit, .. } => {
+            let items = match edit {
+                QueueEdit::Push(item) => vec![*item],
+                QueueEdit::Replace(items) => items.clone(),
+                _ => Vec::new(),
+            };
+            for item in items {
+                let botdef = frame
+                    .bot_defs
+                    .get(&item)
+                    .ok_or_else(|| format!("unknown botdef {}", item))?;
+                if botdef.commander || botdef.tier > player.tech_tier {
+                    return Err(format!("can't queue {}", item));
+                }
+            }
+            Ok(())
+        }
+        AreaOrder { center, radius, .. } => {
+            finite(center)?;
+            if radius.is_finite() && *radius > 0.0 && *radius <= MAX_AREA_RADIUS {
+                Ok(())
+            } else {
+                Err(format!("area of radius {}", radius))
+            }
+        }
+        Gift {
+            to, metal, energy, ..
+        } => {
+            if *to == id || !frame.players.contains_key(to) {
+                return Err(format!("gift to {}", to));
+            }
+            let valid = |amount: f64| amount.is_finite() && amount >= 0.0;
+            if valid(*metal) && valid(*energy) {
+                Ok(())
+            } else {
+                Err(format!("gift of {} metal and {} energy", metal, energy))
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+///Units given the order, each one must obey the player. Those that died since the order was
+///given are left out.
+fn controlled<'a>(
+    frame: &'a Frame,
+    player: &Player,
+    selected: &FnvHashSet<Id<KBot>>,
+) -> Result<Vec<&'a KBot>, String> {
+    let mut kbots = Vec::with_capacity(selected.len());
+    for kbot in selected.iter().filter_map(|id| frame.kbots.get(id)) {
+        if !frame.alliance.controls(player, kbot) {
+            return Err(format!("unit {} belongs to another player", kbot.id));
+        }
+        kbots.push(kbot);
+    }
+    if kbots.is_empty() {
+        return Err("no unit selected".to_owned());
+    }
+    Ok(kbots)
+}
+
+///Unit targeted by the order, which must be seen by the team of the player with fog of war
+fn target<'a>(frame: &'a Frame, player: &Player, id: Id<KBot>) -> Result<&'a KBot, String> {
+    let kbot = frame
+        .kbots
+        .get(&id)
+        .ok_or_else(|| format!("unknown target {}", id))?;
+    if frame.alliance.fog_of_war
+        && kbot.team != player.team
+        && !Vision::of_team(frame, player.id).is_visible(kbot.position.x, kbot.position.y)
+    {
+        return Err(format!("target {} is not in sight", id));
+    }
+    Ok(kbot)
+}
+
+///A mobile builder walks to the target, a static one must already reach it
+fn in_build_range(frame: &Frame, selected: &[&KBot], to: Vector3<f32>) -> Result<(), String> {
+    let reachable = selected
+        .iter()
+        .any(|kbot| match frame.bot_defs.get(&kbot.botdef_id) {
+            Some(botdef) if botdef.build_power > 0.0 => {
+                !botdef.is_factory()
+                    || (kbot.position.coords - to).xy().magnitude() <= botdef.build_dist
+            }
+            _ => false,
+        });
+    if reachable {
+        Ok(())
+    } else {
+        Err("no builder in range".to_owned())
+    }
+}
+
+fn finite(position: &Vector3<f32>) -> Result<(), String> {
+    if position.iter().all(|c| c.is_finite()) {
+        Ok(())
+    } else {
+        Err(format!("position {:?}", position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::botdef::BotDef;
+    use crate::mobile::AreaKind;
+    use crate::utils;
+    use na::Point3;
+
+    const BOTDEF_DIR: &str = "src/asset/botdef";
+
+    struct Game {
+        frame: Frame,
+        me: Id<Player>,
+        other: Id<Player>,
+    }
+
+    impl Game {
+        ///Two players on two teams, without any unit
+        fn new() -> Self {
+            let mut frame = Frame::new();
+            for name in &["unit", "unit_t2", "building", "commander"] {
+                let path = format!("{}/{}_example.json", BOTDEF_DIR, name);
+                let mut botdef = BotDef::open(&path).unwrap();
+                botdef.file_path = path;
+                frame.add_bot_def(botdef);
+            }
+            let mut silo = BotDef::open(&format!("{}/building_example.json", BOTDEF_DIR)).unwrap();
+            silo.id = utils::rand_id();
+            silo.file_path = format!("{}/silo", BOTDEF_DIR);
+            silo.strategic = Strategic::Nuke {
+                stockpile_frames: 100,
+                damage: 1000,
+                radius: 50.0,
+            };
+            frame.add_bot_def(silo);
+
+            let me = Player::new();
+            let mut other = Player::new();
+            other.team = 1;
+            other.slot = 1;
+            let (me_id, other_id) = (me.id, other.id);
+            frame.players.insert(me.id, me);
+            frame.players.insert(other.id, other);
+            Game {
+                frame,
+                me: me_id,
+                other: other_id,
+            }
+        }
+
+        ///Botdef whose file name starts with name
+        fn botdef(&self, name: &str) -> Id<BotDef> {
+            let file = format!("{}/{}", BOTDEF_DIR, name);
+            self.frame
+                .bot_defs
+                .values()
+                .find(|b| b.file_path.starts_with(&file))
+                .unwrap()
+                .id
+        }
+
+        fn spawn(&mut self, botdef: Id<BotDef>, player: Id<Player>, x: f32, y: f32) -> Id<KBot> {
+            let mut kbot = KBot::new(
+                Point3::new(x, y, 0.0),
+                &self.frame.bot_defs[&botdef],
+                player,
+            );
+            let owner = self.frame.players.get_mut(&player).unwrap();
+            kbot.team = owner.team;
+            owner.kbots.insert(kbot.id);
+            let id = kbot.id;
+            self.frame.kbots.insert(id, kbot);
+            id
+        }
+    }
+
+    fn selected(ids: &[Id<KBot>]) -> FnvHashSet<Id<KBot>> {
+        ids.iter().cloned().collect()
+    }
+
+    fn at(x: f32, y: f32) -> Vector3<f32> {
+        Vector3::new(x, y, 0.0)
+    }
+
+    #[test]
+    fn rejects_units_not_owned() {
+        let mut game = Game::new();
+        let mine = game.spawn(game.botdef("unit_example"), game.me, 100.0, 100.0);
+        let theirs = game.spawn(game.botdef("unit_example"), game.other, 120.0, 100.0);
+        let order = |ids: &[Id<KBot>]| FrameEventFromPlayer::MoveOrder {
+            id: game.me,
+            selected: selected(ids),
+            mouse_world_pos: at(300.0, 300.0),
+        };
+        assert!(check(&game.frame, &order(&[mine])).is_ok());
+        assert!(check(&game.frame, &order(&[mine, theirs])).is_err());
+    }
+
+    #[test]
+    fn rejects_targets_out_of_sight() {
+        let mut game = Game::new();
+        game.frame.alliance.fog_of_war = true;
+        let mine = game.spawn(game.botdef("unit_example"), game.me, 100.0, 100.0);
+        let near = game.spawn(game.botdef("unit_example"), game.other, 110.0, 100.0);
+        let far = game.spawn(game.botdef("unit_example"), game.other, 1000.0, 1000.0);
+        let order = |to_guard| FrameEventFromPlayer::GuardOrder {
+            id: game.me,
+            selected: selected(&[mine]),
+            to_guard,
+        };
+        assert!(check(&game.frame, &order(near)).is_ok());
+        assert!(check(&game.frame, &order(far)).is_err());
+    }
+
+    #[test]
+    fn rejects_builds_out_of_range() {
+        let mut game = Game::new();
+        let factory = game.spawn(game.botdef("building"), game.me, 100.0, 100.0);
+        let order = |x| FrameEventFromPlayer::ConOrder {
+            id: game.me,
+            selected: selected(&[factory]),
+            mouse_world_pos: at(x, 100.0),
+            botdef_id: game.botdef("unit_example"),
+        };
+        assert!(check(&game.frame, &order(105.0)).is_ok());
+        assert!(check(&game.frame, &order(500.0)).is_err());
+    }
+
+    #[test]
+    fn rejects_locked_tiers() {
+        let mut game = Game::new();
+        let me = game.me;
+        let builder = game.spawn(game.botdef("unit_example"), me, 100.0, 100.0);
+        let order = |botdef_id| FrameEventFromPlayer::ConOrder {
+            id: me,
+            selected: selected(&[builder]),
+            mouse_world_pos: at(120.0, 100.0),
+            botdef_id,
+        };
+        let (t1, t2) = (game.botdef("unit_example"), game.botdef("unit_t2"));
+        assert!(check(&game.frame, &order(t1)).is_ok());
+        assert!(check(&game.frame, &order(t2)).is_err());
+        game.frame.players.get_mut(&me).unwrap().tech_tier = 2;
+        assert!(check(&game.frame, &order(t2)).is_ok());
+    }
+
+    #[test]
+    fn rejects_dgun_without_energy() {
+        let mut game = Game::new();
+        let commander = game.spawn(game.botdef("commander"), game.me, 100.0, 100.0);
+        let order = FrameEventFromPlayer::DGunOrder {
+            id: game.me,
+            selected: selected(&[commander]),
+            mouse_world_pos: at(110.0, 100.0),
+        };
+        game.frame.players.get_mut(&game.me).unwrap().energy = 500.0;
+        assert!(check(&game.frame, &order).is_ok());
+        game.frame.players.get_mut(&game.me).unwrap().energy = 100.0;
+        assert!(check(&game.frame, &order).is_err());
+    }
+
+    #[test]
+    fn rejects_nuke_without_stock() {
+        let mut game = Game::new();
+        let silo = game.spawn(game.botdef("silo"), game.me, 100.0, 100.0);
+        let order = FrameEventFromPlayer::NukeOrder {
+            id: game.me,
+            selected: selected(&[silo]),
+            mouse_world_pos: at(1000.0, 1000.0),
+        };
+        assert!(check(&game.frame, &order).is_err());
+        game.frame.kbots.get_mut(&silo).unwrap().stockpile = 1;
+        assert!(check(&game.frame, &order).is_ok());
+    }
+
+    #[test]
+    fn rejects_area_radius() {
+        let mut game = Game::new();
+        let builder = game.spawn(game.botdef("unit_example"), game.me, 100.0, 100.0);
+        let order = |radius| FrameEventFromPlayer::AreaOrder {
+            id: game.me,
+            selected: selected(&[builder]),
+            kind: AreaKind::Reclaim,
+            center: at(150.0, 100.0),
+            radius,
+        };
+        assert!(check(&game.frame, &order(50.0)).is_ok());
+        for radius in &[0.0, -10.0, MAX_AREA_RADIUS + 1.0, std::f32::NAN] {
+            assert!(check(&game.frame, &order(*radius)).is_err(), "{}", radius);
+        }
+    }
+
+    #[test]
+    fn rejects_gift() {
+        let game = Game::new();
+        let gift = |to, metal, energy| FrameEventFromPlayer::Gift {
+            id: game.me,
+            to,
+            metal,
+            energy,
+        };
+        assert!(check(&game.frame, &gift(game.other, 100.0, 0.0)).is_ok());
+        assert!(check(&game.frame, &gift(game.me, 100.0, 0.0)).is_err());
+        assert!(check(&game.frame, &gift(utils::rand_id(), 100.0, 0.0)).is_err());
+        assert!(check(&game.frame, &gift(game.other, -100.0, 0.0)).is_err());
+        assert!(check(&game.frame, &gift(game.other, 0.0, std::f64::NAN)).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use crate::frame::{Frame, Player};
+use crate::utils::Id;
+use fnv::FnvHashSet;
+
+///m, side of a cell of the visibility grid
+pub const CELL: f32 = 8.0;
+///m, distance at which units see, the same for every unit
+pub const SIGHT_RADIUS: f32 = 40.0;
+
+///Cells seen by a player, only the seen cells are stored so the map size isn't needed
+pub struct Vision {
+    cells: FnvHashSet<(i32, i32)>,
+}
+
+impl Vision {
+    pub fn new() -> Self {
+        Vision {
+            cells: FnvHashSet::default(),
+        }
+    }
+
+    ///Cells whose center is within radius of x y
+    pub fn mark(&mut self, x: f32, y: f32, radius: f32) {
+        let reach = (radius / CELL).ceil() as i32;
+        let (cx, cy) = cell(x, y);
+        for i in cx - reach..=cx + reach {
+            for j in cy - reach..=cy + reach {
+                let dx = (i as f32 + 0.5) * CELL - x;
+                let dy = (j as f32 + 0.5) * CELL - y;
+                if dx * dx + dy * dy <= radius * radius {
+                    self.cells.insert((i, j));
+                }
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Vision) {
+        self.cells.extend(other.cells.iter().copied());
+    }
+
+    pub fn is_visible(&self, x: f32, y: f32) -> bool {
+        self.cells.contains(&cell(x, y))
+    }
+
+    ///Sight of the units of one player
+    pub fn of_player(frame: &Frame, player: &Player) -> Self {
+        let mut vision = Vision::new();
+        for kbot in player.kbots.iter().filter_map(|id| frame.kbots.get(id)) {
+            vision.mark(kbot.position.x, kbot.position.y, SIGHT_RADIUS);
+        }
+        vision
+    }
+
+    ///Grids of the players of the team of player_id merged, allies share what they see
+    pub fn of_team(frame: &Frame, player_id: Id<Player>) -> Self {
+        let mut vision = Vision::new();
+        if let Some(team) = frame.players.get(&player_id).map(|p| p.team) {
+            for ally in frame.players.values().filter(|p| p.team == team) {
+                vision.merge(&Vision::of_player(frame, ally));
+            }
+        }
+        vision
+    }
+}
+
+fn cell(x: f32, y: f32) -> (i32, i32) {
+    ((x / CELL).floor() as i32, (y / CELL).floor() as i32)
+}
@@ -0,0 +1,91 @@
+use na::Vector2;
+use serde::{Deserialize, Serialize};
+
+///Upper bound of particles drawn around the camera at intensity 1.0
+pub const MAX_PARTICLES: u32 = 40000;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum WeatherKind {
+    Clear,
+    Rain,
+    Snow,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Weather {
+    pub kind: WeatherKind,
+    ///between 0 and 1
+    pub intensity: f32,
+    ///m/sec
+    pub wind: Vector2<f32>,
+}
+
+impl Weather {
+    pub fn clear() -> Self {
+        Weather {
+            kind: WeatherKind::Clear,
+            intensity: 0.0,
+            wind: Vector2::new(0.0, 0.0),
+        }
+    }
+
+    pub fn presets() -> Vec<(&'static str, Weather)> {
+        vec![
+            ("Clear", Self::clear()),
+            (
+                "Drizzle",
+                Weather {
+                    kind: WeatherKind::Rain,
+                    intensity: 0.2,
+                    wind: Vector2::new(1.0, 0.5),
+                },
+            ),
+            (
+                "Storm",
+                Weather {
+                    kind: WeatherKind::Rain,
+                    intensity: 1.0,
+                    wind: Vector2::new(9.0, 4.0),
+                },
+            ),
+            (
+                "Snow",
+                Weather {
+                    kind: WeatherKind::Snow,
+                    intensity: 0.5,
+                    wind: Vector2::new(0.5, 0.2),
+                },
+            ),
+            (
+                "Blizzard",
+                Weather {
+                    kind: WeatherKind::Snow,
+                    intensity: 1.0,
+                    wind: Vector2::new(12.0, -3.0),
+                },
+            ),
+        ]
+    }
+
+    pub fn particle_count(&self) -> u32 {
+        match self.kind {
+            WeatherKind::Clear => 0,
+            _ => (MAX_PARTICLES as f32 * self.intensity.max(0.0).min(1.0)) as u32,
+        }
+    }
+
+    ///m/sec
+    pub fn fall_speed(&self) -> f32 {
+        match self.kind {
+            WeatherKind::Clear => 0.0,
+            WeatherKind::Rain => 25.0,
+            WeatherKind::Snow => 2.0,
+        }
+    }
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::clear()
+    }
+}